@@ -0,0 +1,15 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    //the protobuf definitions are only compiled when building the gRPC API
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/teleport.proto");
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("no vendored protoc");
+        std::env::set_var("PROTOC", protoc);
+        //the daemon only serves the api, integrators generate their own clients
+        tonic_build::configure()
+            .build_client(false)
+            .compile_protos(&["proto/teleport.proto"], &["proto"])
+            .expect("cant compile teleport.proto");
+    }
+}
@@ -0,0 +1,38 @@
+//redeemscripts are sent by the other side of a coinswap, so every reader must handle garbage
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use bitcoin::Script;
+
+use teleport::contracts::{
+    create_contract_redeemscript, read_hashlock_pubkey_from_contract, read_hashvalue_from_contract,
+    read_locktime_from_contract, read_pubkeys_from_multisig_redeemscript,
+    read_timelock_pubkey_from_contract,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let redeemscript = Script::from(data.to_vec());
+    let _ = read_pubkeys_from_multisig_redeemscript(&redeemscript);
+    let hashvalue = read_hashvalue_from_contract(&redeemscript);
+    let locktime = read_locktime_from_contract(&redeemscript);
+    let hashlock_pubkey = read_hashlock_pubkey_from_contract(&redeemscript);
+    let timelock_pubkey = read_timelock_pubkey_from_contract(&redeemscript);
+
+    //rebuilding a contract from what was read must give a script the readers agree with
+    if let (Ok(hashvalue), Some(locktime), Ok(hashlock_pubkey), Ok(timelock_pubkey)) =
+        (hashvalue, locktime, hashlock_pubkey, timelock_pubkey)
+    {
+        let contract =
+            create_contract_redeemscript(&hashlock_pubkey, &timelock_pubkey, hashvalue, locktime);
+        assert_eq!(read_hashvalue_from_contract(&contract), Ok(hashvalue));
+        assert_eq!(read_locktime_from_contract(&contract), Some(locktime));
+        assert_eq!(
+            read_hashlock_pubkey_from_contract(&contract),
+            Ok(hashlock_pubkey)
+        );
+        assert_eq!(
+            read_timelock_pubkey_from_contract(&contract),
+            Ok(timelock_pubkey)
+        );
+    }
+});
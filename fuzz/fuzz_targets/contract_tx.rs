@@ -0,0 +1,29 @@
+//contract transactions and redeemscripts from the other side of a coinswap
+//the first byte is the length of the redeemscript, the rest after it is the transaction
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use bitcoin::consensus::deserialize;
+use bitcoin::{Script, Transaction};
+
+use teleport::contracts::{find_funding_output, validate_contract_tx};
+
+fuzz_target!(|data: &[u8]| {
+    let (script_len, data) = match data.split_first() {
+        Some((script_len, data)) => (*script_len as usize, data),
+        None => return,
+    };
+    if data.len() < script_len {
+        return;
+    }
+    let (redeemscript, tx_bytes) = data.split_at(script_len);
+    let redeemscript = Script::from(redeemscript.to_vec());
+    let tx = match deserialize::<Transaction>(tx_bytes) {
+        Ok(tx) => tx,
+        Err(_) => return,
+    };
+    let _ = find_funding_output(&tx, &redeemscript);
+    let funding_outpoint = tx.input.first().map(|input| input.previous_output);
+    let _ = validate_contract_tx(&tx, None, &redeemscript);
+    let _ = validate_contract_tx(&tx, funding_outpoint.as_ref(), &redeemscript);
+});
@@ -0,0 +1,13 @@
+//every message a maker or watchtower reads from its socket, and the replies a taker reads
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use teleport::messages::{MakerToTakerMessage, TakerToMakerMessage};
+use teleport::watchtower_protocol::{MakerToWatchtowerMessage, WatchtowerToMakerMessage};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = serde_json::from_slice::<TakerToMakerMessage>(data);
+    let _ = serde_json::from_slice::<MakerToTakerMessage>(data);
+    let _ = serde_json::from_slice::<MakerToWatchtowerMessage>(data);
+    let _ = serde_json::from_slice::<WatchtowerToMakerMessage>(data);
+});
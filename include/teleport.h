@@ -0,0 +1,71 @@
+/*
+ * C API of teleport, for embedding coinswap in wallets written in other languages
+ *
+ * build the library with
+ *     cargo rustc --lib --release --crate-type cdylib
+ * or --crate-type staticlib, then link against target/release/libteleport
+ *
+ * every function returning int32_t returns TELEPORT_OK or one of the error codes below,
+ * teleport_last_error() then gives a message describing the error
+ */
+
+#ifndef TELEPORT_H
+#define TELEPORT_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define TELEPORT_OK 0
+#define TELEPORT_ERR_INVALID_ARGUMENT 1
+#define TELEPORT_ERR_NOT_INITIALIZED 2
+#define TELEPORT_ERR_NETWORK 3
+#define TELEPORT_ERR_DISK 4
+#define TELEPORT_ERR_PROTOCOL 5
+#define TELEPORT_ERR_RPC 6
+#define TELEPORT_ERR_SOCKS 7
+#define TELEPORT_ERR_CONFIG 8
+#define TELEPORT_ERR_COINSWAP_FAILED 9
+#define TELEPORT_ERR_PANIC 10
+
+typedef struct TeleportWallet TeleportWallet;
+
+/* called with every coinswap progress event as a JSON object, for example
+ * {"event":"maker_chosen","maker_address":"..."}
+ * event_json is only valid for the duration of the call */
+typedef void (*TeleportEventCallback)(const char *event_json, void *user_data);
+
+/* message of the last error returned on this thread, or NULL
+ * valid until the next teleport call on this thread */
+const char *teleport_last_error(void);
+
+/* load teleport.conf from data_dir, or the default data directory if NULL, and set up
+ * logging. must be called once before anything else */
+int32_t teleport_init(const char *data_dir);
+
+/* load and sync a wallet file, free it with teleport_wallet_free */
+int32_t teleport_wallet_load(const char *wallet_file, TeleportWallet **wallet_out);
+void teleport_wallet_free(TeleportWallet *wallet);
+
+/* download the offers of every advertised maker as a JSON array
+ * free the string with teleport_string_free */
+int32_t teleport_sync_offerbook(char **offers_json_out);
+void teleport_string_free(char *s);
+
+/* do a coinswap, blocking until it finishes. callback may be NULL, it is called on the
+ * calling thread. fee_rate is in sat per 1000 vbytes */
+int32_t teleport_run_coinswap(TeleportWallet *wallet,
+                              uint64_t send_amount,
+                              uint16_t maker_count,
+                              uint32_t tx_count,
+                              uint64_t fee_rate,
+                              TeleportEventCallback callback,
+                              void *user_data);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* TELEPORT_H */
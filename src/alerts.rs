@@ -0,0 +1,259 @@
+//alerts to page operators about contracts which need attention, sent to a webhook, a
+// telegram chat and or an email address as configured in the [alerts] section of teleport.conf
+//
+//the webhook is POSTed a JSON object like
+//    {"source": "watchtower", "type": "response_broadcast", "txid": "...",
+//     "spend_type": "hashlock", "message": "teleport watchtower: broadcast hashlock ..."}
+
+use std::fmt;
+use std::process::Stdio;
+use std::time::Duration;
+
+use bitcoin::{OutPoint, Txid};
+use serde::Serialize;
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::directory_servers::TOR_ADDR;
+use crate::error::Error;
+use crate::settings::{AlertSettings, Settings};
+
+const ALERT_REQUEST_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Alert {
+    //contract txes of a coinswap appeared on the network
+    ContractBroadcast {
+        txids: Vec<Txid>,
+    },
+    //the timelock of a contract can be spent in blocks_remaining blocks
+    TimelockNearExpiry {
+        txid: Txid,
+        blocks_remaining: u32,
+    },
+    //a pre-signed hashlock or timelock spend of a contract was broadcast
+    ResponseBroadcast {
+        txid: Txid,
+        spend_type: &'static str,
+    },
+    //a maker's fidelity bond unlocks in days_remaining days
+    FidelityBondExpiring {
+        outpoint: OutPoint,
+        days_remaining: u32,
+    },
+    //a maker's fidelity bond can be spent, from now on it loses value
+    FidelityBondUnlocked {
+        outpoint: OutPoint,
+    },
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Alert::ContractBroadcast { txids } => write!(
+                f,
+                "contract transactions broadcast: {}",
+                txids
+                    .iter()
+                    .map(|txid| txid.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Alert::TimelockNearExpiry {
+                txid,
+                blocks_remaining,
+            } => write!(
+                f,
+                "timelock of contract {} expires in {} blocks",
+                txid, blocks_remaining
+            ),
+            Alert::ResponseBroadcast { txid, spend_type } => {
+                write!(f, "broadcast {} spend transaction {}", spend_type, txid)
+            }
+            Alert::FidelityBondExpiring {
+                outpoint,
+                days_remaining,
+            } => write!(
+                f,
+                "fidelity bond {} unlocks in {} days, plan moving it into a new bond",
+                outpoint, days_remaining
+            ),
+            Alert::FidelityBondUnlocked { outpoint } => write!(
+                f,
+                "fidelity bond {} can now be spent and loses value until it is moved into a new bond",
+                outpoint
+            ),
+        }
+    }
+}
+
+/// Send the alert in the background to every configured destination, does nothing if
+/// there are none. source is e.g. "maker" or "watchtower"
+pub fn send_alert(source: &'static str, alert: Alert) {
+    tracing::info!("alert: {}", alert);
+    if !Settings::is_initialized() || !Settings::global().alerts.is_configured() {
+        return;
+    }
+    let alert_settings = Settings::global().alerts.clone();
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => {
+            runtime.spawn(async move { deliver_alert(&alert_settings, source, &alert).await });
+        }
+        Err(_) => tracing::warn!("not sending alert, no tokio runtime"),
+    }
+}
+
+fn alert_message(source: &str, alert: &Alert) -> String {
+    format!("teleport {}: {}", source, alert)
+}
+
+fn webhook_body(source: &str, alert: &Alert) -> serde_json::Value {
+    let mut body = json!(alert);
+    body["source"] = json!(source);
+    body["message"] = json!(alert_message(source, alert));
+    body
+}
+
+//every destination is tried even if an earlier one fails
+async fn deliver_alert(settings: &AlertSettings, source: &str, alert: &Alert) {
+    if let Some(url) = &settings.webhook_url {
+        if let Err(e) = post_json(url, &webhook_body(source, alert)).await {
+            tracing::warn!("unable to send alert to webhook: {:?}", e);
+        }
+    }
+    if let (Some(token), Some(chat_id)) = (&settings.telegram_bot_token, &settings.telegram_chat_id)
+    {
+        let url = format!("https://api.telegram.org/bot{}/sendMessage", token);
+        let body = json!({"chat_id": chat_id, "text": alert_message(source, alert)});
+        if let Err(e) = post_json(&url, &body).await {
+            tracing::warn!("unable to send alert to telegram: {:?}", e);
+        }
+    }
+    if let Some(email_to) = &settings.email_to {
+        if let Err(e) = send_email(&settings.sendmail_command, email_to, source, alert).await {
+            tracing::warn!("unable to send alert email: {:?}", e);
+        }
+    }
+}
+
+async fn post_json(url: &str, body: &serde_json::Value) -> Result<(), Error> {
+    let mut client_builder =
+        reqwest::Client::builder().timeout(Duration::from_secs(ALERT_REQUEST_TIMEOUT_SECS));
+    if Settings::is_tor_only() || url.contains(".onion") {
+        let proxy = reqwest::Proxy::all(format!("socks5h://{}", TOR_ADDR))
+            .map_err(|e| Error::Network(Box::new(e)))?;
+        client_builder = client_builder.proxy(proxy);
+    }
+    let client = client_builder
+        .build()
+        .map_err(|e| Error::Network(Box::new(e)))?;
+    let response = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(body.to_string())
+        .send()
+        .await
+        .map_err(|e| Error::Network(Box::new(e)))?;
+    if !response.status().is_success() {
+        tracing::debug!("alert request returned status {}", response.status());
+        return Err(Error::Protocol("alert request was not successful"));
+    }
+    Ok(())
+}
+
+//pipes the email to `sendmail -t`, which reads the recipient from the headers
+async fn send_email(
+    sendmail_command: &str,
+    email_to: &str,
+    source: &str,
+    alert: &Alert,
+) -> Result<(), Error> {
+    let email = format!(
+        "To: {}\nSubject: teleport {} alert\n\n{}\n",
+        email_to,
+        source,
+        alert_message(source, alert)
+    );
+    let mut sendmail = Command::new(sendmail_command)
+        .arg("-t")
+        .stdin(Stdio::piped())
+        .spawn()?;
+    //take stdin so it is closed before waiting, otherwise sendmail waits for more input
+    let mut stdin = sendmail.stdin.take().unwrap();
+    stdin.write_all(email.as_bytes()).await?;
+    drop(stdin);
+    if !sendmail.wait().await?.success() {
+        return Err(Error::Protocol("sendmail failed"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_alert_message() {
+        let txid = Txid::from_slice(&[1u8; 32]).unwrap();
+        let alert = Alert::ResponseBroadcast {
+            txid,
+            spend_type: "hashlock",
+        };
+        assert_eq!(
+            alert_message("watchtower", &alert),
+            format!(
+                "teleport watchtower: broadcast hashlock spend transaction {}",
+                txid
+            )
+        );
+        let body = webhook_body("watchtower", &alert);
+        assert_eq!(body["type"], "response_broadcast");
+        assert_eq!(body["source"], "watchtower");
+        assert_eq!(body["spend_type"], "hashlock");
+        assert_eq!(body["txid"], txid.to_string());
+
+        let alert = Alert::ContractBroadcast {
+            txids: vec![txid, txid],
+        };
+        assert_eq!(
+            alert.to_string(),
+            format!("contract transactions broadcast: {}, {}", txid, txid)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_post_json() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let webhook = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for status in ["200 OK", "500 Internal Server Error"] {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                //the body is small json, read until all of it arrived
+                let mut request = Vec::new();
+                while !request.ends_with(b"}") {
+                    let mut buf = [0u8; 1024];
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                requests.push(String::from_utf8(request).unwrap());
+                let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+
+        let body = json!({"type": "test"});
+        post_json(&url, &body).await.unwrap();
+        assert!(post_json(&url, &body).await.is_err());
+        let requests = webhook.await.unwrap();
+        assert!(requests[0].starts_with("POST /hook "));
+        assert!(requests[0].ends_with(r#"{"type":"test"}"#));
+    }
+}
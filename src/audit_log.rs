@@ -0,0 +1,350 @@
+//append-only record of every message sent and received during coinswaps
+//so that disputes like "the maker never sent the sigs" can be investigated after the fact
+//only the hash of each message is stored, the messages themselves contain private keys
+//each entry commits to the entry before it and is signed with a key from the wallet,
+// so entries cant be edited or removed later without it being noticed. the log is verified
+// against the wallet's key, e.g. the one a swap receipt is signed with, so it cant be
+// replaced by a log signed with another key. entries cut off the end are only noticed
+// against a hash committing to a later entry, like the transcript hashes of swap receipts
+//entries of messages to and from makers record the maker's identity key once its hello
+// proved it, see maker_identity.rs
+//each wallet records into its own AuditLog, see Wallet::open_audit_log
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{All, Message, Secp256k1, SecretKey, Signature};
+use bitcoin::util::ecdsa::PublicKey;
+
+use crate::error::Error;
+
+pub const AUDIT_LOG_FILE_NAME: &str = "audit.log";
+
+//every audit log opened by this process, so two wallets opening the same file share the
+// hash of its last entry
+static OPEN_AUDIT_LOGS: Mutex<Vec<Arc<AuditLog>>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditLogEntry {
+    pub timestamp: u64,
+    pub peer: String,
+    //not recorded by older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer_identity: Option<PublicKey>,
+    pub direction: Direction,
+    pub message_hash: sha256::Hash,
+    pub previous_entry_hash: sha256::Hash,
+    pub signing_pubkey: PublicKey,
+    pub signature: Signature,
+}
+
+fn entry_digest(
+    timestamp: u64,
+    peer: &str,
+    peer_identity: Option<&PublicKey>,
+    direction: Direction,
+    message_hash: &sha256::Hash,
+    previous_entry_hash: &sha256::Hash,
+) -> Message {
+    let mut engine = sha256::Hash::engine();
+    engine.write_all(&previous_entry_hash[..]).unwrap();
+    engine.write_all(&message_hash[..]).unwrap();
+    engine.write_all(&timestamp.to_be_bytes()).unwrap();
+    engine
+        .write_all(&[match direction {
+            Direction::Sent => 0,
+            Direction::Received => 1,
+        }])
+        .unwrap();
+    engine.write_all(peer.as_bytes()).unwrap();
+    //entries without one have the same digest as those written by older versions
+    if let Some(peer_identity) = peer_identity {
+        engine.write_all(&peer_identity.to_bytes()).unwrap();
+    }
+    Message::from_slice(&sha256::Hash::from_engine(engine)[..]).unwrap()
+}
+
+//the first entry commits to this instead of a previous entry
+fn empty_entry_hash() -> sha256::Hash {
+    sha256::Hash::from_inner([0; 32])
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    privkey: SecretKey,
+    pubkey: PublicKey,
+    secp: Secp256k1<All>,
+    //hash of the last line written, which the next entry commits to
+    last_entry_hash: Mutex<sha256::Hash>,
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuditLog")
+            .field("path", &self.path)
+            .field("pubkey", &self.pubkey)
+            .finish()
+    }
+}
+
+//hash of the last entry and the key of the first, None for an empty log
+fn read_log_state(path: &Path) -> Result<(sha256::Hash, Option<PublicKey>), Error> {
+    if !path.exists() {
+        return Ok((empty_entry_hash(), None));
+    }
+    let lines = BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| !line.as_ref().is_ok_and(|line| line.is_empty()))
+        .collect::<Result<Vec<String>, _>>()?;
+    let (first_line, last_line) = match (lines.first(), lines.last()) {
+        (Some(first_line), Some(last_line)) => (first_line, last_line),
+        _ => return Ok((empty_entry_hash(), None)),
+    };
+    let first_entry = serde_json::from_str::<AuditLogEntry>(first_line)
+        .map_err(|_| Error::Protocol("unable to parse audit log entry"))?;
+    Ok((
+        sha256::Hash::hash(last_line.as_bytes()),
+        Some(first_entry.signing_pubkey),
+    ))
+}
+
+impl AuditLog {
+    fn new(path: PathBuf, privkey: SecretKey) -> Result<AuditLog, Error> {
+        let secp = Secp256k1::new();
+        let pubkey = PublicKey {
+            compressed: true,
+            key: bitcoin::secp256k1::PublicKey::from_secret_key(&secp, &privkey),
+        };
+        let (last_entry_hash, signing_pubkey) = read_log_state(&path)?;
+        if signing_pubkey.is_some_and(|signing_pubkey| signing_pubkey != pubkey) {
+            return Err(Error::Protocol(
+                "audit log is signed by another wallet's key",
+            ));
+        }
+        Ok(AuditLog {
+            path,
+            privkey,
+            pubkey,
+            secp,
+            last_entry_hash: Mutex::new(last_entry_hash),
+        })
+    }
+
+    /// Open the audit log at path, whose entries are signed with privkey. A log already
+    /// signed with another key is refused, so one file never has the entries of two wallets
+    pub fn open(path: PathBuf, privkey: SecretKey) -> Result<Arc<AuditLog>, Error> {
+        let mut open_audit_logs = OPEN_AUDIT_LOGS.lock().unwrap();
+        if let Some(audit_log) = open_audit_logs.iter().find(|log| log.path == path) {
+            if audit_log.privkey != privkey {
+                return Err(Error::Protocol(
+                    "audit log is signed by another wallet's key",
+                ));
+            }
+            return Ok(Arc::clone(audit_log));
+        }
+        let audit_log = Arc::new(AuditLog::new(path, privkey)?);
+        open_audit_logs.push(Arc::clone(&audit_log));
+        Ok(audit_log)
+    }
+
+    pub fn signing_pubkey(&self) -> PublicKey {
+        self.pubkey
+    }
+
+    /// Hash of the last entry written, which commits to every entry before it
+    pub fn last_entry_hash(&self) -> sha256::Hash {
+        *self.last_entry_hash.lock().unwrap()
+    }
+
+    fn append(
+        &self,
+        peer: &str,
+        peer_identity: Option<&PublicKey>,
+        direction: Direction,
+        message: &[u8],
+    ) -> Result<(), Error> {
+        let mut last_entry_hash = self.last_entry_hash.lock().unwrap();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let message_hash = sha256::Hash::hash(message);
+        let signature = self.secp.sign(
+            &entry_digest(
+                timestamp,
+                peer,
+                peer_identity,
+                direction,
+                &message_hash,
+                &last_entry_hash,
+            ),
+            &self.privkey,
+        );
+        let entry = AuditLogEntry {
+            timestamp,
+            peer: peer.to_string(),
+            peer_identity: peer_identity.copied(),
+            direction,
+            message_hash,
+            previous_entry_hash: *last_entry_hash,
+            signing_pubkey: self.pubkey,
+            signature,
+        };
+
+        let line = serde_json::to_string(&entry).map_err(std::io::Error::from)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", line)?;
+        *last_entry_hash = sha256::Hash::hash(line.as_bytes());
+        Ok(())
+    }
+}
+
+//does nothing without an audit log, e.g. when only downloading offers
+//failing to write is logged but doesnt stop the coinswap
+pub fn record_message(
+    audit_log: Option<&AuditLog>,
+    peer: &str,
+    peer_identity: Option<&PublicKey>,
+    direction: Direction,
+    message: &[u8],
+) {
+    if let Some(audit_log) = audit_log {
+        if let Err(e) = audit_log.append(peer, peer_identity, direction, message) {
+            tracing::error!("unable to write to audit log: {:?}", e);
+        }
+    }
+}
+
+//checks every entry is signed by signing_pubkey and commits to the entry before it
+//returns the number of entries
+pub fn verify_audit_log<P: AsRef<Path>>(
+    path: P,
+    signing_pubkey: &PublicKey,
+) -> Result<usize, Error> {
+    let secp = Secp256k1::verification_only();
+    let mut previous_entry_hash = empty_entry_hash();
+    let mut entry_count = 0;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str::<AuditLogEntry>(&line)
+            .map_err(|_| Error::Protocol("unable to parse audit log entry"))?;
+        if entry.previous_entry_hash != previous_entry_hash {
+            return Err(Error::Protocol("audit log entry missing or modified"));
+        }
+        if entry.signing_pubkey != *signing_pubkey {
+            return Err(Error::Protocol("audit log entry signed by another key"));
+        }
+        secp.verify(
+            &entry_digest(
+                entry.timestamp,
+                &entry.peer,
+                entry.peer_identity.as_ref(),
+                entry.direction,
+                &entry.message_hash,
+                &entry.previous_entry_hash,
+            ),
+            &entry.signature,
+            &signing_pubkey.key,
+        )
+        .map_err(|_| Error::Protocol("invalid audit log entry signature"))?;
+        previous_entry_hash = sha256::Hash::hash(line.as_bytes());
+        entry_count += 1;
+    }
+    Ok(entry_count)
+}
+
+//true if the audit log at path has an entry with this hash, the hash of the empty log
+// counts as well because a transcript can start before the first entry
+pub fn has_entry_hash<P: AsRef<Path>>(path: P, entry_hash: &sha256::Hash) -> Result<bool, Error> {
+    if *entry_hash == empty_entry_hash() {
+        return Ok(true);
+    }
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.is_empty() && sha256::Hash::hash(line.as_bytes()) == *entry_hash {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_audit_log_verification() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(AUDIT_LOG_FILE_NAME);
+        let privkey = SecretKey::from_slice(&[3; 32]).unwrap();
+        let other_privkey = SecretKey::from_slice(&[4; 32]).unwrap();
+
+        let audit_log = AuditLog::new(path.clone(), privkey).unwrap();
+        let signing_pubkey = audit_log.signing_pubkey();
+        let maker_identity = AuditLog::new(path.clone(), other_privkey)
+            .unwrap()
+            .signing_pubkey();
+        audit_log
+            .append("maker1.onion:6102", None, Direction::Sent, b"hello")
+            .unwrap();
+        audit_log
+            .append(
+                "maker1.onion:6102",
+                Some(&maker_identity),
+                Direction::Received,
+                b"hi",
+            )
+            .unwrap();
+        //opening an existing log must continue the chain
+        let audit_log = AuditLog::new(path.clone(), privkey).unwrap();
+        audit_log
+            .append("maker2.onion:6102", None, Direction::Sent, b"bye")
+            .unwrap();
+        assert_eq!(verify_audit_log(&path, &signing_pubkey).unwrap(), 3);
+        let last_entry_hash = audit_log.last_entry_hash();
+        assert!(has_entry_hash(&path, &last_entry_hash).unwrap());
+        assert!(!has_entry_hash(&path, &sha256::Hash::hash(b"bye")).unwrap());
+
+        //a log signed by another key is neither verified nor appended to
+        assert!(verify_audit_log(&path, &maker_identity).is_err());
+        assert!(AuditLog::new(path.clone(), other_privkey).is_err());
+
+        //removing an entry breaks the chain
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines = contents.lines().collect::<Vec<&str>>();
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(verify_audit_log(&path, &signing_pubkey).is_err());
+
+        //editing an entry invalidates its signature, as does changing the peer identity
+        std::fs::write(&path, format!("{}\n", lines[0].replace("maker1", "maker3"))).unwrap();
+        assert!(verify_audit_log(&path, &signing_pubkey).is_err());
+        let mut entry = serde_json::from_str::<AuditLogEntry>(lines[1]).unwrap();
+        entry.peer_identity = Some(signing_pubkey);
+        std::fs::write(
+            &path,
+            format!("{}\n{}\n", lines[0], serde_json::to_string(&entry).unwrap()),
+        )
+        .unwrap();
+        assert!(verify_audit_log(&path, &signing_pubkey).is_err());
+    }
+}
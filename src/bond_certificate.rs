@@ -0,0 +1,126 @@
+//a maker's fidelity bond proof written to a file by `teleport fidelity export-bond`, so that
+// takers and anyone else can check it with `teleport fidelity verify-bond` or their own tools
+// without syncing the offerbook
+//the file is JSON with the fields
+//  version         1
+//  maker_address   the onion or i2p address with port that the maker announces
+//  network         "bitcoin", "testnet", "signet" or "regtest"
+//  proof           the proof sent in offers:
+//                    utxo         "txid:vout" of the bond
+//                    utxo_key     hex pubkey of the bond
+//                    locktime     unix time the bond can be spent after
+//                    cert_pubkey  hex pubkey of the certificate
+//                    cert_expiry  the certificate is valid until block 2016 * cert_expiry
+//                    cert_sig     DER hex signature by utxo_key of the bitcoin signed message
+//                                 "fidelity-bond-cert|<cert_pubkey>|<cert_expiry>"
+//                    onion_sig    DER hex signature by cert_pubkey of the sha256d of the
+//                                 hostname of maker_address
+//  redeemscript    hex of `<locktime> OP_CHECKLOCKTIMEVERIFY OP_DROP <utxo_key> OP_CHECKSIG`
+//  bond_address    the p2wsh address of redeemscript, which the utxo pays to
+
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::{Address, Network};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::fidelity_bonds::create_timelocked_redeemscript;
+use crate::gossip::onion_hostname;
+use crate::messages::FidelityBondProof;
+
+pub const BOND_CERTIFICATE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BondCertificate {
+    pub version: u32,
+    pub maker_address: String,
+    pub network: Network,
+    pub proof: FidelityBondProof,
+    pub redeemscript: String,
+    pub bond_address: String,
+}
+
+impl BondCertificate {
+    pub fn new(proof: FidelityBondProof, maker_address: &str, network: Network) -> BondCertificate {
+        let redeemscript = create_timelocked_redeemscript(proof.locktime, &proof.utxo_key);
+        BondCertificate {
+            version: BOND_CERTIFICATE_VERSION,
+            maker_address: maker_address.to_string(),
+            network,
+            bond_address: Address::p2wsh(&redeemscript, network).to_string(),
+            redeemscript: redeemscript.as_bytes().to_hex(),
+            proof,
+        }
+    }
+
+    /// Check everything that doesnt need a bitcoin node: the signatures over the maker's
+    /// hostname and that the redeemscript and address are of the proof's key and locktime.
+    /// Whether the bond exists and its value need a node
+    pub fn verify_offline(&self) -> Result<(), Error> {
+        if self.version != BOND_CERTIFICATE_VERSION {
+            return Err(Error::Protocol("unsupported bond certificate version"));
+        }
+        self.proof
+            .verify_signatures(onion_hostname(&self.maker_address))?;
+        let redeemscript =
+            create_timelocked_redeemscript(self.proof.locktime, &self.proof.utxo_key);
+        if self.redeemscript != redeemscript.as_bytes().to_hex() {
+            return Err(Error::Protocol(
+                "redeemscript isnt of the bond's key and locktime",
+            ));
+        }
+        if self.bond_address != Address::p2wsh(&redeemscript, self.network).to_string() {
+            return Err(Error::Protocol("bond address isnt of the redeemscript"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use bitcoin::util::key::PublicKey;
+    use bitcoin::OutPoint;
+
+    use crate::fidelity_bonds::sign_fidelity_bond_proof;
+
+    #[test]
+    fn test_bond_certificate() {
+        let utxo_privkey = SecretKey::from_slice(&[1; 32]).unwrap();
+        let maker_address = "abcdefghijklmnop.onion:6102";
+        let utxo_key = PublicKey {
+            compressed: true,
+            key: bitcoin::secp256k1::PublicKey::from_secret_key(&Secp256k1::new(), &utxo_privkey),
+        };
+        let proof = sign_fidelity_bond_proof(
+            OutPoint::default(),
+            utxo_key,
+            &utxo_privkey,
+            1_800_000_000,
+            500,
+            "abcdefghijklmnop.onion",
+        );
+        let certificate = BondCertificate::new(proof, maker_address, Network::Regtest);
+        assert!(certificate.bond_address.starts_with("bcrt1q"));
+        assert!(certificate.verify_offline().is_ok());
+
+        let json = serde_json::to_string_pretty(&certificate).unwrap();
+        let certificate = serde_json::from_str::<BondCertificate>(&json).unwrap();
+        assert!(certificate.verify_offline().is_ok());
+
+        let other_maker = BondCertificate {
+            maker_address: "qrstuvwxyz.onion:6102".to_string(),
+            ..certificate.clone()
+        };
+        assert!(other_maker.verify_offline().is_err());
+        let mut later_locktime = certificate.clone();
+        later_locktime.proof.locktime += 1;
+        assert!(later_locktime.verify_offline().is_err());
+        let other_network = BondCertificate {
+            network: Network::Testnet,
+            ..certificate
+        };
+        assert!(other_network.verify_offline().is_err());
+    }
+}
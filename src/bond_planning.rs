@@ -0,0 +1,149 @@
+//what a fidelity bond would be worth before locking the coins, shown by
+// `teleport fidelity plan <amount> <months>`
+//timelocked addresses unlock at the start of a month, so a bond locked for n months unlocks at
+// the start of the n-th month after the current one
+//a bond's value grows faster than its amount so one large bond is worth more than several
+// smaller ones of the same total, but each maker instance shows only one bond
+
+use chrono::Datelike;
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::fidelity_bonds::{
+    fidelity_bond_value, get_locktime_from_index, BOND_VALUE_EXPONENT, TIMELOCKED_ADDRESS_COUNT,
+};
+
+//the splits compared are into 1 up to this many bonds, or more if there are more maker instances
+const MIN_COMPARED_BOND_COUNT: u32 = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BondSplit {
+    pub bond_count: u32,
+    pub amount_per_bond_sat: u64,
+    pub value_per_bond: f64,
+    pub total_value: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BondPlan {
+    pub amount_sat: u64,
+    pub locktime: i64,
+    //"yyyy-mm", as given to get-fidelity-bond-address
+    pub year_and_month: String,
+    pub value: f64,
+    pub splits: Vec<BondSplit>,
+    pub warnings: Vec<String>,
+}
+
+fn format_date(timestamp: i64, format: &str) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|d| d.format(format).to_string())
+        .unwrap_or_default()
+}
+
+/// The bond of amount_sat locked for months from now, compared with splitting it into several
+/// bonds. operation_months is how long the maker expects to run, if known, and maker_instances
+/// how many makers run from the wallet, each needing its own bond
+pub fn plan_bond(
+    amount_sat: u64,
+    months: u32,
+    operation_months: Option<u32>,
+    maker_instances: u32,
+    now: i64,
+) -> Result<BondPlan, Error> {
+    if months == 0 {
+        return Err(Error::Protocol(
+            "a bond must be locked for at least a month",
+        ));
+    }
+    let today = chrono::DateTime::from_timestamp(now, 0).ok_or(Error::Protocol("invalid time"))?;
+    let index = (today.year() as u32)
+        .checked_sub(2020)
+        .map(|years| years * 12 + today.month0() + months)
+        .filter(|&index| index < TIMELOCKED_ADDRESS_COUNT)
+        .ok_or(Error::Protocol("no timelocked address for that locktime"))?;
+    let locktime = get_locktime_from_index(index);
+    let bond_value =
+        |amount: u64| fidelity_bond_value(amount, locktime, now, now as u64, BOND_VALUE_EXPONENT);
+
+    let splits = (1..=MIN_COMPARED_BOND_COUNT.max(maker_instances))
+        .map(|bond_count| {
+            let amount_per_bond_sat = amount_sat / bond_count as u64;
+            let value_per_bond = bond_value(amount_per_bond_sat);
+            BondSplit {
+                bond_count,
+                amount_per_bond_sat,
+                value_per_bond,
+                total_value: value_per_bond * bond_count as f64,
+            }
+        })
+        .collect::<Vec<BondSplit>>();
+
+    let mut warnings = Vec::new();
+    if maker_instances > 1 {
+        warnings.push(format!(
+            "each of the {} maker instances shows its own bond, with one bond the others have none",
+            maker_instances
+        ));
+    }
+    match operation_months {
+        Some(operation_months) if operation_months > months => warnings.push(format!(
+            "the bond unlocks {} months before the maker stops and then loses value every \
+            month, move the coins into a new bond before {}",
+            operation_months - months,
+            format_date(locktime, "%Y-%m-%d")
+        )),
+        Some(operation_months) if operation_months < months => warnings.push(format!(
+            "the coins stay locked for {} months after the maker stops",
+            months - operation_months
+        )),
+        _ => {}
+    }
+
+    Ok(BondPlan {
+        amount_sat,
+        locktime,
+        year_and_month: format_date(locktime, "%Y-%m"),
+        value: bond_value(amount_sat),
+        splits,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    //2026-10-15
+    const NOW: i64 = 1_792_022_400;
+
+    #[test]
+    fn test_plan_bond() {
+        let plan = plan_bond(100_000_000, 12, None, 1, NOW).unwrap();
+        assert_eq!(plan.year_and_month, "2027-10");
+        assert_eq!(plan.locktime, 1_822_348_800);
+        assert!(plan.warnings.is_empty());
+        assert_eq!(plan.splits.len(), 4);
+        assert_eq!(plan.splits[0].total_value, plan.value);
+        assert_eq!(plan.splits[1].amount_per_bond_sat, 50_000_000);
+        //one large bond is worth more than several small ones
+        assert!(plan
+            .splits
+            .windows(2)
+            .all(|w| w[0].total_value > w[1].total_value));
+        //and locking for longer is worth more
+        assert!(plan_bond(100_000_000, 24, None, 1, NOW).unwrap().value > plan.value);
+
+        let expires_early = plan_bond(100_000_000, 12, Some(18), 1, NOW).unwrap();
+        assert_eq!(expires_early.warnings.len(), 1);
+        assert!(expires_early.warnings[0].contains("2027-10-01"));
+        let locked_after = plan_bond(100_000_000, 12, Some(6), 1, NOW).unwrap();
+        assert!(locked_after.warnings[0].contains("6 months after"));
+        let instances = plan_bond(100_000_000, 12, Some(12), 6, NOW).unwrap();
+        assert_eq!(instances.splits.len(), 6);
+        assert_eq!(instances.warnings.len(), 1);
+
+        assert!(plan_bond(100_000_000, 0, None, 1, NOW).is_err());
+        assert!(plan_bond(100_000_000, 2000, None, 1, NOW).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//opens a lightning channel with the coins received in coinswaps, through the psbt funding
+// flow of lnd (`lncli openchannel --psbt`) or core lightning (`fundchannel_start` and then
+// `fundchannel_complete`)
+//the lightning node gives the address and amount of the channel's funding output, teleport
+// pays it from the wallet's swap coins and prints the transaction as a psbt without
+// signatures for the node to check and then with them for the node to finalize
+//teleport never broadcasts it, the node has to know the channel's funding transaction
+// before it is in the mempool
+
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{Address, Amount, OutPoint, Transaction, TxOut, Txid};
+
+use crate::coin_viability::{input_vbytes, tx_fee};
+use crate::direct_send::{select_coins, Destination, SendAmount};
+use crate::error::Error;
+use crate::payjoin::create_original_psbt;
+use crate::rpc::Client;
+use crate::wallet_sync::{UTXOSpendInfo, Wallet};
+
+pub struct ChannelFunding {
+    pub txid: Txid,
+    //for `lncli openchannel --psbt` to check the funding output before anything is signed
+    pub unsigned_psbt: PartiallySignedTransaction,
+    //for the node to finalize, has every input's witness
+    pub signed_psbt: PartiallySignedTransaction,
+}
+
+/// The funding transaction as a psbt with and without its signatures
+pub fn channel_funding_psbts(
+    tx: &Transaction,
+    spent_outputs: &[TxOut],
+) -> (PartiallySignedTransaction, PartiallySignedTransaction) {
+    let signed_psbt = create_original_psbt(tx, spent_outputs);
+    let mut unsigned_psbt = signed_psbt.clone();
+    for input in unsigned_psbt.inputs.iter_mut() {
+        input.final_script_witness = None;
+    }
+    (unsigned_psbt, signed_psbt)
+}
+
+impl Wallet {
+    /// Pay amount to the channel funding address from the coins received in coinswaps,
+    /// without broadcasting
+    pub fn create_channel_funding(
+        &mut self,
+        rpc: &Client,
+        funding_address: &Address,
+        amount: u64,
+        fee_rate: u64,
+    ) -> Result<ChannelFunding, Error> {
+        let swap_coins = self
+            .list_unspent_from_wallet(rpc, false, false)?
+            .into_iter()
+            .filter(|(_, spend_info)| matches!(spend_info, UTXOSpendInfo::SwapCoin { .. }))
+            .collect::<Vec<_>>();
+        if swap_coins.is_empty() {
+            return Err(Error::Protocol(
+                "no coins from coinswaps to fund the channel",
+            ));
+        }
+        let send_amount = SendAmount::Amount(Amount::from_sat(amount));
+        let coins_to_spend = select_coins(
+            &swap_coins
+                .iter()
+                .map(|(u, spend_info)| {
+                    (
+                        OutPoint {
+                            txid: u.txid,
+                            vout: u.vout,
+                        },
+                        u.amount.as_sat(),
+                        input_vbytes(spend_info),
+                    )
+                })
+                .collect::<Vec<(OutPoint, u64, u64)>>(),
+            &send_amount,
+            fee_rate,
+            tx_fee(0, 2, fee_rate),
+        )?;
+        let tx = self.create_direct_send(
+            rpc,
+            fee_rate,
+            send_amount,
+            Destination::Address(funding_address.clone()),
+            &coins_to_spend,
+        )?;
+        let spent_outputs = tx
+            .input
+            .iter()
+            .map(|input| {
+                swap_coins
+                    .iter()
+                    .find(|(u, _)| {
+                        u.txid == input.previous_output.txid && u.vout == input.previous_output.vout
+                    })
+                    .map(|(u, _)| TxOut {
+                        value: u.amount.as_sat(),
+                        script_pubkey: u.script_pub_key.clone(),
+                    })
+                    .ok_or(Error::Protocol("channel funding input not found in wallet"))
+            })
+            .collect::<Result<Vec<TxOut>, Error>>()?;
+        let (unsigned_psbt, signed_psbt) = channel_funding_psbts(&tx, &spent_outputs);
+        Ok(ChannelFunding {
+            txid: tx.txid(),
+            unsigned_psbt,
+            signed_psbt,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::{Script, TxIn};
+
+    #[test]
+    fn test_channel_funding_psbts() {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                witness: vec![vec![1; 72], vec![2; 33]],
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 90_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let spent_output = TxOut {
+            value: 100_000,
+            script_pubkey: Script::new(),
+        };
+        let (unsigned_psbt, signed_psbt) = channel_funding_psbts(&tx, &[spent_output.clone()]);
+        assert!(unsigned_psbt.global.unsigned_tx.input[0].witness.is_empty());
+        assert_eq!(unsigned_psbt.global.unsigned_tx.txid(), tx.txid());
+        assert_eq!(unsigned_psbt.inputs[0].witness_utxo, Some(spent_output));
+        assert_eq!(unsigned_psbt.inputs[0].final_script_witness, None);
+        assert_eq!(
+            signed_psbt.inputs[0].final_script_witness,
+            Some(tx.input[0].witness.clone())
+        );
+        assert_eq!(signed_psbt.clone().extract_tx(), tx);
+    }
+}
@@ -0,0 +1,256 @@
+//which of the wallet's coins an observer of the blockchain can already tell belong together,
+// shown by `teleport coin-linkage`
+//coins are linked by the heuristics chain analysis uses:
+//  common input ownership  all the inputs of a transaction belong to the same wallet
+//  change                  a change output, or the only output of a sweep, belongs to the
+//                          wallet which owned the inputs
+//the transactions which made the wallet's coins are followed back through the wallet's own
+// earlier spends, so two coins are linked if any of their histories were ever spent together
+//coins of different clusters arent linked yet, spending them together in a coinswap or a
+// direct-send links the clusters for good
+
+use std::collections::{HashMap, HashSet};
+
+use bitcoin::{Address, OutPoint, Script, Transaction, Txid};
+use bitcoincore_rpc::RpcApi;
+use serde_json::{json, Value};
+
+use crate::error::Error;
+use crate::rpc::Client;
+use crate::wallet_sync::{get_hd_path_from_descriptor, Wallet};
+
+//a transaction in the history of the wallet's coins
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkageTx {
+    //inputs which spent coins of the wallet
+    pub wallet_inputs: Vec<OutPoint>,
+    //outputs paying the wallet's change addresses
+    pub change_vouts: Vec<u32>,
+    pub output_count: u32,
+}
+
+fn find_root(parents: &mut HashMap<OutPoint, OutPoint>, outpoint: OutPoint) -> OutPoint {
+    let mut root = outpoint;
+    while let Some(&parent) = parents.get(&root) {
+        if parent == root {
+            break;
+        }
+        root = parent;
+    }
+    parents.insert(outpoint, root);
+    root
+}
+
+fn union(parents: &mut HashMap<OutPoint, OutPoint>, a: OutPoint, b: OutPoint) {
+    let root_a = find_root(parents, a);
+    let root_b = find_root(parents, b);
+    if root_a != root_b {
+        parents.insert(root_a, root_b);
+    }
+}
+
+/// Group coins into clusters of coins linked to each other, given the transactions in their
+/// history by txid. Clusters and the coins in them are in the order of coins
+pub fn cluster_coins(coins: &[OutPoint], txes: &HashMap<Txid, LinkageTx>) -> Vec<Vec<OutPoint>> {
+    let mut parents = HashMap::<OutPoint, OutPoint>::new();
+    for (txid, tx) in txes {
+        let first_input = match tx.wallet_inputs.first() {
+            Some(&input) => input,
+            None => continue,
+        };
+        for &input in &tx.wallet_inputs[1..] {
+            union(&mut parents, first_input, input);
+        }
+        let linked_vouts = if tx.output_count == 1 {
+            vec![0]
+        } else {
+            tx.change_vouts.clone()
+        };
+        for vout in linked_vouts {
+            union(&mut parents, OutPoint { txid: *txid, vout }, first_input);
+        }
+    }
+
+    let mut clusters = Vec::<Vec<OutPoint>>::new();
+    let mut cluster_of_root = HashMap::<OutPoint, usize>::new();
+    for &coin in coins {
+        let root = find_root(&mut parents, coin);
+        let index = *cluster_of_root.entry(root).or_insert_with(|| {
+            clusters.push(Vec::new());
+            clusters.len() - 1
+        });
+        clusters[index].push(coin);
+    }
+    clusters
+}
+
+/// Indexes of the clusters the coins are in, spending the coins together links these clusters
+pub fn clusters_of_coins(clusters: &[Vec<OutPoint>], coins: &[OutPoint]) -> Vec<usize> {
+    clusters
+        .iter()
+        .enumerate()
+        .filter(|(_, cluster)| cluster.iter().any(|c| coins.contains(c)))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+//whether an output is the wallet's, and if so whether it is change
+fn output_owner(
+    rpc: &Client,
+    wallet: &Wallet,
+    own_label: &str,
+    script: &Script,
+) -> Result<Option<bool>, Error> {
+    let address = match Address::from_script(script, wallet.network) {
+        Some(address) => address,
+        None => return Ok(None),
+    };
+    let info = rpc.call::<Value>("getaddressinfo", &[json!(address.to_string())])?;
+    let is_own = info["labels"]
+        .as_array()
+        .is_some_and(|labels| labels.iter().any(|l| l.as_str() == Some(own_label)));
+    if !is_own {
+        return Ok(None);
+    }
+    let is_change = info["desc"]
+        .as_str()
+        .filter(|desc| desc.contains('['))
+        .and_then(get_hd_path_from_descriptor)
+        .is_some_and(|(_, addr_type, _)| addr_type == 1);
+    Ok(Some(is_change))
+}
+
+fn get_wallet_tx(rpc: &Client, txid: &Txid) -> Result<Option<Transaction>, Error> {
+    match rpc.get_transaction(txid, Some(true)) {
+        Ok(result) => {
+            Ok(Some(result.transaction().map_err(|_| {
+                Error::Protocol("unable to decode wallet transaction")
+            })?))
+        }
+        //not a transaction of the wallet, so none of its outputs are ours
+        Err(_) => Ok(None),
+    }
+}
+
+impl Wallet {
+    /// The transactions which made coins, and those which made the wallet's coins they spent
+    /// back to the first ones paid to the wallet
+    pub fn find_linkage_txes(
+        &self,
+        rpc: &Client,
+        coins: &[OutPoint],
+    ) -> Result<HashMap<Txid, LinkageTx>, Error> {
+        let own_label = self.get_core_wallet_label();
+        let mut txes = HashMap::<Txid, LinkageTx>::new();
+        let mut visited = HashSet::<Txid>::new();
+        let mut to_visit = coins.iter().map(|c| c.txid).collect::<Vec<Txid>>();
+        while let Some(txid) = to_visit.pop() {
+            if !visited.insert(txid) {
+                continue;
+            }
+            let tx = match get_wallet_tx(rpc, &txid)? {
+                Some(tx) => tx,
+                None => continue,
+            };
+            let mut wallet_inputs = Vec::<OutPoint>::new();
+            for input in &tx.input {
+                let prevout = input.previous_output;
+                let prev_tx = match get_wallet_tx(rpc, &prevout.txid)? {
+                    Some(prev_tx) => prev_tx,
+                    None => continue,
+                };
+                let script = match prev_tx.output.get(prevout.vout as usize) {
+                    Some(output) => &output.script_pubkey,
+                    None => continue,
+                };
+                if output_owner(rpc, self, &own_label, script)?.is_some() {
+                    wallet_inputs.push(prevout);
+                    to_visit.push(prevout.txid);
+                }
+            }
+            let mut change_vouts = Vec::<u32>::new();
+            if !wallet_inputs.is_empty() {
+                for (vout, output) in tx.output.iter().enumerate() {
+                    if output_owner(rpc, self, &own_label, &output.script_pubkey)? == Some(true) {
+                        change_vouts.push(vout as u32);
+                    }
+                }
+            }
+            txes.insert(
+                txid,
+                LinkageTx {
+                    wallet_inputs,
+                    change_vouts,
+                    output_count: tx.output.len() as u32,
+                },
+            );
+        }
+        Ok(txes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+
+    fn outpoint(n: u8, vout: u32) -> OutPoint {
+        OutPoint {
+            txid: Txid::from_slice(&[n; 32]).unwrap(),
+            vout,
+        }
+    }
+
+    #[test]
+    fn test_cluster_coins() {
+        //two deposits (1 and 2) spent together in tx 3, which paid someone else and made
+        // change, and a third deposit 4 which was never spent
+        let change = outpoint(3, 1);
+        let untouched = outpoint(4, 0);
+        //deposit 5 swept on its own by tx 6
+        let swept = outpoint(6, 0);
+        let mut txes = HashMap::<Txid, LinkageTx>::new();
+        txes.insert(outpoint(1, 0).txid, LinkageTx::default());
+        txes.insert(outpoint(2, 0).txid, LinkageTx::default());
+        txes.insert(
+            change.txid,
+            LinkageTx {
+                wallet_inputs: vec![outpoint(1, 0), outpoint(2, 0)],
+                change_vouts: vec![1],
+                output_count: 2,
+            },
+        );
+        txes.insert(
+            swept.txid,
+            LinkageTx {
+                wallet_inputs: vec![outpoint(5, 0)],
+                change_vouts: Vec::new(),
+                output_count: 1,
+            },
+        );
+        //an unspent coin paid to the wallet in the same tx as deposit 1, but not as change
+        let sibling = outpoint(1, 1);
+
+        let coins = vec![change, untouched, swept, sibling];
+        let clusters = cluster_coins(&coins, &txes);
+        assert_eq!(
+            clusters,
+            vec![vec![change], vec![untouched], vec![swept], vec![sibling]]
+        );
+        assert_eq!(
+            clusters_of_coins(&clusters, &[untouched, swept]),
+            vec![1, 2]
+        );
+
+        //the other output of tx 3 was also change, so both are linked to deposits 1 and 2
+        txes.get_mut(&change.txid).unwrap().change_vouts = vec![0, 1];
+        let other_change = outpoint(3, 0);
+        let clusters = cluster_coins(&[change, other_change, untouched], &txes);
+        assert_eq!(clusters, vec![vec![change, other_change], vec![untouched]]);
+        assert_eq!(
+            clusters_of_coins(&clusters, &[change, other_change]),
+            vec![0]
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//whether a coin is worth spending, a coin which costs more in miner fees to spend than it
+// is worth is uneconomic and left out of coin selection
+//fee rates here are in sat per 1000 vbytes, like everywhere else in teleport
+
+use bitcoin::OutPoint;
+use bitcoincore_rpc::json::ListUnspentResultEntry;
+use bitcoincore_rpc::RpcApi;
+
+use crate::rpc::Client;
+use crate::wallet_accounts::{group_by_account, WalletAccount};
+use crate::wallet_sync::UTXOSpendInfo;
+
+//vbytes of an input of each type, including its witness
+const P2WPKH_INPUT_VBYTES: u64 = 68;
+const MULTISIG_2OF2_INPUT_VBYTES: u64 = 96;
+const CONTRACT_INPUT_VBYTES: u64 = 95;
+const FIDELITY_BOND_INPUT_VBYTES: u64 = 70;
+
+//version, locktime, segwit marker and input/output counts
+const TX_OVERHEAD_VBYTES: u64 = 11;
+const P2WPKH_OUTPUT_VBYTES: u64 = 31;
+
+//a coin is small if spending it at this fee rate costs more than 5% of its value, a maker
+// with many small coins pays a lot more for its funding txes when fees are high
+const CONSOLIDATION_REFERENCE_FEE_RATE: u64 = 10_000;
+const SMALL_COIN_SPEND_COST_MULTIPLE: u64 = 20;
+
+const FEE_RATE_CONF_TARGET: u16 = 6;
+
+//each is consolidated into a new address of the same account, swap coins are left alone as
+// no address of the wallet is in the swap account
+const CONSOLIDATION_ACCOUNTS: [WalletAccount; 2] = [WalletAccount::Receive, WalletAccount::Change];
+
+pub fn input_vbytes(spend_info: &UTXOSpendInfo) -> u64 {
+    match spend_info {
+        UTXOSpendInfo::SeedCoin { .. } => P2WPKH_INPUT_VBYTES,
+        UTXOSpendInfo::SwapCoin { .. } => MULTISIG_2OF2_INPUT_VBYTES,
+        UTXOSpendInfo::TimelockContract { .. } | UTXOSpendInfo::HashlockContract { .. } => {
+            CONTRACT_INPUT_VBYTES
+        }
+        UTXOSpendInfo::FidelityBondCoin { .. } => FIDELITY_BOND_INPUT_VBYTES,
+    }
+}
+
+/// Miner fee of spending an input of vbytes at fee_rate, rounded up
+pub fn spend_cost_sat(vbytes: u64, fee_rate: u64) -> u64 {
+    (vbytes * fee_rate).div_ceil(1000)
+}
+
+pub fn is_economic(value: u64, spend_info: &UTXOSpendInfo, fee_rate: u64) -> bool {
+    value > spend_cost_sat(input_vbytes(spend_info), fee_rate)
+}
+
+/// Miner fee of a transaction with inputs of inputs_vbytes in total and output_count outputs
+pub fn tx_fee(inputs_vbytes: u64, output_count: u64, fee_rate: u64) -> u64 {
+    spend_cost_sat(
+        TX_OVERHEAD_VBYTES + inputs_vbytes + output_count * P2WPKH_OUTPUT_VBYTES,
+        fee_rate,
+    )
+}
+
+fn coin_size(u: &ListUnspentResultEntry, spend_info: &UTXOSpendInfo) -> (OutPoint, u64, u64) {
+    (
+        OutPoint {
+            txid: u.txid,
+            vout: u.vout,
+        },
+        u.amount.as_sat(),
+        input_vbytes(spend_info),
+    )
+}
+
+/// The coins as (outpoint, value, input vbytes)
+pub fn coin_sizes(
+    unspents: &[(ListUnspentResultEntry, UTXOSpendInfo)],
+) -> Vec<(OutPoint, u64, u64)> {
+    unspents
+        .iter()
+        .map(|(u, spend_info)| coin_size(u, spend_info))
+        .collect()
+}
+
+/// The coins of an account as (outpoint, value, input vbytes)
+pub type AccountCoins = (WalletAccount, Vec<(OutPoint, u64, u64)>);
+
+/// The coins as in coin_sizes, grouped by their account
+pub fn coin_sizes_by_account(
+    unspents: &[(ListUnspentResultEntry, UTXOSpendInfo)],
+) -> Vec<AccountCoins> {
+    group_by_account(
+        unspents
+            .iter()
+            .map(|(u, spend_info)| (spend_info, coin_size(u, spend_info))),
+    )
+}
+
+/// The node's fee rate estimate for confirmation within about an hour, or fallback_fee_rate
+/// if it doesnt have one e.g. on regtest
+pub fn current_fee_rate(rpc: &Client, fallback_fee_rate: u64) -> u64 {
+    match rpc.estimate_smart_fee(FEE_RATE_CONF_TARGET, None) {
+        Ok(estimate) => estimate
+            .fee_rate
+            .map(|fee_rate| fee_rate.as_sat())
+            .unwrap_or(fallback_fee_rate),
+        Err(e) => {
+            tracing::debug!("unable to estimate fee rate: {:?}", e);
+            fallback_fee_rate
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsolidationSuggestion {
+    //not worth spending at the current fee rate
+    pub uneconomic_coins: Vec<OutPoint>,
+    //small coins worth merging into one
+    pub coins: Vec<OutPoint>,
+    pub total_value: u64,
+    //miner fee of the consolidation tx at the current fee rate
+    pub consolidation_fee: u64,
+    //miner fee saved by spending one coin instead of all of them, at the reference fee rate
+    pub fee_saved_later: u64,
+    pub reference_fee_rate: u64,
+}
+
+/// Suggest which coins, given as (outpoint, value, input vbytes), to consolidate, the
+/// smallest max_coins of them if given. None if there arent at least two small coins or
+/// consolidating costs more than it would save
+pub fn suggest_consolidation(
+    coins: &[(OutPoint, u64, u64)],
+    fee_rate: u64,
+    max_coins: Option<usize>,
+) -> Option<ConsolidationSuggestion> {
+    let reference_fee_rate = fee_rate.max(CONSOLIDATION_REFERENCE_FEE_RATE);
+    let (economic, uneconomic): (Vec<&(OutPoint, u64, u64)>, Vec<_>) = coins
+        .iter()
+        .partition(|(_, value, vbytes)| *value > spend_cost_sat(*vbytes, fee_rate));
+    let mut small_coins = economic
+        .into_iter()
+        .filter(|(_, value, vbytes)| {
+            *value < SMALL_COIN_SPEND_COST_MULTIPLE * spend_cost_sat(*vbytes, reference_fee_rate)
+        })
+        .collect::<Vec<&(OutPoint, u64, u64)>>();
+    if let Some(max_coins) = max_coins {
+        small_coins.sort_by_key(|(_, value, _)| *value);
+        small_coins.truncate(max_coins);
+    }
+    if small_coins.len() < 2 {
+        return None;
+    }
+    let inputs_vbytes = small_coins.iter().map(|(_, _, v)| v).sum::<u64>();
+    let consolidation_fee = tx_fee(inputs_vbytes, 1, fee_rate);
+    let fee_saved_later = spend_cost_sat(inputs_vbytes, reference_fee_rate)
+        .saturating_sub(spend_cost_sat(P2WPKH_INPUT_VBYTES, reference_fee_rate));
+    if fee_saved_later <= consolidation_fee {
+        return None;
+    }
+    Some(ConsolidationSuggestion {
+        uneconomic_coins: uneconomic.iter().map(|(o, _, _)| *o).collect(),
+        coins: small_coins.iter().map(|(o, _, _)| *o).collect(),
+        total_value: small_coins.iter().map(|(_, v, _)| v).sum(),
+        consolidation_fee,
+        fee_saved_later,
+        reference_fee_rate,
+    })
+}
+
+/// Suggest a consolidation of each account separately, as coins of different accounts are
+/// never spent together. Coins are given grouped by account as from coin_sizes_by_account
+pub fn suggest_account_consolidations(
+    coins_by_account: &[AccountCoins],
+    fee_rate: u64,
+    max_coins: Option<usize>,
+) -> Vec<(WalletAccount, ConsolidationSuggestion)> {
+    coins_by_account
+        .iter()
+        .filter(|(account, _)| CONSOLIDATION_ACCOUNTS.contains(account))
+        .filter_map(|(account, coins)| {
+            suggest_consolidation(coins, fee_rate, max_coins).map(|s| (*account, s))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::Txid;
+
+    fn coin(vout: u32, value: u64) -> (OutPoint, u64, u64) {
+        (
+            OutPoint {
+                txid: Txid::from_slice(&[1; 32]).unwrap(),
+                vout,
+            },
+            value,
+            P2WPKH_INPUT_VBYTES,
+        )
+    }
+
+    #[test]
+    fn test_coin_viability() {
+        let seed_coin = UTXOSpendInfo::SeedCoin {
+            path: "m/0/0".to_string(),
+            input_value: 0,
+        };
+        //68 vbytes at 10 sat/vb
+        assert_eq!(spend_cost_sat(68, 10_000), 680);
+        assert_eq!(spend_cost_sat(68, 1_500), 102);
+        assert!(!is_economic(680, &seed_coin, 10_000));
+        assert!(is_economic(681, &seed_coin, 10_000));
+        assert!(is_economic(681, &seed_coin, 1_000));
+
+        //at 1 sat/vb the 50 sat coin is uneconomic and the 5000 and 9000 sat coins are small
+        let coins = vec![
+            coin(0, 50),
+            coin(1, 5_000),
+            coin(2, 9_000),
+            coin(3, 1_000_000),
+        ];
+        let suggestion = suggest_consolidation(&coins, 1_000, None).unwrap();
+        assert_eq!(suggestion.uneconomic_coins, vec![coins[0].0]);
+        assert_eq!(suggestion.coins, vec![coins[1].0, coins[2].0]);
+        assert_eq!(suggestion.total_value, 14_000);
+        assert_eq!(suggestion.consolidation_fee, 11 + 68 * 2 + 31);
+        assert_eq!(suggestion.fee_saved_later, 680);
+
+        //nothing to merge with a single small coin
+        assert_eq!(suggest_consolidation(&coins[..2], 1_000, None), None);
+        assert_eq!(suggest_consolidation(&coins, 1_000, Some(1)), None);
+        //at a high fee rate consolidating costs more than it saves
+        assert_eq!(suggest_consolidation(&coins, 50_000, None), None);
+
+        //batches take the smallest coins first
+        let coins = vec![coin(0, 9_000), coin(1, 5_000), coin(2, 7_000)];
+        let suggestion = suggest_consolidation(&coins, 1_000, Some(2)).unwrap();
+        assert_eq!(suggestion.coins, vec![coins[1].0, coins[2].0]);
+    }
+
+    #[test]
+    fn test_account_consolidations() {
+        let seed_coin = |path: &str| UTXOSpendInfo::SeedCoin {
+            path: path.to_string(),
+            input_value: 0,
+        };
+        let swap_coin = UTXOSpendInfo::SwapCoin {
+            multisig_redeemscript: bitcoin::Script::new(),
+        };
+        //small coins in every account, but only one small coin in the change account
+        let coins = vec![
+            (seed_coin("m/0/1"), coin(0, 5_000)),
+            (seed_coin("m/1/1"), coin(1, 6_000)),
+            (seed_coin("m/0/2"), coin(2, 7_000)),
+            (swap_coin.clone(), coin(3, 8_000)),
+            (swap_coin, coin(4, 9_000)),
+            (seed_coin("m/1/2"), coin(5, 1_000_000)),
+        ];
+        let coins_by_account = group_by_account(coins.iter().map(|(s, c)| (s, *c)));
+        let suggestions = suggest_account_consolidations(&coins_by_account, 1_000, None);
+        assert_eq!(suggestions.len(), 1);
+        let (account, suggestion) = &suggestions[0];
+        assert_eq!(*account, WalletAccount::Receive);
+        assert_eq!(suggestion.coins, vec![coins[0].1 .0, coins[2].1 .0]);
+
+        //a second small change coin makes the change account worth consolidating on its own
+        let mut coins = coins;
+        coins.push((seed_coin("m/1/3"), coin(6, 4_000)));
+        let coins_by_account = group_by_account(coins.iter().map(|(s, c)| (s, *c)));
+        let suggestions = suggest_account_consolidations(&coins_by_account, 1_000, None);
+        assert_eq!(
+            suggestions
+                .iter()
+                .map(|(a, s)| (*a, s.coins.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (WalletAccount::Receive, vec![coins[0].1 .0, coins[2].1 .0]),
+                (WalletAccount::Change, vec![coins[1].1 .0, coins[6].1 .0]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//decodes contract redeemscripts and transactions for `teleport decode-contract`, to audit a
+// counterparty's contract or to recover coins by hand
+//a transaction's contract redeemscripts are only in the witnesses of its inputs, so decoding
+// a hashlock or timelock spend finds the contract it spent, and decoding a signed contract
+// transaction finds the 2-of-2 multisig it spent. the contract it pays to is only in its
+// output as a p2wsh address
+
+use bitcoin::consensus::encode::deserialize;
+use bitcoin::hashes::hash160::Hash as Hash160;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::{Address, Network, OutPoint, PublicKey, Script, Transaction, Txid};
+
+use crate::contracts::{
+    create_contract_redeemscript, read_hashlock_pubkey_from_contract, read_hashvalue_from_contract,
+    read_locktime_from_contract, read_pubkeys_from_multisig_redeemscript,
+    read_timelock_pubkey_from_contract,
+};
+use crate::error::Error;
+use crate::wallet_sync::create_multisig_redeemscript;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodedScript {
+    Contract {
+        hashvalue: Hash160,
+        hashlock_pubkey: PublicKey,
+        timelock_pubkey: PublicKey,
+        locktime: u16,
+        address: Address,
+    },
+    Multisig {
+        pubkeys: (PublicKey, PublicKey),
+        address: Address,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedInput {
+    pub previous_output: OutPoint,
+    //None if the input's witness has no contract or multisig redeemscript
+    pub redeemscript: Option<DecodedScript>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodedContract {
+    Redeemscript(DecodedScript),
+    Transaction {
+        txid: Txid,
+        inputs: Vec<DecodedInput>,
+        //value and address of each output
+        outputs: Vec<(u64, Option<Address>)>,
+    },
+}
+
+/// Decode a contract or 2-of-2 multisig redeemscript, only scripts of exactly the form
+/// teleport creates are accepted
+pub fn decode_redeemscript(redeemscript: &Script, network: Network) -> Option<DecodedScript> {
+    let address = Address::p2wsh(redeemscript, network);
+    if let Some((pubkey1, pubkey2)) = read_pubkeys_from_multisig_redeemscript(redeemscript) {
+        if create_multisig_redeemscript(&pubkey1, &pubkey2) == *redeemscript {
+            return Some(DecodedScript::Multisig {
+                pubkeys: (pubkey1, pubkey2),
+                address,
+            });
+        }
+    }
+    let hashvalue = read_hashvalue_from_contract(redeemscript).ok()?;
+    let hashlock_pubkey = read_hashlock_pubkey_from_contract(redeemscript).ok()?;
+    let timelock_pubkey = read_timelock_pubkey_from_contract(redeemscript).ok()?;
+    let locktime = read_locktime_from_contract(redeemscript)?;
+    if create_contract_redeemscript(&hashlock_pubkey, &timelock_pubkey, hashvalue, locktime)
+        != *redeemscript
+    {
+        return None;
+    }
+    Some(DecodedScript::Contract {
+        hashvalue,
+        hashlock_pubkey,
+        timelock_pubkey,
+        locktime,
+        address,
+    })
+}
+
+/// Decode the hex of a contract redeemscript, or of a transaction spending or creating
+/// contracts
+pub fn decode_contract_hex(hex: &str, network: Network) -> Result<DecodedContract, Error> {
+    let bytes = Vec::<u8>::from_hex(hex.trim()).map_err(|_| Error::Protocol("invalid hex"))?;
+    if let Some(decoded) = decode_redeemscript(&Script::from(bytes.clone()), network) {
+        return Ok(DecodedContract::Redeemscript(decoded));
+    }
+    let tx = deserialize::<Transaction>(&bytes)
+        .map_err(|_| Error::Protocol("neither a contract redeemscript nor a transaction"))?;
+    let inputs = tx
+        .input
+        .iter()
+        .map(|input| DecodedInput {
+            previous_output: input.previous_output,
+            //the redeemscript is the last witness element of a p2wsh spend
+            redeemscript: input
+                .witness
+                .last()
+                .and_then(|script| decode_redeemscript(&Script::from(script.clone()), network)),
+        })
+        .collect();
+    let outputs = tx
+        .output
+        .iter()
+        .map(|output| {
+            (
+                output.value,
+                Address::from_script(&output.script_pubkey, network),
+            )
+        })
+        .collect();
+    Ok(DecodedContract::Transaction {
+        txid: tx.txid(),
+        inputs,
+        outputs,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::consensus::encode::serialize_hex;
+    use bitcoin::hashes::hex::ToHex;
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::{self, Secp256k1, SecretKey};
+    use bitcoin::{TxIn, TxOut};
+
+    #[test]
+    fn test_decode_contract() {
+        let secp = Secp256k1::new();
+        let pubkey = |n: u8| PublicKey {
+            compressed: true,
+            key: secp256k1::PublicKey::from_secret_key(
+                &secp,
+                &SecretKey::from_slice(&[n; 32]).unwrap(),
+            ),
+        };
+        let hashlock_pubkey = pubkey(1);
+        let timelock_pubkey = pubkey(2);
+        let hashvalue = Hash160::hash(&[3; 32]);
+        let redeemscript =
+            create_contract_redeemscript(&hashlock_pubkey, &timelock_pubkey, hashvalue, 40);
+        let address = Address::p2wsh(&redeemscript, Network::Regtest);
+        let contract = DecodedScript::Contract {
+            hashvalue,
+            hashlock_pubkey,
+            timelock_pubkey,
+            locktime: 40,
+            address: address.clone(),
+        };
+        assert_eq!(
+            decode_contract_hex(&redeemscript.to_bytes().to_hex(), Network::Regtest).unwrap(),
+            DecodedContract::Redeemscript(contract.clone())
+        );
+        //a script of another form, here a contract with one byte changed
+        let mut other_script = redeemscript.to_bytes();
+        *other_script.last_mut().unwrap() = 0x51;
+        assert!(decode_redeemscript(&Script::from(other_script), Network::Regtest).is_none());
+
+        //a timelock spend of the contract, whose redeemscript is in the witness
+        let multisig_redeemscript =
+            create_multisig_redeemscript(&hashlock_pubkey, &timelock_pubkey);
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![
+                TxIn {
+                    previous_output: OutPoint::default(),
+                    script_sig: Script::new(),
+                    sequence: 40,
+                    witness: vec![vec![1; 71], Vec::new(), redeemscript.to_bytes()],
+                },
+                TxIn {
+                    previous_output: OutPoint::default(),
+                    script_sig: Script::new(),
+                    sequence: 0,
+                    witness: vec![Vec::new(), multisig_redeemscript.to_bytes()],
+                },
+            ],
+            output: vec![TxOut {
+                value: 50_000,
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+        match decode_contract_hex(&serialize_hex(&tx), Network::Regtest).unwrap() {
+            DecodedContract::Transaction {
+                txid,
+                inputs,
+                outputs,
+            } => {
+                assert_eq!(txid, tx.txid());
+                assert_eq!(inputs[0].redeemscript, Some(contract));
+                match &inputs[1].redeemscript {
+                    Some(DecodedScript::Multisig { pubkeys, .. }) => {
+                        assert!(pubkeys.0 == hashlock_pubkey || pubkeys.1 == hashlock_pubkey)
+                    }
+                    _ => panic!("expected a multisig"),
+                }
+                assert_eq!(outputs, vec![(50_000, Some(address))]);
+            }
+            _ => panic!("expected a transaction"),
+        }
+
+        assert!(decode_contract_hex("00", Network::Regtest).is_err());
+        assert!(decode_contract_hex("zz", Network::Regtest).is_err());
+    }
+}
@@ -0,0 +1,428 @@
+//exports the wallet's live contracts to a JSON file, so a watchtower or any other monitoring
+// tool can watch them without the wallet process running
+//transactions and the hash preimage are hex, the format is versioned so other tools can
+// rely on it
+//a contract is live if its coinswap is incomplete or its contract tx was broadcast and the
+// contract output isnt spent yet
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use bitcoin::consensus::encode::{deserialize, serialize_hex};
+use bitcoin::hashes::hash160::Hash as Hash160;
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::util::bip32::{ChildNumber, ExtendedPrivKey};
+use bitcoin::{Address, Network, OutPoint, Script, Transaction, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::contracts::{read_locktime_from_contract, read_timelock_pubkey_from_contract};
+use crate::error::Error;
+use crate::messages::{Preimage, PREIMAGE_LEN};
+use crate::recovery_destinations::DestinationProvider;
+use crate::rpc::Client;
+use crate::wallet_sync::{
+    create_timelock_spend_with_key, IncomingSwapCoin, OutgoingSwapCoin, Wallet, WalletSwapCoin,
+};
+use crate::watchtower_protocol::{ContractTransaction, ContractsInfo};
+
+pub const CONTRACT_EXPORT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContractDirection {
+    //we receive the coins by the hashlock branch
+    Incoming,
+    //we get the coins back by the timelock branch
+    Outgoing,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedContract {
+    pub hashvalue: Hash160,
+    pub direction: ContractDirection,
+    //the 2-of-2 multisig output the contract tx spends
+    pub funding_outpoint: OutPoint,
+    pub funding_amount_sat: u64,
+    pub contract_txid: Txid,
+    pub contract_tx: String,
+    pub redeemscript: Script,
+    //relative locktime of the timelock branch, in blocks
+    pub locktime: Option<u16>,
+    //only one of these two is set, depending on the direction
+    pub hashlock_spend_without_preimage: Option<String>,
+    pub timelock_spend: Option<String>,
+    pub hash_preimage: Option<String>,
+    //outgoing contracts whose timelock key is this child of the maker's cold xpub have no
+    // timelock_spend, sign_cold_timelock_spends makes it with the cold key
+    #[serde(default)]
+    pub cold_timelock_key_index: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractExport {
+    pub version: u32,
+    pub network: String,
+    pub wallet_label: String,
+    pub exported_timestamp: i64,
+    pub contracts: Vec<ExportedContract>,
+}
+
+fn tx_from_hex(hex: &str) -> Result<Transaction, Error> {
+    Vec::<u8>::from_hex(hex)
+        .ok()
+        .and_then(|bytes| deserialize::<Transaction>(&bytes).ok())
+        .ok_or(Error::Protocol("invalid transaction in contract export"))
+}
+
+fn preimage_from_hex(hex: &str) -> Result<Preimage, Error> {
+    let bytes = Vec::<u8>::from_hex(hex)
+        .map_err(|_| Error::Protocol("invalid hash preimage in contract export"))?;
+    if bytes.len() != PREIMAGE_LEN {
+        return Err(Error::Protocol("invalid hash preimage in contract export"));
+    }
+    let mut preimage = [0u8; PREIMAGE_LEN];
+    preimage.copy_from_slice(&bytes);
+    Ok(preimage)
+}
+
+fn export_contract<S: WalletSwapCoin>(
+    swapcoin: &S,
+    contract_tx: &Transaction,
+    direction: ContractDirection,
+    spend: Option<Transaction>,
+    hash_preimage: Option<Preimage>,
+    cold_timelock_key_index: Option<u32>,
+) -> ExportedContract {
+    let signed_contract_tx = swapcoin.get_fully_signed_contract_tx();
+    let redeemscript = swapcoin.get_contract_redeemscript();
+    let spend = spend.map(|tx| serialize_hex(&tx));
+    ExportedContract {
+        hashvalue: swapcoin.get_hashvalue(),
+        direction,
+        funding_outpoint: contract_tx.input[0].previous_output,
+        funding_amount_sat: swapcoin.get_funding_amount(),
+        contract_txid: signed_contract_tx.txid(),
+        contract_tx: serialize_hex(&signed_contract_tx),
+        locktime: read_locktime_from_contract(&redeemscript),
+        redeemscript,
+        hashlock_spend_without_preimage: if direction == ContractDirection::Incoming {
+            spend.clone()
+        } else {
+            None
+        },
+        timelock_spend: if direction == ContractDirection::Outgoing {
+            spend
+        } else {
+            None
+        },
+        hash_preimage: hash_preimage.map(|p| p.to_hex()),
+        cold_timelock_key_index,
+    }
+}
+
+/// Sign the timelock spends of the contracts whose timelock key is a child of cold_xprv,
+/// paying to destination. Returns the contract txid with the spend, which is only valid once
+/// the contract tx has the contract's locktime in confirmations
+pub fn sign_cold_timelock_spends(
+    export: &ContractExport,
+    cold_xprv: &ExtendedPrivKey,
+    destination: &Address,
+) -> Result<Vec<(Txid, Transaction)>, Error> {
+    let secp = Secp256k1::new();
+    let mut spends = Vec::new();
+    for contract in &export.contracts {
+        let index = match contract.cold_timelock_key_index {
+            Some(i) => i,
+            None => continue,
+        };
+        let timelock_privkey = cold_xprv
+            .ckd_priv(&secp, ChildNumber::from_normal_idx(index).unwrap())
+            .unwrap()
+            .private_key;
+        if read_timelock_pubkey_from_contract(&contract.redeemscript)
+            != Ok(timelock_privkey.public_key(&secp))
+        {
+            return Err(Error::Protocol(
+                "cold key isnt the timelock key of the contract",
+            ));
+        }
+        let contract_tx = tx_from_hex(&contract.contract_tx)?;
+        spends.push((
+            contract.contract_txid,
+            create_timelock_spend_with_key(
+                &contract_tx,
+                &contract.redeemscript,
+                &timelock_privkey.key,
+                destination,
+            ),
+        ));
+    }
+    Ok(spends)
+}
+
+impl ContractExport {
+    /// Collect the wallet's live contracts, with spends of them paying to fresh addresses of
+    /// the DestinationProvider
+    pub fn from_wallet(
+        rpc: &Client,
+        wallet: &Wallet,
+        network: Network,
+    ) -> Result<ContractExport, Error> {
+        let mut incoming_swapcoins = Vec::<&IncomingSwapCoin>::new();
+        let mut outgoing_swapcoins = Vec::<&OutgoingSwapCoin>::new();
+        let mut seen_redeemscripts = HashSet::<Script>::new();
+        let incomplete_coinswaps = wallet.find_incomplete_coinswaps(rpc)?;
+        let (live_incoming, live_outgoing) = wallet.find_live_contract_unspents(rpc)?;
+        for isc in incomplete_coinswaps
+            .values()
+            .flat_map(|(incoming, _)| incoming.iter().map(|(_, isc)| *isc))
+            .chain(live_incoming.iter().map(|(isc, _)| *isc))
+        {
+            if seen_redeemscripts.insert(isc.contract_redeemscript.clone()) {
+                incoming_swapcoins.push(isc);
+            }
+        }
+        for osc in incomplete_coinswaps
+            .values()
+            .flat_map(|(_, outgoing)| outgoing.iter().map(|(_, osc)| *osc))
+            .chain(live_outgoing.iter().map(|(osc, _)| *osc))
+        {
+            if seen_redeemscripts.insert(osc.contract_redeemscript.clone()) {
+                outgoing_swapcoins.push(osc);
+            }
+        }
+
+        //every spend gets an address of its own
+        let addresses = DestinationProvider::from_settings().next_addresses(
+            wallet,
+            rpc,
+            (incoming_swapcoins.len() + outgoing_swapcoins.len()) as u32,
+        )?;
+        let (incoming_addresses, outgoing_addresses) = addresses.split_at(incoming_swapcoins.len());
+        let contracts = incoming_swapcoins
+            .iter()
+            .zip(incoming_addresses.iter())
+            .map(|(isc, addr)| {
+                export_contract(
+                    *isc,
+                    &isc.contract_tx,
+                    ContractDirection::Incoming,
+                    Some(isc.create_hashlock_spend_without_preimage(addr)),
+                    isc.hash_preimage,
+                    None,
+                )
+            })
+            .chain(
+                outgoing_swapcoins
+                    .iter()
+                    .zip(outgoing_addresses.iter())
+                    .map(|(osc, addr)| {
+                        export_contract(
+                            *osc,
+                            &osc.contract_tx,
+                            ContractDirection::Outgoing,
+                            osc.create_timelock_spend(addr),
+                            osc.hash_preimage,
+                            osc.cold_timelock_key_index,
+                        )
+                    }),
+            )
+            .collect();
+        Ok(ContractExport {
+            version: CONTRACT_EXPORT_VERSION,
+            network: network.to_string(),
+            wallet_label: wallet.get_core_wallet_label(),
+            exported_timestamp: chrono::Utc::now().timestamp(),
+            contracts,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<ContractExport, Error> {
+        let export = serde_json::from_reader::<_, ContractExport>(File::open(path)?)
+            .map_err(|_| Error::Protocol("unable to parse contract export"))?;
+        if export.version != CONTRACT_EXPORT_VERSION {
+            return Err(Error::Protocol("unsupported contract export version"));
+        }
+        Ok(export)
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), Error> {
+        serde_json::to_writer_pretty(writer, self).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    /// The contracts grouped into one ContractsInfo per coinswap, the way makers register
+    /// them with watchtowers, along with the hash preimage if it is known
+    pub fn to_contracts_infos(&self) -> Result<Vec<(ContractsInfo, Option<Preimage>)>, Error> {
+        let mut coinswaps =
+            BTreeMap::<Hash160, (Vec<ContractTransaction>, Option<Preimage>)>::new();
+        for contract in &self.contracts {
+            let (contract_txes, preimage) = coinswaps.entry(contract.hashvalue).or_default();
+            contract_txes.push(ContractTransaction {
+                tx: tx_from_hex(&contract.contract_tx)?,
+                redeemscript: contract.redeemscript.clone(),
+                hashlock_spend_without_preimage: contract
+                    .hashlock_spend_without_preimage
+                    .as_deref()
+                    .map(tx_from_hex)
+                    .transpose()?,
+                timelock_spend: contract
+                    .timelock_spend
+                    .as_deref()
+                    .map(tx_from_hex)
+                    .transpose()?,
+                timelock_spend_broadcasted: false,
+                hashlock_spend_broadcasted: false,
+            });
+            if let Some(hex) = &contract.hash_preimage {
+                *preimage = Some(preimage_from_hex(hex)?);
+            }
+        }
+        Ok(coinswaps
+            .into_values()
+            .map(|(contract_txes, preimage)| {
+                (
+                    ContractsInfo {
+                        contract_txes,
+                        wallet_label: self.wallet_label.clone(),
+                    },
+                    preimage,
+                )
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::util::bip32::ExtendedPubKey;
+    use bitcoin::{TxIn, TxOut};
+
+    use crate::contracts::create_contract_redeemscript;
+    use crate::wallet_sync::{derive_cold_timelock_pubkey, generate_keypair};
+
+    fn contract(hashvalue_byte: u8, direction: ContractDirection) -> ExportedContract {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: 10_000 + hashvalue_byte as u64,
+                script_pubkey: Script::new(),
+            }],
+        };
+        let spend = Some(serialize_hex(&tx));
+        ExportedContract {
+            hashvalue: Hash160::hash(&[hashvalue_byte]),
+            direction,
+            funding_outpoint: OutPoint::default(),
+            funding_amount_sat: 10_000,
+            contract_txid: tx.txid(),
+            contract_tx: serialize_hex(&tx),
+            redeemscript: Script::new(),
+            locktime: Some(20),
+            hashlock_spend_without_preimage: if direction == ContractDirection::Incoming {
+                spend.clone()
+            } else {
+                None
+            },
+            timelock_spend: if direction == ContractDirection::Outgoing {
+                spend
+            } else {
+                None
+            },
+            hash_preimage: None,
+            cold_timelock_key_index: None,
+        }
+    }
+
+    #[test]
+    fn test_contract_export() {
+        let mut with_preimage = contract(1, ContractDirection::Outgoing);
+        with_preimage.hash_preimage = Some([7u8; PREIMAGE_LEN].to_hex());
+        let mut export = ContractExport {
+            version: CONTRACT_EXPORT_VERSION,
+            network: "regtest".to_string(),
+            wallet_label: "abcd1234".to_string(),
+            exported_timestamp: 1000,
+            contracts: vec![
+                contract(1, ContractDirection::Incoming),
+                contract(2, ContractDirection::Incoming),
+                with_preimage,
+            ],
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("contracts.json");
+        export.write(File::create(&path).unwrap()).unwrap();
+        assert_eq!(ContractExport::load(&path).unwrap(), export);
+
+        let infos = export.to_contracts_infos().unwrap();
+        assert_eq!(infos.len(), 2);
+        let (info, preimage) = infos
+            .iter()
+            .find(|(info, _)| info.contract_txes.len() == 2)
+            .unwrap();
+        assert_eq!(info.wallet_label, "abcd1234");
+        assert_eq!(*preimage, Some([7u8; PREIMAGE_LEN]));
+        assert_eq!(
+            info.contract_txes[0].tx.txid(),
+            export.contracts[0].contract_txid
+        );
+        assert!(info.contract_txes[0]
+            .hashlock_spend_without_preimage
+            .is_some());
+        assert!(info.contract_txes[1].timelock_spend.is_some());
+
+        export.contracts[1].contract_tx = "00".to_string();
+        assert!(export.to_contracts_infos().is_err());
+        export.version = 2;
+        export.write(File::create(&path).unwrap()).unwrap();
+        assert!(ContractExport::load(&path).is_err());
+    }
+
+    #[test]
+    fn test_sign_cold_timelock_spends() {
+        let secp = Secp256k1::new();
+        let cold_xprv = ExtendedPrivKey::new_master(Network::Regtest, &[5u8; 32]).unwrap();
+        let cold_xpub = ExtendedPubKey::from_private(&secp, &cold_xprv);
+        let hashlock_pubkey = generate_keypair().0;
+        let redeemscript = create_contract_redeemscript(
+            &hashlock_pubkey,
+            &derive_cold_timelock_pubkey(&cold_xpub, 77),
+            Hash160::hash(&[1]),
+            20,
+        );
+        let mut cold_contract = contract(1, ContractDirection::Outgoing);
+        cold_contract.redeemscript = redeemscript;
+        cold_contract.timelock_spend = None;
+        cold_contract.cold_timelock_key_index = Some(77);
+        let mut export = ContractExport {
+            version: CONTRACT_EXPORT_VERSION,
+            network: "regtest".to_string(),
+            wallet_label: "abcd1234".to_string(),
+            exported_timestamp: 1000,
+            contracts: vec![contract(2, ContractDirection::Incoming), cold_contract],
+        };
+        let destination = Address::p2wpkh(&hashlock_pubkey, Network::Regtest).unwrap();
+
+        let spends = sign_cold_timelock_spends(&export, &cold_xprv, &destination).unwrap();
+        assert_eq!(spends.len(), 1);
+        let (contract_txid, spend) = &spends[0];
+        assert_eq!(*contract_txid, export.contracts[1].contract_txid);
+        assert_eq!(spend.input[0].previous_output.txid, *contract_txid);
+        assert_eq!(spend.input[0].sequence, 20);
+        assert_eq!(spend.input[0].witness.len(), 3);
+        assert_eq!(spend.output[0].script_pubkey, destination.script_pubkey());
+
+        let other_xprv = ExtendedPrivKey::new_master(Network::Regtest, &[6u8; 32]).unwrap();
+        assert!(sign_cold_timelock_spends(&export, &other_xprv, &destination).is_err());
+        export.contracts[1].cold_timelock_key_index = Some(78);
+        assert!(sign_cold_timelock_spends(&export, &cold_xprv, &destination).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+//builds and signs the spend of a contract output by hand for `teleport spend-contract`, the
+// escape hatch when automated recovery cant be used, e.g. the swapcoin is missing from the
+// wallet file or the key is only known as WIF
+//  hashlock  needs the hash preimage, spendable once the contract tx has 1 confirmation
+//  timelock  spendable once the contract tx has the contract's locktime in confirmations
+
+use bitcoin::hashes::hash160::Hash as Hash160;
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::{self, Secp256k1, SecretKey};
+use bitcoin::util::bip143::SigHashCache;
+use bitcoin::{
+    Address, Network, OutPoint, PublicKey, Script, SigHashType, Transaction, TxIn, TxOut,
+};
+
+use crate::contract_decoder::{decode_redeemscript, DecodedScript};
+use crate::error::Error;
+use crate::messages::PREIMAGE_LEN;
+
+//sizes of the spends with a p2wpkh output, calculated using testmempoolaccept
+pub const HASHLOCK_SPEND_VBYTES: u64 = 136;
+pub const TIMELOCK_SPEND_VBYTES: u64 = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContractBranch {
+    //with the hash preimage
+    Hashlock(Vec<u8>),
+    Timelock,
+}
+
+//the parts of the contract which the spend needs
+struct ContractSpendInfo {
+    branch_pubkey: PublicKey,
+    //relative locktime of the branch, which the spend's nSequence must have
+    sequence: u32,
+    vbytes: u64,
+}
+
+fn contract_spend_info(
+    redeemscript: &Script,
+    branch: &ContractBranch,
+) -> Result<ContractSpendInfo, Error> {
+    let (hashvalue, hashlock_pubkey, timelock_pubkey, locktime) =
+        match decode_redeemscript(redeemscript, Network::Bitcoin) {
+            Some(DecodedScript::Contract {
+                hashvalue,
+                hashlock_pubkey,
+                timelock_pubkey,
+                locktime,
+                ..
+            }) => (hashvalue, hashlock_pubkey, timelock_pubkey, locktime),
+            _ => return Err(Error::Protocol("not a contract redeemscript")),
+        };
+    match branch {
+        ContractBranch::Hashlock(preimage) => {
+            if preimage.len() != PREIMAGE_LEN || Hash160::hash(preimage) != hashvalue {
+                return Err(Error::Protocol(
+                    "preimage doesnt match the contract's hashvalue",
+                ));
+            }
+            Ok(ContractSpendInfo {
+                branch_pubkey: hashlock_pubkey,
+                //hashlock spends must have 1 because of the `OP_CSV 1`
+                sequence: 1,
+                vbytes: HASHLOCK_SPEND_VBYTES,
+            })
+        }
+        ContractBranch::Timelock => Ok(ContractSpendInfo {
+            branch_pubkey: timelock_pubkey,
+            sequence: locktime as u32,
+            vbytes: TIMELOCK_SPEND_VBYTES,
+        }),
+    }
+}
+
+/// Confirmations the contract output needs before the branch can spend it
+pub fn required_confirmations(
+    redeemscript: &Script,
+    branch: &ContractBranch,
+) -> Result<u32, Error> {
+    Ok(contract_spend_info(redeemscript, branch)?.sequence)
+}
+
+/// Spend the contract output at contract_outpoint, worth input_value, by the branch, signed
+/// with privkey which must be the key of that branch. The whole value minus the miner fee at
+/// fee_rate (sats per thousand vbytes) is paid to destination
+pub fn create_contract_spend(
+    contract_outpoint: OutPoint,
+    input_value: u64,
+    redeemscript: &Script,
+    branch: &ContractBranch,
+    privkey: &SecretKey,
+    destination: &Address,
+    fee_rate: u64,
+) -> Result<Transaction, Error> {
+    let info = contract_spend_info(redeemscript, branch)?;
+    let secp = Secp256k1::new();
+    if secp256k1::PublicKey::from_secret_key(&secp, privkey) != info.branch_pubkey.key {
+        return Err(Error::Protocol("key isnt the key of the contract's branch"));
+    }
+    let miner_fee = info.vbytes * fee_rate / 1000;
+    if input_value <= miner_fee + destination.script_pubkey().dust_value() {
+        return Err(Error::Protocol("contract too small to pay the miner fee"));
+    }
+    let mut tx = Transaction {
+        input: vec![TxIn {
+            previous_output: contract_outpoint,
+            sequence: info.sequence,
+            witness: Vec::new(),
+            script_sig: Script::new(),
+        }],
+        output: vec![TxOut {
+            script_pubkey: destination.script_pubkey(),
+            value: input_value - miner_fee,
+        }],
+        lock_time: 0,
+        version: 2,
+    };
+    let sighash = secp256k1::Message::from_slice(
+        &SigHashCache::new(&tx).signature_hash(0, redeemscript, input_value, SigHashType::All)[..],
+    )
+    .unwrap();
+    let mut sig = secp.sign(&sighash, privkey).serialize_der().to_vec();
+    sig.push(SigHashType::All as u8);
+    let branch_element = match branch {
+        ContractBranch::Hashlock(preimage) => preimage.clone(),
+        ContractBranch::Timelock => Vec::new(),
+    };
+    tx.input[0].witness = vec![sig, branch_element, redeemscript.to_bytes()];
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::contracts::create_contract_redeemscript;
+
+    #[test]
+    fn test_create_contract_spend() {
+        let secp = Secp256k1::new();
+        let hashlock_privkey = SecretKey::from_slice(&[1; 32]).unwrap();
+        let timelock_privkey = SecretKey::from_slice(&[2; 32]).unwrap();
+        let pubkey = |privkey: &SecretKey| PublicKey {
+            compressed: true,
+            key: secp256k1::PublicKey::from_secret_key(&secp, privkey),
+        };
+        let preimage = vec![3; PREIMAGE_LEN];
+        let redeemscript = create_contract_redeemscript(
+            &pubkey(&hashlock_privkey),
+            &pubkey(&timelock_privkey),
+            Hash160::hash(&preimage),
+            40,
+        );
+        let outpoint = OutPoint::default();
+        let destination = Address::p2wpkh(&pubkey(&hashlock_privkey), Network::Regtest).unwrap();
+        let hashlock = ContractBranch::Hashlock(preimage.clone());
+
+        let tx = create_contract_spend(
+            outpoint,
+            100_000,
+            &redeemscript,
+            &hashlock,
+            &hashlock_privkey,
+            &destination,
+            2000,
+        )
+        .unwrap();
+        assert_eq!(tx.input[0].sequence, 1);
+        assert_eq!(tx.input[0].witness[1], preimage);
+        assert_eq!(tx.output[0].value, 100_000 - 2 * HASHLOCK_SPEND_VBYTES);
+        assert_eq!(required_confirmations(&redeemscript, &hashlock).unwrap(), 1);
+
+        let tx = create_contract_spend(
+            outpoint,
+            100_000,
+            &redeemscript,
+            &ContractBranch::Timelock,
+            &timelock_privkey,
+            &destination,
+            1000,
+        )
+        .unwrap();
+        assert_eq!(tx.input[0].sequence, 40);
+        assert!(tx.input[0].witness[1].is_empty());
+        assert_eq!(
+            required_confirmations(&redeemscript, &ContractBranch::Timelock).unwrap(),
+            40
+        );
+
+        //the key of the other branch, a wrong preimage and too small a contract
+        assert!(create_contract_spend(
+            outpoint,
+            100_000,
+            &redeemscript,
+            &ContractBranch::Timelock,
+            &hashlock_privkey,
+            &destination,
+            1000,
+        )
+        .is_err());
+        assert!(create_contract_spend(
+            outpoint,
+            100_000,
+            &redeemscript,
+            &ContractBranch::Hashlock(vec![4; PREIMAGE_LEN]),
+            &hashlock_privkey,
+            &destination,
+            1000,
+        )
+        .is_err());
+        assert!(create_contract_spend(
+            outpoint,
+            400,
+            &redeemscript,
+            &hashlock,
+            &hashlock_privkey,
+            &destination,
+            1000,
+        )
+        .is_err());
+    }
+}
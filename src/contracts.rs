@@ -15,13 +15,14 @@ use bitcoin::{
     OutPoint, SigHashType, Transaction, TxIn, TxOut,
 };
 
-use bitcoincore_rpc::{Client, RpcApi};
+use bitcoincore_rpc::RpcApi;
 
 use rand::rngs::OsRng;
-use rand::RngCore;
+use rand::{CryptoRng, RngCore};
 
 use crate::error::Error;
-use crate::messages::ConfirmedCoinSwapTxInfo;
+use crate::messages::{ConfirmedCoinSwapTxInfo, ConfirmsTier};
+use crate::rpc::Client;
 use crate::wallet_sync::{
     create_multisig_redeemscript, IncomingSwapCoin, OutgoingSwapCoin, Wallet,
 };
@@ -41,6 +42,10 @@ use crate::wallet_sync::{
 // to pay for
 pub const MAKER_FUNDING_TX_VBYTE_SIZE: u64 = 372;
 
+//a signature verification takes tens of microseconds, so starting a thread is only worth it
+// for several of them
+const MIN_SIGS_PER_VERIFY_THREAD: usize = 4;
+
 //like the Incoming/OutgoingSwapCoin structs but no privkey or signature information
 //used by the taker to monitor coinswaps between two makers
 #[derive(Debug, Clone)]
@@ -52,7 +57,7 @@ pub struct WatchOnlySwapCoin {
     pub funding_amount: u64,
 }
 
-pub trait SwapCoin {
+pub trait SwapCoin: Sync {
     fn get_multisig_redeemscript(&self) -> Script;
     fn get_contract_tx(&self) -> Transaction;
     fn get_contract_redeemscript(&self) -> Script;
@@ -79,6 +84,160 @@ pub fn calculate_coinswap_fee(
         + (time_in_blocks * time_relative_fee_ppb / 1_000_000_000)
 }
 
+//bigger coinswaps are worth more to an attacker trying to reorg out a funding tx
+// so they may need more confirmations
+pub fn calculate_required_confirms(
+    required_confirms: i32,
+    required_confirms_tiers: &[ConfirmsTier],
+    amount: u64,
+) -> i32 {
+    required_confirms_tiers
+        .iter()
+        .filter(|tier| amount >= tier.amount)
+        .map(|tier| tier.required_confirms)
+        .fold(required_confirms, i32::max)
+}
+
+//bigger coinswaps may also be given a shorter maximum locktime, so the maker's coins are tied
+// up for less time if the coinswap fails
+pub fn calculate_maximum_locktime(
+    maximum_locktime: Option<u16>,
+    required_confirms_tiers: &[ConfirmsTier],
+    amount: u64,
+) -> Option<u16> {
+    required_confirms_tiers
+        .iter()
+        .filter(|tier| amount >= tier.amount)
+        .filter_map(|tier| tier.maximum_locktime)
+        .fold(maximum_locktime, |max, locktime| {
+            Some(max.map_or(locktime, |max| max.min(locktime)))
+        })
+}
+
+//the coinswap fee scaled by the multiplier of the biggest tier the amount reaches which has one
+pub fn apply_fee_multiplier(
+    coinswap_fee: u64,
+    required_confirms_tiers: &[ConfirmsTier],
+    amount: u64,
+) -> u64 {
+    required_confirms_tiers
+        .iter()
+        .filter(|tier| amount >= tier.amount)
+        .filter_map(|tier| {
+            tier.fee_multiplier_percent
+                .map(|percent| (tier.amount, percent))
+        })
+        .max_by_key(|(tier_amount, _)| *tier_amount)
+        .map_or(coinswap_fee, |(_, percent)| coinswap_fee * percent / 100)
+}
+
+//fee rates are in sats per 1000 vbytes, same as the fee_rate used for creating funding txes
+pub fn check_fee_rate(fee: u64, vsize: u64, min_fee_rate: u64) -> Result<(), Error> {
+    if fee * 1000 < min_fee_rate * vsize {
+        return Err(Error::FundingTxFeeRate {
+            fee_rate: fee * 1000 / vsize.max(1),
+            min_fee_rate,
+        });
+    }
+    Ok(())
+}
+
+//getrawtransaction only finds confirmed txes of the node's wallet or with -txindex, otherwise
+// any unspent output of the tx in the utxo set shows it is confirmed
+fn is_tx_confirmed<R: RpcApi>(rpc: &R, tx: &Transaction) -> Result<bool, Error> {
+    let txid = tx.txid();
+    if let Ok(info) = rpc.get_raw_transaction_info(&txid, None) {
+        return Ok(info.confirmations.unwrap_or(0) > 0);
+    }
+    for vout in 0..tx.output.len() as u32 {
+        if let Some(txout) = rpc.get_tx_out(&txid, vout, Some(false))? {
+            return Ok(txout.confirmations > 0);
+        }
+    }
+    Ok(false)
+}
+
+//a funding tx paying too low a fee rate might never confirm, leaving the receiving party
+// waiting forever while the sender is still able to double spend its inputs
+//txes in the mempool are checked, a tx which is already confirmed obviously paid enough and
+// a tx which is in neither, for example because its fee is too low for the mempool, is rejected
+pub fn verify_funding_tx_fee_rate<R: RpcApi>(
+    rpc: &R,
+    tx: &Transaction,
+    min_fee_rate: u64,
+) -> Result<(), Error> {
+    match rpc.get_mempool_entry(&tx.txid()) {
+        Ok(mempool_entry) => check_fee_rate(
+            mempool_entry.fees.base.as_sat(),
+            mempool_entry.vsize,
+            min_fee_rate,
+        ),
+        Err(_) if is_tx_confirmed(rpc, tx)? => Ok(()),
+        Err(_) => Err(Error::Protocol(
+            "funding tx is neither in the mempool nor confirmed",
+        )),
+    }
+}
+
+//core gives a wallet tx which conflicts with a confirmed tx negative confirmations
+//a tx in the mempool cant conflict with another tx there, so an unconfirmed tx conflicts with
+// one in the mempool when it has left the mempool and its inputs are spent by something else
+fn is_funding_tx_conflicted(
+    confirmations: i32,
+    in_mempool: bool,
+    inputs_spent: bool,
+    outputs_exist: bool,
+) -> bool {
+    confirmations < 0 || (confirmations == 0 && !in_mempool && inputs_spent && !outputs_exist)
+}
+
+//a funding tx which isnt confirmed yet can still be replaced with rbf or double spent, so it
+// is checked again every time until it confirms
+pub fn check_funding_tx_conflicts(
+    rpc: &Client,
+    funding_tx: &Transaction,
+    confirmations: i32,
+) -> Result<(), Error> {
+    if confirmations > 0 {
+        return Ok(());
+    }
+    let txid = funding_tx.txid();
+    let in_mempool = rpc.get_mempool_entry(&txid).is_ok();
+    let mut inputs_spent = false;
+    let mut outputs_exist = false;
+    if confirmations == 0 && !in_mempool {
+        for input in &funding_tx.input {
+            let prevout = input.previous_output;
+            if rpc
+                .get_tx_out(&prevout.txid, prevout.vout, Some(true))?
+                .is_none()
+            {
+                inputs_spent = true;
+                break;
+            }
+        }
+        //the inputs are also spent if the tx confirmed since its confirmations were read
+        if inputs_spent {
+            for vout in 0..funding_tx.output.len() as u32 {
+                if rpc.get_tx_out(&txid, vout, Some(true))?.is_some() {
+                    outputs_exist = true;
+                    break;
+                }
+            }
+        }
+    }
+    if is_funding_tx_conflicted(confirmations, in_mempool, inputs_spent, outputs_exist) {
+        tracing::warn!(
+            "funding tx {} conflicts with another tx spending its inputs",
+            txid
+        );
+        return Err(Error::Protocol(
+            "funding tx conflicts with another tx spending its inputs",
+        ));
+    }
+    Ok(())
+}
+
 pub fn redeemscript_to_scriptpubkey(redeemscript: &Script) -> Script {
     //p2wsh address
     Script::new_witness_program(
@@ -102,9 +261,17 @@ pub fn calculate_maker_pubkey_from_nonce(
 
 pub fn derive_maker_pubkey_and_nonce(
     tweakable_point: PublicKey,
+) -> Result<(PublicKey, SecretKey), secp256k1::Error> {
+    derive_maker_pubkey_and_nonce_with_rng(tweakable_point, &mut OsRng)
+}
+
+//tests and fuzzers can pass a seeded rng to get the same nonces on every run
+pub fn derive_maker_pubkey_and_nonce_with_rng<R: RngCore + CryptoRng>(
+    tweakable_point: PublicKey,
+    rng: &mut R,
 ) -> Result<(PublicKey, SecretKey), secp256k1::Error> {
     let mut nonce_bytes = [0u8; 32];
-    OsRng.fill_bytes(&mut nonce_bytes);
+    rng.fill_bytes(&mut nonce_bytes);
     let nonce = SecretKey::from_slice(&nonce_bytes)?;
     let maker_pubkey = calculate_maker_pubkey_from_nonce(tweakable_point, nonce)?;
 
@@ -204,6 +371,8 @@ pub fn read_hashvalue_from_contract(redeemscript: &Script) -> Result<Hash160, &'
 pub fn read_locktime_from_contract(redeemscript: &Script) -> Option<u16> {
     match redeemscript.instructions().nth(12)?.ok()? {
         Instruction::PushBytes(locktime_bytes) => match locktime_bytes.len() {
+            //a locktime of 0 is pushed as OP_0, which is an empty push
+            0 => Some(0),
             1 => Some(locktime_bytes[0] as u16),
             2 | 3 => {
                 let (int_bytes, _rest) = locktime_bytes.split_at(std::mem::size_of::<u16>());
@@ -243,6 +412,9 @@ pub fn read_pubkeys_from_multisig_redeemscript(
     redeemscript: &Script,
 ) -> Option<(PublicKey, PublicKey)> {
     let ms_rs_bytes = redeemscript.to_bytes();
+    if ms_rs_bytes.len() < 69 {
+        return None;
+    }
     //TODO put these magic numbers in consts, PUBKEY1_OFFSET maybe
     let pubkey1 = PublicKey::from_slice(&ms_rs_bytes[2..35]);
     let pubkey2 = PublicKey::from_slice(&ms_rs_bytes[36..69]);
@@ -384,23 +556,67 @@ pub fn find_funding_output<'a>(
         .find(|(_i, o)| o.script_pubkey == multisig_spk)
 }
 
-//returns the keys of the multisig, ready for importing
-//or None if the proof is invalid for some reason
-//or an error if the RPC connection fails
+/// A funding output of a proof of funding which passed verify_proof_of_funding, along with the
+/// maker's keys for it
+#[derive(Debug, Clone)]
+pub struct VerifiedFunding {
+    pub funding_outpoint: OutPoint,
+    pub funding_amount: u64,
+    //derived from the pubkeys, so it is the one the maker's swapcoin uses
+    pub multisig_redeemscript: Script,
+    pub contract_redeemscript: Script,
+    pub my_multisig_privkey: SecretKey,
+    pub other_multisig_pubkey: PublicKey,
+    pub hashlock_privkey: SecretKey,
+}
+
+impl VerifiedFunding {
+    /// The maker's swapcoin of the funding output, with the receivers contract tx spending it
+    pub fn to_incoming_swapcoin(&self) -> IncomingSwapCoin {
+        let receivers_contract_tx = create_receivers_contract_tx(
+            self.funding_outpoint,
+            self.funding_amount,
+            &self.contract_redeemscript,
+        );
+        IncomingSwapCoin::new(
+            self.my_multisig_privkey,
+            self.other_multisig_pubkey,
+            receivers_contract_tx,
+            self.contract_redeemscript.clone(),
+            self.hashlock_privkey,
+            self.funding_amount,
+        )
+    }
+}
+
+//returns the funding output and the keys of the multisig, ready for importing
+//or an error if the proof is invalid for some reason or the RPC connection fails
+//tweakable_keypair is the one of the maker instance whose offer the taker used
 pub fn verify_proof_of_funding(
     rpc: Arc<Client>,
     wallet: &mut Wallet,
+    tweakable_keypair: (SecretKey, PublicKey),
     funding_info: &ConfirmedCoinSwapTxInfo,
-    funding_output_index: u32,
     next_locktime: u16,
     min_contract_react_time: u16,
-    //returns my_multisig_privkey, other_multisig_pubkey, my_hashlock_privkey
-) -> Result<(SecretKey, PublicKey, SecretKey), Error> {
+    required_confirms: i32,
+) -> Result<VerifiedFunding, Error> {
+    //check that the claimed multisig redeemscript is in the transaction
+    let (funding_output_index, funding_output) = find_funding_output(
+        &funding_info.funding_tx,
+        &funding_info.multisig_redeemscript,
+    )
+    .ok_or(Error::Protocol("funding tx doesnt pay to multisig"))?;
+    let funding_outpoint = OutPoint {
+        txid: funding_info.funding_tx.txid(),
+        vout: funding_output_index,
+    };
+
     //check the funding_tx exists and was really confirmed
     if let Some(txout) =
         rpc.get_tx_out(&funding_info.funding_tx.txid(), funding_output_index, None)?
     {
-        if txout.confirmations < 1 {
+        if (txout.confirmations as i32) < required_confirms {
             return Err(Error::Protocol("funding tx not confirmed"));
         }
     } else {
@@ -428,7 +644,7 @@ pub fn verify_proof_of_funding(
     let (pubkey1, pubkey2) =
         read_pubkeys_from_multisig_redeemscript(&funding_info.multisig_redeemscript)
             .ok_or(Error::Protocol("invalid multisig_redeemscript"))?;
-    let (tweakable_privkey, tweakable_point) = wallet.get_tweakable_keypair();
+    let (tweakable_privkey, tweakable_point) = tweakable_keypair;
     let my_pubkey =
         calculate_maker_pubkey_from_nonce(tweakable_point, funding_info.multisig_key_nonce)
             .map_err(|_| Error::Protocol("unable to calculate maker pubkey from nonce"))?;
@@ -463,13 +679,7 @@ pub fn verify_proof_of_funding(
     //cache which was populated when the signsendercontracttx message arrived
     let contract_spk = redeemscript_to_scriptpubkey(&funding_info.contract_redeemscript);
 
-    if !wallet.does_prevout_match_cached_contract(
-        &OutPoint {
-            txid: funding_info.funding_tx.txid(),
-            vout: funding_output_index,
-        },
-        &contract_spk,
-    )? {
+    if !wallet.does_prevout_match_cached_contract(&funding_outpoint, &contract_spk)? {
         return Err(Error::Protocol(
             "provided contract does not match sender contract tx, rejecting",
         ));
@@ -489,7 +699,15 @@ pub fn verify_proof_of_funding(
     } else {
         pubkey1
     };
-    Ok((my_privkey, other_pubkey, hashlock_privkey))
+    Ok(VerifiedFunding {
+        funding_outpoint,
+        funding_amount: funding_output.value,
+        multisig_redeemscript: create_multisig_redeemscript(&pubkey1, &pubkey2),
+        contract_redeemscript: funding_info.contract_redeemscript.clone(),
+        my_multisig_privkey: my_privkey,
+        other_multisig_pubkey: other_pubkey,
+        hashlock_privkey,
+    })
 }
 
 pub fn validate_contract_tx(
@@ -551,10 +769,35 @@ fn verify_contract_tx_sig(
         Ok(sig) => sig,
         Err(_) => return false,
     };
-    let secp = Secp256k1::new();
+    let secp = Secp256k1::verification_only();
     secp.verify(&sighash, sig, &pubkey.key).is_ok()
 }
 
+/// Whether verify passes for every item, with the items split between threads when there are
+/// enough of them, so the signatures of coinswaps with many funding txes are checked faster
+pub fn verify_all_parallel<T, F>(items: &[T], verify: F) -> bool
+where
+    T: Sync,
+    F: Fn(&T) -> bool + Sync,
+{
+    let thread_count = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(items.len() / MIN_SIGS_PER_VERIFY_THREAD);
+    if thread_count < 2 {
+        return items.iter().all(verify);
+    }
+    let verify = &verify;
+    std::thread::scope(|scope| {
+        items
+            .chunks(items.len().div_ceil(thread_count))
+            .map(|chunk| scope.spawn(move || chunk.iter().all(verify)))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .all(|handle| handle.join().unwrap_or(false))
+    })
+}
+
 macro_rules! add_simple_swapcoin_get_functions {
     () => {
         //unwrap() here because previously checked that contract_redeemscript is good
@@ -788,7 +1031,8 @@ mod test {
     use bitcoin::consensus::encode::deserialize;
     use bitcoin::hashes::hex::{FromHex, ToHex};
     use bitcoin::PrivateKey;
-    use rand::{thread_rng, Rng};
+    use rand::rngs::StdRng;
+    use rand::{thread_rng, Rng, SeedableRng};
     use std::str::FromStr;
     use std::string::String;
 
@@ -805,6 +1049,107 @@ mod test {
         Ok((hashpub, timepub))
     }
 
+    #[test]
+    fn test_verify_all_parallel() {
+        let items = (0..100u64).collect::<Vec<u64>>();
+        assert!(verify_all_parallel(&items, |i| *i < 100));
+        assert!(!verify_all_parallel(&items, |i| *i != 73));
+        assert!(!verify_all_parallel(&items[..3], |i| *i != 2));
+        assert!(verify_all_parallel(&Vec::<u64>::new(), |_| false));
+    }
+
+    #[test]
+    fn test_calculate_required_confirms() {
+        let tiers = [
+            ConfirmsTier {
+                amount: 1_000_000,
+                required_confirms: 2,
+                ..ConfirmsTier::default()
+            },
+            ConfirmsTier {
+                amount: 100_000_000,
+                required_confirms: 3,
+                ..ConfirmsTier::default()
+            },
+        ];
+        assert_eq!(calculate_required_confirms(1, &tiers, 500_000), 1);
+        assert_eq!(calculate_required_confirms(1, &tiers, 1_000_000), 2);
+        assert_eq!(calculate_required_confirms(1, &tiers, 200_000_000), 3);
+        assert_eq!(calculate_required_confirms(4, &tiers, 200_000_000), 4);
+        assert_eq!(calculate_required_confirms(1, &[], 200_000_000), 1);
+    }
+
+    #[test]
+    fn test_tier_locktime_and_fee() {
+        let tiers = [
+            ConfirmsTier {
+                amount: 1_000_000,
+                required_confirms: 2,
+                maximum_locktime: Some(240),
+                fee_multiplier_percent: Some(150),
+            },
+            ConfirmsTier {
+                amount: 10_000_000,
+                required_confirms: 2,
+                maximum_locktime: Some(300),
+                fee_multiplier_percent: None,
+            },
+            ConfirmsTier {
+                amount: 100_000_000,
+                required_confirms: 3,
+                maximum_locktime: Some(144),
+                fee_multiplier_percent: Some(200),
+            },
+        ];
+        assert_eq!(
+            calculate_maximum_locktime(Some(480), &tiers, 500_000),
+            Some(480)
+        );
+        assert_eq!(
+            calculate_maximum_locktime(Some(480), &tiers, 20_000_000),
+            Some(240)
+        );
+        assert_eq!(
+            calculate_maximum_locktime(None, &tiers, 200_000_000),
+            Some(144)
+        );
+        assert_eq!(calculate_maximum_locktime(None, &[], 200_000_000), None);
+
+        assert_eq!(apply_fee_multiplier(1000, &tiers, 500_000), 1000);
+        //the 10_000_000 tier has no multiplier so the one of the tier below is used
+        assert_eq!(apply_fee_multiplier(1000, &tiers, 20_000_000), 1500);
+        assert_eq!(apply_fee_multiplier(1000, &tiers, 200_000_000), 2000);
+    }
+
+    #[test]
+    fn test_check_fee_rate() {
+        //250 vbyte tx paying 250 sats is exactly 1000 sat/kvbyte
+        assert!(check_fee_rate(250, 250, 1000).is_ok());
+        assert!(check_fee_rate(500, 250, 1000).is_ok());
+        assert!(matches!(
+            check_fee_rate(249, 250, 1000),
+            Err(Error::FundingTxFeeRate {
+                fee_rate: 996,
+                min_fee_rate: 1000
+            })
+        ));
+        assert!(check_fee_rate(0, 250, 0).is_ok());
+    }
+
+    #[test]
+    fn test_is_funding_tx_conflicted() {
+        assert!(!is_funding_tx_conflicted(3, false, true, false));
+        assert!(!is_funding_tx_conflicted(0, true, false, true));
+        //not in the mempool, e.g. evicted, but nothing else spent its inputs
+        assert!(!is_funding_tx_conflicted(0, false, false, false));
+        //confirmed since its confirmations were read
+        assert!(!is_funding_tx_conflicted(0, false, true, true));
+        //replaced in the mempool
+        assert!(is_funding_tx_conflicted(0, false, true, false));
+        //double spent by a confirmed tx
+        assert!(is_funding_tx_conflicted(-1, false, true, false));
+    }
+
     #[test]
     fn test_maker_pubkey_computation() {
         let secp = Secp256k1::new();
@@ -833,6 +1178,13 @@ mod test {
             key: pubkey_org.key.combine(&nonce_point).unwrap(),
         };
         assert_eq!(pubkey_derived, expected_derivation);
+
+        let seeded_derivation = || {
+            derive_maker_pubkey_and_nonce_with_rng(pubkey_org, &mut StdRng::seed_from_u64(1))
+                .unwrap()
+        };
+        assert_eq!(seeded_derivation(), seeded_derivation());
+        assert_ne!(seeded_derivation().1, nonce);
     }
 
     #[test]
@@ -882,6 +1234,15 @@ mod test {
             read_locktime_from_contract(&contract_script).unwrap(),
             locktime
         );
+
+        for locktime in [0, 1, 16, 17, 0x7fff, 0x8000, u16::MAX] {
+            let contract_script =
+                create_contract_redeemscript(&pub_hashlock, &pub_timelock, hashvalue, locktime);
+            assert_eq!(
+                read_locktime_from_contract(&contract_script),
+                Some(locktime)
+            );
+        }
     }
 
     #[test]
@@ -908,6 +1269,9 @@ mod test {
 
         assert_eq!(fetched_pub1, pub1);
         assert_eq!(fetched_pub2, pub2);
+
+        let truncated = Script::from(multisig.to_bytes()[..40].to_vec());
+        assert_eq!(read_pubkeys_from_multisig_redeemscript(&truncated), None);
     }
 
     #[test]
@@ -0,0 +1,865 @@
+//teleportd, a long running process which lets GUIs and other programs use the wallet,
+// query the offerbook and do coinswaps over JSON-RPC
+//requests and responses are JSON-RPC 2.0 objects, one per line, sent over a tcp
+// connection to localhost
+//the first request on every connection must be `authenticate` with the contents of
+// the cookie file written to the data directory at startup, like bitcoind's .cookie
+//with the grpc feature the same methods are also served over gRPC, see proto/teleport.proto
+//events like balance changes and coinswap progress can be followed over a websocket,
+// see websocket.rs
+
+use std::collections::HashSet;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex as StdMutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::select;
+use tokio::sync::{broadcast, Mutex, OwnedMutexGuard};
+use tokio::time::{interval, sleep};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::{Amount, Network, OutPoint, Txid};
+use bitcoincore_rpc::json::ListUnspentResultEntry;
+use bitcoincore_rpc::RpcApi;
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use crate::error::Error;
+use crate::messages::Offer;
+use crate::offerbook_sync::{get_advertised_maker_addresses, sync_offerbook_with_addresses};
+use crate::rpc::Client;
+use crate::swap_timeline::swap_timeline;
+use crate::taker_protocol::TakerEvent;
+use crate::utils::teleport_data_dir;
+use crate::wallet_sync::{UTXOSpendInfo, Wallet, WalletSyncAddressAmount};
+use crate::{get_bitcoin_rpc, run_taker, websocket};
+
+pub const DAEMON_PORT: u16 = 6104;
+pub const DAEMON_COOKIE_FILE_NAME: &str = ".daemon_cookie";
+
+//how often the wallet and offerbook are checked for changes, only while someone is
+// subscribed to daemon events
+const WALLET_CHECK_INTERVAL_SECS: u64 = 60;
+const OFFERBOOK_SYNC_INTERVAL_SECS: u64 = 600;
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+pub(crate) const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+const UNAUTHORIZED: i64 = -32001;
+pub(crate) const WALLET_BUSY: i64 = -32002;
+
+#[derive(Debug, Deserialize)]
+struct JsonRpcRequest {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct JsonRpcError {
+    pub(crate) code: i64,
+    pub(crate) message: String,
+}
+
+impl JsonRpcError {
+    pub(crate) fn new(code: i64, message: impl Into<String>) -> JsonRpcError {
+        JsonRpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<Error> for JsonRpcError {
+    fn from(e: Error) -> JsonRpcError {
+        JsonRpcError::new(INTERNAL_ERROR, format!("{:?}", e))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JsonRpcResponse {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<JsonRpcError>,
+}
+
+impl JsonRpcResponse {
+    fn new(id: Value, result: Result<Value, JsonRpcError>) -> JsonRpcResponse {
+        let (result, error) = match result {
+            Ok(r) => (Some(r), None),
+            Err(e) => (None, Some(e)),
+        };
+        JsonRpcResponse {
+            jsonrpc: "2.0",
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthenticateParams {
+    cookie: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct StartCoinswapParams {
+    pub(crate) send_amount: u64,
+    #[serde(default = "default_maker_count")]
+    pub(crate) maker_count: u16,
+    #[serde(default = "default_tx_count")]
+    pub(crate) tx_count: u32,
+    #[serde(default = "default_fee_rate")]
+    pub(crate) fee_rate: u64,
+}
+
+pub(crate) fn default_maker_count() -> u16 {
+    2
+}
+
+pub(crate) fn default_tx_count() -> u32 {
+    3
+}
+
+pub(crate) fn default_fee_rate() -> u64 {
+    1000
+}
+
+#[derive(Debug, Deserialize)]
+struct GetCoinswapParams {
+    swap_id: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CoinswapState {
+    Running,
+    Complete,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct CoinswapStatus {
+    pub(crate) swap_id: usize,
+    pub(crate) send_amount: u64,
+    pub(crate) maker_count: u16,
+    pub(crate) tx_count: u32,
+    pub(crate) state: CoinswapState,
+    pub(crate) events: Vec<TakerEvent>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct DaemonStatus {
+    pub(crate) network: String,
+    pub(crate) wallet_file: PathBuf,
+    pub(crate) coinswap_in_progress: bool,
+    pub(crate) coinswap_count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct WalletBalance {
+    pub(crate) spendable_sat: u64,
+    pub(crate) spendable_utxo_count: usize,
+    pub(crate) fidelity_bond_sat: u64,
+    //unconfirmed coins paid to the wallet by someone else, not in spendable_sat
+    pub(crate) pending_incoming_sat: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct WalletUtxo {
+    pub(crate) txid: String,
+    pub(crate) vout: u32,
+    pub(crate) address: Option<String>,
+    pub(crate) amount_sat: u64,
+    pub(crate) confirmations: u32,
+    #[serde(rename = "type")]
+    pub(crate) utxo_type: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct OfferInfo {
+    pub(crate) address: String,
+    pub(crate) offer: Offer,
+}
+
+//a contract transaction of one of our coinswaps was broadcast, by us or the other side
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ContractAlert {
+    pub(crate) txid: Txid,
+    pub(crate) vout: u32,
+    pub(crate) amount_sat: u64,
+    pub(crate) confirmations: u32,
+    //"timelock" if it pays to our timelock branch, "hashlock" if to our hashlock branch
+    pub(crate) contract_type: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum DaemonEvent {
+    BalanceChanged(WalletBalance),
+    OfferbookUpdated {
+        offers: Vec<OfferInfo>,
+    },
+    Coinswap {
+        swap_id: usize,
+        event: TakerEvent,
+    },
+    CoinswapFinished {
+        swap_id: usize,
+        state: CoinswapState,
+    },
+    ContractBroadcast(ContractAlert),
+    //an unconfirmed transaction in the mempool pays to the wallet
+    IncomingTransaction(WalletUtxo),
+}
+
+pub(crate) struct DaemonState {
+    pub(crate) wallet_file_name: PathBuf,
+    pub(crate) network: Network,
+    pub(crate) cookie: String,
+    //events are sent while holding this lock, so a subscriber which takes the lock,
+    // copies a coinswap's events and then subscribes wont miss or repeat any
+    pub(crate) coinswaps: StdMutex<Vec<CoinswapStatus>>,
+    pub(crate) events: broadcast::Sender<DaemonEvent>,
+    //held for as long as anything is using the wallet file, the wallet file isnt
+    // made for being written by more than one thing at once
+    wallet_lock: Arc<Mutex<()>>,
+}
+
+impl DaemonState {
+    pub(crate) fn new(wallet_file_name: &Path, network: Network, cookie: String) -> DaemonState {
+        DaemonState {
+            wallet_file_name: wallet_file_name.to_path_buf(),
+            network,
+            cookie,
+            coinswaps: StdMutex::new(Vec::new()),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            wallet_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub(crate) fn status(&self) -> DaemonStatus {
+        let coinswaps = self.coinswaps.lock().unwrap();
+        DaemonStatus {
+            network: self.network.to_string(),
+            wallet_file: self.wallet_file_name.clone(),
+            coinswap_in_progress: coinswaps.iter().any(|c| c.state == CoinswapState::Running),
+            coinswap_count: coinswaps.len(),
+        }
+    }
+}
+
+#[tokio::main]
+pub async fn start_daemon(
+    wallet_file_name: &Path,
+    network: Network,
+    port: u16,
+    grpc_port: Option<u16>,
+    websocket_port: Option<u16>,
+    kill_flag: Arc<RwLock<bool>>,
+) {
+    match run(
+        wallet_file_name,
+        network,
+        port,
+        grpc_port,
+        websocket_port,
+        kill_flag,
+    )
+    .await
+    {
+        Ok(_o) => tracing::info!("daemon ended without error"),
+        Err(e) => tracing::info!("daemon ended with err {:?}", e),
+    };
+}
+
+/// Whether a client gave the right cookie, compared in constant time so that it cant be found
+/// out byte by byte from how long the comparison takes. Only the length of the cookie, which is
+/// always the same, can be learned
+pub(crate) fn is_correct_cookie(given: &str, cookie: &str) -> bool {
+    given.len() == cookie.len() && openssl::memcmp::eq(given.as_bytes(), cookie.as_bytes())
+}
+
+//writes a new random cookie to the data directory, only readable by this user
+//the cookie of an earlier run is removed and the file created anew, so the cookie is never
+// readable by others even for a moment, and never written through a link put in its place
+fn write_cookie_file(path: &Path) -> Result<String, Error> {
+    let mut cookie_bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut cookie_bytes);
+    let cookie = cookie_bytes.to_hex();
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => (),
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    std::io::Write::write_all(&mut options.open(path)?, cookie.as_bytes())?;
+    Ok(cookie)
+}
+
+async fn run(
+    wallet_file_name: &Path,
+    network: Network,
+    port: u16,
+    grpc_port: Option<u16>,
+    websocket_port: Option<u16>,
+    kill_flag: Arc<RwLock<bool>>,
+) -> Result<(), Error> {
+    let cookie_path = teleport_data_dir().join(DAEMON_COOKIE_FILE_NAME);
+    let state = Arc::new(DaemonState::new(
+        wallet_file_name,
+        network,
+        write_cookie_file(&cookie_path)?,
+    ));
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
+    tracing::info!(
+        "Starting teleport daemon. Listening On Port {}, cookie file {}",
+        port,
+        cookie_path.display()
+    );
+    if let Some(grpc_port) = grpc_port {
+        #[cfg(feature = "grpc")]
+        tokio::spawn(crate::grpc::serve(Arc::clone(&state), grpc_port));
+        #[cfg(not(feature = "grpc"))]
+        {
+            let _ = fs::remove_file(&cookie_path);
+            tracing::error!(
+                "cant serve gRPC on port {}, built without the grpc feature",
+                grpc_port
+            );
+            return Err(Error::Protocol("built without the grpc feature"));
+        }
+    }
+    if let Some(websocket_port) = websocket_port {
+        tokio::spawn(websocket::serve(Arc::clone(&state), websocket_port));
+    }
+    tokio::spawn(watch_wallet(Arc::clone(&state)));
+    tokio::spawn(watch_offerbook(Arc::clone(&state)));
+
+    let result = loop {
+        let (mut socket, addr) = select! {
+            new_client = listener.accept() => new_client?,
+            _ = sleep(Duration::from_secs(3)) => {
+                if *kill_flag.read().unwrap() {
+                    break Ok(());
+                }
+                continue;
+            },
+        };
+        tracing::debug!("[{}] ===> Accepted Connection", addr.port());
+
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            let (socket_reader, mut socket_writer) = socket.split();
+            let mut reader = BufReader::new(socket_reader);
+            let mut authenticated = false;
+
+            loop {
+                let mut line = String::new();
+                match reader.read_line(&mut line).await {
+                    Ok(0) => break,
+                    Ok(_n) => (),
+                    Err(e) => {
+                        tracing::debug!("error reading from socket: {:?}", e);
+                        break;
+                    }
+                }
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let response = handle_line(&state, &mut authenticated, &line).await;
+                let mut response_bytes = serde_json::to_vec(&response).unwrap();
+                response_bytes.push(b'\n');
+                if let Err(e) = socket_writer.write_all(&response_bytes).await {
+                    tracing::debug!("error writing to socket: {:?}", e);
+                    break;
+                }
+            }
+            tracing::debug!("[{}] Connection closed", addr.port());
+        });
+    };
+    //the cookie is useless once the daemon stops
+    let _ = fs::remove_file(&cookie_path);
+    result
+}
+
+async fn handle_line(
+    state: &Arc<DaemonState>,
+    authenticated: &mut bool,
+    line: &str,
+) -> JsonRpcResponse {
+    let request = match serde_json::from_str::<Value>(line) {
+        Ok(v) => v,
+        Err(e) => {
+            return JsonRpcResponse::new(
+                Value::Null,
+                Err(JsonRpcError::new(PARSE_ERROR, e.to_string())),
+            )
+        }
+    };
+    let request = match serde_json::from_value::<JsonRpcRequest>(request) {
+        Ok(r) => r,
+        Err(e) => {
+            return JsonRpcResponse::new(
+                Value::Null,
+                Err(JsonRpcError::new(INVALID_REQUEST, e.to_string())),
+            )
+        }
+    };
+    tracing::debug!("JSON-RPC request method = {}", request.method);
+
+    let result = if request.method == "authenticate" {
+        parse_params::<AuthenticateParams>(request.params).and_then(|params| {
+            *authenticated = is_correct_cookie(&params.cookie, &state.cookie);
+            if *authenticated {
+                Ok(json!(true))
+            } else {
+                Err(JsonRpcError::new(UNAUTHORIZED, "incorrect cookie"))
+            }
+        })
+    } else if !*authenticated {
+        Err(JsonRpcError::new(
+            UNAUTHORIZED,
+            "call authenticate with the contents of the cookie file first",
+        ))
+    } else {
+        handle_request(state, &request.method, request.params).await
+    };
+    JsonRpcResponse::new(request.id, result)
+}
+
+fn parse_params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, JsonRpcError> {
+    serde_json::from_value(params).map_err(|e| JsonRpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+async fn handle_request(
+    state: &Arc<DaemonState>,
+    method: &str,
+    params: Value,
+) -> Result<Value, JsonRpcError> {
+    match method {
+        "getstatus" => Ok(json!(state.status())),
+        "getbalance" => with_wallet(state, get_balance).await.map(|b| json!(b)),
+        "listunspent" => with_wallet(state, list_unspent).await.map(|u| json!(u)),
+        "getswaptimeline" => with_wallet(state, swap_timeline).await.map(|t| json!(t)),
+        "getnewaddress" => with_wallet(state, get_new_address)
+            .await
+            .map(|address| json!({ "address": address })),
+        "getoffers" => get_offers(state).await.map(|offers| json!(offers)),
+        "startcoinswap" => {
+            let params = parse_params::<StartCoinswapParams>(params)?;
+            start_coinswap(state, params).map(|swap_id| json!({ "swap_id": swap_id }))
+        }
+        "getcoinswap" => {
+            let params = parse_params::<GetCoinswapParams>(params)?;
+            get_coinswap(state, params.swap_id).map(|coinswap| json!(coinswap))
+        }
+        "listcoinswaps" => Ok(json!(*state.coinswaps.lock().unwrap())),
+        _ => Err(JsonRpcError::new(
+            METHOD_NOT_FOUND,
+            format!("unknown method {}", method),
+        )),
+    }
+}
+
+fn lock_wallet(state: &Arc<DaemonState>) -> Result<OwnedMutexGuard<()>, JsonRpcError> {
+    Arc::clone(&state.wallet_lock)
+        .try_lock_owned()
+        .map_err(|_| JsonRpcError::new(WALLET_BUSY, "wallet is in use, try again later"))
+}
+
+//loads and syncs the wallet then calls f with it, on a thread where blocking is allowed
+pub(crate) async fn with_wallet<F, T>(state: &Arc<DaemonState>, f: F) -> Result<T, JsonRpcError>
+where
+    F: FnOnce(&Client, &mut Wallet) -> Result<T, Error> + Send + 'static,
+    T: Send + 'static,
+{
+    let wallet_guard = lock_wallet(state)?;
+    let state = Arc::clone(state);
+    tokio::task::spawn_blocking(move || {
+        let _wallet_guard = wallet_guard;
+        let (rpc, network) = get_bitcoin_rpc()?;
+        let mut wallet = Wallet::load_wallet_from_file(
+            &state.wallet_file_name,
+            network,
+            WalletSyncAddressAmount::Normal,
+        )?;
+        wallet.startup_sync(&rpc)?;
+        Ok(f(&rpc, &mut wallet)?)
+    })
+    .await
+    .map_err(|e| JsonRpcError::new(INTERNAL_ERROR, e.to_string()))?
+}
+
+pub(crate) fn get_balance(rpc: &Client, wallet: &mut Wallet) -> Result<WalletBalance, Error> {
+    let utxos = wallet.list_unspent_from_wallet(rpc, false, true)?;
+    let mut pending_incoming_utxos = Vec::new();
+    let mut confirmed_utxos = Vec::new();
+    for utxo in &utxos {
+        if is_pending_incoming(rpc, &utxo.0)? {
+            pending_incoming_utxos.push(utxo);
+        } else {
+            confirmed_utxos.push(utxo);
+        }
+    }
+    let (fidelity_bond_utxos, spendable_utxos): (Vec<_>, Vec<_>) = confirmed_utxos
+        .into_iter()
+        .partition(|(_, usi)| matches!(usi, UTXOSpendInfo::FidelityBondCoin { .. }));
+    let sum = |utxos: &[&(ListUnspentResultEntry, UTXOSpendInfo)]| {
+        utxos
+            .iter()
+            .fold(Amount::ZERO, |acc, (u, _)| acc + u.amount)
+            .as_sat()
+    };
+    Ok(WalletBalance {
+        spendable_sat: sum(&spendable_utxos),
+        spendable_utxo_count: spendable_utxos.len(),
+        fidelity_bond_sat: sum(&fidelity_bond_utxos),
+        pending_incoming_sat: sum(&pending_incoming_utxos),
+    })
+}
+
+/// Whether the coin is still in the mempool and was paid to the wallet by someone else,
+/// change of the wallet's own unconfirmed transactions is spendable right away
+pub(crate) fn is_pending_incoming(
+    rpc: &Client,
+    utxo: &ListUnspentResultEntry,
+) -> Result<bool, Error> {
+    if utxo.confirmations > 0 {
+        return Ok(false);
+    }
+    //bitcoind only gives a fee for transactions spending the wallet's coins
+    Ok(rpc.get_transaction(&utxo.txid, Some(true))?.fee.is_none())
+}
+
+pub(crate) fn list_pending_incoming(
+    rpc: &Client,
+    wallet: &mut Wallet,
+) -> Result<Vec<WalletUtxo>, Error> {
+    let mut pending = Vec::new();
+    for (utxo, usi) in wallet.list_unspent_from_wallet(rpc, false, true)? {
+        if is_pending_incoming(rpc, &utxo)? {
+            pending.push(wallet_utxo(&utxo, &usi));
+        }
+    }
+    Ok(pending)
+}
+
+fn utxo_type(usi: &UTXOSpendInfo) -> &'static str {
+    match usi {
+        UTXOSpendInfo::SeedCoin { .. } => "seed",
+        UTXOSpendInfo::SwapCoin { .. } => "swapcoin",
+        UTXOSpendInfo::TimelockContract { .. } => "timelock",
+        UTXOSpendInfo::HashlockContract { .. } => "hashlock",
+        UTXOSpendInfo::FidelityBondCoin { .. } => "fidelitybond",
+    }
+}
+
+fn wallet_utxo(utxo: &ListUnspentResultEntry, usi: &UTXOSpendInfo) -> WalletUtxo {
+    WalletUtxo {
+        txid: utxo.txid.to_hex(),
+        vout: utxo.vout,
+        address: utxo.address.as_ref().map(|a| a.to_string()),
+        amount_sat: utxo.amount.as_sat(),
+        confirmations: utxo.confirmations,
+        utxo_type: utxo_type(usi),
+    }
+}
+
+pub(crate) fn list_unspent(rpc: &Client, wallet: &mut Wallet) -> Result<Vec<WalletUtxo>, Error> {
+    Ok(wallet
+        .list_unspent_from_wallet(rpc, false, true)?
+        .iter()
+        .map(|(utxo, usi)| wallet_utxo(utxo, usi))
+        .collect())
+}
+
+pub(crate) fn get_new_address(rpc: &Client, wallet: &mut Wallet) -> Result<String, Error> {
+    Ok(wallet.get_next_external_address(rpc)?.to_string())
+}
+
+pub(crate) async fn get_offers(state: &Arc<DaemonState>) -> Result<Vec<OfferInfo>, JsonRpcError> {
+    let maker_addresses = get_advertised_maker_addresses(state.network)
+        .await
+        .map_err(|e| JsonRpcError::new(INTERNAL_ERROR, format!("{:?}", e)))?;
+    Ok(sync_offerbook_with_addresses(maker_addresses)
+        .await
+        .into_iter()
+        .map(|offer_address| OfferInfo {
+            address: offer_address.address.to_string(),
+            offer: offer_address.offer,
+        })
+        .collect())
+}
+
+pub(crate) fn get_coinswap(
+    state: &Arc<DaemonState>,
+    swap_id: usize,
+) -> Result<CoinswapStatus, JsonRpcError> {
+    state
+        .coinswaps
+        .lock()
+        .unwrap()
+        .get(swap_id)
+        .cloned()
+        .ok_or_else(|| JsonRpcError::new(INVALID_PARAMS, "unknown swap_id"))
+}
+
+//runs the coinswap on its own threads and records its progress in state.coinswaps
+pub(crate) fn start_coinswap(
+    state: &Arc<DaemonState>,
+    params: StartCoinswapParams,
+) -> Result<usize, JsonRpcError> {
+    let wallet_guard = lock_wallet(state)?;
+    let swap_id = {
+        let mut coinswaps = state.coinswaps.lock().unwrap();
+        let swap_id = coinswaps.len();
+        coinswaps.push(CoinswapStatus {
+            swap_id,
+            send_amount: params.send_amount,
+            maker_count: params.maker_count,
+            tx_count: params.tx_count,
+            state: CoinswapState::Running,
+            events: Vec::new(),
+        });
+        swap_id
+    };
+
+    let state = Arc::clone(state);
+    thread::spawn(move || {
+        let _wallet_guard = wallet_guard;
+        let (progress_tx, progress_rx) = channel::<TakerEvent>();
+        let wallet_file_name = state.wallet_file_name.clone();
+        //run_taker starts its own tokio runtime so cant run on one of the daemon's threads
+        let taker_thread = thread::spawn(move || {
+            run_taker(
+                &wallet_file_name,
+                WalletSyncAddressAmount::Normal,
+                params.fee_rate,
+                params.send_amount,
+                params.maker_count,
+                params.tx_count,
+                None,
+                Some(progress_tx),
+            )
+        });
+        //ends when the taker finishes and drops progress_tx
+        for event in progress_rx {
+            let mut coinswaps = state.coinswaps.lock().unwrap();
+            coinswaps[swap_id].events.push(event.clone());
+            //no subscribers is not an error
+            let _ = state.events.send(DaemonEvent::Coinswap { swap_id, event });
+        }
+        let taker_panicked = taker_thread.join().is_err();
+
+        let mut coinswaps = state.coinswaps.lock().unwrap();
+        let coinswap = &mut coinswaps[swap_id];
+        coinswap.state = match coinswap.events.last() {
+            Some(TakerEvent::CoinswapComplete) if !taker_panicked => CoinswapState::Complete,
+            _ => CoinswapState::Failed,
+        };
+        tracing::info!(
+            "coinswap {} finished, state = {:?}",
+            swap_id,
+            coinswap.state
+        );
+        let _ = state.events.send(DaemonEvent::CoinswapFinished {
+            swap_id,
+            state: coinswap.state,
+        });
+    });
+    Ok(swap_id)
+}
+
+//sends balance changes and contract alerts
+//contract transactions are only broadcast when something went wrong in a coinswap, so
+// anyone subscribed to daemon events is told about every contract output in the wallet
+async fn watch_wallet(state: Arc<DaemonState>) {
+    let mut last_balance = None;
+    let mut seen_contracts = HashSet::<OutPoint>::new();
+    let mut seen_incoming = HashSet::<(String, u32)>::new();
+    let mut check_interval = interval(Duration::from_secs(WALLET_CHECK_INTERVAL_SECS));
+    loop {
+        check_interval.tick().await;
+        if state.events.receiver_count() == 0 {
+            continue;
+        }
+        //a coinswap using the wallet will see the contracts itself, and the balance
+        // is checked again once it finishes
+        let (balance, contracts, pending_incoming) = match with_wallet(&state, |rpc, wallet| {
+            Ok((
+                get_balance(rpc, wallet)?,
+                find_contract_outputs(rpc, wallet)?,
+                list_pending_incoming(rpc, wallet)?,
+            ))
+        })
+        .await
+        {
+            Ok(b) => b,
+            Err(e) if e.code == WALLET_BUSY => continue,
+            Err(e) => {
+                tracing::warn!("error checking wallet: {}", e.message);
+                continue;
+            }
+        };
+        if last_balance.as_ref() != Some(&balance) {
+            last_balance = Some(balance.clone());
+            let _ = state.events.send(DaemonEvent::BalanceChanged(balance));
+        }
+        for alert in contracts {
+            let outpoint = OutPoint {
+                txid: alert.txid,
+                vout: alert.vout,
+            };
+            if seen_contracts.insert(outpoint) {
+                tracing::info!("contract transaction found = {:?}", alert);
+                let _ = state.events.send(DaemonEvent::ContractBroadcast(alert));
+            }
+        }
+        for utxo in pending_incoming {
+            if seen_incoming.insert((utxo.txid.clone(), utxo.vout)) {
+                tracing::info!("incoming unconfirmed transaction = {:?}", utxo);
+                let _ = state.events.send(DaemonEvent::IncomingTransaction(utxo));
+            }
+        }
+    }
+}
+
+async fn watch_offerbook(state: Arc<DaemonState>) {
+    let mut last_offers = None;
+    let mut sync_interval = interval(Duration::from_secs(OFFERBOOK_SYNC_INTERVAL_SECS));
+    loop {
+        sync_interval.tick().await;
+        if state.events.receiver_count() == 0 {
+            continue;
+        }
+        let offers = match get_offers(&state).await {
+            Ok(o) => o,
+            Err(e) => {
+                tracing::warn!("error syncing offerbook: {}", e.message);
+                continue;
+            }
+        };
+        //Offer has no PartialEq, its json is compared instead
+        let offers_json = json!(offers);
+        if last_offers.as_ref() != Some(&offers_json) {
+            last_offers = Some(offers_json);
+            let _ = state.events.send(DaemonEvent::OfferbookUpdated { offers });
+        }
+    }
+}
+
+pub(crate) fn find_contract_outputs(
+    rpc: &Client,
+    wallet: &mut Wallet,
+) -> Result<Vec<ContractAlert>, Error> {
+    Ok(wallet
+        .list_unspent_from_wallet(rpc, true, false)?
+        .iter()
+        .filter(|(_, usi)| {
+            matches!(
+                usi,
+                UTXOSpendInfo::TimelockContract { .. } | UTXOSpendInfo::HashlockContract { .. }
+            )
+        })
+        .map(|(utxo, usi)| ContractAlert {
+            txid: utxo.txid,
+            vout: utxo.vout,
+            amount_sat: utxo.amount.as_sat(),
+            confirmations: utxo.confirmations,
+            contract_type: utxo_type(usi),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_correct_cookie() {
+        assert!(is_correct_cookie("secret", "secret"));
+        assert!(!is_correct_cookie("secreT", "secret"));
+        assert!(!is_correct_cookie("secre", "secret"));
+        assert!(!is_correct_cookie("", "secret"));
+    }
+
+    fn test_state() -> Arc<DaemonState> {
+        Arc::new(DaemonState::new(
+            Path::new("wallet.teleport"),
+            Network::Regtest,
+            "secret".to_string(),
+        ))
+    }
+
+    fn error_code(response: &JsonRpcResponse) -> Option<i64> {
+        response.error.as_ref().map(|e| e.code)
+    }
+
+    #[tokio::test]
+    async fn test_authentication_and_dispatch() {
+        let state = test_state();
+        let mut authenticated = false;
+
+        let response = handle_line(&state, &mut authenticated, "not json").await;
+        assert_eq!(error_code(&response), Some(PARSE_ERROR));
+
+        let getstatus = r#"{"jsonrpc":"2.0","id":1,"method":"getstatus"}"#;
+        let response = handle_line(&state, &mut authenticated, getstatus).await;
+        assert_eq!(error_code(&response), Some(UNAUTHORIZED));
+
+        let bad_auth = r#"{"id":2,"method":"authenticate","params":{"cookie":"wrong"}}"#;
+        let response = handle_line(&state, &mut authenticated, bad_auth).await;
+        assert_eq!(error_code(&response), Some(UNAUTHORIZED));
+        assert!(!authenticated);
+
+        let auth = r#"{"id":3,"method":"authenticate","params":{"cookie":"secret"}}"#;
+        let response = handle_line(&state, &mut authenticated, auth).await;
+        assert_eq!(response.result, Some(json!(true)));
+        assert_eq!(response.id, json!(3));
+
+        let response = handle_line(&state, &mut authenticated, getstatus).await;
+        let result = response.result.unwrap();
+        assert_eq!(result["network"], json!("regtest"));
+        assert_eq!(result["coinswap_in_progress"], json!(false));
+
+        let unknown = r#"{"id":4,"method":"getnothing"}"#;
+        let response = handle_line(&state, &mut authenticated, unknown).await;
+        assert_eq!(error_code(&response), Some(METHOD_NOT_FOUND));
+
+        let bad_params = r#"{"id":5,"method":"getcoinswap","params":{"swap":0}}"#;
+        let response = handle_line(&state, &mut authenticated, bad_params).await;
+        assert_eq!(error_code(&response), Some(INVALID_PARAMS));
+
+        //the wallet is in use while a coinswap runs
+        let _wallet_guard = lock_wallet(&state).unwrap();
+        let getbalance = r#"{"id":6,"method":"getbalance"}"#;
+        let response = handle_line(&state, &mut authenticated, getbalance).await;
+        assert_eq!(error_code(&response), Some(WALLET_BUSY));
+    }
+}
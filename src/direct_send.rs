@@ -1,14 +1,22 @@
+use std::fmt;
 use std::num::ParseIntError;
 use std::str::FromStr;
 
+use bitcoin::util::amount::{Denomination, ParseAmountError};
 use bitcoin::{Address, Amount, Network, OutPoint, Script, Transaction, TxIn, TxOut};
 
 use bitcoincore_rpc::json::ListUnspentResultEntry;
-use bitcoincore_rpc::Client;
 
+use url::Url;
+
+use crate::coin_viability::{input_vbytes, is_economic, spend_cost_sat, tx_fee};
 use crate::contracts::SwapCoin;
 use crate::error::Error;
 use crate::fidelity_bonds::get_locktime_from_index;
+use crate::rpc::Client;
+use crate::wallet_accounts::{
+    account_balances, choose_funding_account, utxo_account, WalletAccount,
+};
 use crate::wallet_sync::{UTXOSpendInfo, Wallet};
 
 #[derive(Debug)]
@@ -33,20 +41,146 @@ impl FromStr for SendAmount {
 pub enum Destination {
     Wallet,
     Address(Address),
+    Uri(PaymentUri),
 }
 
 impl FromStr for Destination {
-    type Err = bitcoin::util::address::Error;
+    type Err = PaymentUriError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(if s == "wallet" {
             Destination::Wallet
+        } else if s.len() > 8
+            && s.get(..8)
+                .is_some_and(|p| p.eq_ignore_ascii_case("bitcoin:"))
+        {
+            Destination::Uri(PaymentUri::from_str(s)?)
         } else {
-            Destination::Address(Address::from_str(s)?)
+            Destination::Address(Address::from_str(s).map_err(PaymentUriError::Address)?)
+        })
+    }
+}
+
+//BIP21 payment uri, e.g. bitcoin:bc1q...?amount=0.01&label=Shop&pj=https://example.com/payjoin
+#[derive(Debug, Clone)]
+pub struct PaymentUri {
+    pub address: Address,
+    pub amount: Option<Amount>,
+    //stored in the wallet's send history
+    pub label: Option<String>,
+    //BIP78 payjoin endpoint of the receiver
+    pub payjoin_endpoint: Option<Url>,
+}
+
+#[derive(Debug)]
+pub enum PaymentUriError {
+    Url(url::ParseError),
+    Address(bitcoin::util::address::Error),
+    Amount(ParseAmountError),
+    UnknownRequiredParameter(String),
+    InvalidPayjoinEndpoint(String),
+    WrongNetwork(Network),
+    AmountMismatch(Amount),
+}
+
+impl fmt::Display for PaymentUriError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaymentUriError::Url(e) => write!(f, "invalid uri: {}", e),
+            PaymentUriError::Address(e) => write!(f, "invalid address: {}", e),
+            PaymentUriError::Amount(e) => write!(f, "invalid amount: {}", e),
+            PaymentUriError::UnknownRequiredParameter(p) => {
+                write!(f, "unsupported required uri parameter {}", p)
+            }
+            PaymentUriError::InvalidPayjoinEndpoint(e) => {
+                write!(f, "payjoin endpoint must be https or a .onion: {}", e)
+            }
+            PaymentUriError::WrongNetwork(n) => {
+                write!(f, "uri address is not for the configured network {}", n)
+            }
+            PaymentUriError::AmountMismatch(a) => write!(
+                f,
+                "send amount must be the {} sat requested by the uri",
+                a.as_sat()
+            ),
+        }
+    }
+}
+
+impl FromStr for PaymentUri {
+    type Err = PaymentUriError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let uri = Url::parse(s).map_err(PaymentUriError::Url)?;
+        let address = Address::from_str(uri.path()).map_err(PaymentUriError::Address)?;
+        let mut amount = None;
+        let mut label = None;
+        let mut payjoin_endpoint = None;
+        for (key, value) in uri.query_pairs() {
+            match key.as_ref() {
+                "amount" => {
+                    amount = Some(
+                        Amount::from_str_in(&value, Denomination::Bitcoin)
+                            .map_err(PaymentUriError::Amount)?,
+                    );
+                }
+                "label" => label = Some(value.to_string()),
+                "pj" => {
+                    let endpoint = Url::parse(&value)
+                        .map_err(|_| PaymentUriError::InvalidPayjoinEndpoint(value.to_string()))?;
+                    let is_onion = endpoint
+                        .host_str()
+                        .is_some_and(|host| host.ends_with(".onion"));
+                    if endpoint.scheme() != "https" && !(endpoint.scheme() == "http" && is_onion) {
+                        return Err(PaymentUriError::InvalidPayjoinEndpoint(value.to_string()));
+                    }
+                    payjoin_endpoint = Some(endpoint);
+                }
+                //BIP21 says to reject uris with required parameters we dont understand
+                k if k.starts_with("req-") => {
+                    return Err(PaymentUriError::UnknownRequiredParameter(k.to_string()))
+                }
+                _ => (),
+            }
+        }
+        Ok(PaymentUri {
+            address,
+            amount,
+            label,
+            payjoin_endpoint,
         })
     }
 }
 
+impl PaymentUri {
+    //checked before sending, as the uri is parsed before the settings are loaded
+    pub fn check_payment(
+        &self,
+        network: Network,
+        send_amount: &SendAmount,
+    ) -> Result<(), PaymentUriError> {
+        if !address_network_matches(&self.address, network) {
+            return Err(PaymentUriError::WrongNetwork(network));
+        }
+        match (self.amount, send_amount) {
+            (Some(uri_amount), SendAmount::Amount(a)) if uri_amount != *a => {
+                Err(PaymentUriError::AmountMismatch(uri_amount))
+            }
+            (Some(uri_amount), SendAmount::Max) => Err(PaymentUriError::AmountMismatch(uri_amount)),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub(crate) fn address_network_matches(address: &Address, network: Network) -> bool {
+    //testnet and signet addresses have the same vbyte
+    //so address.network is always testnet even if the address is signet
+    let testnet_signet_type = (address.network == Network::Testnet
+        || address.network == Network::Signet)
+        && (network == Network::Testnet || network == Network::Signet);
+    address.network == network || testnet_signet_type
+}
+
 #[derive(Debug)]
 pub enum CoinToSpend {
     LongForm(OutPoint),
@@ -57,6 +191,22 @@ pub enum CoinToSpend {
     },
 }
 
+impl CoinToSpend {
+    pub fn matches(&self, outpoint: &OutPoint) -> bool {
+        match self {
+            CoinToSpend::LongForm(o) => o == outpoint,
+            CoinToSpend::ShortForm {
+                prefix,
+                suffix,
+                vout,
+            } => {
+                let txid_hex = outpoint.txid.to_string();
+                txid_hex.starts_with(prefix) && txid_hex.ends_with(suffix) && outpoint.vout == *vout
+            }
+        }
+    }
+}
+
 fn parse_short_form_coin(s: &str) -> Option<CoinToSpend> {
     //example short form: 568a4e..83a2e8:0
     if s.len() < 15 {
@@ -95,6 +245,48 @@ impl FromStr for CoinToSpend {
     }
 }
 
+//when no coins are given, the biggest coins worth spending at fee_rate are used until they
+// cover the amount, or all of them for a max send
+//coins are given as (outpoint, value, input vbytes)
+pub(crate) fn select_coins(
+    coins: &[(OutPoint, u64, u64)],
+    send_amount: &SendAmount,
+    fee_rate: u64,
+    miner_fee: u64,
+) -> Result<Vec<CoinToSpend>, Error> {
+    let mut coins = coins
+        .iter()
+        .filter(|(_, value, vbytes)| *value > spend_cost_sat(*vbytes, fee_rate))
+        .collect::<Vec<&(OutPoint, u64, u64)>>();
+    coins.sort_by_key(|(_, value, _)| std::cmp::Reverse(*value));
+    let amount = match send_amount {
+        SendAmount::Max => {
+            if coins.is_empty() {
+                return Err(Error::Protocol("no coins worth spending at this fee rate"));
+            }
+            return Ok(coins
+                .iter()
+                .map(|(outpoint, _, _)| CoinToSpend::LongForm(*outpoint))
+                .collect());
+        }
+        SendAmount::Amount(amount) => amount.as_sat(),
+    };
+    let mut selected = Vec::<CoinToSpend>::new();
+    let mut total = 0;
+    let mut inputs_vbytes = 0;
+    for (outpoint, value, vbytes) in coins {
+        selected.push(CoinToSpend::LongForm(*outpoint));
+        total += value;
+        inputs_vbytes += vbytes;
+        if total >= amount + miner_fee.max(tx_fee(inputs_vbytes, 2, fee_rate)) {
+            return Ok(selected);
+        }
+    }
+    Err(Error::Protocol(
+        "not enough coins worth spending at this fee rate",
+    ))
+}
+
 impl Wallet {
     pub fn create_direct_send(
         &mut self,
@@ -104,10 +296,59 @@ impl Wallet {
         destination: Destination,
         coins_to_spend: &[CoinToSpend],
     ) -> Result<Transaction, Error> {
+        let miner_fee = 500 * fee_rate / 1000; //TODO this is just a rough estimate now
+
         let mut tx_inputs = Vec::<TxIn>::new();
         let mut unspent_inputs = Vec::<(ListUnspentResultEntry, UTXOSpendInfo)>::new();
         //TODO this search within a search could get very slow
         let list_unspent_result = self.list_unspent_from_wallet(rpc, true, true)?;
+        let selected_coins;
+        let coins_to_spend = if coins_to_spend.is_empty() {
+            let spendable_coins = list_unspent_result
+                .iter()
+                .filter(|(_, spend_info)| {
+                    matches!(
+                        spend_info,
+                        UTXOSpendInfo::SeedCoin { .. } | UTXOSpendInfo::SwapCoin { .. }
+                    )
+                })
+                .collect::<Vec<&(ListUnspentResultEntry, UTXOSpendInfo)>>();
+            //coins are only selected from one account, like the funding of a coinswap
+            let account = choose_funding_account(
+                &account_balances(
+                    spendable_coins
+                        .iter()
+                        .map(|(u, spend_info)| (spend_info, u.amount.as_sat())),
+                ),
+                match send_amount {
+                    SendAmount::Max => 0,
+                    SendAmount::Amount(amount) => amount.as_sat(),
+                },
+            )?;
+            tracing::debug!("sending from the {} account", account);
+            selected_coins = select_coins(
+                &spendable_coins
+                    .iter()
+                    .filter(|(_, spend_info)| utxo_account(spend_info) == Some(account))
+                    .map(|(u, spend_info)| {
+                        (
+                            OutPoint {
+                                txid: u.txid,
+                                vout: u.vout,
+                            },
+                            u.amount.as_sat(),
+                            input_vbytes(spend_info),
+                        )
+                    })
+                    .collect::<Vec<(OutPoint, u64, u64)>>(),
+                &send_amount,
+                fee_rate,
+                miner_fee,
+            )?;
+            &selected_coins[..]
+        } else {
+            coins_to_spend
+        };
         for (list_unspent_entry, spend_info) in list_unspent_result {
             for cts in coins_to_spend {
                 let previous_output = match cts {
@@ -139,7 +380,13 @@ impl Wallet {
                         }
                     }
                 };
-                log::debug!("found coin to spend = {:?}", previous_output);
+                tracing::debug!("found coin to spend = {:?}", previous_output);
+                if !is_economic(list_unspent_entry.amount.as_sat(), &spend_info, fee_rate) {
+                    tracing::warn!(
+                        "coin {} costs more in miner fees to spend than it is worth",
+                        previous_output
+                    );
+                }
 
                 let sequence = match spend_info {
                     UTXOSpendInfo::TimelockContract {
@@ -170,22 +417,48 @@ impl Wallet {
                 tx_inputs
             );
         }
+        let mut accounts = unspent_inputs
+            .iter()
+            .filter_map(|(_, spend_info)| utxo_account(spend_info))
+            .collect::<Vec<WalletAccount>>();
+        accounts.sort();
+        accounts.dedup();
+        if accounts.len() > 1 {
+            tracing::error!(
+                "spending coins of the {} accounts together links them on the blockchain",
+                accounts
+                    .iter()
+                    .map(|a| a.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" and ")
+            );
+            return Err(Error::Protocol(
+                "the given coins are of different accounts, which are never spent together",
+            ));
+        }
 
         let dest_addr = match destination {
             Destination::Wallet => self.get_next_external_address(rpc)?,
-            Destination::Address(a) => {
-                //testnet and signet addresses have the same vbyte
-                //so a.network is always testnet even if the address is signet
-                let testnet_signet_type = (a.network == Network::Testnet
-                    || a.network == Network::Signet)
-                    && (self.network == Network::Testnet || self.network == Network::Signet);
-                if a.network != self.network && !testnet_signet_type {
+            Destination::Address(a) | Destination::Uri(PaymentUri { address: a, .. }) => {
+                if !address_network_matches(&a, self.network) {
                     panic!("wrong address network type (e.g. mainnet, testnet, regtest, signet)");
                 }
                 a
             }
         };
-        let miner_fee = 500 * fee_rate / 1000; //TODO this is just a rough estimate now
+        //the rough estimate is too low for transactions with many inputs
+        let miner_fee = miner_fee.max(tx_fee(
+            unspent_inputs
+                .iter()
+                .map(|(_, spend_info)| input_vbytes(spend_info))
+                .sum(),
+            if let SendAmount::Max = send_amount {
+                1
+            } else {
+                2
+            },
+            fee_rate,
+        ));
 
         let mut output = Vec::<TxOut>::new();
         let total_input_value = unspent_inputs
@@ -228,7 +501,7 @@ impl Wallet {
             lock_time,
             version: 2,
         };
-        log::debug!("unsigned transaction = {:#?}", tx);
+        tracing::debug!("unsigned transaction = {:#?}", tx);
         self.sign_transaction(
             &mut tx,
             &mut unspent_inputs.iter().map(|(_u, usi)| usi.clone()),
@@ -236,3 +509,111 @@ impl Wallet {
         Ok(tx)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ADDRESS: &str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+
+    #[test]
+    fn test_parse_payment_uri() {
+        let uri = format!(
+            "bitcoin:{}?amount=0.1&pj=https%3A%2F%2Fexample.com%2Fpj%3Fid%3D1",
+            ADDRESS
+        );
+        let uri = match Destination::from_str(&uri).unwrap() {
+            Destination::Uri(uri) => uri,
+            d => panic!("not a uri {:?}", d),
+        };
+        assert_eq!(uri.address.to_string(), ADDRESS);
+        assert_eq!(uri.amount, Some(Amount::from_sat(10000000)));
+        assert_eq!(uri.label, None);
+        assert_eq!(
+            uri.payjoin_endpoint.unwrap().as_str(),
+            "https://example.com/pj?id=1"
+        );
+
+        let uri = format!("BITCOIN:{}", ADDRESS);
+        assert!(matches!(
+            Destination::from_str(&uri),
+            Ok(Destination::Uri(PaymentUri {
+                payjoin_endpoint: None,
+                ..
+            }))
+        ));
+
+        let uri = format!("bitcoin:{}?req-somethingnew=1", ADDRESS);
+        assert!(Destination::from_str(&uri).is_err());
+        let uri = format!("bitcoin:{}?pj=http://example.com/pj", ADDRESS);
+        assert!(Destination::from_str(&uri).is_err());
+        let uri = format!("bitcoin:{}?pj=http://abcdef.onion/pj", ADDRESS);
+        assert!(Destination::from_str(&uri).is_ok());
+        let uri = format!("bitcoin:{}?amount=0.1.2", ADDRESS);
+        assert!(Destination::from_str(&uri).is_err());
+        //non-ascii strings are rejected instead of panicking
+        assert!(Destination::from_str("bitcoiñ:abc").is_err());
+        assert!(Destination::from_str("ééééééééé").is_err());
+    }
+
+    #[test]
+    fn test_check_payment_uri() {
+        let uri = format!("bitcoin:{}?amount=0.00015&label=Luke%20Jr", ADDRESS);
+        let uri = PaymentUri::from_str(&uri).unwrap();
+        assert_eq!(uri.label.as_deref(), Some("Luke Jr"));
+
+        let amount = SendAmount::Amount(Amount::from_sat(15000));
+        assert!(uri.check_payment(Network::Bitcoin, &amount).is_ok());
+        assert!(matches!(
+            uri.check_payment(Network::Regtest, &amount),
+            Err(PaymentUriError::WrongNetwork(Network::Regtest))
+        ));
+        let amount = SendAmount::Amount(Amount::from_sat(20000));
+        assert!(uri.check_payment(Network::Bitcoin, &amount).is_err());
+        assert!(uri
+            .check_payment(Network::Bitcoin, &SendAmount::Max)
+            .is_err());
+
+        //without an amount in the uri any send amount is fine
+        let uri = PaymentUri::from_str(&format!("bitcoin:{}", ADDRESS)).unwrap();
+        assert!(uri.check_payment(Network::Bitcoin, &amount).is_ok());
+    }
+
+    #[test]
+    fn test_select_coins() {
+        use bitcoin::hashes::Hash;
+
+        let coins = [50, 20_000, 5_000, 100_000]
+            .iter()
+            .enumerate()
+            .map(|(vout, value)| {
+                (
+                    OutPoint {
+                        txid: bitcoin::Txid::from_slice(&[1; 32]).unwrap(),
+                        vout: vout as u32,
+                    },
+                    *value,
+                    68,
+                )
+            })
+            .collect::<Vec<(OutPoint, u64, u64)>>();
+        let vouts = |selected: Vec<CoinToSpend>| {
+            selected
+                .iter()
+                .map(|c| match c {
+                    CoinToSpend::LongForm(outpoint) => outpoint.vout,
+                    _ => panic!("selected coins are long form"),
+                })
+                .collect::<Vec<u32>>()
+        };
+
+        //the 50 sat coin isnt worth spending at 1 sat/vb
+        let selected = select_coins(&coins, &SendAmount::Max, 1000, 500).unwrap();
+        assert_eq!(vouts(selected), vec![3, 1, 2]);
+        let amount = SendAmount::Amount(Amount::from_sat(110_000));
+        let selected = select_coins(&coins, &amount, 1000, 500).unwrap();
+        assert_eq!(vouts(selected), vec![3, 1]);
+        let amount = SendAmount::Amount(Amount::from_sat(125_000));
+        assert!(select_coins(&coins, &amount, 1000, 500).is_err());
+    }
+}
@@ -0,0 +1,385 @@
+//a directory server, which makers announce their onion or i2p addresses to and takers download
+// the list of makers from, run with `teleport run-directory-server` behind a tor onion service
+//every announcement must carry a fidelity bond proof signing the address and each bond can
+// only stand behind one address, so flooding the directory with fake makers would need a
+// fidelity bond for each of them. a maker announcing a new address with the same bond, e.g.
+// after rotating its onion service, replaces its old address
+//
+//    POST /directoryserver  form with address, net and fidelity_bond_proof as json
+//                           returns the unix time the entry expires at inside <b></b>
+//    GET /makers-<net>.txt  one "expiry,address" line per maker
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::select;
+use tokio::time::sleep;
+
+use bitcoin::{Network, OutPoint};
+use bitcoincore_rpc::RpcApi;
+
+use crate::directory_servers::network_enum_to_string;
+use crate::error::Error;
+use crate::i2p::is_i2p_address;
+use crate::messages::FidelityBondProof;
+use crate::rpc::Client;
+
+pub const DIRECTORY_SERVER_PORT: u16 = 8080;
+//makers refresh their entry every 12 hours
+const ENTRY_EXPIRY_SECS: i64 = 60 * 60 * 24;
+//headers and body together, an announcement is well under 1kb
+const MAX_REQUEST_SIZE: u64 = 16 * 1024;
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+
+struct DirectoryEntry {
+    address: String,
+    expiry: i64,
+}
+
+#[derive(Default)]
+struct Directory {
+    entries: HashMap<OutPoint, DirectoryEntry>,
+}
+
+impl Directory {
+    //returns the expiry of the entry
+    //an address is also only listed for one bond, so it cant be listed twice by announcing it
+    // with two bonds
+    fn add(&mut self, bond_utxo: OutPoint, address: String, now: i64) -> i64 {
+        self.entries.retain(|utxo, entry| {
+            entry.expiry > now && *utxo != bond_utxo && entry.address != address
+        });
+        let expiry = now + ENTRY_EXPIRY_SECS;
+        self.entries
+            .insert(bond_utxo, DirectoryEntry { address, expiry });
+        expiry
+    }
+
+    fn maker_list(&self, now: i64) -> String {
+        let mut entries = self
+            .entries
+            .values()
+            .filter(|e| e.expiry > now)
+            .collect::<Vec<&DirectoryEntry>>();
+        entries.sort_by(|a, b| a.address.cmp(&b.address));
+        entries
+            .iter()
+            .map(|e| format!("{},{}\n", e.expiry, e.address))
+            .collect()
+    }
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    body: String,
+}
+
+struct HttpResponse {
+    status: &'static str,
+    body: String,
+}
+
+impl HttpResponse {
+    fn ok(body: String) -> HttpResponse {
+        HttpResponse {
+            status: "200 OK",
+            body,
+        }
+    }
+
+    fn bad_request(reason: &str) -> HttpResponse {
+        HttpResponse {
+            status: "400 Bad Request",
+            body: format!("{}\n", reason),
+        }
+    }
+
+    fn not_found() -> HttpResponse {
+        HttpResponse {
+            status: "404 Not Found",
+            body: "not found\n".to_string(),
+        }
+    }
+}
+
+//regtest makers dont use directory servers, but a regtest directory server is useful for testing
+fn directory_network_name(network: Network) -> &'static str {
+    match network {
+        Network::Regtest => "regtest",
+        n => network_enum_to_string(n),
+    }
+}
+
+#[tokio::main]
+pub async fn start_directory_server(
+    rpc: Arc<Client>,
+    network: Network,
+    port: u16,
+    kill_flag: Arc<RwLock<bool>>,
+) {
+    match run(rpc, network, port, kill_flag).await {
+        Ok(_o) => tracing::info!("directory server ended without error"),
+        Err(e) => tracing::info!("directory server ended with err {:?}", e),
+    };
+}
+
+async fn run(
+    rpc: Arc<Client>,
+    network: Network,
+    port: u16,
+    kill_flag: Arc<RwLock<bool>>,
+) -> Result<(), Error> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
+    tracing::info!(
+        "Starting teleport directory server for {}. Listening On Port {}",
+        directory_network_name(network),
+        port
+    );
+    let directory = Arc::new(Mutex::new(Directory::default()));
+
+    loop {
+        let (mut socket, addr) = select! {
+            new_client = listener.accept() => new_client?,
+            _ = sleep(Duration::from_secs(3)) => {
+                if *kill_flag.read().unwrap() {
+                    break Ok(());
+                }
+                continue;
+            },
+        };
+        tracing::debug!("[{}] ===> Accepted Connection", addr.port());
+
+        let rpc = Arc::clone(&rpc);
+        let directory = Arc::clone(&directory);
+        tokio::spawn(async move {
+            let (socket_reader, mut socket_writer) = socket.split();
+            let mut reader = BufReader::new(socket_reader.take(MAX_REQUEST_SIZE));
+            let request = select! {
+                ret = read_request(&mut reader) => ret,
+                _ = sleep(Duration::from_secs(REQUEST_TIMEOUT_SECS)) => {
+                    Err(Error::Protocol("timed out reading request"))
+                },
+            };
+            let response = match request {
+                Ok(request) => handle_request(&rpc, network, &directory, &request),
+                Err(e) => {
+                    tracing::debug!("[{}] invalid request: {:?}", addr.port(), e);
+                    HttpResponse::bad_request("invalid request")
+                }
+            };
+            let response_bytes = format!(
+                "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\
+                Connection: close\r\n\r\n{}",
+                response.status,
+                response.body.len(),
+                response.body
+            );
+            if let Err(e) = socket_writer.write_all(response_bytes.as_bytes()).await {
+                tracing::debug!("error writing to socket: {:?}", e);
+            }
+        });
+    }
+}
+
+async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<HttpRequest, Error> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut request_line_parts = request_line.split_whitespace();
+    let (method, path) = match (request_line_parts.next(), request_line_parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(Error::Protocol("invalid request line")),
+    };
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(Error::Protocol("request ended in the headers"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| Error::Protocol("invalid content length"))?;
+            }
+        }
+    }
+    if content_length as u64 > MAX_REQUEST_SIZE {
+        return Err(Error::Protocol("request too big"));
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).await?;
+    let body = String::from_utf8(body).map_err(|_| Error::Protocol("body not utf8"))?;
+    Ok(HttpRequest { method, path, body })
+}
+
+fn handle_request(
+    rpc: &Client,
+    network: Network,
+    directory: &Mutex<Directory>,
+    request: &HttpRequest,
+) -> HttpResponse {
+    let now = chrono::Utc::now().timestamp();
+    let network_name = directory_network_name(network);
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", path) if path == format!("/makers-{}.txt", network_name) => {
+            HttpResponse::ok(directory.lock().unwrap().maker_list(now))
+        }
+        ("POST", "/directoryserver") => {
+            let mut address = None;
+            let mut net = None;
+            let mut fidelity_bond_proof = None;
+            for (name, value) in url::form_urlencoded::parse(request.body.as_bytes()) {
+                match name.as_ref() {
+                    "address" => address = Some(value.into_owned()),
+                    "net" => net = Some(value.into_owned()),
+                    "fidelity_bond_proof" => fidelity_bond_proof = Some(value.into_owned()),
+                    _ => (),
+                }
+            }
+            let (address, fidelity_bond_proof) = match (address, fidelity_bond_proof) {
+                (Some(a), Some(p)) => (a, p),
+                (Some(_a), None) => {
+                    return HttpResponse::bad_request("fidelity bond proof required")
+                }
+                _ => return HttpResponse::bad_request("address required"),
+            };
+            if net.as_deref() != Some(network_name) {
+                return HttpResponse::bad_request("wrong network");
+            }
+            let fidelity_bond_proof =
+                match serde_json::from_str::<FidelityBondProof>(&fidelity_bond_proof) {
+                    Ok(p) => p,
+                    Err(_) => return HttpResponse::bad_request("invalid fidelity bond proof"),
+                };
+            if let Err(e) = verify_announcement(rpc, &address, &fidelity_bond_proof) {
+                tracing::debug!("rejected announcement of {}: {:?}", address, e);
+                return HttpResponse::bad_request(match e {
+                    Error::Protocol(reason) => reason,
+                    _ => "unable to check fidelity bond",
+                });
+            }
+            tracing::info!(
+                "maker {} announced with fidelity bond {}",
+                address,
+                fidelity_bond_proof.utxo
+            );
+            let expiry =
+                directory
+                    .lock()
+                    .unwrap()
+                    .add(fidelity_bond_proof.utxo, address.clone(), now);
+            HttpResponse::ok(format!(
+                "<html><body>added {} until <b>{}</b></body></html>\n",
+                address, expiry
+            ))
+        }
+        _ => HttpResponse::not_found(),
+    }
+}
+
+fn verify_announcement(
+    rpc: &Client,
+    address: &str,
+    fidelity_bond_proof: &FidelityBondProof,
+) -> Result<(), Error> {
+    let hostname = address
+        .rsplit_once(':')
+        .map_or(address, |(host, _port)| host);
+    if !hostname.ends_with(".onion") && !is_i2p_address(hostname) {
+        return Err(Error::Protocol("only onion and i2p addresses are listed"));
+    }
+    let block_count = rpc.get_block_count()?;
+    fidelity_bond_proof.verify_and_get_txo(rpc, block_count, hostname)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use bitcoin::Txid;
+    use bitcoincore_rpc::Auth;
+
+    fn bond_utxo(vout: u32) -> OutPoint {
+        OutPoint {
+            txid: Txid::from_str(
+                "5f6c1fb5f8b25bdd2b1b9e87d24ca4ba2a8e2a0c0f1d1b7ab0e5b0c7d3c3e0a1",
+            )
+            .unwrap(),
+            vout,
+        }
+    }
+
+    #[test]
+    fn test_directory() {
+        let mut directory = Directory::default();
+        let expiry = directory.add(bond_utxo(0), "a.onion:6102".to_string(), 1000);
+        assert_eq!(expiry, 1000 + ENTRY_EXPIRY_SECS);
+        directory.add(bond_utxo(1), "b.onion:6102".to_string(), 1000);
+        assert_eq!(
+            directory.maker_list(1000),
+            format!("{0},a.onion:6102\n{0},b.onion:6102\n", expiry)
+        );
+
+        //same bond, new address
+        directory.add(bond_utxo(0), "c.onion:6102".to_string(), 1000);
+        //same address, new bond
+        directory.add(bond_utxo(2), "b.onion:6102".to_string(), 1000);
+        assert_eq!(
+            directory.maker_list(1000),
+            format!("{0},b.onion:6102\n{0},c.onion:6102\n", expiry)
+        );
+        assert_eq!(directory.maker_list(expiry), "");
+    }
+
+    #[tokio::test]
+    async fn test_read_request() {
+        let body = "address=a.onion%3A6102&net=regtest";
+        let request_bytes = format!(
+            "POST /directoryserver HTTP/1.1\r\nHost: a.onion\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let request = read_request(&mut request_bytes.as_bytes()).await.unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/directoryserver");
+        assert_eq!(request.body, body);
+
+        //no rpc call is made before the announcement is found to be incomplete
+        let rpc = Client::new("http://127.0.0.1:1".to_string(), Auth::None).unwrap();
+        let directory = Mutex::new(Directory::default());
+        let response = handle_request(&rpc, Network::Regtest, &directory, &request);
+        assert_eq!(response.status, "400 Bad Request");
+        assert_eq!(response.body, "fidelity bond proof required\n");
+
+        let request = read_request(&mut &b"GET /makers-regtest.txt HTTP/1.1\r\n\r\n"[..])
+            .await
+            .unwrap();
+        let response = handle_request(&rpc, Network::Regtest, &directory, &request);
+        assert_eq!(response.status, "200 OK");
+        assert_eq!(response.body, "");
+        let request = read_request(&mut &b"GET /makers-mainnet.txt HTTP/1.1\r\n\r\n"[..])
+            .await
+            .unwrap();
+        let response = handle_request(&rpc, Network::Regtest, &directory, &request);
+        assert_eq!(response.status, "404 Not Found");
+
+        assert!(
+            read_request(&mut &b"GET /makers-regtest.txt HTTP/1.1\r\n"[..])
+                .await
+                .is_err()
+        );
+    }
+}
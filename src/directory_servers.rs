@@ -3,7 +3,9 @@ pub const TOR_ADDR: &str = "127.0.0.1:9050";
 
 use bitcoin::Network;
 
+use crate::messages::FidelityBondProof;
 use crate::offerbook_sync::MakerAddress;
+use crate::settings::Settings;
 
 //for now just one of these, but later we'll need multiple for good decentralization
 const DIRECTORY_SERVER_ADDR: &str =
@@ -21,18 +23,37 @@ impl From<reqwest::Error> for DirectoryServerError {
     }
 }
 
-fn network_enum_to_string(network: Network) -> &'static str {
+pub(crate) fn network_enum_to_string(network: Network) -> &'static str {
     match network {
         Network::Bitcoin => "mainnet",
+        //testnet3 and testnet4 are both Network::Testnet, but have different makers
+        Network::Testnet
+            if Settings::is_initialized() && Settings::global().blockchain.is_testnet4() =>
+        {
+            "testnet4"
+        }
         Network::Testnet => "testnet",
         Network::Signet => "signet",
         Network::Regtest => panic!("dont use directory servers if using regtest"),
     }
 }
 
+//all requests go through tor anyway, but in tor only mode dont even leave tor for a
+// clearnet directory server
+fn check_directory_server_addr() -> Result<(), DirectoryServerError> {
+    let host = DIRECTORY_SERVER_ADDR.split(':').next().unwrap();
+    if Settings::is_tor_only() && !host.ends_with(".onion") {
+        return Err(DirectoryServerError::Other(
+            "tor only, refusing to contact clearnet directory server",
+        ));
+    }
+    Ok(())
+}
+
 pub async fn sync_maker_addresses_from_directory_servers(
     network: Network,
 ) -> Result<Vec<MakerAddress>, DirectoryServerError> {
+    check_directory_server_addr()?;
     // https://github.com/seanmonstar/reqwest/blob/master/examples/tor_socks.rs
     let proxy =
         reqwest::Proxy::all(format!("socks5h://{}", TOR_ADDR)).expect("tor proxy should be there");
@@ -57,29 +78,36 @@ pub async fn sync_maker_addresses_from_directory_servers(
         if csv_chunks.len() < 2 {
             continue;
         }
-        maker_addresses.push(MakerAddress::Tor {
-            address: String::from(csv_chunks[1]),
-        });
-        log::debug!(target:"directory_servers", "expiry timestamp = {} address = {}",
+        maker_addresses.push(MakerAddress::from_published(String::from(csv_chunks[1])));
+        tracing::debug!(target:"directory_servers", "expiry timestamp = {} address = {}",
             csv_chunks[0], csv_chunks[1]);
     }
     Ok(maker_addresses)
 }
 
+//directory servers may refuse makers without a fidelity bond proof for the address
 pub async fn post_maker_address_to_directory_servers(
     network: Network,
     address: &str,
+    fidelity_bond_proof: Option<&FidelityBondProof>,
 ) -> Result<u64, DirectoryServerError> {
+    check_directory_server_addr()?;
     let proxy =
         reqwest::Proxy::all(format!("socks5h://{}", TOR_ADDR)).expect("tor proxy should be there");
     let client = reqwest::Client::builder()
         .proxy(proxy)
         .build()
         .expect("should be able to build reqwest client");
-    let params = [
-        ("address", address),
-        ("net", network_enum_to_string(network)),
+    let mut params = vec![
+        ("address", address.to_string()),
+        ("net", network_enum_to_string(network).to_string()),
     ];
+    if let Some(proof) = fidelity_bond_proof {
+        params.push((
+            "fidelity_bond_proof",
+            serde_json::to_string(proof).expect("proof should serialize"),
+        ));
+    }
     let res = client
         .post(format!("http://{}/directoryserver", DIRECTORY_SERVER_ADDR))
         .form(&params)
@@ -8,8 +8,12 @@ pub enum Error {
     Network(Box<dyn error::Error + Send>),
     Disk(io::Error),
     Protocol(&'static str),
+    //a funding tx pays less than the minimum fee rate, both in sats per 1000 vbytes
+    FundingTxFeeRate { fee_rate: u64, min_fee_rate: u64 },
     Rpc(bitcoincore_rpc::Error),
     Socks(tokio_socks::Error),
+    //every problem found with the configuration
+    Config(Vec<String>),
 }
 
 impl From<Box<dyn error::Error + Send>> for Error {
@@ -0,0 +1,334 @@
+//sends the coins a coinswap made on to several destinations, one transaction per destination
+// at a random time, so the destinations cant be linked by sharing a transaction or by being
+// paid at the same moment
+//coins arent split, each one goes whole to the destination the split policy picks for it. the
+// plan is saved in the data directory so the sends left carry on if teleport is restarted
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use bitcoin::{Address, OutPoint, Txid};
+use bitcoincore_rpc::RpcApi;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::direct_send::{CoinToSpend, Destination, SendAmount};
+use crate::error::Error;
+use crate::rpc::Client;
+use crate::utils::teleport_data_dir;
+use crate::wallet_sync::{UTXOSpendInfo, Wallet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitPolicy {
+    //every destination gets about the same value
+    Even,
+    //coins go to random destinations, each destination gets at least one
+    Random,
+}
+
+impl FromStr for SplitPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "even" => Ok(SplitPolicy::Even),
+            "random" => Ok(SplitPolicy::Random),
+            _ => Err(format!(
+                "unknown split policy `{}`, must be even or random",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FanOutParams {
+    pub destinations: Vec<Address>,
+    pub split_policy: SplitPolicy,
+    pub max_delay_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlannedSend {
+    pub destination: String,
+    pub coins: Vec<OutPoint>,
+    pub send_timestamp: i64,
+    //set once it is broadcast
+    pub txid: Option<Txid>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FanOutPlan {
+    pub sends: Vec<PlannedSend>,
+}
+
+impl FanOutPlan {
+    /// Plan a send of the coins, given with their values, to each destination at a random
+    /// time within max_delay_secs. Destinations left without a coin are dropped
+    pub fn new<R: Rng>(
+        coins: &[(OutPoint, u64)],
+        params: &FanOutParams,
+        now: i64,
+        rng: &mut R,
+    ) -> FanOutPlan {
+        let sends = split_coins(coins, params.destinations.len(), params.split_policy, rng)
+            .into_iter()
+            .zip(params.destinations.iter())
+            .filter(|(coins, _)| !coins.is_empty())
+            .map(|(coins, destination)| PlannedSend {
+                destination: destination.to_string(),
+                coins,
+                send_timestamp: now + rng.gen_range(0, params.max_delay_secs + 1) as i64,
+                txid: None,
+            })
+            .collect();
+        FanOutPlan { sends }
+    }
+
+    pub fn load(path: &Path) -> Result<FanOutPlan, Error> {
+        Ok(serde_json::from_reader(File::open(path)?).map_err(io::Error::from)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        serde_json::to_writer_pretty(File::create(path)?, self).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    /// Index of the earliest send not yet broadcast
+    pub fn next_send(&self) -> Option<usize> {
+        self.sends
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.txid.is_none())
+            .min_by_key(|(_, s)| s.send_timestamp)
+            .map(|(i, _)| i)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_send().is_none()
+    }
+}
+
+//returns the coins of each destination
+fn split_coins<R: Rng>(
+    coins: &[(OutPoint, u64)],
+    destination_count: usize,
+    split_policy: SplitPolicy,
+    rng: &mut R,
+) -> Vec<Vec<OutPoint>> {
+    let mut split = vec![Vec::new(); destination_count];
+    if destination_count == 0 {
+        return split;
+    }
+    match split_policy {
+        SplitPolicy::Even => {
+            //biggest coins first, each to the destination with the least so far
+            let mut coins = coins.to_vec();
+            coins.sort_by_key(|(_, value)| std::cmp::Reverse(*value));
+            let mut totals = vec![0u64; destination_count];
+            for (coin, value) in coins {
+                let (i, _) = totals
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, total)| **total)
+                    .unwrap();
+                totals[i] += value;
+                split[i].push(coin);
+            }
+        }
+        SplitPolicy::Random => {
+            let mut coins = coins.iter().map(|(c, _)| *c).collect::<Vec<OutPoint>>();
+            coins.shuffle(rng);
+            for (i, coin) in coins.into_iter().enumerate() {
+                let destination = if i < destination_count {
+                    i
+                } else {
+                    rng.gen_range(0, destination_count)
+                };
+                split[destination].push(coin);
+            }
+        }
+    }
+    split
+}
+
+/// Path of the fan out plan of a wallet in the data directory
+pub fn fan_out_plan_path(wallet_file_name: &Path) -> PathBuf {
+    let mut file_name = wallet_file_name
+        .file_name()
+        .unwrap_or(wallet_file_name.as_os_str())
+        .to_os_string();
+    file_name.push(".fanout.json");
+    teleport_data_dir().join(file_name)
+}
+
+/// The wallet's spendable coins from completed coinswaps, with their values in sats
+pub fn swapcoin_values(rpc: &Client, wallet: &Wallet) -> Result<Vec<(OutPoint, u64)>, Error> {
+    Ok(wallet
+        .list_unspent_from_wallet(rpc, false, false)?
+        .into_iter()
+        .filter(|(_, spend_info)| matches!(spend_info, UTXOSpendInfo::SwapCoin { .. }))
+        .map(|(u, _)| {
+            (
+                OutPoint {
+                    txid: u.txid,
+                    vout: u.vout,
+                },
+                u.amount.as_sat(),
+            )
+        })
+        .collect())
+}
+
+/// Send all of the coins to destination in one transaction
+pub fn send_coins_to_destination(
+    rpc: &Client,
+    wallet: &mut Wallet,
+    fee_rate: u64,
+    coins: &[OutPoint],
+    destination: &str,
+) -> Result<Txid, Error> {
+    let destination = Address::from_str(destination)
+        .map_err(|_| Error::Protocol("invalid destination address"))?;
+    let coins_to_spend = coins
+        .iter()
+        .map(|c| CoinToSpend::LongForm(*c))
+        .collect::<Vec<CoinToSpend>>();
+    let tx = wallet.create_direct_send(
+        rpc,
+        fee_rate,
+        SendAmount::Max,
+        Destination::Address(destination),
+        &coins_to_spend,
+    )?;
+    Ok(rpc.send_raw_transaction(&tx)?)
+}
+
+/// Broadcast the sends of the plan saved at plan_path as each one's time comes, saving the
+/// plan after each
+pub fn run_fan_out(
+    rpc: &Client,
+    wallet: &mut Wallet,
+    plan_path: &Path,
+    fee_rate: u64,
+) -> Result<(), Error> {
+    let mut plan = FanOutPlan::load(plan_path)?;
+    while let Some(index) = plan.next_send() {
+        let send = plan.sends[index].clone();
+        let wait_secs = send.send_timestamp - chrono::Utc::now().timestamp();
+        if wait_secs > 0 {
+            tracing::info!(
+                "waiting {} seconds before sending to {}",
+                wait_secs,
+                send.destination
+            );
+            std::thread::sleep(Duration::from_secs(wait_secs as u64));
+        }
+        let txid =
+            send_coins_to_destination(rpc, wallet, fee_rate, &send.coins, &send.destination)?;
+        tracing::info!(
+            "sent {} coins to {} in {}",
+            send.coins.len(),
+            send.destination,
+            txid
+        );
+        plan.sends[index].txid = Some(txid);
+        plan.save(plan_path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn coin(vout: u32, value: u64) -> (OutPoint, u64) {
+        (
+            OutPoint {
+                txid: Txid::from_slice(&[vout as u8; 32]).unwrap(),
+                vout,
+            },
+            value,
+        )
+    }
+
+    fn total(split: &[OutPoint], coins: &[(OutPoint, u64)]) -> u64 {
+        coins
+            .iter()
+            .filter(|(c, _)| split.contains(c))
+            .map(|(_, v)| v)
+            .sum()
+    }
+
+    #[test]
+    fn test_split_coins() {
+        let coins = vec![
+            coin(0, 500),
+            coin(1, 300),
+            coin(2, 200),
+            coin(3, 250),
+            coin(4, 250),
+        ];
+        let mut rng = StdRng::seed_from_u64(1);
+        let split = split_coins(&coins, 2, SplitPolicy::Even, &mut rng);
+        assert_eq!(total(&split[0], &coins), 750);
+        assert_eq!(total(&split[1], &coins), 750);
+
+        for _ in 0..10 {
+            let split = split_coins(&coins, 3, SplitPolicy::Random, &mut rng);
+            assert!(split.iter().all(|s| !s.is_empty()));
+            assert_eq!(split.iter().map(|s| s.len()).sum::<usize>(), coins.len());
+        }
+
+        //more destinations than coins leaves some empty
+        let split = split_coins(&coins[..1], 2, SplitPolicy::Random, &mut rng);
+        assert_eq!(split.iter().filter(|s| s.is_empty()).count(), 1);
+    }
+
+    #[test]
+    fn test_fan_out_plan() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("taker.teleport.fanout.json");
+        let destinations = vec![
+            Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap(),
+            Address::from_str("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2").unwrap(),
+            Address::from_str("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy").unwrap(),
+        ];
+        let params = FanOutParams {
+            destinations,
+            split_policy: SplitPolicy::Even,
+            max_delay_secs: 3600,
+        };
+        let coins = vec![coin(0, 500), coin(1, 300)];
+        let mut plan = FanOutPlan::new(&coins, &params, 1000, &mut rand::thread_rng());
+        //only two coins for three destinations
+        assert_eq!(plan.sends.len(), 2);
+        for send in &plan.sends {
+            assert!(send.send_timestamp >= 1000 && send.send_timestamp <= 1000 + 3600);
+        }
+
+        let first = plan.next_send().unwrap();
+        assert!(plan
+            .sends
+            .iter()
+            .all(|s| s.send_timestamp >= plan.sends[first].send_timestamp));
+        plan.sends[first].txid = Some(Txid::from_slice(&[9; 32]).unwrap());
+        plan.save(&path).unwrap();
+        let mut plan = FanOutPlan::load(&path).unwrap();
+        let second = plan.next_send().unwrap();
+        assert_ne!(first, second);
+        plan.sends[second].txid = Some(Txid::from_slice(&[8; 32]).unwrap());
+        assert!(plan.is_finished());
+
+        assert_eq!("random".parse::<SplitPolicy>(), Ok(SplitPolicy::Random));
+        assert!("half".parse::<SplitPolicy>().is_err());
+    }
+}
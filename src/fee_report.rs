@@ -0,0 +1,202 @@
+//miner fees and coinswap fees of a wallet by month, shown by `teleport fee-report`
+//the miner fees of payments come from the send history in the wallet file, a maker's coinswap
+// fees and the miner fees of its funding txes from the swap history in the wallet file, and a
+// taker's from the swap receipts signed with the wallet's key
+//payments sent before the send history recorded their miner fee are counted without it
+
+use std::collections::BTreeMap;
+
+use bitcoin::util::ecdsa::PublicKey;
+use serde::Serialize;
+
+use crate::swap_receipt::SwapReceipt;
+use crate::wallet_sync::{SendHistoryEntry, SwapHistoryEntry};
+
+pub const FEE_REPORT_CSV_HEADER: &str =
+    "month,miner_fee_sat,coinswap_fee_paid_sat,coinswap_fee_earned_sat";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct MonthlyFees {
+    //e.g. "2026-10", or "total"
+    pub month: String,
+    pub miner_fee_sat: u64,
+    //by a taker to makers
+    pub coinswap_fee_paid_sat: u64,
+    //by a maker from takers
+    pub coinswap_fee_earned_sat: u64,
+}
+
+impl MonthlyFees {
+    pub fn to_csv_line(&self) -> String {
+        format!(
+            "{},{},{},{}",
+            self.month,
+            self.miner_fee_sat,
+            self.coinswap_fee_paid_sat,
+            self.coinswap_fee_earned_sat
+        )
+    }
+}
+
+fn month_entry(months: &mut BTreeMap<String, MonthlyFees>, timestamp: i64) -> &mut MonthlyFees {
+    let month = chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|d| d.format("%Y-%m").to_string())
+        .unwrap_or_default();
+    months.entry(month.clone()).or_insert_with(|| MonthlyFees {
+        month,
+        ..MonthlyFees::default()
+    })
+}
+
+/// Fees of each month with any, oldest first, only the receipts signed with signing_pubkey
+/// are of this wallet's coinswaps and only completed coinswaps are counted
+pub fn fees_by_month(
+    send_history: &[SendHistoryEntry],
+    swap_history: &[SwapHistoryEntry],
+    receipts: &[SwapReceipt],
+    signing_pubkey: &PublicKey,
+) -> Vec<MonthlyFees> {
+    let mut months = BTreeMap::<String, MonthlyFees>::new();
+    for entry in send_history {
+        month_entry(&mut months, entry.timestamp).miner_fee_sat += entry.miner_fee_sat.unwrap_or(0);
+    }
+    for entry in swap_history {
+        if let Some(completed_timestamp) = entry.completed_timestamp {
+            let fees = month_entry(&mut months, completed_timestamp);
+            fees.miner_fee_sat += entry.miner_fee_sat;
+            fees.coinswap_fee_earned_sat += entry.fee_income_sat();
+        }
+    }
+    for receipt in receipts
+        .iter()
+        .filter(|r| &r.signing_pubkey == signing_pubkey)
+    {
+        let fees = month_entry(&mut months, receipt.contents.completed_timestamp);
+        fees.miner_fee_sat += receipt.contents.miner_fee_sat;
+        fees.coinswap_fee_paid_sat += receipt.contents.coinswap_fee_sat;
+    }
+    months.into_values().collect()
+}
+
+pub fn total_fees(months: &[MonthlyFees]) -> MonthlyFees {
+    months.iter().fold(
+        MonthlyFees {
+            month: "total".to_string(),
+            ..MonthlyFees::default()
+        },
+        |mut total, fees| {
+            total.miner_fee_sat += fees.miner_fee_sat;
+            total.coinswap_fee_paid_sat += fees.coinswap_fee_paid_sat;
+            total.coinswap_fee_earned_sat += fees.coinswap_fee_earned_sat;
+            total
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::{hash160::Hash as Hash160, Hash};
+    use bitcoin::secp256k1::SecretKey;
+    use bitcoin::Txid;
+
+    use crate::swap_receipt::SwapReceiptContents;
+
+    //2026-09-15 and 2026-10-15
+    const SEPTEMBER: i64 = 1_789_430_400;
+    const OCTOBER: i64 = 1_792_022_400;
+
+    fn receipt(privkey: &SecretKey, completed_timestamp: i64) -> SwapReceipt {
+        let contents = SwapReceiptContents {
+            swap_id: Hash160::hash(&completed_timestamp.to_le_bytes()),
+            completed_timestamp,
+            sent_amount_sat: 100_000,
+            received_amount_sat: 98_000,
+            coinswap_fee_sat: 2_000,
+            miner_fee_sat: 500,
+            funding_txids: Vec::new(),
+            settlement_txids: Vec::new(),
+            makers: Vec::new(),
+            transcript_start_hash: None,
+            transcript_end_hash: None,
+        };
+        SwapReceipt::sign(contents, privkey).unwrap()
+    }
+
+    #[test]
+    fn test_fees_by_month() {
+        let send_history = vec![
+            SendHistoryEntry {
+                txid: Txid::default(),
+                address: String::new(),
+                amount_sat: 50_000,
+                label: None,
+                timestamp: SEPTEMBER,
+                miner_fee_sat: Some(200),
+            },
+            //sent before the miner fee was recorded
+            SendHistoryEntry {
+                txid: Txid::default(),
+                address: String::new(),
+                amount_sat: 50_000,
+                label: None,
+                timestamp: SEPTEMBER,
+                miner_fee_sat: None,
+            },
+        ];
+        let swap_history = vec![
+            SwapHistoryEntry {
+                hashvalue: Hash160::hash(&[1]),
+                incoming_amount_sat: 100_000,
+                outgoing_amount_sat: 99_000,
+                coinswap_fee_sat: 700,
+                miner_fee_sat: 300,
+                started_timestamp: OCTOBER,
+                completed_timestamp: Some(OCTOBER),
+            },
+            SwapHistoryEntry {
+                hashvalue: Hash160::hash(&[2]),
+                incoming_amount_sat: 100_000,
+                outgoing_amount_sat: 99_000,
+                coinswap_fee_sat: 700,
+                miner_fee_sat: 300,
+                started_timestamp: OCTOBER,
+                completed_timestamp: None,
+            },
+        ];
+        let privkey = SecretKey::from_slice(&[1; 32]).unwrap();
+        let other_privkey = SecretKey::from_slice(&[2; 32]).unwrap();
+        let receipts = vec![
+            receipt(&privkey, OCTOBER),
+            //of another wallet
+            receipt(&other_privkey, OCTOBER),
+        ];
+
+        let months = fees_by_month(
+            &send_history,
+            &swap_history,
+            &receipts,
+            &receipts[0].signing_pubkey,
+        );
+        assert_eq!(
+            months,
+            vec![
+                MonthlyFees {
+                    month: "2026-09".to_string(),
+                    miner_fee_sat: 200,
+                    coinswap_fee_paid_sat: 0,
+                    coinswap_fee_earned_sat: 0,
+                },
+                MonthlyFees {
+                    month: "2026-10".to_string(),
+                    miner_fee_sat: 800,
+                    coinswap_fee_paid_sat: 2_000,
+                    coinswap_fee_earned_sat: 1_000,
+                },
+            ]
+        );
+        let total = total_fees(&months);
+        assert_eq!(total.to_csv_line(), "total,1000,2000,1000");
+    }
+}
@@ -0,0 +1,328 @@
+//C API for embedding the taker in wallets written in other languages, see include/teleport.h
+//build a shared library with `cargo rustc --lib --release --crate-type cdylib`
+//
+//every function returns one of the TELEPORT_* codes below, the message of the last error
+// on the calling thread is returned by teleport_last_error()
+//wallets are opaque handles made by teleport_wallet_load() and freed by teleport_wallet_free()
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::mpsc::channel;
+use std::thread;
+
+use crate::daemon::OfferInfo;
+use crate::error::Error;
+use crate::offerbook_sync::sync_offerbook;
+use crate::rpc::Client;
+use crate::settings::{Settings, SettingsOverrides};
+use crate::taker_protocol::{min_funding_tx_fee_rate, start_taker, TakerConfig, TakerEvent};
+use crate::utils::default_data_dir;
+use crate::wallet_sync::{Wallet, WalletSyncAddressAmount};
+use crate::{get_bitcoin_rpc, setup_teleport, str_to_bitcoin_network};
+
+pub const TELEPORT_OK: i32 = 0;
+pub const TELEPORT_ERR_INVALID_ARGUMENT: i32 = 1;
+pub const TELEPORT_ERR_NOT_INITIALIZED: i32 = 2;
+pub const TELEPORT_ERR_NETWORK: i32 = 3;
+pub const TELEPORT_ERR_DISK: i32 = 4;
+pub const TELEPORT_ERR_PROTOCOL: i32 = 5;
+pub const TELEPORT_ERR_RPC: i32 = 6;
+pub const TELEPORT_ERR_SOCKS: i32 = 7;
+pub const TELEPORT_ERR_CONFIG: i32 = 8;
+pub const TELEPORT_ERR_COINSWAP_FAILED: i32 = 9;
+pub const TELEPORT_ERR_PANIC: i32 = 10;
+
+/// Called with every coinswap progress event as a JSON object, the string is only
+/// valid for the duration of the call
+pub type TeleportEventCallback =
+    Option<unsafe extern "C" fn(event_json: *const c_char, user_data: *mut c_void)>;
+
+pub struct TeleportWallet {
+    rpc: Client,
+    wallet: Wallet,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', "")).unwrap();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+struct FfiError {
+    code: i32,
+    message: String,
+}
+
+impl FfiError {
+    fn new(code: i32, message: impl Into<String>) -> FfiError {
+        FfiError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<Error> for FfiError {
+    fn from(e: Error) -> FfiError {
+        let code = match e {
+            Error::Network(_) => TELEPORT_ERR_NETWORK,
+            Error::Disk(_) => TELEPORT_ERR_DISK,
+            Error::Protocol(_) | Error::FundingTxFeeRate { .. } => TELEPORT_ERR_PROTOCOL,
+            Error::Rpc(_) => TELEPORT_ERR_RPC,
+            Error::Socks(_) => TELEPORT_ERR_SOCKS,
+            Error::Config(_) => TELEPORT_ERR_CONFIG,
+        };
+        FfiError::new(code, format!("{:?}", e))
+    }
+}
+
+//runs f, turning errors and panics into an error code and the last error message
+fn ffi_call<F: FnOnce() -> Result<(), FfiError>>(f: F) -> i32 {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => TELEPORT_OK,
+        Ok(Err(e)) => {
+            set_last_error(e.message);
+            e.code
+        }
+        Err(_) => {
+            set_last_error("panic inside teleport".to_string());
+            TELEPORT_ERR_PANIC
+        }
+    }
+}
+
+fn check_initialized() -> Result<(), FfiError> {
+    if Settings::is_initialized() {
+        Ok(())
+    } else {
+        Err(FfiError::new(
+            TELEPORT_ERR_NOT_INITIALIZED,
+            "call teleport_init first",
+        ))
+    }
+}
+
+unsafe fn path_arg(arg: *const c_char, name: &str) -> Result<PathBuf, FfiError> {
+    if arg.is_null() {
+        return Err(null_argument(name));
+    }
+    match CStr::from_ptr(arg).to_str() {
+        Ok(s) => Ok(PathBuf::from(s)),
+        Err(_) => Err(FfiError::new(
+            TELEPORT_ERR_INVALID_ARGUMENT,
+            format!("{} is not utf-8", name),
+        )),
+    }
+}
+
+fn null_argument(name: &str) -> FfiError {
+    FfiError::new(TELEPORT_ERR_INVALID_ARGUMENT, format!("{} is null", name))
+}
+
+/// Message of the last error returned on this thread, or null if there wasnt one.
+/// Valid until the next teleport call on this thread
+#[no_mangle]
+pub extern "C" fn teleport_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| match &*last_error.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Load the settings and set up logging, must be called once before anything else
+///
+/// # Safety
+/// data_dir must be null, for the default data directory, or a nul-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn teleport_init(data_dir: *const c_char) -> i32 {
+    ffi_call(|| {
+        if Settings::is_initialized() {
+            return Err(FfiError::new(
+                TELEPORT_ERR_INVALID_ARGUMENT,
+                "teleport_init was already called",
+            ));
+        }
+        let data_dir = if data_dir.is_null() {
+            default_data_dir("teleport")
+        } else {
+            path_arg(data_dir, "data_dir")?
+        };
+        Settings::init_settings(&data_dir, &SettingsOverrides::default())?;
+        setup_teleport();
+        Ok(())
+    })
+}
+
+/// Load and sync a wallet file, relative paths are relative to the current directory
+///
+/// # Safety
+/// wallet_file must be a nul-terminated string and wallet_out a valid pointer
+#[no_mangle]
+pub unsafe extern "C" fn teleport_wallet_load(
+    wallet_file: *const c_char,
+    wallet_out: *mut *mut TeleportWallet,
+) -> i32 {
+    ffi_call(|| {
+        let wallet_file = path_arg(wallet_file, "wallet_file")?;
+        if wallet_out.is_null() {
+            return Err(null_argument("wallet_out"));
+        }
+        check_initialized()?;
+        let (rpc, network) = get_bitcoin_rpc()?;
+        let mut wallet =
+            Wallet::load_wallet_from_file(&wallet_file, network, WalletSyncAddressAmount::Normal)?;
+        wallet.startup_sync(&rpc)?;
+        *wallet_out = Box::into_raw(Box::new(TeleportWallet { rpc, wallet }));
+        Ok(())
+    })
+}
+
+/// # Safety
+/// wallet must be null or a handle from teleport_wallet_load which wasnt freed already
+#[no_mangle]
+pub unsafe extern "C" fn teleport_wallet_free(wallet: *mut TeleportWallet) {
+    if !wallet.is_null() {
+        drop(Box::from_raw(wallet));
+    }
+}
+
+/// Download the offers of every advertised maker, written to offers_json_out as a JSON
+/// array which must be freed with teleport_string_free
+///
+/// # Safety
+/// offers_json_out must be a valid pointer
+#[no_mangle]
+pub unsafe extern "C" fn teleport_sync_offerbook(offers_json_out: *mut *mut c_char) -> i32 {
+    ffi_call(|| {
+        if offers_json_out.is_null() {
+            return Err(null_argument("offers_json_out"));
+        }
+        check_initialized()?;
+        let network = str_to_bitcoin_network(&Settings::global().blockchain.network);
+        let runtime = tokio::runtime::Runtime::new().map_err(Error::Disk)?;
+        let offers = runtime
+            .block_on(sync_offerbook(network))
+            .map_err(|e| FfiError::new(TELEPORT_ERR_NETWORK, format!("{:?}", e)))?
+            .into_iter()
+            .map(|offer_address| OfferInfo {
+                address: offer_address.address.to_string(),
+                offer: offer_address.offer,
+            })
+            .collect::<Vec<OfferInfo>>();
+        let offers_json = serde_json::to_string(&offers).unwrap();
+        *offers_json_out = CString::new(offers_json).unwrap().into_raw();
+        Ok(())
+    })
+}
+
+/// # Safety
+/// s must be null or a string returned by this library which wasnt freed already
+#[no_mangle]
+pub unsafe extern "C" fn teleport_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Do a coinswap with the wallet, blocking until it finishes
+/// callback, if not null, is called on this thread with every progress event
+///
+/// # Safety
+/// wallet must be a handle from teleport_wallet_load, user_data is only passed to callback
+#[no_mangle]
+pub unsafe extern "C" fn teleport_run_coinswap(
+    wallet: *mut TeleportWallet,
+    send_amount: u64,
+    maker_count: u16,
+    tx_count: u32,
+    fee_rate: u64,
+    callback: TeleportEventCallback,
+    user_data: *mut c_void,
+) -> i32 {
+    ffi_call(|| {
+        let wallet = wallet.as_mut().ok_or_else(|| null_argument("wallet"))?;
+        check_initialized()?;
+        wallet.wallet.open_audit_log()?;
+        let config = TakerConfig {
+            send_amount,
+            maker_count,
+            tx_count,
+            required_confirms: 1,
+            fee_rate,
+            min_funding_tx_fee_rate: min_funding_tx_fee_rate(),
+            account: None,
+        };
+
+        let (progress_tx, progress_rx) = channel::<TakerEvent>();
+        let mut last_event = None;
+        let taker_result = thread::scope(|scope| {
+            //start_taker makes its own tokio runtime, the callback stays on the caller's thread
+            let taker_thread = scope
+                .spawn(|| start_taker(&wallet.rpc, &mut wallet.wallet, config, Some(progress_tx)));
+            //ends when the taker finishes and drops progress_tx
+            for event in progress_rx {
+                if let Some(callback) = callback {
+                    let event_json = CString::new(serde_json::to_string(&event).unwrap()).unwrap();
+                    callback(event_json.as_ptr(), user_data);
+                }
+                last_event = Some(event);
+            }
+            taker_thread.join()
+        });
+        match (taker_result, last_event) {
+            (Ok(()), Some(TakerEvent::CoinswapComplete)) => Ok(()),
+            (Err(_), _) => Err(FfiError::new(TELEPORT_ERR_PANIC, "panic during coinswap")),
+            _ => Err(FfiError::new(
+                TELEPORT_ERR_COINSWAP_FAILED,
+                "coinswap did not complete, see the log for why",
+            )),
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    unsafe fn last_error() -> String {
+        CStr::from_ptr(teleport_last_error())
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_ffi_errors() {
+        unsafe {
+            assert_eq!(
+                teleport_wallet_load(ptr::null(), ptr::null_mut()),
+                TELEPORT_ERR_INVALID_ARGUMENT,
+            );
+            assert_eq!(last_error(), "wallet_file is null");
+
+            let mut offers_json: *mut c_char = ptr::null_mut();
+            //the settings are never initialized in unit tests
+            assert_eq!(
+                teleport_sync_offerbook(&mut offers_json),
+                TELEPORT_ERR_NOT_INITIALIZED
+            );
+            assert!(offers_json.is_null());
+            assert_eq!(last_error(), "call teleport_init first");
+
+            teleport_wallet_free(ptr::null_mut());
+            teleport_string_free(ptr::null_mut());
+        }
+        assert_eq!(ffi_call(|| panic!("oops")), TELEPORT_ERR_PANIC);
+        assert_eq!(
+            ffi_call(|| Err(Error::Protocol("bad message").into())),
+            TELEPORT_ERR_PROTOCOL
+        );
+    }
+}
@@ -1,14 +1,14 @@
 // To (strongly) disincentivize Sybil behaviour, the value assessment of the bond
 // is based on the (time value of the bond)^x where x is the bond_value_exponent here,
 // where x > 1.
-const BOND_VALUE_EXPONENT: f64 = 1.3;
+pub const BOND_VALUE_EXPONENT: f64 = 1.3;
 
 // Interest rate used when calculating the value of fidelity bonds created
 // by locking bitcoins in timelocked addresses
 // See also:
 // https://gist.github.com/chris-belcher/87ebbcbb639686057a389acb9ab3e25b#determining-interest-rate-r
 // Set as a real number, i.e. 1 = 100% and 0.01 = 1%
-const BOND_VALUE_INTEREST_RATE: f64 = 0.015;
+pub const BOND_VALUE_INTEREST_RATE: f64 = 0.015;
 
 use std::collections::HashMap;
 use std::fmt::Display;
@@ -16,6 +16,7 @@ use std::num::ParseIntError;
 use std::str::FromStr;
 
 use chrono::NaiveDate;
+use rand::Rng;
 
 use bitcoin::blockdata::opcodes;
 use bitcoin::blockdata::script::{Builder, Instruction, Script};
@@ -27,11 +28,12 @@ use bitcoin::util::key::{PrivateKey, PublicKey};
 use bitcoin::{Address, OutPoint};
 
 use bitcoincore_rpc::json::{GetTxOutResult, ListUnspentResultEntry};
-use bitcoincore_rpc::{Client, RpcApi};
+use bitcoincore_rpc::RpcApi;
 
 use crate::contracts::redeemscript_to_scriptpubkey;
 use crate::error::Error;
 use crate::messages::FidelityBondProof;
+use crate::rpc::Client;
 use crate::wallet_sync::{generate_keypair, UTXOSpendInfo, Wallet};
 
 pub const TIMELOCKED_MPK_PATH: &str = "m/84'/0'/0'/2";
@@ -138,6 +140,10 @@ impl HotWalletFidelityBond {
         }
     }
 
+    pub fn locktime(&self) -> i64 {
+        self.locktime
+    }
+
     pub fn create_proof(
         &self,
         rpc: &Client,
@@ -152,35 +158,52 @@ impl HotWalletFidelityBond {
             ((blocks + BLOCK_COUNT_SAFETY) / RETARGET_INTERVAL) + CERT_MAX_VALIDITY_TIME;
         let cert_expiry = cert_expiry as u16;
 
-        let (cert_pubkey, cert_privkey) = generate_keypair();
-        let secp = Secp256k1::new();
-
-        let cert_msg_hash = create_cert_msg_hash(&cert_pubkey, cert_expiry);
-        let cert_sig = secp.sign(&cert_msg_hash, &self.utxo_privkey);
+        Ok(sign_fidelity_bond_proof(
+            self.utxo,
+            self.utxo_key,
+            &self.utxo_privkey,
+            self.locktime,
+            cert_expiry,
+            onion_hostname,
+        ))
+    }
+}
 
-        let onion_msg_hash =
-            Message::from_slice(&sha256d::Hash::hash(onion_hostname.as_bytes())).unwrap();
-        let onion_sig = secp.sign(&onion_msg_hash, &cert_privkey);
+/// Proof that the owner of the bond at utxo locked until locktime controls onion_hostname, signed
+/// with a new certificate key which the bond's key signs until cert_expiry
+pub fn sign_fidelity_bond_proof(
+    utxo: OutPoint,
+    utxo_key: PublicKey,
+    utxo_privkey: &SecretKey,
+    locktime: i64,
+    cert_expiry: u16,
+    onion_hostname: &str,
+) -> FidelityBondProof {
+    let (cert_pubkey, cert_privkey) = generate_keypair();
+    let secp = Secp256k1::new();
 
-        Ok(FidelityBondProof {
-            utxo: self.utxo,
-            utxo_key: self.utxo_key,
-            locktime: self.locktime,
-            cert_sig,
-            cert_expiry,
-            cert_pubkey,
-            onion_sig,
-        })
+    let cert_msg_hash = create_cert_msg_hash(&cert_pubkey, cert_expiry);
+    let cert_sig = secp.sign(&cert_msg_hash, utxo_privkey);
+
+    let onion_msg_hash =
+        Message::from_slice(&sha256d::Hash::hash(onion_hostname.as_bytes())).unwrap();
+    let onion_sig = secp.sign(&onion_msg_hash, &cert_privkey);
+
+    FidelityBondProof {
+        utxo,
+        utxo_key,
+        locktime,
+        cert_sig,
+        cert_expiry,
+        cert_pubkey,
+        onion_sig,
     }
 }
 
 impl FidelityBondProof {
-    pub fn verify_and_get_txo(
-        &self,
-        rpc: &Client,
-        block_count: u64,
-        onion_hostname: &str,
-    ) -> Result<GetTxOutResult, Error> {
+    /// Check that the bond's key signed the onion hostname, without checking the bond
+    /// itself exists so no node is needed
+    pub fn verify_signatures(&self, onion_hostname: &str) -> Result<(), Error> {
         let secp = Secp256k1::new();
 
         let onion_msg_hash =
@@ -191,6 +214,16 @@ impl FidelityBondProof {
         let cert_msg_hash = create_cert_msg_hash(&self.cert_pubkey, self.cert_expiry);
         secp.verify(&cert_msg_hash, &self.cert_sig, &self.utxo_key.key)
             .map_err(|_| Error::Protocol("cert sig does not verify"))?;
+        Ok(())
+    }
+
+    pub fn verify_and_get_txo(
+        &self,
+        rpc: &Client,
+        block_count: u64,
+        onion_hostname: &str,
+    ) -> Result<GetTxOutResult, Error> {
+        self.verify_signatures(onion_hostname)?;
 
         let txo_data = rpc
             .get_tx_out(&self.utxo.txid, self.utxo.vout, None)?
@@ -224,21 +257,40 @@ impl FidelityBondProof {
         mediantime: u64,
     ) -> Result<f64, Error> {
         let blockhash = rpc.get_block_hash(block_count - txo_data.confirmations as u64 + 1)?;
-        Ok(calculate_timelocked_fidelity_bond_value(
+        Ok(fidelity_bond_value(
             txo_data.value.as_sat(),
             self.locktime,
             rpc.get_block_header_info(&blockhash)?.time as i64,
             mediantime,
+            BOND_VALUE_EXPONENT,
         ))
     }
+
+    /// Value of the bond, after checking it signs onion_hostname and exists
+    pub fn verify_and_get_value(&self, rpc: &Client, onion_hostname: &str) -> Result<f64, Error> {
+        let blockchain_info = rpc.get_blockchain_info()?;
+        let txo_data = self.verify_and_get_txo(rpc, blockchain_info.blocks, onion_hostname)?;
+        self.calculate_fidelity_bond_value(
+            rpc,
+            blockchain_info.blocks,
+            &txo_data,
+            blockchain_info.median_time,
+        )
+    }
 }
 
+/// Value of a fidelity bond of value_sats locked until locktime and confirmed at
+/// confirmation_time, as of current_time, all unix times. It is the time value of the locked
+/// coins raised to exponent, which is BOND_VALUE_EXPONENT everywhere except to compare with it.
+/// The time value stays the same until locktime and then decays, and it is never more than
+/// the value of coins burned forever
 #[allow(non_snake_case)]
-fn calculate_timelocked_fidelity_bond_value(
+pub fn fidelity_bond_value(
     value_sats: u64,
     locktime: i64,
     confirmation_time: i64,
     current_time: u64,
+    exponent: f64,
 ) -> f64 {
     const YEAR: f64 = 60.0 * 60.0 * 24.0 * 365.2425; //gregorian calender year length
 
@@ -252,7 +304,30 @@ fn calculate_timelocked_fidelity_bond_value(
 
     let timevalue = f64::max(0.0, f64::min(1.0, exp_rT_m1) - f64::min(1.0, exp_rtL_m1));
 
-    (value_sats as f64 * timevalue).powf(BOND_VALUE_EXPONENT)
+    (value_sats as f64 * timevalue).powf(exponent)
+}
+
+/// Random order of the makers with bond_values, in which the makers with more valuable bonds are
+/// more likely to come later. Makers are tried from the back so a maker is picked with a
+/// probability proportional to its bond value, and makers without a bond (value 0) only after
+/// all makers with one
+pub fn weighted_bond_order<R: Rng>(bond_values: &[f64], rng: &mut R) -> Vec<usize> {
+    //weighted sampling without replacement by sorting on u^(1/value) with u uniform in (0,1],
+    // compared as ln(u)/value which has the same order and doesnt round to 1 for large values
+    let keys = bond_values
+        .iter()
+        .map(|&value| {
+            if value > 0.0 {
+                (1.0 - rng.gen::<f64>()).ln() / value
+            } else {
+                f64::NEG_INFINITY
+            }
+        })
+        .collect::<Vec<f64>>();
+    let mut order = (0..bond_values.len()).collect::<Vec<usize>>();
+    //partial_cmp fails if NaN value involved, which wont happen, so unwrap() is acceptable
+    order.sort_by(|&a, &b| keys[a].partial_cmp(&keys[b]).unwrap());
+    order
 }
 
 fn calculate_timelocked_fidelity_bond_value_from_utxo(
@@ -260,7 +335,7 @@ fn calculate_timelocked_fidelity_bond_value_from_utxo(
     usi: &UTXOSpendInfo,
     rpc: &Client,
 ) -> Result<f64, Error> {
-    Ok(calculate_timelocked_fidelity_bond_value(
+    Ok(fidelity_bond_value(
         utxo.amount.as_sat(),
         get_locktime_from_index(
             if let UTXOSpendInfo::FidelityBondCoin {
@@ -278,10 +353,11 @@ fn calculate_timelocked_fidelity_bond_value_from_utxo(
             .blocktime
             .unwrap() as i64,
         rpc.get_blockchain_info()?.median_time,
+        BOND_VALUE_EXPONENT,
     ))
 }
 
-fn create_timelocked_redeemscript(locktime: i64, pubkey: &PublicKey) -> Script {
+pub fn create_timelocked_redeemscript(locktime: i64, pubkey: &PublicKey) -> Script {
     Builder::new()
         .push_int(locktime)
         .push_opcode(opcodes::all::OP_CLTV)
@@ -388,6 +464,16 @@ impl Wallet {
     pub fn find_most_valuable_fidelity_bond(
         &self,
         rpc: &Client,
+    ) -> Result<Option<HotWalletFidelityBond>, Error> {
+        self.find_fidelity_bond_by_rank(rpc, 0)
+    }
+
+    /// The confirmed fidelity bond which is rank-th most valuable, counting from 0, so each
+    /// maker instance run from the wallet can show its own bond
+    pub fn find_fidelity_bond_by_rank(
+        &self,
+        rpc: &Client,
+        rank: usize,
     ) -> Result<Option<HotWalletFidelityBond>, Error> {
         let list_unspent_result = self.list_unspent_from_wallet(rpc, false, true)?;
         let fidelity_bond_utxos = list_unspent_result
@@ -407,18 +493,15 @@ impl Wallet {
             .iter()
             .map(|(utxo, usi)| calculate_timelocked_fidelity_bond_value_from_utxo(utxo, usi, rpc))
             .collect::<Result<Vec<f64>, Error>>()?;
-        Ok(fidelity_bond_utxos
+        let mut bonds_and_values = fidelity_bond_utxos
             .iter()
             .zip(fidelity_bond_values.iter())
-            //partial_cmp fails if NaN value involved, which wont happen, so unwrap() is acceptable
-            .max_by(|(_, x), (_, y)| x.partial_cmp(y).unwrap())
-            .map(|most_valuable_fidelity_bond| {
-                HotWalletFidelityBond::new(
-                    self,
-                    &most_valuable_fidelity_bond.0 .0,
-                    &most_valuable_fidelity_bond.0 .1,
-                )
-            }))
+            .collect::<Vec<_>>();
+        //partial_cmp fails if NaN value involved, which wont happen, so unwrap() is acceptable
+        bonds_and_values.sort_by(|(_, x), (_, y)| y.partial_cmp(x).unwrap());
+        Ok(bonds_and_values.get(rank).map(|(fidelity_bond, _)| {
+            HotWalletFidelityBond::new(self, &fidelity_bond.0, &fidelity_bond.1)
+        }))
     }
 }
 
@@ -434,11 +517,12 @@ mod test {
         //the function should be flat anywhere before the locktime ends
         let values = (0..4)
             .map(|y| {
-                calculate_timelocked_fidelity_bond_value(
+                fidelity_bond_value(
                     100000000,
                     (6.0 * YEAR) as i64,
                     0,
                     y * YEAR as u64,
+                    BOND_VALUE_EXPONENT,
                 )
             })
             .collect::<Vec<f64>>();
@@ -452,11 +536,12 @@ mod test {
         //after locktime, the value should go down
         let values = (0..5)
             .map(|y| {
-                calculate_timelocked_fidelity_bond_value(
+                fidelity_bond_value(
                     100000000,
                     (6.0 * YEAR) as i64,
                     0,
                     (6 + y) * YEAR as u64,
+                    BOND_VALUE_EXPONENT,
                 )
             })
             .collect::<Vec<f64>>();
@@ -470,7 +555,13 @@ mod test {
         //value of a bond goes up as the locktime goes up
         let values = (0..5)
             .map(|y| {
-                calculate_timelocked_fidelity_bond_value(100000000, (y as f64 * YEAR) as i64, 0, 0)
+                fidelity_bond_value(
+                    100000000,
+                    (y as f64 * YEAR) as i64,
+                    0,
+                    0,
+                    BOND_VALUE_EXPONENT,
+                )
             })
             .collect::<Vec<f64>>();
         let value_ratio = (0..values.len() - 1)
@@ -486,11 +577,12 @@ mod test {
         //value of a bond locked into the far future is constant, clamped at the value of burned coins
         let values = (0..5)
             .map(|y| {
-                calculate_timelocked_fidelity_bond_value(
+                fidelity_bond_value(
                     100000000,
                     ((200 + y) as f64 * YEAR) as i64,
                     0,
                     0,
+                    BOND_VALUE_EXPONENT,
                 )
             })
             .collect::<Vec<f64>>();
@@ -501,4 +593,48 @@ mod test {
             assert!(v.abs() < EPSILON);
         }
     }
+
+    #[test]
+    fn test_fidelity_bond_value() {
+        const YEAR: f64 = 60.0 * 60.0 * 24.0 * 365.2425;
+        let locktime = YEAR as i64;
+
+        //with exponent 1 the value is the amount times the time value of locking it for a year
+        let value = fidelity_bond_value(100_000_000, locktime, 0, 0, 1.0);
+        let expected = 100_000_000.0 * f64::exp_m1(BOND_VALUE_INTEREST_RATE);
+        assert!((value - expected).abs() < 1.0);
+        assert!(
+            (fidelity_bond_value(100_000_000, locktime, 0, 0, BOND_VALUE_EXPONENT)
+                - expected.powf(BOND_VALUE_EXPONENT))
+            .abs()
+                < 1.0
+        );
+
+        //twice the coins are worth more than twice as much
+        let double = fidelity_bond_value(200_000_000, locktime, 0, 0, BOND_VALUE_EXPONENT);
+        let single = fidelity_bond_value(100_000_000, locktime, 0, 0, BOND_VALUE_EXPONENT);
+        assert!(double > 2.0 * single);
+
+        //a bond confirmed after its locktime is worth nothing
+        assert_eq!(
+            fidelity_bond_value(100_000_000, locktime, locktime + 1, 0, BOND_VALUE_EXPONENT),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_weighted_bond_order() {
+        let mut rng = rand::thread_rng();
+        assert!(weighted_bond_order(&[], &mut rng).is_empty());
+        //makers without a bond are tried last, so they are at the front
+        let order = weighted_bond_order(&[0.0, 5.0, 0.0, 1.0], &mut rng);
+        assert_eq!(order.len(), 4);
+        assert!(order[..2].contains(&0) && order[..2].contains(&2));
+
+        let last_counts = (0..1000).fold([0; 2], |mut counts, _| {
+            counts[*weighted_bond_order(&[1.0, 9.0], &mut rng).last().unwrap()] += 1;
+            counts
+        });
+        assert!(last_counts[1] > last_counts[0] * 3);
+    }
 }
@@ -1,21 +1,49 @@
 //this file contains routines for creating funding transactions
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
 
 use itertools::izip;
 
 use bitcoin::{hashes::hex::FromHex, Address, Amount, OutPoint, Transaction, Txid};
 
-use bitcoincore_rpc::json::{CreateRawTransactionInput, WalletCreateFundedPsbtOptions};
-use bitcoincore_rpc::{Client, RpcApi};
+use bitcoincore_rpc::json::{
+    CreateRawTransactionInput, ListUnspentResultEntry, WalletCreateFundedPsbtOptions,
+};
+use bitcoincore_rpc::RpcApi;
 
 use serde_json::Value;
 
 use rand::rngs::OsRng;
 use rand::RngCore;
 
+use crate::coin_viability::{coin_sizes, is_economic, spend_cost_sat, tx_fee};
 use crate::error::Error;
-use crate::wallet_sync::{convert_json_rpc_bitcoin_to_satoshis, Wallet};
+use crate::rpc::Client;
+use crate::wallet_accounts::{
+    account_balances, choose_funding_account, utxo_account, WalletAccount,
+};
+use crate::wallet_sync::{convert_json_rpc_bitcoin_to_satoshis, UTXOSpendInfo, Wallet};
+
+//coins spent by funding txes which arent broadcast yet, with when they were reserved, so that
+// other coinswaps from the same wallet, for example those of another maker instance, dont
+// spend them too. Kept until the coins are spent, or expired in case the coinswap was given up
+static RESERVED_UTXOS: Mutex<BTreeMap<OutPoint, i64>> = Mutex::new(BTreeMap::new());
+pub const UTXO_RESERVATION_EXPIRY_SECS: i64 = 60 * 60;
+
+pub fn reserve_utxos(outpoints: &[OutPoint], now: i64) {
+    let mut reserved = RESERVED_UTXOS.lock().unwrap();
+    for outpoint in outpoints {
+        reserved.insert(*outpoint, now);
+    }
+}
+
+/// The coins reserved by funding txes created in the last UTXO_RESERVATION_EXPIRY_SECS
+pub fn reserved_utxos(now: i64) -> Vec<OutPoint> {
+    let mut reserved = RESERVED_UTXOS.lock().unwrap();
+    reserved.retain(|_, reserved_at| now - *reserved_at < UTXO_RESERVATION_EXPIRY_SECS);
+    reserved.keys().cloned().collect()
+}
 
 pub struct CreateFundingTxesResult {
     pub funding_txes: Vec<Transaction>,
@@ -30,23 +58,128 @@ impl Wallet {
         coinswap_amount: u64,
         destinations: &[Address],
         fee_rate: u64,
+        change_avoidance_tolerance_ppb: u64,
+        account: Option<WalletAccount>,
     ) -> Result<Option<CreateFundingTxesResult>, Error> {
         //returns Ok(None) if there was no error but the wallet was unable to create funding txes
+        let account = match account {
+            Some(account) => account,
+            None => choose_funding_account(
+                &account_balances(
+                    self.list_unreserved_unspent_from_wallet(rpc)?
+                        .iter()
+                        .map(|(u, spend_info)| (spend_info, u.amount.as_sat())),
+                ),
+                coinswap_amount,
+            )?,
+        };
+        tracing::debug!(target: "wallet", "funding from the {} account", account);
+        let ret = self.create_funding_txes_from_unreserved_coins(
+            rpc,
+            coinswap_amount,
+            destinations,
+            fee_rate,
+            change_avoidance_tolerance_ppb,
+            account,
+        );
+        if let Ok(Some(result)) = &ret {
+            reserve_utxos(
+                &result
+                    .funding_txes
+                    .iter()
+                    .flat_map(|tx| tx.input.iter().map(|vin| vin.previous_output))
+                    .collect::<Vec<OutPoint>>(),
+                chrono::Utc::now().timestamp(),
+            );
+        }
+        ret
+    }
 
-        log::debug!(target: "wallet", "coinswap_amount = {} destinations = {:?}",
+    /// The wallet's coins not reserved by a funding tx of another coinswap, leaving out
+    /// live contracts and fidelity bonds
+    pub fn list_unreserved_unspent_from_wallet(
+        &self,
+        rpc: &Client,
+    ) -> Result<Vec<(ListUnspentResultEntry, UTXOSpendInfo)>, Error> {
+        let reserved = reserved_utxos(chrono::Utc::now().timestamp());
+        Ok(self
+            .list_unspent_from_wallet(rpc, false, false)?
+            .into_iter()
+            .filter(|(u, _)| {
+                !reserved.contains(&OutPoint {
+                    txid: u.txid,
+                    vout: u.vout,
+                })
+            })
+            .collect())
+    }
+
+    fn list_unreserved_unspent_from_account(
+        &self,
+        rpc: &Client,
+        account: WalletAccount,
+    ) -> Result<Vec<(ListUnspentResultEntry, UTXOSpendInfo)>, Error> {
+        Ok(self
+            .list_unreserved_unspent_from_wallet(rpc)?
+            .into_iter()
+            .filter(|(_, spend_info)| utxo_account(spend_info) == Some(account))
+            .collect())
+    }
+
+    fn create_funding_txes_from_unreserved_coins(
+        &self,
+        rpc: &Client,
+        coinswap_amount: u64,
+        destinations: &[Address],
+        fee_rate: u64,
+        change_avoidance_tolerance_ppb: u64,
+        account: WalletAccount,
+    ) -> Result<Option<CreateFundingTxesResult>, Error> {
+        tracing::debug!(target: "wallet", "coinswap_amount = {} destinations = {:?}",
             coinswap_amount, destinations);
 
-        let ret =
-            self.create_funding_txes_random_amounts(rpc, coinswap_amount, destinations, fee_rate);
+        if change_avoidance_tolerance_ppb > 0 {
+            let ret = self.create_funding_txes_without_change(
+                rpc,
+                coinswap_amount,
+                destinations,
+                fee_rate,
+                change_avoidance_tolerance_ppb,
+                account,
+            );
+            match ret {
+                Ok(Some(_)) => {
+                    tracing::debug!(target: "wallet", "created funding txes without change");
+                    return ret;
+                }
+                Ok(None) => (),
+                Err(e) => {
+                    tracing::debug!(target: "wallet", "unable to avoid change: {:?}", e);
+                }
+            }
+        }
+
+        let ret = self.create_funding_txes_random_amounts(
+            rpc,
+            coinswap_amount,
+            destinations,
+            fee_rate,
+            account,
+        );
         if ret.is_ok() {
-            log::debug!(target: "wallet", "created funding txes with random amounts");
+            tracing::debug!(target: "wallet", "created funding txes with random amounts");
             return ret;
         }
 
-        let ret =
-            self.create_funding_txes_utxo_max_sends(rpc, coinswap_amount, destinations, fee_rate);
+        let ret = self.create_funding_txes_utxo_max_sends(
+            rpc,
+            coinswap_amount,
+            destinations,
+            fee_rate,
+            account,
+        );
         if ret.is_ok() {
-            log::debug!(target: "wallet", "created funding txes with fully-spending utxos");
+            tracing::debug!(target: "wallet", "created funding txes with fully-spending utxos");
             return ret;
         }
 
@@ -55,24 +188,26 @@ impl Wallet {
             coinswap_amount,
             destinations,
             fee_rate,
+            account,
         );
         if ret.is_ok() {
-            log::debug!(target: "wallet", "created funding txes with using the biggest utxos");
+            tracing::debug!(target: "wallet", "created funding txes with using the biggest utxos");
             return ret;
         }
 
-        log::debug!(target: "wallet", "failed to create funding txes with any method");
+        tracing::debug!(target: "wallet", "failed to create funding txes with any method");
         ret
     }
 
-    fn generate_amount_fractions_without_correction(
+    fn generate_amount_fractions_without_correction<R: RngCore>(
         count: usize,
         total_amount: u64,
         lower_limit: u64,
+        rng: &mut R,
     ) -> Result<Vec<f32>, Error> {
         for _ in 0..100000 {
             let mut knives = (1..count)
-                .map(|_| OsRng.next_u32() as f32 / u32::MAX as f32)
+                .map(|_| rng.next_u32() as f32 / u32::MAX as f32)
                 .collect::<Vec<f32>>();
             knives.sort_by(|a, b| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
 
@@ -96,12 +231,19 @@ impl Wallet {
         ))
     }
 
-    fn generate_amount_fractions(count: usize, total_amount: u64) -> Result<Vec<u64>, Error> {
+    //tests can pass a seeded rng to get the same amounts on every run
+    fn generate_amount_fractions<R: RngCore>(
+        count: usize,
+        total_amount: u64,
+        rng: &mut R,
+    ) -> Result<Vec<u64>, Error> {
         let mut output_values = Wallet::generate_amount_fractions_without_correction(
             count,
             total_amount,
-            5000, //use 5000 satoshi as the lower limit for now
-                  //there should always be enough to pay miner fees
+            //use 5000 satoshi as the lower limit for now
+            //there should always be enough to pay miner fees
+            5000,
+            rng,
         )?
         .iter()
         .map(|f| (*f * total_amount as f32) as u64)
@@ -118,28 +260,111 @@ impl Wallet {
         *output_values.first_mut().unwrap() =
             total_amount - output_values.iter().skip(1).sum::<u64>();
         assert_eq!(output_values.iter().sum::<u64>(), total_amount);
-        log::debug!(target: "wallet", "output values = {:?}", output_values);
+        tracing::debug!(target: "wallet", "output values = {:?}", output_values);
 
         Ok(output_values)
     }
 
+    fn create_funding_txes_without_change(
+        &self,
+        rpc: &Client,
+        coinswap_amount: u64,
+        destinations: &[Address],
+        fee_rate: u64,
+        change_avoidance_tolerance_ppb: u64,
+        account: WalletAccount,
+    ) -> Result<Option<CreateFundingTxesResult>, Error> {
+        //this function creates funding txes which each spend whole coins to one destination,
+        //so none of them has a change output, by searching for coins whose value after fees
+        //is within the tolerance above the coinswap amount
+
+        let max_amount = coinswap_amount
+            + (coinswap_amount as u128 * change_avoidance_tolerance_ppb as u128 / 1_000_000_000)
+                as u64;
+        let coins = coin_sizes(
+            &self
+                .list_unreserved_unspent_from_account(rpc, account)?
+                .into_iter()
+                .filter(|(u, _)| u.confirmations > 0)
+                .collect::<Vec<(ListUnspentResultEntry, UTXOSpendInfo)>>(),
+        );
+        let coin_groups = match find_changeless_inputs(
+            &coins,
+            fee_rate,
+            coinswap_amount,
+            max_amount,
+            destinations.len(),
+        ) {
+            Some(g) => g,
+            None => return Ok(None),
+        };
+
+        let mut funding_txes = Vec::<Transaction>::new();
+        let mut total_miner_fee = 0;
+        for (coin_group, destination) in coin_groups.iter().zip(destinations.iter()) {
+            let mut outputs = HashMap::<String, Amount>::new();
+            outputs.insert(
+                destination.to_string(),
+                Amount::from_sat(coin_group.iter().map(|c| coins[*c].1).sum::<u64>()),
+            );
+            let wcfp_result = rpc.wallet_create_funded_psbt(
+                &coin_group
+                    .iter()
+                    .map(|c| CreateRawTransactionInput {
+                        txid: coins[*c].0.txid,
+                        vout: coins[*c].0.vout,
+                        sequence: None,
+                    })
+                    .collect::<Vec<CreateRawTransactionInput>>(),
+                &outputs,
+                None,
+                Some(WalletCreateFundedPsbtOptions {
+                    add_inputs: Some(false),
+                    subtract_fee_from_outputs: vec![0],
+                    fee_rate: Some(Amount::from_sat(fee_rate)),
+                    ..Default::default()
+                }),
+                None,
+            )?;
+            total_miner_fee += wcfp_result.fee.as_sat();
+            tracing::debug!(target: "wallet", "created funding tx, miner fee={}", wcfp_result.fee);
+            funding_txes.push(self.from_walletcreatefundedpsbt_to_tx(rpc, &wcfp_result.psbt)?);
+        }
+
+        //the node's fee can be a little different from the estimate
+        let total_funding_amount = funding_txes
+            .iter()
+            .map(|tx| tx.output[0].value)
+            .sum::<u64>();
+        if total_funding_amount < coinswap_amount || total_funding_amount > max_amount {
+            return Ok(None);
+        }
+        Ok(Some(CreateFundingTxesResult {
+            payment_output_positions: vec![0; funding_txes.len()],
+            funding_txes,
+            total_miner_fee,
+        }))
+    }
+
     fn create_funding_txes_random_amounts(
         &self,
         rpc: &Client,
         coinswap_amount: u64,
         destinations: &[Address],
         fee_rate: u64,
+        account: WalletAccount,
     ) -> Result<Option<CreateFundingTxesResult>, Error> {
         //this function creates funding txes by
         //randomly generating some satoshi amounts and send them into
         //walletcreatefundedpsbt to create txes that create change
 
         let change_addresses = self.get_next_internal_addresses(rpc, destinations.len() as u32)?;
-        log::debug!(target: "wallet", "change addrs = {:?}", change_addresses);
+        tracing::debug!(target: "wallet", "change addrs = {:?}", change_addresses);
 
-        let output_values = Wallet::generate_amount_fractions(destinations.len(), coinswap_amount)?;
+        let output_values =
+            Wallet::generate_amount_fractions(destinations.len(), coinswap_amount, &mut OsRng)?;
 
-        self.lock_all_nonwallet_unspents(rpc)?;
+        self.lock_unspents_not_to_fund_from(rpc, fee_rate, account)?;
 
         let mut funding_txes = Vec::<Transaction>::new();
         let mut payment_output_positions = Vec::<u32>::new();
@@ -149,7 +374,7 @@ impl Wallet {
             output_values.iter(),
             change_addresses.iter()
         ) {
-            log::debug!(target: "wallet", "output_value = {} to addr={}", output_value, address);
+            tracing::debug!(target: "wallet", "output_value = {} to addr={}", output_value, address);
 
             let mut outputs = HashMap::<String, Amount>::new();
             outputs.insert(address.to_string(), Amount::from_sat(output_value));
@@ -167,7 +392,7 @@ impl Wallet {
                 None,
             )?;
             total_miner_fee += wcfp_result.fee.as_sat();
-            log::debug!(target: "wallet", "created funding tx, miner fee={}", wcfp_result.fee);
+            tracing::debug!(target: "wallet", "created funding tx, miner fee={}", wcfp_result.fee);
 
             let funding_tx = self.from_walletcreatefundedpsbt_to_tx(rpc, &wcfp_result.psbt)?;
 
@@ -184,7 +409,7 @@ impl Wallet {
             } else {
                 0
             };
-            log::debug!(target: "wallet", "payment_pos = {}", payment_pos);
+            tracing::debug!(target: "wallet", "payment_pos = {}", payment_pos);
 
             funding_txes.push(funding_tx);
             payment_output_positions.push(payment_pos);
@@ -244,7 +469,7 @@ impl Wallet {
             leftover_coinswap_amount -= funding_tx.output[0].value;
 
             total_miner_fee += wcfp_result.fee.as_sat();
-            log::debug!(target: "wallet", "created funding tx, miner fee={}", wcfp_result.fee);
+            tracing::debug!(target: "wallet", "created funding tx, miner fee={}", wcfp_result.fee);
 
             funding_txes.push(funding_tx);
             payment_output_positions.push(0);
@@ -283,7 +508,7 @@ impl Wallet {
         leftover_coinswap_amount -= funding_tx.output[0].value;
 
         total_miner_fee += wcfp_result.fee.as_sat();
-        log::debug!(target: "wallet", "created funding tx, miner fee={}", wcfp_result.fee);
+        tracing::debug!(target: "wallet", "created funding tx, miner fee={}", wcfp_result.fee);
 
         funding_txes.push(funding_tx);
         payment_output_positions.push(0);
@@ -313,7 +538,7 @@ impl Wallet {
         let funding_tx = self.from_walletcreatefundedpsbt_to_tx(rpc, &wcfp_result.psbt)?;
 
         total_miner_fee += wcfp_result.fee.as_sat();
-        log::debug!(target: "wallet", "created funding tx, miner fee={}", wcfp_result.fee);
+        tracing::debug!(target: "wallet", "created funding tx, miner fee={}", wcfp_result.fee);
 
         funding_txes.push(funding_tx);
         payment_output_positions.push(if wcfp_result.change_position == 0 {
@@ -335,6 +560,7 @@ impl Wallet {
         coinswap_amount: u64,
         destinations: &[Address],
         fee_rate: u64,
+        account: WalletAccount,
     ) -> Result<Option<CreateFundingTxesResult>, Error> {
         //this function creates funding txes by
         //using walletcreatefundedpsbt for the total amount, and if
@@ -348,7 +574,7 @@ impl Wallet {
         );
         let change_address = self.get_next_internal_addresses(rpc, 1)?[0].clone();
 
-        self.lock_all_nonwallet_unspents(rpc)?;
+        self.lock_unspents_not_to_fund_from(rpc, fee_rate, account)?;
         let wcfp_result = rpc.wallet_create_funded_psbt(
             &[],
             &outputs,
@@ -363,10 +589,10 @@ impl Wallet {
         )?;
         //TODO rust-bitcoin handles psbt, use those functions instead
         let decoded_psbt = rpc.call::<Value>("decodepsbt", &[Value::String(wcfp_result.psbt)])?;
-        log::debug!(target: "wallet", "total tx decoded_psbt = {:?}", decoded_psbt);
+        tracing::debug!(target: "wallet", "total tx decoded_psbt = {:?}", decoded_psbt);
 
         let total_tx_inputs_len = decoded_psbt["inputs"].as_array().unwrap().len();
-        log::debug!(target: "wallet", "total tx inputs.len = {}", total_tx_inputs_len);
+        tracing::debug!(target: "wallet", "total tx inputs.len = {}", total_tx_inputs_len);
         if total_tx_inputs_len < destinations.len() {
             return Err(Error::Protocol(
                 "not enough UTXOs found, cant use this method",
@@ -410,11 +636,16 @@ impl Wallet {
         coinswap_amount: u64,
         destinations: &[Address],
         fee_rate: u64,
+        account: WalletAccount,
     ) -> Result<Option<CreateFundingTxesResult>, Error> {
         //this function will pick the top most valuable UTXOs and use them
         //to create funding transactions
 
-        let mut list_unspent_result = self.list_unspent_from_wallet(rpc, false, false)?;
+        let mut list_unspent_result = self
+            .list_unreserved_unspent_from_account(rpc, account)?
+            .into_iter()
+            .filter(|(u, spend_info)| is_economic(u.amount.as_sat(), spend_info, fee_rate))
+            .collect::<Vec<(ListUnspentResultEntry, UTXOSpendInfo)>>();
         if list_unspent_result.len() < destinations.len() {
             return Err(Error::Protocol(
                 "Not enough UTXOs to create this many funding txes",
@@ -444,7 +675,7 @@ impl Wallet {
         }
 
         let inputs = &list_unspent_result[..list_unspent_count.unwrap()];
-        log::debug!(target: "wallet", "inputs sizes = {:?}",
+        tracing::debug!(target: "wallet", "inputs sizes = {:?}",
             inputs.iter().map(|(l, _)| l.amount.as_sat()).collect::<Vec<u64>>());
 
         if inputs[1..]
@@ -455,7 +686,7 @@ impl Wallet {
             //at least two utxos bigger than the coinswap amount
 
             //not implemented yet!
-            log::debug!(target: "wallet",
+            tracing::debug!(target: "wallet",
                 concat!("failed to create funding txes with the biggest-utxos method, this ",
                     "branch not implemented"));
             Ok(None)
@@ -480,3 +711,212 @@ impl Wallet {
         }
     }
 }
+
+/// A random amount within randomization_ppb parts per billion of amount, so coinswaps of the
+/// same requested amount dont all send the same round amount. The funding txes then split
+/// this amount between them
+pub fn randomize_amount<R: RngCore>(amount: u64, randomization_ppb: u64, rng: &mut R) -> u64 {
+    let max_offset = (amount as u128 * randomization_ppb as u128 / 1_000_000_000) as u64;
+    if max_offset == 0 {
+        return amount;
+    }
+    amount - max_offset + rng.next_u64() % (2 * max_offset + 1)
+}
+
+//a changeless search gives up after trying this many combinations of coins
+const MAX_CHANGELESS_SEARCH_TRIES: usize = 100_000;
+
+fn search_changeless_inputs(
+    effective_values: &[(usize, u64)],
+    remaining_values: &[u64],
+    (min_value, max_value, min_count): (u64, u64, usize),
+    position: usize,
+    value: u64,
+    selected: &mut Vec<usize>,
+    tries: &mut usize,
+) -> bool {
+    *tries += 1;
+    if *tries > MAX_CHANGELESS_SEARCH_TRIES || value > max_value {
+        return false;
+    }
+    if value >= min_value && selected.len() >= min_count {
+        return true;
+    }
+    if position == effective_values.len() || value + remaining_values[position] < min_value {
+        return false;
+    }
+    let (coin, effective_value) = effective_values[position];
+    selected.push(coin);
+    if search_changeless_inputs(
+        effective_values,
+        remaining_values,
+        (min_value, max_value, min_count),
+        position + 1,
+        value + effective_value,
+        selected,
+        tries,
+    ) {
+        return true;
+    }
+    selected.pop();
+    search_changeless_inputs(
+        effective_values,
+        remaining_values,
+        (min_value, max_value, min_count),
+        position + 1,
+        value,
+        selected,
+        tries,
+    )
+}
+
+/// Find coins, given as (outpoint, value, input vbytes), to spend whole in tx_count txes
+/// of one output each, so that the outputs add up to between min_amount and max_amount
+/// after miner fees. Returns the indexes of the coins of each tx
+pub fn find_changeless_inputs(
+    coins: &[(OutPoint, u64, u64)],
+    fee_rate: u64,
+    min_amount: u64,
+    max_amount: u64,
+    tx_count: usize,
+) -> Option<Vec<Vec<usize>>> {
+    if tx_count == 0 {
+        return None;
+    }
+    //what each coin adds to the outputs after paying for its input
+    let mut effective_values = coins
+        .iter()
+        .enumerate()
+        .map(|(i, (_, value, vbytes))| (i, value.saturating_sub(spend_cost_sat(*vbytes, fee_rate))))
+        .filter(|(_, effective_value)| *effective_value > 0)
+        .collect::<Vec<(usize, u64)>>();
+    effective_values.sort_by_key(|(_, effective_value)| std::cmp::Reverse(*effective_value));
+    let mut remaining_values = effective_values
+        .iter()
+        .rev()
+        .scan(0, |sum, (_, effective_value)| {
+            *sum += effective_value;
+            Some(*sum)
+        })
+        .collect::<Vec<u64>>();
+    remaining_values.reverse();
+
+    //the rest of each tx is paid for out of the outputs
+    let tx_overhead_fee = tx_count as u64 * tx_fee(0, 1, fee_rate);
+    let mut selected = Vec::<usize>::new();
+    if !search_changeless_inputs(
+        &effective_values,
+        &remaining_values,
+        (
+            min_amount + tx_overhead_fee,
+            max_amount + tx_overhead_fee,
+            tx_count,
+        ),
+        0,
+        0,
+        &mut selected,
+        &mut 0,
+    ) {
+        return None;
+    }
+
+    //biggest coins first, each to the tx with the least so far
+    let mut groups = vec![(0u64, Vec::<usize>::new()); tx_count];
+    for coin in selected {
+        let group = groups.iter_mut().min_by_key(|(total, _)| *total).unwrap();
+        group.0 += coins[coin].1;
+        group.1.push(coin);
+    }
+    Some(groups.into_iter().map(|(_, coins)| coins).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_generate_amount_fractions() {
+        let amounts = |seed| {
+            Wallet::generate_amount_fractions(3, 500_000, &mut StdRng::seed_from_u64(seed)).unwrap()
+        };
+        let output_values = amounts(1);
+        assert_eq!(output_values.len(), 3);
+        assert_eq!(output_values.iter().sum::<u64>(), 500_000);
+        assert!(output_values.iter().all(|v| *v > 5000));
+        assert_eq!(output_values, amounts(1));
+        assert_ne!(output_values, amounts(2));
+
+        assert!(Wallet::generate_amount_fractions(3, 10_000, &mut OsRng).is_err());
+    }
+
+    #[test]
+    fn test_randomize_amount() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let amounts = (0..100)
+            .map(|_| randomize_amount(500_000, 10_000_000, &mut rng))
+            .collect::<Vec<u64>>();
+        assert!(amounts.iter().all(|a| (495_000..=505_000).contains(a)));
+        assert!(amounts.iter().any(|a| *a != amounts[0]));
+        assert_eq!(randomize_amount(500_000, 0, &mut rng), 500_000);
+        assert_eq!(randomize_amount(50, 10_000_000, &mut rng), 50);
+    }
+
+    #[test]
+    fn test_reserved_utxos_expire() {
+        let outpoint = OutPoint {
+            txid: Txid::from_hex(
+                "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456",
+            )
+            .unwrap(),
+            vout: 1,
+        };
+        reserve_utxos(&[outpoint], 1_000);
+        assert!(reserved_utxos(1_000 + UTXO_RESERVATION_EXPIRY_SECS - 1).contains(&outpoint));
+        assert!(!reserved_utxos(1_000 + UTXO_RESERVATION_EXPIRY_SECS).contains(&outpoint));
+    }
+
+    #[test]
+    fn test_find_changeless_inputs() {
+        use bitcoin::hashes::Hash;
+
+        let coins = [100_000u64, 300_000, 250_000, 52_000, 1_000, 100]
+            .iter()
+            .enumerate()
+            .map(|(vout, value)| {
+                (
+                    OutPoint {
+                        txid: Txid::from_slice(&[1; 32]).unwrap(),
+                        vout: vout as u32,
+                    },
+                    *value,
+                    68,
+                )
+            })
+            .collect::<Vec<(OutPoint, u64, u64)>>();
+        let value_of = |groups: &Vec<Vec<usize>>| {
+            groups
+                .iter()
+                .map(|g| g.iter().map(|c| coins[*c].1).sum::<u64>())
+                .collect::<Vec<u64>>()
+        };
+
+        //at 1 sat/vb, 300000 + 52000 less the fees of two inputs and one output is 351822
+        let groups = find_changeless_inputs(&coins, 1_000, 351_800, 351_850, 1).unwrap();
+        assert_eq!(value_of(&groups), vec![352_000]);
+
+        //one group per tx, the biggest coin to the tx with the least so far
+        let groups = find_changeless_inputs(&coins, 1_000, 650_000, 652_000, 3).unwrap();
+        assert_eq!(value_of(&groups), vec![300_000, 250_000, 101_000]);
+
+        //not possible within the tolerance, and the 100 sat coin is uneconomic at 2 sat/vb
+        assert_eq!(find_changeless_inputs(&coins, 1_000, 2_000, 2_010, 1), None);
+        assert_eq!(find_changeless_inputs(&coins, 2_000, 10, 100, 1), None);
+        assert_eq!(
+            find_changeless_inputs(&coins, 1_000, 1_000, 1_000_000, 7),
+            None
+        );
+    }
+}
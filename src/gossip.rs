@@ -0,0 +1,216 @@
+//makers tell each other and the takers which connect to them about the other makers they
+// know of, so makers can still be found when a directory server is down or leaves some out
+//every address comes with the fidelity bond proof signing it. makers check that the bond
+// exists before passing an address on and keep one address per bond like the directory
+// server, takers only check the signatures
+//to limit the work a peer can cause each connection can only ask once, at most
+// MAX_GOSSIP_ADDRESSES are sent and only that many of a reply are looked at
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rand::seq::IteratorRandom;
+
+use tokio::net::TcpStream;
+use tokio::select;
+use tokio::time::sleep;
+
+use bitcoin::OutPoint;
+
+use crate::error::Error;
+use crate::i2p::is_i2p_address;
+use crate::messages::{
+    GiveMakerAddresses, MakerAddresses, SignedMakerAddress, TakerToMakerMessage,
+};
+use crate::offerbook_sync::MakerAddress;
+use crate::protocol_phase::expect_message;
+use crate::taker_protocol::{handshake_maker, read_message, send_message};
+
+pub const MAX_GOSSIP_ADDRESSES: usize = 50;
+//how many makers are asked for the makers they know each time
+pub const GOSSIP_PEER_COUNT: usize = 3;
+const GOSSIP_REQUEST_TIMEOUT_SECS: u64 = 30;
+//makers which arent heard of again for this long are dropped
+const GOSSIP_ENTRY_EXPIRY_SECS: i64 = 60 * 60 * 24 * 2;
+
+struct GossipEntry {
+    signed_address: SignedMakerAddress,
+    last_seen: i64,
+}
+
+//the makers a maker knows of and tells its peers about
+#[derive(Default)]
+pub struct GossipBook {
+    entries: HashMap<OutPoint, GossipEntry>,
+}
+
+impl GossipBook {
+    /// Add an address whose fidelity bond has been checked, replacing any other address
+    /// of the same bond
+    pub fn add(&mut self, signed_address: SignedMakerAddress, now: i64) {
+        let bond_utxo = signed_address.fidelity_bond_proof.utxo;
+        self.entries.retain(|utxo, entry| {
+            entry.last_seen + GOSSIP_ENTRY_EXPIRY_SECS > now
+                && *utxo != bond_utxo
+                && entry.signed_address.address != signed_address.address
+        });
+        self.entries.insert(
+            bond_utxo,
+            GossipEntry {
+                signed_address,
+                last_seen: now,
+            },
+        );
+    }
+
+    pub fn addresses(&self, now: i64) -> Vec<String> {
+        self.entries
+            .values()
+            .filter(|entry| entry.last_seen + GOSSIP_ENTRY_EXPIRY_SECS > now)
+            .map(|entry| entry.signed_address.address.clone())
+            .collect()
+    }
+
+    /// A random sample, so that peers asking again learn about different makers
+    pub fn sample(&self, count: usize, now: i64) -> Vec<SignedMakerAddress> {
+        self.entries
+            .values()
+            .filter(|entry| entry.last_seen + GOSSIP_ENTRY_EXPIRY_SECS > now)
+            .map(|entry| entry.signed_address.clone())
+            .choose_multiple(&mut rand::thread_rng(), count)
+    }
+}
+
+pub fn onion_hostname(address: &str) -> &str {
+    address
+        .rsplit_once(':')
+        .map_or(address, |(host, _port)| host)
+}
+
+fn check_signatures(signed_address: &SignedMakerAddress) -> Result<(), Error> {
+    let hostname = onion_hostname(&signed_address.address);
+    if !hostname.ends_with(".onion") && !is_i2p_address(hostname) {
+        return Err(Error::Protocol("gossiped address not onion or i2p"));
+    }
+    signed_address
+        .fidelity_bond_proof
+        .verify_signatures(hostname)
+}
+
+/// Ask a maker for the makers it knows of. Only the first MAX_GOSSIP_ADDRESSES of the
+/// reply are looked at, and addresses not signed by their fidelity bond are left out
+pub async fn request_maker_addresses(
+    address: &MakerAddress,
+) -> Result<Vec<SignedMakerAddress>, Error> {
+    let mut socket = TcpStream::connect(address.get_tcpstream_address()).await?;
+    let (mut socket_reader, mut socket_writer, _) = handshake_maker(&mut socket, address).await?;
+    send_message(
+        &mut socket_writer,
+        address,
+        None,
+        None,
+        &TakerToMakerMessage::GiveMakerAddresses(GiveMakerAddresses),
+    )
+    .await?;
+    let maker_addresses = expect_message::<MakerAddresses>(
+        read_message(&mut socket_reader, address, None, None).await?,
+    )?
+    .addresses;
+    Ok(maker_addresses
+        .into_iter()
+        .take(MAX_GOSSIP_ADDRESSES)
+        .filter(|signed_address| match check_signatures(signed_address) {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::debug!(target: "gossip", "{} sent invalid address {}: {:?}",
+                    address, signed_address.address, e);
+                false
+            }
+        })
+        .collect())
+}
+
+/// Ask GOSSIP_PEER_COUNT randomly chosen makers at once for the makers they know of,
+/// makers which dont answer are skipped
+pub async fn gossip_with_makers(maker_addresses: &[MakerAddress]) -> Vec<SignedMakerAddress> {
+    let peers = maker_addresses
+        .iter()
+        .choose_multiple(&mut rand::thread_rng(), GOSSIP_PEER_COUNT);
+    let requests = peers.into_iter().map(|peer| async move {
+        select! {
+            ret = request_maker_addresses(peer) => ret.unwrap_or_else(|e| {
+                tracing::debug!(target: "gossip", "unable to get addresses from {}: {:?}",
+                    peer, e);
+                Vec::new()
+            }),
+            _ = sleep(Duration::from_secs(GOSSIP_REQUEST_TIMEOUT_SECS)) => {
+                tracing::debug!(target: "gossip", "timeout getting addresses from {}", peer);
+                Vec::new()
+            },
+        }
+    });
+    futures::future::join_all(requests).await.concat()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use bitcoin::util::ecdsa::PublicKey;
+    use bitcoin::Txid;
+
+    use crate::messages::FidelityBondProof;
+
+    fn signed_address(address: &str, vout: u32) -> SignedMakerAddress {
+        let secp = Secp256k1::new();
+        let key = PublicKey::new(bitcoin::secp256k1::PublicKey::from_secret_key(
+            &secp,
+            &SecretKey::from_slice(&[1; 32]).unwrap(),
+        ));
+        let sig = secp.sign(
+            &bitcoin::secp256k1::Message::from_slice(&[2; 32]).unwrap(),
+            &SecretKey::from_slice(&[1; 32]).unwrap(),
+        );
+        SignedMakerAddress {
+            address: address.to_string(),
+            fidelity_bond_proof: FidelityBondProof {
+                utxo: OutPoint {
+                    txid: Txid::from_str(
+                        "5f6c1fb5f8b25bdd2b1b9e87d24ca4ba2a8e2a0c0f1d1b7ab0e5b0c7d3c3e0a1",
+                    )
+                    .unwrap(),
+                    vout,
+                },
+                utxo_key: key,
+                locktime: 0,
+                cert_sig: sig,
+                cert_expiry: 1,
+                cert_pubkey: key,
+                onion_sig: sig,
+            },
+        }
+    }
+
+    #[test]
+    fn test_gossip_book() {
+        let mut book = GossipBook::default();
+        book.add(signed_address("a.onion:6102", 0), 1000);
+        book.add(signed_address("b.onion:6102", 1), 1000);
+        //the maker of bond 0 moved to a new address
+        book.add(signed_address("c.onion:6102", 0), 2000);
+        let mut addresses = book.addresses(2000);
+        addresses.sort();
+        assert_eq!(addresses, vec!["b.onion:6102", "c.onion:6102"]);
+
+        assert_eq!(book.sample(1, 2000).len(), 1);
+        assert_eq!(book.sample(10, 2000).len(), 2);
+        assert_eq!(book.sample(10, 1000 + GOSSIP_ENTRY_EXPIRY_SECS).len(), 1);
+
+        //the signatures are over other data so dont verify
+        assert!(check_signatures(&signed_address("a.onion:6102", 0)).is_err());
+        assert!(check_signatures(&signed_address("localhost:6102", 0)).is_err());
+    }
+}
@@ -0,0 +1,455 @@
+//gRPC API of teleportd, serving the same things as the daemon's JSON-RPC interface plus
+// server streams of coinswap progress and contract alerts
+//see proto/teleport.proto
+
+use std::convert::TryFrom;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::service::Interceptor;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use crate::daemon::{
+    self, CoinswapState, CoinswapStatus, ContractAlert, DaemonEvent, DaemonState, JsonRpcError,
+    StartCoinswapParams, INVALID_PARAMS, WALLET_BUSY,
+};
+use crate::taker_protocol::TakerEvent;
+
+pub mod pb {
+    tonic::include_proto!("teleport");
+}
+
+use pb::teleport_server::{Teleport, TeleportServer};
+
+//metadata key which must hold the contents of the daemon cookie file
+pub const COOKIE_METADATA_KEY: &str = "cookie";
+
+const STREAM_CHANNEL_CAPACITY: usize = 32;
+
+impl From<JsonRpcError> for Status {
+    fn from(e: JsonRpcError) -> Status {
+        match e.code {
+            INVALID_PARAMS => Status::invalid_argument(e.message),
+            WALLET_BUSY => Status::unavailable(e.message),
+            _ => Status::internal(e.message),
+        }
+    }
+}
+
+fn coinswap_state_to_pb(state: CoinswapState) -> pb::CoinswapState {
+    match state {
+        CoinswapState::Running => pb::CoinswapState::Running,
+        CoinswapState::Complete => pb::CoinswapState::Complete,
+        CoinswapState::Failed => pb::CoinswapState::Failed,
+    }
+}
+
+fn taker_event_to_pb(swap_id: usize, event: TakerEvent) -> pb::CoinswapEvent {
+    use pb::coinswap_event::Event;
+    let event = match event {
+        TakerEvent::OfferReceived { maker_address } => {
+            Event::OfferReceived(pb::OfferReceived { maker_address })
+        }
+        TakerEvent::OfferbookSynced { offer_count } => {
+            Event::OfferbookSynced(pb::OfferbookSynced {
+                offer_count: offer_count as u64,
+            })
+        }
+        TakerEvent::MakerChosen { maker_address } => {
+            Event::MakerChosen(pb::MakerChosen { maker_address })
+        }
+        TakerEvent::RecoveryTimeline { hops } => Event::RecoveryTimeline(pb::RecoveryTimeline {
+            hops: hops
+                .into_iter()
+                .map(|h| pb::HopTimeline {
+                    hop: h.hop as u64,
+                    locktime: h.locktime as u32,
+                    estimated_secs: h.estimated_secs,
+                })
+                .collect(),
+        }),
+        TakerEvent::FundingTxesBroadcast { txids } => {
+            Event::FundingTxesBroadcast(pb::FundingTxesBroadcast {
+                txids: txids.iter().map(|txid| txid.to_string()).collect(),
+            })
+        }
+        TakerEvent::FundingTxConfirmations {
+            txid,
+            confirmations,
+            required_confirmations,
+        } => Event::FundingTxConfirmations(pb::FundingTxConfirmations {
+            txid: txid.to_string(),
+            confirmations,
+            required_confirmations,
+        }),
+        TakerEvent::HashPreimageRevealed => {
+            Event::HashPreimageRevealed(pb::HashPreimageRevealed {})
+        }
+        TakerEvent::PrivateKeysHandedOver { maker_address } => {
+            Event::PrivateKeysHandedOver(pb::PrivateKeysHandedOver { maker_address })
+        }
+        TakerEvent::CoinswapComplete => Event::CoinswapComplete(pb::CoinswapComplete {}),
+    };
+    pb::CoinswapEvent {
+        swap_id: swap_id as u64,
+        event: Some(event),
+    }
+}
+
+fn coinswap_finished_to_pb(swap_id: usize, state: CoinswapState) -> pb::CoinswapEvent {
+    pb::CoinswapEvent {
+        swap_id: swap_id as u64,
+        event: Some(pb::coinswap_event::Event::CoinswapFinished(
+            pb::CoinswapFinished {
+                state: coinswap_state_to_pb(state) as i32,
+            },
+        )),
+    }
+}
+
+fn coinswap_to_pb(coinswap: CoinswapStatus) -> pb::Coinswap {
+    let swap_id = coinswap.swap_id;
+    pb::Coinswap {
+        swap_id: swap_id as u64,
+        send_amount: coinswap.send_amount,
+        maker_count: coinswap.maker_count as u32,
+        tx_count: coinswap.tx_count,
+        state: coinswap_state_to_pb(coinswap.state) as i32,
+        events: coinswap
+            .events
+            .into_iter()
+            .map(|event| taker_event_to_pb(swap_id, event))
+            .collect(),
+    }
+}
+
+fn contract_alert_to_pb(alert: ContractAlert) -> pb::ContractAlert {
+    pb::ContractAlert {
+        txid: alert.txid.to_string(),
+        vout: alert.vout,
+        amount_sat: alert.amount_sat,
+        confirmations: alert.confirmations,
+        contract_type: alert.contract_type.to_string(),
+    }
+}
+
+struct TeleportService {
+    state: Arc<DaemonState>,
+}
+
+#[tonic::async_trait]
+impl Teleport for TeleportService {
+    async fn get_status(
+        &self,
+        _request: Request<pb::GetStatusRequest>,
+    ) -> Result<Response<pb::GetStatusResponse>, Status> {
+        let status = self.state.status();
+        Ok(Response::new(pb::GetStatusResponse {
+            network: status.network,
+            wallet_file: status.wallet_file.display().to_string(),
+            coinswap_in_progress: status.coinswap_in_progress,
+            coinswap_count: status.coinswap_count as u64,
+        }))
+    }
+
+    async fn get_balance(
+        &self,
+        _request: Request<pb::GetBalanceRequest>,
+    ) -> Result<Response<pb::GetBalanceResponse>, Status> {
+        let balance = daemon::with_wallet(&self.state, daemon::get_balance).await?;
+        Ok(Response::new(pb::GetBalanceResponse {
+            spendable_sat: balance.spendable_sat,
+            spendable_utxo_count: balance.spendable_utxo_count as u64,
+            fidelity_bond_sat: balance.fidelity_bond_sat,
+            pending_incoming_sat: balance.pending_incoming_sat,
+        }))
+    }
+
+    async fn list_unspent(
+        &self,
+        _request: Request<pb::ListUnspentRequest>,
+    ) -> Result<Response<pb::ListUnspentResponse>, Status> {
+        let utxos = daemon::with_wallet(&self.state, daemon::list_unspent).await?;
+        Ok(Response::new(pb::ListUnspentResponse {
+            utxos: utxos
+                .into_iter()
+                .map(|utxo| pb::Utxo {
+                    txid: utxo.txid,
+                    vout: utxo.vout,
+                    address: utxo.address,
+                    amount_sat: utxo.amount_sat,
+                    confirmations: utxo.confirmations,
+                    r#type: utxo.utxo_type.to_string(),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn get_new_address(
+        &self,
+        _request: Request<pb::GetNewAddressRequest>,
+    ) -> Result<Response<pb::GetNewAddressResponse>, Status> {
+        let address = daemon::with_wallet(&self.state, daemon::get_new_address).await?;
+        Ok(Response::new(pb::GetNewAddressResponse { address }))
+    }
+
+    async fn get_offers(
+        &self,
+        _request: Request<pb::GetOffersRequest>,
+    ) -> Result<Response<pb::GetOffersResponse>, Status> {
+        let offers = daemon::get_offers(&self.state).await?;
+        Ok(Response::new(pb::GetOffersResponse {
+            offers: offers
+                .into_iter()
+                .map(|offer_info| {
+                    let offer = offer_info.offer;
+                    pb::Offer {
+                        address: offer_info.address,
+                        absolute_fee_sat: offer.absolute_fee_sat,
+                        amount_relative_fee_ppb: offer.amount_relative_fee_ppb,
+                        time_relative_fee_ppb: offer.time_relative_fee_ppb,
+                        required_confirms: offer.required_confirms,
+                        required_confirms_tiers: offer
+                            .required_confirms_tiers
+                            .iter()
+                            .map(|tier| pb::ConfirmsTier {
+                                amount: tier.amount,
+                                required_confirms: tier.required_confirms,
+                                maximum_locktime: tier.maximum_locktime.map(u32::from),
+                                fee_multiplier_percent: tier.fee_multiplier_percent,
+                            })
+                            .collect(),
+                        minimum_locktime: offer.minimum_locktime as u32,
+                        max_size: offer.max_size,
+                        min_size: offer.min_size,
+                        maximum_locktime: offer.maximum_locktime.map(u32::from),
+                        minimum_contract_react_time: offer
+                            .minimum_contract_react_time
+                            .map(u32::from),
+                    }
+                })
+                .collect(),
+        }))
+    }
+
+    async fn start_coinswap(
+        &self,
+        request: Request<pb::StartCoinswapRequest>,
+    ) -> Result<Response<pb::StartCoinswapResponse>, Status> {
+        let request = request.into_inner();
+        let maker_count = match request.maker_count {
+            Some(maker_count) => u16::try_from(maker_count)
+                .map_err(|_| Status::invalid_argument("maker_count too large"))?,
+            None => daemon::default_maker_count(),
+        };
+        let params = StartCoinswapParams {
+            send_amount: request.send_amount,
+            maker_count,
+            tx_count: request.tx_count.unwrap_or_else(daemon::default_tx_count),
+            fee_rate: request.fee_rate.unwrap_or_else(daemon::default_fee_rate),
+        };
+        let swap_id = daemon::start_coinswap(&self.state, params)?;
+        Ok(Response::new(pb::StartCoinswapResponse {
+            swap_id: swap_id as u64,
+        }))
+    }
+
+    async fn get_coinswap(
+        &self,
+        request: Request<pb::GetCoinswapRequest>,
+    ) -> Result<Response<pb::Coinswap>, Status> {
+        let coinswap = daemon::get_coinswap(&self.state, request.into_inner().swap_id as usize)?;
+        Ok(Response::new(coinswap_to_pb(coinswap)))
+    }
+
+    async fn list_coinswaps(
+        &self,
+        _request: Request<pb::ListCoinswapsRequest>,
+    ) -> Result<Response<pb::ListCoinswapsResponse>, Status> {
+        let coinswaps = self.state.coinswaps.lock().unwrap().clone();
+        Ok(Response::new(pb::ListCoinswapsResponse {
+            coinswaps: coinswaps.into_iter().map(coinswap_to_pb).collect(),
+        }))
+    }
+
+    type WatchCoinswapStream = ReceiverStream<Result<pb::CoinswapEvent, Status>>;
+
+    async fn watch_coinswap(
+        &self,
+        request: Request<pb::WatchCoinswapRequest>,
+    ) -> Result<Response<Self::WatchCoinswapStream>, Status> {
+        let swap_id = request.into_inner().swap_id as usize;
+        //subscribing while holding the lock means no event is missed or sent twice
+        let (coinswap, mut events_rx) = {
+            let coinswaps = self.state.coinswaps.lock().unwrap();
+            let coinswap = coinswaps
+                .get(swap_id)
+                .cloned()
+                .ok_or_else(|| Status::invalid_argument("unknown swap_id"))?;
+            (coinswap, self.state.events.subscribe())
+        };
+
+        let (tx, rx) = mpsc::channel(STREAM_CHANNEL_CAPACITY);
+        tokio::spawn(async move {
+            for event in coinswap.events {
+                if tx
+                    .send(Ok(taker_event_to_pb(swap_id, event)))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            if coinswap.state != CoinswapState::Running {
+                let _ = tx
+                    .send(Ok(coinswap_finished_to_pb(swap_id, coinswap.state)))
+                    .await;
+                return;
+            }
+            loop {
+                let event = match events_rx.recv().await {
+                    Ok(DaemonEvent::Coinswap {
+                        swap_id: event_swap_id,
+                        event,
+                    }) if event_swap_id == swap_id => taker_event_to_pb(swap_id, event),
+                    Ok(DaemonEvent::CoinswapFinished {
+                        swap_id: event_swap_id,
+                        state,
+                    }) if event_swap_id == swap_id => {
+                        let _ = tx.send(Ok(coinswap_finished_to_pb(swap_id, state))).await;
+                        return;
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(_)) => {
+                        let _ = tx
+                            .send(Err(Status::data_loss("too slow reading coinswap events")))
+                            .await;
+                        return;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                if tx.send(Ok(event)).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type WatchContractsStream = ReceiverStream<Result<pb::ContractAlert, Status>>;
+
+    async fn watch_contracts(
+        &self,
+        _request: Request<pb::WatchContractsRequest>,
+    ) -> Result<Response<Self::WatchContractsStream>, Status> {
+        let mut events_rx = self.state.events.subscribe();
+        let (tx, rx) = mpsc::channel(STREAM_CHANNEL_CAPACITY);
+        tokio::spawn(async move {
+            loop {
+                let alert = match events_rx.recv().await {
+                    Ok(DaemonEvent::ContractBroadcast(alert)) => contract_alert_to_pb(alert),
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(n)) => {
+                        tracing::warn!("gRPC contract alert stream lagged by {} events", n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+                if tx.send(Ok(alert)).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[derive(Clone)]
+struct CookieAuth {
+    cookie: String,
+}
+
+impl Interceptor for CookieAuth {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        match request.metadata().get(COOKIE_METADATA_KEY) {
+            Some(cookie)
+                if cookie
+                    .to_str()
+                    .is_ok_and(|cookie| daemon::is_correct_cookie(cookie, &self.cookie)) =>
+            {
+                Ok(request)
+            }
+            Some(_) => Err(Status::unauthenticated("incorrect cookie")),
+            None => Err(Status::unauthenticated(
+                "send the contents of the cookie file as the cookie metadata value",
+            )),
+        }
+    }
+}
+
+pub(crate) async fn serve(state: Arc<DaemonState>, port: u16) {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let auth = CookieAuth {
+        cookie: state.cookie.clone(),
+    };
+    let service = TeleportServer::with_interceptor(TeleportService { state }, auth);
+    tracing::info!("Serving gRPC API On Port {}", port);
+    if let Err(e) = Server::builder().add_service(service).serve(addr).await {
+        tracing::error!("gRPC server ended with err {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cookie_interceptor() {
+        let mut auth = CookieAuth {
+            cookie: "secret".to_string(),
+        };
+
+        assert!(auth.call(Request::new(())).is_err());
+
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert(COOKIE_METADATA_KEY, "wrong".parse().unwrap());
+        assert!(auth.call(request).is_err());
+
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert(COOKIE_METADATA_KEY, "secret".parse().unwrap());
+        assert!(auth.call(request).is_ok());
+    }
+
+    #[test]
+    fn test_coinswap_events_to_pb() {
+        let coinswap = CoinswapStatus {
+            swap_id: 3,
+            send_amount: 500000,
+            maker_count: 2,
+            tx_count: 3,
+            state: CoinswapState::Complete,
+            events: vec![
+                TakerEvent::OfferbookSynced { offer_count: 4 },
+                TakerEvent::CoinswapComplete,
+            ],
+        };
+        let coinswap = coinswap_to_pb(coinswap);
+        assert_eq!(coinswap.state, pb::CoinswapState::Complete as i32);
+        assert_eq!(coinswap.events.len(), 2);
+        assert!(coinswap.events.iter().all(|e| e.swap_id == 3));
+        assert_eq!(
+            coinswap.events[0].event,
+            Some(pb::coinswap_event::Event::OfferbookSynced(
+                pb::OfferbookSynced { offer_count: 4 }
+            ))
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//runs the shell commands of the [hooks] section of teleport.conf on coinswap events, so
+// operators can hook up their own alerting and accounting
+//
+//the command is run with `sh -c`, gets the event name in the TELEPORT_EVENT environment
+// variable and a JSON object on stdin like
+//    {"event": "swap_success", "source": "taker", "swap_id": "...", "timestamp": 1650000000}
+
+use std::process::Stdio;
+use std::time::Duration;
+
+use bitcoin::hashes::hash160::Hash as Hash160;
+use bitcoin::Txid;
+use serde::Serialize;
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use crate::error::Error;
+use crate::settings::{HookSettings, Settings};
+
+//a hook still running after this long is killed
+const HOOK_TIMEOUT_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum HookEvent {
+    //amount_sat is what the taker sends or what the maker receives
+    SwapStart { swap_id: Hash160, amount_sat: u64 },
+    SwapSuccess { swap_id: Hash160 },
+    SwapFailure { swap_id: Hash160, error: String },
+    ContractBroadcast { txids: Vec<Txid> },
+}
+
+impl HookEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            HookEvent::SwapStart { .. } => "swap_start",
+            HookEvent::SwapSuccess { .. } => "swap_success",
+            HookEvent::SwapFailure { .. } => "swap_failure",
+            HookEvent::ContractBroadcast { .. } => "contract_broadcast",
+        }
+    }
+
+    fn command<'a>(&self, settings: &'a HookSettings) -> Option<&'a str> {
+        match self {
+            HookEvent::SwapStart { .. } => &settings.on_swap_start,
+            HookEvent::SwapSuccess { .. } => &settings.on_swap_success,
+            HookEvent::SwapFailure { .. } => &settings.on_swap_failure,
+            HookEvent::ContractBroadcast { .. } => &settings.on_contract_broadcast,
+        }
+        .as_deref()
+        .filter(|command| !command.is_empty())
+    }
+}
+
+fn hook_command(event: &HookEvent) -> Option<String> {
+    if !Settings::is_initialized() {
+        return None;
+    }
+    event
+        .command(&Settings::global().hooks)
+        .map(|command| command.to_string())
+}
+
+fn hook_payload(source: &str, event: &HookEvent, timestamp: i64) -> serde_json::Value {
+    let mut payload = json!(event);
+    payload["source"] = json!(source);
+    payload["timestamp"] = json!(timestamp);
+    payload
+}
+
+/// Run the hook of the event and wait for it to finish, does nothing if it isnt configured.
+/// source is e.g. "taker" or "maker"
+pub async fn run_hook(source: &'static str, event: HookEvent) {
+    if let Some(command) = hook_command(&event) {
+        let payload = hook_payload(source, &event, chrono::Utc::now().timestamp());
+        if let Err(e) = execute_hook(&command, event.name(), &payload).await {
+            tracing::warn!("{} hook failed: {:?}", event.name(), e);
+        }
+    }
+}
+
+/// Run the hook of the event in the background, for code which shouldnt wait for it
+pub fn spawn_hook(source: &'static str, event: HookEvent) {
+    if hook_command(&event).is_none() {
+        return;
+    }
+    match tokio::runtime::Handle::try_current() {
+        Ok(runtime) => {
+            runtime.spawn(run_hook(source, event));
+        }
+        Err(_) => tracing::warn!("not running {} hook, no tokio runtime", event.name()),
+    }
+}
+
+async fn execute_hook(
+    command: &str,
+    event_name: &str,
+    payload: &serde_json::Value,
+) -> Result<(), Error> {
+    let mut hook = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("TELEPORT_EVENT", event_name)
+        .stdin(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let mut stdin = hook.stdin.take().unwrap();
+    //a hook which doesnt read its stdin closes it, which isnt an error
+    let _ = stdin.write_all(payload.to_string().as_bytes()).await;
+    drop(stdin);
+    let status = timeout(Duration::from_secs(HOOK_TIMEOUT_SECS), hook.wait())
+        .await
+        .map_err(|_| Error::Protocol("hook timed out"))??;
+    if !status.success() {
+        return Err(Error::Protocol("hook exited with an error"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_hook_payload() {
+        let event = HookEvent::SwapFailure {
+            swap_id: Hash160::hash(&[1; 32]),
+            error: "timeout".to_string(),
+        };
+        let payload = hook_payload("maker", &event, 1000);
+        assert_eq!(payload["event"], "swap_failure");
+        assert_eq!(payload["source"], "maker");
+        assert_eq!(payload["swap_id"], Hash160::hash(&[1; 32]).to_string());
+        assert_eq!(payload["error"], "timeout");
+        assert_eq!(payload["timestamp"], 1000);
+
+        let settings = HookSettings {
+            on_swap_failure: Some("notify".to_string()),
+            on_contract_broadcast: Some(String::new()),
+            ..HookSettings::default()
+        };
+        assert_eq!(event.command(&settings), Some("notify"));
+        let event = HookEvent::ContractBroadcast { txids: Vec::new() };
+        assert_eq!(event.command(&settings), None);
+    }
+
+    #[tokio::test]
+    async fn test_execute_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("event");
+        let command = format!("(echo $TELEPORT_EVENT; cat) > {}", output.display());
+        execute_hook(&command, "swap_start", &json!({"amount_sat": 5}))
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "swap_start\n{\"amount_sat\":5}"
+        );
+        assert!(execute_hook("exit 1", "swap_start", &json!({}))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,412 @@
+//minimal client for the SAM v3 bridge of an i2p router, used by makers to accept takers on an
+// i2p destination and by takers to connect to makers with .b32.i2p addresses
+//https://geti2p.net/en/docs/api/samv3
+//streams are made in a session, which lasts as long as the socket that created it stays open.
+// makers keep a session for their destination saved in the data directory so their address
+// doesnt change between runs, takers share one session with a new destination each run
+
+use std::collections::HashMap;
+use std::net::TcpStream as StdTcpStream;
+use std::path::{Path, PathBuf};
+
+use bitcoin::hashes::{sha256, Hash};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+use crate::error::Error;
+use crate::settings::{I2pSettings, Settings};
+use crate::utils::teleport_data_dir;
+
+const SAM_VERSION: &str = "3.1";
+//ed25519
+const SIGNATURE_TYPE: u8 = 7;
+//private keys are the longest replies, around 900 characters
+const MAX_REPLY_LINE_LEN: usize = 4096;
+const INVALID_SESSION_ERROR: &str = "i2p session no longer exists";
+
+//session used for connecting to makers, made on the first connection
+static TAKER_SESSION: Mutex<Option<SamSession>> = Mutex::const_new(None);
+
+pub fn is_i2p_address(address: &str) -> bool {
+    address
+        .rsplit_once(':')
+        .map_or(address, |(host, _port)| host)
+        .ends_with(".i2p")
+}
+
+pub fn i2p_settings() -> I2pSettings {
+    if Settings::is_initialized() {
+        Settings::global().i2p.clone()
+    } else {
+        Settings::default().i2p
+    }
+}
+
+/// Path of the maker's i2p private key in the data directory
+pub fn i2p_destination_path() -> PathBuf {
+    teleport_data_dir().join("i2p-private-key")
+}
+
+struct SamSession {
+    id: String,
+    //the bridge closes the session when this is closed, it is a std socket so it can outlive
+    // the tokio runtime it was made on
+    _control: StdTcpStream,
+}
+
+impl SamSession {
+    //destination is a private key or TRANSIENT for a new one, returns the session and the
+    // private key of its destination
+    async fn create(sam_address: &str, destination: &str) -> Result<(Self, String), Error> {
+        let mut socket = sam_connect(sam_address).await?;
+        let id = format!("teleport-{:016x}", rand::random::<u64>());
+        let reply = command(
+            &mut socket,
+            &format!(
+                "SESSION CREATE STYLE=STREAM ID={} DESTINATION={} SIGNATURE_TYPE={}",
+                id, destination, SIGNATURE_TYPE
+            ),
+            "SESSION STATUS",
+        )
+        .await?;
+        let private_key = reply
+            .get("DESTINATION")
+            .cloned()
+            .ok_or(Error::Protocol("i2p session gave no destination"))?;
+        let session = SamSession {
+            id,
+            _control: socket.into_std()?,
+        };
+        Ok((session, private_key))
+    }
+}
+
+/// A maker's i2p destination, which takers connect to
+pub struct I2pListener {
+    sam_address: String,
+    session: SamSession,
+    address: String,
+}
+
+impl I2pListener {
+    /// Create a session for the destination whose private key is saved at destination_path,
+    /// a new destination is made and saved there if the file doesnt exist
+    pub async fn bind(sam_address: &str, destination_path: &Path) -> Result<Self, Error> {
+        let private_key = match std::fs::read_to_string(destination_path) {
+            Ok(private_key) => private_key.trim().to_string(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut socket = sam_connect(sam_address).await?;
+                let reply = command(
+                    &mut socket,
+                    &format!("DEST GENERATE SIGNATURE_TYPE={}", SIGNATURE_TYPE),
+                    "DEST REPLY",
+                )
+                .await?;
+                let private_key = reply
+                    .get("PRIV")
+                    .cloned()
+                    .ok_or(Error::Protocol("i2p gave no private key"))?;
+                std::fs::write(destination_path, &private_key)?;
+                private_key
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let address = b32_address(&private_key)?;
+        let (session, _private_key) = SamSession::create(sam_address, &private_key).await?;
+        Ok(I2pListener {
+            sam_address: sam_address.to_string(),
+            session,
+            address,
+        })
+    }
+
+    /// The .b32.i2p address of the destination
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Wait for a taker to connect, returns the stream and the taker's .b32.i2p address
+    pub async fn accept(&self) -> Result<(TcpStream, String), Error> {
+        let mut socket = sam_connect(&self.sam_address).await?;
+        command(
+            &mut socket,
+            &format!("STREAM ACCEPT ID={} SILENT=false", self.session.id),
+            "STREAM STATUS",
+        )
+        .await?;
+        //once a peer connects the bridge sends its destination, then the stream starts
+        let line = read_line(&mut socket).await?;
+        let peer_destination = line.split(' ').next().unwrap_or_default();
+        Ok((socket, b32_address(peer_destination)?))
+    }
+}
+
+/// Turn a socket connected to the SAM bridge into a stream to the i2p address
+pub async fn connect(socket: &mut TcpStream, address: &str) -> Result<(), Error> {
+    let session_id = {
+        let mut session = TAKER_SESSION.lock().await;
+        if session.is_none() {
+            let (new_session, _private_key) =
+                SamSession::create(&i2p_settings().sam_address, "TRANSIENT").await?;
+            *session = Some(new_session);
+        }
+        session.as_ref().unwrap().id.clone()
+    };
+    let result = stream_connect(socket, &session_id, address).await;
+    if let Err(Error::Protocol(INVALID_SESSION_ERROR)) = result {
+        //e.g. the router restarted, the next connection makes a new session
+        let mut session = TAKER_SESSION.lock().await;
+        if session.as_ref().is_some_and(|s| s.id == session_id) {
+            *session = None;
+        }
+    }
+    result
+}
+
+async fn stream_connect(
+    socket: &mut TcpStream,
+    session_id: &str,
+    address: &str,
+) -> Result<(), Error> {
+    hello(socket).await?;
+    let hostname = address
+        .rsplit_once(':')
+        .map_or(address, |(host, _port)| host);
+    let lookup = command(
+        socket,
+        &format!("NAMING LOOKUP NAME={}", hostname),
+        "NAMING REPLY",
+    )
+    .await?;
+    let destination = lookup
+        .get("VALUE")
+        .ok_or(Error::Protocol("i2p lookup gave no destination"))?;
+    command(
+        socket,
+        &format!(
+            "STREAM CONNECT ID={} DESTINATION={} SILENT=false",
+            session_id, destination
+        ),
+        "STREAM STATUS",
+    )
+    .await?;
+    Ok(())
+}
+
+async fn sam_connect(sam_address: &str) -> Result<TcpStream, Error> {
+    let mut socket = TcpStream::connect(sam_address).await?;
+    hello(&mut socket).await?;
+    Ok(socket)
+}
+
+async fn hello<S: AsyncRead + AsyncWrite + Unpin>(socket: &mut S) -> Result<(), Error> {
+    command(
+        socket,
+        &format!("HELLO VERSION MIN={0} MAX={0}", SAM_VERSION),
+        "HELLO REPLY",
+    )
+    .await?;
+    Ok(())
+}
+
+//returns the KEY=VALUE pairs of the reply
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+    socket: &mut S,
+    command: &str,
+    expected_reply: &str,
+) -> Result<HashMap<String, String>, Error> {
+    socket
+        .write_all(format!("{}\n", command).as_bytes())
+        .await?;
+    let line = read_line(socket).await?;
+    let reply = parse_reply(&line, expected_reply)?;
+    match reply.get("RESULT").map(String::as_str) {
+        None | Some("OK") => Ok(reply),
+        Some("INVALID_ID") => Err(Error::Protocol(INVALID_SESSION_ERROR)),
+        Some(_) => {
+            tracing::warn!("i2p sam error reply: {}", line);
+            Err(Error::Protocol("i2p sam command failed"))
+        }
+    }
+}
+
+//reads a byte at a time so that none of the stream after the reply is taken from the socket
+async fn read_line<R: AsyncRead + Unpin>(socket: &mut R) -> Result<String, Error> {
+    let mut line = Vec::new();
+    loop {
+        match socket.read_u8().await? {
+            b'\n' => break,
+            byte if line.len() < MAX_REPLY_LINE_LEN => line.push(byte),
+            _ => return Err(Error::Protocol("i2p sam reply too long")),
+        }
+    }
+    String::from_utf8(line)
+        .map(|line| line.trim_end().to_string())
+        .map_err(|_| Error::Protocol("i2p sam reply not utf8"))
+}
+
+//values may be quoted, e.g. MESSAGE="Session not found"
+fn parse_reply(line: &str, expected_reply: &str) -> Result<HashMap<String, String>, Error> {
+    let pairs = line
+        .strip_prefix(expected_reply)
+        .ok_or(Error::Protocol("unexpected i2p sam reply"))?;
+    let mut reply = HashMap::new();
+    let mut rest = pairs.trim_start();
+    while let Some((key, after_key)) = rest.split_once('=') {
+        let (value, after_value) = match after_key.strip_prefix('"') {
+            Some(quoted) => quoted
+                .split_once('"')
+                .ok_or(Error::Protocol("unterminated i2p sam reply value"))?,
+            None => after_key.split_once(' ').unwrap_or((after_key, "")),
+        };
+        reply.insert(key.to_string(), value.to_string());
+        rest = after_value.trim_start();
+    }
+    Ok(reply)
+}
+
+//i2p uses base64 with - and ~ instead of + and /
+fn i2p_base64_decode(s: &str) -> Result<Vec<u8>, Error> {
+    bitcoin::base64::decode(&s.replace('-', "+").replace('~', "/"))
+        .map_err(|_| Error::Protocol("invalid i2p base64"))
+}
+
+//the address is the base32 hash of the destination, which is at the start of a private key.
+// a destination is 387 bytes followed by a certificate whose length is in its last two bytes
+fn b32_address(destination_or_private_key: &str) -> Result<String, Error> {
+    let bytes = i2p_base64_decode(destination_or_private_key)?;
+    if bytes.len() < 387 {
+        return Err(Error::Protocol("i2p destination too short"));
+    }
+    let destination_len = 387 + u16::from_be_bytes([bytes[385], bytes[386]]) as usize;
+    let destination = bytes
+        .get(..destination_len)
+        .ok_or(Error::Protocol("i2p destination too short"))?;
+    Ok(format!(
+        "{}.b32.i2p",
+        base32_encode(&sha256::Hash::hash(destination)[..])
+    ))
+}
+
+//rfc4648 lowercase without padding
+fn base32_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut encoded = String::new();
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_parse_reply() {
+        let reply = parse_reply(
+            "SESSION STATUS RESULT=I2P_ERROR MESSAGE=\"tunnel build failed\" ID=a",
+            "SESSION STATUS",
+        )
+        .unwrap();
+        assert_eq!(reply["RESULT"], "I2P_ERROR");
+        assert_eq!(reply["MESSAGE"], "tunnel build failed");
+        assert_eq!(reply["ID"], "a");
+        assert!(parse_reply("HELLO REPLY RESULT=OK", "SESSION STATUS").is_err());
+        assert!(parse_reply("DEST REPLY PUB=\"abc", "DEST REPLY").is_err());
+
+        assert!(is_i2p_address("abcdef.b32.i2p"));
+        assert!(is_i2p_address("abcdef.b32.i2p:6102"));
+        assert!(!is_i2p_address("abcdef.onion:6102"));
+    }
+
+    #[test]
+    fn test_b32_address() {
+        assert_eq!(base32_encode(b""), "");
+        assert_eq!(base32_encode(b"f"), "my");
+        assert_eq!(base32_encode(b"foobar"), "mzxw6ytboi");
+
+        //destination with an ed25519 key certificate, whose 4 bytes follow its 3 byte header
+        let mut destination = vec![7u8; 384];
+        destination.extend_from_slice(&[5, 0, 4, 0, 7, 0, 4]);
+        let mut private_key = destination.clone();
+        private_key.extend_from_slice(&[9u8; 64]);
+        let encoded = bitcoin::base64::encode(&private_key)
+            .replace('+', "-")
+            .replace('/', "~");
+        let expected = format!(
+            "{}.b32.i2p",
+            base32_encode(&sha256::Hash::hash(&destination)[..])
+        );
+        assert_eq!(b32_address(&encoded).unwrap(), expected);
+        assert_eq!(expected.len(), 52 + ".b32.i2p".len());
+        assert!(b32_address("AAAA").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sam_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sam_address = listener.local_addr().unwrap().to_string();
+        //pretends to be the sam bridge, replying to the commands the client is expected to send
+        let fake_sam = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = socket.into_split();
+            let mut reader = BufReader::new(reader);
+            let exchanges = [
+                (
+                    "HELLO VERSION MIN=3.1 MAX=3.1",
+                    "HELLO REPLY RESULT=OK VERSION=3.1\n",
+                ),
+                (
+                    "NAMING LOOKUP NAME=abcdef.b32.i2p",
+                    "NAMING REPLY RESULT=OK NAME=abcdef.b32.i2p VALUE=dest\n",
+                ),
+                (
+                    "STREAM CONNECT ID=session DESTINATION=dest SILENT=false",
+                    "STREAM STATUS RESULT=OK\nmakerhello",
+                ),
+            ];
+            for (expected_command, reply) in exchanges.iter() {
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                assert_eq!(line.trim_end(), *expected_command);
+                writer.write_all(reply.as_bytes()).await.unwrap();
+            }
+            let (socket, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = socket.into_split();
+            let mut reader = BufReader::new(reader);
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            writer
+                .write_all(b"HELLO REPLY RESULT=I2P_ERROR MESSAGE=\"no\"\n")
+                .await
+                .unwrap();
+        });
+
+        let mut socket = TcpStream::connect(&sam_address).await.unwrap();
+        stream_connect(&mut socket, "session", "abcdef.b32.i2p")
+            .await
+            .unwrap();
+        //whatever follows the reply belongs to the stream
+        let mut buf = [0; 10];
+        socket.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"makerhello");
+
+        assert!(sam_connect(&sam_address).await.is_err());
+        fake_sam.await.unwrap();
+    }
+}
@@ -2,46 +2,60 @@ extern crate bitcoin;
 extern crate bitcoin_wallet;
 extern crate bitcoincore_rpc;
 
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::convert::TryInto;
 use std::fs;
 use std::io;
 use std::iter::repeat;
-use std::path::PathBuf;
-use std::sync::{Arc, Once, RwLock};
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc::Sender, Arc, Once, RwLock};
 
-use bitcoin::hashes::{hash160::Hash as Hash160, hex::ToHex};
-use bitcoin::{Amount, Network};
+use bitcoin::hashes::{
+    hash160::Hash as Hash160,
+    hex::{FromHex, ToHex},
+};
+use bitcoin::util::bip32::ExtendedPrivKey;
+use bitcoin::{Address, Amount, Network, OutPoint, PrivateKey, Script};
 use bitcoin_wallet::mnemonic;
-use bitcoincore_rpc::{Auth, Client, RpcApi};
+use bitcoincore_rpc::{Auth, RpcApi};
+use serde_json::{json, Value};
 
 use chrono::NaiveDateTime;
 
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
+
 pub mod wallet_sync;
 use wallet_sync::{
-    DisplayAddressType, UTXOSpendInfo, Wallet, WalletSwapCoin, WalletSyncAddressAmount,
+    reuse_warning, DisplayAddressType, SendHistoryEntry, UTXOSpendInfo, Wallet, WalletSwapCoin,
+    WalletSyncAddressAmount,
 };
 
 pub mod direct_send;
 use direct_send::{CoinToSpend, Destination, SendAmount};
 
 pub mod contracts;
-use contracts::{read_locktime_from_contract, SwapCoin};
+use contracts::{read_locktime_from_contract, redeemscript_to_scriptpubkey, SwapCoin};
 
 pub mod maker_protocol;
-use maker_protocol::MakerBehavior;
+use maker_protocol::{ListenAddress, MakerBehavior};
 
 pub mod taker_protocol;
-use taker_protocol::TakerConfig;
+use taker_protocol::{TakerConfig, TakerEvent};
 
 pub mod offerbook_sync;
-use offerbook_sync::{get_advertised_maker_addresses, sync_offerbook_with_addresses, MakerAddress};
+use offerbook_sync::{
+    get_advertised_maker_addresses, load_offerbook_cache, offerbook_cache_path,
+    save_offerbook_cache, stream_offerbook_with_addresses, sync_offerbook, MakerAddress,
+};
 
 pub mod fidelity_bonds;
 use fidelity_bonds::{get_locktime_from_index, YearAndMonth};
 
 pub mod settings;
-use settings::Settings;
+use settings::{BlockchainSettings, Settings};
 
 pub mod utils;
 use utils::teleport_data_dir;
@@ -49,54 +63,285 @@ use utils::teleport_data_dir;
 pub mod error;
 use error::Error;
 
+pub mod audit_log;
+
+pub mod rpc;
+use rpc::Client;
+
+pub mod alerts;
+pub mod bond_certificate;
+pub mod bond_planning;
+pub mod channel_funding;
+pub mod coin_linkage;
+pub mod coin_viability;
+pub mod contract_decoder;
+pub mod contract_export;
+pub mod contract_spend;
+pub mod daemon;
+pub mod directory_server;
 pub mod directory_servers;
+pub mod fan_out;
+pub mod fee_report;
+pub mod ffi;
 pub mod funding_tx;
+pub mod gossip;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hooks;
+pub mod i2p;
+pub mod locktime_policy;
+pub mod maker_banlist;
+pub mod maker_connections;
+pub mod maker_health;
+pub mod maker_identity;
+pub mod maker_rebalance;
+pub mod maker_stats;
+pub mod maker_uptime;
 pub mod messages;
+pub mod offer_fees;
+pub mod payjoin;
+pub mod protocol_phase;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod recovery_destinations;
+pub mod route_filter;
+pub mod swap_monitor;
+pub mod swap_receipt;
+pub mod swap_sessions;
+pub mod swap_timeline;
+pub mod swapcoin_descriptors;
+pub mod taker_blacklist;
+#[cfg(feature = "test-framework")]
+pub mod test_framework;
+pub mod tor_control;
+pub mod tumbler;
+pub mod wallet_accounts;
+pub mod wallet_backup;
+pub mod wallet_files;
 pub mod watchtower_client;
 pub mod watchtower_protocol;
+pub mod websocket;
 
 static INIT: Once = Once::new();
 
+const DISPLAYED_ROUTE_COUNT: usize = 10;
+
 fn str_to_bitcoin_network(net_str: &str) -> Network {
     match net_str {
-        "main" => Network::Bitcoin,
-        "test" => Network::Testnet,
+        "main" | "mainnet" => Network::Bitcoin,
+        "test" | "testnet" => Network::Testnet,
+        //testnet4 uses the same address and key prefixes as testnet3
+        "testnet4" => Network::Testnet,
         "signet" => Network::Signet,
         "regtest" => Network::Regtest,
         _ => panic!("unknown network: {}", net_str),
     }
 }
 
+/// Credentials of the bitcoin node RPC, in order of preference rpc_user and rpc_password,
+/// the output of rpc_cookie_command and the cookie file
+fn bitcoin_rpc_auth(blockchain_settings: &BlockchainSettings) -> Result<Auth, Error> {
+    if let Some((user, pass)) = blockchain_settings.rpc_userpass() {
+        return Ok(Auth::UserPass(user, pass));
+    }
+    if let Some(command) = &blockchain_settings.rpc_cookie_command {
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .output()?;
+        if !output.status.success() {
+            return Err(Error::Protocol("rpc_cookie_command failed"));
+        }
+        let cookie = String::from_utf8_lossy(&output.stdout);
+        let (user, pass) = cookie
+            .trim()
+            .split_once(':')
+            .ok_or(Error::Rpc(bitcoincore_rpc::Error::InvalidCookieFile))?;
+        return Ok(Auth::UserPass(user.to_string(), pass.to_string()));
+    }
+    Ok(Auth::CookieFile(blockchain_settings.rpc_cookie_path()))
+}
+
 pub fn get_bitcoin_rpc() -> Result<(Client, Network), Error> {
     let blockchain_settings = &Settings::global().blockchain;
-    let auth = match blockchain_settings.rpc_userpass() {
-        Some((user, pass)) => Auth::UserPass(user, pass),
-        _ => Auth::CookieFile(blockchain_settings.rpc_cookie_path()),
-    };
+    let auth = bitcoin_rpc_auth(blockchain_settings)?;
+    //tor encrypts the connection to an onion service already
+    if !blockchain_settings.rpc_tls
+        && !blockchain_settings.rpc_host.ends_with(".onion")
+        && !["localhost", "127.0.0.1", "::1", "[::1]"]
+            .contains(&blockchain_settings.rpc_host.as_str())
+    {
+        tracing::warn!(
+            "rpc credentials are sent in cleartext to {}, set rpc_tls to connect over https",
+            blockchain_settings.rpc_host
+        );
+    }
     let rpc = Client::new(blockchain_settings.rpc_url(), auth)?;
     let network = str_to_bitcoin_network(rpc.get_blockchain_info()?.chain.as_str());
+    load_or_create_core_wallet(&rpc, &blockchain_settings.rpc_wallet_file, true)?;
     Ok((rpc, network))
 }
 
+//bitcoind's error codes for a wallet which doesnt exist and one already loaded
+const RPC_WALLET_NOT_FOUND: i32 = -18;
+const RPC_WALLET_ALREADY_LOADED: i32 = -35;
+
+fn rpc_error_code(error: &bitcoincore_rpc::Error) -> Option<i32> {
+    match error {
+        bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Rpc(e)) => Some(e.code),
+        _ => None,
+    }
+}
+
+/// Load a Core wallet, creating it if it doesnt exist yet as a blank wallet. The wallet named
+/// in rpc_wallet_file is watch_only, teleport only imports addresses into it to watch them
+fn load_or_create_core_wallet(
+    rpc: &Client,
+    wallet_name: &str,
+    watch_only: bool,
+) -> Result<(), Error> {
+    if rpc.list_wallets()?.iter().any(|w| w == wallet_name) {
+        return Ok(());
+    }
+    let error = match rpc.call::<Value>("loadwallet", &[json!(wallet_name)]) {
+        Ok(_) => return Ok(()),
+        Err(e) => e,
+    };
+    match rpc_error_code(&error) {
+        Some(RPC_WALLET_ALREADY_LOADED) => Ok(()),
+        Some(RPC_WALLET_NOT_FOUND) => {
+            let wallet_kind = if watch_only {
+                "watch-only"
+            } else {
+                "descriptor"
+            };
+            tracing::info!("creating {} Core wallet {}", wallet_kind, wallet_name);
+            //wallet_name, disable_private_keys, blank, passphrase, avoid_reuse, descriptors
+            //the addresses are imported with importmulti which needs a legacy wallet, keys
+            // with importdescriptors which needs a descriptor wallet
+            rpc.call::<Value>(
+                "createwallet",
+                &[
+                    json!(wallet_name),
+                    json!(watch_only),
+                    json!(true),
+                    json!(""),
+                    json!(false),
+                    json!(!watch_only),
+                ],
+            )?;
+            Ok(())
+        }
+        _ => Err(error.into()),
+    }
+}
+
+/// Check the bitcoin node can be reached with the configured settings and that
+/// none of listen_ports conflict with anything, before starting any long-running subsystem
+pub fn check_bitcoin_node_settings(listen_ports: &[u16]) -> Result<(), Error> {
+    let blockchain_settings = &Settings::global().blockchain;
+    let mut problems = Vec::new();
+
+    let cookie_path = blockchain_settings.rpc_cookie_path();
+    if blockchain_settings.rpc_userpass().is_none()
+        && blockchain_settings.rpc_cookie_command.is_none()
+        && !cookie_path.exists()
+    {
+        problems.push(format!(
+            "rpc_user and rpc_password not set and cookie file {} does not exist, \
+            check bitcoind is running on network {}",
+            cookie_path.display(),
+            blockchain_settings.network
+        ));
+    } else {
+        match get_bitcoin_rpc() {
+            Ok((_rpc, network)) => {
+                if network != str_to_bitcoin_network(&blockchain_settings.network) {
+                    problems.push(format!(
+                        "network is {} but bitcoin node at {}:{} is on {}",
+                        blockchain_settings.network,
+                        blockchain_settings.rpc_host,
+                        blockchain_settings.rpc_port,
+                        network
+                    ));
+                }
+            }
+            Err(error) => problems.push(format!(
+                "unable to reach bitcoin node at {}:{}: {:?}",
+                blockchain_settings.rpc_host, blockchain_settings.rpc_port, error
+            )),
+        }
+    }
+
+    let rpc_is_local =
+        ["localhost", "127.0.0.1", "::1"].contains(&blockchain_settings.rpc_host.as_str());
+    for port in listen_ports {
+        if rpc_is_local && *port == blockchain_settings.rpc_port {
+            problems.push(format!("port {} conflicts with rpc_port", port));
+        } else if let Err(e) = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, *port)) {
+            problems.push(format!("unable to listen on port {}: {}", port, e));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Config(problems))
+    }
+}
+
 /// Setup function that will only run once, even if called multiple times.
 pub fn setup_teleport() {
     INIT.call_once(|| {
-        // Setup logger
-        env_logger::Builder::from_env(
-            env_logger::Env::default()
-                .default_filter_or("teleport=info,main=info,wallet=info")
-                .default_write_style_or("always"),
-        )
-        .init();
-
         // Setup app data directory
         let datadir = teleport_data_dir();
         if !datadir.exists() {
             fs::create_dir(&datadir).expect("Error making app data dir");
         }
-        if !datadir.join("wallets").exists() {
-            fs::create_dir(datadir.join("wallets")).expect("Error making wallet dir");
+        if !datadir.join(wallet_files::WALLETS_DIR_NAME).exists() {
+            fs::create_dir(datadir.join(wallet_files::WALLETS_DIR_NAME))
+                .expect("Error making wallet dir");
         }
+
+        // Setup logger from the [logging] settings, RUST_LOG overrides the log levels
+        let logging = &Settings::global().logging;
+        let filter = EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| EnvFilter::new(logging.filter_directives()));
+        let console_layer = if logging.console_format == "json" {
+            tracing_subscriber::fmt::layer().json().boxed()
+        } else {
+            tracing_subscriber::fmt::layer().with_ansi(true).boxed()
+        };
+        let file_layer = logging.file.as_ref().and_then(|file| {
+            let path = datadir.join(file);
+            let rotation = match logging.file_rotation.as_str() {
+                "hourly" => Rotation::HOURLY,
+                "daily" => Rotation::DAILY,
+                _ => Rotation::NEVER,
+            };
+            let mut builder = RollingFileAppender::builder()
+                .rotation(rotation)
+                .filename_prefix(path.file_name()?.to_string_lossy());
+            if let Some(max_log_files) = logging.max_log_files {
+                builder = builder.max_log_files(max_log_files);
+            }
+            match builder.build(path.parent()?) {
+                Ok(appender) => Some(
+                    tracing_subscriber::fmt::layer()
+                        .with_ansi(false)
+                        .with_writer(appender),
+                ),
+                Err(e) => {
+                    eprintln!("unable to open log file {}: {}", path.display(), e);
+                    None
+                }
+            }
+        });
+        tracing_subscriber::registry()
+            .with(console_layer)
+            .with(file_layer)
+            .with(filter)
+            .init();
     });
 }
 
@@ -104,7 +349,7 @@ pub fn generate_wallet(wallet_file_name: &PathBuf) -> std::io::Result<()> {
     let (rpc, network) = match get_bitcoin_rpc() {
         Ok(rpc) => rpc,
         Err(error) => {
-            log::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
             return Ok(());
         }
     };
@@ -116,8 +361,14 @@ pub fn generate_wallet(wallet_file_name: &PathBuf) -> std::io::Result<()> {
         mnemonic::Mnemonic::new_random(bitcoin_wallet::account::MasterKeyEntropy::Sufficient)
             .unwrap();
 
-    Wallet::save_new_wallet_file(wallet_file_name, mnemonic.to_string(), extension.clone())
-        .unwrap();
+    let birthday_height = rpc.get_block_count().ok();
+    Wallet::save_new_wallet_file(
+        wallet_file_name,
+        mnemonic.to_string(),
+        extension.clone(),
+        birthday_height,
+    )
+    .unwrap();
 
     let w = match Wallet::load_wallet_from_file(
         wallet_file_name,
@@ -171,16 +422,314 @@ pub fn recover_wallet(wallet_file_name: &PathBuf) -> std::io::Result<()> {
     io::stdin().read_line(&mut extension)?;
     extension = extension.trim().to_string();
 
-    Wallet::save_new_wallet_file(wallet_file_name, seed_phrase, extension).unwrap();
+    //the wallet could have been used at any height, `wallet rescan` needs to be given one
+    Wallet::save_new_wallet_file(wallet_file_name, seed_phrase, extension, None).unwrap();
     println!("\nSaved to file `{}`", wallet_file_name.to_string_lossy());
     Ok(())
 }
 
+//how many receive addresses verify-seed compares
+const VERIFY_SEED_ADDRESS_COUNT: u32 = 3;
+
+fn read_stdin_line() -> io::Result<String> {
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(line.trim().to_string())
+}
+
+/// Print the wallet's seed phrase after the user confirms, with check_extension the user
+/// types the extension and is told whether it matches, the extension itself is never printed
+pub fn show_seed(wallet_file_name: &PathBuf, check_extension: bool) -> io::Result<()> {
+    let network = str_to_bitcoin_network(&Settings::global().blockchain.network);
+    let wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return Ok(());
+        }
+    };
+    let (seedphrase, extension) = match wallet.get_seed_phrase() {
+        Ok(s) => s,
+        Err(error) => {
+            tracing::error!(target: "main", "error reading seed phrase: {:?}", error);
+            return Ok(());
+        }
+    };
+
+    println!(
+        "anyone who sees the seed phrase can take the wallet's coins, make sure nobody \
+        can see the screen. type `yes` to show it: "
+    );
+    if read_stdin_line()? != "yes" {
+        println!("not shown");
+        return Ok(());
+    }
+    println!("\nseed phrase:\n\n{}\n", seedphrase);
+    if extension.is_empty() {
+        println!("this wallet has no seed phrase extension");
+        return Ok(());
+    }
+    println!("this wallet also has a seed phrase extension, it is needed to recover the wallet");
+    if check_extension {
+        println!("input seed phrase extension: ");
+        if read_stdin_line()? == extension {
+            println!("extension matches");
+        } else {
+            println!("extension DOES NOT match");
+        }
+    }
+    Ok(())
+}
+
+/// Ask for a written down seed phrase and extension and check that they derive the same first
+/// receive addresses as the wallet file
+pub fn verify_seed(wallet_file_name: &PathBuf) -> io::Result<()> {
+    let network = str_to_bitcoin_network(&Settings::global().blockchain.network);
+    let wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return Ok(());
+        }
+    };
+
+    println!("input seed phrase: ");
+    let seedphrase = read_stdin_line()?;
+    println!("input seed phrase extension (or leave blank for none): ");
+    let extension = read_stdin_line()?;
+    let master_key =
+        match wallet_sync::master_key_from_seed_phrase(&seedphrase, &extension, network) {
+            Ok(k) => k,
+            Err(_) => {
+                println!("invalid seed phrase, check the words and their order");
+                return Ok(());
+            }
+        };
+
+    let backup_addresses =
+        wallet_sync::derive_receive_addresses(&master_key, network, VERIFY_SEED_ADDRESS_COUNT);
+    let wallet_addresses = wallet.get_first_receive_addresses(VERIFY_SEED_ADDRESS_COUNT);
+    for (backup_address, wallet_address) in backup_addresses.iter().zip(wallet_addresses.iter()) {
+        println!(
+            "{} {}",
+            backup_address,
+            if backup_address == wallet_address {
+                "matches"
+            } else {
+                "DOES NOT match"
+            }
+        );
+    }
+    if backup_addresses == wallet_addresses {
+        println!(
+            "\nthe backup matches wallet file `{}`",
+            wallet_file_name.display()
+        );
+    } else {
+        println!(
+            "\nthe backup DOES NOT match wallet file `{}`, check the seed phrase and extension",
+            wallet_file_name.display()
+        );
+    }
+    Ok(())
+}
+
+/// Write an encrypted backup of the whole wallet file to backup_path, with a passphrase
+/// asked for twice
+pub fn backup_wallet(wallet_file_name: &PathBuf, backup_path: &PathBuf) -> io::Result<()> {
+    if backup_path.exists() {
+        tracing::error!(target: "main", "backup file {} already exists", backup_path.display());
+        return Ok(());
+    }
+    let wallet_file = match Wallet::read_wallet_file(wallet_file_name) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error reading wallet file: {:?}", error);
+            return Ok(());
+        }
+    };
+
+    println!("input backup passphrase: ");
+    let passphrase = read_stdin_line()?;
+    println!("input backup passphrase again: ");
+    if read_stdin_line()? != passphrase {
+        println!("passphrases dont match, no backup written");
+        return Ok(());
+    }
+    if passphrase.is_empty() {
+        println!("the backup contains the seed phrase, it needs a passphrase");
+        return Ok(());
+    }
+
+    let backup = wallet_backup::encrypt_wallet_file(
+        &wallet_file,
+        &passphrase,
+        wallet_backup::DEFAULT_PBKDF2_ITERATIONS,
+        chrono::Utc::now().timestamp(),
+    );
+    let backup_file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(backup_path)?;
+    serde_json::to_writer_pretty(backup_file, &backup)?;
+    println!(
+        "backup of wallet file `{}` written to {}",
+        wallet_file_name.display(),
+        backup_path.display()
+    );
+    Ok(())
+}
+
+/// Decrypt a backup written by backup_wallet into a new wallet file
+pub fn restore_wallet(wallet_file_name: &PathBuf, backup_path: &PathBuf) -> io::Result<()> {
+    let backup = match fs::read(backup_path).and_then(|contents| {
+        serde_json::from_slice::<wallet_backup::WalletBackup>(&contents).map_err(io::Error::from)
+    }) {
+        Ok(b) => b,
+        Err(error) => {
+            tracing::error!(target: "main", "error reading backup file: {:?}", error);
+            return Ok(());
+        }
+    };
+
+    println!("input backup passphrase: ");
+    let passphrase = read_stdin_line()?;
+    let wallet_file = match wallet_backup::decrypt_wallet_file(&backup, &passphrase) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error decrypting backup: {:?}", error);
+            return Ok(());
+        }
+    };
+    if let Err(error) = Wallet::restore_wallet_file(wallet_file_name, &wallet_file) {
+        tracing::error!(target: "main", "error writing wallet file: {:?}", error);
+        return Ok(());
+    }
+    //the Core wallet of a new machine wont have the wallet's transactions until it rescans
+    println!(
+        "restored backup from {} to wallet file `{}`, run `wallet rescan` if the bitcoin node \
+        is new to this wallet",
+        chrono::DateTime::from_timestamp(backup.created_timestamp, 0)
+            .map(|d| d.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default(),
+        wallet_file_name.display()
+    );
+    Ok(())
+}
+
+pub fn display_wallet_list() {
+    let names = match wallet_files::list_wallet_names(
+        &teleport_data_dir().join(wallet_files::WALLETS_DIR_NAME),
+    ) {
+        Ok(names) => names,
+        Err(error) => {
+            tracing::error!(target: "main", "error reading wallets directory: {:?}", error);
+            return;
+        }
+    };
+    let default_file_name = wallet_files::resolve_wallet_file_name(
+        None,
+        None,
+        Settings::global().default_wallet.as_deref(),
+    );
+    for name in names {
+        if wallet_files::wallet_file_name(&name) == default_file_name {
+            println!("* {} (default)", name);
+        } else {
+            println!("  {}", name);
+        }
+    }
+}
+
+/// Generate a new wallet in the wallets directory, it becomes the default wallet if there
+/// isnt one yet
+pub fn create_wallet(name: &str) -> Result<(), Error> {
+    let wallet_file_name = wallet_files::wallet_file_name(name);
+    let path = teleport_data_dir()
+        .join(wallet_files::WALLETS_DIR_NAME)
+        .join(&wallet_file_name);
+    if path.exists() {
+        return Err(Error::Config(vec![format!(
+            "wallet {} already exists",
+            path.display()
+        )]));
+    }
+    generate_wallet(&wallet_file_name)?;
+    if path.exists() && Settings::global().default_wallet.is_none() {
+        set_default_wallet(name)?;
+    }
+    Ok(())
+}
+
+/// Set default_wallet in teleport.conf
+pub fn set_default_wallet(name: &str) -> Result<(), Error> {
+    let datadir = teleport_data_dir();
+    let wallet_path = datadir
+        .join(wallet_files::WALLETS_DIR_NAME)
+        .join(wallet_files::wallet_file_name(name));
+    if !wallet_path.exists() {
+        return Err(Error::Config(vec![format!(
+            "wallet {} does not exist",
+            wallet_path.display()
+        )]));
+    }
+    let config_path = datadir.join(settings::CONFIG_FILE_NAME);
+    let contents = match fs::read_to_string(&config_path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    fs::write(
+        &config_path,
+        wallet_files::set_default_wallet_in_config(&contents, name),
+    )?;
+    println!("default wallet is now {}", name);
+    Ok(())
+}
+
+pub fn rescan_wallet(wallet_file_name: &PathBuf, height: Option<u64>) {
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    let wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    println!("Rescanning, this can take a while. . .");
+    match wallet.rescan(&rpc, height) {
+        Ok((start_height, stop_height)) => println!(
+            "rescanned blocks {} to {}",
+            start_height,
+            stop_height.map_or("the tip".to_string(), |h| h.to_string())
+        ),
+        Err(error) => tracing::error!(target: "main", "error rescanning: {:?}", error),
+    }
+}
+
 pub fn display_wallet_balance(wallet_file_name: &PathBuf, long_form: Option<bool>) {
     let (rpc, network) = match get_bitcoin_rpc() {
         Ok(rpc) => rpc,
         Err(error) => {
-            log::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
             return;
         }
     };
@@ -191,7 +740,7 @@ pub fn display_wallet_balance(wallet_file_name: &PathBuf, long_form: Option<bool
     ) {
         Ok(w) => w,
         Err(error) => {
-            log::error!(target: "main", "error loading wallet file: {:?}", error);
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
             return;
         }
     };
@@ -200,7 +749,7 @@ pub fn display_wallet_balance(wallet_file_name: &PathBuf, long_form: Option<bool
     let long_form = long_form.unwrap_or(false);
 
     let utxos_incl_fbonds = wallet.list_unspent_from_wallet(&rpc, false, true).unwrap();
-    let (mut utxos, mut fidelity_bond_utxos): (Vec<_>, Vec<_>) =
+    let (utxos, mut fidelity_bond_utxos): (Vec<_>, Vec<_>) =
         utxos_incl_fbonds.iter().partition(|(_, usi)| {
             matches!(
                 usi,
@@ -210,11 +759,20 @@ pub fn display_wallet_balance(wallet_file_name: &PathBuf, long_form: Option<bool
                 }
             )
         });
+    //deposits still in the mempool arent counted in the balance until they confirm
+    let (pending_incoming_utxos, mut utxos): (Vec<_>, Vec<_>) = utxos
+        .into_iter()
+        .partition(|(u, _)| daemon::is_pending_incoming(&rpc, u).unwrap_or(false));
     utxos.sort_by(|(a, _), (b, _)| b.confirmations.cmp(&a.confirmations));
     let utxo_count = utxos.len();
     let balance: Amount = utxos
         .iter()
         .fold(Amount::ZERO, |acc, (u, _)| acc + u.amount);
+    let account_balances = wallet_accounts::account_balances(
+        utxos
+            .iter()
+            .map(|(u, spend_info)| (spend_info, u.amount.as_sat())),
+    );
     println!("= spendable wallet balance =");
     println!(
         "{:16} {:24} {:^8} {:<7} value",
@@ -242,6 +800,22 @@ pub fn display_wallet_balance(wallet_file_name: &PathBuf, long_form: Option<bool
     }
     println!("coin count = {}", utxo_count);
     println!("total balance = {}", balance);
+    for (account, account_balance) in account_balances {
+        println!(
+            "{} account balance = {}",
+            account,
+            Amount::from_sat(account_balance)
+        );
+    }
+    if !pending_incoming_utxos.is_empty() {
+        println!(
+            "pending incoming = {} in {} unconfirmed coins",
+            pending_incoming_utxos
+                .iter()
+                .fold(Amount::ZERO, |acc, (u, _)| acc + u.amount),
+            pending_incoming_utxos.len()
+        );
+    }
 
     let incomplete_coinswaps = wallet.find_incomplete_coinswaps(&rpc).unwrap();
     if !incomplete_coinswaps.is_empty() {
@@ -404,6 +978,26 @@ pub fn display_wallet_balance(wallet_file_name: &PathBuf, long_form: Option<bool
             );
         }
     }
+
+    display_address_reuse(&rpc, &wallet);
+}
+
+fn display_address_reuse(rpc: &Client, wallet: &Wallet) {
+    let address_reuse = match wallet.find_address_reuse(rpc) {
+        Ok(r) => r,
+        Err(error) => {
+            tracing::error!(target: "main", "error checking address reuse: {:?}", error);
+            return;
+        }
+    };
+    if address_reuse.is_empty() {
+        return;
+    }
+    println!("= address reuse warnings =");
+    for reuse in address_reuse {
+        println!("{}", reuse_warning(&reuse));
+    }
+    println!("coins on reused addresses can be linked to each other, consider coinswapping them");
 }
 
 pub fn display_wallet_addresses(
@@ -411,11 +1005,11 @@ pub fn display_wallet_addresses(
     types: DisplayAddressType,
     network: Option<String>,
 ) {
-    let network = match get_bitcoin_rpc() {
-        Ok((_rpc, network)) => network,
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok((rpc, network)) => (Some(rpc), network),
         Err(error) => {
             if let Some(net_str) = network {
-                str_to_bitcoin_network(net_str.as_str())
+                (None, str_to_bitcoin_network(net_str.as_str()))
             } else {
                 panic!(
                     "network string not provided, and error connecting to bitcoin node: {:?}",
@@ -431,18 +1025,21 @@ pub fn display_wallet_addresses(
     ) {
         Ok(w) => w,
         Err(error) => {
-            log::error!(target: "main", "error loading wallet file: {:?}", error);
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
             return;
         }
     };
     wallet.display_addresses(types);
+    if let Some(rpc) = rpc {
+        display_address_reuse(&rpc, &wallet);
+    }
 }
 
 pub fn print_receive_invoice(wallet_file_name: &PathBuf) {
     let (rpc, network) = match get_bitcoin_rpc() {
         Ok(rpc) => rpc,
         Err(error) => {
-            log::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
             return;
         }
     };
@@ -453,7 +1050,7 @@ pub fn print_receive_invoice(wallet_file_name: &PathBuf) {
     ) {
         Ok(w) => w,
         Err(error) => {
-            log::error!(target: "main", "error loading wallet file: {:?}", error);
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
             return;
         }
     };
@@ -469,11 +1066,18 @@ pub fn print_receive_invoice(wallet_file_name: &PathBuf) {
     println!("{}", addr);
 }
 
-pub fn print_fidelity_bond_address(wallet_file_name: &PathBuf, locktime: &YearAndMonth) {
+/// Write the proof of the instance-th most valuable fidelity bond for maker_address to
+/// certificate_path, in the format described in bond_certificate.rs
+pub fn export_fidelity_bond(
+    wallet_file_name: &PathBuf,
+    maker_address: &str,
+    instance: u32,
+    certificate_path: &PathBuf,
+) {
     let (rpc, network) = match get_bitcoin_rpc() {
         Ok(rpc) => rpc,
         Err(error) => {
-            log::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
             return;
         }
     };
@@ -484,70 +1088,270 @@ pub fn print_fidelity_bond_address(wallet_file_name: &PathBuf, locktime: &YearAn
     ) {
         Ok(w) => w,
         Err(error) => {
-            log::error!(target: "main", "error loading wallet file: {:?}", error);
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
             return;
         }
     };
     wallet.startup_sync(&rpc).unwrap();
 
-    let (addr, unix_locktime) = wallet.get_timelocked_address(locktime);
-    println!(concat!(
-        "WARNING: You should send coins to this address only once.",
-        " Only single biggest value UTXO will be announced as a fidelity bond.",
-        " Sending coins to this address multiple times will not increase",
-        " fidelity bond value."
-    ));
-    println!(concat!(
-        "WARNING: Only send coins here which are from coinjoins, coinswaps or",
-        " otherwise not linked to your identity. Also, use a sweep transaction when funding the",
-        " timelocked address, i.e. Don't create a change address."
-    ));
+    let hostname = gossip::onion_hostname(maker_address);
+    let proof = match wallet
+        .find_fidelity_bond_by_rank(&rpc, instance as usize)
+        .and_then(|bond| bond.map(|b| b.create_proof(&rpc, hostname)).transpose())
+    {
+        Ok(Some(proof)) => proof,
+        Ok(None) => {
+            println!(
+                "the wallet has no confirmed fidelity bond for maker instance {}",
+                instance
+            );
+            return;
+        }
+        Err(error) => {
+            tracing::error!(target: "main", "error creating fidelity bond proof: {:?}", error);
+            return;
+        }
+    };
+    let certificate = bond_certificate::BondCertificate::new(proof, maker_address, network);
+    let written = fs::File::create(certificate_path)
+        .and_then(|file| serde_json::to_writer_pretty(file, &certificate).map_err(io::Error::from));
+    if let Err(error) = written {
+        tracing::error!(target: "main", "error writing certificate file: {:?}", error);
+        return;
+    }
     println!(
-        "Coins sent to this address will not be spendable until {}",
-        NaiveDateTime::from_timestamp_opt(unix_locktime, 0)
-            .unwrap()
-            .format("%Y-%m-%d")
+        "fidelity bond {} of {} written to {}",
+        certificate.proof.utxo,
+        maker_address,
+        certificate_path.display()
     );
-    println!("{}", addr);
 }
 
-pub fn run_maker(
-    wallet_file_name: &PathBuf,
-    sync_amount: WalletSyncAddressAmount,
-    port: u16,
-    maker_behavior: MakerBehavior,
-    kill_flag: Option<Arc<RwLock<bool>>>,
-) {
-    let (rpc, network) = match get_bitcoin_rpc() {
-        Ok(rpc) => rpc,
+/// Check a certificate written by export_fidelity_bond, without a bitcoin node only the
+/// signatures and scripts are checked
+pub fn verify_fidelity_bond(certificate_path: &PathBuf) {
+    let certificate = match fs::read(certificate_path).and_then(|contents| {
+        serde_json::from_slice::<bond_certificate::BondCertificate>(&contents)
+            .map_err(io::Error::from)
+    }) {
+        Ok(c) => c,
         Err(error) => {
-            log::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            tracing::error!(target: "main", "error reading certificate file: {:?}", error);
             return;
         }
     };
-    let mut wallet = match Wallet::load_wallet_from_file(wallet_file_name, network, sync_amount) {
+    if let Err(error) = certificate.verify_offline() {
+        println!("INVALID fidelity bond certificate: {:?}", error);
+        return;
+    }
+    println!(
+        "signatures valid, bond {} at {} locked until {} signs maker address {}",
+        certificate.proof.utxo,
+        certificate.bond_address,
+        chrono::DateTime::from_timestamp(certificate.proof.locktime, 0)
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .unwrap_or_default(),
+        certificate.maker_address
+    );
+
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            println!(
+                "not checked whether the bond exists, no bitcoin node: {:?}",
+                error
+            );
+            return;
+        }
+    };
+    if network != certificate.network {
+        println!(
+            "not checked whether the bond exists, the bitcoin node is on {} but the bond on {}",
+            network, certificate.network
+        );
+        return;
+    }
+    let checked = rpc
+        .get_blockchain_info()
+        .map_err(Error::from)
+        .and_then(|info| {
+            let txo_data = certificate.proof.verify_and_get_txo(
+                &rpc,
+                info.blocks,
+                gossip::onion_hostname(&certificate.maker_address),
+            )?;
+            let value = certificate.proof.calculate_fidelity_bond_value(
+                &rpc,
+                info.blocks,
+                &txo_data,
+                info.median_time,
+            )?;
+            Ok((txo_data, value))
+        });
+    match checked {
+        Ok((txo_data, value)) => println!(
+            "VALID fidelity bond of {} with {} confirmations, bond value {:.0}",
+            txo_data.value, txo_data.confirmations, value
+        ),
+        Err(error) => println!("INVALID fidelity bond: {:?}", error),
+    }
+}
+
+pub fn display_bond_plan(amount_sat: u64, months: u32, operation_months: Option<u32>) {
+    let plan = match bond_planning::plan_bond(
+        amount_sat,
+        months,
+        operation_months,
+        Settings::global().maker.instances,
+        chrono::Utc::now().timestamp(),
+    ) {
+        Ok(p) => p,
+        Err(error) => {
+            tracing::error!(target: "main", "unable to plan fidelity bond: {:?}", error);
+            return;
+        }
+    };
+    println!(
+        "a bond of {} locked until {} is worth {:.0}",
+        Amount::from_sat(plan.amount_sat),
+        plan.year_and_month,
+        plan.value
+    );
+    println!(
+        "its address is shown by `get-fidelity-bond-address {}`\n",
+        plan.year_and_month
+    );
+    println!(
+        "{:<8} {:<20} {:<20} {:<20}",
+        "bonds", "amount per bond", "value per bond", "total value"
+    );
+    for split in &plan.splits {
+        println!(
+            "{:<8} {:<20} {:<20.0} {:<20.0}",
+            split.bond_count,
+            Amount::from_sat(split.amount_per_bond_sat).to_string(),
+            split.value_per_bond,
+            split.total_value
+        );
+    }
+    for warning in &plan.warnings {
+        println!("\nWARNING: {}", warning);
+    }
+}
+
+pub fn print_fidelity_bond_address(wallet_file_name: &PathBuf, locktime: &YearAndMonth) {
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    let mut wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    wallet.startup_sync(&rpc).unwrap();
+
+    let (addr, unix_locktime) = wallet.get_timelocked_address(locktime);
+    println!(concat!(
+        "WARNING: You should send coins to this address only once.",
+        " Only single biggest value UTXO will be announced as a fidelity bond.",
+        " Sending coins to this address multiple times will not increase",
+        " fidelity bond value."
+    ));
+    println!(concat!(
+        "WARNING: Only send coins here which are from coinjoins, coinswaps or",
+        " otherwise not linked to your identity. Also, use a sweep transaction when funding the",
+        " timelocked address, i.e. Don't create a change address."
+    ));
+    println!(
+        "Coins sent to this address will not be spendable until {}",
+        NaiveDateTime::from_timestamp_opt(unix_locktime, 0)
+            .unwrap()
+            .format("%Y-%m-%d")
+    );
+    println!("{}", addr);
+}
+
+pub fn run_maker(
+    wallet_file_name: &PathBuf,
+    sync_amount: WalletSyncAddressAmount,
+    listen_addresses: Vec<ListenAddress>,
+    maker_behavior: MakerBehavior,
+    kill_flag: Option<Arc<RwLock<bool>>>,
+) {
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    let mut wallet = match Wallet::load_wallet_from_file(wallet_file_name, network, sync_amount) {
         Ok(w) => w,
         Err(error) => {
-            log::error!(target: "main", "error loading wallet file: {:?}", error);
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
             return;
         }
     };
     wallet.startup_sync(&rpc).unwrap();
+    if let Err(error) = wallet.open_audit_log() {
+        tracing::error!(target: "main", "error opening audit log: {:?}", error);
+        return;
+    }
+
+    let instances = Settings::global().maker.instances;
+    let mut instance_listen_addresses = Vec::new();
+    for instance in 0..instances {
+        match maker_protocol::instance_listen_addresses(&listen_addresses, instance) {
+            Ok(addresses) => instance_listen_addresses.push(addresses),
+            Err(error) => {
+                tracing::error!(target: "main", "error running maker instances: {}", error);
+                return;
+            }
+        }
+    }
 
     let rpc_ptr = Arc::new(rpc);
     let wallet_ptr = Arc::new(RwLock::new(wallet));
-    let config = maker_protocol::MakerConfig {
-        port,
-        rpc_ping_interval_secs: 60,
-        watchtower_ping_interval_secs: 300,
-        directory_servers_refresh_interval_secs: 60 * 60 * 12, //12 hours
-        maker_behavior,
-        kill_flag: kill_flag.unwrap_or(Arc::new(RwLock::new(false))),
-        idle_connection_timeout: 300,
-    };
-    maker_protocol::start_maker(rpc_ptr, wallet_ptr, config);
+    let kill_flag = kill_flag.unwrap_or(Arc::new(RwLock::new(false)));
+    //every instance runs in its own thread, all of them on the same wallet
+    let maker_threads = instance_listen_addresses
+        .into_iter()
+        .zip(0..)
+        .map(|(listen_addresses, instance)| {
+            let config = maker_protocol::MakerConfig {
+                listen_addresses,
+                rpc_ping_interval_secs: 60,
+                watchtower_ping_interval_secs: 300,
+                directory_servers_refresh_interval_secs: 60 * 60 * 12, //12 hours
+                maker_behavior,
+                kill_flag: Arc::clone(&kill_flag),
+                idle_connection_timeout: 300,
+                maker_settings: Settings::global().maker.clone(),
+                tor_settings: Settings::global().tor.clone(),
+                i2p_settings: Settings::global().i2p.clone(),
+                instance,
+            };
+            let rpc_ptr = Arc::clone(&rpc_ptr);
+            let wallet_ptr = Arc::clone(&wallet_ptr);
+            std::thread::spawn(move || maker_protocol::start_maker(rpc_ptr, wallet_ptr, config))
+        })
+        .collect::<Vec<_>>();
+    for maker_thread in maker_threads {
+        maker_thread.join().unwrap();
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn run_taker(
     wallet_file_name: &PathBuf,
     sync_amount: WalletSyncAddressAmount,
@@ -555,22 +1359,28 @@ pub fn run_taker(
     send_amount: u64,
     maker_count: u16,
     tx_count: u32,
+    account: Option<wallet_accounts::WalletAccount>,
+    progress_tx: Option<Sender<TakerEvent>>,
 ) {
     let (rpc, network) = match get_bitcoin_rpc() {
         Ok(rpc) => rpc,
         Err(error) => {
-            log::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
             return;
         }
     };
     let mut wallet = match Wallet::load_wallet_from_file(wallet_file_name, network, sync_amount) {
         Ok(w) => w,
         Err(error) => {
-            log::error!(target: "main", "error loading wallet file: {:?}", error);
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
             return;
         }
     };
     wallet.startup_sync(&rpc).unwrap();
+    if let Err(error) = wallet.open_audit_log() {
+        tracing::error!(target: "main", "error opening audit log: {:?}", error);
+        return;
+    }
     taker_protocol::start_taker(
         &rpc,
         &mut wallet,
@@ -580,19 +1390,23 @@ pub fn run_taker(
             tx_count,
             required_confirms: 1,
             fee_rate,
+            min_funding_tx_fee_rate: taker_protocol::min_funding_tx_fee_rate(),
+            account,
         },
+        progress_tx,
     );
 }
 
-pub fn recover_from_incomplete_coinswap(
+/// Start a tumble with params, or carry on with the wallet's unfinished tumble if params is None
+pub fn run_tumbler(
     wallet_file_name: &PathBuf,
-    hashvalue: Hash160,
-    dont_broadcast: bool,
+    fee_rate: u64,
+    params: Option<tumbler::TumbleParams>,
 ) {
     let (rpc, network) = match get_bitcoin_rpc() {
         Ok(rpc) => rpc,
         Err(error) => {
-            log::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
             return;
         }
     };
@@ -603,148 +1417,109 @@ pub fn recover_from_incomplete_coinswap(
     ) {
         Ok(w) => w,
         Err(error) => {
-            log::error!(target: "main", "error loading wallet file: {:?}", error);
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
             return;
         }
     };
     wallet.startup_sync(&rpc).unwrap();
-
-    let incomplete_coinswaps = wallet.find_incomplete_coinswaps(&rpc).unwrap();
-    let incomplete_coinswap = incomplete_coinswaps.get(&hashvalue);
-    if incomplete_coinswap.is_none() {
-        log::error!(target: "main", "hashvalue not refering to incomplete coinswap, run \
-                `wallet-balance` to see list of incomplete coinswaps");
+    if let Err(error) = wallet.open_audit_log() {
+        tracing::error!(target: "main", "error opening audit log: {:?}", error);
         return;
     }
-    let incomplete_coinswap = incomplete_coinswap.unwrap();
-    for (ii, swapcoin) in incomplete_coinswap
-        .0
-        .iter()
-        .map(|(l, i)| (l, (*i as &dyn WalletSwapCoin)))
-        .chain(
-            incomplete_coinswap
-                .1
+    let schedule_path = tumbler::tumble_schedule_path(wallet_file_name);
+    let existing_schedule = tumbler::TumbleSchedule::load(&schedule_path)
+        .ok()
+        .filter(|s| !s.is_finished());
+    match (&existing_schedule, params) {
+        (Some(_), Some(_)) => {
+            tracing::error!(target: "main", "a tumble is already in progress, run `tumble` \
+                without an amount to carry on with it or delete {} to start a new one",
+                schedule_path.display());
+            return;
+        }
+        (Some(schedule), None) => println!(
+            "carrying on with the tumble, {} of {} coinswaps left",
+            schedule.coinswaps.len() - schedule.current().unwrap(),
+            schedule.coinswaps.len()
+        ),
+        (None, Some(params)) => {
+            if let Some(address) = params
+                .destinations
                 .iter()
-                .map(|(l, o)| (l, (*o as &dyn WalletSwapCoin))),
-        )
-        .enumerate()
-    {
-        wallet
-            .import_wallet_contract_redeemscript(&rpc, &swapcoin.1.get_contract_redeemscript())
-            .unwrap();
-
-        let signed_contract_tx = swapcoin.1.get_fully_signed_contract_tx();
-        if dont_broadcast {
-            let txhex = bitcoin::consensus::encode::serialize_hex(&signed_contract_tx);
-            println!(
-                "contract_tx_{} (txid = {}) = \n{}",
-                ii,
-                signed_contract_tx.txid(),
-                txhex
+                .find(|a| !direct_send::address_network_matches(a, network))
+            {
+                tracing::error!(target: "main", "destination {} is for the wrong network", address);
+                return;
+            }
+            let schedule = tumbler::TumbleSchedule::generate(
+                &params,
+                chrono::Utc::now().timestamp(),
+                &mut rand::thread_rng(),
             );
-            let accepted = rpc
-                .test_mempool_accept(&[txhex.clone()])
-                .unwrap()
-                .iter()
-                .any(|tma| tma.allowed);
-            assert!(accepted);
-        } else {
-            let txid = rpc.send_raw_transaction(&signed_contract_tx).unwrap();
-            println!("broadcasted {}", txid);
-        }
-    }
-}
-
-#[tokio::main]
-pub async fn download_and_display_offers(
-    network_str: Option<String>,
-    maker_address: Option<String>,
-) {
-    let maker_addresses = if let Some(maker_addr) = maker_address {
-        vec![MakerAddress::Tor {
-            address: maker_addr,
-        }]
-    } else {
-        let network = match get_bitcoin_rpc() {
-            Ok((_rpc, network)) => network,
-            Err(error) => {
-                if let Some(net_str) = network_str {
-                    str_to_bitcoin_network(net_str.as_str())
-                } else {
-                    panic!(
-                        "network string not provided, and error connecting to bitcoin node: {:?}",
-                        error
-                    );
-                }
+            for (i, coinswap) in schedule.coinswaps.iter().enumerate() {
+                println!(
+                    "coinswap {}: {} sat through {} makers after {} minutes{}",
+                    i + 1,
+                    coinswap.amount,
+                    coinswap.maker_count,
+                    coinswap.delay_secs / 60,
+                    coinswap
+                        .destination
+                        .as_ref()
+                        .map(|d| format!(", then sent to {}", d))
+                        .unwrap_or_default()
+                );
             }
-        };
-        get_advertised_maker_addresses(network)
-            .await
-            .expect("unable to sync maker addresses from directory servers")
-    };
-    let offers_addresses = sync_offerbook_with_addresses(maker_addresses.clone()).await;
-    let mut addresses_offers_map = HashMap::new();
-    for offer_address in offers_addresses.iter() {
-        let address_str = match &offer_address.address {
-            MakerAddress::Clearnet { address } => address,
-            MakerAddress::Tor { address } => address,
-        };
-        addresses_offers_map.insert(address_str, offer_address);
+            if let Err(error) = schedule.save(&schedule_path) {
+                tracing::error!(target: "main", "error saving tumble schedule: {:?}", error);
+                return;
+            }
+        }
+        (None, None) => {
+            tracing::error!(target: "main", "no tumble in progress, give an amount to start one");
+            return;
+        }
     }
 
-    println!(
-        "{:<3} {:<70} {:<12} {:<12} {:<12} {:<12} {:<12} {:<12} {:<19}",
-        "n",
-        "maker address",
-        "max size",
-        "min size",
-        "abs fee",
-        "amt rel fee",
-        "time rel fee",
-        "minlocktime",
-        "fidelity bond value",
-    );
-
-    for (ii, address) in maker_addresses.iter().enumerate() {
-        let address_str = match &address {
-            MakerAddress::Clearnet { address } => address,
-            MakerAddress::Tor { address } => address,
-        };
-        if let Some(offer_address) = addresses_offers_map.get(&address_str) {
-            let o = &offer_address.offer;
-
-            println!(
-                "{:<3} {:<70} {:<12} {:<12} {:<12} {:<12} {:<12} {:<12}",
-                ii,
-                address_str,
-                o.max_size,
-                o.min_size,
-                o.absolute_fee_sat,
-                o.amount_relative_fee_ppb,
-                o.time_relative_fee_ppb,
-                o.minimum_locktime,
-            );
-        } else {
-            println!("{:<3} {:<70} UNREACHABLE", ii, address_str);
-        }
+    match tumbler::run_tumble(
+        &rpc,
+        &mut wallet,
+        &schedule_path,
+        fee_rate,
+        taker_protocol::min_funding_tx_fee_rate(),
+    ) {
+        Ok(()) => println!("tumble finished"),
+        Err(error) => tracing::error!(target: "main", "tumble stopped, run `tumble` again to \
+            carry on with it: {:?}", error),
     }
 }
 
-pub fn direct_send(
+/// Do a coinswap then send the coins it made on to the destinations of fan_out_params, one
+/// transaction per destination at random times
+pub fn run_taker_with_fan_out(
     wallet_file_name: &PathBuf,
     fee_rate: u64,
-    send_amount: SendAmount,
-    destination: Destination,
-    coins_to_spend: &[CoinToSpend],
-    dont_broadcast: bool,
+    send_amount: u64,
+    maker_count: u16,
+    tx_count: u32,
+    account: Option<wallet_accounts::WalletAccount>,
+    fan_out_params: fan_out::FanOutParams,
 ) {
     let (rpc, network) = match get_bitcoin_rpc() {
         Ok(rpc) => rpc,
         Err(error) => {
-            log::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
             return;
         }
     };
+    if let Some(address) = fan_out_params
+        .destinations
+        .iter()
+        .find(|a| !direct_send::address_network_matches(a, network))
+    {
+        tracing::error!(target: "main", "destination {} is for the wrong network", address);
+        return;
+    }
     let mut wallet = match Wallet::load_wallet_from_file(
         wallet_file_name,
         network,
@@ -752,54 +1527,1832 @@ pub fn direct_send(
     ) {
         Ok(w) => w,
         Err(error) => {
-            log::error!(target: "main", "error loading wallet file: {:?}", error);
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
             return;
         }
     };
-    wallet.startup_sync(&rpc).unwrap();
-    let tx = wallet
-        .create_direct_send(&rpc, fee_rate, send_amount, destination, coins_to_spend)
-        .unwrap();
-    let txhex = bitcoin::consensus::encode::serialize_hex(&tx);
-    log::debug!("fully signed tx hex = {}", txhex);
-    let test_mempool_accept_result = &rpc.test_mempool_accept(&[txhex.clone()]).unwrap()[0];
-    if !test_mempool_accept_result.allowed {
-        panic!(
-            "created invalid transaction, reason = {:#?}",
-            test_mempool_accept_result
-        );
+    let plan_path = fan_out::fan_out_plan_path(wallet_file_name);
+    if fan_out::FanOutPlan::load(&plan_path).is_ok_and(|p| !p.is_finished()) {
+        tracing::error!(target: "main", "the coins of an earlier coinswap are still being \
+            sent on, run `fan-out` to carry on with it or delete {} to start a new one",
+            plan_path.display());
+        return;
     }
-    println!(
-        "actual fee rate = {:.3} sat/vb",
-        test_mempool_accept_result
-            .fees
-            .as_ref()
-            .unwrap()
-            .base
-            .as_sat() as f64
-            / test_mempool_accept_result.vsize.unwrap() as f64
-    );
-    if dont_broadcast {
-        println!("tx = \n{}", txhex);
-    } else {
-        let txid = rpc.send_raw_transaction(&tx).unwrap();
-        println!("broadcasted {}", txid);
+    wallet.startup_sync(&rpc).unwrap();
+    if let Err(error) = wallet.open_audit_log() {
+        tracing::error!(target: "main", "error opening audit log: {:?}", error);
+        return;
     }
-}
 
-pub fn run_watchtower(data_file_path: &PathBuf, kill_flag: Option<Arc<RwLock<bool>>>) {
-    let (rpc, network) = match get_bitcoin_rpc() {
-        Ok(rpc) => rpc,
+    let swapcoins_before = match fan_out::swapcoin_values(&rpc, &wallet) {
+        Ok(coins) => coins,
         Err(error) => {
-            log::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            tracing::error!(target: "main", "error listing wallet coins: {:?}", error);
             return;
         }
     };
-
-    watchtower_protocol::start_watchtower(
+    if let Err(error) = taker_protocol::start_taker_avoiding_makers(
         &rpc,
-        data_file_path,
-        network,
-        kill_flag.unwrap_or(Arc::new(RwLock::new(false))),
-    );
+        &mut wallet,
+        TakerConfig {
+            send_amount,
+            maker_count,
+            tx_count,
+            required_confirms: 1,
+            fee_rate,
+            min_funding_tx_fee_rate: taker_protocol::min_funding_tx_fee_rate(),
+            account,
+        },
+        &[],
+        None,
+    ) {
+        tracing::error!(target: "main", "coinswap failed: {:?}", error);
+        return;
+    }
+    let new_coins = match fan_out::swapcoin_values(&rpc, &wallet) {
+        Ok(coins) => coins
+            .into_iter()
+            .filter(|c| !swapcoins_before.contains(c))
+            .collect::<Vec<_>>(),
+        Err(error) => {
+            tracing::error!(target: "main", "error listing wallet coins: {:?}", error);
+            return;
+        }
+    };
+    if new_coins.is_empty() {
+        tracing::error!(target: "main", "the coinswap made no coins to send on");
+        return;
+    }
+    let plan = fan_out::FanOutPlan::new(
+        &new_coins,
+        &fan_out_params,
+        chrono::Utc::now().timestamp(),
+        &mut rand::thread_rng(),
+    );
+    if plan.sends.len() < fan_out_params.destinations.len() {
+        tracing::warn!(target: "main", "the coinswap made only {} coins, some destinations \
+            wont be sent to", new_coins.len());
+    }
+    for send in &plan.sends {
+        println!(
+            "{} coins to be sent to {} at {}",
+            send.coins.len(),
+            send.destination,
+            chrono::DateTime::from_timestamp(send.send_timestamp, 0).unwrap()
+        );
+    }
+    if let Err(error) = plan.save(&plan_path) {
+        tracing::error!(target: "main", "error saving fan out plan: {:?}", error);
+        return;
+    }
+    send_fanned_out_coins(&rpc, &mut wallet, &plan_path, fee_rate);
+}
+
+/// Carry on sending the coins of the wallet's last coinswap to its destinations
+pub fn resume_fan_out(wallet_file_name: &PathBuf, fee_rate: u64) {
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    let mut wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    let plan_path = fan_out::fan_out_plan_path(wallet_file_name);
+    match fan_out::FanOutPlan::load(&plan_path) {
+        Ok(plan) if !plan.is_finished() => println!(
+            "carrying on sending coins, {} of {} destinations left",
+            plan.sends.iter().filter(|s| s.txid.is_none()).count(),
+            plan.sends.len()
+        ),
+        _ => {
+            tracing::error!(target: "main", "no coins waiting to be sent on");
+            return;
+        }
+    }
+    wallet.startup_sync(&rpc).unwrap();
+    send_fanned_out_coins(&rpc, &mut wallet, &plan_path, fee_rate);
+}
+
+fn send_fanned_out_coins(rpc: &Client, wallet: &mut Wallet, plan_path: &Path, fee_rate: u64) {
+    match fan_out::run_fan_out(rpc, wallet, plan_path, fee_rate) {
+        Ok(()) => println!("sent the coins to every destination"),
+        Err(error) => tracing::error!(target: "main", "sending coins stopped, run `fan-out` to \
+            carry on with it: {:?}", error),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn spend_contract(
+    wallet_file_name: &PathBuf,
+    fee_rate: u64,
+    contract_outpoint: OutPoint,
+    redeemscript_hex: &str,
+    preimage_hex: Option<String>,
+    wif: Option<PrivateKey>,
+    destination: Option<Address>,
+    dont_broadcast: bool,
+) {
+    let redeemscript = match Vec::<u8>::from_hex(redeemscript_hex) {
+        Ok(bytes) => Script::from(bytes),
+        Err(_) => {
+            tracing::error!(target: "main", "invalid redeemscript hex");
+            return;
+        }
+    };
+    let branch = match preimage_hex.as_deref().map(Vec::<u8>::from_hex) {
+        Some(Ok(preimage)) => contract_spend::ContractBranch::Hashlock(preimage),
+        Some(Err(_)) => {
+            tracing::error!(target: "main", "invalid preimage hex");
+            return;
+        }
+        None => contract_spend::ContractBranch::Timelock,
+    };
+    let required_confirmations =
+        match contract_spend::required_confirmations(&redeemscript, &branch) {
+            Ok(c) => c,
+            Err(error) => {
+                tracing::error!(target: "main", "unable to spend contract: {:?}", error);
+                return;
+            }
+        };
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    let contract_output = rpc.get_tx_out(&contract_outpoint.txid, contract_outpoint.vout, None);
+    let contract_output = match contract_output {
+        Ok(Some(output)) => output,
+        Ok(None) => {
+            tracing::error!(target: "main", "contract output not found, it doesnt exist \
+                or is already spent");
+            return;
+        }
+        Err(error) => {
+            tracing::error!(target: "main", "error reading contract output: {:?}", error);
+            return;
+        }
+    };
+    let contract_spk = Script::from(contract_output.script_pub_key.hex);
+    if contract_spk != redeemscript_to_scriptpubkey(&redeemscript) {
+        tracing::error!(target: "main", "contract output doesnt pay to the redeemscript");
+        return;
+    }
+    if contract_output.confirmations < required_confirmations {
+        tracing::error!(target: "main", "contract output has {} confirmations, the branch can \
+            spend it after {} more blocks", contract_output.confirmations,
+            required_confirmations - contract_output.confirmations);
+        return;
+    }
+
+    //the wallet is only needed for a key or an address not given
+    let wallet = if wif.is_none() || destination.is_none() {
+        let mut wallet = match Wallet::load_wallet_from_file(
+            wallet_file_name,
+            network,
+            WalletSyncAddressAmount::Normal,
+        ) {
+            Ok(w) => w,
+            Err(error) => {
+                tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+                return;
+            }
+        };
+        wallet.startup_sync(&rpc).unwrap();
+        Some(wallet)
+    } else {
+        None
+    };
+    let privkey = match wif {
+        Some(wif) => wif.key,
+        None => {
+            let hashlock = matches!(branch, contract_spend::ContractBranch::Hashlock(_));
+            match wallet
+                .as_ref()
+                .unwrap()
+                .find_contract_branch_privkey(&redeemscript, hashlock)
+            {
+                Some(privkey) => privkey,
+                None => {
+                    tracing::error!(target: "main", "wallet doesnt have the key of the \
+                        contract's branch, give it with --wif");
+                    return;
+                }
+            }
+        }
+    };
+    let destination = match destination {
+        Some(destination) => destination,
+        None => {
+            let provider = recovery_destinations::DestinationProvider::from_settings();
+            match provider.next_addresses(wallet.as_ref().unwrap(), &rpc, 1) {
+                Ok(mut addresses) => addresses.remove(0),
+                Err(error) => {
+                    tracing::error!(target: "main", "error getting address: {:?}", error);
+                    return;
+                }
+            }
+        }
+    };
+
+    let tx = match contract_spend::create_contract_spend(
+        contract_outpoint,
+        contract_output.value.as_sat(),
+        &redeemscript,
+        &branch,
+        &privkey,
+        &destination,
+        fee_rate,
+    ) {
+        Ok(tx) => tx,
+        Err(error) => {
+            tracing::error!(target: "main", "unable to spend contract: {:?}", error);
+            return;
+        }
+    };
+    if dont_broadcast {
+        println!(
+            "contract spend (txid = {}) = \n{}",
+            tx.txid(),
+            bitcoin::consensus::encode::serialize_hex(&tx)
+        );
+    } else {
+        match rpc.send_raw_transaction(&tx) {
+            Ok(txid) => println!("broadcasted {}", txid),
+            Err(error) => {
+                tracing::error!(target: "main", "error broadcasting contract spend: {:?}", error)
+            }
+        }
+    }
+}
+
+pub fn recover_from_incomplete_coinswap(
+    wallet_file_name: &PathBuf,
+    hashvalue: Hash160,
+    dont_broadcast: bool,
+) {
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    let mut wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    wallet.startup_sync(&rpc).unwrap();
+
+    let incomplete_coinswaps = wallet.find_incomplete_coinswaps(&rpc).unwrap();
+    let incomplete_coinswap = incomplete_coinswaps.get(&hashvalue);
+    if incomplete_coinswap.is_none() {
+        tracing::error!(target: "main", "hashvalue not refering to incomplete coinswap, run \
+                `wallet-balance` to see list of incomplete coinswaps");
+        return;
+    }
+    let incomplete_coinswap = incomplete_coinswap.unwrap();
+    for (ii, swapcoin) in incomplete_coinswap
+        .0
+        .iter()
+        .map(|(l, i)| (l, (*i as &dyn WalletSwapCoin)))
+        .chain(
+            incomplete_coinswap
+                .1
+                .iter()
+                .map(|(l, o)| (l, (*o as &dyn WalletSwapCoin))),
+        )
+        .enumerate()
+    {
+        wallet
+            .import_wallet_contract_redeemscript(&rpc, &swapcoin.1.get_contract_redeemscript())
+            .unwrap();
+
+        let signed_contract_tx = swapcoin.1.get_fully_signed_contract_tx();
+        if dont_broadcast {
+            let txhex = bitcoin::consensus::encode::serialize_hex(&signed_contract_tx);
+            println!(
+                "contract_tx_{} (txid = {}) = \n{}",
+                ii,
+                signed_contract_tx.txid(),
+                txhex
+            );
+            let accepted = rpc
+                .test_mempool_accept(&[txhex.clone()])
+                .unwrap()
+                .iter()
+                .any(|tma| tma.allowed);
+            assert!(accepted);
+        } else {
+            let txid = rpc.send_raw_transaction(&signed_contract_tx).unwrap();
+            println!("broadcasted {}", txid);
+        }
+    }
+}
+
+/// Broadcast the contract tx of every live contract of the wallet, for when the other side of
+/// one or more coinswaps has disappeared. The wallet spends the contracts itself once their
+/// timelock expires or the hash preimage is known, and the watchtowers in teleport.conf are
+/// given the spends so they are broadcast even when the wallet isnt running
+#[tokio::main]
+pub async fn broadcast_all_contracts(wallet_file_name: &PathBuf, dont_broadcast: bool) {
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    let mut wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    wallet.startup_sync(&rpc).unwrap();
+
+    let export = match contract_export::ContractExport::from_wallet(&rpc, &wallet, network) {
+        Ok(e) => e,
+        Err(error) => {
+            tracing::error!(target: "main", "error finding live contracts: {:?}", error);
+            return;
+        }
+    };
+    if export.contracts.is_empty() {
+        println!("no live contracts");
+        return;
+    }
+    for contract in &export.contracts {
+        wallet
+            .import_wallet_contract_redeemscript(&rpc, &contract.redeemscript)
+            .unwrap();
+        if dont_broadcast {
+            println!(
+                "contract_tx (txid = {}) = \n{}",
+                contract.contract_txid, contract.contract_tx
+            );
+            continue;
+        }
+        //contracts found by their unspent outputs were already broadcast, which the node
+        // refuses again
+        match rpc.send_raw_transaction(contract.contract_tx.as_str()) {
+            Ok(txid) => println!("broadcasted {}", txid),
+            Err(error) => println!(
+                "contract tx {} not broadcast: {:?}",
+                contract.contract_txid, error
+            ),
+        }
+    }
+    if dont_broadcast {
+        return;
+    }
+
+    let contracts_infos = match export.to_contracts_infos() {
+        Ok(c) => c,
+        Err(error) => {
+            tracing::error!(target: "main", "invalid contracts: {:?}", error);
+            return;
+        }
+    };
+    for (contracts_info, preimage) in contracts_infos {
+        let registered =
+            match watchtower_client::register_coinswap_with_watchtowers(contracts_info).await {
+                Ok(()) => match preimage {
+                    Some(preimage) => {
+                        watchtower_client::register_hash_preimage_with_watchtowers(preimage).await
+                    }
+                    None => Ok(()),
+                },
+                Err(error) => Err(error),
+            };
+        if let Err(error) = registered {
+            println!(
+                "contracts not registered with watchtowers, keep the wallet running until they \
+                are spent. error={:?}",
+                error
+            );
+            return;
+        }
+    }
+    println!(
+        "registered {} contracts with watchtowers",
+        export.contracts.len()
+    );
+}
+
+/// Write descriptors with the private keys of the wallet's swapcoins as JSON to output_file or
+/// stdout, in the form `importdescriptors` takes
+pub fn export_swapcoin_descriptors(wallet_file_name: &PathBuf, output_file: Option<&Path>) {
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    let wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    let timestamp = match swapcoin_descriptors::wallet_birthday_timestamp(&rpc, &wallet) {
+        Ok(t) => t,
+        Err(error) => {
+            tracing::error!(target: "main", "error reading wallet birthday: {:?}", error);
+            return;
+        }
+    };
+    let imports = swapcoin_descriptors::swapcoin_descriptor_imports(&wallet, timestamp);
+    //the descriptors hold private keys, so the file is only readable by this user and an
+    // existing file, which may be readable by others, is never written to
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let result = match output_file {
+        Some(path) => options.open(path).map_err(Error::from).and_then(|file| {
+            Ok(serde_json::to_writer_pretty(file, &imports).map_err(io::Error::from)?)
+        }),
+        None => serde_json::to_writer_pretty(io::stdout(), &imports)
+            .map(|_| println!())
+            .map_err(|e| io::Error::from(e).into()),
+    };
+    match result {
+        Ok(()) => {
+            if let Some(path) = output_file {
+                println!(
+                    "exported {} swapcoins to {}, the file has private keys so keep it safe",
+                    imports.len(),
+                    path.display()
+                );
+            }
+        }
+        Err(error) => tracing::error!(target: "main", "error writing descriptors: {:?}", error),
+    }
+}
+
+/// Import the private keys of the wallet's swapcoins into the Core descriptor wallet
+/// core_wallet_name, creating it if needed, so Core alone can recover their coins
+pub fn import_swapcoin_descriptors(wallet_file_name: &PathBuf, core_wallet_name: &str) {
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    let wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    if core_wallet_name == Settings::global().blockchain.rpc_wallet_file {
+        tracing::error!(target: "main", "teleport's own Core wallet is watch-only, give another \
+            Core wallet to import the keys into");
+        return;
+    }
+    let timestamp = match swapcoin_descriptors::wallet_birthday_timestamp(&rpc, &wallet) {
+        Ok(t) => t,
+        Err(error) => {
+            tracing::error!(target: "main", "error reading wallet birthday: {:?}", error);
+            return;
+        }
+    };
+    let imports = swapcoin_descriptors::swapcoin_descriptor_imports(&wallet, timestamp);
+    if imports.is_empty() {
+        println!("wallet has no swapcoins");
+        return;
+    }
+
+    let blockchain_settings = &Settings::global().blockchain;
+    let recovery_rpc = match bitcoin_rpc_auth(blockchain_settings).and_then(|auth| {
+        Ok(Client::new(
+            blockchain_settings.rpc_wallet_url(core_wallet_name),
+            auth,
+        )?)
+    }) {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    if let Err(error) = load_or_create_core_wallet(&recovery_rpc, core_wallet_name, false) {
+        tracing::error!(target: "main", "error loading Core wallet {}: {:?}", core_wallet_name,
+            error);
+        return;
+    }
+    println!("Importing and rescanning, this can take a while. . .");
+    let results = match recovery_rpc.call::<Vec<Value>>("importdescriptors", &[json!(imports)]) {
+        Ok(results) => results,
+        Err(error) => {
+            tracing::error!(target: "main", "error importing descriptors: {:?}", error);
+            return;
+        }
+    };
+    let failed = results
+        .iter()
+        .filter(|r| r["success"].as_bool() != Some(true))
+        .collect::<Vec<&Value>>();
+    for result in &failed {
+        tracing::error!(target: "main", "failed import: {}", result["error"]);
+    }
+    println!(
+        "imported {} of {} swapcoins into Core wallet {}",
+        imports.len() - failed.len(),
+        imports.len(),
+        core_wallet_name
+    );
+}
+
+/// Write the wallet's live contracts as JSON to output_file or stdout, for a watchtower or
+/// other monitoring tool to watch
+pub fn export_contracts(wallet_file_name: &PathBuf, output_file: Option<&Path>) {
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    let mut wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    wallet.startup_sync(&rpc).unwrap();
+
+    let export = match contract_export::ContractExport::from_wallet(&rpc, &wallet, network) {
+        Ok(e) => e,
+        Err(error) => {
+            tracing::error!(target: "main", "error finding live contracts: {:?}", error);
+            return;
+        }
+    };
+    let result = match output_file {
+        Some(path) => fs::File::create(path)
+            .map_err(Error::from)
+            .and_then(|file| export.write(file)),
+        None => export.write(io::stdout()).map(|_| println!()),
+    };
+    match result {
+        Ok(()) => {
+            if let Some(path) = output_file {
+                println!(
+                    "exported {} contracts to {}",
+                    export.contracts.len(),
+                    path.display()
+                );
+            }
+        }
+        Err(error) => tracing::error!(target: "main", "error writing contracts: {:?}", error),
+    }
+}
+
+/// Print the timelock spends of the contracts in contracts_file whose timelock key is a child
+/// of the cold xprv read from stdin, paying to destination
+pub fn sign_cold_timelock_spends(contracts_file: &Path, destination: &Address) {
+    let export = match contract_export::ContractExport::load(contracts_file) {
+        Ok(e) => e,
+        Err(error) => {
+            tracing::error!(target: "main", "invalid contracts file: {:?}", error);
+            return;
+        }
+    };
+    println!("input cold xprv: ");
+    let mut xprv = String::new();
+    if let Err(error) = io::stdin().read_line(&mut xprv) {
+        tracing::error!(target: "main", "error reading xprv: {:?}", error);
+        return;
+    }
+    let xprv = match xprv.trim().parse::<ExtendedPrivKey>() {
+        Ok(x) => x,
+        Err(error) => {
+            println!("invalid xprv: {:?}", error);
+            return;
+        }
+    };
+    let spends = match contract_export::sign_cold_timelock_spends(&export, &xprv, destination) {
+        Ok(s) => s,
+        Err(error) => {
+            tracing::error!(target: "main", "error signing timelock spends: {:?}", error);
+            return;
+        }
+    };
+    if spends.is_empty() {
+        println!("no contracts with cold timelock keys");
+        return;
+    }
+    for (contract_txid, spend) in spends {
+        println!(
+            "timelock spend of contract {} (txid = {}) = \n{}",
+            contract_txid,
+            spend.txid(),
+            bitcoin::consensus::encode::serialize_hex(&spend)
+        );
+    }
+    println!(
+        "the spends are valid once the contract transactions have their locktime in \
+        confirmations"
+    );
+}
+
+/// Register the contracts of a file written by export-contracts with the configured
+/// watchtowers
+#[tokio::main]
+pub async fn import_contracts(contracts_file: &Path) {
+    let contracts_infos = match contract_export::ContractExport::load(contracts_file)
+        .and_then(|export| export.to_contracts_infos())
+    {
+        Ok(c) => c,
+        Err(error) => {
+            tracing::error!(target: "main", "invalid contracts file: {:?}", error);
+            return;
+        }
+    };
+    for (contracts_info, preimage) in contracts_infos {
+        let contract_count = contracts_info.contract_txes.len();
+        if let Err(error) =
+            watchtower_client::register_coinswap_with_watchtowers(contracts_info).await
+        {
+            tracing::error!(target: "main", "error registering contracts: {:?}", error);
+            return;
+        }
+        if let Some(preimage) = preimage {
+            if let Err(error) =
+                watchtower_client::register_hash_preimage_with_watchtowers(preimage).await
+            {
+                tracing::error!(target: "main", "error sending hash preimage: {:?}", error);
+                return;
+            }
+        }
+        println!("registered coinswap of {} contracts", contract_count);
+    }
+}
+
+#[tokio::main]
+pub async fn download_and_display_offers(
+    network_str: Option<String>,
+    maker_address: Option<String>,
+) {
+    //without a node the fidelity bonds cant be checked, their values are left out
+    let rpc = get_bitcoin_rpc();
+    let (maker_addresses, network) = if let Some(maker_addr) = maker_address {
+        let maker_addresses = vec![MakerAddress::from_published(maker_addr)];
+        (maker_addresses, None)
+    } else {
+        let network = match &rpc {
+            Ok((_rpc, network)) => *network,
+            Err(error) => {
+                if let Some(net_str) = network_str {
+                    str_to_bitcoin_network(net_str.as_str())
+                } else {
+                    panic!(
+                        "network string not provided, and error connecting to bitcoin node: {:?}",
+                        error
+                    );
+                }
+            }
+        };
+        let maker_addresses = get_advertised_maker_addresses(network)
+            .await
+            .expect("unable to sync maker addresses from directory servers");
+        (maker_addresses, Some(network))
+    };
+    println!(
+        "{:<3} {:<70} {:<12} {:<12} {:<12} {:<12} {:<12} {:<12} {:<12} {:<19}",
+        "n",
+        "maker address",
+        "max size",
+        "min size",
+        "abs fee",
+        "amt rel fee",
+        "time rel fee",
+        "minlocktime",
+        "uptime",
+        "fidelity bond value",
+    );
+    //the uptime from earlier runs, this sync is only counted next time
+    let maker_uptime = maker_uptime::load_maker_uptime();
+
+    //offers are shown as they arrive, the unreachable makers once all others responded
+    let mut offers_reader =
+        stream_offerbook_with_addresses(maker_addresses.clone(), &Settings::global().taker);
+    let mut reachable = HashSet::new();
+    let mut offers_addresses = Vec::new();
+    while let Some(offer_address) = offers_reader.recv().await {
+        let address_str = offer_address.address.to_string();
+        let ii = maker_addresses
+            .iter()
+            .position(|a| a.to_string() == address_str)
+            .unwrap();
+        let o = &offer_address.offer;
+        let stats = maker_uptime.stats(&address_str);
+        let uptime = match stats.uptime_score() {
+            Some(score) => format!(
+                "{:.0}% of {}",
+                score * 100.0,
+                stats.successes + stats.failures
+            ),
+            None => "-".to_string(),
+        };
+        let fidelity_bond_value = rpc
+            .as_ref()
+            .ok()
+            .and_then(|(rpc, _network)| offer_address.fidelity_bond_value(rpc))
+            .map_or("-".to_string(), |value| format!("{:.0}", value));
+        println!(
+            "{:<3} {:<70} {:<12} {:<12} {:<12} {:<12} {:<12} {:<12} {:<12} {:<19}",
+            ii,
+            address_str,
+            o.max_size,
+            o.min_size,
+            o.absolute_fee_sat,
+            o.amount_relative_fee_ppb,
+            o.time_relative_fee_ppb,
+            o.minimum_locktime,
+            uptime,
+            fidelity_bond_value,
+        );
+        reachable.insert(ii);
+        offers_addresses.push(offer_address);
+    }
+    for (ii, address) in maker_addresses.iter().enumerate() {
+        if !reachable.contains(&ii) {
+            println!("{:<3} {:<70} UNREACHABLE", ii, address);
+        }
+    }
+    //only a full sync replaces the cache used by `teleport fees`
+    if let Some(network) = network {
+        if let Err(error) = save_offerbook_cache(&offerbook_cache_path(network), &offers_addresses)
+        {
+            tracing::warn!(target: "main", "unable to save offerbook cache: {:?}", error);
+        }
+    }
+}
+
+/// Print what every maker would charge for a coinswap of amount, and the cheapest
+/// routes through 2 and 3 makers. Uses the offers from the last sync unless refresh is set
+#[tokio::main]
+pub async fn display_offer_fees(amount: u64, locktime: u64, refresh: bool, json: bool) {
+    let network = str_to_bitcoin_network(&Settings::global().blockchain.network);
+    let cache = if refresh {
+        None
+    } else {
+        load_offerbook_cache(&offerbook_cache_path(network)).ok()
+    };
+    let offers_addresses = match cache {
+        Some(cache) => {
+            let synced = chrono::DateTime::from_timestamp(cache.synced_timestamp, 0)
+                .map(|d| d.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            if !json {
+                println!("offers synced at {}, use --refresh to sync again", synced);
+                let now = chrono::Utc::now().timestamp();
+                let expired_count = cache
+                    .offers
+                    .iter()
+                    .filter(|o| o.offer.is_expired(now))
+                    .count();
+                if expired_count > 0 {
+                    println!(
+                        "{} of the offers have expired, fees may be out of date",
+                        expired_count
+                    );
+                }
+            }
+            cache.offers
+        }
+        None => match sync_offerbook(network).await {
+            Ok(offers_addresses) => offers_addresses,
+            Err(error) => {
+                tracing::error!(target: "main", "unable to sync offerbook: {:?}", error);
+                return;
+            }
+        },
+    };
+
+    let maker_fees = offer_fees::maker_fees(&offers_addresses, amount, locktime);
+    let routes = [2, 3]
+        .iter()
+        .map(|maker_count| {
+            let mut routes =
+                offer_fees::route_fees(&offers_addresses, amount, locktime, *maker_count);
+            routes.truncate(DISPLAYED_ROUTE_COUNT);
+            routes
+        })
+        .collect::<Vec<Vec<offer_fees::RouteFee>>>();
+    if json {
+        let output = serde_json::json!({
+            "amount": amount,
+            "locktime": locktime,
+            "makers": maker_fees,
+            "routes": routes.concat(),
+        });
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        return;
+    }
+
+    println!(
+        "\n{:<3} {:<70} {:>10} {:>8}",
+        "n", "maker address", "fee(sat)", "fee(%)"
+    );
+    for (ii, maker_fee) in maker_fees.iter().enumerate() {
+        println!(
+            "{:<3} {:<70} {:>10} {:>8.3}",
+            ii,
+            maker_fee.address,
+            maker_fee.fee_sat,
+            maker_fee.fee_sat as f64 * 100.0 / amount as f64
+        );
+    }
+    let unusable = offers_addresses.len() - maker_fees.len();
+    if unusable > 0 {
+        println!(
+            "{} makers dont accept a coinswap of {} sat",
+            unusable, amount
+        );
+    }
+    for routes in routes.iter().filter(|r| !r.is_empty()) {
+        println!(
+            "\ncheapest routes through {} makers\n{:>10} {:>8} route",
+            routes[0].addresses.len(),
+            "fee(sat)",
+            "fee(%)"
+        );
+        for route in routes {
+            println!(
+                "{:>10} {:>8.3} {}",
+                route.fee_sat,
+                route.fee_sat as f64 * 100.0 / amount as f64,
+                route.addresses.join(" -> ")
+            );
+        }
+    }
+}
+
+pub fn direct_send(
+    wallet_file_name: &PathBuf,
+    fee_rate: u64,
+    send_amount: SendAmount,
+    destination: Destination,
+    coins_to_spend: &[CoinToSpend],
+    dont_broadcast: bool,
+) {
+    if let Destination::Uri(uri) = &destination {
+        let network = str_to_bitcoin_network(&Settings::global().blockchain.network);
+        if let Err(error) = uri.check_payment(network, &send_amount) {
+            tracing::error!(target: "main", "{}", error);
+            return;
+        }
+    }
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    let mut wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    wallet.startup_sync(&rpc).unwrap();
+    let payjoin_destination = match &destination {
+        Destination::Uri(uri) => uri
+            .payjoin_endpoint
+            .clone()
+            .map(|endpoint| (uri.address.clone(), endpoint)),
+        _ => None,
+    };
+    let history_destination = match &destination {
+        Destination::Wallet => None,
+        Destination::Address(address) => Some((address.clone(), None)),
+        Destination::Uri(uri) => Some((uri.address.clone(), uri.label.clone())),
+    };
+    let tx = wallet
+        .create_direct_send(&rpc, fee_rate, send_amount, destination, coins_to_spend)
+        .unwrap();
+    //the original transaction is still a valid payment if the payjoin fails
+    //the receiver is never sent the signed original tx if nothing is to be broadcast
+    let tx = match payjoin_destination {
+        Some(_) if dont_broadcast => {
+            println!("not attempting payjoin as the tx will not be broadcast");
+            tx
+        }
+        Some((payee, endpoint)) => {
+            match payjoin::send_payjoin(&rpc, &wallet, &tx, &endpoint, &payee, fee_rate) {
+                Ok(payjoin_tx) => {
+                    let payjoin_txhex = bitcoin::consensus::encode::serialize_hex(&payjoin_tx);
+                    match rpc.test_mempool_accept(&[payjoin_txhex]) {
+                        Ok(results) if results[0].allowed => {
+                            println!("payjoin accepted by receiver");
+                            payjoin_tx
+                        }
+                        Ok(results) => {
+                            tracing::warn!(target: "main",
+                                "payjoin tx rejected by node, sending without: {:?}", results[0]);
+                            tx
+                        }
+                        Err(error) => {
+                            tracing::warn!(target: "main",
+                                "unable to test payjoin tx, sending without: {:?}", error);
+                            tx
+                        }
+                    }
+                }
+                Err(error) => {
+                    tracing::warn!(target: "main", "payjoin failed, sending without: {:?}", error);
+                    tx
+                }
+            }
+        }
+        None => tx,
+    };
+    let txhex = bitcoin::consensus::encode::serialize_hex(&tx);
+    tracing::debug!("fully signed tx hex = {}", txhex);
+    let test_mempool_accept_result = &rpc.test_mempool_accept(&[txhex.clone()]).unwrap()[0];
+    if !test_mempool_accept_result.allowed {
+        panic!(
+            "created invalid transaction, reason = {:#?}",
+            test_mempool_accept_result
+        );
+    }
+    let miner_fee_sat = test_mempool_accept_result
+        .fees
+        .as_ref()
+        .unwrap()
+        .base
+        .as_sat();
+    println!(
+        "actual fee rate = {:.3} sat/vb",
+        miner_fee_sat as f64 / test_mempool_accept_result.vsize.unwrap() as f64
+    );
+    if dont_broadcast {
+        println!("tx = \n{}", txhex);
+    } else {
+        let txid = rpc.send_raw_transaction(&tx).unwrap();
+        println!("broadcasted {}", txid);
+        if let Some((address, label)) = history_destination {
+            let amount_sat = tx
+                .output
+                .iter()
+                .find(|o| o.script_pubkey == address.script_pubkey())
+                .map(|o| o.value)
+                .unwrap_or(0);
+            let entry = SendHistoryEntry {
+                txid,
+                address: address.to_string(),
+                amount_sat,
+                label,
+                timestamp: chrono::Utc::now().timestamp(),
+                miner_fee_sat: Some(miner_fee_sat),
+            };
+            if let Err(error) = wallet.add_send_history_entry(entry) {
+                tracing::error!(target: "main", "error saving send history: {:?}", error);
+            }
+        }
+    }
+}
+
+pub fn fund_channel(
+    wallet_file_name: &PathBuf,
+    fee_rate: u64,
+    funding_address: &Address,
+    amount: u64,
+) {
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    if funding_address.network != network {
+        tracing::error!(target: "main", "channel funding address is for the wrong network");
+        return;
+    }
+    let mut wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    wallet.startup_sync(&rpc).unwrap();
+
+    let funding = match wallet.create_channel_funding(&rpc, funding_address, amount, fee_rate) {
+        Ok(f) => f,
+        Err(error) => {
+            tracing::error!(target: "main", "unable to create channel funding: {:?}", error);
+            return;
+        }
+    };
+    println!("channel funding txid = {}", funding.txid);
+    println!("= psbt for the node to verify (lnd) =");
+    println!("{}", funding.unsigned_psbt);
+    println!(
+        "= signed psbt for the node to finalize (lnd, or fundchannel_complete and sendpsbt) ="
+    );
+    println!("{}", funding.signed_psbt);
+    println!("the transaction isnt broadcast, the lightning node does that");
+}
+
+fn display_decoded_script(indent: &str, decoded: &contract_decoder::DecodedScript) {
+    match decoded {
+        contract_decoder::DecodedScript::Contract {
+            hashvalue,
+            hashlock_pubkey,
+            timelock_pubkey,
+            locktime,
+            address,
+        } => {
+            println!("{}contract", indent);
+            println!("{}  hashvalue       = {}", indent, hashvalue);
+            println!("{}  hashlock pubkey = {}", indent, hashlock_pubkey);
+            println!("{}  timelock pubkey = {}", indent, timelock_pubkey);
+            println!("{}  locktime        = {} blocks", indent, locktime);
+            println!("{}  address         = {}", indent, address);
+        }
+        contract_decoder::DecodedScript::Multisig { pubkeys, address } => {
+            println!("{}2-of-2 multisig", indent);
+            println!("{}  pubkeys         = {} {}", indent, pubkeys.0, pubkeys.1);
+            println!("{}  address         = {}", indent, address);
+        }
+    }
+}
+
+pub fn display_decoded_contract(hex: &str, network_str: Option<String>) {
+    let network = match (get_bitcoin_rpc(), network_str) {
+        (Ok((_rpc, network)), _) => network,
+        (Err(_), Some(net_str)) => str_to_bitcoin_network(net_str.as_str()),
+        (Err(error), None) => {
+            tracing::error!(target: "main", "network not given, and error connecting to bitcoin \
+                node: {:?}", error);
+            return;
+        }
+    };
+    match contract_decoder::decode_contract_hex(hex, network) {
+        Ok(contract_decoder::DecodedContract::Redeemscript(decoded)) => {
+            display_decoded_script("", &decoded)
+        }
+        Ok(contract_decoder::DecodedContract::Transaction {
+            txid,
+            inputs,
+            outputs,
+        }) => {
+            println!("txid = {}", txid);
+            for (index, input) in inputs.iter().enumerate() {
+                println!("input {} spending {}", index, input.previous_output);
+                match &input.redeemscript {
+                    Some(decoded) => display_decoded_script("  ", decoded),
+                    None => println!("  no contract or multisig redeemscript in witness"),
+                }
+            }
+            for (index, (value, address)) in outputs.iter().enumerate() {
+                println!(
+                    "output {} = {} to {}",
+                    index,
+                    Amount::from_sat(*value),
+                    address
+                        .as_ref()
+                        .map(|a| a.to_string())
+                        .unwrap_or_else(|| "nonstandard script".to_string())
+                );
+            }
+        }
+        Err(error) => {
+            tracing::error!(target: "main", "unable to decode contract: {:?}", error);
+        }
+    }
+}
+
+pub fn display_swap_receipt(receipt_file: &Path, audit_log: Option<&Path>) {
+    let receipt = match swap_receipt::verify_swap_receipt(receipt_file) {
+        Ok(r) => r,
+        Err(error) => {
+            tracing::error!(target: "main", "invalid swap receipt: {:?}", error);
+            return;
+        }
+    };
+    let contents = &receipt.contents;
+    if let Some(audit_log) = audit_log {
+        //signed with the same key as the receipt
+        if let Err(error) = audit_log::verify_audit_log(audit_log, &receipt.signing_pubkey) {
+            tracing::error!(target: "main", "invalid audit log: {:?}", error);
+            return;
+        }
+        let transcript_hashes = [contents.transcript_start_hash, contents.transcript_end_hash];
+        if transcript_hashes.iter().any(Option::is_none) {
+            tracing::error!(target: "main", "receipt has no transcript, the taker didnt keep \
+                an audit log");
+            return;
+        }
+        for hash in transcript_hashes.iter().flatten() {
+            match audit_log::has_entry_hash(audit_log, hash) {
+                Ok(true) => (),
+                Ok(false) => {
+                    tracing::error!(target: "main", "audit log doesnt have the transcript of \
+                        the coinswap");
+                    return;
+                }
+                Err(error) => {
+                    tracing::error!(target: "main", "error reading audit log: {:?}", error);
+                    return;
+                }
+            }
+        }
+    }
+    println!("swap id        = {}", contents.swap_id);
+    println!(
+        "completed      = {}",
+        chrono::DateTime::from_timestamp(contents.completed_timestamp, 0).unwrap()
+    );
+    println!(
+        "sent           = {}",
+        Amount::from_sat(contents.sent_amount_sat)
+    );
+    println!(
+        "received       = {}",
+        Amount::from_sat(contents.received_amount_sat)
+    );
+    println!(
+        "coinswap fee   = {}",
+        Amount::from_sat(contents.coinswap_fee_sat)
+    );
+    println!(
+        "miner fee      = {}",
+        Amount::from_sat(contents.miner_fee_sat)
+    );
+    for txid in &contents.funding_txids {
+        println!("funding tx     = {}", txid);
+    }
+    for txid in &contents.settlement_txids {
+        println!("settlement tx  = {}", txid);
+    }
+    for maker in &contents.makers {
+        println!(
+            "maker          = {} {}",
+            maker.address, maker.identity_pubkey
+        );
+    }
+    println!("signed by      = {}", receipt.signing_pubkey);
+    println!(
+        "receipt signature valid{}",
+        if audit_log.is_some() {
+            ", transcript found in the audit log"
+        } else {
+            ""
+        }
+    );
+}
+
+/// Print the block heights at which the contracts of the wallet's unfinished coinswaps can
+/// be spent by the hashlock and by the timelock
+pub fn display_swap_timeline(wallet_file_name: &PathBuf, json: bool) {
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    let mut wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    let timelines = match wallet
+        .startup_sync(&rpc)
+        .and_then(|_| swap_timeline::swap_timeline(&rpc, &mut wallet))
+    {
+        Ok(t) => t,
+        Err(error) => {
+            tracing::error!(target: "main", "error finding swapcoins: {:?}", error);
+            return;
+        }
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&timelines).unwrap());
+        return;
+    }
+    if timelines.is_empty() {
+        println!("no unfinished coinswaps");
+        return;
+    }
+    let height = |h: Option<u64>| h.map_or("unconfirmed".to_string(), |h| h.to_string());
+    println!(
+        "{:70} {:8} {:9} {:8} {:11} {:11} {:16} value",
+        "coin", "type", "contract", "locktime", "hashlock at", "timelock at", "timelock time"
+    );
+    for t in timelines {
+        let timelock_time = t
+            .timelock_timestamp
+            .and_then(|ts| chrono::DateTime::from_timestamp(ts, 0))
+            .map(|d| d.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or("-".to_string());
+        println!(
+            "{:70} {:8} {:9} {:<8} {:11} {:11} {:16} {}",
+            t.outpoint,
+            t.direction,
+            if t.contract_broadcast {
+                "broadcast"
+            } else {
+                "-"
+            },
+            t.locktime,
+            height(t.hashlock_height),
+            height(t.timelock_height),
+            timelock_time,
+            Amount::from_sat(t.amount_sat)
+        );
+    }
+}
+
+pub fn display_send_history(wallet_file_name: &PathBuf) {
+    //the history doesnt depend on the network so no need to connect to the node
+    let network = str_to_bitcoin_network(&Settings::global().blockchain.network);
+    let wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    let history = match wallet.get_send_history() {
+        Ok(h) => h,
+        Err(error) => {
+            tracing::error!(target: "main", "error reading send history: {:?}", error);
+            return;
+        }
+    };
+    println!(
+        "{:19} {:64} {:12} {:42} label",
+        "date", "txid", "amount(sat)", "address"
+    );
+    for entry in history {
+        let date = chrono::DateTime::from_timestamp(entry.timestamp, 0)
+            .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        println!(
+            "{:19} {:64} {:12} {:42} {}",
+            date,
+            entry.txid,
+            entry.amount_sat,
+            entry.address,
+            entry.label.unwrap_or_default()
+        );
+    }
+}
+
+pub fn display_fee_report(wallet_file_name: &PathBuf, csv: bool) {
+    let network = str_to_bitcoin_network(&Settings::global().blockchain.network);
+    let wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    let histories = wallet
+        .get_send_history()
+        .and_then(|send_history| Ok((send_history, wallet.get_swap_history()?)));
+    let (send_history, swap_history) = match histories {
+        Ok(h) => h,
+        Err(error) => {
+            tracing::error!(target: "main", "error reading history: {:?}", error);
+            return;
+        }
+    };
+    let receipts = swap_receipt::load_swap_receipts(
+        &teleport_data_dir().join(swap_receipt::SWAP_RECEIPTS_DIR_NAME),
+    );
+    let months = fee_report::fees_by_month(
+        &send_history,
+        &swap_history,
+        &receipts,
+        &wallet.get_tweakable_keypair().1,
+    );
+    let total = fee_report::total_fees(&months);
+    if csv {
+        println!("{}", fee_report::FEE_REPORT_CSV_HEADER);
+        for fees in months.iter().chain(Some(&total)) {
+            println!("{}", fees.to_csv_line());
+        }
+        return;
+    }
+    println!(
+        "{:8} {:>16} {:>22} {:>24}",
+        "month", "miner fees(sat)", "coinswap fees paid", "coinswap fees earned"
+    );
+    for fees in months.iter().chain(Some(&total)) {
+        println!(
+            "{:8} {:>16} {:>22} {:>24}",
+            fees.month,
+            fees.miner_fee_sat,
+            fees.coinswap_fee_paid_sat,
+            fees.coinswap_fee_earned_sat
+        );
+    }
+}
+
+pub fn display_coin_linkage(wallet_file_name: &PathBuf, coins: &[CoinToSpend]) {
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    let mut wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    wallet.startup_sync(&rpc).unwrap();
+
+    let utxos = match wallet.list_unspent_from_wallet(&rpc, false, false) {
+        Ok(utxos) => utxos,
+        Err(error) => {
+            tracing::error!(target: "main", "error listing wallet coins: {:?}", error);
+            return;
+        }
+    };
+    let outpoints = utxos
+        .iter()
+        .map(|(u, _)| OutPoint {
+            txid: u.txid,
+            vout: u.vout,
+        })
+        .collect::<Vec<OutPoint>>();
+    let txes = match wallet.find_linkage_txes(&rpc, &outpoints) {
+        Ok(txes) => txes,
+        Err(error) => {
+            tracing::error!(target: "main", "error reading wallet transactions: {:?}", error);
+            return;
+        }
+    };
+    let clusters = coin_linkage::cluster_coins(&outpoints, &txes);
+    for (index, cluster) in clusters.iter().enumerate() {
+        let cluster_utxos = utxos
+            .iter()
+            .zip(outpoints.iter())
+            .filter(|(_, outpoint)| cluster.contains(outpoint))
+            .collect::<Vec<_>>();
+        println!(
+            "= cluster {} = {} in {} coins",
+            index + 1,
+            cluster_utxos
+                .iter()
+                .fold(Amount::ZERO, |acc, ((u, _), _)| acc + u.amount),
+            cluster.len()
+        );
+        for ((utxo, spend_info), outpoint) in cluster_utxos {
+            println!(
+                "{} {:8} {}",
+                outpoint,
+                wallet_accounts::utxo_account(spend_info)
+                    .map(|a| a.to_string())
+                    .unwrap_or_default(),
+                utxo.amount
+            );
+        }
+    }
+
+    if coins.is_empty() {
+        if clusters.len() > 1 {
+            println!(
+                "coins of different clusters arent linked yet, spending them together in a \
+                coinswap or direct-send links them"
+            );
+        }
+        return;
+    }
+    let selected = outpoints
+        .iter()
+        .filter(|outpoint| coins.iter().any(|c| c.matches(outpoint)))
+        .cloned()
+        .collect::<Vec<OutPoint>>();
+    if selected.len() != coins.len() {
+        tracing::error!(target: "main", "some of the given coins arent in the wallet");
+        return;
+    }
+    let linked_clusters = coin_linkage::clusters_of_coins(&clusters, &selected);
+    if linked_clusters.len() > 1 {
+        println!(
+            "warning: spending these coins together links clusters {}",
+            linked_clusters
+                .iter()
+                .map(|c| (c + 1).to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    } else {
+        println!("these coins are already linked, spending them together links nothing new");
+    }
+}
+
+pub fn display_maker_stats(wallet_file_name: &PathBuf) {
+    let network = str_to_bitcoin_network(&Settings::global().blockchain.network);
+    let mut wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    let history = match wallet.get_swap_history() {
+        Ok(h) => h,
+        Err(error) => {
+            tracing::error!(target: "main", "error reading swap history: {:?}", error);
+            return;
+        }
+    };
+    let now = chrono::Utc::now().timestamp();
+    let total = maker_stats::total_earnings(&history);
+    println!(
+        "completed coinswaps = {}, not completed = {}",
+        total.swap_count,
+        history.len() as u64 - total.swap_count
+    );
+    println!("turnover = {}", Amount::from_sat(total.turnover_sat));
+    println!(
+        "average swap size = {}",
+        total
+            .average_swap_size_sat()
+            .map(|s| Amount::from_sat(s).to_string())
+            .unwrap_or("none".to_string())
+    );
+    println!(
+        "fee income = {}, miner fees paid = {}, earned = {} sat",
+        Amount::from_sat(total.fee_income_sat),
+        Amount::from_sat(total.miner_fee_sat),
+        total.earned_sat()
+    );
+
+    let print_periods = |name: &str, period_secs: i64, period_count: usize| {
+        println!(
+            "\n{:10} {:5} {:>14} {:>14} {:>14} {:>12}",
+            name, "swaps", "turnover(sat)", "fees(sat)", "miner fees", "earned(sat)"
+        );
+        let periods = maker_stats::earnings_by_period(&history, now, period_secs, period_count);
+        for (i, earnings) in periods.iter().enumerate() {
+            let start = now - (i as i64 + 1) * period_secs;
+            let date = chrono::DateTime::from_timestamp(start, 0)
+                .map(|d| d.format("%Y-%m-%d").to_string())
+                .unwrap_or_default();
+            println!(
+                "{:10} {:5} {:>14} {:>14} {:>14} {:>12}",
+                date,
+                earnings.swap_count,
+                earnings.turnover_sat,
+                earnings.fee_income_sat,
+                earnings.miner_fee_sat,
+                earnings.earned_sat()
+            );
+        }
+    };
+    print_periods("day", maker_stats::SECONDS_PER_DAY, 7);
+    print_periods("week", 7 * maker_stats::SECONDS_PER_DAY, 4);
+
+    //utilization needs the wallet balance
+    let balance = get_bitcoin_rpc().and_then(|(rpc, _network)| {
+        wallet.startup_sync(&rpc)?;
+        daemon::get_balance(&rpc, &mut wallet)
+    });
+    match balance {
+        Ok(balance) => println!(
+            "\nutilization = {}, daily turnover over the last 30 days as a share of the {} \
+                spendable balance",
+            maker_stats::utilization(&history, now, 30, balance.spendable_sat)
+                .map(|u| format!("{:.1}%", u * 100.0))
+                .unwrap_or("unknown".to_string()),
+            Amount::from_sat(balance.spendable_sat)
+        ),
+        Err(error) => println!(
+            "\nutilization unknown, unable to get the wallet balance: {:?}",
+            error
+        ),
+    }
+}
+
+/// Print the wallet's coins not worth spending at the current fee rate, and which small coins
+/// are worth merging into one while fees are low. fee_rate is used if the node has no fee
+/// estimate
+pub fn display_consolidation_suggestion(wallet_file_name: &PathBuf, fee_rate: u64) {
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    let mut wallet = match Wallet::load_wallet_from_file(
+        wallet_file_name,
+        network,
+        WalletSyncAddressAmount::Normal,
+    ) {
+        Ok(w) => w,
+        Err(error) => {
+            tracing::error!(target: "main", "error loading wallet file: {:?}", error);
+            return;
+        }
+    };
+    wallet.startup_sync(&rpc).unwrap();
+
+    let fee_rate = coin_viability::current_fee_rate(&rpc, fee_rate);
+    let coins_by_account = coin_viability::coin_sizes_by_account(
+        &wallet.list_unspent_from_wallet(&rpc, false, false).unwrap(),
+    );
+    println!("fee rate = {} sat/vbyte", fee_rate as f64 / 1000.0);
+    let suggestions =
+        coin_viability::suggest_account_consolidations(&coins_by_account, fee_rate, None);
+    if suggestions.is_empty() {
+        println!("no coins worth consolidating at this fee rate");
+        return;
+    }
+    //coins of different accounts are never spent together, so each is consolidated on its own
+    for (account, suggestion) in suggestions {
+        println!("\n== {} account ==", account);
+        if !suggestion.uneconomic_coins.is_empty() {
+            println!("= coins not worth spending =");
+            for coin in &suggestion.uneconomic_coins {
+                println!("{}", coin);
+            }
+        }
+        println!("= coins to consolidate =");
+        for coin in &suggestion.coins {
+            println!("{}", coin);
+        }
+        println!(
+            "consolidating {} coins of {} total costs {} now and saves {} when spending at {} \
+                sat/vbyte",
+            suggestion.coins.len(),
+            Amount::from_sat(suggestion.total_value),
+            Amount::from_sat(suggestion.consolidation_fee),
+            Amount::from_sat(suggestion.fee_saved_later),
+            suggestion.reference_fee_rate as f64 / 1000.0
+        );
+        //back into the same account, `wallet` is a receive address
+        let destination = match account {
+            wallet_accounts::WalletAccount::Receive => "wallet".to_string(),
+            wallet_accounts::WalletAccount::Swap | wallet_accounts::WalletAccount::Change => {
+                match wallet.get_next_internal_addresses(&rpc, 1) {
+                    Ok(mut addresses) => addresses.remove(0).to_string(),
+                    Err(error) => {
+                        tracing::error!(target: "main", "error getting change address: {:?}", error);
+                        continue;
+                    }
+                }
+            }
+        };
+        println!(
+            "consolidating links the coins together, to do it run\n\
+                direct-send --fee-rate {} max {} {}",
+            fee_rate,
+            destination,
+            suggestion
+                .coins
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<String>>()
+                .join(" ")
+        );
+    }
+}
+
+pub fn set_maker_paused(paused: bool) {
+    if let Err(error) = maker_protocol::set_maker_paused(paused) {
+        tracing::error!(target: "main", "error writing maker pause file: {:?}", error);
+        return;
+    }
+    if paused {
+        println!("maker paused, it will finish the coinswaps it already started");
+    } else {
+        println!("maker resumed");
+    }
+}
+
+pub fn run_watchtower(data_file_path: &PathBuf, kill_flag: Option<Arc<RwLock<bool>>>) {
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+
+    watchtower_protocol::start_watchtower(
+        &rpc,
+        data_file_path,
+        network,
+        kill_flag.unwrap_or(Arc::new(RwLock::new(false))),
+    );
+}
+
+pub fn run_directory_server(port: u16, kill_flag: Option<Arc<RwLock<bool>>>) {
+    let (rpc, network) = match get_bitcoin_rpc() {
+        Ok(rpc) => rpc,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+
+    directory_server::start_directory_server(
+        Arc::new(rpc),
+        network,
+        port,
+        kill_flag.unwrap_or(Arc::new(RwLock::new(false))),
+    );
+}
+
+#[tokio::main]
+pub async fn display_watchtower_status(address: &str) {
+    let status = match watchtower_client::get_watchtower_status(address).await {
+        Ok(s) => s,
+        Err(e) => {
+            println!("unable to get status of watchtower at {}: {:?}", address, e);
+            return;
+        }
+    };
+    println!(
+        "bitcoin node = {}",
+        if status.bitcoin_node_connected {
+            "connected"
+        } else {
+            "disconnected"
+        }
+    );
+    println!(
+        "block height = {}",
+        status
+            .block_height
+            .map(|h| h.to_string())
+            .unwrap_or("unknown".to_string())
+    );
+    println!(
+        "last scanned block height = {}",
+        status
+            .last_checked_block_height
+            .map(|h| h.to_string())
+            .unwrap_or("none".to_string())
+    );
+    println!("accepting clients = {}", status.accepting_clients);
+    println!(
+        "watching {} coinswaps, {} encrypted",
+        status.contracts.len(),
+        status.encrypted_contracts
+    );
+    for fidelity_bond in &status.fidelity_bonds {
+        println!(
+            "watching fidelity bond {} unlocking {}",
+            fidelity_bond.outpoint,
+            chrono::DateTime::from_timestamp(fidelity_bond.locktime, 0)
+                .map(|d| d.format("%Y-%m-%d").to_string())
+                .unwrap_or_default()
+        );
+    }
+    if status.contracts.is_empty() {
+        return;
+    }
+    println!(
+        "{:12} {:8} {:4} first contract txid",
+        "state", "locktime", "txes"
+    );
+    for contracts in status.contracts {
+        println!(
+            "{:12} {:8} {:4} {}",
+            contracts.state,
+            contracts
+                .locktime
+                .map(|l| l.to_string())
+                .unwrap_or("unknown".to_string()),
+            contracts.txids.len(),
+            contracts
+                .txids
+                .first()
+                .map(|txid| txid.to_string())
+                .unwrap_or_default()
+        );
+    }
+}
+
+pub fn generate_config(overwrite: bool) -> Result<(), Error> {
+    let path = teleport_data_dir().join(settings::CONFIG_FILE_NAME);
+    settings::write_default_config_file(&path, overwrite)?;
+    println!("wrote default config file to {}", path.display());
+    Ok(())
+}
+
+pub fn run_daemon(
+    wallet_file_name: &Path,
+    port: u16,
+    grpc_port: Option<u16>,
+    websocket_port: Option<u16>,
+    kill_flag: Option<Arc<RwLock<bool>>>,
+) {
+    let network = match get_bitcoin_rpc() {
+        Ok((_rpc, network)) => network,
+        Err(error) => {
+            tracing::error!(target: "main", "error connecting to bitcoin node: {:?}", error);
+            return;
+        }
+    };
+    daemon::start_daemon(
+        wallet_file_name,
+        network,
+        port,
+        grpc_port,
+        websocket_port,
+        kill_flag.unwrap_or(Arc::new(RwLock::new(false))),
+    );
+}
+
+pub fn display_settings() {
+    match toml::to_string(&Settings::global().redacted()) {
+        Ok(s) => print!("{}", s),
+        Err(e) => tracing::error!(target: "main", "unable to display settings: {:?}", e),
+    }
 }
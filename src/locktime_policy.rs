@@ -0,0 +1,134 @@
+//the locktimes of the contracts of each hop of a coinswap route
+//the taker's own contract has the longest locktime and each maker's outgoing contract is
+// one step shorter than its incoming one, so every maker has at least the react time to see
+// the hash preimage on the blockchain and claim its incoming coins before the next hop's
+// contract can be refunded
+//the base, step and react time are in the [taker] section of teleport.conf
+
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::settings::TakerSettings;
+
+//for showing how long a locktime is in wall-clock time
+pub const AVERAGE_BLOCK_INTERVAL_SECS: u64 = 600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocktimePolicy {
+    //locktime of the last hop, which pays back to the taker
+    pub base: u16,
+    //how much longer each hop's locktime is than the next one's
+    pub step: u16,
+    pub min_contract_react_time: u16,
+}
+
+//one hop of the recovery timeline shown to the user before the coinswap is funded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HopTimeline {
+    //0 is the taker's own funding, hop i+1 is funded by the i'th maker
+    pub hop: usize,
+    pub locktime: u16,
+    pub estimated_secs: u64,
+}
+
+impl LocktimePolicy {
+    pub fn from_settings(settings: &TakerSettings) -> LocktimePolicy {
+        LocktimePolicy {
+            base: settings.refund_locktime,
+            step: settings.refund_locktime_step,
+            min_contract_react_time: settings.min_contract_react_time,
+        }
+    }
+
+    /// Locktime of every hop of a route through maker_count makers, the first is the taker's
+    /// own contract and the last is the contract paying back to the taker
+    pub fn hop_locktimes(&self, maker_count: u16) -> Result<Vec<u16>, Error> {
+        let locktimes = (0..=maker_count)
+            .rev()
+            .map(|i| {
+                self.step
+                    .checked_mul(i)
+                    .and_then(|l| l.checked_add(self.base))
+            })
+            .collect::<Option<Vec<u16>>>()
+            .ok_or(Error::Protocol("locktime too long"))?;
+        validate_hop_locktimes(&locktimes, self.min_contract_react_time)?;
+        Ok(locktimes)
+    }
+
+    /// Locktime of the contract the taker funds
+    pub fn first_hop_locktime(&self, maker_count: u16) -> Result<u16, Error> {
+        Ok(self.hop_locktimes(maker_count)?[0])
+    }
+
+    /// Locktime of the contract the maker at maker_index funds
+    pub fn maker_refund_locktime(&self, maker_count: u16, maker_index: u16) -> Result<u16, Error> {
+        Ok(self.hop_locktimes(maker_count)?[maker_index as usize + 1])
+    }
+}
+
+/// Each locktime must be at least the react time shorter than the one of the hop before it
+pub fn validate_hop_locktimes(
+    locktimes: &[u16],
+    min_contract_react_time: u16,
+) -> Result<(), Error> {
+    if locktimes.first() == Some(&0) || locktimes.last() == Some(&0) {
+        return Err(Error::Protocol("locktime of zero"));
+    }
+    if locktimes
+        .windows(2)
+        .any(|w| w[0] < w[1] || w[0] - w[1] < min_contract_react_time)
+    {
+        return Err(Error::Protocol(
+            "locktimes dont decrease by the react time each hop",
+        ));
+    }
+    Ok(())
+}
+
+/// How long after its funding confirms each hop's coins can be refunded
+pub fn recovery_timeline(locktimes: &[u16]) -> Vec<HopTimeline> {
+    locktimes
+        .iter()
+        .enumerate()
+        .map(|(hop, &locktime)| HopTimeline {
+            hop,
+            locktime,
+            estimated_secs: locktime as u64 * AVERAGE_BLOCK_INTERVAL_SECS,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hop_locktimes() {
+        let policy = LocktimePolicy {
+            base: 48,
+            step: 48,
+            min_contract_react_time: 48,
+        };
+        assert_eq!(policy.hop_locktimes(2).unwrap(), vec![144, 96, 48]);
+        assert_eq!(policy.first_hop_locktime(3).unwrap(), 192);
+        assert_eq!(policy.maker_refund_locktime(3, 0).unwrap(), 144);
+        assert_eq!(policy.maker_refund_locktime(3, 2).unwrap(), 48);
+
+        let too_short_step = LocktimePolicy { step: 24, ..policy };
+        assert!(too_short_step.hop_locktimes(2).is_err());
+        let overflowing = LocktimePolicy {
+            step: 30_000,
+            ..policy
+        };
+        assert!(overflowing.hop_locktimes(3).is_err());
+
+        assert!(validate_hop_locktimes(&[144, 96, 48], 48).is_ok());
+        assert!(validate_hop_locktimes(&[144, 48, 96], 48).is_err());
+        assert!(validate_hop_locktimes(&[96, 48, 0], 48).is_err());
+
+        let timeline = recovery_timeline(&[96, 48]);
+        assert_eq!(timeline[1].hop, 1);
+        assert_eq!(timeline[1].estimated_secs, 48 * 600);
+    }
+}
@@ -1,25 +1,37 @@
 use bitcoin::consensus::encode::deserialize;
 use bitcoin::hashes::{hash160::Hash as Hash160, hex::FromHex};
-use bitcoin::{Script, Transaction};
+use bitcoin::{Address, OutPoint, PrivateKey, Script, Transaction};
 
+use std::net::Ipv4Addr;
 use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
-
+use teleport::daemon::DAEMON_PORT;
 use teleport::direct_send::{CoinToSpend, Destination, SendAmount};
+use teleport::directory_server::DIRECTORY_SERVER_PORT;
+use teleport::error::Error;
+use teleport::fan_out::{FanOutParams, SplitPolicy};
 use teleport::fidelity_bonds::YearAndMonth;
-use teleport::maker_protocol::MakerBehavior;
-use teleport::settings::Settings;
+use teleport::maker_protocol::{ListenAddress, MakerBehavior};
+use teleport::settings::{Settings, SettingsOverrides};
+use teleport::tumbler::TumbleParams;
 use teleport::utils::default_data_dir;
+use teleport::wallet_accounts::WalletAccount;
+use teleport::wallet_files::resolve_wallet_file_name;
 use teleport::wallet_sync::{DisplayAddressType, WalletSyncAddressAmount};
-use teleport::watchtower_protocol::{ContractTransaction, ContractsInfo};
+use teleport::watchtower_protocol::{ContractTransaction, ContractsInfo, WATCHTOWER_PORT};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "teleport", about = "A tool for CoinSwap")]
 struct ArgsWithWalletFile {
-    /// Wallet file
-    #[structopt(default_value = "wallet.teleport", parse(from_os_str), long)]
-    wallet_file_name: PathBuf,
+    /// Wallet file in the wallets directory of the datadir
+    #[structopt(parse(from_os_str), long, conflicts_with = "wallet")]
+    wallet_file_name: Option<PathBuf>,
+
+    /// Name of the wallet to use, defaults to default_wallet in teleport.conf or else
+    /// "wallet", see `teleport wallet list`
+    #[structopt(short, long)]
+    wallet: Option<String>,
 
     /// Dont broadcast transactions, only output their transaction hex string
     /// Only for commands which involve sending transactions e.g. recover-from-incomplete-coinswap
@@ -34,6 +46,9 @@ struct ArgsWithWalletFile {
     #[structopt(parse(from_os_str), long)]
     datadir: Option<PathBuf>,
 
+    #[structopt(flatten)]
+    settings_overrides: SettingsOverrides,
+
     /// Subcommand
     #[structopt(flatten)]
     subcommand: Subcommand,
@@ -45,6 +60,13 @@ enum Subcommand {
     /// Generates a new seed phrase and wallet file
     GenerateWallet,
 
+    /// Writes a default teleport.conf with every option commented out into the data directory
+    GenerateConfig {
+        /// Overwrite teleport.conf if it already exists
+        #[structopt(long)]
+        force: bool,
+    },
+
     /// Recovers a wallet file from an existing seed phrase
     RecoverWallet,
 
@@ -54,14 +76,22 @@ enum Subcommand {
         long_form: Option<bool>,
     },
 
+    /// Show the block heights at which the contracts of unfinished coinswaps can be spent
+    /// by the hashlock and by the timelock, with an estimate of when the timelock ends
+    SwapTimeline {
+        /// Print as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+
     /// Dumps all addresses in wallet file, only useful for debugging
     DisplayWalletAddresses {
         /// Address types: "all", "masterkey", "seed", "incomingswap", "outgoingswap",
         /// "swap", "incomingcontract", "outgoingcontract", "contract", "fidelitybond".
         /// Default is "all"
         types: Option<DisplayAddressType>,
-        /// Network in question, options are "main", "test", "signet", "regtest". Only used
-        /// if configured bitcoin node RPC is unreachable
+        /// Network in question, options are "main", "test", "testnet4", "signet", "regtest".
+        /// Only used if configured bitcoin node RPC is unreachable
         network: Option<String>,
     },
 
@@ -70,10 +100,15 @@ enum Subcommand {
 
     /// Runs yield generator aiming to produce an income
     RunYieldGenerator {
-        /// Port to listen on, default is 6102
+        /// Port to listen on localhost, default is maker.listen_addresses in teleport.conf
         port: Option<u16>,
-        /// Special behavior used for testing e.g. "closeonsignsenderscontracttx"
-        special_behavior: Option<String>,
+        /// Address to listen on instead, ip:port, unix:<path> or i2p, can be given more than once
+        #[structopt(long = "listen")]
+        listen_addresses: Vec<ListenAddress>,
+        /// Special behavior used for testing, one of "closeonsignsenderscontracttx",
+        /// "closeaftersendingsenderssigs", "broadcastcontractaftersetup",
+        /// "sendinvalidsignatures" or "stallatprivkeyhandover"
+        special_behavior: Option<MakerBehavior>,
     },
 
     /// Prints a fidelity bond timelocked address
@@ -90,6 +125,51 @@ enum Subcommand {
         maker_count: Option<u16>,
         /// How many transactions per hop, default 3
         tx_count: Option<u32>,
+        /// Address the coins made by the coinswap are sent on to, can be given more than
+        /// once. Each destination is sent to in its own transaction at a random time
+        #[structopt(long = "destination")]
+        destinations: Vec<Address>,
+        /// How the coins are split between the destinations, "even" or "random", default even
+        #[structopt(long)]
+        split: Option<SplitPolicy>,
+        /// Longest wait before sending to a destination in minutes, default 180
+        #[structopt(long)]
+        max_send_delay_mins: Option<u64>,
+        /// Account the coinswap is funded from, "receive", "swap" or "change", default the
+        /// one with the biggest balance
+        #[structopt(long)]
+        account: Option<WalletAccount>,
+    },
+
+    /// Carry on sending the coins of the last coinswap to its destinations, after teleport
+    /// was stopped while doing it
+    FanOut,
+
+    /// Run a series of coinswaps with random amounts and delays, each preferring makers
+    /// the earlier ones didnt use. The schedule is saved in the data directory, run again
+    /// without an amount to carry on with it after a restart
+    Tumble {
+        /// Total amount to coinswap (in sats), split randomly between the coinswaps
+        amount: Option<u64>,
+        /// How many coinswaps, default 4
+        #[structopt(long)]
+        coinswap_count: Option<usize>,
+        /// How many makers each coinswap routes through, default 2
+        #[structopt(long)]
+        maker_count: Option<u16>,
+        /// How many transactions per hop, default 3
+        #[structopt(long)]
+        tx_count: Option<u32>,
+        /// Shortest wait between coinswaps in minutes, default 30
+        #[structopt(long)]
+        min_delay_mins: Option<u64>,
+        /// Longest wait between coinswaps in minutes, default 240
+        #[structopt(long)]
+        max_delay_mins: Option<u64>,
+        /// Address the coins of one of the last coinswaps are sent to, can be given once per
+        /// coinswap. Coinswaps without one keep their coins in the wallet
+        #[structopt(long = "destination")]
+        destinations: Vec<Address>,
     },
 
     /// Broadcast contract transactions for incomplete coinswap. Locked up bitcoins are
@@ -99,38 +179,342 @@ enum Subcommand {
         hashvalue: Hash160,
     },
 
+    /// Spend a contract output by hand, by the hashlock branch if the preimage is given or
+    /// otherwise by the timelock branch once it has matured. For when automated recovery isnt
+    /// possible
+    SpendContract {
+        /// Contract output as "<txid>:vout"
+        contract_outpoint: OutPoint,
+        /// Contract redeemscript as hex
+        redeemscript: String,
+        /// Hash preimage as hex, to spend by the hashlock branch
+        #[structopt(long)]
+        preimage: Option<String>,
+        /// Key of the branch as WIF, if not given it is taken from the wallet's swapcoins
+        #[structopt(long)]
+        wif: Option<PrivateKey>,
+        /// Address to pay the coins to, default is a new address of the wallet or of
+        /// recovery_descriptor if set
+        #[structopt(long)]
+        destination: Option<Address>,
+    },
+
+    /// Write the wallet's live contracts as JSON, for a watchtower or other monitoring tool
+    ExportContracts {
+        /// File to write to, stdout if not given
+        #[structopt(parse(from_os_str))]
+        output_file: Option<PathBuf>,
+    },
+
+    /// Register the contracts of a file written by export-contracts with the watchtowers in
+    /// teleport.conf
+    ImportContracts {
+        #[structopt(parse(from_os_str))]
+        contracts_file: PathBuf,
+    },
+
+    /// Check the signature of a receipt written after a coinswap and print it
+    VerifySwapReceipt {
+        /// Receipt file, they are in the receipts directory of the data directory
+        #[structopt(parse(from_os_str))]
+        receipt_file: PathBuf,
+        /// Also check the audit log has the receipt's transcript and wasnt modified
+        #[structopt(parse(from_os_str), long)]
+        audit_log: Option<PathBuf>,
+    },
+
+    /// Decode a contract redeemscript, or a transaction spending or creating contracts, and
+    /// print its hashvalue, pubkeys, locktime and p2wsh address
+    DecodeContract {
+        /// Redeemscript or transaction as hex
+        hex: String,
+        /// Network of the printed addresses, options are "main", "test", "testnet4", "signet",
+        /// "regtest". Only used if the configured bitcoin node RPC is unreachable
+        network: Option<String>,
+    },
+
     /// Download all offers from all makers out there. If bitcoin node not configured then
     /// provide the network as an argument, can also optionally download from one given maker
     DownloadOffers {
-        /// Network in question, options are "main", "test", "signet". Only used if configured
-        /// bitcoin node RPC is unreachable
+        /// Network in question, options are "main", "test", "testnet4", "signet". Only used if
+        /// configured bitcoin node RPC is unreachable
         network: Option<String>,
         /// Optional single maker address to only download from. Useful if testing if your own
         /// maker is reachable
         maker_address: Option<String>,
     },
 
+    /// Compare what the makers would charge for a coinswap, each maker alone and the
+    /// cheapest routes through 2 and 3 makers. Uses the offers from the last download-offers
+    /// if there are any
+    Fees {
+        /// Coinswap amount (in sats)
+        amount: u64,
+        /// Locktime in blocks used for the time relative fee. Coinswaps currently only
+        /// pay the time relative fee for 1 block
+        #[structopt(long, default_value = "1")]
+        locktime: u64,
+        /// Download the offers again instead of using the ones from the last sync
+        #[structopt(long)]
+        refresh: bool,
+        /// Print as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+
     /// Send a transaction from the wallet
     DirectSend {
         /// Amount to send (in sats), or "max" for fully-spending with no change
         send_amount: SendAmount,
-        /// Address to send coins to, or "wallet" to send back to own wallet
+        /// Address to send coins to, "wallet" to send back to own wallet, or a bitcoin:
+        /// uri whose amount must match send_amount if it has one, the uri's label is
+        /// saved in the send history and it will be paid with a payjoin if it has a pj=
+        /// endpoint
         destination: Destination,
         /// Coins to spend as inputs, either in long form "<txid>:vout" or short
-        /// form "txid-prefix..txid-suffix:vout"
+        /// form "txid-prefix..txid-suffix:vout", all of the same account. If none are given
+        /// the biggest coins of the account with the biggest balance are used, leaving out
+        /// coins which cost more to spend than they're worth
         coins_to_spend: Vec<CoinToSpend>,
     },
 
+    /// Open a lightning channel with the coins received in coinswaps. Start the channel open
+    /// on the lightning node with `lncli openchannel --psbt` or `fundchannel_start`, then
+    /// give its funding address and amount here and pass the printed psbts back to the node
+    FundChannel {
+        /// Channel funding address given by the lightning node
+        funding_address: Address,
+        /// Channel capacity (in sats) given by the lightning node
+        amount: u64,
+    },
+
+    /// Show payments made with direct-send, with their labels from bitcoin: uris
+    SendHistory,
+
+    /// Miner fees and coinswap fees paid or earned by the wallet each month
+    FeeReport {
+        /// Print as CSV, e.g. to redirect into a file for a spreadsheet
+        #[structopt(long)]
+        csv: bool,
+    },
+
+    /// Show which of the wallet's coins chain analysis can already link to each other, and
+    /// whether spending the given coins together would link more of them
+    CoinLinkage {
+        /// Coins to check before spending them together, in the same forms as direct-send
+        coins: Vec<CoinToSpend>,
+    },
+
     /// Run watchtower
     RunWatchtower {
         /// File path used for the watchtower data file, default "watchtower.dat"
         data_file_path: Option<PathBuf>,
     },
 
+    /// Run a directory server, which lists the makers announcing themselves to it with a
+    /// fidelity bond, one maker per bond
+    RunDirectoryServer {
+        /// Port to listen on, default is 8080
+        port: Option<u16>,
+    },
+
+    /// Run teleportd, which exposes the wallet, offerbook and coinswaps over JSON-RPC
+    /// for GUIs and other programs
+    RunDaemon {
+        /// Port to listen on, default is 6104
+        port: Option<u16>,
+
+        /// Also serve the gRPC API on this port, needs the grpc feature
+        #[structopt(long)]
+        grpc_port: Option<u16>,
+
+        /// Also push events to websocket clients on this port
+        #[structopt(long)]
+        websocket_port: Option<u16>,
+    },
+
     /// Test watchtower client
     TestWatchtowerClient {
         contract_transactions_hex: Vec<String>,
     },
+
+    /// Inspect the configuration
+    Config(ConfigSubcommand),
+
+    /// Query a running watchtower
+    Watchtower(WatchtowerSubcommand),
+
+    /// Information for makers
+    Maker(MakerSubcommand),
+
+    /// Maintain the wallet
+    Wallet(WalletSubcommand),
+
+    /// Export and check fidelity bond proofs
+    Fidelity(FidelitySubcommand),
+
+    /// Get coins back from coinswaps which cant be completed
+    Recover(RecoverSubcommand),
+}
+
+#[derive(Debug, StructOpt)]
+enum ConfigSubcommand {
+    /// Prints the effective configuration after merging teleport.conf, environment
+    /// variables and command line flags
+    Show,
+}
+
+#[derive(Debug, StructOpt)]
+enum MakerSubcommand {
+    /// Prints the earnings per day and week, average swap size and utilization of the
+    /// wallet's liquidity, from the coinswaps done by the yield generator
+    Stats,
+
+    /// Prints the coins not worth spending at the current fee rate, and the small coins
+    /// worth consolidating so future funding transactions need fewer inputs
+    Consolidation,
+
+    /// Stops a running yield generator giving out offers and starting new coinswaps, while
+    /// it finishes the coinswaps already started. For maintenance without a restart
+    Pause,
+
+    /// Lets a paused yield generator give out offers and do coinswaps again
+    Resume,
+}
+
+#[derive(Debug, StructOpt)]
+enum WalletSubcommand {
+    /// Lists the wallets in the wallets directory of the datadir and which is the default
+    List,
+
+    /// Generates a new seed phrase and wallet, which becomes the default wallet if there
+    /// is none yet
+    Create { name: String },
+
+    /// Sets the wallet used when no --wallet is given, by writing default_wallet into
+    /// teleport.conf
+    Default { name: String },
+
+    /// Prints the wallet's seed phrase after asking for confirmation
+    ShowSeed {
+        /// Also asks for the seed phrase extension and says whether it is the wallet's
+        #[structopt(long)]
+        check_extension: bool,
+    },
+
+    /// Asks for a written down seed phrase and extension and checks they derive the same
+    /// first addresses as the wallet file
+    VerifySeed,
+
+    /// Writes an encrypted backup of the whole wallet file, with the seed phrase, the
+    /// swapcoins of unfinished coinswaps and their contracts, and the history
+    Backup {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+
+    /// Restores a backup written by `wallet backup` into a new wallet file
+    Restore {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+
+    /// Writes descriptors with the private keys of the wallet's swapcoins, as JSON which Core's
+    /// importdescriptors takes, so their coins can be recovered with Core alone
+    ExportSwapcoinKeys {
+        /// File to write to, which mustnt exist yet and is only readable by this user, stdout
+        /// if not given
+        #[structopt(parse(from_os_str))]
+        output_file: Option<PathBuf>,
+    },
+
+    /// Imports the private keys of the wallet's swapcoins into a Core descriptor wallet,
+    /// creating it if it doesnt exist, and rescans from the wallet's birthday for their coins
+    ImportSwapcoinKeys {
+        /// Core wallet to import into, it cant be teleport's own watch-only Core wallet
+        #[structopt(long, default_value = "teleport-recovery")]
+        core_wallet: String,
+    },
+
+    /// Imports the wallet's scripts into Core again, including those of swapcoins and
+    /// contracts, and rescans the blockchain for their transactions
+    Rescan {
+        /// Block height to rescan from, defaults to the height when the wallet was created
+        height: Option<u64>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum FidelitySubcommand {
+    /// Writes the proof of the maker's fidelity bond for the address it announces into a
+    /// JSON file, which anyone can check with verify-bond
+    ExportBond {
+        /// Onion or i2p address with port that the maker announces
+        maker_address: String,
+        #[structopt(parse(from_os_str))]
+        certificate_file: PathBuf,
+        /// Maker instance whose bond is exported, the n-th most valuable one
+        #[structopt(long, default_value = "0")]
+        instance: u32,
+    },
+
+    /// Shows what a fidelity bond would be worth, compared with splitting the coins into
+    /// several bonds
+    Plan {
+        /// Amount to lock (in sats)
+        amount: u64,
+        /// Months to lock the coins for
+        months: u32,
+        /// How many months the maker is expected to run, to warn if the bond unlocks before
+        /// or long after
+        #[structopt(long)]
+        operation_months: Option<u32>,
+    },
+
+    /// Checks a fidelity bond proof file written by export-bond, and if a bitcoin node is
+    /// available that the bond exists and its value
+    VerifyBond {
+        #[structopt(parse(from_os_str))]
+        certificate_file: PathBuf,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum RecoverSubcommand {
+    /// Broadcast the contract transactions of every live contract of the wallet and register
+    /// their spends with the watchtowers, for when coinswap counterparties have disappeared
+    BroadcastContracts,
+
+    /// Sign the timelock spends of the contracts in a file written by export-contracts whose
+    /// timelock key is a child of maker.cold_timelock_xpub. Asks for the cold xprv, needs no
+    /// bitcoin node so it can be run on the machine holding the cold key
+    SignColdTimelockSpends {
+        #[structopt(parse(from_os_str))]
+        contracts_file: PathBuf,
+        /// Address the coins are sent to
+        destination: Address,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum WatchtowerSubcommand {
+    /// Prints the contracts being watched, the last scanned block height and whether
+    /// the watchtower is connected to its bitcoin node
+    Status {
+        /// Address of the watchtower, defaults to the local one
+        #[structopt(long)]
+        address: Option<String>,
+    },
+}
+
+fn exit_on_config_error(result: Result<(), Error>) {
+    if let Err(Error::Config(problems)) = result {
+        eprintln!("invalid configuration:");
+        for problem in problems {
+            eprintln!("  {}", problem);
+        }
+        std::process::exit(1);
+    }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -139,82 +523,216 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Some(d) => d.clone(),
         None => default_data_dir("teleport"),
     };
-    Settings::init_settings(&datadir);
+    exit_on_config_error(Settings::init_settings(&datadir, &args.settings_overrides).map(|_| ()));
     teleport::setup_teleport();
+    let wallet_file_name = resolve_wallet_file_name(
+        args.wallet_file_name.clone(),
+        args.wallet.as_deref(),
+        Settings::global().default_wallet.as_deref(),
+    );
 
     match args.subcommand {
         Subcommand::GenerateWallet => {
-            teleport::generate_wallet(&args.wallet_file_name)?;
+            teleport::generate_wallet(&wallet_file_name)?;
+        }
+        Subcommand::GenerateConfig { force } => {
+            exit_on_config_error(teleport::generate_config(force));
         }
         Subcommand::RecoverWallet => {
-            teleport::recover_wallet(&args.wallet_file_name)?;
+            teleport::recover_wallet(&wallet_file_name)?;
         }
         Subcommand::WalletBalance { long_form } => {
-            teleport::display_wallet_balance(&args.wallet_file_name, long_form);
+            teleport::display_wallet_balance(&wallet_file_name, long_form);
+        }
+        Subcommand::SwapTimeline { json } => {
+            teleport::display_swap_timeline(&wallet_file_name, json);
         }
         Subcommand::DisplayWalletAddresses { types, network } => {
             teleport::display_wallet_addresses(
-                &args.wallet_file_name,
+                &wallet_file_name,
                 types.unwrap_or(DisplayAddressType::All),
                 network,
             );
         }
         Subcommand::GetReceiveInvoice => {
-            teleport::print_receive_invoice(&args.wallet_file_name);
+            teleport::print_receive_invoice(&wallet_file_name);
         }
         Subcommand::RunYieldGenerator {
             port,
+            listen_addresses,
             special_behavior,
         } => {
-            let maker_special_behavior = match special_behavior.unwrap_or(String::new()).as_str() {
-                "closeonsignsenderscontracttx" => MakerBehavior::CloseOnSignSendersContractTx,
-                _ => MakerBehavior::Normal,
+            let maker_special_behavior = special_behavior.unwrap_or(MakerBehavior::Normal);
+            let listen_addresses = if !listen_addresses.is_empty() {
+                listen_addresses
+            } else if let Some(port) = port {
+                vec![ListenAddress::Tcp((Ipv4Addr::LOCALHOST, port).into())]
+            } else {
+                //already checked when the settings were loaded
+                Settings::global()
+                    .maker
+                    .listen_addresses
+                    .iter()
+                    .map(|a| a.parse().unwrap())
+                    .collect()
             };
+            let listen_ports = listen_addresses
+                .iter()
+                .filter_map(|a| match a {
+                    ListenAddress::Tcp(addr) => Some(addr.port()),
+                    ListenAddress::Unix(_) | ListenAddress::I2p => None,
+                })
+                .collect::<Vec<u16>>();
+            exit_on_config_error(teleport::check_bitcoin_node_settings(&listen_ports));
             teleport::run_maker(
-                &args.wallet_file_name,
+                &wallet_file_name,
                 WalletSyncAddressAmount::Normal,
-                port.unwrap_or(6102),
+                listen_addresses,
                 maker_special_behavior,
                 None,
             );
         }
         Subcommand::GetFidelityBondAddress { year_and_month } => {
-            teleport::print_fidelity_bond_address(&args.wallet_file_name, &year_and_month);
+            teleport::print_fidelity_bond_address(&wallet_file_name, &year_and_month);
         }
         Subcommand::DoCoinswap {
             send_amount,
             maker_count,
             tx_count,
+            destinations,
+            split,
+            max_send_delay_mins,
+            account,
         } => {
-            teleport::run_taker(
-                &args.wallet_file_name,
-                WalletSyncAddressAmount::Normal,
-                args.fee_rate,
-                send_amount,
-                maker_count.unwrap_or(2),
-                tx_count.unwrap_or(3),
-            );
+            exit_on_config_error(teleport::check_bitcoin_node_settings(&[]));
+            if destinations.is_empty() {
+                teleport::run_taker(
+                    &wallet_file_name,
+                    WalletSyncAddressAmount::Normal,
+                    args.fee_rate,
+                    send_amount,
+                    maker_count.unwrap_or(2),
+                    tx_count.unwrap_or(3),
+                    account,
+                    None,
+                );
+            } else {
+                teleport::run_taker_with_fan_out(
+                    &wallet_file_name,
+                    args.fee_rate,
+                    send_amount,
+                    maker_count.unwrap_or(2),
+                    tx_count.unwrap_or(3),
+                    account,
+                    FanOutParams {
+                        destinations,
+                        split_policy: split.unwrap_or(SplitPolicy::Even),
+                        max_delay_secs: max_send_delay_mins.unwrap_or(180) * 60,
+                    },
+                );
+            }
+        }
+        Subcommand::FanOut => {
+            exit_on_config_error(teleport::check_bitcoin_node_settings(&[]));
+            teleport::resume_fan_out(&wallet_file_name, args.fee_rate);
+        }
+        Subcommand::Tumble {
+            amount,
+            coinswap_count,
+            maker_count,
+            tx_count,
+            min_delay_mins,
+            max_delay_mins,
+            destinations,
+        } => {
+            let params = amount.map(|amount| TumbleParams {
+                amount,
+                coinswap_count: coinswap_count.unwrap_or(4),
+                maker_count: maker_count.unwrap_or(2),
+                tx_count: tx_count.unwrap_or(3),
+                min_delay_secs: min_delay_mins.unwrap_or(30) * 60,
+                max_delay_secs: max_delay_mins.unwrap_or(240) * 60,
+                destinations,
+            });
+            if let Some(params) = &params {
+                let problem = if params.coinswap_count == 0 {
+                    Some("--coinswap-count must be more than 0")
+                } else if params.destinations.len() > params.coinswap_count {
+                    Some("more destinations than coinswaps")
+                } else if params.min_delay_secs > params.max_delay_secs {
+                    Some("--min-delay-mins is more than --max-delay-mins")
+                } else {
+                    None
+                };
+                if let Some(problem) = problem {
+                    eprintln!("{}", problem);
+                    std::process::exit(1);
+                }
+            }
+            exit_on_config_error(teleport::check_bitcoin_node_settings(&[]));
+            teleport::run_tumbler(&wallet_file_name, args.fee_rate, params);
         }
         Subcommand::RecoverFromIncompleteCoinswap { hashvalue } => {
             teleport::recover_from_incomplete_coinswap(
-                &args.wallet_file_name,
+                &wallet_file_name,
                 hashvalue,
                 args.dont_broadcast,
             );
         }
+        Subcommand::SpendContract {
+            contract_outpoint,
+            redeemscript,
+            preimage,
+            wif,
+            destination,
+        } => {
+            teleport::spend_contract(
+                &wallet_file_name,
+                args.fee_rate,
+                contract_outpoint,
+                &redeemscript,
+                preimage,
+                wif,
+                destination,
+                args.dont_broadcast,
+            );
+        }
+        Subcommand::ExportContracts { output_file } => {
+            teleport::export_contracts(&wallet_file_name, output_file.as_deref());
+        }
+        Subcommand::ImportContracts { contracts_file } => {
+            teleport::import_contracts(&contracts_file);
+        }
+        Subcommand::VerifySwapReceipt {
+            receipt_file,
+            audit_log,
+        } => {
+            teleport::display_swap_receipt(&receipt_file, audit_log.as_deref());
+        }
+        Subcommand::DecodeContract { hex, network } => {
+            teleport::display_decoded_contract(&hex, network);
+        }
         Subcommand::DownloadOffers {
             network,
             maker_address,
         } => {
             teleport::download_and_display_offers(network, maker_address);
         }
+        Subcommand::Fees {
+            amount,
+            locktime,
+            refresh,
+            json,
+        } => {
+            teleport::display_offer_fees(amount, locktime, refresh, json);
+        }
         Subcommand::DirectSend {
             send_amount,
             destination,
             coins_to_spend,
         } => {
             teleport::direct_send(
-                &args.wallet_file_name,
+                &wallet_file_name,
                 args.fee_rate,
                 send_amount,
                 destination,
@@ -222,12 +740,44 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 args.dont_broadcast,
             );
         }
+        Subcommand::FundChannel {
+            funding_address,
+            amount,
+        } => {
+            teleport::fund_channel(&wallet_file_name, args.fee_rate, &funding_address, amount);
+        }
+        Subcommand::SendHistory => {
+            teleport::display_send_history(&wallet_file_name);
+        }
+        Subcommand::FeeReport { csv } => {
+            teleport::display_fee_report(&wallet_file_name, csv);
+        }
+        Subcommand::CoinLinkage { coins } => {
+            teleport::display_coin_linkage(&wallet_file_name, &coins);
+        }
         Subcommand::RunWatchtower { data_file_path } => {
+            exit_on_config_error(teleport::check_bitcoin_node_settings(&[WATCHTOWER_PORT]));
             teleport::run_watchtower(
                 &data_file_path.unwrap_or(Path::new("watchtower.dat").to_path_buf()),
                 None,
             );
         }
+        Subcommand::RunDirectoryServer { port } => {
+            let port = port.unwrap_or(DIRECTORY_SERVER_PORT);
+            exit_on_config_error(teleport::check_bitcoin_node_settings(&[port]));
+            teleport::run_directory_server(port, None);
+        }
+        Subcommand::RunDaemon {
+            port,
+            grpc_port,
+            websocket_port,
+        } => {
+            let port = port.unwrap_or(DAEMON_PORT);
+            let listen_ports = [Some(port), grpc_port, websocket_port];
+            let listen_ports = listen_ports.iter().flatten().copied().collect::<Vec<u16>>();
+            exit_on_config_error(teleport::check_bitcoin_node_settings(&listen_ports));
+            teleport::run_daemon(&wallet_file_name, port, grpc_port, websocket_port, None);
+        }
         Subcommand::TestWatchtowerClient {
             mut contract_transactions_hex,
         } => {
@@ -252,6 +802,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     hashlock_spend_without_preimage: None,
                     timelock_spend: None,
                     timelock_spend_broadcasted: false,
+                    hashlock_spend_broadcasted: false,
                 })
                 .collect::<Vec<ContractTransaction>>();
             teleport::watchtower_client::test_watchtower_client(ContractsInfo {
@@ -259,6 +810,87 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 wallet_label: String::new(),
             });
         }
+        Subcommand::Config(ConfigSubcommand::Show) => {
+            teleport::display_settings();
+        }
+        Subcommand::Maker(MakerSubcommand::Stats) => {
+            teleport::display_maker_stats(&wallet_file_name);
+        }
+        Subcommand::Maker(MakerSubcommand::Consolidation) => {
+            teleport::display_consolidation_suggestion(&wallet_file_name, args.fee_rate);
+        }
+        Subcommand::Maker(MakerSubcommand::Pause) => {
+            teleport::set_maker_paused(true);
+        }
+        Subcommand::Maker(MakerSubcommand::Resume) => {
+            teleport::set_maker_paused(false);
+        }
+        Subcommand::Wallet(WalletSubcommand::List) => {
+            teleport::display_wallet_list();
+        }
+        Subcommand::Wallet(WalletSubcommand::Create { name }) => {
+            exit_on_config_error(teleport::create_wallet(&name));
+        }
+        Subcommand::Wallet(WalletSubcommand::Default { name }) => {
+            exit_on_config_error(teleport::set_default_wallet(&name));
+        }
+        Subcommand::Wallet(WalletSubcommand::ShowSeed { check_extension }) => {
+            teleport::show_seed(&wallet_file_name, check_extension)?;
+        }
+        Subcommand::Wallet(WalletSubcommand::VerifySeed) => {
+            teleport::verify_seed(&wallet_file_name)?;
+        }
+        Subcommand::Wallet(WalletSubcommand::Backup { path }) => {
+            teleport::backup_wallet(&wallet_file_name, &path)?;
+        }
+        Subcommand::Wallet(WalletSubcommand::Restore { path }) => {
+            teleport::restore_wallet(&wallet_file_name, &path)?;
+        }
+        Subcommand::Wallet(WalletSubcommand::ExportSwapcoinKeys { output_file }) => {
+            teleport::export_swapcoin_descriptors(&wallet_file_name, output_file.as_deref());
+        }
+        Subcommand::Wallet(WalletSubcommand::ImportSwapcoinKeys { core_wallet }) => {
+            teleport::import_swapcoin_descriptors(&wallet_file_name, &core_wallet);
+        }
+        Subcommand::Wallet(WalletSubcommand::Rescan { height }) => {
+            teleport::rescan_wallet(&wallet_file_name, height);
+        }
+        Subcommand::Fidelity(FidelitySubcommand::ExportBond {
+            maker_address,
+            certificate_file,
+            instance,
+        }) => {
+            teleport::export_fidelity_bond(
+                &wallet_file_name,
+                &maker_address,
+                instance,
+                &certificate_file,
+            );
+        }
+        Subcommand::Fidelity(FidelitySubcommand::Plan {
+            amount,
+            months,
+            operation_months,
+        }) => {
+            teleport::display_bond_plan(amount, months, operation_months);
+        }
+        Subcommand::Fidelity(FidelitySubcommand::VerifyBond { certificate_file }) => {
+            teleport::verify_fidelity_bond(&certificate_file);
+        }
+        Subcommand::Recover(RecoverSubcommand::BroadcastContracts) => {
+            teleport::broadcast_all_contracts(&wallet_file_name, args.dont_broadcast);
+        }
+        Subcommand::Recover(RecoverSubcommand::SignColdTimelockSpends {
+            contracts_file,
+            destination,
+        }) => {
+            teleport::sign_cold_timelock_spends(&contracts_file, &destination);
+        }
+        Subcommand::Watchtower(WatchtowerSubcommand::Status { address }) => {
+            teleport::display_watchtower_status(
+                &address.unwrap_or(format!("localhost:{}", WATCHTOWER_PORT)),
+            );
+        }
     }
 
     Ok(())
@@ -0,0 +1,120 @@
+//makers this taker caught cheating, kept in banned-makers.json in the data directory and
+// never put in a route again
+//for now a maker is only banned for taking more fees than its offer says
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::settings::Settings;
+use crate::utils::teleport_data_dir;
+
+static BANLIST_FILE_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MakerBan {
+    pub reason: String,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MakerBanlist {
+    pub makers: HashMap<String, MakerBan>,
+}
+
+impl MakerBanlist {
+    /// Read the banlist from path, a missing file means no maker was banned yet
+    pub fn load(path: &Path) -> Result<MakerBanlist, Error> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(file).map_err(io::Error::from)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(MakerBanlist::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        serde_json::to_writer(File::create(path)?, self).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    /// Ban the maker, a maker already banned keeps its first reason
+    pub fn ban(&mut self, address: &str, reason: &str, now: i64) {
+        self.makers
+            .entry(address.to_string())
+            .or_insert_with(|| MakerBan {
+                reason: reason.to_string(),
+                timestamp: now,
+            });
+    }
+
+    pub fn is_banned(&self, address: &str) -> bool {
+        self.makers.contains_key(address)
+    }
+}
+
+pub fn maker_banlist_path() -> PathBuf {
+    teleport_data_dir().join("banned-makers.json")
+}
+
+/// Load the banlist from the data directory, empty if the settings arent initialized or
+/// the file cant be read
+pub fn load_maker_banlist() -> MakerBanlist {
+    if !Settings::is_initialized() {
+        return MakerBanlist::default();
+    }
+    MakerBanlist::load(&maker_banlist_path()).unwrap_or_else(|e| {
+        tracing::warn!("unable to load maker banlist: {:?}", e);
+        MakerBanlist::default()
+    })
+}
+
+/// Add the maker to the banlist in the data directory, does nothing if the settings
+/// arent initialized
+pub fn ban_maker(address: &str, reason: &str) {
+    if !Settings::is_initialized() {
+        return;
+    }
+    tracing::warn!("banning maker {}, reason={}", address, reason);
+    let _lock = BANLIST_FILE_LOCK.lock().unwrap();
+    let path = maker_banlist_path();
+    let result = MakerBanlist::load(&path).and_then(|mut banlist| {
+        banlist.ban(address, reason, chrono::Utc::now().timestamp());
+        banlist.save(&path)
+    });
+    if let Err(e) = result {
+        tracing::warn!("unable to ban maker: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_maker_banlist() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("banned-makers.json");
+        let mut banlist = MakerBanlist::load(&path).unwrap();
+        assert!(!banlist.is_banned("maker-a"));
+
+        banlist.ban("maker-a", "fee mismatch", 100);
+        banlist.ban("maker-a", "something else", 200);
+        banlist.save(&path).unwrap();
+
+        let banlist = MakerBanlist::load(&path).unwrap();
+        assert!(banlist.is_banned("maker-a"));
+        assert!(!banlist.is_banned("maker-b"));
+        assert_eq!(
+            banlist.makers["maker-a"],
+            MakerBan {
+                reason: "fee mismatch".to_string(),
+                timestamp: 100,
+            }
+        );
+    }
+}
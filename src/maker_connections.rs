@@ -0,0 +1,287 @@
+//a taker keeps one connection to each maker of a coinswap open for the whole coinswap and sends
+// every message of the coinswap over it, instead of connecting again for each step, which over
+// tor means waiting for a new circuit every time
+//a connection which dropped, e.g. the tor circuit was closed or the maker closed it while the
+// taker waited for confirmations, is replaced by a new one and the coinswap carries on from the
+// same step, as makers accept a new connection starting at any step
+//the first connection to a maker takes up the session the maker offers in its hello, and every
+// later connection carries on that session, see swap_sessions.rs
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bitcoin::PublicKey;
+use tokio::io::BufReader;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+use crate::audit_log::AuditLog;
+use crate::error::Error;
+use crate::messages::{MakerToTakerMessage, SessionId, TakerToMakerMessage};
+use crate::offerbook_sync::MakerAddress;
+use crate::taker_protocol::{
+    connect_to_maker, read_maker_hello, read_message, send_message, send_taker_hello,
+};
+
+struct MakerSession {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    session_id: SessionId,
+    //proven by the maker after its hello, recorded in the audit log with every message
+    maker_identity: Option<PublicKey>,
+    //a request was sent and its reply never read, e.g. because it timed out, so whatever the
+    // maker sends next cant be matched up with a request
+    awaiting_reply: bool,
+}
+
+impl MakerSession {
+    //carries on session_id if given, otherwise starts the session the maker offers
+    async fn connect(
+        maker_address: &MakerAddress,
+        audit_log: Option<&AuditLog>,
+        session_id: Option<SessionId>,
+    ) -> Result<MakerSession, Error> {
+        tracing::info!("Connecting to {}", maker_address);
+        let mut socket = TcpStream::connect(maker_address.get_tcpstream_address()).await?;
+        connect_to_maker(&mut socket, maker_address).await?;
+        let (reader, mut writer) = socket.into_split();
+        let mut reader = BufReader::new(reader);
+        let makerhello = read_maker_hello(&mut reader, maker_address, audit_log).await?;
+        let session_id = session_id
+            .or(makerhello.session_id)
+            .ok_or(Error::Protocol("maker gave no session id"))?;
+        let maker_identity = send_taker_hello(
+            &mut reader,
+            &mut writer,
+            maker_address,
+            audit_log,
+            &makerhello,
+            Some(session_id),
+        )
+        .await?;
+        Ok(MakerSession {
+            reader,
+            writer,
+            session_id,
+            maker_identity,
+            awaiting_reply: false,
+        })
+    }
+
+    async fn request(
+        &mut self,
+        maker_address: &MakerAddress,
+        audit_log: Option<&AuditLog>,
+        message: &TakerToMakerMessage,
+    ) -> Result<MakerToTakerMessage, Error> {
+        self.awaiting_reply = true;
+        send_message(
+            &mut self.writer,
+            maker_address,
+            audit_log,
+            self.maker_identity.as_ref(),
+            message,
+        )
+        .await?;
+        let reply = read_message(
+            &mut self.reader,
+            maker_address,
+            audit_log,
+            self.maker_identity.as_ref(),
+        )
+        .await?;
+        self.awaiting_reply = false;
+        Ok(reply)
+    }
+}
+
+fn is_connection_error(error: &Error) -> bool {
+    matches!(error, Error::Network(_) | Error::Disk(_) | Error::Socks(_))
+}
+
+/// The connections of a taker to the makers of one coinswap, closed when dropped
+#[derive(Default)]
+pub struct MakerConnections {
+    sessions: HashMap<String, MakerSession>,
+    //kept after a connection is closed, so the next one carries on the session
+    session_ids: HashMap<String, SessionId>,
+    //records every message of the coinswap, see audit_log.rs
+    audit_log: Option<Arc<AuditLog>>,
+}
+
+impl MakerConnections {
+    pub fn new(audit_log: Option<Arc<AuditLog>>) -> MakerConnections {
+        MakerConnections {
+            audit_log,
+            ..MakerConnections::default()
+        }
+    }
+
+    //the open connection to the maker or a new one, and whether it was already open
+    async fn session(
+        &mut self,
+        maker_address: &MakerAddress,
+    ) -> Result<(&mut MakerSession, bool), Error> {
+        let key = maker_address.to_string();
+        if self.sessions.get(&key).is_some_and(|s| s.awaiting_reply) {
+            self.sessions.remove(&key);
+        }
+        let reused = self.sessions.contains_key(&key);
+        if !reused {
+            let session = MakerSession::connect(
+                maker_address,
+                self.audit_log.as_deref(),
+                self.session_ids.get(&key).copied(),
+            )
+            .await?;
+            self.session_ids.insert(key.clone(), session.session_id);
+            self.sessions.insert(key.clone(), session);
+        }
+        Ok((self.sessions.get_mut(&key).unwrap(), reused))
+    }
+
+    /// The session of the coinswap with the maker, which every coinswap message sent to it
+    /// carries. Connects to the maker if this is the first message to it
+    pub async fn session_id(&mut self, maker_address: &MakerAddress) -> Result<SessionId, Error> {
+        match self.session_ids.get(&maker_address.to_string()) {
+            Some(session_id) => Ok(*session_id),
+            None => Ok(self.session(maker_address).await?.0.session_id),
+        }
+    }
+
+    /// Send the message to the maker and read its reply. If an already open connection turns
+    /// out to have dropped, the message is sent again once over a new connection
+    pub async fn request(
+        &mut self,
+        maker_address: &MakerAddress,
+        message: &TakerToMakerMessage,
+    ) -> Result<MakerToTakerMessage, Error> {
+        let audit_log = self.audit_log.clone();
+        let (session, reused) = self.session(maker_address).await?;
+        let result = match session
+            .request(maker_address, audit_log.as_deref(), message)
+            .await
+        {
+            Err(e) if reused && is_connection_error(&e) => {
+                tracing::debug!(
+                    "connection to {} dropped, reconnecting. error={:?}",
+                    maker_address,
+                    e
+                );
+                self.close(maker_address);
+                let (session, _) = self.session(maker_address).await?;
+                session
+                    .request(maker_address, audit_log.as_deref(), message)
+                    .await
+            }
+            result => result,
+        };
+        if result.is_err() {
+            //the maker may have given up on the connection after the error
+            self.close(maker_address);
+        }
+        result
+    }
+
+    /// Send a message which has no reply, over the connection the previous message to the maker
+    /// went over, as the maker only accepts it following that message
+    pub async fn send(
+        &mut self,
+        maker_address: &MakerAddress,
+        message: &TakerToMakerMessage,
+    ) -> Result<(), Error> {
+        let session = self
+            .sessions
+            .get_mut(&maker_address.to_string())
+            .ok_or(Error::Protocol("no connection to maker"))?;
+        let result = send_message(
+            &mut session.writer,
+            maker_address,
+            self.audit_log.as_deref(),
+            session.maker_identity.as_ref(),
+            message,
+        )
+        .await;
+        if result.is_err() {
+            self.close(maker_address);
+        }
+        result
+    }
+
+    pub fn close(&mut self, maker_address: &MakerAddress) {
+        self.sessions.remove(&maker_address.to_string());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::messages::{GiveOffer, MakerHello};
+
+    fn maker_hello_line(session_id: SessionId) -> Vec<u8> {
+        let mut line = serde_json::to_vec(&MakerToTakerMessage::MakerHello(MakerHello {
+            protocol_version_min: 0,
+            protocol_version_max: 0,
+            session_id: Some(session_id),
+            identity_pubkey: None,
+        }))
+        .unwrap();
+        line.push(b'\n');
+        line
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_dropped_connection() {
+        //a maker which answers two requests on its first connection and one on the next, then
+        // closes the connection
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let maker_address = MakerAddress::Clearnet {
+            address: listener.local_addr().unwrap().to_string(),
+        };
+        let maker = tokio::spawn(async move {
+            let mut taker_hellos = Vec::new();
+            for (request_count, session_byte) in [(2, 1), (1, 2)] {
+                let (socket, _) = listener.accept().await.unwrap();
+                let (reader, mut writer) = socket.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                let hello = maker_hello_line([session_byte; 16]);
+                writer.write_all(&hello).await.unwrap();
+                taker_hellos.push(lines.next_line().await.unwrap().unwrap());
+                //the requests are all answered with a maker hello
+                for _ in 0..request_count {
+                    lines.next_line().await.unwrap().unwrap();
+                    writer.write_all(&hello).await.unwrap();
+                }
+            }
+            taker_hellos
+        });
+
+        let mut connections = MakerConnections::new(None);
+        let request = TakerToMakerMessage::GiveOffer(GiveOffer);
+        for _ in 0..3 {
+            let reply = connections.request(&maker_address, &request).await;
+            assert!(matches!(reply, Ok(MakerToTakerMessage::MakerHello(_))));
+        }
+        //the second connection carries on the session offered on the first
+        let taker_hellos = maker.await.unwrap();
+        for taker_hello in taker_hellos {
+            match serde_json::from_str::<TakerToMakerMessage>(&taker_hello).unwrap() {
+                TakerToMakerMessage::TakerHello(hello) => {
+                    assert_eq!(hello.session_id, Some([1; 16]))
+                }
+                _ => panic!("expected a taker hello"),
+            }
+        }
+        assert_eq!(
+            connections.session_id(&maker_address).await.unwrap(),
+            [1; 16]
+        );
+        assert!(connections.request(&maker_address, &request).await.is_err());
+        assert!(connections.sessions.is_empty());
+        assert!(connections.send(&maker_address, &request).await.is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//before a maker advertises its offers it checks it could carry out a coinswap, that is the
+// bitcoin node is reachable and synced, no other program uses its Core wallet, the fidelity bond
+// is confirmed and unexpired, the onion service is reachable and there is disk space left for
+// the wallet and logs
+//a maker failing any check keeps checking and only advertises once they all pass, as a taker
+// who got an offer from it would otherwise waste time on a coinswap which cant complete
+
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::Duration;
+
+use tokio::time::timeout;
+use tokio_socks::tcp::Socks5Stream;
+
+use bitcoin::Network;
+use bitcoincore_rpc::RpcApi;
+
+use crate::directory_servers::TOR_ADDR;
+use crate::error::Error;
+use crate::maker_protocol::is_onion_address;
+use crate::rpc::Client;
+use crate::wallet_sync::Wallet;
+
+pub const HEALTH_CHECK_RETRY_INTERVAL_SECS: u64 = 60;
+const MIN_FREE_DISK_SPACE_BYTES: u64 = 100 * 1024 * 1024;
+const ONION_CONNECT_TIMEOUT_SECS: u64 = 120;
+
+fn fidelity_bond_problem(bond_locktime: Option<i64>, now: i64) -> Option<String> {
+    match bond_locktime {
+        None => Some(
+            "no confirmed fidelity bond, directory servers refuse makers without one".to_string(),
+        ),
+        Some(locktime) if locktime <= now => Some(format!(
+            "fidelity bond expired at {}, create a new one",
+            chrono::DateTime::from_timestamp(locktime, 0)
+                .map_or(locktime.to_string(), |t| t.to_string())
+        )),
+        Some(_) => None,
+    }
+}
+
+fn disk_space_problem(path: &Path, free_bytes: u64) -> Option<String> {
+    if free_bytes < MIN_FREE_DISK_SPACE_BYTES {
+        Some(format!(
+            "only {} MiB of disk space left at {}, need at least {} MiB",
+            free_bytes / 1024 / 1024,
+            path.display(),
+            MIN_FREE_DISK_SPACE_BYTES / 1024 / 1024
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(unix)]
+fn free_disk_space(path: &Path) -> Result<u64, Error> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(std::io::Error::from)?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let stat = unsafe { stat.assume_init() };
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn free_disk_space(_path: &Path) -> Result<u64, Error> {
+    Ok(u64::MAX)
+}
+
+async fn onion_service_problem(address: &str) -> Option<String> {
+    let connect = Socks5Stream::connect(TOR_ADDR, address.to_string());
+    match timeout(Duration::from_secs(ONION_CONNECT_TIMEOUT_SECS), connect).await {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => Some(format!("onion service {} unreachable: {}", address, e)),
+        Err(_) => Some(format!("onion service {} unreachable: timed out", address)),
+    }
+}
+
+/// Check the maker is able to take part in coinswaps, returning every failed check as
+/// Error::Config. public_addresses are the addresses the maker is about to advertise, instance
+/// which maker of the wallet it is and data_dir the directory of the wallet file and logs
+pub async fn check_maker_health(
+    rpc: &Client,
+    wallet: &RwLock<Wallet>,
+    public_addresses: &[String],
+    instance: u32,
+    data_dir: &Path,
+) -> Result<(), Error> {
+    let mut problems = Vec::new();
+
+    match rpc.get_blockchain_info() {
+        Ok(info) if info.initial_block_download => problems.push(format!(
+            "bitcoin node still in initial block download, {} of {} blocks",
+            info.blocks, info.headers
+        )),
+        Ok(_) => {}
+        Err(e) => problems.push(format!("unable to reach bitcoin node: {:?}", e)),
+    }
+
+    //another program using the same Core wallet could spend or lock the maker's coins
+    match wallet.read().unwrap().find_core_wallet_conflicts(rpc) {
+        Ok(conflicts) => problems.extend(conflicts),
+        Err(e) => problems.push(format!("unable to check the Core wallet: {:?}", e)),
+    }
+
+    //fidelity bonds and onion addresses only matter to directory servers, which regtest makers
+    // dont use
+    let network = wallet.read().unwrap().network;
+    if network != Network::Regtest {
+        let bond = wallet
+            .read()
+            .unwrap()
+            .find_fidelity_bond_by_rank(rpc, instance as usize);
+        match bond {
+            Ok(bond) => problems.extend(fidelity_bond_problem(
+                bond.map(|b| b.locktime()),
+                chrono::Utc::now().timestamp(),
+            )),
+            Err(e) => problems.push(format!("unable to find fidelity bond: {:?}", e)),
+        }
+        for address in public_addresses.iter().filter(|a| is_onion_address(a)) {
+            problems.extend(onion_service_problem(address).await);
+        }
+    }
+
+    match free_disk_space(data_dir) {
+        Ok(free_bytes) => problems.extend(disk_space_problem(data_dir, free_bytes)),
+        Err(e) => problems.push(format!(
+            "unable to read disk space at {}: {:?}",
+            data_dir.display(),
+            e
+        )),
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Config(problems))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_health_check_problems() {
+        let now = 1_700_000_000;
+        assert!(fidelity_bond_problem(None, now).is_some());
+        assert!(fidelity_bond_problem(Some(now - 1), now).is_some());
+        assert_eq!(fidelity_bond_problem(Some(now + 1), now), None);
+
+        let path = Path::new("/tmp");
+        assert_eq!(disk_space_problem(path, MIN_FREE_DISK_SPACE_BYTES), None);
+        assert!(disk_space_problem(path, 5 * 1024 * 1024)
+            .unwrap()
+            .starts_with("only 5 MiB"));
+        assert!(free_disk_space(path).unwrap() > 0);
+    }
+}
@@ -0,0 +1,357 @@
+//every maker has a long-term identity key, derived from its wallet, which its hello presents and
+// which signs the taker's challenge and the maker's offers
+//  challenge  the sha256d of "teleport-maker-identity|<hostname>|<hex nonce>", the nonce is
+//             chosen by the taker and the hostname is that of the address it reached the maker
+//             at, so a signature cant be replayed to another taker or by another maker
+//  offer      the sha256d of "teleport-offer|" followed by the offer's fees, limits and
+//             tweakable point, see offer_message
+//takers pin the key of each maker address the first time they see it, in known-maker-keys.json
+// in the data directory, and warn loudly if the address later presents another key, which
+// means the maker lost or replaced its wallet or someone else is answering at its address
+//older makers sign nothing and are never pinned, an address with a pinned key which presents
+// none is refused
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::{sha256d, Hash};
+use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+use bitcoin::util::ecdsa::PublicKey;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::gossip::onion_hostname;
+use crate::messages::{IdentityChallenge, IdentitySignature, Offer};
+use crate::settings::Settings;
+use crate::utils::teleport_data_dir;
+
+static KNOWN_KEYS_FILE_LOCK: Mutex<()> = Mutex::new(());
+
+fn message_hash(message: &str) -> Message {
+    Message::from_slice(&sha256d::Hash::hash(message.as_bytes())).unwrap()
+}
+
+fn challenge_message(challenge: &IdentityChallenge) -> String {
+    format!(
+        "teleport-maker-identity|{}|{}",
+        onion_hostname(&challenge.maker_address),
+        challenge.nonce.to_hex()
+    )
+}
+
+//everything in the offer apart from the fidelity bond proof, which is signed by the bond
+fn offer_message(offer: &Offer) -> String {
+    let tiers = offer
+        .required_confirms_tiers
+        .iter()
+        .map(|tier| {
+            format!(
+                "{}:{}:{}:{}",
+                tier.amount,
+                tier.required_confirms,
+                tier.maximum_locktime.unwrap_or(0),
+                tier.fee_multiplier_percent.unwrap_or(100)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(",");
+    format!(
+        "teleport-offer|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}",
+        offer.absolute_fee_sat,
+        offer.amount_relative_fee_ppb,
+        offer.time_relative_fee_ppb,
+        offer.required_confirms,
+        tiers,
+        offer.minimum_locktime,
+        offer.maximum_locktime.unwrap_or(0),
+        offer.minimum_contract_react_time.unwrap_or(0),
+        offer.max_size,
+        offer.min_size,
+        offer.tweakable_point,
+        offer.expires_at.unwrap_or(0)
+    )
+}
+
+fn sign(identity_privkey: &SecretKey, message: &str) -> IdentitySignature {
+    let secp = Secp256k1::new();
+    IdentitySignature {
+        pubkey: PublicKey {
+            compressed: true,
+            key: bitcoin::secp256k1::PublicKey::from_secret_key(&secp, identity_privkey),
+        },
+        sig: secp.sign(&message_hash(message), identity_privkey),
+    }
+}
+
+fn verify(identity: &IdentitySignature, message: &str) -> Result<(), Error> {
+    Secp256k1::verification_only()
+        .verify(&message_hash(message), &identity.sig, &identity.pubkey.key)
+        .map_err(|_| Error::Protocol("maker identity signature does not verify"))
+}
+
+pub fn identity_challenge(maker_address: &str) -> IdentityChallenge {
+    IdentityChallenge {
+        nonce: rand::thread_rng().gen(),
+        maker_address: maker_address.to_string(),
+    }
+}
+
+pub fn sign_identity_challenge(
+    identity_privkey: &SecretKey,
+    challenge: &IdentityChallenge,
+) -> IdentitySignature {
+    sign(identity_privkey, &challenge_message(challenge))
+}
+
+/// Check the maker's proof signs the challenge with the key its hello presented
+pub fn verify_identity_proof(
+    identity: &IdentitySignature,
+    identity_pubkey: &PublicKey,
+    challenge: &IdentityChallenge,
+) -> Result<(), Error> {
+    if identity.pubkey != *identity_pubkey {
+        return Err(Error::Protocol(
+            "maker identity proof is for another key than its hello",
+        ));
+    }
+    verify(identity, &challenge_message(challenge))
+}
+
+pub fn sign_offer(identity_privkey: &SecretKey, offer: &Offer) -> IdentitySignature {
+    sign(identity_privkey, &offer_message(offer))
+}
+
+/// Check the offer is signed by the key the maker proved in its hello on the same connection,
+/// or by none if the hello presented none. The key of an offer is never pinned on its own, as
+/// an offer can be copied from another maker
+pub fn verify_offer_identity(offer: &Offer, proven_pubkey: Option<PublicKey>) -> Result<(), Error> {
+    verify_offer(offer)?;
+    if offer.identity.as_ref().map(|identity| identity.pubkey) != proven_pubkey {
+        return Err(Error::Protocol(
+            "offer isnt signed by the identity key the maker proved in its hello",
+        ));
+    }
+    Ok(())
+}
+
+pub fn verify_offer(offer: &Offer) -> Result<(), Error> {
+    match &offer.identity {
+        Some(identity) => verify(identity, &offer_message(offer)),
+        None => Ok(()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinnedKey {
+    pub pubkey: PublicKey,
+    pub first_seen: i64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PinResult {
+    //first time the address was seen, its key is now pinned
+    New,
+    Known,
+    //the address presented another key than the one pinned
+    Changed { pinned: PublicKey },
+    //the address presented no key although one is pinned
+    Missing { pinned: PublicKey },
+    //the address presented no key and none is pinned, an older maker
+    Unidentified,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct KnownMakerKeys {
+    //by onion or i2p hostname, the port isnt part of the maker's identity
+    pub makers: HashMap<String, PinnedKey>,
+}
+
+impl KnownMakerKeys {
+    /// Read the pinned keys from path, a missing file means no maker was seen yet
+    pub fn load(path: &Path) -> Result<KnownMakerKeys, Error> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(file).map_err(io::Error::from)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(KnownMakerKeys::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        serde_json::to_writer(File::create(path)?, self).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    /// Pin the key of the maker address on first use, a changed key stays pinned to the old one
+    pub fn pin(&mut self, address: &str, pubkey: Option<PublicKey>, now: i64) -> PinResult {
+        match (self.makers.get(onion_hostname(address)), pubkey) {
+            (Some(pinned), Some(pubkey)) if pinned.pubkey == pubkey => PinResult::Known,
+            (Some(pinned), Some(_)) => PinResult::Changed {
+                pinned: pinned.pubkey,
+            },
+            (Some(pinned), None) => PinResult::Missing {
+                pinned: pinned.pubkey,
+            },
+            (None, None) => PinResult::Unidentified,
+            (None, Some(pubkey)) => {
+                self.makers.insert(
+                    onion_hostname(address).to_string(),
+                    PinnedKey {
+                        pubkey,
+                        first_seen: now,
+                    },
+                );
+                PinResult::New
+            }
+        }
+    }
+}
+
+pub fn known_maker_keys_path() -> PathBuf {
+    teleport_data_dir().join("known-maker-keys.json")
+}
+
+/// Pin the maker's identity key in the data directory or check it against the pinned one,
+/// warning if it changed or is missing. Does nothing if the settings arent initialized
+pub fn check_maker_identity(address: &str, pubkey: Option<PublicKey>) -> PinResult {
+    if !Settings::is_initialized() {
+        return PinResult::Unidentified;
+    }
+    let _lock = KNOWN_KEYS_FILE_LOCK.lock().unwrap();
+    let path = known_maker_keys_path();
+    let mut known_keys = KnownMakerKeys::load(&path).unwrap_or_else(|e| {
+        tracing::warn!("unable to load known maker keys: {:?}", e);
+        KnownMakerKeys::default()
+    });
+    let result = known_keys.pin(address, pubkey, chrono::Utc::now().timestamp());
+    match &result {
+        PinResult::New => {
+            if let Err(e) = known_keys.save(&path) {
+                tracing::warn!("unable to save known maker keys: {:?}", e);
+            }
+        }
+        PinResult::Known | PinResult::Unidentified => (),
+        PinResult::Missing { pinned } => tracing::warn!(
+            concat!(
+                "!!! MAKER IDENTITY KEY MISSING !!! maker {} presented no identity key but {} ",
+                "was pinned when it was first seen. Someone else may be answering at its ",
+                "address, so it isnt used. If the maker is known to have downgraded, remove it ",
+                "from {}"
+            ),
+            address,
+            pinned,
+            path.display()
+        ),
+        PinResult::Changed { pinned } => tracing::warn!(
+            concat!(
+                "!!! MAKER IDENTITY KEY CHANGED !!! maker {} presented identity key {} but {} ",
+                "was pinned when it was first seen. Either the maker replaced its wallet or ",
+                "someone else is answering at its address. If the maker is known to have ",
+                "changed its key, remove it from {}"
+            ),
+            address,
+            pubkey.expect("a changed key is given"),
+            pinned,
+            path.display()
+        ),
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::offerbook_sync::test_offer_and_address;
+
+    #[test]
+    fn test_maker_identity() {
+        let identity_privkey = SecretKey::from_slice(&[1; 32]).unwrap();
+        let challenge = identity_challenge("abcdefghijklmnop.onion:6102");
+        let hello = sign_identity_challenge(&identity_privkey, &challenge);
+        assert!(verify_identity_proof(&hello, &hello.pubkey, &challenge).is_ok());
+        //a proof cant be replayed to another taker, nor relayed by a maker at another address
+        assert!(verify_identity_proof(
+            &hello,
+            &hello.pubkey,
+            &identity_challenge("abcdefghijklmnop.onion:6102")
+        )
+        .is_err());
+        let relayed_challenge = IdentityChallenge {
+            maker_address: "qrstuvwxyzabcdef.onion:6102".to_string(),
+            ..challenge.clone()
+        };
+        assert!(verify_identity_proof(&hello, &hello.pubkey, &relayed_challenge).is_err());
+
+        let mut offer = Offer {
+            tweakable_point: hello.pubkey,
+            expires_at: Some(1000),
+            ..test_offer_and_address("abcdefghijklmnop.onion:6102", 10_000, 100_000_000).offer
+        };
+        assert!(verify_offer(&offer).is_ok());
+        offer.identity = Some(sign_offer(&identity_privkey, &offer));
+        assert!(verify_offer(&offer).is_ok());
+        assert!(verify_offer_identity(&offer, Some(hello.pubkey)).is_ok());
+        //an offer signed by another key than the hello's, e.g. copied from another maker
+        let other_privkey = SecretKey::from_slice(&[2; 32]).unwrap();
+        let copied_offer = Offer {
+            identity: Some(sign_offer(&other_privkey, &offer)),
+            ..offer.clone()
+        };
+        assert!(verify_offer(&copied_offer).is_ok());
+        assert!(verify_offer_identity(&copied_offer, Some(hello.pubkey)).is_err());
+        //a keyed offer after a hello which presented no key
+        assert!(verify_offer_identity(&offer, None).is_err());
+        //an unsigned offer after a keyed hello
+        let unsigned_offer = Offer {
+            identity: None,
+            ..offer.clone()
+        };
+        assert!(verify_offer_identity(&unsigned_offer, Some(hello.pubkey)).is_err());
+        assert!(verify_offer_identity(&unsigned_offer, None).is_ok());
+        offer.absolute_fee_sat += 1;
+        assert!(verify_offer(&offer).is_err());
+
+        let other_pubkey =
+            sign_identity_challenge(&SecretKey::from_slice(&[2; 32]).unwrap(), &challenge).pubkey;
+        assert!(verify_identity_proof(&hello, &other_pubkey, &challenge).is_err());
+        let mut known_keys = KnownMakerKeys::default();
+        let address = "abcdefghijklmnop.onion:6102";
+        assert_eq!(known_keys.pin(address, None, 50), PinResult::Unidentified);
+        assert_eq!(
+            known_keys.pin(address, Some(hello.pubkey), 100),
+            PinResult::New
+        );
+        //another port of the same onion address is the same maker
+        assert_eq!(
+            known_keys.pin("abcdefghijklmnop.onion:6103", Some(hello.pubkey), 200),
+            PinResult::Known
+        );
+        assert_eq!(
+            known_keys.pin(address, Some(other_pubkey), 300),
+            PinResult::Changed {
+                pinned: hello.pubkey
+            }
+        );
+        assert_eq!(
+            known_keys.pin(address, None, 350),
+            PinResult::Missing {
+                pinned: hello.pubkey
+            }
+        );
+        assert_eq!(known_keys.makers["abcdefghijklmnop.onion"].first_seen, 100);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("known-maker-keys.json");
+        known_keys.save(&path).unwrap();
+        let mut known_keys = KnownMakerKeys::load(&path).unwrap();
+        assert_eq!(
+            known_keys.pin(address, Some(hello.pubkey), 400),
+            PinResult::Known
+        );
+    }
+}
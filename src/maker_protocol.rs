@@ -1,101 +1,680 @@
 //put your onion address and port here
 const MAKER_ONION_ADDR: &str = "myhiddenserviceaddress.onion:6102";
-const ABSOLUTE_FEE_SAT: u64 = 1000;
-const AMOUNT_RELATIVE_FEE_PPB: u64 = 10_000_000;
-const TIME_RELATIVE_FEE_PPB: u64 = 100_000;
+//port of the onion services made when rotating onion addresses
+const ROTATED_ONION_PORT: u16 = 6102;
 const REQUIRED_CONFIRMS: i32 = 1;
 const MINIMUM_LOCKTIME: u16 = 48;
-const MIN_SIZE: u64 = 10000;
+//longest locktime the maker will lock its own coins in an outgoing contract for, the tiers in
+// maker.tiers can lower it for bigger coinswaps
+const MAXIMUM_LOCKTIME: u16 = 480;
+//blocks the maker needs between its incoming and outgoing contract locktimes to react
+const MINIMUM_CONTRACT_REACT_TIME: u16 = 48;
 
 //TODO this goes in the config file
 
-use std::net::{Ipv4Addr, SocketAddr};
-use std::sync::{Arc, RwLock};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
 use std::time::{Duration, Instant};
 
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::tcp::WriteHalf;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::TcpListener;
 use tokio::select;
 use tokio::sync::mpsc;
 use tokio::time::sleep;
 
-use bitcoin::hashes::{hash160::Hash as Hash160, Hash};
-use bitcoin::secp256k1::{SecretKey, Signature};
-use bitcoin::{Amount, Network, OutPoint, PublicKey, Transaction, TxOut, Txid};
-use bitcoincore_rpc::{Client, RpcApi};
+use tracing::Instrument;
 
-use itertools::izip;
+use bitcoin::hashes::{hash160::Hash as Hash160, Hash};
+use bitcoin::secp256k1::{Message, Secp256k1, SecretKey, Signature};
+use bitcoin::util::bip32::ExtendedPubKey;
+use bitcoin::{Amount, Network, OutPoint, PublicKey, Transaction, Txid};
+use bitcoincore_rpc::RpcApi;
 
+use crate::alerts::{send_alert, Alert};
+use crate::audit_log::{record_message, AuditLog, Direction};
+use crate::coin_viability::{coin_sizes_by_account, suggest_account_consolidations};
 use crate::contracts;
 use crate::contracts::SwapCoin;
 use crate::contracts::{
-    calculate_coinswap_fee, find_funding_output, read_hashvalue_from_contract,
+    apply_fee_multiplier, calculate_coinswap_fee, calculate_maximum_locktime,
+    calculate_required_confirms, find_funding_output, read_hashvalue_from_contract,
     read_locktime_from_contract, read_pubkeys_from_multisig_redeemscript,
-    MAKER_FUNDING_TX_VBYTE_SIZE,
+    verify_funding_tx_fee_rate, VerifiedFunding, MAKER_FUNDING_TX_VBYTE_SIZE,
+};
+use crate::daemon::find_contract_outputs;
+use crate::direct_send::{CoinToSpend, Destination, SendAmount};
+use crate::directory_servers::{
+    post_maker_address_to_directory_servers, sync_maker_addresses_from_directory_servers,
 };
-use crate::directory_servers::post_maker_address_to_directory_servers;
 use crate::error::Error;
+use crate::gossip::{
+    gossip_with_makers, onion_hostname, GossipBook, GOSSIP_PEER_COUNT, MAX_GOSSIP_ADDRESSES,
+};
+use crate::hooks::{spawn_hook, HookEvent};
+use crate::i2p::{i2p_destination_path, is_i2p_address, I2pListener};
+use crate::maker_health::{check_maker_health, HEALTH_CHECK_RETRY_INTERVAL_SECS};
+use crate::maker_identity::{sign_identity_challenge, sign_offer};
+use crate::maker_rebalance::{
+    rebalance_amount, rebalance_trigger, REBALANCE_MAKER_COUNT, REBALANCE_TX_COUNT,
+};
 use crate::messages::{
-    HashPreimage, MakerHello, MakerToTakerMessage, Offer, PrivateKeyHandover, ProofOfFunding,
-    ReceiversContractSig, SenderContractTxInfo, SendersAndReceiversContractSigs,
-    SendersContractSig, SignReceiversContractTx, SignSendersAndReceiversContractTxes,
-    SignSendersContractTx, SwapCoinPrivateKey, TakerToMakerMessage,
+    FidelityBondProof, HashPreimage, IdentityProof, MakerAddresses, MakerHello,
+    MakerToTakerMessage, Offer, PrivateKeyHandover, ProofOfFunding, ReceiversContractSig,
+    SenderContractTxInfo, SendersAndReceiversContractSigs, SendersContractSig, SessionId,
+    SignReceiversContractTx, SignSendersAndReceiversContractTxes, SignSendersContractTx,
+    SignedMakerAddress, SwapCoinPrivateKey, TakerHello, TakerToMakerMessage,
+};
+use crate::offerbook_sync::MakerAddress;
+use crate::protocol_phase::MakerPhase;
+use crate::recovery_destinations::DestinationProvider;
+use crate::rpc::Client;
+use crate::settings::{I2pSettings, MakerSettings, Settings, TorSettings};
+use crate::swap_sessions::{
+    accept_session, complete_session, generate_session_id, resume_session, start_session,
+};
+use crate::taker_blacklist::{Misbehavior, TakerBlacklist};
+use crate::taker_protocol::{start_taker_avoiding_makers, TakerConfig};
+use crate::tor_control::TorControl;
+use crate::utils::teleport_data_dir;
+use crate::wallet_accounts::WalletAccount;
+use crate::wallet_sync::{
+    IncomingSwapCoin, OutgoingSwapCoin, SwapHistoryEntry, Wallet, WalletSwapCoin,
+};
+use crate::watchtower_client::{
+    ping_watchtowers, register_coinswap_with_watchtowers, register_fidelity_bond_with_watchtowers,
+    register_hash_preimage_with_watchtowers,
 };
-use crate::wallet_sync::{IncomingSwapCoin, OutgoingSwapCoin, Wallet, WalletSwapCoin};
-use crate::watchtower_client::{ping_watchtowers, register_coinswap_with_watchtowers};
 use crate::watchtower_protocol::{ContractTransaction, ContractsInfo};
 
 const MAKER_HEARTBEAT_INTERVAL_SECS: u64 = 3;
+const MEMPOOL_FEE_RATE_REFRESH_INTERVAL_SECS: u64 = 600;
+//blocks within which our funding txes should confirm
+const MEMPOOL_FEE_RATE_CONF_TARGET: u16 = 2;
+//the fee may change when the mempool fee rate is next checked
+const OFFER_EXPIRY_SECS: u64 = MEMPOOL_FEE_RATE_REFRESH_INTERVAL_SECS;
+const GOSSIP_INTERVAL_SECS: u64 = 60 * 60;
+//while this file is in the data directory the maker gives out no offers and starts no new
+// coinswaps, but carries on with the ones already started
+pub const MAKER_PAUSE_FILE_NAME: &str = "maker.paused";
 
-//used to configure the maker do weird things for testing
-#[derive(Debug, Clone, Copy)]
+//used to configure the maker do weird things for testing, so integration tests can go
+// through every way the taker recovers from a misbehaving maker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MakerBehavior {
     Normal,
     CloseOnSignSendersContractTx,
+    //sends the signatures of the senders contract txes then closes the connection
+    CloseAfterSendingSendersSigs,
+    //broadcasts its incoming contract txes right after broadcasting its funding txes
+    BroadcastContractAfterSetup,
+    //signs the senders and receivers contract txes with signatures which dont verify
+    SendInvalidSignatures,
+    //never replies to the hash preimage with the private keys
+    StallAtPrivkeyHandover,
+}
+
+impl FromStr for MakerBehavior {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "normal" => MakerBehavior::Normal,
+            "closeonsignsenderscontracttx" => MakerBehavior::CloseOnSignSendersContractTx,
+            "closeaftersendingsenderssigs" => MakerBehavior::CloseAfterSendingSendersSigs,
+            "broadcastcontractaftersetup" => MakerBehavior::BroadcastContractAfterSetup,
+            "sendinvalidsignatures" => MakerBehavior::SendInvalidSignatures,
+            "stallatprivkeyhandover" => MakerBehavior::StallAtPrivkeyHandover,
+            _ => return Err(format!("unknown maker behavior `{}`", s)),
+        })
+    }
+}
+
+//where the maker accepts takers, e.g. 127.0.0.1:6102, [::]:6102,
+// unix:/run/teleport/maker.sock for running behind a reverse proxy or i2p for an i2p
+// destination made through the router's SAM bridge
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    I2p,
+}
+
+impl FromStr for ListenAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "i2p" {
+            return Ok(ListenAddress::I2p);
+        }
+        match s.strip_prefix("unix:") {
+            Some("") => Err("unix socket path missing".to_string()),
+            Some(path) => Ok(ListenAddress::Unix(PathBuf::from(path))),
+            None => s
+                .parse::<SocketAddr>()
+                .map(ListenAddress::Tcp)
+                .map_err(|_| {
+                    format!(
+                        "invalid listen address `{}`, must be ip:port, unix:<path> or i2p",
+                        s
+                    )
+                }),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListenAddress::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+            ListenAddress::I2p => write!(f, "i2p"),
+        }
+    }
+}
+
+impl ListenAddress {
+    //only reachable from this machine, e.g. by tor forwarding an onion service to it
+    pub fn is_local(&self) -> bool {
+        match self {
+            ListenAddress::Tcp(addr) => addr.ip().is_loopback(),
+            ListenAddress::Unix(_) => true,
+            ListenAddress::I2p => false,
+        }
+    }
+}
+
+/// The addresses maker instance number instance listens on, the ports of listen_addresses
+/// plus instance. Only the first instance can listen on unix sockets and i2p, as the others
+/// would need addresses of their own
+pub fn instance_listen_addresses(
+    listen_addresses: &[ListenAddress],
+    instance: u32,
+) -> Result<Vec<ListenAddress>, String> {
+    listen_addresses
+        .iter()
+        .map(|address| match address {
+            ListenAddress::Tcp(addr) => u16::try_from(addr.port() as u32 + instance)
+                .map(|port| ListenAddress::Tcp(SocketAddr::new(addr.ip(), port)))
+                .map_err(|_| format!("no port for maker instance {} of {}", instance, addr)),
+            _ if instance == 0 => Ok(address.clone()),
+            _ => Err(format!(
+                "only the first maker instance can listen on {}",
+                address
+            )),
+        })
+        .collect()
+}
+
+//address of a connected taker, unix socket and i2p peers are numbered instead
+#[derive(Debug, Clone, Copy)]
+enum PeerAddr {
+    Tcp(SocketAddr),
+    Unix(u64),
+    I2p(u64),
+}
+
+impl PeerAddr {
+    //what the logs show for the connection
+    fn id(&self) -> String {
+        match self {
+            PeerAddr::Tcp(addr) => addr.port().to_string(),
+            PeerAddr::Unix(n) => format!("unix-{}", n),
+            PeerAddr::I2p(n) => format!("i2p-{}", n),
+        }
+    }
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PeerAddr::Tcp(addr) => write!(f, "{}", addr),
+            PeerAddr::Unix(n) => write!(f, "unix-{}", n),
+            PeerAddr::I2p(n) => write!(f, "i2p-{}", n),
+        }
+    }
+}
+
+trait TakerStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> TakerStream for T {}
+
+//the last element is what the blacklist tells the taker apart by, its ip address or i2p
+// destination. None if takers cant be told apart, as when they come through tor
+type AcceptedConnection = (Box<dyn TakerStream>, PeerAddr, Option<String>);
+
+//accepts connections on a new task, which sends them or its error to accepted_tx
+//returns the .b32.i2p address when listening on i2p, as it isnt known until then
+async fn spawn_listener(
+    listen_address: &ListenAddress,
+    i2p_settings: &I2pSettings,
+    accepted_tx: mpsc::Sender<Result<AcceptedConnection, Error>>,
+) -> Result<Option<String>, Error> {
+    let mut i2p_address = None;
+    match listen_address {
+        ListenAddress::Tcp(addr) => {
+            let listener = TcpListener::bind(addr).await?;
+            tokio::spawn(async move {
+                loop {
+                    let accepted = listener.accept().await.map_err(Error::from).map(
+                        |(socket, addr)| -> AcceptedConnection {
+                            let fingerprint =
+                                (!addr.ip().is_loopback()).then(|| addr.ip().to_string());
+                            (Box::new(socket), PeerAddr::Tcp(addr), fingerprint)
+                        },
+                    );
+                    let failed = accepted.is_err();
+                    if accepted_tx.send(accepted).await.is_err() || failed {
+                        break;
+                    }
+                }
+            });
+        }
+        #[cfg(unix)]
+        ListenAddress::Unix(path) => {
+            use std::os::unix::fs::FileTypeExt;
+            //a socket left behind by a previous run would make bind fail
+            if let Ok(metadata) = std::fs::metadata(path) {
+                if metadata.file_type().is_socket() {
+                    std::fs::remove_file(path)?;
+                }
+            }
+            let listener = tokio::net::UnixListener::bind(path)?;
+            tokio::spawn(async move {
+                let mut count = 0;
+                loop {
+                    count += 1;
+                    let accepted = listener.accept().await.map_err(Error::from).map(
+                        |(socket, _addr)| -> AcceptedConnection {
+                            (Box::new(socket), PeerAddr::Unix(count), None)
+                        },
+                    );
+                    let failed = accepted.is_err();
+                    if accepted_tx.send(accepted).await.is_err() || failed {
+                        break;
+                    }
+                }
+            });
+        }
+        #[cfg(not(unix))]
+        ListenAddress::Unix(_) => {
+            return Err(Error::Protocol(
+                "unix sockets not supported on this platform",
+            ))
+        }
+        ListenAddress::I2p => {
+            let listener =
+                I2pListener::bind(&i2p_settings.sam_address, &i2p_destination_path()).await?;
+            tracing::info!("I2P address {}", listener.address());
+            i2p_address = Some(listener.address().to_string());
+            tokio::spawn(async move {
+                let mut count = 0;
+                loop {
+                    count += 1;
+                    let accepted = listener.accept().await.map(
+                        |(socket, peer_address)| -> AcceptedConnection {
+                            tracing::debug!("i2p-{} is {}", count, peer_address);
+                            (Box::new(socket), PeerAddr::I2p(count), Some(peer_address))
+                        },
+                    );
+                    let failed = accepted.is_err();
+                    if accepted_tx.send(accepted).await.is_err() || failed {
+                        break;
+                    }
+                }
+            });
+        }
+    }
+    tracing::info!("Listening On {}", listen_address);
+    Ok(i2p_address)
 }
 
 #[derive(Debug, Clone)]
 pub struct MakerConfig {
-    pub port: u16,
+    pub listen_addresses: Vec<ListenAddress>,
     pub rpc_ping_interval_secs: u64,
     pub watchtower_ping_interval_secs: u64,
     pub directory_servers_refresh_interval_secs: u64,
     pub maker_behavior: MakerBehavior,
     pub kill_flag: Arc<RwLock<bool>>,
     pub idle_connection_timeout: u64,
+    pub maker_settings: MakerSettings,
+    pub tor_settings: TorSettings,
+    pub i2p_settings: I2pSettings,
+    //which of the makers run from the wallet this is, counting from 0, see
+    // MakerSettings::instances
+    pub instance: u32,
+}
+
+//moves the maker to a fresh onion service every interval, the previous one is kept
+// until the next rotation so takers in the middle of a coinswap can still reconnect
+struct OnionRotation {
+    control: TorControl,
+    target: String,
+    //None never rotates, for the onion services of maker instances after the first
+    interval: Option<Duration>,
+    last_rotation: Instant,
+    current: String,
+    previous: Option<String>,
+}
+
+impl OnionRotation {
+    async fn start(tor_settings: &TorSettings, target: &ListenAddress) -> Result<Self, Error> {
+        let mut control = TorControl::connect(
+            &tor_settings.control_address,
+            tor_settings.control_password.as_deref(),
+        )
+        .await?;
+        let target = target.to_string();
+        let current = control.add_onion(ROTATED_ONION_PORT, &target).await?;
+        tracing::info!("Created onion service {}", current);
+        Ok(OnionRotation {
+            control,
+            target,
+            interval: Some(tor_settings.onion_rotation_hours)
+                .filter(|hours| *hours > 0)
+                .map(|hours| Duration::from_secs(hours * 60 * 60)),
+            last_rotation: Instant::now(),
+            current,
+            previous: None,
+        })
+    }
+
+    fn address(&self) -> String {
+        format!("{}:{}", self.current, ROTATED_ONION_PORT)
+    }
+
+    fn is_due(&self) -> bool {
+        self.interval
+            .is_some_and(|interval| self.last_rotation.elapsed() > interval)
+    }
+
+    async fn rotate(&mut self) -> Result<(), Error> {
+        let new = self
+            .control
+            .add_onion(ROTATED_ONION_PORT, &self.target)
+            .await?;
+        if let Some(old) = self.previous.take() {
+            if let Err(e) = self.control.del_onion(&old).await {
+                tracing::warn!("unable to remove old onion service {}: {:?}", old, e);
+            }
+        }
+        tracing::info!("Rotated onion service {} to {}", self.current, new);
+        self.previous = Some(std::mem::replace(&mut self.current, new));
+        self.last_rotation = Instant::now();
+        Ok(())
+    }
+}
+
+//the hostnames takers reach the maker at, it only proves its identity key to takers which reached
+// it at one of them, see maker_identity.rs. on regtest makers are reached on this machine
+fn own_hostnames(
+    public_addresses: &[String],
+    onion_rotation: Option<&OnionRotation>,
+    network: Network,
+) -> Vec<String> {
+    let mut hostnames = public_addresses
+        .iter()
+        .map(|address| onion_hostname(address).to_string())
+        .collect::<Vec<String>>();
+    //takers in the middle of a coinswap can still reconnect to the previous onion service
+    hostnames.extend(onion_rotation.and_then(|rotation| rotation.previous.clone()));
+    if network == Network::Regtest {
+        hostnames.extend(["localhost".to_string(), "127.0.0.1".to_string()]);
+    }
+    hostnames
+}
+
+//signs the address's onion or i2p hostname with the instance-th most valuable fidelity bond so
+// that takers can tell which bond is behind it, None if there is no such bond
+fn create_fidelity_bond_proof(
+    rpc: &Client,
+    wallet: &RwLock<Wallet>,
+    address: &str,
+    instance: u32,
+) -> Option<FidelityBondProof> {
+    if !(is_onion_address(address) || is_i2p_address(address)) || address == MAKER_ONION_ADDR {
+        return None;
+    }
+    let hostname = address
+        .rsplit_once(':')
+        .map_or(address, |(host, _port)| host);
+    let proof = wallet
+        .read()
+        .unwrap()
+        .find_fidelity_bond_by_rank(rpc, instance as usize)
+        .and_then(|bond| bond.map(|b| b.create_proof(rpc, hostname)).transpose());
+    match proof {
+        Ok(proof) => proof,
+        Err(e) => {
+            tracing::warn!("unable to create fidelity bond proof: {:?}", e);
+            None
+        }
+    }
+}
+
+//so the maker is reminded to move the bond into a new one before it unlocks and loses value
+async fn register_fidelity_bond_expiry(rpc: &Client, wallet: &RwLock<Wallet>, instance: u32) {
+    let bond = wallet
+        .read()
+        .unwrap()
+        .find_fidelity_bond_by_rank(rpc, instance as usize);
+    let result = match bond {
+        Ok(Some(bond)) => register_fidelity_bond_with_watchtowers(bond.utxo, bond.locktime()).await,
+        Ok(None) => Ok(()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        tracing::warn!("unable to register fidelity bond with watchtowers: {:?}", e);
+    }
+}
+
+//our own addresses are gossiped too, so that peers learn about us from each other
+fn add_own_addresses_to_gossip_book(
+    rpc: &Client,
+    wallet: &RwLock<Wallet>,
+    public_addresses: &[String],
+    instance: u32,
+    gossip_book: &RwLock<GossipBook>,
+) {
+    let now = chrono::Utc::now().timestamp();
+    for address in public_addresses {
+        if let Some(fidelity_bond_proof) =
+            create_fidelity_bond_proof(rpc, wallet, address, instance)
+        {
+            let signed_address = SignedMakerAddress {
+                address: address.clone(),
+                fidelity_bond_proof,
+            };
+            gossip_book.write().unwrap().add(signed_address, now);
+        }
+    }
+}
+
+//asks a few known makers which makers they know of, the directory servers are only needed
+// to find the first ones
+async fn gossip_with_other_makers(
+    rpc: Arc<Client>,
+    gossip_book: Arc<RwLock<GossipBook>>,
+    network: Network,
+    own_addresses: Vec<String>,
+) {
+    let now = chrono::Utc::now().timestamp();
+    let mut peers = gossip_book.read().unwrap().addresses(now);
+    peers.retain(|a| !own_addresses.contains(a));
+    if peers.len() < GOSSIP_PEER_COUNT {
+        match sync_maker_addresses_from_directory_servers(network).await {
+            Ok(maker_addresses) => {
+                for address in maker_addresses.iter().map(|a| a.to_string()) {
+                    if !own_addresses.contains(&address) && !peers.contains(&address) {
+                        peers.push(address);
+                    }
+                }
+            }
+            Err(e) => tracing::debug!("unable to get gossip peers from directory: {:?}", e),
+        }
+    }
+    let peers = peers
+        .into_iter()
+        .map(MakerAddress::from_published)
+        .collect::<Vec<MakerAddress>>();
+    let signed_addresses = gossip_with_makers(&peers).await;
+    let block_count = match rpc.get_block_count() {
+        Ok(b) => b,
+        Err(e) => {
+            tracing::debug!("unable to check gossiped addresses: {:?}", e);
+            return;
+        }
+    };
+    for signed_address in signed_addresses {
+        let hostname = onion_hostname(&signed_address.address);
+        match signed_address
+            .fidelity_bond_proof
+            .verify_and_get_txo(&rpc, block_count, hostname)
+        {
+            Ok(_txo_data) => gossip_book.write().unwrap().add(signed_address, now),
+            Err(e) => tracing::debug!(
+                "gossiped address {} has invalid fidelity bond: {:?}",
+                signed_address.address,
+                e
+            ),
+        }
+    }
 }
 
 #[tokio::main]
 pub async fn start_maker(rpc: Arc<Client>, wallet: Arc<RwLock<Wallet>>, config: MakerConfig) {
     match run(rpc, wallet, config).await {
-        Ok(_o) => log::info!("maker ended without error"),
-        Err(e) => log::info!("maker ended with err: {:?}", e),
+        Ok(_o) => tracing::info!("maker ended without error"),
+        Err(e) => tracing::info!("maker ended with err: {:?}", e),
     };
 }
 
-// A structure denoting expectation of type of taker message.
-// Used in the [ConnectionState] structure.
-//
-// If the recieved message doesn't match expected method,
-// protocol error will be returned.
-#[derive(Debug)]
-enum ExpectedMessage {
-    TakerHello,
-    NewlyConnectedTaker,
-    SignSendersContractTx,
-    ProofOfFunding,
-    ProofOfFundingORSendersAndReceiversContractSigs,
-    SignReceiversContractTx,
-    HashPreimage,
-    PrivateKeyHandover,
-}
-
 struct ConnectionState {
-    allowed_message: ExpectedMessage,
+    phase: MakerPhase,
     incoming_swapcoins: Option<Vec<IncomingSwapCoin>>,
     outgoing_swapcoins: Option<Vec<OutgoingSwapCoin>>,
     pending_funding_txes: Option<Vec<Transaction>>,
+    //copy of the settings when the connection was made, so reloading the settings
+    //doesnt change them halfway through a coinswap
+    maker_settings: MakerSettings,
+    fidelity_bond_proof: Option<FidelityBondProof>,
+    gossip_book: Arc<RwLock<GossipBook>>,
+    gave_maker_addresses: bool,
+    //set once the taker has funded a coinswap, errors after that run the swap failure hook
+    swap_id: Option<Hash160>,
+    //given in the maker's hello, see swap_sessions.rs
+    offered_session_id: SessionId,
+    //the session the taker's hello took up, None if the connection isnt for a coinswap
+    session_id: Option<SessionId>,
+    //a session taken up which no coinswap used for this long is forgotten
+    idle_connection_timeout: u64,
+    //proves the maker's identity to the taker and signs its offers, see maker_identity.rs
+    identity_key: SecretKey,
+    //the instance's own tweakable key, its offers give the point and the keys of the coinswaps
+    // it takes part in are derived from it
+    tweakable_keypair: (SecretKey, PublicKey),
+    //see own_hostnames()
+    own_hostnames: Vec<String>,
+    audit_log: Option<Arc<AuditLog>>,
+}
+
+//counts the connections currently being handled, decrements the count when dropped
+struct ConnectionCount(Arc<AtomicUsize>);
+
+impl ConnectionCount {
+    fn new(count: &Arc<AtomicUsize>) -> ConnectionCount {
+        count.fetch_add(1, Ordering::SeqCst);
+        ConnectionCount(Arc::clone(count))
+    }
+}
+
+impl Drop for ConnectionCount {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+//sends on reload_tx whenever the process receives SIGHUP
+#[cfg(unix)]
+fn spawn_reload_signal_listener(reload_tx: mpsc::Sender<()>) -> Result<(), Error> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sighup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while sighup.recv().await.is_some() {
+            if reload_tx.send(()).await.is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn spawn_reload_signal_listener(_reload_tx: mpsc::Sender<()>) -> Result<(), Error> {
+    Ok(())
+}
+
+pub fn maker_pause_file_path() -> PathBuf {
+    teleport_data_dir().join(MAKER_PAUSE_FILE_NAME)
+}
+
+/// Pause or resume a running maker, by creating or removing the pause file it checks before
+/// giving out an offer or starting a coinswap
+pub fn set_maker_paused(paused: bool) -> Result<(), Error> {
+    let path = maker_pause_file_path();
+    if paused {
+        std::fs::write(&path, chrono::Utc::now().timestamp().to_string())?;
+    } else if path.exists() {
+        std::fs::remove_file(&path)?;
+    }
+    Ok(())
+}
+
+pub fn is_maker_paused() -> bool {
+    maker_pause_file_path().exists()
+}
+
+//runs the health checks until they all pass, reporting every failed check each time
+async fn wait_until_healthy(
+    rpc: &Client,
+    wallet: &RwLock<Wallet>,
+    public_addresses: &[String],
+    instance: u32,
+    kill_flag: &RwLock<bool>,
+) -> Result<(), Error> {
+    loop {
+        match check_maker_health(
+            rpc,
+            wallet,
+            public_addresses,
+            instance,
+            &teleport_data_dir(),
+        )
+        .await
+        {
+            Ok(()) => return Ok(()),
+            Err(Error::Config(problems)) => {
+                for problem in problems {
+                    tracing::error!("health check failed: {}", problem);
+                }
+                tracing::warn!(
+                    "not advertising offers, checking again in {} seconds",
+                    HEALTH_CHECK_RETRY_INTERVAL_SECS
+                );
+            }
+            Err(e) => return Err(e),
+        }
+        if *kill_flag.read().unwrap() {
+            return Err(Error::Protocol("kill flag is true"));
+        }
+        sleep(Duration::from_secs(HEALTH_CHECK_RETRY_INTERVAL_SECS)).await;
+    }
 }
 
 async fn run(
@@ -103,8 +682,9 @@ async fn run(
     wallet: Arc<RwLock<Wallet>>,
     config: MakerConfig,
 ) -> Result<(), Error> {
-    log::debug!(
-        "Running maker with special behavior = {:?}",
+    tracing::debug!(
+        "Running maker instance {} with special behavior = {:?}",
+        config.instance,
         config.maker_behavior
     );
     wallet
@@ -112,36 +692,142 @@ async fn run(
         .unwrap()
         .refresh_offer_maxsize_cache(Arc::clone(&rpc))?;
 
-    log::info!("Pinging watchtowers. . .");
+    tracing::info!("Pinging watchtowers. . .");
     ping_watchtowers().await?;
+    register_fidelity_bond_expiry(&rpc, &wallet, config.instance).await;
 
-    if wallet.read().unwrap().network != Network::Regtest {
-        if MAKER_ONION_ADDR == "myhiddenserviceaddress.onion:6102" {
-            panic!("You must set config variable MAKER_ONION_ADDR in file src/maker_protocol.rs");
+    let network = wallet.read().unwrap().network;
+    if Settings::is_tor_only() {
+        //only listen where tor forwards connections to the onion service, or on i2p
+        if let Some(address) = config
+            .listen_addresses
+            .iter()
+            .find(|a| !a.is_local() && **a != ListenAddress::I2p)
+        {
+            panic!("Tor only mode cant listen on non-local address {}", address);
+        }
+    }
+    let (accepted_tx, mut accepted_rx) = mpsc::channel::<Result<AcceptedConnection, Error>>(100);
+    let mut i2p_address = None;
+    for listen_address in &config.listen_addresses {
+        if let Some(address) =
+            spawn_listener(listen_address, &config.i2p_settings, accepted_tx.clone()).await?
+        {
+            i2p_address = Some(address);
+        }
+    }
+
+    let mut onion_rotation = None;
+    //makers after the first need an onion service of their own, public_addresses belong
+    // to the first
+    let mut public_addresses =
+        if config.tor_settings.onion_rotation_hours > 0 || config.instance > 0 {
+            let target = config
+                .listen_addresses
+                .iter()
+                .find(|a| a.is_local())
+                .ok_or(Error::Protocol(
+                    "onion rotation needs a local listen address",
+                ))?;
+            let rotation = OnionRotation::start(&config.tor_settings, target).await?;
+            let addresses = vec![rotation.address()];
+            onion_rotation = Some(rotation);
+            addresses
+        } else if config.maker_settings.public_addresses.is_empty() && i2p_address.is_none() {
+            vec![MAKER_ONION_ADDR.to_string()]
+        } else {
+            config.maker_settings.public_addresses.clone()
+        };
+    //comes after any onion address so rotating it only replaces the first address
+    if let Some(address) = i2p_address.filter(|a| !public_addresses.contains(a)) {
+        public_addresses.push(address);
+    }
+    let fidelity_bond_proof = Arc::new(RwLock::new(create_fidelity_bond_proof(
+        &rpc,
+        &wallet,
+        &public_addresses[0],
+        config.instance,
+    )));
+    let (identity_key, identity_pubkey) =
+        wallet.read().unwrap().get_identity_keypair(config.instance);
+    tracing::info!("maker identity key {}", identity_pubkey);
+    let tweakable_keypair = wallet
+        .read()
+        .unwrap()
+        .get_instance_tweakable_keypair(config.instance);
+    let audit_log = wallet.read().unwrap().audit_log();
+    let gossip_book = Arc::new(RwLock::new(GossipBook::default()));
+    add_own_addresses_to_gossip_book(
+        &rpc,
+        &wallet,
+        &public_addresses,
+        config.instance,
+        &gossip_book,
+    );
+    let uses_placeholder_addr = public_addresses
+        .iter()
+        .any(|a| a == "myhiddenserviceaddress.onion:6102");
+    if network != Network::Regtest && uses_placeholder_addr {
+        panic!("You must set config variable MAKER_ONION_ADDR in file src/maker_protocol.rs");
+    }
+    //offers are only given out from the main loop, so until then nothing is advertised
+    wait_until_healthy(
+        &rpc,
+        &wallet,
+        &public_addresses,
+        config.instance,
+        &config.kill_flag,
+    )
+    .await?;
+    if network != Network::Regtest {
+        for address in &public_addresses {
+            tracing::info!(
+                "Adding my address ({}) to the directory servers. . .",
+                address
+            );
+            let proof = create_fidelity_bond_proof(&rpc, &wallet, address, config.instance);
+            post_maker_address_to_directory_servers(network, address, proof.as_ref())
+                .await
+                .expect("unable to add my address to the directory servers, is tor reachable?");
         }
-        log::info!(
-            "Adding my address ({}) to the directory servers. . .",
-            MAKER_ONION_ADDR
-        );
-        post_maker_address_to_directory_servers(wallet.read().unwrap().network, MAKER_ONION_ADDR)
-            .await
-            .expect("unable to add my address to the directory servers, is tor reachable?");
     }
 
-    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, config.port)).await?;
-    log::info!("Listening On Port {}", config.port);
+    if Settings::is_tor_only() {
+        if uses_placeholder_addr {
+            panic!("Tor only mode needs config variable MAKER_ONION_ADDR in src/maker_protocol.rs");
+        }
+        if let Some(address) = public_addresses
+            .iter()
+            .find(|a| !is_onion_address(a) && !is_i2p_address(a))
+        {
+            panic!("Tor only mode cant publish clearnet address {}", address);
+        }
+    }
 
     let (server_loop_comms_tx, mut server_loop_comms_rx) = mpsc::channel::<Error>(100);
+    let (reload_tx, mut reload_rx) = mpsc::channel::<()>(1);
+    spawn_reload_signal_listener(reload_tx.clone())?;
+    let maker_settings = Arc::new(RwLock::new(config.maker_settings.clone()));
+    let connection_count = Arc::new(AtomicUsize::new(0));
+    let blacklist = Arc::new(Mutex::new(TakerBlacklist::default()));
     let mut accepting_clients = true;
     let mut last_rpc_ping = Instant::now();
     let mut last_watchtowers_ping = Instant::now();
     let mut last_directory_servers_refresh = Instant::now();
+    //None until the first gossip, which is right after starting
+    let mut last_gossip: Option<Instant> = None;
+    let mut seen_contract_outpoints = HashSet::<OutPoint>::new();
+    let mut mempool_fee_rate = get_mempool_fee_rate(&rpc);
+    let mut last_mempool_fee_rate_refresh = Instant::now();
+    let mut rebalance: Option<thread::JoinHandle<Result<(), Error>>> = None;
+    let mut last_rebalance_check = Instant::now();
 
     let my_kill_flag = config.kill_flag.clone();
 
     loop {
-        let (mut socket, addr) = select! {
-            new_client = listener.accept() => new_client?,
+        let (socket, addr, fingerprint) = select! {
+            //unwrap the option because accepted_tx is kept until here returns
+            new_client = accepted_rx.recv() => new_client.unwrap()?,
             client_err = server_loop_comms_rx.recv() => {
                 //unwrap the option here because we'll never close the mscp so it will always work
                 match client_err.as_ref().unwrap() {
@@ -150,16 +836,26 @@ async fn run(
                         //will be unrelated to the connection itself e.g. "insufficent funds"
                         let rpc_connection_success = rpc.get_best_block_hash().is_ok();
                         if !rpc_connection_success {
-                            log::warn!("lost connection with bitcoin node, temporarily shutting \
+                            tracing::warn!("lost connection with bitcoin node, temporarily shutting \
                                       down server until connection reestablished, error={:?}", e);
                             accepting_clients = false;
                         }
                         continue;
                     },
-                    _ => log::error!("ending server"),
+                    _ => tracing::error!("ending server"),
                 }
                 break Err(client_err.unwrap());
             },
+            _ = reload_rx.recv() => {
+                match Settings::reload() {
+                    Ok(settings) => {
+                        tracing::info!("reloaded maker settings {:?}", settings.maker);
+                        *maker_settings.write().unwrap() = settings.maker;
+                    }
+                    Err(e) => tracing::warn!("not reloading maker settings, error={:?}", e),
+                }
+                continue;
+            },
             _ = sleep(Duration::from_secs(MAKER_HEARTBEAT_INTERVAL_SECS)) => {
                 let mut rpc_ping_success = true;
                 let mut watchtowers_ping_success = true;
@@ -172,7 +868,54 @@ async fn run(
                         .unwrap()
                         .refresh_offer_maxsize_cache(Arc::clone(&rpc))
                         .is_ok();
-                    log::debug!("rpc_ping_success = {}", rpc_ping_success);
+                    tracing::debug!("rpc_ping_success = {}", rpc_ping_success);
+                    if rpc_ping_success {
+                        alert_new_contract_outputs(&rpc, &wallet, &mut seen_contract_outpoints);
+                    }
+                }
+                let mempool_fee_rate_refresh_interval =
+                    Duration::from_secs(MEMPOOL_FEE_RATE_REFRESH_INTERVAL_SECS);
+                if Instant::now().saturating_duration_since(last_mempool_fee_rate_refresh)
+                        > mempool_fee_rate_refresh_interval {
+                    last_mempool_fee_rate_refresh = Instant::now();
+                    //keep the last estimate if there isnt a new one
+                    mempool_fee_rate = get_mempool_fee_rate(&rpc).or(mempool_fee_rate);
+                    //the other instances leave the coins alone, they cant see each other's
+                    // connections
+                    if config.instance == 0 && connection_count.load(Ordering::SeqCst) == 0
+                            && rebalance.is_none() {
+                        let maker_settings = maker_settings.read().unwrap().clone();
+                        if let Err(e) = consolidate_small_coins(&rpc, &wallet, &maker_settings,
+                                mempool_fee_rate) {
+                            tracing::warn!("unable to consolidate coins: {:?}", e);
+                        }
+                    }
+                }
+                if rebalance.as_ref().is_some_and(|r| r.is_finished()) {
+                    if let Err(e) = finish_rebalance(&rpc, &wallet, rebalance.take().unwrap()) {
+                        tracing::error!("unable to reload wallet after rebalancing: {:?}", e);
+                        break Err(e);
+                    }
+                }
+                let rebalance_settings = maker_settings.read().unwrap().clone();
+                let rebalance_interval =
+                    Duration::from_secs(rebalance_settings.rebalance_interval_hours * 60 * 60);
+                //rebalancing replaces the wallet, which makers of other instances are using
+                if rebalance_settings.rebalance_interval_hours > 0
+                        && config.maker_settings.instances == 1
+                        && rebalance.is_none()
+                        && connection_count.load(Ordering::SeqCst) == 0
+                        && last_rebalance_check.elapsed() > rebalance_interval {
+                    last_rebalance_check = Instant::now();
+                    //min_funding_tx_fee_rate is in sat/vbyte
+                    let min_funding_tx_fee_rate =
+                        rebalance_settings.min_funding_tx_fee_rate * 1000;
+                    match start_rebalance(&rpc, &wallet, &rebalance_settings,
+                            mempool_fee_rate.unwrap_or(min_funding_tx_fee_rate),
+                            min_funding_tx_fee_rate, &public_addresses) {
+                        Ok(r) => rebalance = r,
+                        Err(e) => tracing::warn!("unable to start rebalancing: {:?}", e),
+                    }
                 }
                 let watchtowers_ping_interval
                     = Duration::from_secs(config.watchtower_ping_interval_secs);
@@ -180,76 +923,174 @@ async fn run(
                         > watchtowers_ping_interval {
                     last_watchtowers_ping = Instant::now();
                     watchtowers_ping_success = ping_watchtowers().await.is_ok();
-                    log::debug!("watchtowers_ping_success = {}", watchtowers_ping_success);
+                    tracing::debug!("watchtowers_ping_success = {}", watchtowers_ping_success);
                 }
-                accepting_clients = rpc_ping_success && watchtowers_ping_success;
+                accepting_clients = rpc_ping_success && watchtowers_ping_success
+                    && rebalance.is_none();
                 if !accepting_clients {
-                    log::warn!("not accepting clients, rpc_ping_success={} \
-                        watchtowers_ping_success={}", rpc_ping_success, watchtowers_ping_success);
+                    tracing::warn!("not accepting clients, rpc_ping_success={} \
+                        watchtowers_ping_success={} rebalancing={}", rpc_ping_success,
+                        watchtowers_ping_success, rebalance.is_some());
                 }
 
                 if *my_kill_flag.read().unwrap() {
                     break Err(Error::Protocol("kill flag is true"));
                 }
 
+                if let Some(rotation) = onion_rotation.as_mut().filter(|r| r.is_due()) {
+                    match rotation.rotate().await {
+                        Ok(()) => {
+                            public_addresses[0] = rotation.address();
+                            *fidelity_bond_proof.write().unwrap() = create_fidelity_bond_proof(
+                                &rpc, &wallet, &public_addresses[0], config.instance);
+                            add_own_addresses_to_gossip_book(&rpc, &wallet, &public_addresses,
+                                config.instance, &gossip_book);
+                            //publish the new address now instead of at the next refresh
+                            if network != Network::Regtest {
+                                last_directory_servers_refresh = Instant::now();
+                                let result_expiry_time = post_maker_address_to_directory_servers(
+                                    network,
+                                    &public_addresses[0],
+                                    fidelity_bond_proof.read().unwrap().clone().as_ref(),
+                                ).await;
+                                tracing::info!("Adding my new address {} to the directory \
+                                    servers = {:?}", public_addresses[0], result_expiry_time);
+                            }
+                        }
+                        Err(e) => tracing::warn!("unable to rotate onion service: {:?}", e),
+                    }
+                }
+
+                let gossip_interval = Duration::from_secs(GOSSIP_INTERVAL_SECS);
+                if network != Network::Regtest
+                        && last_gossip.is_none_or(|t| t.elapsed() > gossip_interval) {
+                    last_gossip = Some(Instant::now());
+                    add_own_addresses_to_gossip_book(&rpc, &wallet, &public_addresses,
+                        config.instance, &gossip_book);
+                    tokio::spawn(gossip_with_other_makers(
+                        Arc::clone(&rpc),
+                        Arc::clone(&gossip_book),
+                        network,
+                        public_addresses.clone(),
+                    ));
+                }
+
                 let directory_servers_refresh_interval = Duration::from_secs(
                     config.directory_servers_refresh_interval_secs
                 );
-                if wallet.read().unwrap().network != Network::Regtest
+                if network != Network::Regtest
                         && Instant::now().saturating_duration_since(last_directory_servers_refresh)
                         > directory_servers_refresh_interval {
                     last_directory_servers_refresh = Instant::now();
-                    let result_expiry_time = post_maker_address_to_directory_servers(
-                        wallet.read().unwrap().network,
-                        MAKER_ONION_ADDR
-                    ).await;
-                    log::info!("Refreshing my address at the directory servers = {:?}",
-                        result_expiry_time);
+                    for address in &public_addresses {
+                        let proof = create_fidelity_bond_proof(&rpc, &wallet, address,
+                            config.instance);
+                        let result_expiry_time = post_maker_address_to_directory_servers(
+                            network,
+                            address,
+                            proof.as_ref(),
+                        ).await;
+                        tracing::info!("Refreshing my address {} at the directory servers = {:?}",
+                            address, result_expiry_time);
+                    }
                 }
                 continue;
             },
         };
 
         if !accepting_clients {
-            log::warn!("Rejecting Connection From {:?}", addr);
+            tracing::warn!("Rejecting Connection From {:?}", addr);
             continue;
         }
+        if fingerprint.as_ref().is_some_and(|f| {
+            blacklist
+                .lock()
+                .unwrap()
+                .is_banned(f, chrono::Utc::now().timestamp())
+        }) {
+            tracing::warn!(
+                "Rejecting Connection From {:?}, banned for misbehaving",
+                addr
+            );
+            continue;
+        }
+        let mut connection_settings = maker_settings.read().unwrap().clone();
+        //fixed for the whole connection, so the fee charged is the one offered
+        connection_settings.absolute_fee_sat =
+            scaled_absolute_fee(&connection_settings, mempool_fee_rate);
+        let connection_bond_proof = fidelity_bond_proof.read().unwrap().clone();
+        let connection_gossip_book = Arc::clone(&gossip_book);
+        if connection_count.load(Ordering::SeqCst) >= connection_settings.max_concurrent_connections
+        {
+            tracing::warn!(
+                "Rejecting Connection From {:?}, already handling {} connections",
+                addr,
+                connection_settings.max_concurrent_connections
+            );
+            continue;
+        }
+        let connection_count_guard = ConnectionCount::new(&connection_count);
 
-        log::info!(
-            "[{}] ===> Accepted Connection on port={}",
-            addr.port(),
-            addr.port()
-        );
+        tracing::info!("[{}] ===> Accepted Connection from {}", addr.id(), addr);
         let client_rpc = Arc::clone(&rpc);
         let client_wallet = Arc::clone(&wallet);
         let server_loop_comms_tx = server_loop_comms_tx.clone();
         let maker_behavior = config.maker_behavior;
         let idle_connection_timeout = config.idle_connection_timeout;
+        //min_funding_tx_fee_rate is in sat/vbyte
+        let min_funding_tx_fee_rate = connection_settings.min_funding_tx_fee_rate * 1000;
+        let connection_blacklist = Arc::clone(&blacklist);
+        let connection_audit_log = audit_log.clone();
+        let connection_own_hostnames =
+            own_hostnames(&public_addresses, onion_rotation.as_ref(), network);
 
-        tokio::spawn(async move {
-            let (socket_reader, mut socket_writer) = socket.split();
+        let connection_span = tracing::info_span!(
+            "connection",
+            peer = %addr.id(),
+            swap_id = tracing::field::Empty
+        );
+
+        let handle_connection = async move {
+            let _connection_count_guard = connection_count_guard;
+            let (socket_reader, mut socket_writer) = tokio::io::split(socket);
             let mut reader = BufReader::new(socket_reader);
 
             let mut connection_state = ConnectionState {
-                allowed_message: ExpectedMessage::TakerHello,
+                phase: MakerPhase::TakerHello,
                 incoming_swapcoins: None,
                 outgoing_swapcoins: None,
                 pending_funding_txes: None,
+                maker_settings: connection_settings,
+                fidelity_bond_proof: connection_bond_proof,
+                gossip_book: connection_gossip_book,
+                gave_maker_addresses: false,
+                swap_id: None,
+                offered_session_id: generate_session_id(),
+                session_id: None,
+                idle_connection_timeout,
+                identity_key,
+                tweakable_keypair,
+                own_hostnames: connection_own_hostnames,
+                audit_log: connection_audit_log,
             };
 
             if let Err(e) = send_message(
                 &mut socket_writer,
+                addr,
+                connection_state.audit_log.as_deref(),
                 &MakerToTakerMessage::MakerHello(MakerHello {
                     protocol_version_min: 0,
                     protocol_version_max: 0,
+                    session_id: Some(connection_state.offered_session_id),
+                    identity_pubkey: Some(identity_pubkey),
                 }),
             )
             .await
             {
-                log::error!("io error sending first message: {:?}", e);
+                tracing::error!("io error sending first message: {:?}", e);
                 return;
             }
-            log::info!("[{}] <=== MakerHello", addr.port());
+            tracing::info!("[{}] <=== MakerHello", addr.id());
 
             loop {
                 let mut line = String::new();
@@ -257,23 +1098,41 @@ async fn run(
                     readline_ret = reader.read_line(&mut line) => {
                         match readline_ret {
                             Ok(n) if n == 0 => {
-                                log::info!("[{}] Connection closed by peer", addr.port());
+                                tracing::info!("[{}] Connection closed by peer", addr.id());
+                                if is_aborted_connection(&connection_state) {
+                                    record_misbehavior(&connection_blacklist, &fingerprint,
+                                        Misbehavior::AbortedConnection,
+                                        &connection_state.maker_settings);
+                                }
                                 break;
                             }
                             Ok(_n) => (),
                             Err(e) => {
-                                log::error!("error reading from socket: {:?}", e);
+                                tracing::error!("error reading from socket: {:?}", e);
                                 break;
                             }
                         }
                     },
                     _ = sleep(Duration::from_secs(idle_connection_timeout)) => {
-                        log::info!("[{}] Idle connection closed", addr.port());
+                        tracing::info!("[{}] Idle connection closed", addr.id());
+                        if is_aborted_connection(&connection_state) {
+                            record_misbehavior(&connection_blacklist, &fingerprint,
+                                Misbehavior::AbortedConnection, &connection_state.maker_settings);
+                        }
                         break;
                     },
                 };
 
                 line = line.trim_end().to_string();
+                record_message(
+                    connection_state.audit_log.as_deref(),
+                    &addr.to_string(),
+                    None,
+                    Direction::Received,
+                    line.as_bytes(),
+                );
+                let is_private_key_handover =
+                    connection_state.phase == MakerPhase::PrivateKeyHandover;
                 let message_result = handle_message(
                     line,
                     &mut connection_state,
@@ -281,23 +1140,70 @@ async fn run(
                     Arc::clone(&client_wallet),
                     addr,
                     maker_behavior,
+                    min_funding_tx_fee_rate,
                 )
                 .await;
                 match message_result {
                     Ok(reply) => {
+                        if is_private_key_handover {
+                            if let Some(fingerprint) = &fingerprint {
+                                connection_blacklist
+                                    .lock()
+                                    .unwrap()
+                                    .record_completed_swap(fingerprint);
+                            }
+                        }
                         if let Some(message) = reply {
-                            if let Err(e) = send_message(&mut socket_writer, &message).await {
-                                log::error!("closing due to io error sending message: {:?}", e);
+                            if matches!(message, MakerToTakerMessage::Offer(_)) {
+                                record_misbehavior(
+                                    &connection_blacklist,
+                                    &fingerprint,
+                                    Misbehavior::OfferRequest,
+                                    &connection_state.maker_settings,
+                                );
+                            }
+                            if let Err(e) = send_message(
+                                &mut socket_writer,
+                                addr,
+                                connection_state.audit_log.as_deref(),
+                                &message,
+                            )
+                            .await
+                            {
+                                tracing::error!("closing due to io error sending message: {:?}", e);
+                                break;
+                            }
+                            if maker_behavior == MakerBehavior::CloseAfterSendingSendersSigs
+                                && matches!(message, MakerToTakerMessage::SendersContractSig(_))
+                            {
+                                tracing::info!("closing connection due to special maker behavior");
                                 break;
                             }
                         }
                         //if reply is None then dont send anything to client
                     }
                     Err(err) => {
-                        log::error!("error handling client request: {:?}", err);
+                        tracing::error!("error handling client request: {:?}", err);
+                        if let Some(swap_id) = connection_state.swap_id {
+                            spawn_hook(
+                                "maker",
+                                HookEvent::SwapFailure {
+                                    swap_id,
+                                    error: format!("{:?}", err),
+                                },
+                            );
+                        }
                         match err {
                             Error::Network(_e) => (),
-                            Error::Protocol(_e) => (),
+                            Error::Protocol(_) | Error::FundingTxFeeRate { .. } => {
+                                record_misbehavior(
+                                    &connection_blacklist,
+                                    &fingerprint,
+                                    Misbehavior::ProtocolError,
+                                    &connection_state.maker_settings,
+                                )
+                            }
+                            Error::Config(_e) => (),
                             Error::Disk(e) => {
                                 server_loop_comms_tx.send(Error::Disk(e)).await.unwrap()
                             }
@@ -312,16 +1218,100 @@ async fn run(
                     }
                 };
             }
-        });
+        };
+        tokio::spawn(handle_connection.instrument(connection_span));
+    }
+}
+
+//the taker said hello then left without asking for anything
+fn is_aborted_connection(connection_state: &ConnectionState) -> bool {
+    matches!(
+        connection_state.phase,
+        MakerPhase::TakerHello | MakerPhase::NewlyConnectedTaker
+    ) && !connection_state.gave_maker_addresses
+}
+
+//does nothing for takers which cant be told apart from others, e.g. ones coming through tor
+fn record_misbehavior(
+    blacklist: &Mutex<TakerBlacklist>,
+    fingerprint: &Option<String>,
+    misbehavior: Misbehavior,
+    maker_settings: &MakerSettings,
+) {
+    if let Some(fingerprint) = fingerprint {
+        if blacklist.lock().unwrap().record(
+            fingerprint,
+            misbehavior,
+            maker_settings,
+            chrono::Utc::now().timestamp(),
+        ) {
+            tracing::warn!(
+                "refusing {} for {} seconds, too many {:?}",
+                fingerprint,
+                maker_settings.ban_duration_secs,
+                misbehavior
+            );
+        }
     }
 }
 
-async fn send_message(
-    socket_writer: &mut WriteHalf<'_>,
+//alerts about contract outputs of our coinswaps which appeared since the last check
+fn alert_new_contract_outputs(
+    rpc: &Client,
+    wallet: &RwLock<Wallet>,
+    seen_contract_outpoints: &mut HashSet<OutPoint>,
+) {
+    let contract_outputs = match find_contract_outputs(rpc, &mut wallet.write().unwrap()) {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::debug!("unable to look for contract outputs: {:?}", e);
+            return;
+        }
+    };
+    for contract_output in contract_outputs {
+        let outpoint = OutPoint {
+            txid: contract_output.txid,
+            vout: contract_output.vout,
+        };
+        if seen_contract_outpoints.insert(outpoint) {
+            send_alert(
+                "maker",
+                Alert::ContractBroadcast {
+                    txids: vec![contract_output.txid],
+                },
+            );
+            spawn_hook(
+                "maker",
+                HookEvent::ContractBroadcast {
+                    txids: vec![contract_output.txid],
+                },
+            );
+        }
+    }
+}
+
+pub(crate) fn is_onion_address(address: &str) -> bool {
+    address
+        .rsplit_once(':')
+        .map_or(address, |(host, _port)| host)
+        .ends_with(".onion")
+}
+
+async fn send_message<W: AsyncWrite + Unpin>(
+    socket_writer: &mut W,
+    peer: PeerAddr,
+    audit_log: Option<&AuditLog>,
     first_message: &MakerToTakerMessage,
 ) -> Result<(), Error> {
-    let mut message_bytes =
-        serde_json::to_vec(first_message).map_err(std::io::Error::from)?;
+    let mut message_bytes = serde_json::to_vec(first_message).map_err(std::io::Error::from)?;
+    //takers have no identity key
+    record_message(
+        audit_log,
+        &peer.to_string(),
+        None,
+        Direction::Sent,
+        &message_bytes,
+    );
     message_bytes.push(b'\n');
     socket_writer.write_all(&message_bytes).await?;
     Ok(())
@@ -332,17 +1322,18 @@ async fn handle_message(
     connection_state: &mut ConnectionState,
     rpc: Arc<Client>,
     wallet: Arc<RwLock<Wallet>>,
-    from_addrs: SocketAddr,
+    from_addrs: PeerAddr,
     maker_behavior: MakerBehavior,
+    min_funding_tx_fee_rate: u64,
 ) -> Result<Option<MakerToTakerMessage>, Error> {
     let request: TakerToMakerMessage = match serde_json::from_str(&line) {
         Ok(r) => r,
         Err(_e) => return Err(Error::Protocol("message parsing error")),
     };
 
-    log::info!(
+    tracing::info!(
         "[{}] ===> {} ",
-        from_addrs.port(),
+        from_addrs.id(),
         match request {
             TakerToMakerMessage::TakerHello(_) => "TakerHello",
             TakerToMakerMessage::GiveOffer(_) => "GiveOffer",
@@ -353,159 +1344,348 @@ async fn handle_message(
             TakerToMakerMessage::SignReceiversContractTx(_) => "SignReceiversContractTx",
             TakerToMakerMessage::HashPreimage(_) => "HashPreimage",
             TakerToMakerMessage::PrivateKeyHandover(_) => "PrivateKeyHandover",
+            TakerToMakerMessage::GiveMakerAddresses(_) => "GiveMakerAddresses",
         }
     );
-    log::debug!("{:#?}", request);
-
-    let outgoing_message = match connection_state.allowed_message {
-        ExpectedMessage::TakerHello => {
-            if let TakerToMakerMessage::TakerHello(_) = request {
-                connection_state.allowed_message = ExpectedMessage::NewlyConnectedTaker;
-                None
-            } else {
-                return Err(Error::Protocol("Expected Taker Hello Message"));
-            }
+    tracing::debug!("{:#?}", request);
+
+    let next_phase = connection_state.phase.next(&request)?;
+    //disconnecting takers asking for an offer or starting a coinswap while paused, the
+    // taker treats the maker as down and routes through other makers instead
+    //a config error so the taker isnt counted as misbehaving
+    if matches!(
+        request,
+        TakerToMakerMessage::GiveOffer(_) | TakerToMakerMessage::SignSendersContractTx(_)
+    ) && is_maker_paused()
+    {
+        return Err(Error::Config(vec!["maker is paused".to_string()]));
+    }
+    //coinswap messages must be of the connection's session, see swap_sessions.rs
+    if let Some(session_id) = request.session_id() {
+        if connection_state.session_id != Some(session_id) {
+            return Err(Error::Protocol("message of another session"));
         }
-        ExpectedMessage::NewlyConnectedTaker => match request {
-            TakerToMakerMessage::GiveOffer(_) => {
-                let max_size = wallet.read().unwrap().get_offer_maxsize_cache();
-                let tweakable_point = wallet.read().unwrap().get_tweakable_keypair().1;
-                connection_state.allowed_message = ExpectedMessage::SignSendersContractTx;
-                Some(MakerToTakerMessage::Offer(Offer {
-                    absolute_fee_sat: ABSOLUTE_FEE_SAT,
-                    amount_relative_fee_ppb: AMOUNT_RELATIVE_FEE_PPB,
-                    time_relative_fee_ppb: TIME_RELATIVE_FEE_PPB,
-                    required_confirms: REQUIRED_CONFIRMS,
-                    minimum_locktime: MINIMUM_LOCKTIME,
-                    max_size,
-                    min_size: MIN_SIZE,
-                    tweakable_point,
-                }))
-            }
-            TakerToMakerMessage::SignSendersContractTx(message) => {
-                connection_state.allowed_message = ExpectedMessage::ProofOfFunding;
-                handle_sign_senders_contract_tx(wallet, message, maker_behavior)?
-            }
-            TakerToMakerMessage::ProofOfFunding(proof) => {
-                connection_state.allowed_message =
-                    ExpectedMessage::ProofOfFundingORSendersAndReceiversContractSigs;
-                handle_proof_of_funding(connection_state, rpc, wallet, &proof)?
-            }
-            TakerToMakerMessage::SignReceiversContractTx(message) => {
-                connection_state.allowed_message = ExpectedMessage::HashPreimage;
-                handle_sign_receivers_contract_tx(wallet, message)?
-            }
-            TakerToMakerMessage::HashPreimage(message) => {
-                connection_state.allowed_message = ExpectedMessage::PrivateKeyHandover;
-                handle_hash_preimage(wallet, message)?
-            }
-            _ => {
-                return Err(Error::Protocol("Unexpected Newly Connected Taker message"));
-            }
-        },
-        ExpectedMessage::SignSendersContractTx => {
-            if let TakerToMakerMessage::SignSendersContractTx(message) = request {
-                connection_state.allowed_message = ExpectedMessage::ProofOfFunding;
-                handle_sign_senders_contract_tx(wallet, message, maker_behavior)?
-            } else {
-                return Err(Error::Protocol(
-                    "Expected Sign sender's contract transaction message",
-                ));
-            }
+        resume_session(&session_id)?;
+    }
+    let outgoing_message = match request {
+        TakerToMakerMessage::TakerHello(hello) => handle_taker_hello(connection_state, &hello)?,
+        TakerToMakerMessage::GiveOffer(_) => {
+            let maker_settings = &connection_state.maker_settings;
+            let max_size = offer_max_size(&wallet, maker_settings);
+            let tweakable_point = connection_state.tweakable_keypair.1;
+            let mut offer = Offer {
+                absolute_fee_sat: maker_settings.absolute_fee_sat,
+                amount_relative_fee_ppb: maker_settings.amount_relative_fee_ppb,
+                time_relative_fee_ppb: maker_settings.time_relative_fee_ppb,
+                required_confirms: REQUIRED_CONFIRMS,
+                required_confirms_tiers: maker_settings.tiers.clone(),
+                minimum_locktime: MINIMUM_LOCKTIME,
+                maximum_locktime: Some(MAXIMUM_LOCKTIME),
+                minimum_contract_react_time: Some(MINIMUM_CONTRACT_REACT_TIME),
+                max_size,
+                min_size: maker_settings.min_size,
+                tweakable_point,
+                fidelity_bond_proof: connection_state.fidelity_bond_proof.clone().map(Box::new),
+                expires_at: Some(chrono::Utc::now().timestamp() + OFFER_EXPIRY_SECS as i64),
+                identity: None,
+            };
+            offer.identity = Some(sign_offer(&connection_state.identity_key, &offer));
+            Some(MakerToTakerMessage::Offer(offer))
         }
-        ExpectedMessage::ProofOfFunding => {
-            if let TakerToMakerMessage::ProofOfFunding(proof) = request {
-                connection_state.allowed_message =
-                    ExpectedMessage::ProofOfFundingORSendersAndReceiversContractSigs;
-                handle_proof_of_funding(connection_state, rpc, wallet, &proof)?
-            } else {
-                return Err(Error::Protocol("Expected proof of funding message"));
+        TakerToMakerMessage::GiveMakerAddresses(_) => {
+            if connection_state.gave_maker_addresses {
+                return Err(Error::Protocol("maker addresses already given"));
             }
+            connection_state.gave_maker_addresses = true;
+            let addresses = connection_state
+                .gossip_book
+                .read()
+                .unwrap()
+                .sample(MAX_GOSSIP_ADDRESSES, chrono::Utc::now().timestamp());
+            Some(MakerToTakerMessage::MakerAddresses(MakerAddresses {
+                addresses,
+            }))
         }
-        ExpectedMessage::ProofOfFundingORSendersAndReceiversContractSigs => {
-            match request {
-                TakerToMakerMessage::ProofOfFunding(proof) => {
-                    connection_state.allowed_message =
-                        ExpectedMessage::ProofOfFundingORSendersAndReceiversContractSigs;
-                    handle_proof_of_funding(connection_state, rpc, wallet, &proof)?
-                }
-                TakerToMakerMessage::SendersAndReceiversContractSigs(message) => {
-                    // Nothing to send. Maker now creates and broadcasts his funding Txs
-                    connection_state.allowed_message = ExpectedMessage::SignReceiversContractTx;
-                    handle_senders_and_receivers_contract_sigs(
-                        connection_state,
-                        rpc,
-                        wallet,
-                        message,
-                    )
-                    .await?
-                }
-                _ => {
-                    return Err(Error::Protocol(
-                        "Expected proof of funding or sender's and reciever's contract signatures",
-                    ));
-                }
-            }
+        TakerToMakerMessage::SignSendersContractTx(message) => {
+            let session_id = message.session_id;
+            let reply = handle_sign_senders_contract_tx(
+                wallet,
+                message,
+                maker_behavior,
+                &connection_state.maker_settings,
+                &connection_state.tweakable_keypair.0,
+            )?;
+            accept_session(&session_id);
+            reply
         }
-        ExpectedMessage::SignReceiversContractTx => {
-            if let TakerToMakerMessage::SignReceiversContractTx(message) = request {
-                connection_state.allowed_message = ExpectedMessage::HashPreimage;
-                handle_sign_receivers_contract_tx(wallet, message)?
-            } else {
-                return Err(Error::Protocol("Expected reciever's contract transaction"));
-            }
+        TakerToMakerMessage::ProofOfFunding(proof) => handle_proof_of_funding(
+            connection_state,
+            rpc,
+            wallet,
+            &proof,
+            min_funding_tx_fee_rate,
+        )?,
+        TakerToMakerMessage::SendersAndReceiversContractSigs(message) => {
+            // Nothing to send. Maker now creates and broadcasts his funding Txs
+            handle_senders_and_receivers_contract_sigs(
+                connection_state,
+                rpc,
+                wallet,
+                message,
+                maker_behavior,
+            )
+            .await?
         }
-        ExpectedMessage::HashPreimage => {
-            if let TakerToMakerMessage::HashPreimage(message) = request {
-                connection_state.allowed_message = ExpectedMessage::PrivateKeyHandover;
-                handle_hash_preimage(wallet, message)?
-            } else {
-                return Err(Error::Protocol("Expected hash preimgae"));
-            }
+        TakerToMakerMessage::SignReceiversContractTx(message) => {
+            handle_sign_receivers_contract_tx(wallet, message, maker_behavior)?
         }
-        ExpectedMessage::PrivateKeyHandover => {
-            if let TakerToMakerMessage::PrivateKeyHandover(message) = request {
-                // Nothing to send. Succesfully completed swap
-                handle_private_key_handover(wallet, message)?
-            } else {
-                return Err(Error::Protocol("expected privatekey handover"));
-            }
+        TakerToMakerMessage::HashPreimage(message) => {
+            handle_hash_preimage(wallet, message, maker_behavior)?
+        }
+        TakerToMakerMessage::PrivateKeyHandover(message) => {
+            // Nothing to send. Succesfully completed swap
+            let session_id = message.session_id;
+            let reply = handle_private_key_handover(wallet, message)?;
+            complete_session(&session_id);
+            reply
         }
     };
+    connection_state.phase = next_phase;
 
     match outgoing_message {
         Some(reply_message) => {
-            log::info!(
+            tracing::info!(
                 "[{}] <=== {} ",
-                from_addrs.port(),
+                from_addrs.id(),
                 match reply_message {
                     MakerToTakerMessage::MakerHello(_) => "MakerHello",
+                    MakerToTakerMessage::IdentityProof(_) => "IdentityProof",
                     MakerToTakerMessage::Offer(_) => "Offer",
                     MakerToTakerMessage::SendersContractSig(_) => "SendersContractSig",
                     MakerToTakerMessage::SignSendersAndReceiversContractTxes(_) =>
                         "SignSendersAndReceiversContractTxes",
                     MakerToTakerMessage::ReceiversContractSig(_) => "ReceiversContractSig",
                     MakerToTakerMessage::PrivateKeyHandover(_) => "PrivateKeyHandover",
+                    MakerToTakerMessage::MakerAddresses(_) => "MakerAddresses",
                 }
             );
-            log::debug!("{:#?}", reply_message);
+            tracing::debug!("{:#?}", reply_message);
             Ok(Some(reply_message))
         }
         None => Ok(None),
     }
 }
 
+//the taker takes up the session offered in the maker's hello, or carries on an earlier session
+// of its coinswap over this new connection
+//a taker which challenged the maker's identity gets the proof, see maker_identity.rs
+fn handle_taker_hello(
+    connection_state: &mut ConnectionState,
+    hello: &TakerHello,
+) -> Result<Option<MakerToTakerMessage>, Error> {
+    match hello.session_id {
+        Some(session_id) if session_id == connection_state.offered_session_id => {
+            start_session(&session_id, connection_state.idle_connection_timeout as i64)
+        }
+        Some(session_id) => resume_session(&session_id)?,
+        None => (),
+    }
+    connection_state.session_id = hello.session_id;
+    Ok(hello.identity_challenge.as_ref().map(|challenge| {
+        let is_own_address = connection_state
+            .own_hostnames
+            .iter()
+            .any(|hostname| hostname == onion_hostname(&challenge.maker_address));
+        if !is_own_address {
+            tracing::warn!(
+                "taker challenged the identity of {}, which isnt this maker's address",
+                challenge.maker_address
+            );
+        }
+        MakerToTakerMessage::IdentityProof(IdentityProof {
+            identity: is_own_address
+                .then(|| sign_identity_challenge(&connection_state.identity_key, challenge)),
+        })
+    }))
+}
+
+//while fees are low and no taker is connected, merges the wallet's small confirmed coins so
+// the next funding txes need fewer inputs
+fn consolidate_small_coins(
+    rpc: &Client,
+    wallet: &RwLock<Wallet>,
+    maker_settings: &MakerSettings,
+    mempool_fee_rate: Option<u64>,
+) -> Result<(), Error> {
+    let fee_rate = match (maker_settings.consolidation_max_fee_rate, mempool_fee_rate) {
+        (Some(max_fee_rate), Some(fee_rate)) if fee_rate <= max_fee_rate * 1000 => fee_rate,
+        _ => return Ok(()),
+    };
+    let mut wallet = wallet.write().unwrap();
+    let unspents = wallet
+        .list_unreserved_unspent_from_wallet(rpc)?
+        .into_iter()
+        .filter(|(u, _)| u.confirmations > 0)
+        .collect::<Vec<_>>();
+    //each account is consolidated on its own, back into the same account
+    for (account, suggestion) in suggest_account_consolidations(
+        &coin_sizes_by_account(&unspents),
+        fee_rate,
+        Some(maker_settings.consolidation_batch_size),
+    ) {
+        let destination = match account {
+            WalletAccount::Receive => wallet.get_next_external_address(rpc)?,
+            WalletAccount::Swap | WalletAccount::Change => {
+                wallet.get_next_internal_addresses(rpc, 1)?.remove(0)
+            }
+        };
+        let tx = wallet.create_direct_send(
+            rpc,
+            fee_rate,
+            SendAmount::Max,
+            Destination::Address(destination),
+            &suggestion
+                .coins
+                .iter()
+                .map(|c| CoinToSpend::LongForm(*c))
+                .collect::<Vec<CoinToSpend>>(),
+        )?;
+        let txid = rpc.send_raw_transaction(&tx)?;
+        tracing::info!(
+            "consolidated {} coins of {} sat of the {} account in {}, fee rate = {} sat/vb",
+            suggestion.coins.len(),
+            suggestion.total_value,
+            account,
+            txid,
+            fee_rate / 1000
+        );
+    }
+    Ok(())
+}
+
+//starts a coinswap of the maker's earnings as a taker, in its own thread with its own Wallet
+// on the wallet file, the maker doesnt accept takers until it is done so that only one of
+// them writes the file
+fn start_rebalance(
+    rpc: &Arc<Client>,
+    wallet: &RwLock<Wallet>,
+    maker_settings: &MakerSettings,
+    fee_rate: u64,
+    min_funding_tx_fee_rate: u64,
+    own_addresses: &[String],
+) -> Result<Option<thread::JoinHandle<Result<(), Error>>>, Error> {
+    let wallet = wallet.read().unwrap();
+    let coin_values = wallet
+        .list_unspent_from_wallet(rpc, false, false)?
+        .iter()
+        .filter(|(u, _)| u.confirmations > 0)
+        .map(|(u, _)| u.amount.as_sat())
+        .collect::<Vec<u64>>();
+    let trigger = match rebalance_trigger(&coin_values, maker_settings) {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+    let send_amount = match rebalance_amount(
+        &wallet.get_swap_history()?,
+        chrono::Utc::now().timestamp(),
+        maker_settings,
+    ) {
+        Some(a) => a,
+        None => {
+            tracing::info!(
+                "coins need rebalancing ({:?}) but not enough was earned",
+                trigger
+            );
+            return Ok(None);
+        }
+    };
+    tracing::info!(
+        "rebalancing coins ({:?}), coinswapping {} sat as a taker",
+        trigger,
+        send_amount
+    );
+    let mut taker_wallet = wallet.reload()?;
+    let rpc = Arc::clone(rpc);
+    let config = TakerConfig {
+        send_amount,
+        maker_count: REBALANCE_MAKER_COUNT,
+        tx_count: REBALANCE_TX_COUNT,
+        required_confirms: REQUIRED_CONFIRMS,
+        fee_rate,
+        min_funding_tx_fee_rate,
+        account: None,
+    };
+    //our own addresses are only tried if there arent enough other makers, and fail then
+    let own_addresses = own_addresses.to_vec();
+    Ok(Some(thread::spawn(move || {
+        start_taker_avoiding_makers(&rpc, &mut taker_wallet, config, &own_addresses, None)
+    })))
+}
+
+fn finish_rebalance(
+    rpc: &Arc<Client>,
+    wallet: &RwLock<Wallet>,
+    rebalance: thread::JoinHandle<Result<(), Error>>,
+) -> Result<(), Error> {
+    match rebalance.join() {
+        Ok(Ok(())) => tracing::info!("rebalancing coinswap finished"),
+        Ok(Err(e)) => tracing::warn!("rebalancing coinswap failed: {:?}", e),
+        Err(_) => tracing::warn!("rebalancing coinswap panicked"),
+    }
+    let reloaded = wallet.read().unwrap().reload()?;
+    let mut wallet = wallet.write().unwrap();
+    *wallet = reloaded;
+    wallet.refresh_offer_maxsize_cache(Arc::clone(rpc))
+}
+
+//in sat per 1000 vbytes, None if the node doesnt have an estimate e.g. on regtest
+fn get_mempool_fee_rate(rpc: &Client) -> Option<u64> {
+    match rpc.estimate_smart_fee(MEMPOOL_FEE_RATE_CONF_TARGET, None) {
+        Ok(estimate) => estimate.fee_rate.map(|fee_rate| fee_rate.as_sat()),
+        Err(e) => {
+            tracing::debug!("unable to estimate mempool fee rate: {:?}", e);
+            None
+        }
+    }
+}
+
+//with maker.dynamic_fee_reference_rate set the absolute fee rises with the mempool fee rate
+// above that reference rate, it is never less than absolute_fee_sat
+fn scaled_absolute_fee(maker_settings: &MakerSettings, mempool_fee_rate: Option<u64>) -> u64 {
+    let reference_fee_rate = match maker_settings.dynamic_fee_reference_rate {
+        Some(rate) if rate > 0 => rate * 1000,
+        _ => return maker_settings.absolute_fee_sat,
+    };
+    match mempool_fee_rate {
+        Some(fee_rate) if fee_rate > reference_fee_rate => {
+            maker_settings.absolute_fee_sat * fee_rate / reference_fee_rate
+        }
+        _ => maker_settings.absolute_fee_sat,
+    }
+}
+
+//the wallet balance limits the offer size as well as max_size in the settings
+fn offer_max_size(wallet: &Arc<RwLock<Wallet>>, maker_settings: &MakerSettings) -> u64 {
+    let wallet_max_size = wallet.read().unwrap().get_offer_maxsize_cache();
+    match maker_settings.max_size {
+        Some(max_size) => wallet_max_size.min(max_size),
+        None => wallet_max_size,
+    }
+}
+
 fn handle_sign_senders_contract_tx(
     wallet: Arc<RwLock<Wallet>>,
     message: SignSendersContractTx,
     maker_behavior: MakerBehavior,
+    maker_settings: &MakerSettings,
+    tweakable_privkey: &SecretKey,
 ) -> Result<Option<MakerToTakerMessage>, Error> {
     if let MakerBehavior::CloseOnSignSendersContractTx = maker_behavior {
         return Err(Error::Protocol(
             "closing connection early due to special maker behavior",
         ));
     }
-    let tweakable_privkey = wallet.read().unwrap().get_tweakable_keypair().0;
+    record_swap_id(message.hashvalue);
     //TODO this for loop could be replaced with an iterator and map
     //see that other example where Result<> inside an iterator is used
     let mut sigs = Vec::<Signature>::new();
@@ -522,15 +1702,21 @@ fn handle_sign_senders_contract_tx(
             message.hashvalue,
             message.locktime,
             MINIMUM_LOCKTIME,
-            &tweakable_privkey,
+            tweakable_privkey,
             &mut wallet.write().unwrap(),
         )?;
-        sigs.push(sig);
+        sigs.push(if maker_behavior == MakerBehavior::SendInvalidSignatures {
+            invalid_signature(tweakable_privkey)
+        } else {
+            sig
+        });
         funding_txids.push(txinfo.senders_contract_tx.input[0].previous_output.txid);
         total_amount += txinfo.funding_input_value;
     }
-    if total_amount >= MIN_SIZE && total_amount < wallet.read().unwrap().get_offer_maxsize_cache() {
-        log::info!(
+    if total_amount >= maker_settings.min_size
+        && total_amount < offer_max_size(&wallet, maker_settings)
+    {
+        tracing::info!(
             "requested contracts amount={}, for funding txids = {:?}",
             Amount::from_sat(total_amount),
             funding_txids
@@ -539,7 +1725,7 @@ fn handle_sign_senders_contract_tx(
             SendersContractSig { sigs },
         )))
     } else {
-        log::info!(
+        tracing::info!(
             "rejecting contracts for amount={} because not enough funds",
             Amount::from_sat(total_amount)
         );
@@ -547,43 +1733,71 @@ fn handle_sign_senders_contract_tx(
     }
 }
 
+//a signature of some other message, for MakerBehavior::SendInvalidSignatures
+fn invalid_signature(privkey: &SecretKey) -> Signature {
+    Secp256k1::signing_only().sign(&Message::from_slice(&[1u8; 32]).unwrap(), privkey)
+}
+
+//every funding tx of the proof must pay at least min_funding_tx_fee_rate or be confirmed already,
+// returns Error::FundingTxFeeRate otherwise
+fn verify_proof_fee_rates<R: RpcApi>(
+    rpc: &R,
+    proof: &ProofOfFunding,
+    min_funding_tx_fee_rate: u64,
+) -> Result<(), Error> {
+    for funding_info in &proof.confirmed_funding_txes {
+        verify_funding_tx_fee_rate(rpc, &funding_info.funding_tx, min_funding_tx_fee_rate)?;
+    }
+    Ok(())
+}
+
 fn handle_proof_of_funding(
     connection_state: &mut ConnectionState,
     rpc: Arc<Client>,
     wallet: Arc<RwLock<Wallet>>,
     proof: &ProofOfFunding,
+    min_funding_tx_fee_rate: u64,
 ) -> Result<Option<MakerToTakerMessage>, Error> {
-    let mut funding_output_indexes = Vec::<u32>::new();
-    let mut funding_outputs = Vec::<&TxOut>::new();
-    let mut incoming_swapcoin_keys = Vec::<(SecretKey, PublicKey, SecretKey)>::new();
+    let mut verified_fundings = Vec::<VerifiedFunding>::new();
     if proof.confirmed_funding_txes.is_empty() {
         return Err(Error::Protocol("zero funding txes provided"));
     }
+    //the confirmations required and the longest locktime accepted depend on the total amount,
+    // so find it before checking any tx
+    let tiers = connection_state.maker_settings.tiers.clone();
+    let proof_amount = proof
+        .confirmed_funding_txes
+        .iter()
+        .filter_map(|funding_info| {
+            find_funding_output(
+                &funding_info.funding_tx,
+                &funding_info.multisig_redeemscript,
+            )
+        })
+        .map(|(_index, funding_output)| funding_output.value)
+        .sum::<u64>();
+    let maximum_locktime = calculate_maximum_locktime(Some(MAXIMUM_LOCKTIME), &tiers, proof_amount)
+        .unwrap_or(MAXIMUM_LOCKTIME);
+    if proof.next_locktime > maximum_locktime {
+        return Err(Error::Protocol("next locktime too long"));
+    }
+    let required_confirms = calculate_required_confirms(REQUIRED_CONFIRMS, &tiers, proof_amount);
+    verify_proof_fee_rates(rpc.as_ref(), proof, min_funding_tx_fee_rate)?;
     for funding_info in &proof.confirmed_funding_txes {
-        //check that the claimed multisig redeemscript is in the transaction
-        log::debug!(
+        tracing::debug!(
             "Proof of Funding: \ntx = {:#?}\nMultisig_Reedimscript = {:x}",
             funding_info.funding_tx,
             funding_info.multisig_redeemscript
         );
-        let (funding_output_index, funding_output) = match find_funding_output(
-            &funding_info.funding_tx,
-            &funding_info.multisig_redeemscript,
-        ) {
-            Some(fo) => fo,
-            None => return Err(Error::Protocol("funding tx doesnt pay to multisig")),
-        };
-        funding_output_indexes.push(funding_output_index);
-        funding_outputs.push(funding_output);
-        let verify_result = contracts::verify_proof_of_funding(
+        verified_fundings.push(contracts::verify_proof_of_funding(
             Arc::clone(&rpc),
             &mut wallet.write().unwrap(),
+            connection_state.tweakable_keypair,
             funding_info,
-            funding_output_index,
             proof.next_locktime,
-            MINIMUM_LOCKTIME,
-        )?;
-        incoming_swapcoin_keys.push(verify_result);
+            MINIMUM_CONTRACT_REACT_TIME,
+            required_confirms,
+        )?);
     }
 
     //check that all the contract redeemscripts involve the same hashvalue
@@ -595,6 +1809,7 @@ fn handle_proof_of_funding(
             .contract_redeemscript,
     )
     .map_err(|_| Error::Protocol("unable to read hashvalue from contract"))?;
+    record_swap_id(hashvalue);
     for hv in confirmed_funding_txes_hashvalue_check_iter
         .map(|info| read_hashvalue_from_contract(&info.contract_redeemscript))
     {
@@ -605,17 +1820,16 @@ fn handle_proof_of_funding(
         }
     }
 
-    log::debug!("proof of funding valid, creating own funding txes");
+    tracing::debug!("proof of funding valid, creating own funding txes");
 
-    connection_state.incoming_swapcoins = Some(Vec::<IncomingSwapCoin>::new());
-    for (funding_info, &funding_output_index, &funding_output, &incoming_swapcoin_keys) in izip!(
-        proof.confirmed_funding_txes.iter(),
-        funding_output_indexes.iter(),
-        funding_outputs.iter(),
-        incoming_swapcoin_keys.iter()
-    ) {
+    let mut incoming_swapcoins = Vec::<IncomingSwapCoin>::new();
+    for (funding_info, verified_funding) in proof
+        .confirmed_funding_txes
+        .iter()
+        .zip(verified_fundings.iter())
+    {
         let (pubkey1, pubkey2) =
-            read_pubkeys_from_multisig_redeemscript(&funding_info.multisig_redeemscript)
+            read_pubkeys_from_multisig_redeemscript(&verified_funding.multisig_redeemscript)
                 .ok_or(Error::Protocol("invalid multisig redeemscript"))?;
         wallet
             .read()
@@ -629,49 +1843,44 @@ fn handle_proof_of_funding(
         wallet
             .read()
             .unwrap()
-            .import_wallet_contract_redeemscript(&rpc, &funding_info.contract_redeemscript)?;
-        let my_receivers_contract_tx = contracts::create_receivers_contract_tx(
-            OutPoint {
-                txid: funding_info.funding_tx.txid(),
-                vout: funding_output_index,
-            },
-            funding_output.value,
-            &funding_info.contract_redeemscript,
-        );
-        let (coin_privkey, coin_other_pubkey, hashlock_privkey) = incoming_swapcoin_keys;
-        log::debug!(
-            "Adding incoming_swapcoin contract_tx = {:?} fo = {:?}",
-            my_receivers_contract_tx.clone(),
-            funding_output
+            .import_wallet_contract_redeemscript(&rpc, &verified_funding.contract_redeemscript)?;
+        let incoming_swapcoin = verified_funding.to_incoming_swapcoin();
+        tracing::debug!(
+            "Adding incoming_swapcoin contract_tx = {:?} funding_outpoint = {:?}",
+            incoming_swapcoin.contract_tx,
+            verified_funding.funding_outpoint
         );
-        connection_state
-            .incoming_swapcoins
-            .as_mut()
-            .unwrap()
-            .push(IncomingSwapCoin::new(
-                coin_privkey,
-                coin_other_pubkey,
-                my_receivers_contract_tx.clone(),
-                funding_info.contract_redeemscript.clone(),
-                hashlock_privkey,
-                funding_output.value,
-            ));
+        incoming_swapcoins.push(incoming_swapcoin);
     }
+    connection_state.incoming_swapcoins = Some(incoming_swapcoins);
 
     //set up the next coinswap in the route
-    let incoming_amount = funding_outputs.iter().map(|o| o.value).sum::<u64>();
-    let coinswap_fees = calculate_coinswap_fee(
-        ABSOLUTE_FEE_SAT,
-        AMOUNT_RELATIVE_FEE_PPB,
-        TIME_RELATIVE_FEE_PPB,
+    let incoming_amount = verified_fundings
+        .iter()
+        .map(|v| v.funding_amount)
+        .sum::<u64>();
+    let coinswap_fees = apply_fee_multiplier(
+        calculate_coinswap_fee(
+            connection_state.maker_settings.absolute_fee_sat,
+            connection_state.maker_settings.amount_relative_fee_ppb,
+            connection_state.maker_settings.time_relative_fee_ppb,
+            incoming_amount,
+            1, //time_in_blocks just 1 for now
+        ),
+        &tiers,
         incoming_amount,
-        1, //time_in_blocks just 1 for now
     );
     let miner_fees_paid_by_taker =
         MAKER_FUNDING_TX_VBYTE_SIZE * proof.next_fee_rate * (proof.next_coinswap_info.len() as u64)
             / 1000;
     let outgoing_amount = incoming_amount - coinswap_fees - miner_fees_paid_by_taker;
 
+    //already checked when the settings were loaded
+    let cold_timelock_xpub = connection_state
+        .maker_settings
+        .cold_timelock_xpub
+        .as_ref()
+        .map(|xpub| ExtendedPubKey::from_str(xpub).unwrap());
     let (my_funding_txes, outgoing_swapcoins, total_miner_fee) =
         wallet.write().unwrap().initalize_coinswap(
             &rpc,
@@ -689,9 +1898,13 @@ fn handle_proof_of_funding(
             hashvalue,
             proof.next_locktime,
             proof.next_fee_rate,
+            //only takers avoid change, a maker keeps coinswapping from the same wallet anyway
+            0,
+            cold_timelock_xpub.as_ref(),
+            None,
         )?;
 
-    log::info!(
+    tracing::info!(
         "Proof of funding valid. Incoming funding txes, txids = {:?}",
         proof
             .confirmed_funding_txes
@@ -699,7 +1912,7 @@ fn handle_proof_of_funding(
             .map(|cft| cft.funding_tx.txid())
             .collect::<Vec<Txid>>()
     );
-    log::info!(
+    tracing::info!(
         "incoming_amount={}, incoming_locktime={}, hashvalue={}",
         Amount::from_sat(incoming_amount),
         read_locktime_from_contract(&proof.confirmed_funding_txes[0].contract_redeemscript)
@@ -707,7 +1920,7 @@ fn handle_proof_of_funding(
         //unwrap() as format of contract_redeemscript already checked in verify_proof_of_funding
         hashvalue
     );
-    log::info!(
+    tracing::info!(
         concat!(
             "outgoing_amount={}, outgoing_locktime={}, miner fees paid by taker={}, ",
             "actual miner fee={}, coinswap_fees={}, POTENTIALLY EARNED={}"
@@ -719,10 +1932,34 @@ fn handle_proof_of_funding(
         Amount::from_sat(coinswap_fees),
         Amount::from_sat(incoming_amount - outgoing_amount - total_miner_fee)
     );
+    //for `teleport maker stats`, a failure here shouldnt stop the coinswap
+    if let Err(e) = wallet
+        .read()
+        .unwrap()
+        .add_swap_history_entry(SwapHistoryEntry {
+            hashvalue,
+            incoming_amount_sat: incoming_amount,
+            outgoing_amount_sat: outgoing_amount,
+            coinswap_fee_sat: coinswap_fees,
+            miner_fee_sat: total_miner_fee,
+            started_timestamp: chrono::Utc::now().timestamp(),
+            completed_timestamp: None,
+        })
+    {
+        tracing::warn!("unable to save swap history: {:?}", e);
+    }
+    spawn_hook(
+        "maker",
+        HookEvent::SwapStart {
+            swap_id: hashvalue,
+            amount_sat: incoming_amount,
+        },
+    );
+    connection_state.swap_id = Some(hashvalue);
 
     connection_state.pending_funding_txes = Some(my_funding_txes);
     connection_state.outgoing_swapcoins = Some(outgoing_swapcoins);
-    log::debug!(
+    tracing::debug!(
         "Incoming_swapcoins = {:#?}\nOutgoing_swapcoins = {:#?}",
         connection_state.incoming_swapcoins,
         connection_state.outgoing_swapcoins,
@@ -754,11 +1991,31 @@ fn handle_proof_of_funding(
     ))
 }
 
+//the incoming funding txes may have been reorged out since the proof of funding was checked,
+// then the maker mustnt broadcast its own funding txes on the strength of it
+fn check_incoming_funding_still_confirmed(
+    rpc: &Client,
+    incoming_swapcoins: &[IncomingSwapCoin],
+) -> Result<(), Error> {
+    for incoming_swapcoin in incoming_swapcoins {
+        let funding_outpoint = incoming_swapcoin.contract_tx.input[0].previous_output;
+        //without the mempool only outputs of confirmed txes are found
+        if rpc
+            .get_tx_out(&funding_outpoint.txid, funding_outpoint.vout, Some(false))?
+            .is_none()
+        {
+            return Err(Error::Protocol("incoming funding tx no longer confirmed"));
+        }
+    }
+    Ok(())
+}
+
 async fn handle_senders_and_receivers_contract_sigs(
     connection_state: &mut ConnectionState,
     rpc: Arc<Client>,
     wallet: Arc<RwLock<Wallet>>,
     sigs: SendersAndReceiversContractSigs,
+    maker_behavior: MakerBehavior,
 ) -> Result<Option<MakerToTakerMessage>, Error> {
     //if incoming/outgoing_swapcoin are None then the app should crash because
     //its a logic error, so no error handling, just use unwrap()
@@ -767,12 +2024,17 @@ async fn handle_senders_and_receivers_contract_sigs(
     if sigs.receivers_sigs.len() != incoming_swapcoins.len() {
         return Err(Error::Protocol("invalid number of recv signatures"));
     }
-    for (receivers_sig, incoming_swapcoin) in
-        sigs.receivers_sigs.iter().zip(incoming_swapcoins.iter())
-    {
-        if !incoming_swapcoin.verify_contract_tx_sig(receivers_sig) {
-            return Err(Error::Protocol("invalid recv signature"));
-        }
+    if !contracts::verify_all_parallel(
+        &sigs
+            .receivers_sigs
+            .iter()
+            .zip(incoming_swapcoins.iter())
+            .collect::<Vec<_>>(),
+        |(receivers_sig, incoming_swapcoin)| {
+            incoming_swapcoin.verify_contract_tx_sig(receivers_sig)
+        },
+    ) {
+        return Err(Error::Protocol("invalid recv signature"));
     }
     sigs.receivers_sigs
         .iter()
@@ -785,11 +2047,15 @@ async fn handle_senders_and_receivers_contract_sigs(
     if sigs.senders_sigs.len() != outgoing_swapcoins.len() {
         return Err(Error::Protocol("invalid number of send signatures"));
     }
-    for (senders_sig, outgoing_swapcoin) in sigs.senders_sigs.iter().zip(outgoing_swapcoins.iter())
-    {
-        if !outgoing_swapcoin.verify_contract_tx_sig(senders_sig) {
-            return Err(Error::Protocol("invalid send signature"));
-        }
+    if !contracts::verify_all_parallel(
+        &sigs
+            .senders_sigs
+            .iter()
+            .zip(outgoing_swapcoins.iter())
+            .collect::<Vec<_>>(),
+        |(senders_sig, outgoing_swapcoin)| outgoing_swapcoin.verify_contract_tx_sig(senders_sig),
+    ) {
+        return Err(Error::Protocol("invalid send signature"));
     }
     sigs.senders_sigs
         .iter()
@@ -798,15 +2064,20 @@ async fn handle_senders_and_receivers_contract_sigs(
             outgoing_swapcoin.others_contract_sig = Some(senders_sig)
         });
 
+    check_incoming_funding_still_confirmed(&rpc, incoming_swapcoins)?;
+
     let wallet_label = wallet.read().unwrap().get_core_wallet_label();
-    let internal_addresses = wallet
-        .read()
-        .unwrap()
-        .get_next_internal_addresses(&rpc, incoming_swapcoins.len() as u32)?;
+    //every spend gets an address of its own
+    let addresses = DestinationProvider::from_settings().next_addresses(
+        &wallet.write().unwrap(),
+        &rpc,
+        (incoming_swapcoins.len() + outgoing_swapcoins.len()) as u32,
+    )?;
+    let (incoming_addresses, outgoing_addresses) = addresses.split_at(incoming_swapcoins.len());
     register_coinswap_with_watchtowers(ContractsInfo {
         contract_txes: incoming_swapcoins
             .iter()
-            .zip(internal_addresses.iter())
+            .zip(incoming_addresses.iter())
             .map(|(isc, addr)| ContractTransaction {
                 tx: isc.get_fully_signed_contract_tx(),
                 redeemscript: isc.contract_redeemscript.clone(),
@@ -815,17 +2086,19 @@ async fn handle_senders_and_receivers_contract_sigs(
                 ),
                 timelock_spend: None,
                 timelock_spend_broadcasted: false,
+                hashlock_spend_broadcasted: false,
             })
             .chain(
                 outgoing_swapcoins
                     .iter()
-                    .zip(internal_addresses.iter())
+                    .zip(outgoing_addresses.iter())
                     .map(|(osc, addr)| ContractTransaction {
                         tx: osc.get_fully_signed_contract_tx(),
                         redeemscript: osc.contract_redeemscript.clone(),
                         hashlock_spend_without_preimage: None,
-                        timelock_spend: Some(osc.create_timelock_spend(addr)),
+                        timelock_spend: osc.create_timelock_spend(addr),
                         timelock_spend_broadcasted: false,
+                        hashlock_spend_broadcasted: false,
                     }),
             )
             .collect::<Vec<ContractTransaction>>(),
@@ -844,12 +2117,23 @@ async fn handle_senders_and_receivers_contract_sigs(
 
     let mut my_funding_txids = Vec::<Txid>::new();
     for my_funding_tx in connection_state.pending_funding_txes.as_ref().unwrap() {
-        log::debug!("Broadcasting My Funding Tx : {:#?}", my_funding_tx);
+        tracing::debug!("Broadcasting My Funding Tx : {:#?}", my_funding_tx);
         let txid = rpc.send_raw_transaction(my_funding_tx)?;
         assert_eq!(txid, my_funding_tx.txid());
         my_funding_txids.push(txid);
     }
-    log::info!("Broadcasted My Funding Txes: {:?}", my_funding_txids);
+    tracing::info!("Broadcasted My Funding Txes: {:?}", my_funding_txids);
+
+    if maker_behavior == MakerBehavior::BroadcastContractAfterSetup {
+        for incoming_swapcoin in incoming_swapcoins.iter() {
+            let txid =
+                rpc.send_raw_transaction(&incoming_swapcoin.get_fully_signed_contract_tx())?;
+            tracing::info!(
+                "Broadcasted incoming contract tx {} due to special maker behavior",
+                txid
+            );
+        }
+    }
 
     //set these to None which might be helpful in picking up logic errors later
     connection_state.incoming_swapcoins = None;
@@ -859,23 +2143,32 @@ async fn handle_senders_and_receivers_contract_sigs(
     Ok(None)
 }
 
+//each step of a coinswap is a fresh connection, the hashvalue is what ties them together in logs
+fn record_swap_id(hashvalue: Hash160) {
+    tracing::Span::current().record("swap_id", tracing::field::display(hashvalue));
+}
+
 fn handle_sign_receivers_contract_tx(
     wallet: Arc<RwLock<Wallet>>,
     message: SignReceiversContractTx,
+    maker_behavior: MakerBehavior,
 ) -> Result<Option<MakerToTakerMessage>, Error> {
     let mut sigs = Vec::<Signature>::new();
     for receivers_contract_tx_info in message.txes {
-        sigs.push(
-            //the fact that the peer knows the correct multisig_redeemscript is what ensures
-            //security here, a random peer out there who isnt involved in a coinswap wont know
-            //what the multisig_redeemscript is
-            wallet
-                .read()
-                .unwrap()
-                .find_outgoing_swapcoin(&receivers_contract_tx_info.multisig_redeemscript)
-                .ok_or(Error::Protocol("multisig_redeemscript not found"))?
-                .sign_contract_tx_with_my_privkey(&receivers_contract_tx_info.contract_tx)?,
-        );
+        //the fact that the peer knows the correct multisig_redeemscript is what ensures
+        //security here, a random peer out there who isnt involved in a coinswap wont know
+        //what the multisig_redeemscript is
+        let wallet_ref = wallet.read().unwrap();
+        let outgoing_swapcoin = wallet_ref
+            .find_outgoing_swapcoin(&receivers_contract_tx_info.multisig_redeemscript)
+            .ok_or(Error::Protocol("multisig_redeemscript not found"))?;
+        record_swap_id(outgoing_swapcoin.get_hashvalue());
+        sigs.push(if maker_behavior == MakerBehavior::SendInvalidSignatures {
+            invalid_signature(&outgoing_swapcoin.my_privkey)
+        } else {
+            outgoing_swapcoin
+                .sign_contract_tx_with_my_privkey(&receivers_contract_tx_info.contract_tx)?
+        });
     }
     Ok(Some(MakerToTakerMessage::ReceiversContractSig(
         ReceiversContractSig { sigs },
@@ -885,8 +2178,10 @@ fn handle_sign_receivers_contract_tx(
 fn handle_hash_preimage(
     wallet: Arc<RwLock<Wallet>>,
     message: HashPreimage,
+    maker_behavior: MakerBehavior,
 ) -> Result<Option<MakerToTakerMessage>, Error> {
     let hashvalue = Hash160::hash(&message.preimage);
+    record_swap_id(hashvalue);
     {
         let mut wallet_mref = wallet.write().unwrap();
         for multisig_redeemscript in message.senders_multisig_redeemscripts {
@@ -901,9 +2196,15 @@ fn handle_hash_preimage(
             }
             incoming_swapcoin.hash_preimage = Some(message.preimage);
         }
-        //TODO tell preimage to watchtowers
     }
-    log::info!("received preimage for hashvalue={}", hashvalue);
+    //so the watchtowers can claim the incoming contracts if they get broadcast while we're offline
+    let preimage = message.preimage;
+    tokio::spawn(async move {
+        if let Err(e) = register_hash_preimage_with_watchtowers(preimage).await {
+            tracing::warn!("unable to send hash preimage to watchtowers: {:?}", e);
+        }
+    });
+    tracing::info!("received preimage for hashvalue={}", hashvalue);
     let wallet_ref = wallet.read().unwrap();
     let mut swapcoin_private_keys = Vec::<SwapCoinPrivateKey>::new();
     for multisig_redeemscript in message.receivers_multisig_redeemscripts {
@@ -923,8 +2224,13 @@ fn handle_hash_preimage(
     }
 
     wallet_ref.update_swapcoins_list()?;
+    if maker_behavior == MakerBehavior::StallAtPrivkeyHandover {
+        tracing::info!("not handing over private keys due to special maker behavior");
+        return Ok(None);
+    }
     Ok(Some(MakerToTakerMessage::PrivateKeyHandover(
         PrivateKeyHandover {
+            session_id: message.session_id,
             swapcoin_private_keys,
         },
     )))
@@ -935,13 +2241,252 @@ fn handle_private_key_handover(
     message: PrivateKeyHandover,
 ) -> Result<Option<MakerToTakerMessage>, Error> {
     let mut wallet_ref = wallet.write().unwrap();
+    let mut hashvalue = None;
     for swapcoin_private_key in message.swapcoin_private_keys {
-        wallet_ref
+        let incoming_swapcoin = wallet_ref
             .find_incoming_swapcoin_mut(&swapcoin_private_key.multisig_redeemscript)
-            .ok_or(Error::Protocol("multisig_redeemscript not found"))?
-            .apply_privkey(swapcoin_private_key.key)?
+            .ok_or(Error::Protocol("multisig_redeemscript not found"))?;
+        incoming_swapcoin.apply_privkey(swapcoin_private_key.key)?;
+        hashvalue = Some(incoming_swapcoin.get_hashvalue());
     }
     wallet_ref.update_swapcoins_list()?;
-    log::info!("Successfully Completed Coinswap");
+    tracing::info!("Successfully Completed Coinswap");
+    if let Some(hashvalue) = hashvalue {
+        if let Err(e) =
+            wallet_ref.complete_swap_history_entry(hashvalue, chrono::Utc::now().timestamp())
+        {
+            tracing::warn!("unable to save swap history: {:?}", e);
+        }
+        spawn_hook("maker", HookEvent::SwapSuccess { swap_id: hashvalue });
+    }
     Ok(None)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::{Script, TxOut};
+    use bitcoincore_rpc::jsonrpc;
+
+    use crate::messages::{ConfirmedCoinSwapTxInfo, SESSION_ID_LEN};
+
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+
+    //a node which knows one tx, in its mempool paying fee sats for vsize vbytes or confirmed,
+    // and has no -txindex
+    struct FakeNode {
+        mempool_fee: Option<(u64, u64)>,
+        confirmations: Option<u32>,
+    }
+
+    impl RpcApi for FakeNode {
+        fn call<T: for<'a> serde::de::Deserialize<'a>>(
+            &self,
+            cmd: &str,
+            _args: &[serde_json::Value],
+        ) -> bitcoincore_rpc::Result<T> {
+            let not_found =
+                bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(jsonrpc::error::RpcError {
+                    code: -5,
+                    message: "No such mempool or blockchain transaction".to_string(),
+                    data: None,
+                }));
+            let txid = "00".repeat(32);
+            let result = match (cmd, self.mempool_fee, self.confirmations) {
+                ("getmempoolentry", Some((fee, vsize)), _) => serde_json::json!({
+                    "vsize": vsize, "time": 0, "height": 0, "descendantcount": 1,
+                    "descendantsize": vsize, "ancestorcount": 1, "ancestorsize": vsize,
+                    "wtxid": txid, "depends": [], "spentby": [], "bip125-replaceable": false,
+                    "fees": {
+                        "base": Amount::from_sat(fee).as_btc(),
+                        "modified": Amount::from_sat(fee).as_btc(),
+                        "ancestor": Amount::from_sat(fee).as_btc(),
+                        "descendant": Amount::from_sat(fee).as_btc(),
+                    },
+                }),
+                ("gettxout", _, Some(confirmations)) => serde_json::json!({
+                    "bestblock": txid, "confirmations": confirmations, "value": 0.001,
+                    "scriptPubKey": { "asm": "", "hex": "" }, "coinbase": false,
+                }),
+                ("gettxout", _, None) => serde_json::Value::Null,
+                _ => return Err(not_found),
+            };
+            Ok(serde_json::from_value(result)?)
+        }
+    }
+
+    #[test]
+    fn test_verify_proof_fee_rates() {
+        let key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let proof = ProofOfFunding {
+            session_id: [0; SESSION_ID_LEN],
+            confirmed_funding_txes: vec![ConfirmedCoinSwapTxInfo {
+                funding_tx: Transaction {
+                    version: 2,
+                    lock_time: 0,
+                    input: Vec::new(),
+                    output: vec![TxOut {
+                        value: 100_000,
+                        script_pubkey: Script::new(),
+                    }],
+                },
+                funding_tx_merkleproof: String::new(),
+                multisig_redeemscript: Script::new(),
+                multisig_key_nonce: key,
+                contract_redeemscript: Script::new(),
+                hashlock_key_nonce: key,
+            }],
+            next_coinswap_info: Vec::new(),
+            next_locktime: 0,
+            next_fee_rate: 0,
+        };
+        let node = |mempool_fee, confirmations| FakeNode {
+            mempool_fee,
+            confirmations,
+        };
+        //2 sat/vb in the mempool against a minimum of 1 and 5 sat/vb
+        assert!(verify_proof_fee_rates(&node(Some((500, 250)), None), &proof, 1000).is_ok());
+        assert!(matches!(
+            verify_proof_fee_rates(&node(Some((500, 250)), None), &proof, 5000),
+            Err(Error::FundingTxFeeRate {
+                fee_rate: 2000,
+                min_fee_rate: 5000
+            })
+        ));
+        //a confirmed tx obviously paid enough
+        assert!(verify_proof_fee_rates(&node(None, Some(3)), &proof, 5000).is_ok());
+        //neither in the mempool nor confirmed, e.g. its fee is too low to get into the mempool
+        assert!(matches!(
+            verify_proof_fee_rates(&node(None, None), &proof, 5000),
+            Err(Error::Protocol(_))
+        ));
+        assert!(matches!(
+            verify_proof_fee_rates(&node(None, Some(0)), &proof, 5000),
+            Err(Error::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_listen_address() {
+        let address = "[::1]:6102".parse::<ListenAddress>().unwrap();
+        assert_eq!(address, ListenAddress::Tcp("[::1]:6102".parse().unwrap()));
+        assert!(address.is_local());
+        let address = "0.0.0.0:6102".parse::<ListenAddress>().unwrap();
+        assert!(!address.is_local());
+        let address = "unix:/run/teleport/maker.sock"
+            .parse::<ListenAddress>()
+            .unwrap();
+        assert_eq!(address.to_string(), "unix:/run/teleport/maker.sock");
+        assert!(address.is_local());
+        assert_eq!("i2p".parse::<ListenAddress>(), Ok(ListenAddress::I2p));
+        assert!(!ListenAddress::I2p.is_local());
+        assert!("unix:".parse::<ListenAddress>().is_err());
+        assert!("localhost:6102".parse::<ListenAddress>().is_err());
+
+        assert!(is_onion_address("abcdef.onion:6102"));
+        assert!(is_onion_address("abcdef.onion"));
+        assert!(!is_onion_address("example.com:6102"));
+    }
+
+    #[test]
+    fn test_instance_listen_addresses() {
+        let addresses = ["127.0.0.1:6102", "[::1]:7000"]
+            .iter()
+            .map(|a| a.parse::<ListenAddress>().unwrap())
+            .collect::<Vec<ListenAddress>>();
+        assert_eq!(
+            instance_listen_addresses(&addresses, 0),
+            Ok(addresses.clone())
+        );
+        assert_eq!(
+            instance_listen_addresses(&addresses, 2),
+            Ok(vec![
+                "127.0.0.1:6104".parse().unwrap(),
+                "[::1]:7002".parse().unwrap()
+            ])
+        );
+        let addresses = vec![
+            "127.0.0.1:65535".parse::<ListenAddress>().unwrap(),
+            ListenAddress::I2p,
+        ];
+        assert!(instance_listen_addresses(&addresses, 0).is_ok());
+        assert!(instance_listen_addresses(&addresses[..1], 1).is_err());
+        assert!(instance_listen_addresses(&addresses[1..], 1).is_err());
+    }
+
+    #[test]
+    fn test_parse_maker_behavior() {
+        assert_eq!(
+            "stallatprivkeyhandover".parse::<MakerBehavior>(),
+            Ok(MakerBehavior::StallAtPrivkeyHandover)
+        );
+        assert_eq!(
+            "closeonsignsenderscontracttx".parse::<MakerBehavior>(),
+            Ok(MakerBehavior::CloseOnSignSendersContractTx)
+        );
+        assert!("stall".parse::<MakerBehavior>().is_err());
+
+        let privkey = SecretKey::from_slice(&[2u8; 32]).unwrap();
+        let secp = Secp256k1::new();
+        let pubkey = bitcoin::secp256k1::PublicKey::from_secret_key(&secp, &privkey);
+        let message = Message::from_slice(&[3u8; 32]).unwrap();
+        assert!(secp
+            .verify(&message, &invalid_signature(&privkey), &pubkey)
+            .is_err());
+    }
+
+    #[test]
+    fn test_scaled_absolute_fee() {
+        let mut maker_settings = Settings::default().maker;
+        maker_settings.absolute_fee_sat = 1000;
+        assert_eq!(scaled_absolute_fee(&maker_settings, Some(50_000)), 1000);
+
+        maker_settings.dynamic_fee_reference_rate = Some(10);
+        assert_eq!(scaled_absolute_fee(&maker_settings, Some(50_000)), 5000);
+        assert_eq!(scaled_absolute_fee(&maker_settings, Some(15_500)), 1550);
+        //never cheaper than absolute_fee_sat
+        assert_eq!(scaled_absolute_fee(&maker_settings, Some(2_000)), 1000);
+        assert_eq!(scaled_absolute_fee(&maker_settings, None), 1000);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_listen_on_multiple_addresses() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("maker.sock");
+        let tcp_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp_addr = tcp_listener.local_addr().unwrap();
+        drop(tcp_listener);
+
+        let (accepted_tx, mut accepted_rx) = mpsc::channel(10);
+        let listen_addresses = [
+            ListenAddress::Tcp(tcp_addr),
+            ListenAddress::Unix(socket_path.clone()),
+        ];
+        for listen_address in &listen_addresses {
+            spawn_listener(
+                listen_address,
+                &Settings::default().i2p,
+                accepted_tx.clone(),
+            )
+            .await
+            .unwrap();
+        }
+
+        let mut tcp_client = TcpStream::connect(tcp_addr).await.unwrap();
+        let (mut socket, addr, fingerprint) = accepted_rx.recv().await.unwrap().unwrap();
+        assert!(matches!(addr, PeerAddr::Tcp(_)));
+        //could be any taker coming through tor
+        assert_eq!(fingerprint, None);
+        socket.write_all(b"hello").await.unwrap();
+        let mut buf = [0; 5];
+        tcp_client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let _unix_client = tokio::net::UnixStream::connect(&socket_path).await.unwrap();
+        let (_socket, addr, _) = accepted_rx.recv().await.unwrap().unwrap();
+        assert_eq!(addr.id(), "unix-1");
+    }
+}
@@ -0,0 +1,109 @@
+//a maker can coinswap its earnings with other makers, acting as a taker, which changes the
+// sizes of its coins and breaks the link between its coins and the coinswaps it took part in
+//every rebalance_interval_hours the maker checks whether its coins need rebalancing, see the
+// [maker] section of teleport.conf, and maker_protocol.rs for how the coinswap is run
+
+use crate::maker_stats::earnings_by_period;
+use crate::settings::MakerSettings;
+use crate::wallet_sync::SwapHistoryEntry;
+
+pub const REBALANCE_MAKER_COUNT: u16 = 2;
+pub const REBALANCE_TX_COUNT: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebalanceTrigger {
+    //the wallet has this many coins
+    UtxoCount(usize),
+    //the biggest coin is this many times the size of the median coin
+    SizeSkew(u64),
+}
+
+/// Whether coins of these values need rebalancing
+pub fn rebalance_trigger(
+    coin_values: &[u64],
+    maker_settings: &MakerSettings,
+) -> Option<RebalanceTrigger> {
+    if coin_values.len() > maker_settings.rebalance_max_utxo_count {
+        return Some(RebalanceTrigger::UtxoCount(coin_values.len()));
+    }
+    let mut sorted_values = coin_values.to_vec();
+    sorted_values.sort_unstable();
+    let median = *sorted_values.get(sorted_values.len() / 2)?;
+    let skew = sorted_values.last()? / median.max(1);
+    if skew > maker_settings.rebalance_max_size_skew {
+        return Some(RebalanceTrigger::SizeSkew(skew));
+    }
+    None
+}
+
+/// What the maker earned from coinswaps completed in the last interval, which it coinswaps
+/// when rebalancing. None if that is less than its own min_size, as other makers are unlikely
+/// to accept it
+pub fn rebalance_amount(
+    history: &[SwapHistoryEntry],
+    now: i64,
+    maker_settings: &MakerSettings,
+) -> Option<u64> {
+    let interval_secs = maker_settings.rebalance_interval_hours as i64 * 60 * 60;
+    let earned = earnings_by_period(history, now, interval_secs, 1)[0].earned_sat();
+    if earned < maker_settings.min_size as i64 {
+        return None;
+    }
+    Some(earned as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::hash160::Hash as Hash160;
+    use bitcoin::hashes::Hash;
+
+    use crate::settings::Settings;
+
+    #[test]
+    fn test_rebalance_trigger() {
+        let mut settings = Settings::default().maker;
+        settings.rebalance_max_utxo_count = 4;
+        settings.rebalance_max_size_skew = 10;
+        assert_eq!(rebalance_trigger(&[], &settings), None);
+        assert_eq!(rebalance_trigger(&[100_000], &settings), None);
+        assert_eq!(
+            rebalance_trigger(&[1, 2, 3, 4, 5], &settings),
+            Some(RebalanceTrigger::UtxoCount(5))
+        );
+        assert_eq!(
+            rebalance_trigger(&[20_000, 2_000_000, 10_000], &settings),
+            Some(RebalanceTrigger::SizeSkew(100))
+        );
+        assert_eq!(
+            rebalance_trigger(&[200_000, 2_000_000, 300_000], &settings),
+            None
+        );
+    }
+
+    #[test]
+    fn test_rebalance_amount() {
+        let mut settings = Settings::default().maker;
+        settings.rebalance_interval_hours = 24;
+        settings.min_size = 10_000;
+        let entry = |completed_timestamp, incoming_amount_sat| SwapHistoryEntry {
+            hashvalue: Hash160::hash(&[1]),
+            incoming_amount_sat,
+            outgoing_amount_sat: 1_000_000,
+            coinswap_fee_sat: incoming_amount_sat - 1_000_000,
+            miner_fee_sat: 1_000,
+            started_timestamp: 0,
+            completed_timestamp,
+        };
+        let now = 10 * 24 * 60 * 60;
+        let mut history = vec![
+            entry(Some(now - 60), 1_008_000),
+            entry(None, 1_050_000),
+            entry(Some(now - 2 * 24 * 60 * 60), 1_050_000),
+        ];
+        assert_eq!(rebalance_amount(&history, now, &settings), None);
+        history.push(entry(Some(now - 120), 1_005_000));
+        assert_eq!(rebalance_amount(&history, now, &settings), Some(11_000));
+    }
+}
@@ -0,0 +1,137 @@
+//earnings of a maker worked out from the swap history in its wallet file, shown by
+// `teleport maker stats` to help tune the fee parameters in the [maker] section of teleport.conf
+//only completed coinswaps are counted, they're placed in time by when they completed
+
+use crate::wallet_sync::SwapHistoryEntry;
+
+pub const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Earnings {
+    pub swap_count: u64,
+    //sum of the incoming amounts
+    pub turnover_sat: u64,
+    pub fee_income_sat: u64,
+    pub miner_fee_sat: u64,
+}
+
+impl Earnings {
+    fn add(&mut self, entry: &SwapHistoryEntry) {
+        self.swap_count += 1;
+        self.turnover_sat += entry.incoming_amount_sat;
+        self.fee_income_sat += entry.fee_income_sat();
+        self.miner_fee_sat += entry.miner_fee_sat;
+    }
+
+    pub fn earned_sat(&self) -> i64 {
+        self.fee_income_sat as i64 - self.miner_fee_sat as i64
+    }
+
+    pub fn average_swap_size_sat(&self) -> Option<u64> {
+        self.turnover_sat.checked_div(self.swap_count)
+    }
+}
+
+pub fn total_earnings(history: &[SwapHistoryEntry]) -> Earnings {
+    let mut earnings = Earnings::default();
+    for entry in history.iter().filter(|e| e.completed_timestamp.is_some()) {
+        earnings.add(entry);
+    }
+    earnings
+}
+
+//earnings in each of the last period_count periods of period_secs before now, most recent first
+pub fn earnings_by_period(
+    history: &[SwapHistoryEntry],
+    now: i64,
+    period_secs: i64,
+    period_count: usize,
+) -> Vec<Earnings> {
+    let mut periods = vec![Earnings::default(); period_count];
+    for entry in history {
+        let completed_timestamp = match entry.completed_timestamp {
+            Some(t) if t <= now => t,
+            _ => continue,
+        };
+        let period = ((now - completed_timestamp) / period_secs) as usize;
+        if let Some(earnings) = periods.get_mut(period) {
+            earnings.add(entry);
+        }
+    }
+    periods
+}
+
+//how much of the liquidity was swapped per day on average over the last days, 1.0 means
+// coinswaps as big as the whole wallet every day
+pub fn utilization(
+    history: &[SwapHistoryEntry],
+    now: i64,
+    days: usize,
+    liquidity_sat: u64,
+) -> Option<f64> {
+    if liquidity_sat == 0 || days == 0 {
+        return None;
+    }
+    let turnover_sat =
+        earnings_by_period(history, now, SECONDS_PER_DAY * days as i64, 1)[0].turnover_sat;
+    Some(turnover_sat as f64 / days as f64 / liquidity_sat as f64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::{hash160::Hash as Hash160, Hash};
+
+    fn entry(incoming: u64, outgoing: u64, completed_timestamp: Option<i64>) -> SwapHistoryEntry {
+        SwapHistoryEntry {
+            hashvalue: Hash160::hash(&incoming.to_le_bytes()),
+            incoming_amount_sat: incoming,
+            outgoing_amount_sat: outgoing,
+            coinswap_fee_sat: incoming - outgoing - 100,
+            miner_fee_sat: 300,
+            started_timestamp: 0,
+            completed_timestamp,
+        }
+    }
+
+    #[test]
+    fn test_maker_stats() {
+        let now = 100 * SECONDS_PER_DAY;
+        let history = vec![
+            entry(100_000, 99_000, Some(now - 60)),
+            entry(300_000, 298_000, Some(now - SECONDS_PER_DAY - 60)),
+            entry(200_000, 199_500, Some(now - 8 * SECONDS_PER_DAY)),
+            //not completed, the taker never handed over the private keys
+            entry(500_000, 490_000, None),
+        ];
+
+        let total = total_earnings(&history);
+        assert_eq!(
+            total,
+            Earnings {
+                swap_count: 3,
+                turnover_sat: 600_000,
+                fee_income_sat: 3_500,
+                miner_fee_sat: 900,
+            }
+        );
+        assert_eq!(total.earned_sat(), 2_600);
+        assert_eq!(total.average_swap_size_sat(), Some(200_000));
+        assert_eq!(Earnings::default().average_swap_size_sat(), None);
+
+        let days = earnings_by_period(&history, now, SECONDS_PER_DAY, 7);
+        assert_eq!(days.len(), 7);
+        assert_eq!(days[0].turnover_sat, 100_000);
+        assert_eq!(days[1].turnover_sat, 300_000);
+        assert_eq!(days[2..].iter().map(|d| d.swap_count).sum::<u64>(), 0);
+
+        let weeks = earnings_by_period(&history, now, 7 * SECONDS_PER_DAY, 4);
+        assert_eq!(weeks[0].swap_count, 2);
+        assert_eq!(weeks[1].swap_count, 1);
+        assert_eq!(weeks[1].earned_sat(), 200);
+
+        assert_eq!(utilization(&history, now, 2, 200_000), Some(1.0));
+        assert_eq!(utilization(&history, now, 2, 0), None);
+    }
+}
@@ -0,0 +1,152 @@
+//how often each maker could be reached by this taker, kept across runs in maker-uptime.json
+// in the data directory so makers which often fail to answer are tried last when choosing a
+// route, making it less likely that a coinswap stalls because a maker dropped out
+//only connections count, a maker which answers but rejects the amount is still up
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::settings::Settings;
+use crate::utils::teleport_data_dir;
+
+//makers which answered less often than this are tried after all the others
+pub const FLAKY_MAKER_UPTIME_SCORE: f64 = 0.5;
+
+//every offer download task reads and rewrites the file
+static UPTIME_FILE_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionStats {
+    pub successes: u32,
+    pub failures: u32,
+    pub last_success_timestamp: Option<i64>,
+}
+
+impl ConnectionStats {
+    /// Fraction of the connections to the maker which succeeded, None if it was never tried
+    pub fn uptime_score(&self) -> Option<f64> {
+        let attempts = self.successes + self.failures;
+        if attempts == 0 {
+            None
+        } else {
+            Some(self.successes as f64 / attempts as f64)
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct MakerUptime {
+    pub makers: HashMap<String, ConnectionStats>,
+}
+
+impl MakerUptime {
+    /// Read the stats from path, a missing file means no maker was tried yet
+    pub fn load(path: &Path) -> Result<MakerUptime, Error> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(file).map_err(io::Error::from)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(MakerUptime::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        serde_json::to_writer(File::create(path)?, self).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    pub fn record(&mut self, address: &str, success: bool, now: i64) {
+        let stats = self.makers.entry(address.to_string()).or_default();
+        if success {
+            stats.successes += 1;
+            stats.last_success_timestamp = Some(now);
+        } else {
+            stats.failures += 1;
+        }
+    }
+
+    pub fn stats(&self, address: &str) -> ConnectionStats {
+        self.makers.get(address).copied().unwrap_or_default()
+    }
+
+    /// Makers never tried are not flaky
+    pub fn is_flaky(&self, address: &str) -> bool {
+        self.stats(address)
+            .uptime_score()
+            .is_some_and(|score| score < FLAKY_MAKER_UPTIME_SCORE)
+    }
+}
+
+pub fn maker_uptime_path() -> PathBuf {
+    teleport_data_dir().join("maker-uptime.json")
+}
+
+/// Load the stats from the data directory, empty if the settings arent initialized or
+/// the file cant be read
+pub fn load_maker_uptime() -> MakerUptime {
+    if !Settings::is_initialized() {
+        return MakerUptime::default();
+    }
+    MakerUptime::load(&maker_uptime_path()).unwrap_or_else(|e| {
+        tracing::warn!(target: "offerbook", "unable to load maker uptime: {:?}", e);
+        MakerUptime::default()
+    })
+}
+
+/// Add a connection attempt to the stats in the data directory, does nothing if the
+/// settings arent initialized
+pub fn record_maker_connection(address: &str, success: bool) {
+    if !Settings::is_initialized() {
+        return;
+    }
+    let _lock = UPTIME_FILE_LOCK.lock().unwrap();
+    let path = maker_uptime_path();
+    let result = MakerUptime::load(&path).and_then(|mut uptime| {
+        uptime.record(address, success, chrono::Utc::now().timestamp());
+        uptime.save(&path)
+    });
+    if let Err(e) = result {
+        tracing::warn!(target: "offerbook", "unable to record maker uptime: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_maker_uptime() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("maker-uptime.json");
+        let mut uptime = MakerUptime::load(&path).unwrap();
+        assert_eq!(uptime.stats("maker-a").uptime_score(), None);
+        assert!(!uptime.is_flaky("maker-a"));
+
+        uptime.record("maker-a", true, 100);
+        uptime.record("maker-a", false, 200);
+        uptime.record("maker-a", true, 300);
+        uptime.record("maker-a", true, 400);
+        uptime.record("maker-b", false, 100);
+        uptime.record("maker-b", true, 200);
+        uptime.record("maker-b", false, 300);
+        uptime.save(&path).unwrap();
+
+        let uptime = MakerUptime::load(&path).unwrap();
+        assert_eq!(
+            uptime.stats("maker-a"),
+            ConnectionStats {
+                successes: 3,
+                failures: 1,
+                last_success_timestamp: Some(400),
+            }
+        );
+        assert_eq!(uptime.stats("maker-a").uptime_score(), Some(0.75));
+        assert!(!uptime.is_flaky("maker-a"));
+        assert!(uptime.is_flaky("maker-b"));
+    }
+}
@@ -14,6 +14,10 @@ use bitcoin::{OutPoint, Script, Transaction};
 pub const PREIMAGE_LEN: usize = 32;
 pub type Preimage = [u8; PREIMAGE_LEN];
 
+//see swap_sessions.rs
+pub const SESSION_ID_LEN: usize = 16;
+pub type SessionId = [u8; SESSION_ID_LEN];
+
 //TODO the structs here which are actual messages should have the word Message
 //added to their name e.g. SignSendersContractTx
 //to distinguish them from structs which just collect together
@@ -23,6 +27,21 @@ pub type Preimage = [u8; PREIMAGE_LEN];
 pub struct TakerHello {
     pub protocol_version_min: u32,
     pub protocol_version_max: u32,
+    //the session from the maker's hello to start it, or the session of an earlier connection
+    // of the same coinswap to carry it on. none when not coinswapping e.g. only getting offers
+    #[serde(default)]
+    pub session_id: Option<SessionId>,
+    //for the maker to prove its identity key with, only sent to makers whose hello has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity_challenge: Option<IdentityChallenge>,
+}
+
+//see maker_identity.rs
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct IdentityChallenge {
+    pub nonce: [u8; 32],
+    //the address the taker reached the maker at, the maker only signs its own addresses
+    pub maker_address: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -40,6 +59,7 @@ pub struct SenderContractTxNoncesInfo {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SignSendersContractTx {
+    pub session_id: SessionId,
     pub txes_info: Vec<SenderContractTxNoncesInfo>,
     pub hashvalue: Hash160,
     pub locktime: u16,
@@ -63,6 +83,7 @@ pub struct NextCoinSwapTxInfo {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ProofOfFunding {
+    pub session_id: SessionId,
     pub confirmed_funding_txes: Vec<ConfirmedCoinSwapTxInfo>,
     pub next_coinswap_info: Vec<NextCoinSwapTxInfo>,
     pub next_locktime: u16,
@@ -71,6 +92,7 @@ pub struct ProofOfFunding {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SendersAndReceiversContractSigs {
+    pub session_id: SessionId,
     pub receivers_sigs: Vec<Signature>,
     pub senders_sigs: Vec<Signature>,
 }
@@ -83,11 +105,13 @@ pub struct ReceiversContractTxInfo {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SignReceiversContractTx {
+    pub session_id: SessionId,
     pub txes: Vec<ReceiversContractTxInfo>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HashPreimage {
+    pub session_id: SessionId,
     pub senders_multisig_redeemscripts: Vec<Script>,
     pub receivers_multisig_redeemscripts: Vec<Script>,
     pub preimage: Preimage,
@@ -101,9 +125,15 @@ pub struct SwapCoinPrivateKey {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PrivateKeyHandover {
+    pub session_id: SessionId,
     pub swapcoin_private_keys: Vec<SwapCoinPrivateKey>, //could easily be called private_keys not swapcoin_private_keys
 }
 
+//asks for the makers known to the maker, sent instead of GiveOffer by takers and by other
+// makers gossiping
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GiveMakerAddresses;
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "method", rename_all = "lowercase")]
 pub enum TakerToMakerMessage {
@@ -115,12 +145,51 @@ pub enum TakerToMakerMessage {
     SignReceiversContractTx(SignReceiversContractTx),
     HashPreimage(HashPreimage),
     PrivateKeyHandover(PrivateKeyHandover),
+    GiveMakerAddresses(GiveMakerAddresses),
+}
+
+impl TakerToMakerMessage {
+    /// The session a coinswap message belongs to, None for messages outside of a coinswap
+    pub fn session_id(&self) -> Option<SessionId> {
+        match self {
+            TakerToMakerMessage::TakerHello(_)
+            | TakerToMakerMessage::GiveOffer(_)
+            | TakerToMakerMessage::GiveMakerAddresses(_) => None,
+            TakerToMakerMessage::SignSendersContractTx(m) => Some(m.session_id),
+            TakerToMakerMessage::ProofOfFunding(m) => Some(m.session_id),
+            TakerToMakerMessage::SendersAndReceiversContractSigs(m) => Some(m.session_id),
+            TakerToMakerMessage::SignReceiversContractTx(m) => Some(m.session_id),
+            TakerToMakerMessage::HashPreimage(m) => Some(m.session_id),
+            TakerToMakerMessage::PrivateKeyHandover(m) => Some(m.session_id),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MakerHello {
     pub protocol_version_min: u32,
     pub protocol_version_max: u32,
+    //a new session for the connection, not given by older makers
+    #[serde(default)]
+    pub session_id: Option<SessionId>,
+    //the maker's identity key, proven by signing the taker's challenge. not given by older
+    // makers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity_pubkey: Option<PublicKey>,
+}
+
+//the reply to a taker hello with an identity challenge, None if the challenge is for an
+// address which isnt the maker's
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdentityProof {
+    pub identity: Option<IdentitySignature>,
+}
+
+//a signature by the maker's long-term identity key, see maker_identity.rs
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IdentitySignature {
+    pub pubkey: PublicKey,
+    pub sig: Signature,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -134,16 +203,55 @@ pub struct FidelityBondProof {
     pub onion_sig: Signature,
 }
 
+//coinswaps of at least `amount` sats need `required_confirms` confirmations on their funding txes
+//a tier can also lower the longest locktime the maker accepts for its outgoing contract and
+// scale its coinswap fee, not given by older makers
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ConfirmsTier {
+    pub amount: u64,
+    pub required_confirms: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maximum_locktime: Option<u16>,
+    //100 charges the offer's fee unchanged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee_multiplier_percent: Option<u64>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Offer {
     pub absolute_fee_sat: u64,
     pub amount_relative_fee_ppb: u64,
     pub time_relative_fee_ppb: u64,
     pub required_confirms: i32,
+    #[serde(default)]
+    pub required_confirms_tiers: Vec<ConfirmsTier>,
     pub minimum_locktime: u16,
+    //longest locktime the maker accepts for its outgoing contract, older makers dont say
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maximum_locktime: Option<u16>,
+    //blocks the maker needs between the locktimes of its incoming and outgoing contracts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minimum_contract_react_time: Option<u16>,
     pub max_size: u64,
     pub min_size: u64,
     pub tweakable_point: PublicKey,
+    //proves which fidelity bond is behind the maker's onion address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fidelity_bond_proof: Option<Box<FidelityBondProof>>,
+    //unix timestamp after which the maker may have changed its fees, takers fetch the
+    // offer again before using it anyway
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    //signs the rest of the offer with the maker's identity key, not given by older makers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<IdentitySignature>,
+}
+
+impl Offer {
+    /// Return true if the offer has an expiry time and it is before now
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at < now)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -170,13 +278,27 @@ pub struct ReceiversContractSig {
     pub sigs: Vec<Signature>,
 }
 
+//an onion address and the fidelity bond proof signing it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SignedMakerAddress {
+    pub address: String,
+    pub fidelity_bond_proof: FidelityBondProof,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MakerAddresses {
+    pub addresses: Vec<SignedMakerAddress>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "method", rename_all = "lowercase")]
 pub enum MakerToTakerMessage {
     MakerHello(MakerHello),
+    IdentityProof(IdentityProof),
     Offer(Offer),
     SendersContractSig(SendersContractSig),
     SignSendersAndReceiversContractTxes(SignSendersAndReceiversContractTxes),
     ReceiversContractSig(ReceiversContractSig),
     PrivateKeyHandover(PrivateKeyHandover),
+    MakerAddresses(MakerAddresses),
 }
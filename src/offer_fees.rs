@@ -0,0 +1,192 @@
+//what the makers in the offerbook would charge for a coinswap, shown by `teleport fees`
+//only coinswap fees are counted, the miner fees of the makers' funding txes which the taker
+// also pays depend on the fee rate and number of txes so they're left out
+
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use crate::contracts::{apply_fee_multiplier, calculate_coinswap_fee};
+use crate::offerbook_sync::OfferAndAddress;
+
+//routes are only made from this many of the cheapest makers, trying every ordering of every
+// maker would take too long with hundreds of them
+const ROUTE_CANDIDATE_COUNT: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MakerFee {
+    pub address: String,
+    pub fee_sat: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RouteFee {
+    pub addresses: Vec<String>,
+    pub fee_sat: u64,
+}
+
+//returns None if the offer doesnt accept the amount
+pub fn offer_fee(offer_address: &OfferAndAddress, amount: u64, locktime: u64) -> Option<u64> {
+    let offer = &offer_address.offer;
+    if amount < offer.min_size || amount > offer.max_size {
+        return None;
+    }
+    Some(apply_fee_multiplier(
+        calculate_coinswap_fee(
+            offer.absolute_fee_sat,
+            offer.amount_relative_fee_ppb,
+            offer.time_relative_fee_ppb,
+            amount,
+            locktime,
+        ),
+        &offer.required_confirms_tiers,
+        amount,
+    ))
+}
+
+//fee of every maker which accepts the amount, cheapest first
+pub fn maker_fees(offers: &[OfferAndAddress], amount: u64, locktime: u64) -> Vec<MakerFee> {
+    let mut fees = offers
+        .iter()
+        .filter_map(|o| {
+            offer_fee(o, amount, locktime).map(|fee_sat| MakerFee {
+                address: o.address.to_string(),
+                fee_sat,
+            })
+        })
+        .collect::<Vec<MakerFee>>();
+    fees.sort_by_key(|f| f.fee_sat);
+    fees
+}
+
+//every route through maker_count different makers, cheapest first
+//each maker gets the amount minus the fees of the makers before it, like in a real coinswap
+pub fn route_fees(
+    offers: &[OfferAndAddress],
+    amount: u64,
+    locktime: u64,
+    maker_count: usize,
+) -> Vec<RouteFee> {
+    let cheapest = maker_fees(offers, amount, locktime)
+        .into_iter()
+        .take(ROUTE_CANDIDATE_COUNT)
+        .map(|f| f.address)
+        .collect::<HashSet<String>>();
+    let candidates = offers
+        .iter()
+        .filter(|o| cheapest.contains(&o.address.to_string()))
+        .collect::<Vec<&OfferAndAddress>>();
+
+    let mut routes = Vec::<RouteFee>::new();
+    let mut route = Vec::<usize>::new();
+    add_routes(
+        &candidates,
+        amount,
+        locktime,
+        maker_count,
+        &mut route,
+        &mut routes,
+    );
+    routes.sort_by_key(|r| r.fee_sat);
+    routes
+}
+
+fn add_routes(
+    candidates: &[&OfferAndAddress],
+    amount: u64,
+    locktime: u64,
+    maker_count: usize,
+    route: &mut Vec<usize>,
+    routes: &mut Vec<RouteFee>,
+) {
+    if route.len() == maker_count {
+        let mut hop_amount = amount;
+        for i in route.iter() {
+            match offer_fee(candidates[*i], hop_amount, locktime) {
+                Some(fee) if fee < hop_amount => hop_amount -= fee,
+                _ => return,
+            }
+        }
+        routes.push(RouteFee {
+            addresses: route
+                .iter()
+                .map(|i| candidates[*i].address.to_string())
+                .collect(),
+            fee_sat: amount - hop_amount,
+        });
+        return;
+    }
+    for i in 0..candidates.len() {
+        if !route.contains(&i) {
+            route.push(i);
+            add_routes(candidates, amount, locktime, maker_count, route, routes);
+            route.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::messages::Offer;
+    use crate::offerbook_sync::test_offer_and_address;
+
+    fn offer_address(
+        address: &str,
+        absolute_fee_sat: u64,
+        amount_relative_fee_ppb: u64,
+        min_size: u64,
+    ) -> OfferAndAddress {
+        let offer_and_address = test_offer_and_address(address, min_size, 10_000_000);
+        OfferAndAddress {
+            offer: Offer {
+                absolute_fee_sat,
+                amount_relative_fee_ppb,
+                time_relative_fee_ppb: 1_000_000_000,
+                ..offer_and_address.offer
+            },
+            ..offer_and_address
+        }
+    }
+
+    #[test]
+    fn test_offer_fees() {
+        let offers = vec![
+            //1000 + 1% of the amount + 1 sat per block
+            offer_address("maker-a", 1000, 10_000_000, 10_000),
+            offer_address("maker-b", 2000, 0, 10_000),
+            offer_address("maker-c", 500, 0, 10_000),
+            //too big a minimum for the amount
+            offer_address("maker-d", 0, 0, 1_000_000),
+        ];
+
+        let fees = maker_fees(&offers, 100_000, 10);
+        assert_eq!(
+            fees,
+            vec![
+                MakerFee {
+                    address: "maker-c".to_string(),
+                    fee_sat: 510
+                },
+                MakerFee {
+                    address: "maker-a".to_string(),
+                    fee_sat: 2010
+                },
+                MakerFee {
+                    address: "maker-b".to_string(),
+                    fee_sat: 2010
+                },
+            ]
+        );
+
+        let routes = route_fees(&offers, 100_000, 10, 2);
+        assert_eq!(routes.len(), 6);
+        //maker-a charges less when it gets the amount after maker-c took its fee
+        assert_eq!(routes[0].addresses, vec!["maker-c", "maker-a"]);
+        assert_eq!(routes[0].fee_sat, 510 + 1000 + 994 + 10);
+
+        assert_eq!(route_fees(&offers, 100_000, 10, 3).len(), 6);
+        assert!(route_fees(&offers, 100_000, 10, 4).is_empty());
+    }
+}
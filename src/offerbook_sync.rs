@@ -1,9 +1,15 @@
 use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
+use serde::{Deserialize, Serialize};
+
 use tokio::net::TcpStream;
 use tokio::select;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Semaphore};
 use tokio::time::sleep;
 
 use bitcoin::Network;
@@ -12,24 +18,105 @@ use crate::directory_servers::{
     sync_maker_addresses_from_directory_servers, DirectoryServerError, TOR_ADDR,
 };
 use crate::error::Error;
-use crate::messages::{GiveOffer, MakerToTakerMessage, Offer, TakerToMakerMessage};
-use crate::taker_protocol::{
-    handshake_maker, read_message, send_message, FIRST_CONNECT_ATTEMPTS,
-    FIRST_CONNECT_ATTEMPT_TIMEOUT_SEC, FIRST_CONNECT_SLEEP_DELAY_SEC,
-};
+use crate::gossip::{gossip_with_makers, onion_hostname};
+use crate::i2p::{i2p_settings, is_i2p_address};
+use crate::maker_identity::verify_offer_identity;
+use crate::maker_uptime::record_maker_connection;
+use crate::messages::{GiveOffer, Offer, TakerToMakerMessage};
+use crate::protocol_phase::expect_message;
+use crate::rpc::Client;
+use crate::settings::{Settings, TakerSettings};
+use crate::taker_protocol::{handshake_maker, read_message, send_message, taker_settings};
+use crate::utils::teleport_data_dir;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MakerAddress {
     Clearnet { address: String },
     Tor { address: String },
+    I2p { address: String },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OfferAndAddress {
     pub offer: Offer,
     pub address: MakerAddress,
 }
 
+impl OfferAndAddress {
+    /// Value of the offer's fidelity bond, None if it has none or it doesnt verify
+    pub fn fidelity_bond_value(&self, rpc: &Client) -> Option<f64> {
+        let proof = self.offer.fidelity_bond_proof.as_ref()?;
+        let address = self.address.to_string();
+        match proof.verify_and_get_value(rpc, onion_hostname(&address)) {
+            Ok(value) => Some(value),
+            Err(error) => {
+                tracing::debug!("invalid fidelity bond from {}: {:?}", address, error);
+                None
+            }
+        }
+    }
+}
+
+/// An offer of the maker at address for tests to change what they need of
+#[cfg(test)]
+pub(crate) fn test_offer_and_address(
+    address: &str,
+    min_size: u64,
+    max_size: u64,
+) -> OfferAndAddress {
+    use std::str::FromStr;
+    OfferAndAddress {
+        offer: Offer {
+            absolute_fee_sat: 1000,
+            amount_relative_fee_ppb: 10_000_000,
+            time_relative_fee_ppb: 100_000,
+            required_confirms: 1,
+            required_confirms_tiers: Vec::new(),
+            minimum_locktime: 48,
+            maximum_locktime: None,
+            minimum_contract_react_time: None,
+            max_size,
+            min_size,
+            tweakable_point: bitcoin::PublicKey::from_str(
+                "032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af",
+            )
+            .unwrap(),
+            fidelity_bond_proof: None,
+            expires_at: None,
+            identity: None,
+        },
+        address: MakerAddress::Clearnet {
+            address: address.to_string(),
+        },
+    }
+}
+
+//the offers from the last sync, so commands like `teleport fees` dont need to contact
+// every maker again
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OfferbookCache {
+    pub synced_timestamp: i64,
+    pub offers: Vec<OfferAndAddress>,
+}
+
+/// Path of the offerbook cache of a network in the data directory
+pub fn offerbook_cache_path(network: Network) -> PathBuf {
+    teleport_data_dir().join(format!("offerbook-{}.json", network))
+}
+
+pub fn save_offerbook_cache(path: &Path, offers: &[OfferAndAddress]) -> Result<(), Error> {
+    let cache = OfferbookCache {
+        synced_timestamp: chrono::Utc::now().timestamp(),
+        offers: offers.to_vec(),
+    };
+    serde_json::to_writer(File::create(path)?, &cache).map_err(io::Error::from)?;
+    Ok(())
+}
+
+pub fn load_offerbook_cache(path: &Path) -> Result<OfferbookCache, Error> {
+    Ok(serde_json::from_reader(File::open(path)?).map_err(io::Error::from)?)
+}
+
 const REGTEST_MAKER_ADDRESSES: &[&str] = &[
     "localhost:6102",
     "localhost:16102",
@@ -52,6 +139,16 @@ impl MakerAddress {
         match &self {
             MakerAddress::Clearnet { address } => address.to_string(),
             MakerAddress::Tor { address: _ } => String::from(TOR_ADDR),
+            MakerAddress::I2p { address: _ } => i2p_settings().sam_address,
+        }
+    }
+
+    /// An address as listed by the directory servers or gossiped by makers
+    pub fn from_published(address: String) -> MakerAddress {
+        if is_i2p_address(&address) {
+            MakerAddress::I2p { address }
+        } else {
+            MakerAddress::Tor { address }
         }
     }
 }
@@ -61,32 +158,37 @@ impl fmt::Display for MakerAddress {
         match &self {
             MakerAddress::Clearnet { address } => write!(f, "{}", address),
             MakerAddress::Tor { address } => write!(f, "{}", address),
+            MakerAddress::I2p { address } => write!(f, "{}", address),
         }
     }
 }
 
-async fn download_maker_offer_attempt_once(addr: &MakerAddress) -> Result<Offer, Error> {
-    log::debug!(target: "offerbook", "Connecting to {}", addr);
+pub(crate) async fn download_maker_offer_attempt_once(addr: &MakerAddress) -> Result<Offer, Error> {
+    tracing::debug!(target: "offerbook", "Connecting to {}", addr);
     let mut socket = TcpStream::connect(addr.get_tcpstream_address()).await?;
-    let (mut socket_reader, mut socket_writer) = handshake_maker(&mut socket, addr).await?;
+    let (mut socket_reader, mut socket_writer, identity_pubkey) =
+        handshake_maker(&mut socket, addr).await?;
 
     send_message(
         &mut socket_writer,
-        TakerToMakerMessage::GiveOffer(GiveOffer),
+        addr,
+        None,
+        None,
+        &TakerToMakerMessage::GiveOffer(GiveOffer),
     )
     .await?;
 
-    let offer = if let MakerToTakerMessage::Offer(o) = read_message(&mut socket_reader).await? {
-        o
-    } else {
-        return Err(Error::Protocol("expected method offer"));
-    };
+    let offer: Offer = expect_message(read_message(&mut socket_reader, addr, None, None).await?)?;
+    verify_offer_identity(&offer, identity_pubkey)?;
 
-    log::debug!(target: "offerbook", "Obtained offer from {}", addr);
+    tracing::debug!(target: "offerbook", "Obtained offer from {}", addr);
     Ok(offer)
 }
 
-async fn download_maker_offer(address: MakerAddress) -> Option<OfferAndAddress> {
+async fn download_maker_offer(
+    address: MakerAddress,
+    taker_settings: &TakerSettings,
+) -> Option<OfferAndAddress> {
     let mut ii = 0;
     loop {
         ii += 1;
@@ -95,14 +197,17 @@ async fn download_maker_offer(address: MakerAddress) -> Option<OfferAndAddress>
                 match ret {
                     Ok(offer) => return Some(OfferAndAddress { offer, address }),
                     Err(e) => {
-                        log::debug!(target: "offerbook",
+                        tracing::debug!(target: "offerbook",
                             "Failed to request offer from maker {}, \
                             reattempting... error={:?}",
                             address,
                             e
                         );
-                        if ii <= FIRST_CONNECT_ATTEMPTS {
-                            sleep(Duration::from_secs(FIRST_CONNECT_SLEEP_DELAY_SEC)).await;
+                        if ii <= taker_settings.first_connect_attempts {
+                            sleep(Duration::from_secs(
+                                taker_settings.first_connect_sleep_delay_sec,
+                            ))
+                            .await;
                             continue;
                         } else {
                             return None;
@@ -110,12 +215,12 @@ async fn download_maker_offer(address: MakerAddress) -> Option<OfferAndAddress>
                     }
                 }
             },
-            _ = sleep(Duration::from_secs(FIRST_CONNECT_ATTEMPT_TIMEOUT_SEC)) => {
-                log::debug!(target: "offerbook",
+            _ = sleep(Duration::from_secs(taker_settings.first_connect_attempt_timeout_sec)) => {
+                tracing::debug!(target: "offerbook",
                     "Timeout for request offer from maker {}, reattempting...",
                     address
                 );
-                if ii <= FIRST_CONNECT_ATTEMPTS {
+                if ii <= taker_settings.first_connect_attempts {
                     continue;
                 } else {
                     return None;
@@ -125,43 +230,152 @@ async fn download_maker_offer(address: MakerAddress) -> Option<OfferAndAddress>
     }
 }
 
-pub async fn sync_offerbook_with_addresses(
-    maker_addresses: Vec<MakerAddress>,
-) -> Vec<OfferAndAddress> {
-    let (offers_writer_m, mut offers_reader) = mpsc::channel::<Option<OfferAndAddress>>(100);
-    //unbounded_channel makes more sense here, but results in a compile
-    //error i cant figure out
+fn remove_clearnet_addresses(maker_addresses: Vec<MakerAddress>) -> Vec<MakerAddress> {
+    maker_addresses
+        .into_iter()
+        .filter(|addr| match addr {
+            MakerAddress::Clearnet { address } => {
+                tracing::debug!(target: "offerbook", "Tor only, skipping maker {}", address);
+                false
+            }
+            MakerAddress::Tor { address: _ } | MakerAddress::I2p { address: _ } => true,
+        })
+        .collect()
+}
 
-    let maker_addresses_len = maker_addresses.len();
+/// Request offers from the makers, at most taker_settings.max_concurrent_offer_requests
+/// at once, each offer is sent into the returned channel as soon as its maker responds.
+/// The channel closes when every maker has responded or timed out, unreachable makers
+/// are left out. Must be called from inside a tokio runtime
+pub fn stream_offerbook_with_addresses(
+    maker_addresses: Vec<MakerAddress>,
+    taker_settings: &TakerSettings,
+) -> mpsc::Receiver<OfferAndAddress> {
+    let maker_addresses = if Settings::is_tor_only() {
+        remove_clearnet_addresses(maker_addresses)
+    } else {
+        maker_addresses
+    };
+    let (offers_writer, offers_reader) = mpsc::channel::<OfferAndAddress>(100);
+    let request_permits = Arc::new(Semaphore::new(taker_settings.max_concurrent_offer_requests));
     for addr in maker_addresses {
-        let offers_writer = offers_writer_m.clone();
+        let offers_writer = offers_writer.clone();
+        let request_permits = Arc::clone(&request_permits);
+        let taker_settings = taker_settings.clone();
         tokio::spawn(async move {
-            if let Err(_e) = offers_writer.send(download_maker_offer(addr).await).await {
-                panic!("mpsc failed");
+            //the semaphore is never closed
+            let _permit = request_permits.acquire().await.unwrap();
+            let address_str = addr.to_string();
+            let offer_addr = download_maker_offer(addr, &taker_settings).await;
+            record_maker_connection(&address_str, offer_addr.is_some());
+            if let Some(offer_addr) = offer_addr {
+                //the reader may have stopped once it had enough offers
+                let _ = offers_writer.send(offer_addr).await;
             }
         });
     }
+    offers_reader
+}
+
+pub async fn sync_offerbook_with_addresses(
+    maker_addresses: Vec<MakerAddress>,
+) -> Vec<OfferAndAddress> {
+    let mut offers_reader = stream_offerbook_with_addresses(maker_addresses, &taker_settings());
     let mut result = Vec::<OfferAndAddress>::new();
-    for _ in 0..maker_addresses_len {
-        if let Some(offer_addr) = offers_reader.recv().await.unwrap() {
-            result.push(offer_addr);
-        }
+    while let Some(offer_addr) = offers_reader.recv().await {
+        result.push(offer_addr);
     }
     result
 }
 
+//the makers of the last sync, used if the directory servers cant be reached
+fn cached_maker_addresses(network: Network) -> Vec<MakerAddress> {
+    if !Settings::is_initialized() {
+        return Vec::new();
+    }
+    load_offerbook_cache(&offerbook_cache_path(network))
+        .map(|cache| cache.offers.into_iter().map(|o| o.address).collect())
+        .unwrap_or_default()
+}
+
+/// The makers listed by the directory servers, or known from the last sync if they
+/// cant be reached, together with the makers some of those makers know of
 pub async fn get_advertised_maker_addresses(
     network: Network,
 ) -> Result<Vec<MakerAddress>, DirectoryServerError> {
-    Ok(if network == Network::Regtest {
-        get_regtest_maker_addresses()
-    } else {
-        sync_maker_addresses_from_directory_servers(network).await?
-    })
+    if network == Network::Regtest {
+        return Ok(get_regtest_maker_addresses());
+    }
+    let mut maker_addresses = match sync_maker_addresses_from_directory_servers(network).await {
+        Ok(maker_addresses) => maker_addresses,
+        Err(e) => {
+            let maker_addresses = cached_maker_addresses(network);
+            if maker_addresses.is_empty() {
+                return Err(e);
+            }
+            tracing::warn!(target: "offerbook",
+                "unable to reach directory servers, using makers from the last sync: {:?}", e);
+            maker_addresses
+        }
+    };
+    for signed_address in gossip_with_makers(&maker_addresses).await {
+        if !maker_addresses
+            .iter()
+            .any(|a| a.to_string() == signed_address.address)
+        {
+            maker_addresses.push(MakerAddress::from_published(signed_address.address));
+        }
+    }
+    Ok(maker_addresses)
 }
 
+/// Download the offers of every advertised maker, they're also written to the offerbook
+/// cache if the settings are initialized
 pub async fn sync_offerbook(
     network: Network,
 ) -> Result<Vec<OfferAndAddress>, DirectoryServerError> {
-    Ok(sync_offerbook_with_addresses(get_advertised_maker_addresses(network).await?).await)
+    let offers =
+        sync_offerbook_with_addresses(get_advertised_maker_addresses(network).await?).await;
+    if Settings::is_initialized() {
+        if let Err(e) = save_offerbook_cache(&offerbook_cache_path(network), &offers) {
+            tracing::warn!(target: "offerbook", "unable to save offerbook cache: {:?}", e);
+        }
+    }
+    Ok(offers)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_remove_clearnet_addresses() {
+        let mut maker_addresses = get_regtest_maker_addresses();
+        maker_addresses.push(MakerAddress::Tor {
+            address: "abcdef.onion:6102".to_string(),
+        });
+        maker_addresses.push(MakerAddress::from_published("abcdef.b32.i2p".to_string()));
+        let maker_addresses = remove_clearnet_addresses(maker_addresses);
+        assert_eq!(maker_addresses.len(), 2);
+        assert_eq!(maker_addresses[0].to_string(), "abcdef.onion:6102");
+        assert!(matches!(maker_addresses[1], MakerAddress::I2p { .. }));
+    }
+
+    #[test]
+    fn test_offerbook_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("offerbook.json");
+        assert!(load_offerbook_cache(&path).is_err());
+        save_offerbook_cache(&path, &[]).unwrap();
+        let cache = load_offerbook_cache(&path).unwrap();
+        assert!(cache.offers.is_empty());
+        assert!(cache.synced_timestamp > 0);
+    }
+
+    #[tokio::test]
+    async fn test_stream_offerbook_closes() {
+        let mut offers_reader =
+            stream_offerbook_with_addresses(Vec::new(), &Settings::default().taker);
+        assert!(offers_reader.recv().await.is_none());
+    }
 }
@@ -0,0 +1,425 @@
+//BIP78 payjoin sender, used by direct-send when paying a bitcoin: uri with a pj= endpoint
+//https://github.com/bitcoin/bips/blob/master/bip-0078.mediawiki
+//the signed original transaction is sent to the receiver who adds an input of their own,
+// the returned proposal is checked and signed again. if anything goes wrong the caller
+// should broadcast the original transaction instead
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use bitcoin::util::psbt::PartiallySignedTransaction;
+use bitcoin::{Address, Script, Transaction, TxOut};
+use url::Url;
+
+use crate::directory_servers::TOR_ADDR;
+use crate::error::Error;
+use crate::rpc::Client;
+use crate::settings::Settings;
+use crate::wallet_sync::{UTXOSpendInfo, Wallet};
+
+//vsize of a p2wpkh input, we pay at most this much extra fee for the receiver's input
+const P2WPKH_INPUT_VSIZE: u64 = 68;
+const PAYJOIN_REQUEST_TIMEOUT_SECS: u64 = 60;
+
+#[derive(Debug)]
+pub struct PayjoinParams {
+    //our change output, which the receiver may take the additional fee from
+    pub additional_fee_output_index: Option<usize>,
+    pub max_additional_fee_contribution: u64,
+    //sat/vb
+    pub min_fee_rate: f64,
+}
+
+impl PayjoinParams {
+    fn request_url(&self, endpoint: &Url) -> Url {
+        let mut url = endpoint.clone();
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("v", "1");
+            //the receiver is never allowed to change where the payment goes
+            query.append_pair("disableoutputsubstitution", "true");
+            if let Some(index) = self.additional_fee_output_index {
+                query.append_pair("additionalfeeoutputindex", &index.to_string());
+                query.append_pair(
+                    "maxadditionalfeecontribution",
+                    &self.max_additional_fee_contribution.to_string(),
+                );
+            }
+            query.append_pair("minfeerate", &format!("{:.3}", self.min_fee_rate));
+        }
+        url
+    }
+}
+
+//the payjoin transaction with the receiver's inputs signed and ours not
+#[derive(Debug)]
+pub struct PayjoinProposal {
+    pub tx: Transaction,
+    //position in the proposal of each input of the original transaction
+    pub original_input_indexes: Vec<usize>,
+}
+
+//BIP78 wants the original as a psbt with finalized inputs
+pub fn create_original_psbt(
+    tx: &Transaction,
+    spent_outputs: &[TxOut],
+) -> PartiallySignedTransaction {
+    let mut unsigned_tx = tx.clone();
+    for input in unsigned_tx.input.iter_mut() {
+        input.witness.clear();
+        input.script_sig = Script::new();
+    }
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned_tx).unwrap();
+    for ((psbt_input, input), spent_output) in psbt
+        .inputs
+        .iter_mut()
+        .zip(tx.input.iter())
+        .zip(spent_outputs)
+    {
+        psbt_input.witness_utxo = Some(spent_output.clone());
+        psbt_input.final_script_witness = Some(input.witness.clone());
+    }
+    psbt
+}
+
+//the checks a sender must do from BIP78, with output substitution always disabled
+pub fn check_payjoin_proposal(
+    original: &PartiallySignedTransaction,
+    proposal: &PartiallySignedTransaction,
+    params: &PayjoinParams,
+    payee_script: &Script,
+) -> Result<PayjoinProposal, Error> {
+    let original_tx = &original.global.unsigned_tx;
+    let proposal_tx = &proposal.global.unsigned_tx;
+    if proposal_tx.version != original_tx.version || proposal_tx.lock_time != original_tx.lock_time
+    {
+        return Err(Error::Protocol(
+            "payjoin proposal changed version or locktime",
+        ));
+    }
+    if proposal.inputs.len() != proposal_tx.input.len() {
+        return Err(Error::Protocol("payjoin proposal psbt is malformed"));
+    }
+
+    let our_scripts = original
+        .inputs
+        .iter()
+        .map(|i| i.witness_utxo.as_ref().map(|o| o.script_pubkey.clone()))
+        .collect::<Option<Vec<Script>>>()
+        .ok_or(Error::Protocol("original psbt missing witness utxo"))?;
+    let mut original_input_indexes = vec![None; original_tx.input.len()];
+    let mut tx = proposal_tx.clone();
+    let mut tx_with_witnesses = proposal_tx.clone();
+    let mut input_value = 0;
+    for (ix, (psbt_input, input)) in proposal.inputs.iter().zip(tx.input.iter_mut()).enumerate() {
+        match original_tx
+            .input
+            .iter()
+            .position(|i| i.previous_output == input.previous_output)
+        {
+            Some(original_ix) => {
+                if input.sequence != original_tx.input[original_ix].sequence {
+                    return Err(Error::Protocol(
+                        "payjoin proposal changed our input sequence",
+                    ));
+                }
+                if original_input_indexes[original_ix].replace(ix).is_some() {
+                    return Err(Error::Protocol("payjoin proposal duplicated our input"));
+                }
+                input_value += original.inputs[original_ix]
+                    .witness_utxo
+                    .as_ref()
+                    .unwrap()
+                    .value;
+                //signatures from the original have the same size as the ones we make now
+                tx_with_witnesses.input[ix].witness = original.inputs[original_ix]
+                    .final_script_witness
+                    .clone()
+                    .unwrap_or_default();
+            }
+            None => {
+                let spent_output = psbt_input.witness_utxo.as_ref().ok_or(Error::Protocol(
+                    "payjoin receiver input has no witness utxo",
+                ))?;
+                if !spent_output.script_pubkey.is_v0_p2wpkh() {
+                    return Err(Error::Protocol("payjoin receiver input isnt p2wpkh"));
+                }
+                if our_scripts.contains(&spent_output.script_pubkey) {
+                    return Err(Error::Protocol("payjoin receiver input is one of ours"));
+                }
+                if input.sequence != original_tx.input[0].sequence {
+                    return Err(Error::Protocol(
+                        "payjoin receiver input has a different sequence",
+                    ));
+                }
+                let witness = psbt_input
+                    .final_script_witness
+                    .clone()
+                    .ok_or(Error::Protocol("payjoin receiver input not finalized"))?;
+                input.witness = witness.clone();
+                input.script_sig = psbt_input.final_script_sig.clone().unwrap_or_default();
+                tx_with_witnesses.input[ix].witness = witness;
+                tx_with_witnesses.input[ix].script_sig = input.script_sig.clone();
+                input_value += spent_output.value;
+            }
+        }
+    }
+    let original_input_indexes = original_input_indexes
+        .into_iter()
+        .collect::<Option<Vec<usize>>>()
+        .ok_or(Error::Protocol(
+            "payjoin proposal removed one of our inputs",
+        ))?;
+
+    for (ix, original_output) in original_tx.output.iter().enumerate() {
+        let proposal_output = proposal_tx
+            .output
+            .iter()
+            .find(|o| o.script_pubkey == original_output.script_pubkey)
+            .ok_or(Error::Protocol(
+                "payjoin proposal removed one of our outputs",
+            ))?;
+        if original_output.script_pubkey == *payee_script {
+            if proposal_output.value < original_output.value {
+                return Err(Error::Protocol("payjoin proposal decreased the payment"));
+            }
+        } else if params.additional_fee_output_index == Some(ix) {
+            let fee_contribution = original_output.value.saturating_sub(proposal_output.value);
+            if fee_contribution > params.max_additional_fee_contribution {
+                return Err(Error::Protocol(
+                    "payjoin proposal takes too much fee from us",
+                ));
+            }
+        } else if proposal_output.value != original_output.value {
+            return Err(Error::Protocol(
+                "payjoin proposal changed one of our outputs",
+            ));
+        }
+    }
+
+    let output_value = proposal_tx.output.iter().map(|o| o.value).sum::<u64>();
+    let fee = input_value
+        .checked_sub(output_value)
+        .ok_or(Error::Protocol(
+            "payjoin proposal spends more than its inputs",
+        ))?;
+    let vsize = tx_with_witnesses.get_weight().div_ceil(4);
+    if (fee as f64) < params.min_fee_rate * vsize as f64 {
+        return Err(Error::Protocol("payjoin proposal fee rate too low"));
+    }
+
+    Ok(PayjoinProposal {
+        tx,
+        original_input_indexes,
+    })
+}
+
+async fn request_payjoin_proposal(
+    url: Url,
+    original: &PartiallySignedTransaction,
+) -> Result<PartiallySignedTransaction, Error> {
+    let mut client_builder =
+        reqwest::Client::builder().timeout(Duration::from_secs(PAYJOIN_REQUEST_TIMEOUT_SECS));
+    if Settings::is_tor_only() || url.host_str().is_some_and(|host| host.ends_with(".onion")) {
+        let proxy = reqwest::Proxy::all(format!("socks5h://{}", TOR_ADDR))
+            .map_err(|e| Error::Network(Box::new(e)))?;
+        client_builder = client_builder.proxy(proxy);
+    }
+    let client = client_builder
+        .build()
+        .map_err(|e| Error::Network(Box::new(e)))?;
+    let response = client
+        .post(url)
+        .header("Content-Type", "text/plain")
+        .body(original.to_string())
+        .send()
+        .await
+        .map_err(|e| Error::Network(Box::new(e)))?;
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| Error::Network(Box::new(e)))?;
+    if !status.is_success() {
+        tracing::warn!("payjoin receiver returned {}: {}", status, body);
+        return Err(Error::Protocol("payjoin receiver returned an error"));
+    }
+    PartiallySignedTransaction::from_str(body.trim())
+        .map_err(|_| Error::Protocol("payjoin receiver sent an invalid psbt"))
+}
+
+//returns the payjoin transaction signed and ready to broadcast
+#[tokio::main]
+pub async fn send_payjoin(
+    rpc: &Client,
+    wallet: &Wallet,
+    original_tx: &Transaction,
+    endpoint: &Url,
+    payee: &Address,
+    fee_rate: u64,
+) -> Result<Transaction, Error> {
+    let utxos = wallet.list_unspent_from_wallet(rpc, false, false)?;
+    let mut spent_outputs = Vec::new();
+    let mut spend_infos = Vec::new();
+    for input in &original_tx.input {
+        let (utxo, spend_info) = utxos
+            .iter()
+            .find(|(u, _)| {
+                u.txid == input.previous_output.txid && u.vout == input.previous_output.vout
+            })
+            .ok_or(Error::Protocol("payjoin input not found in wallet"))?;
+        //the receiver is meant to add an input of the same type as ours
+        if !matches!(spend_info, UTXOSpendInfo::SeedCoin { .. }) {
+            return Err(Error::Protocol("payjoins can only spend seed coins"));
+        }
+        spent_outputs.push(TxOut {
+            value: utxo.amount.as_sat(),
+            script_pubkey: utxo.script_pub_key.clone(),
+        });
+        spend_infos.push(spend_info.clone());
+    }
+
+    let original_psbt = create_original_psbt(original_tx, &spent_outputs);
+    let payee_script = payee.script_pubkey();
+    let params = PayjoinParams {
+        additional_fee_output_index: original_tx
+            .output
+            .iter()
+            .position(|o| o.script_pubkey != payee_script),
+        max_additional_fee_contribution: P2WPKH_INPUT_VSIZE * fee_rate / 1000,
+        min_fee_rate: fee_rate as f64 / 1000.0,
+    };
+    tracing::debug!("payjoin params = {:?}", params);
+    let proposal = request_payjoin_proposal(params.request_url(endpoint), &original_psbt).await?;
+    let proposal = check_payjoin_proposal(&original_psbt, &proposal, &params, &payee_script)?;
+
+    let mut tx = proposal.tx;
+    let inputs_info = proposal
+        .original_input_indexes
+        .into_iter()
+        .zip(spend_infos)
+        .collect::<Vec<_>>();
+    wallet.sign_transaction_inputs(&mut tx, &inputs_info);
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::{OutPoint, TxIn, Txid, WPubkeyHash};
+
+    fn p2wpkh_script(n: u8) -> Script {
+        Script::new_v0_wpkh(&WPubkeyHash::hash(&[n]))
+    }
+
+    fn input(n: u8) -> TxIn {
+        TxIn {
+            previous_output: OutPoint {
+                txid: Txid::hash(&[n]),
+                vout: 0,
+            },
+            script_sig: Script::new(),
+            sequence: 0,
+            witness: vec![vec![n; 72], vec![n; 33]],
+        }
+    }
+
+    fn original_psbt() -> PartiallySignedTransaction {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![input(1)],
+            output: vec![
+                TxOut {
+                    value: 50000,
+                    script_pubkey: p2wpkh_script(10),
+                },
+                TxOut {
+                    value: 40000,
+                    script_pubkey: p2wpkh_script(11),
+                },
+            ],
+        };
+        let spent_outputs = vec![TxOut {
+            value: 100000,
+            script_pubkey: p2wpkh_script(1),
+        }];
+        create_original_psbt(&tx, &spent_outputs)
+    }
+
+    //the receiver adds their input in front of ours, adds it to the payment and takes
+    // change_decrease from our change for the fee
+    fn proposal_psbt(change_decrease: u64) -> PartiallySignedTransaction {
+        let original = original_psbt();
+        let mut tx = original.global.unsigned_tx.clone();
+        let mut receiver_input = input(2);
+        let receiver_witness = receiver_input.witness.split_off(0);
+        tx.input.insert(0, receiver_input);
+        tx.output[0].value += 30000;
+        tx.output[1].value -= change_decrease;
+        let mut proposal = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+        proposal.inputs[0].witness_utxo = Some(TxOut {
+            value: 30000,
+            script_pubkey: p2wpkh_script(2),
+        });
+        proposal.inputs[0].final_script_witness = Some(receiver_witness);
+        proposal
+    }
+
+    fn params() -> PayjoinParams {
+        PayjoinParams {
+            additional_fee_output_index: Some(1),
+            max_additional_fee_contribution: 68,
+            min_fee_rate: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_check_payjoin_proposal() {
+        let original = original_psbt();
+        let payee_script = p2wpkh_script(10);
+
+        let proposal =
+            check_payjoin_proposal(&original, &proposal_psbt(68), &params(), &payee_script)
+                .unwrap();
+        assert_eq!(proposal.original_input_indexes, vec![1]);
+        assert_eq!(proposal.tx.input[0].witness.len(), 2);
+        assert!(proposal.tx.input[1].witness.is_empty());
+
+        //taking more fee from us than allowed
+        assert!(
+            check_payjoin_proposal(&original, &proposal_psbt(69), &params(), &payee_script)
+                .is_err()
+        );
+
+        let mut proposal = proposal_psbt(0);
+        proposal.global.unsigned_tx.input[1].sequence = 1;
+        assert!(check_payjoin_proposal(&original, &proposal, &params(), &payee_script).is_err());
+
+        let mut proposal = proposal_psbt(0);
+        proposal.inputs[0].final_script_witness = None;
+        assert!(check_payjoin_proposal(&original, &proposal, &params(), &payee_script).is_err());
+
+        let mut proposal = proposal_psbt(0);
+        proposal.global.unsigned_tx.output[0].value = 49999;
+        assert!(check_payjoin_proposal(&original, &proposal, &params(), &payee_script).is_err());
+
+        let mut proposal = proposal_psbt(0);
+        proposal.global.unsigned_tx.input.remove(1);
+        proposal.inputs.remove(1);
+        assert!(check_payjoin_proposal(&original, &proposal, &params(), &payee_script).is_err());
+    }
+
+    #[test]
+    fn test_payjoin_request_url() {
+        let endpoint = Url::parse("https://example.com/pj?id=1").unwrap();
+        let url = params().request_url(&endpoint);
+        assert_eq!(
+            url.as_str(),
+            "https://example.com/pj?id=1&v=1&disableoutputsubstitution=true\
+            &additionalfeeoutputindex=1&maxadditionalfeecontribution=68&minfeerate=1.000"
+        );
+    }
+}
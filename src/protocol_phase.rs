@@ -0,0 +1,234 @@
+//the phases of a connection between a taker and a maker, and which message is allowed in each
+//both protocols look up the next phase here before handling a message, so an unexpected message
+// is rejected the same way whatever phase it arrives in, and the transitions can be tested
+// without any sockets
+//phases are serializable so the progress of a connection can be saved and resumed
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::messages::{
+    IdentityProof, MakerAddresses, MakerHello, MakerToTakerMessage, Offer, PrivateKeyHandover,
+    ReceiversContractSig, SendersContractSig, SignSendersAndReceiversContractTxes,
+    TakerToMakerMessage,
+};
+
+/// The message a maker waits for next from a taker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MakerPhase {
+    TakerHello,
+    //a newly connected taker may skip ahead to any message which starts a new connection
+    // of a coinswap, as takers make a new connection for each step
+    NewlyConnectedTaker,
+    SignSendersContractTx,
+    ProofOfFunding,
+    ProofOfFundingORSendersAndReceiversContractSigs,
+    SignReceiversContractTx,
+    HashPreimage,
+    PrivateKeyHandover,
+    //the coinswap is complete, nothing more is allowed on the connection
+    Complete,
+}
+
+impl MakerPhase {
+    /// The phase after receiving message, or an error if message isnt allowed in this phase
+    pub fn next(self, message: &TakerToMakerMessage) -> Result<MakerPhase, Error> {
+        use MakerPhase::*;
+        match (self, message) {
+            (TakerHello, TakerToMakerMessage::TakerHello(_)) => Ok(NewlyConnectedTaker),
+            (NewlyConnectedTaker, TakerToMakerMessage::GiveOffer(_)) => Ok(SignSendersContractTx),
+            //the connection stays newly connected, so GiveOffer can follow
+            (NewlyConnectedTaker, TakerToMakerMessage::GiveMakerAddresses(_)) => {
+                Ok(NewlyConnectedTaker)
+            }
+            (
+                NewlyConnectedTaker | SignSendersContractTx,
+                TakerToMakerMessage::SignSendersContractTx(_),
+            ) => Ok(ProofOfFunding),
+            (
+                NewlyConnectedTaker
+                | ProofOfFunding
+                | ProofOfFundingORSendersAndReceiversContractSigs,
+                TakerToMakerMessage::ProofOfFunding(_),
+            ) => Ok(ProofOfFundingORSendersAndReceiversContractSigs),
+            (
+                ProofOfFundingORSendersAndReceiversContractSigs,
+                TakerToMakerMessage::SendersAndReceiversContractSigs(_),
+            ) => Ok(SignReceiversContractTx),
+            (
+                NewlyConnectedTaker | SignReceiversContractTx,
+                TakerToMakerMessage::SignReceiversContractTx(_),
+            ) => Ok(HashPreimage),
+            (NewlyConnectedTaker | HashPreimage, TakerToMakerMessage::HashPreimage(_)) => {
+                Ok(PrivateKeyHandover)
+            }
+            (PrivateKeyHandover, TakerToMakerMessage::PrivateKeyHandover(_)) => Ok(Complete),
+            (phase, _) => Err(Error::Protocol(phase.unexpected_message_error())),
+        }
+    }
+
+    fn unexpected_message_error(self) -> &'static str {
+        match self {
+            MakerPhase::TakerHello => "Expected Taker Hello Message",
+            MakerPhase::NewlyConnectedTaker => "Unexpected Newly Connected Taker message",
+            MakerPhase::SignSendersContractTx => {
+                "Expected Sign sender's contract transaction message"
+            }
+            MakerPhase::ProofOfFunding => "Expected proof of funding message",
+            MakerPhase::ProofOfFundingORSendersAndReceiversContractSigs => {
+                "Expected proof of funding or sender's and reciever's contract signatures"
+            }
+            MakerPhase::SignReceiversContractTx => "Expected reciever's contract transaction",
+            MakerPhase::HashPreimage => "Expected hash preimgae",
+            MakerPhase::PrivateKeyHandover => "expected privatekey handover",
+            MakerPhase::Complete => "coinswap already complete",
+        }
+    }
+}
+
+/// The message a taker waits for next from a maker, each connection of a taker is one request
+/// and its reply, apart from the connection which goes from proof of funding to private key
+/// handover
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TakerPhase {
+    MakerHello,
+    IdentityProof,
+    Offer,
+    MakerAddresses,
+    SendersContractSig,
+    SignSendersAndReceiversContractTxes,
+    ReceiversContractSig,
+    PrivateKeyHandover,
+}
+
+impl TakerPhase {
+    fn unexpected_message_error(self) -> &'static str {
+        match self {
+            TakerPhase::MakerHello => "expected method makerhello",
+            TakerPhase::IdentityProof => "expected method identityproof",
+            TakerPhase::Offer => "expected method offer",
+            TakerPhase::MakerAddresses => "expected method makeraddresses",
+            TakerPhase::SendersContractSig => "expected method senderscontractsig",
+            TakerPhase::SignSendersAndReceiversContractTxes => {
+                "expected method signsendersandreceiverscontracttxes"
+            }
+            TakerPhase::ReceiversContractSig => "expected method receiverscontractsig",
+            TakerPhase::PrivateKeyHandover => "expected method privatekeyhandover",
+        }
+    }
+}
+
+/// A message from a maker which the taker waits for in a phase
+pub trait ExpectedMessage: Sized {
+    const PHASE: TakerPhase;
+    fn from_message(message: MakerToTakerMessage) -> Option<Self>;
+}
+
+macro_rules! expected_message {
+    ($message:ident) => {
+        impl ExpectedMessage for $message {
+            const PHASE: TakerPhase = TakerPhase::$message;
+            fn from_message(message: MakerToTakerMessage) -> Option<Self> {
+                match message {
+                    MakerToTakerMessage::$message(m) => Some(m),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+expected_message!(MakerHello);
+expected_message!(IdentityProof);
+expected_message!(Offer);
+expected_message!(MakerAddresses);
+expected_message!(SendersContractSig);
+expected_message!(SignSendersAndReceiversContractTxes);
+expected_message!(ReceiversContractSig);
+expected_message!(PrivateKeyHandover);
+
+/// The contents of message if it is the one expected, otherwise an error
+pub fn expect_message<M: ExpectedMessage>(message: MakerToTakerMessage) -> Result<M, Error> {
+    M::from_message(message).ok_or(Error::Protocol(M::PHASE.unexpected_message_error()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::messages::{GiveMakerAddresses, GiveOffer, SendersAndReceiversContractSigs};
+
+    fn taker_hello() -> TakerToMakerMessage {
+        TakerToMakerMessage::TakerHello(crate::messages::TakerHello {
+            protocol_version_min: 0,
+            protocol_version_max: 0,
+            session_id: None,
+            identity_challenge: None,
+        })
+    }
+
+    fn contract_sigs() -> TakerToMakerMessage {
+        TakerToMakerMessage::SendersAndReceiversContractSigs(SendersAndReceiversContractSigs {
+            session_id: [0; 16],
+            receivers_sigs: Vec::new(),
+            senders_sigs: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn test_maker_phase_transitions() {
+        let phase = MakerPhase::TakerHello.next(&taker_hello()).unwrap();
+        assert_eq!(phase, MakerPhase::NewlyConnectedTaker);
+        let phase = phase
+            .next(&TakerToMakerMessage::GiveMakerAddresses(GiveMakerAddresses))
+            .unwrap();
+        assert_eq!(phase, MakerPhase::NewlyConnectedTaker);
+        assert_eq!(
+            phase
+                .next(&TakerToMakerMessage::GiveOffer(GiveOffer))
+                .unwrap(),
+            MakerPhase::SignSendersContractTx
+        );
+        assert_eq!(
+            MakerPhase::ProofOfFundingORSendersAndReceiversContractSigs
+                .next(&contract_sigs())
+                .unwrap(),
+            MakerPhase::SignReceiversContractTx
+        );
+
+        //unexpected messages are rejected in every phase
+        assert!(phase.next(&taker_hello()).is_err());
+        assert!(phase.next(&contract_sigs()).is_err());
+        assert!(MakerPhase::TakerHello
+            .next(&TakerToMakerMessage::GiveOffer(GiveOffer))
+            .is_err());
+        assert!(MakerPhase::SignSendersContractTx
+            .next(&TakerToMakerMessage::GiveOffer(GiveOffer))
+            .is_err());
+        assert!(MakerPhase::Complete.next(&taker_hello()).is_err());
+
+        let json = serde_json::to_string(&MakerPhase::HashPreimage).unwrap();
+        assert_eq!(
+            serde_json::from_str::<MakerPhase>(&json).unwrap(),
+            MakerPhase::HashPreimage
+        );
+    }
+
+    #[test]
+    fn test_expect_message() {
+        let hello = || {
+            MakerToTakerMessage::MakerHello(MakerHello {
+                protocol_version_min: 0,
+                protocol_version_max: 1,
+                session_id: None,
+                identity_pubkey: None,
+            })
+        };
+        let makerhello = expect_message::<MakerHello>(hello()).unwrap();
+        assert_eq!(makerhello.protocol_version_max, 1);
+        match expect_message::<SendersContractSig>(hello()) {
+            Err(Error::Protocol(e)) => assert_eq!(e, "expected method senderscontractsig"),
+            _ => panic!("expected a protocol error"),
+        }
+    }
+}
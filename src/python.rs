@@ -0,0 +1,229 @@
+//python module for scripting coinswaps, e.g. on regtest from integration tests
+//build with `cargo rustc --lib --release --features python --crate-type cdylib` and copy
+// target/release/libteleport.so to teleport.so somewhere on the python path
+//
+//    import teleport
+//    teleport.init("/tmp/teleport-regtest")
+//    wallet = teleport.Wallet.load("taker-wallet")
+//    print(wallet.get_balance(), teleport.sync_offerbook())
+//    wallet.run_coinswap(500000, callback=print)
+//
+//results are returned as the same dicts and lists as teleportd's JSON-RPC interface
+
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+use std::thread;
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use serde::Serialize;
+
+use crate::daemon::{get_balance, get_new_address, list_unspent, OfferInfo};
+use crate::error::Error;
+use crate::offerbook_sync::sync_offerbook as sync_offerbook_async;
+use crate::rpc::Client;
+use crate::settings::{Settings, SettingsOverrides};
+use crate::taker_protocol::{min_funding_tx_fee_rate, start_taker, TakerConfig, TakerEvent};
+use crate::utils::default_data_dir;
+use crate::wallet_sync::{Wallet, WalletSyncAddressAmount};
+use crate::{get_bitcoin_rpc, setup_teleport, str_to_bitcoin_network};
+
+create_exception!(teleport, TeleportError, PyException);
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> PyErr {
+        TeleportError::new_err(format!("{:?}", e))
+    }
+}
+
+//converts through json so python gets the same objects as JSON-RPC clients
+fn to_python<T: Serialize>(py: Python<'_>, value: &T) -> PyResult<PyObject> {
+    let value_json = serde_json::to_string(value).unwrap();
+    Ok(py
+        .import("json")?
+        .call_method1("loads", (value_json,))?
+        .unbind())
+}
+
+fn check_initialized() -> PyResult<()> {
+    if Settings::is_initialized() {
+        Ok(())
+    } else {
+        Err(TeleportError::new_err("call teleport.init() first"))
+    }
+}
+
+/// Load the settings from data_dir, or the default data directory, and set up logging
+#[pyfunction]
+#[pyo3(signature = (data_dir=None))]
+fn init(data_dir: Option<PathBuf>) -> PyResult<()> {
+    if Settings::is_initialized() {
+        return Err(TeleportError::new_err("teleport.init() was already called"));
+    }
+    let data_dir = data_dir.unwrap_or_else(|| default_data_dir("teleport"));
+    Settings::init_settings(&data_dir, &SettingsOverrides::default())?;
+    setup_teleport();
+    Ok(())
+}
+
+/// Download the offers of every advertised maker
+#[pyfunction]
+fn sync_offerbook(py: Python<'_>) -> PyResult<PyObject> {
+    check_initialized()?;
+    let network = str_to_bitcoin_network(&Settings::global().blockchain.network);
+    let offers = py.allow_threads(|| {
+        let runtime = tokio::runtime::Runtime::new().map_err(Error::Disk)?;
+        runtime
+            .block_on(sync_offerbook_async(network))
+            .map_err(|e| TeleportError::new_err(format!("{:?}", e)))
+    })?;
+    let offers = offers
+        .into_iter()
+        .map(|offer_address| OfferInfo {
+            address: offer_address.address.to_string(),
+            offer: offer_address.offer,
+        })
+        .collect::<Vec<OfferInfo>>();
+    to_python(py, &offers)
+}
+
+#[pyclass(name = "Wallet", unsendable)]
+struct PyWallet {
+    rpc: Client,
+    wallet: Wallet,
+}
+
+#[pymethods]
+impl PyWallet {
+    /// Load and sync a wallet file
+    #[staticmethod]
+    fn load(py: Python<'_>, wallet_file: PathBuf) -> PyResult<PyWallet> {
+        check_initialized()?;
+        py.allow_threads(|| {
+            let (rpc, network) = get_bitcoin_rpc()?;
+            let mut wallet = Wallet::load_wallet_from_file(
+                &wallet_file,
+                network,
+                WalletSyncAddressAmount::Normal,
+            )?;
+            wallet.startup_sync(&rpc)?;
+            Ok(PyWallet { rpc, wallet })
+        })
+    }
+
+    fn sync(&mut self) -> PyResult<()> {
+        Ok(self.wallet.startup_sync(&self.rpc)?)
+    }
+
+    fn get_balance(&mut self, py: Python<'_>) -> PyResult<PyObject> {
+        let balance = get_balance(&self.rpc, &mut self.wallet)?;
+        to_python(py, &balance)
+    }
+
+    fn list_unspent(&mut self, py: Python<'_>) -> PyResult<PyObject> {
+        let utxos = list_unspent(&self.rpc, &mut self.wallet)?;
+        to_python(py, &utxos)
+    }
+
+    fn get_new_address(&mut self) -> PyResult<String> {
+        Ok(get_new_address(&self.rpc, &mut self.wallet)?)
+    }
+
+    /// Do a coinswap, blocking until it finishes
+    /// callback, if given, is called with every progress event as a dict
+    //same defaults as the daemon's startcoinswap
+    #[pyo3(signature = (send_amount, maker_count=2, tx_count=3, fee_rate=1000, callback=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn run_coinswap(
+        &mut self,
+        py: Python<'_>,
+        send_amount: u64,
+        maker_count: u16,
+        tx_count: u32,
+        fee_rate: u64,
+        callback: Option<PyObject>,
+    ) -> PyResult<()> {
+        self.wallet.open_audit_log()?;
+        let config = TakerConfig {
+            send_amount,
+            maker_count,
+            tx_count,
+            required_confirms: 1,
+            fee_rate,
+            min_funding_tx_fee_rate: min_funding_tx_fee_rate(),
+            account: None,
+        };
+
+        let (progress_tx, mut progress_rx) = channel::<TakerEvent>();
+        let rpc = &self.rpc;
+        let wallet = &mut self.wallet;
+        let mut last_event = None;
+        let taker_result = thread::scope(|scope| {
+            //start_taker makes its own tokio runtime, callbacks run on the calling thread
+            let taker_thread =
+                scope.spawn(move || start_taker(rpc, wallet, config, Some(progress_tx)));
+            loop {
+                //a Receiver can be sent to but not shared with other threads
+                let progress_rx = &mut progress_rx;
+                let event = match py.allow_threads(move || progress_rx.recv()) {
+                    Ok(event) => event,
+                    //the taker finished and dropped progress_tx
+                    Err(_) => break,
+                };
+                if let Some(callback) = &callback {
+                    let event_object = to_python(py, &event)?;
+                    callback.call1(py, (event_object,))?;
+                }
+                last_event = Some(event);
+            }
+            Ok::<_, PyErr>(taker_thread.join())
+        })?;
+        match (taker_result, last_event) {
+            (Ok(()), Some(TakerEvent::CoinswapComplete)) => Ok(()),
+            (Err(_), _) => Err(TeleportError::new_err("panic during coinswap")),
+            _ => Err(TeleportError::new_err(
+                "coinswap did not complete, see the log for why",
+            )),
+        }
+    }
+}
+
+#[pymodule]
+fn teleport(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("TeleportError", m.py().get_type::<TeleportError>())?;
+    m.add_function(wrap_pyfunction!(init, m)?)?;
+    m.add_function(wrap_pyfunction!(sync_offerbook, m)?)?;
+    m.add_class::<PyWallet>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use pyo3::types::PyDict;
+
+    use crate::daemon::WalletBalance;
+
+    #[test]
+    fn test_python_module() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let balance = WalletBalance {
+                spendable_sat: 100000,
+                spendable_utxo_count: 2,
+                fidelity_bond_sat: 0,
+                pending_incoming_sat: 0,
+            };
+            let balance = to_python(py, &balance).unwrap();
+            let balance = balance.downcast_bound::<PyDict>(py).unwrap();
+            let spendable_sat = balance.get_item("spendable_sat").unwrap().unwrap();
+            assert_eq!(spendable_sat.extract::<u64>().unwrap(), 100000);
+
+            //the settings are never initialized in unit tests
+            let error = sync_offerbook(py).unwrap_err();
+            assert!(error.is_instance_of::<TeleportError>(py));
+        });
+    }
+}
@@ -0,0 +1,104 @@
+//destinations of the wallet's recovery spends, the hashlock and timelock spends of contracts
+// given to watchtowers and written to contract exports, and the spends made by hand with
+// `spend-contract`
+//  wallet      fresh addresses of the wallet's change chain. each address is handed out once
+//              even before coins arrive on it, so no two spends pay to the same address
+//  descriptor  addresses of the ranged descriptor in recovery_descriptor of teleport.conf,
+//              e.g. of a cold wallet, so recovered coins never come back to this machine
+//the next index of either chain is kept in the wallet file
+
+use bitcoin::Address;
+use bitcoincore_rpc::RpcApi;
+
+use crate::error::Error;
+use crate::rpc::Client;
+use crate::settings::Settings;
+use crate::swapcoin_descriptors::descriptor_checksum;
+use crate::wallet_sync::Wallet;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DestinationProvider {
+    Wallet,
+    //with its checksum
+    Descriptor(String),
+}
+
+/// The ranged descriptor with its checksum, which Core needs to derive addresses. A checksum
+/// the descriptor already has must be correct
+pub fn checked_descriptor(descriptor: &str) -> Result<String, String> {
+    let (descriptor, checksum) = match descriptor.trim().split_once('#') {
+        Some((descriptor, checksum)) => (descriptor, Some(checksum)),
+        None => (descriptor.trim(), None),
+    };
+    let expected_checksum = descriptor_checksum(descriptor)
+        .ok_or_else(|| "descriptor has invalid characters".to_string())?;
+    if checksum.is_some_and(|checksum| checksum != expected_checksum) {
+        return Err(format!(
+            "invalid descriptor checksum, expected {}",
+            expected_checksum
+        ));
+    }
+    if !descriptor.contains('*') {
+        return Err("descriptor must be ranged, e.g. ending in /*".to_string());
+    }
+    Ok(format!("{}#{}", descriptor, expected_checksum))
+}
+
+impl DestinationProvider {
+    /// The descriptor in recovery_descriptor if set, otherwise the wallet
+    pub fn from_settings() -> DestinationProvider {
+        match Settings::is_initialized()
+            .then(|| Settings::global().recovery_descriptor.as_ref())
+            .flatten()
+        {
+            //checked when the settings were loaded
+            Some(descriptor) => DestinationProvider::Descriptor(
+                checked_descriptor(descriptor).expect("checked recovery_descriptor"),
+            ),
+            None => DestinationProvider::Wallet,
+        }
+    }
+
+    /// count addresses never handed out before, one for each spend
+    pub fn next_addresses(
+        &self,
+        wallet: &Wallet,
+        rpc: &Client,
+        count: u32,
+    ) -> Result<Vec<Address>, Error> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        match self {
+            DestinationProvider::Wallet => wallet.get_fresh_internal_addresses(rpc, count),
+            DestinationProvider::Descriptor(descriptor) => {
+                let first_index = wallet.reserve_recovery_indexes(true, 0, count)?;
+                Ok(rpc.derive_addresses(descriptor, Some([first_index, first_index + count - 1]))?)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_checked_descriptor() {
+        let descriptor = "wpkh(tpubD6NzVbkrYhZ4XgiXtGrdW5XDAPFCL9h7we1vwNCpn8tGbBcgfVYjXyhWo4E1xkh56hjod1RhGjxbaTLV3X4FyWuejifB9jusQ46QzG87VKp/0/*)";
+        let with_checksum = checked_descriptor(descriptor).unwrap();
+        assert_eq!(
+            with_checksum,
+            format!(
+                "{}#{}",
+                descriptor,
+                descriptor_checksum(descriptor).unwrap()
+            )
+        );
+        assert_eq!(checked_descriptor(&with_checksum).unwrap(), with_checksum);
+        assert!(checked_descriptor(&format!("{}#qqqqqqqq", descriptor)).is_err());
+        //not ranged
+        assert!(checked_descriptor(&descriptor.replace("/*", "/1")).is_err());
+        assert!(checked_descriptor("wpkh(\u{e9})").is_err());
+    }
+}
@@ -0,0 +1,323 @@
+//constraints on which makers a taker puts together in a coinswap route
+//each filter can refuse a maker outright or refuse two makers being in the same route, and the
+// taker drops refused makers from its candidates as it chooses the route one maker at a time
+//the filters used are set in the [taker] section of teleport.conf, see route_filters()
+
+use std::collections::{HashMap, HashSet};
+
+use bitcoin::Txid;
+use bitcoincore_rpc::RpcApi;
+
+use crate::contracts::calculate_maximum_locktime;
+use crate::maker_banlist::load_maker_banlist;
+use crate::offerbook_sync::OfferAndAddress;
+use crate::rpc::Client;
+use crate::settings::TakerSettings;
+use crate::swap_receipt::recent_swap_makers;
+
+pub trait RouteFilter {
+    /// Whether the maker may be in a route at all
+    fn allows_maker(&self, _maker: &OfferAndAddress) -> bool {
+        true
+    }
+
+    /// Whether the two makers may be in the same route
+    fn allows_together(&self, _maker: &OfferAndAddress, _other: &OfferAndAddress) -> bool {
+        true
+    }
+}
+
+pub type RouteFilters = Vec<Box<dyn RouteFilter + Send + Sync>>;
+
+/// Makers the user never wants to use
+pub struct ExcludedMakers {
+    pub addresses: HashSet<String>,
+}
+
+impl RouteFilter for ExcludedMakers {
+    fn allows_maker(&self, maker: &OfferAndAddress) -> bool {
+        !self.addresses.contains(&maker.address.to_string())
+    }
+}
+
+/// Makers this taker caught cheating, see maker_banlist.rs
+pub struct BannedMakers {
+    pub addresses: HashSet<String>,
+}
+
+impl RouteFilter for BannedMakers {
+    fn allows_maker(&self, maker: &OfferAndAddress) -> bool {
+        !self.addresses.contains(&maker.address.to_string())
+    }
+}
+
+/// Makers used in the last few coinswaps, so consecutive coinswaps go through different makers
+pub struct RecentMakers {
+    pub addresses: HashSet<String>,
+}
+
+impl RouteFilter for RecentMakers {
+    fn allows_maker(&self, maker: &OfferAndAddress) -> bool {
+        !self.addresses.contains(&maker.address.to_string())
+    }
+}
+
+/// Makers whose fidelity bonds were funded from the same transactions are likely the same
+/// entity, and a route through one entity twice gives that entity both ends of the coinswap
+pub struct BondClusters {
+    //txids of each maker's fidelity bond tx and of the txes it spends
+    pub clusters: HashMap<String, HashSet<Txid>>,
+}
+
+impl BondClusters {
+    /// Look up the funding of every maker's fidelity bond, the inputs of a bond tx are only
+    /// found if the node has it e.g. with txindex, otherwise only the bond tx itself is used
+    pub fn new(rpc: &Client, makers: &[&OfferAndAddress]) -> BondClusters {
+        let clusters = makers
+            .iter()
+            .filter_map(|m| {
+                let proof = m.offer.fidelity_bond_proof.as_ref()?;
+                let mut cluster = HashSet::new();
+                cluster.insert(proof.utxo.txid);
+                match rpc.get_raw_transaction(&proof.utxo.txid, None) {
+                    Ok(tx) => cluster.extend(tx.input.iter().map(|i| i.previous_output.txid)),
+                    Err(e) => tracing::debug!(
+                        "unable to get fidelity bond tx of {}, error={:?}",
+                        m.address,
+                        e
+                    ),
+                }
+                Some((m.address.to_string(), cluster))
+            })
+            .collect();
+        BondClusters { clusters }
+    }
+}
+
+impl RouteFilter for BondClusters {
+    fn allows_together(&self, maker: &OfferAndAddress, other: &OfferAndAddress) -> bool {
+        match (
+            self.clusters.get(&maker.address.to_string()),
+            self.clusters.get(&other.address.to_string()),
+        ) {
+            (Some(cluster), Some(other_cluster)) => cluster.is_disjoint(other_cluster),
+            _ => true,
+        }
+    }
+}
+
+/// Makers whose advertised locktime limits dont fit the contract locktimes of the route,
+/// these would only fail later in the middle of the protocol
+pub struct ContractLocktimes {
+    //see LocktimePolicy::hop_locktimes
+    pub hop_locktimes: Vec<u16>,
+    //the coinswap amount, which picks the maker's tier
+    pub amount: u64,
+}
+
+impl RouteFilter for ContractLocktimes {
+    fn allows_maker(&self, maker: &OfferAndAddress) -> bool {
+        let offer = &maker.offer;
+        //the maker's position in the route isnt known yet, so check the shortest incoming
+        // and longest outgoing locktime it could be given
+        let makers = self.hop_locktimes.len().saturating_sub(1);
+        if makers == 0 {
+            return true;
+        }
+        let shortest_incoming_locktime = self.hop_locktimes[makers - 1];
+        let longest_outgoing_locktime = self.hop_locktimes[1];
+        let shortest_react_time = self
+            .hop_locktimes
+            .windows(2)
+            .map(|w| w[0].saturating_sub(w[1]))
+            .min()
+            .unwrap_or(0);
+        offer.minimum_locktime <= shortest_incoming_locktime
+            && calculate_maximum_locktime(
+                offer.maximum_locktime,
+                &offer.required_confirms_tiers,
+                self.amount,
+            )
+            .is_none_or(|l| l >= longest_outgoing_locktime)
+            && offer
+                .minimum_contract_react_time
+                .is_none_or(|t| t <= shortest_react_time)
+    }
+}
+
+/// The filters set in the [taker] section of teleport.conf, and the ones every route needs
+pub fn route_filters(
+    rpc: &Client,
+    makers: &[&OfferAndAddress],
+    settings: &TakerSettings,
+    hop_locktimes: &[u16],
+    amount: u64,
+) -> RouteFilters {
+    let mut filters: RouteFilters = vec![
+        Box::new(BondClusters::new(rpc, makers)),
+        Box::new(ContractLocktimes {
+            hop_locktimes: hop_locktimes.to_vec(),
+            amount,
+        }),
+        Box::new(BannedMakers {
+            addresses: load_maker_banlist().makers.into_keys().collect(),
+        }),
+    ];
+    if !settings.exclude_makers.is_empty() {
+        filters.push(Box::new(ExcludedMakers {
+            addresses: settings.exclude_makers.iter().cloned().collect(),
+        }));
+    }
+    if settings.avoid_recent_makers > 0 {
+        filters.push(Box::new(RecentMakers {
+            addresses: recent_swap_makers(settings.avoid_recent_makers)
+                .into_iter()
+                .collect(),
+        }));
+    }
+    filters
+}
+
+/// The makers a route can still be made of, tried from the back
+pub struct RouteCandidates<'a> {
+    pub makers: Vec<&'a OfferAndAddress>,
+    filters: RouteFilters,
+}
+
+impl<'a> RouteCandidates<'a> {
+    pub fn new(makers: Vec<&'a OfferAndAddress>, filters: RouteFilters) -> RouteCandidates<'a> {
+        let mut candidates = RouteCandidates { makers, filters };
+        let filters = &candidates.filters;
+        candidates.makers.retain(|m| {
+            let allowed = filters.iter().all(|f| f.allows_maker(m));
+            if !allowed {
+                tracing::debug!("maker {} not allowed in the route", m.address);
+            }
+            allowed
+        });
+        candidates
+    }
+
+    pub fn pop(&mut self) -> Option<&'a OfferAndAddress> {
+        self.makers.pop()
+    }
+
+    /// The maker was put in the route, drop the makers which cant be in the same route
+    pub fn choose(&mut self, chosen: &OfferAndAddress) {
+        let filters = &self.filters;
+        self.makers.retain(|m| {
+            let allowed = filters.iter().all(|f| f.allows_together(m, chosen));
+            if !allowed {
+                tracing::debug!(
+                    "maker {} not allowed in a route with {}",
+                    m.address,
+                    chosen.address
+                );
+            }
+            allowed
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+
+    use crate::messages::ConfirmsTier;
+    use crate::offerbook_sync::test_offer_and_address;
+
+    #[test]
+    fn test_route_candidates() {
+        let offers = [
+            test_offer_and_address("maker-a", 10_000, 1_000_000),
+            test_offer_and_address("maker-b", 10_000, 1_000_000),
+            test_offer_and_address("maker-c", 10_000, 1_000_000),
+            test_offer_and_address("maker-d", 10_000, 1_000_000),
+        ];
+        let txid = |byte| Txid::from_slice(&[byte; 32]).unwrap();
+        //maker-b and maker-d have bonds funded by the same tx
+        let clusters = vec![
+            ("maker-a", vec![txid(1), txid(2)]),
+            ("maker-b", vec![txid(3), txid(4)]),
+            ("maker-d", vec![txid(5), txid(4)]),
+        ]
+        .into_iter()
+        .map(|(address, txids)| (address.to_string(), txids.into_iter().collect()))
+        .collect();
+        let filters: RouteFilters = vec![
+            Box::new(BondClusters { clusters }),
+            Box::new(ExcludedMakers {
+                addresses: vec!["maker-c".to_string()].into_iter().collect(),
+            }),
+        ];
+        let mut candidates = RouteCandidates::new(offers.iter().collect(), filters);
+        let addresses = |candidates: &RouteCandidates| {
+            candidates
+                .makers
+                .iter()
+                .map(|m| m.address.to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            addresses(&candidates),
+            vec!["maker-a", "maker-b", "maker-d"]
+        );
+
+        let chosen = candidates.pop().unwrap();
+        assert_eq!(chosen.address.to_string(), "maker-d");
+        candidates.choose(chosen);
+        assert_eq!(addresses(&candidates), vec!["maker-a"]);
+    }
+
+    #[test]
+    fn test_contract_locktimes() {
+        let mut offers = [
+            test_offer_and_address("maker-a", 10_000, 1_000_000),
+            test_offer_and_address("maker-b", 10_000, 1_000_000),
+            test_offer_and_address("maker-c", 10_000, 1_000_000),
+            test_offer_and_address("maker-d", 10_000, 1_000_000),
+        ];
+        offers[0].offer.maximum_locktime = Some(144);
+        offers[1].offer.maximum_locktime = Some(48);
+        offers[2].offer.minimum_contract_react_time = Some(72);
+        offers[3].offer.minimum_locktime = 100;
+        let filter = ContractLocktimes {
+            hop_locktimes: vec![192, 144, 96, 48],
+            amount: 1_000_000,
+        };
+        let allowed = offers
+            .iter()
+            .filter(|m| filter.allows_maker(m))
+            .map(|m| m.address.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(allowed, vec!["maker-a"]);
+
+        let filter = ContractLocktimes {
+            hop_locktimes: vec![96, 48],
+            amount: 1_000_000,
+        };
+        assert!(filter.allows_maker(&offers[1]));
+        assert!(!filter.allows_maker(&offers[2]));
+        assert!(filter.allows_maker(&test_offer_and_address("maker-e", 10_000, 1_000_000)));
+
+        //a tier of the maker lowers its maximum locktime for big enough coinswaps
+        offers[0].offer.required_confirms_tiers = vec![ConfirmsTier {
+            amount: 10_000_000,
+            required_confirms: 2,
+            maximum_locktime: Some(96),
+            fee_multiplier_percent: None,
+        }];
+        let filter = ContractLocktimes {
+            hop_locktimes: vec![192, 144, 96, 48],
+            amount: 1_000_000,
+        };
+        assert!(filter.allows_maker(&offers[0]));
+        let filter = ContractLocktimes {
+            hop_locktimes: vec![192, 144, 96, 48],
+            amount: 10_000_000,
+        };
+        assert!(!filter.allows_maker(&offers[0]));
+    }
+}
@@ -0,0 +1,692 @@
+//the bitcoind RPC client used everywhere in teleport, it retries calls which failed because of
+// a transient error of bitcoind, so a long coinswap doesnt abort because the node hiccupped once
+//retries wait with exponential backoff and jitter, configured in the [blockchain] section
+//transient errors are bitcoind still loading the block index or a wallet, its work queue being
+// full and the connection failing or timing out
+//only calls which read state are retried after any transient error, a call such as
+// sendrawtransaction or getnewaddress which timed out may have been executed already, so it is
+// only retried when bitcoind certainly didnt execute it
+//requests are made over connections teleport makes itself so that the http status of a failed
+// call is known, e.g. a wrong rpc password is reported at once instead of being retried
+//bitcoind only serves plain http, a node on another machine can be reached over https through a
+// tls terminating proxy such as stunnel or nginx in front of it, see rpc_tls in [blockchain]
+//a node can also be reached through a socks5 proxy, e.g. tor for a node behind an onion service
+// or an ssh tunnel made with `ssh -D`, see rpc_socks_proxy
+//connecting, sending and each read of the response time out after rpc_timeout_secs so a stalled
+// node or proxy fails the call instead of hanging it, except for calls which rescan the chain
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use bitcoincore_rpc::jsonrpc;
+use bitcoincore_rpc::{Auth, RpcApi};
+use openssl::error::ErrorStack;
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod};
+use rand::Rng;
+use url::Url;
+
+use crate::settings::{BlockchainSettings, Settings};
+
+//RPC_IN_WARMUP, returned while bitcoind loads the block index or a wallet
+const RPC_IN_WARMUP: i32 = -28;
+
+//the status bitcoind answers with when its work queue is full
+const HTTP_SERVICE_UNAVAILABLE: u16 = 503;
+
+//a bigger response than this is taken to be bogus rather than allocated for, the biggest
+// responses are verbose blocks of a few tens of megabytes
+const MAX_RESPONSE_BYTES: usize = 256 * 1024 * 1024;
+//bitcoind's headers are a few tens of bytes long
+const MAX_HEADER_LINE_BYTES: usize = 8 * 1024;
+
+//calls which can rescan the block chain and take hours, their response is waited for forever
+const RESCANNING_METHODS: &[&str] = &[
+    "importaddress",
+    "importdescriptors",
+    "importmulti",
+    "importprivkey",
+    "importpubkey",
+    "loadwallet",
+    "rescanblockchain",
+];
+
+//calls which only read state and can be repeated when it is unknown if bitcoind got them
+const IDEMPOTENT_METHODS: &[&str] = &[
+    "decodepsbt",
+    "decoderawtransaction",
+    "deriveaddresses",
+    "estimatesmartfee",
+    "getaddressinfo",
+    "getbestblockhash",
+    "getblock",
+    "getblockchaininfo",
+    "getblockcount",
+    "getblockhash",
+    "getblockheader",
+    "getdescriptorinfo",
+    "getmempoolentry",
+    "getmempoolinfo",
+    "getnetworkinfo",
+    "getrawmempool",
+    "getrawtransaction",
+    "gettransaction",
+    "gettxout",
+    "gettxoutproof",
+    "getwalletinfo",
+    "listreceivedbyaddress",
+    "listtransactions",
+    "listunspent",
+    "listwallets",
+    "testmempoolaccept",
+];
+
+/// A http response without a json-rpc body, e.g. 401 for a wrong rpc password or 503 when the
+/// work queue of bitcoind is full
+#[derive(Debug)]
+pub struct HttpStatusError {
+    pub status: u16,
+    pub body: String,
+}
+
+impl std::fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "http status {}: {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+fn http_status(error: &bitcoincore_rpc::Error) -> Option<u16> {
+    match error {
+        bitcoincore_rpc::Error::Io(e) => e
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<HttpStatusError>())
+            .map(|e| e.status),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    //how many times a call is retried after the first attempt, 0 to never retry
+    pub attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_settings(settings: &BlockchainSettings) -> RetryPolicy {
+        RetryPolicy {
+            attempts: settings.rpc_retry_attempts,
+            initial_delay: Duration::from_millis(settings.rpc_retry_initial_delay_ms),
+            max_delay: Duration::from_millis(settings.rpc_retry_max_delay_ms),
+        }
+    }
+
+    /// Delay before the retry'th retry, counting from 0, jitter is between 0 and 1 and
+    /// shortens the delay by up to half so that clients dont all retry at the same time
+    pub fn delay(&self, retry: u32, jitter: f64) -> Duration {
+        let backoff = self
+            .initial_delay
+            .checked_mul(2u32.saturating_pow(retry))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        backoff.mul_f64(1.0 - jitter / 2.0)
+    }
+}
+
+/// Return true if the call may succeed when retried
+pub fn is_transient_error(error: &bitcoincore_rpc::Error) -> bool {
+    match error {
+        bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(e)) => e.code == RPC_IN_WARMUP,
+        bitcoincore_rpc::Error::Io(e) => match http_status(error) {
+            Some(status) => status == HTTP_SERVICE_UNAVAILABLE,
+            //connection refused, reset or timed out, except a failed tls handshake, e.g. an
+            // untrusted certificate
+            None => e.kind() != io::ErrorKind::InvalidData,
+        },
+        _ => false,
+    }
+}
+
+/// Return true if the failed call can be made again without doing twice what it does, either
+/// because it only reads state or because bitcoind didnt execute it
+pub fn is_retry_safe(cmd: &str, error: &bitcoincore_rpc::Error) -> bool {
+    if IDEMPOTENT_METHODS.contains(&cmd) {
+        return true;
+    }
+    match error {
+        bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(e)) => e.code == RPC_IN_WARMUP,
+        bitcoincore_rpc::Error::Io(e) => {
+            http_status(error) == Some(HTTP_SERVICE_UNAVAILABLE)
+                || e.kind() == io::ErrorKind::ConnectionRefused
+        }
+        _ => false,
+    }
+}
+
+//a read or write timing out is reported as WouldBlock on unix
+fn timed_out_as_such(error: io::Error) -> io::Error {
+    match error.kind() {
+        io::ErrorKind::WouldBlock => io::ErrorKind::TimedOut.into(),
+        _ => error,
+    }
+}
+
+fn invalid_data<E: ToString>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+//user and password of the http basic authorization
+fn auth_user_pass(auth: Auth) -> bitcoincore_rpc::Result<Option<(String, String)>> {
+    match auth {
+        Auth::None => Ok(None),
+        Auth::UserPass(user, pass) => Ok(Some((user, pass))),
+        Auth::CookieFile(path) => {
+            let cookie = fs::read_to_string(path)?;
+            let (user, pass) = cookie
+                .trim_end()
+                .split_once(':')
+                .ok_or(bitcoincore_rpc::Error::InvalidCookieFile)?;
+            Ok(Some((user.to_string(), pass.to_string())))
+        }
+    }
+}
+
+//reads a line of the http response, which is refused if longer than MAX_HEADER_LINE_BYTES
+fn read_header_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<usize> {
+    let read = reader
+        .by_ref()
+        .take(MAX_HEADER_LINE_BYTES as u64 + 1)
+        .read_line(line)?;
+    if read > MAX_HEADER_LINE_BYTES {
+        return Err(invalid_data("http header line too long"));
+    }
+    Ok(read)
+}
+
+//reads the status code and body of a http response, the body is read until the connection
+// is closed if there is no content-length header
+//a body bigger than max_bytes is refused
+fn read_http_response<R: BufRead>(reader: &mut R, max_bytes: usize) -> io::Result<(u16, Vec<u8>)> {
+    let mut status_line = String::new();
+    read_header_line(reader, &mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| invalid_data(format!("invalid http status line: {}", status_line)))?;
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if read_header_line(reader, &mut header)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = Some(value.trim().parse::<usize>().map_err(invalid_data)?);
+            }
+        }
+    }
+    let mut body = Vec::new();
+    match content_length {
+        Some(length) if length > max_bytes => {
+            return Err(invalid_data(format!(
+                "http content-length too big: {}",
+                length
+            )));
+        }
+        Some(length) => {
+            body.resize(length, 0);
+            reader.read_exact(&mut body)?;
+        }
+        None => {
+            reader
+                .by_ref()
+                .take(max_bytes as u64 + 1)
+                .read_to_end(&mut body)?;
+            if body.len() > max_bytes {
+                return Err(invalid_data("http response too big"));
+            }
+        }
+    }
+    Ok((status, body))
+}
+
+//tries each address the host name resolves to, like TcpStream::connect
+fn connect_timeout<A: ToSocketAddrs>(address: A, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = None;
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| invalid_data("address resolved to nothing")))
+}
+
+/// Connect to host:port through a socks5 proxy, the proxy resolves the host name so it can be
+/// an onion address. Connecting to the proxy times out after timeout, the handshake with it
+/// after the read and write timeouts the stream is given
+pub fn socks5_connect(
+    proxy: &str,
+    host: &str,
+    port: u16,
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    let mut stream = connect_timeout(proxy, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    //version 5, one authentication method, no authentication
+    stream.write_all(&[5, 1, 0])?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply)?;
+    if reply != [5, 0] {
+        return Err(invalid_data("socks proxy requires authentication"));
+    }
+    if host.len() > 255 {
+        return Err(invalid_data("host name too long for socks"));
+    }
+    //version 5, connect, reserved, domain name
+    let mut request = vec![5, 1, 0, 3, host.len() as u8];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request)?;
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[1] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!(
+                "socks proxy couldnt connect to {}, reply={}",
+                host, reply[1]
+            ),
+        ));
+    }
+    //skip the address and port the proxy bound to
+    let address_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => return Err(invalid_data("invalid socks reply")),
+    };
+    stream.read_exact(&mut vec![0u8; address_len + 2])?;
+    Ok(stream)
+}
+
+//json-rpc over connections made by teleport itself, for https, connections through a socks
+// proxy and the http status of failed calls which the http client of bitcoincore_rpc doesnt do,
+// a new connection is made each call
+struct DirectTransport {
+    url: Url,
+    //base64 of user:password
+    authorization: Option<String>,
+    //None for plain http
+    tls_connector: Option<SslConnector>,
+    socks_proxy: Option<String>,
+    timeout: Duration,
+    next_id: AtomicU64,
+}
+
+impl DirectTransport {
+    fn new(
+        url: &str,
+        auth: Auth,
+        settings: &BlockchainSettings,
+    ) -> bitcoincore_rpc::Result<DirectTransport> {
+        let url = Url::parse(url).map_err(invalid_data)?;
+        let tls_connector = if url.scheme() == "https" {
+            Some(tls_connector(settings).map_err(invalid_data)?)
+        } else {
+            None
+        };
+        Ok(DirectTransport {
+            url,
+            authorization: auth_user_pass(auth)?.map(|(user, pass)| {
+                bitcoin::base64::encode(format!("{}:{}", user, pass).as_bytes())
+            }),
+            tls_connector,
+            socks_proxy: settings.rpc_socks_proxy.clone(),
+            timeout: Duration::from_secs(settings.rpc_timeout_secs),
+            next_id: AtomicU64::new(0),
+        })
+    }
+
+    fn post(&self, cmd: &str, body: &[u8]) -> io::Result<(u16, Vec<u8>)> {
+        let host = self
+            .url
+            .host_str()
+            .ok_or_else(|| invalid_data("rpc url without host"))?;
+        let port = self
+            .url
+            .port_or_known_default()
+            .ok_or_else(|| invalid_data("rpc url without port"))?;
+        let stream = match &self.socks_proxy {
+            Some(proxy) => socks5_connect(proxy, host, port, self.timeout)?,
+            None => connect_timeout((host, port), self.timeout)?,
+        };
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.set_read_timeout(if RESCANNING_METHODS.contains(&cmd) {
+            None
+        } else {
+            Some(self.timeout)
+        })?;
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\n\
+            Content-Length: {}\r\nConnection: close\r\n",
+            self.url.path(),
+            host,
+            port,
+            body.len()
+        );
+        if let Some(authorization) = &self.authorization {
+            request.push_str(&format!("Authorization: Basic {}\r\n", authorization));
+        }
+        request.push_str("\r\n");
+        match &self.tls_connector {
+            //the certificate is checked against the host name here
+            Some(connector) => {
+                let stream = connector.connect(host, stream).map_err(invalid_data)?;
+                exchange_http(stream, request.as_bytes(), body)
+            }
+            None => exchange_http(stream, request.as_bytes(), body),
+        }
+    }
+
+    fn call<T: for<'a> serde::de::Deserialize<'a>>(
+        &self,
+        cmd: &str,
+        args: &[serde_json::Value],
+    ) -> bitcoincore_rpc::Result<T> {
+        let id = serde_json::Value::from(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let request = jsonrpc::Request {
+            method: cmd,
+            params: args,
+            id: id.clone(),
+            jsonrpc: Some("2.0"),
+        };
+        let (status, body) = self
+            .post(cmd, &serde_json::to_vec(&request)?)
+            .map_err(timed_out_as_such)?;
+        //bitcoind answers rpc errors with a json body and a http error status, other http
+        // errors have no json body
+        let response = match serde_json::from_slice::<jsonrpc::Response>(&body) {
+            Ok(response) => response,
+            Err(e) if status == 200 => return Err(jsonrpc::Error::Json(e).into()),
+            Err(_) => {
+                return Err(io::Error::other(HttpStatusError {
+                    status,
+                    body: String::from_utf8_lossy(&body).trim().to_string(),
+                })
+                .into())
+            }
+        };
+        if response.id != id {
+            return Err(jsonrpc::Error::NonceMismatch.into());
+        }
+        Ok(response.into_result()?)
+    }
+}
+
+fn tls_connector(settings: &BlockchainSettings) -> Result<SslConnector, ErrorStack> {
+    let mut builder = SslConnector::builder(SslMethod::tls())?;
+    //trusted in addition to the system's certificate authorities, for a self-signed proxy
+    if let Some(ca_file) = &settings.rpc_tls_ca_file {
+        builder.set_ca_file(ca_file)?;
+    }
+    if let (Some(cert_file), Some(key_file)) = (
+        &settings.rpc_tls_client_cert_file,
+        &settings.rpc_tls_client_key_file,
+    ) {
+        builder.set_certificate_chain_file(cert_file)?;
+        builder.set_private_key_file(key_file, SslFiletype::PEM)?;
+        builder.check_private_key()?;
+    }
+    Ok(builder.build())
+}
+
+fn exchange_http<S: Read + Write>(
+    mut stream: S,
+    request: &[u8],
+    body: &[u8],
+) -> io::Result<(u16, Vec<u8>)> {
+    stream.write_all(request)?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    read_http_response(&mut BufReader::new(stream), MAX_RESPONSE_BYTES)
+}
+
+pub struct Client {
+    transport: DirectTransport,
+    retry_policy: RetryPolicy,
+}
+
+impl Client {
+    /// Create a client with the tls and retry settings of the global settings, or the default
+    /// ones if they arent initialized
+    pub fn new(url: String, auth: Auth) -> bitcoincore_rpc::Result<Client> {
+        if Settings::is_initialized() {
+            Client::with_settings(url, auth, &Settings::global().blockchain)
+        } else {
+            Client::with_settings(url, auth, &Settings::default().blockchain)
+        }
+    }
+
+    /// Create a client, connecting over https if the url starts with https:// and through
+    /// rpc_socks_proxy if it is set
+    pub fn with_settings(
+        url: String,
+        auth: Auth,
+        settings: &BlockchainSettings,
+    ) -> bitcoincore_rpc::Result<Client> {
+        Ok(Client {
+            transport: DirectTransport::new(&url, auth, settings)?,
+            retry_policy: RetryPolicy::from_settings(settings),
+        })
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Client {
+        self.retry_policy = retry_policy;
+        self
+    }
+}
+
+impl RpcApi for Client {
+    fn call<T: for<'a> serde::de::Deserialize<'a>>(
+        &self,
+        cmd: &str,
+        args: &[serde_json::Value],
+    ) -> bitcoincore_rpc::Result<T> {
+        let mut retry = 0;
+        loop {
+            match self.transport.call(cmd, args) {
+                Err(e)
+                    if retry < self.retry_policy.attempts
+                        && is_transient_error(&e)
+                        && is_retry_safe(cmd, &e) =>
+                {
+                    let delay = self
+                        .retry_policy
+                        .delay(retry, rand::thread_rng().gen_range(0.0, 1.0));
+                    tracing::warn!(
+                        "rpc call {} failed, retrying in {}ms: {:?}",
+                        cmd,
+                        delay.as_millis(),
+                        e
+                    );
+                    thread::sleep(delay);
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            attempts: 5,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_millis(3000),
+        };
+        assert_eq!(policy.delay(0, 0.0), Duration::from_millis(500));
+        assert_eq!(policy.delay(2, 0.0), Duration::from_millis(2000));
+        assert_eq!(policy.delay(3, 0.0), Duration::from_millis(3000));
+        assert_eq!(policy.delay(100, 0.0), Duration::from_millis(3000));
+        assert_eq!(policy.delay(1, 1.0), Duration::from_millis(500));
+
+        let warmup =
+            bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(jsonrpc::error::RpcError {
+                code: RPC_IN_WARMUP,
+                message: "Loading wallet...".to_string(),
+                data: None,
+            }));
+        assert!(is_transient_error(&warmup));
+        let not_found =
+            bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(jsonrpc::error::RpcError {
+                code: -5,
+                message: "No such mempool or blockchain transaction".to_string(),
+                data: None,
+            }));
+        assert!(!is_transient_error(&not_found));
+        assert!(!is_transient_error(
+            &bitcoincore_rpc::Error::InvalidCookieFile
+        ));
+        assert!(!is_transient_error(&bitcoincore_rpc::Error::Io(
+            invalid_data("certificate verify failed")
+        )));
+
+        let http_error = |status| {
+            bitcoincore_rpc::Error::Io(io::Error::other(HttpStatusError {
+                status,
+                body: String::new(),
+            }))
+        };
+        assert!(!is_transient_error(&http_error(401)));
+        assert!(is_transient_error(&http_error(503)));
+        assert!(is_retry_safe("sendrawtransaction", &http_error(503)));
+        assert!(is_retry_safe("sendrawtransaction", &warmup));
+
+        let timed_out = bitcoincore_rpc::Error::Io(io::ErrorKind::TimedOut.into());
+        assert!(is_transient_error(&timed_out));
+        assert!(is_retry_safe("getblockcount", &timed_out));
+        //may have been broadcast or handed out already
+        assert!(!is_retry_safe("sendrawtransaction", &timed_out));
+        assert!(!is_retry_safe("getnewaddress", &timed_out));
+        assert!(!is_retry_safe("importdescriptors", &timed_out));
+        let refused = bitcoincore_rpc::Error::Io(io::ErrorKind::ConnectionRefused.into());
+        assert!(is_retry_safe("sendrawtransaction", &refused));
+    }
+
+    #[test]
+    fn test_read_http_response() {
+        let response = b"HTTP/1.1 500 Internal Server Error\r\nContent-Type: application/json\r\n\
+            Content-Length: 13\r\n\r\n{\"error\":123}trailing";
+        let (status, body) = read_http_response(&mut &response[..], MAX_RESPONSE_BYTES).unwrap();
+        assert_eq!(status, 500);
+        assert_eq!(body, b"{\"error\":123}");
+
+        let response = b"HTTP/1.1 503 Service Unavailable\r\n\r\nWork queue depth exceeded";
+        let (status, body) = read_http_response(&mut &response[..], MAX_RESPONSE_BYTES).unwrap();
+        assert_eq!(status, 503);
+        assert_eq!(body, b"Work queue depth exceeded");
+
+        assert!(read_http_response(&mut &b"garbage\r\n\r\n"[..], MAX_RESPONSE_BYTES).is_err());
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n";
+        assert!(read_http_response(&mut &response[..], MAX_RESPONSE_BYTES).is_err());
+        //a bogus content-length isnt allocated for
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{{}}",
+            usize::MAX
+        );
+        assert_eq!(
+            read_http_response(&mut response.as_bytes(), MAX_RESPONSE_BYTES)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        //nor is a body without a content-length read on forever
+        let response = b"HTTP/1.1 200 OK\r\n\r\n0123456789";
+        let (_, body) = read_http_response(&mut &response[..], 10).unwrap();
+        assert_eq!(body, b"0123456789");
+        assert_eq!(
+            read_http_response(&mut &response[..], 9)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+        //or a header without an end
+        let response = format!("HTTP/1.1 200 OK\r\nServer: {}", "a".repeat(100_000));
+        assert_eq!(
+            read_http_response(&mut response.as_bytes(), MAX_RESPONSE_BYTES)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn test_call_timeout() {
+        //accepts the connection but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let mut settings = Settings::default().blockchain;
+        settings.rpc_timeout_secs = 1;
+        let client = Client::with_settings(url, Auth::None, &settings)
+            .unwrap()
+            .with_retry_policy(RetryPolicy {
+                attempts: 0,
+                initial_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(1),
+            });
+        match client.get_block_count() {
+            Err(bitcoincore_rpc::Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::TimedOut),
+            result => panic!("expected a timeout, got {:?}", result),
+        }
+        drop(listener);
+    }
+
+    #[test]
+    fn test_socks5_connect() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = listener.local_addr().unwrap().to_string();
+        let proxy_thread = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [5, 1, 0]);
+            stream.write_all(&[5, 0]).unwrap();
+            let mut request = vec![0u8; 5 + "node.onion".len() + 2];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request[..5], &[5, 1, 0, 3, 10]);
+            assert_eq!(&request[5..15], b"node.onion");
+            assert_eq!(&request[15..], &8332u16.to_be_bytes());
+            stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).unwrap();
+            stream.write_all(b"hello").unwrap();
+        });
+        let mut stream =
+            socks5_connect(&proxy, "node.onion", 8332, Duration::from_secs(10)).unwrap();
+        let mut hello = String::new();
+        stream.read_to_string(&mut hello).unwrap();
+        assert_eq!(hello, "hello");
+        proxy_thread.join().unwrap();
+    }
+}
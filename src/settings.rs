@@ -1,17 +1,530 @@
-use config::{Config, File, FileFormat};
+use bitcoin::util::bip32::ExtendedPubKey;
+use config::builder::DefaultState;
+use config::{Config, ConfigBuilder, File, FileFormat, Map, Value};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::OnceLock;
+use structopt::StructOpt;
 
+use crate::error::Error;
+use crate::maker_protocol::ListenAddress;
+use crate::messages::ConfirmsTier;
+use crate::recovery_destinations::checked_descriptor;
 use crate::utils::bitcoin_data_dir;
 
 static SETTINGS: OnceLock<Settings> = OnceLock::new();
+//kept so that reloading the settings gives the same result as at startup
+static SETTINGS_OVERRIDES: OnceLock<SettingsOverrides> = OnceLock::new();
+
+const BLOCKCHAIN_KEYS: [&str; 17] = [
+    "network",
+    "rpc_host",
+    "rpc_port",
+    "rpc_user",
+    "rpc_password",
+    "rpc_cookie_file",
+    "rpc_cookie_command",
+    "rpc_wallet_file",
+    "rpc_tls",
+    "rpc_tls_ca_file",
+    "rpc_tls_client_cert_file",
+    "rpc_tls_client_key_file",
+    "rpc_socks_proxy",
+    "rpc_retry_attempts",
+    "rpc_retry_initial_delay_ms",
+    "rpc_retry_max_delay_ms",
+    "rpc_timeout_secs",
+];
+const NETWORK_SECTIONS: [&str; 5] = ["mainnet", "testnet", "testnet4", "signet", "regtest"];
+const MAKER_KEYS: [&str; 23] = [
+    "absolute_fee_sat",
+    "dynamic_fee_reference_rate",
+    "amount_relative_fee_ppb",
+    "time_relative_fee_ppb",
+    "min_size",
+    "max_size",
+    "max_concurrent_connections",
+    "max_aborted_connections",
+    "max_protocol_errors",
+    "max_offer_requests",
+    "misbehavior_window_secs",
+    "ban_duration_secs",
+    "consolidation_max_fee_rate",
+    "consolidation_batch_size",
+    "min_funding_tx_fee_rate",
+    "rebalance_interval_hours",
+    "rebalance_max_utxo_count",
+    "rebalance_max_size_skew",
+    "listen_addresses",
+    "public_addresses",
+    "instances",
+    "cold_timelock_xpub",
+    "tiers",
+];
+const TAKER_KEYS: [&str; 16] = [
+    "first_connect_attempts",
+    "first_connect_sleep_delay_sec",
+    "first_connect_attempt_timeout_sec",
+    "senders_contract_sigs_timeout_sec",
+    "contract_sigs_exchange_timeout_sec",
+    "funding_confirmation_timeout_sec",
+    "private_key_handover_timeout_sec",
+    "max_concurrent_offer_requests",
+    "change_avoidance_tolerance_ppb",
+    "amount_randomization_ppb",
+    "exclude_makers",
+    "avoid_recent_makers",
+    "refund_locktime",
+    "refund_locktime_step",
+    "min_contract_react_time",
+    "min_funding_tx_fee_rate",
+];
+const TOR_KEYS: [&str; 4] = [
+    "only",
+    "control_address",
+    "control_password",
+    "onion_rotation_hours",
+];
+const I2P_KEYS: [&str; 1] = ["sam_address"];
+const WATCHTOWER_KEYS: [&str; 2] = ["addresses", "settled_contract_retention_blocks"];
+const ALERTS_KEYS: [&str; 7] = [
+    "webhook_url",
+    "telegram_bot_token",
+    "telegram_chat_id",
+    "email_to",
+    "sendmail_command",
+    "timelock_warning_blocks",
+    "fidelity_bond_reminder_days",
+];
+const HOOKS_KEYS: [&str; 4] = [
+    "on_swap_start",
+    "on_swap_success",
+    "on_swap_failure",
+    "on_contract_broadcast",
+];
+const LOGGING_KEYS: [&str; 9] = [
+    "level",
+    "taker",
+    "maker",
+    "watchtower",
+    "offerbook",
+    "console_format",
+    "file",
+    "file_rotation",
+    "max_log_files",
+];
+const LOG_LEVELS: [&str; 6] = ["off", "error", "warn", "info", "debug", "trace"];
+
+pub const CONFIG_FILE_NAME: &str = "teleport.conf";
+
+/// Contents of the teleport.conf written by `teleport generate-config`, every option
+/// is commented out and shows its default value
+pub const DEFAULT_CONFIG_FILE: &str = r#"# teleport.conf
+#
+# Every option below is commented out and set to its default value, uncomment
+# a line to change it.
+# Options can also be set with environment variables or command line flags,
+# which take precedence over this file, e.g. TELEPORT_RPC_HOST or --rpc-host.
+# Run `teleport config show` to print the configuration actually in use.
+
+# Wallet in the wallets directory of the datadir used by commands not given --wallet,
+# set with `teleport wallet default <name>`
+#default_wallet = "wallet"
+
+# Ranged descriptor, e.g. of a cold wallet, whose addresses receive the coins of contracts
+# got back by their hashlock or timelock and of `spend-contract`, instead of fresh addresses of
+# the wallet. Each address is used once, its checksum can be left out
+#recovery_descriptor = "wpkh(xpub.../0/*)"
+
+[blockchain]
+# Bitcoin network, one of "main", "testnet", "testnet4", "signet" or "regtest"
+# Also selects which [blockchain.<network>] section below is used
+#network = "regtest"
+
+# Host of the bitcoin node's RPC server
+#rpc_host = "localhost"
+
+# RPC port of the bitcoin node, the default depends on the network:
+# main=8332, testnet=18332, testnet4=48332, signet=38332, regtest=18443
+#rpc_port = 18443
+
+# RPC username and password, if both are unset the cookie file is used instead
+#rpc_user = ""
+#rpc_password = ""
+
+# Cookie file, either a name in the bitcoin data directory of the network or a path,
+# e.g. "/var/lib/bitcoind/regtest/.cookie" when bitcoind runs with a custom -datadir
+#rpc_cookie_file = ".cookie"
+
+# Command printing the contents of the cookie file, used instead of rpc_cookie_file when set,
+# for a node on another machine whose cookie file isnt here. Only read on startup, the cookie
+# changes every time bitcoind restarts
+#rpc_cookie_command = "ssh node cat .bitcoin/.cookie"
+
+# Name of the wallet in the bitcoin node which teleport uses to watch its addresses
+#rpc_wallet_file = "teleport"
+
+# Connect to the RPC server over https, bitcoind itself only serves http so this is for a node
+# on another machine behind a TLS proxy such as stunnel or nginx, which keeps the RPC
+# credentials from being sent in cleartext. The proxy's certificate must be signed by a
+# certificate authority trusted by the system or by the one in rpc_tls_ca_file
+#rpc_tls = false
+#rpc_tls_ca_file = "/etc/teleport/rpc-ca.pem"
+
+# Client certificate and key in PEM format, for a proxy which requires them
+#rpc_tls_client_cert_file = "/etc/teleport/rpc-client.pem"
+#rpc_tls_client_key_file = "/etc/teleport/rpc-client.key"
+
+# Connect to the RPC server through this socks5 proxy, e.g. "127.0.0.1:9050" for tor when
+# rpc_host is the onion address of the node, or the port of an `ssh -D` tunnel to it
+#rpc_socks_proxy = "127.0.0.1:9050"
+
+# RPC calls failing because bitcoind is loading, busy or unreachable are retried this many
+# times, waiting twice as long before each retry up to the max delay, 0 to never retry
+#rpc_retry_attempts = 5
+#rpc_retry_initial_delay_ms = 500
+#rpc_retry_max_delay_ms = 30000
+
+# Connecting to the RPC server, sending a call and each wait for its response fail after this
+# many seconds, calls which rescan the block chain such as importmulti wait forever
+#rpc_timeout_secs = 120
+
+# Options in a per-network section override the ones above when that network
+# is selected, all options except network can be used here
+#[blockchain.mainnet]
+#rpc_wallet_file = "teleport-mainnet"
+
+#[blockchain.testnet]
+#rpc_wallet_file = "teleport-testnet"
+
+#[blockchain.testnet4]
+#rpc_wallet_file = "teleport-testnet4"
+
+#[blockchain.signet]
+#rpc_wallet_file = "teleport-signet"
+
+#[blockchain.regtest]
+#rpc_wallet_file = "teleport-regtest"
+
+# Options for running a maker with `teleport run-yield-generator`
+# A running maker reloads this section when sent SIGHUP, e.g. `kill -HUP <pid>`,
+# new connections use the new values and swaps already in progress are unaffected
+[maker]
+# Fee charged for every coinswap, in sats
+#absolute_fee_sat = 1000
+
+# Mempool fee rate in sat/vbyte at which absolute_fee_sat is charged. If set, the
+# absolute fee is raised in proportion when the mempool fee rate is higher, because
+# the maker pays for its funding transactions to confirm. The rate is checked
+# every 10 minutes, offers already given out keep their fee
+#dynamic_fee_reference_rate = 10
+
+# Fee charged relative to the coinswap amount, in parts per billion
+#amount_relative_fee_ppb = 10000000
+
+# Fee charged relative to the coinswap amount and locktime, in parts per billion
+#time_relative_fee_ppb = 100000
+
+# Smallest coinswap amount accepted, in sats
+#min_size = 10000
+
+# Largest coinswap amount accepted, in sats. Coinswaps are also limited by the
+# wallet balance, if unset only the wallet balance is used
+#max_size = 100000000
+
+# Maximum number of takers connected at the same time
+#max_concurrent_connections = 100
+
+# Takers are refused for ban_duration_secs once within misbehavior_window_secs they
+# disconnected without asking for anything more than max_aborted_connections times,
+# sent more than max_protocol_errors invalid messages, or asked for more than
+# max_offer_requests offers without completing a coinswap. Takers connecting through
+# tor cant be told apart so are never refused. 0 for ban_duration_secs disables it
+#max_aborted_connections = 10
+#max_protocol_errors = 3
+#max_offer_requests = 60
+#misbehavior_window_secs = 3600
+#ban_duration_secs = 86400
+
+# Mempool fee rate in sat/vbyte at or below which the maker merges its small coins
+# into one while no taker is connected, so later funding transactions need fewer
+# inputs. At most consolidation_batch_size coins are merged in one transaction.
+# Merging links the coins together, if unset coins are never merged
+#consolidation_max_fee_rate = 2
+#consolidation_batch_size = 20
+
+# Lowest fee rate in sat/vbyte accepted for a taker's funding transactions, a coinswap
+# whose funding pays less is refused as it could take too long to confirm. Also the
+# fee rate of the maker's own coinswaps when rebalancing if the mempool fee rate is
+# unknown
+#min_funding_tx_fee_rate = 1
+
+# Every rebalance_interval_hours the maker checks whether it has more than
+# rebalance_max_utxo_count coins, or a biggest coin more than rebalance_max_size_skew
+# times the size of its median coin. If so it coinswaps what it earned in the last
+# interval with other makers, acting as a taker, and doesnt accept takers until that
+# coinswap is done. 0 never rebalances
+#rebalance_interval_hours = 0
+#rebalance_max_utxo_count = 50
+#rebalance_max_size_skew = 10
+
+# Addresses to accept takers on, either ip:port, unix:<path> for a unix socket
+# behind a reverse proxy or i2p to listen on an i2p destination, whose address is
+# published along with public_addresses. The port or --listen given to
+# run-yield-generator take precedence, changing these needs a restart
+#listen_addresses = ["127.0.0.1:6102"]
+
+# Addresses published to the directory servers for takers to connect to, if
+# empty MAKER_ONION_ADDR in src/maker_protocol.rs is used. Changing these needs
+# a restart
+#public_addresses = []
+
+# How many makers to run from the one wallet, each taking coinswaps from the same
+# coins. Maker n listens on the ports of listen_addresses plus n, so 3 makers on
+# 127.0.0.1:6102 use ports 6102 to 6104. Every maker after the first publishes its own
+# onion service created with tor's control port, and shows the wallet's n-th most
+# valuable fidelity bond, so the wallet needs one bond per maker. Makers after the
+# first cant listen on unix sockets or i2p, and makers dont rebalance when there is
+# more than one. Changing it needs a restart
+#instances = 1
+
+# An xpub of a key kept off the maker's machine. The timelock keys of the maker's
+# contracts are then children of it, so if the maker's machine is compromised the
+# coins of a failed coinswap can still only be got back after the timelock by the
+# holder of the cold key, with `recover sign-cold-timelock-spends` on a contract
+# export. The maker keeps nothing to get them back itself, so keep the export safe
+#cold_timelock_xpub = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8"
+
+# Policies for bigger coinswaps, each applying to coinswaps of at least amount sats.
+# Their funding transactions need required_confirms confirmations, and a tier can
+# also set maximum_locktime, the longest locktime in blocks accepted for the maker's
+# outgoing contract, and fee_multiplier_percent, which scales the coinswap fee with
+# 100 leaving it unchanged. The fee multiplier of the biggest tier reached is used.
+# The tiers are advertised in the offer, so takers know them before a coinswap
+#tiers = [{ amount = 1000000, required_confirms = 2 }, { amount = 100000000, required_confirms = 3 }]
+
+# Options for takers, used by `teleport do-coinswap` and when downloading offers
+[taker]
+# How many times to retry a maker before giving up on it, when first connecting
+# to it to get its offer or to start a coinswap
+#first_connect_attempts = 5
+
+# Seconds to wait after a failed first connection before retrying
+#first_connect_sleep_delay_sec = 1
+
+# Seconds a first connection attempt is given before it times out
+#first_connect_attempt_timeout_sec = 20
+
+# Seconds each step of a coinswap is given before it is attempted again, raise
+# them if makers over tor are slow to reply. Asking a maker to sign the contract
+# transactions sending coins to it
+#senders_contract_sigs_timeout_sec = 20
+# Sending a maker the proof of its funding and exchanging the contract signatures
+# of the next hop
+#contract_sigs_exchange_timeout_sec = 300
+# Sending a maker the hash preimage and swapping private keys with it
+#private_key_handover_timeout_sec = 300
+
+# Seconds to wait for the funding transactions of each hop to confirm before
+# giving up on the coinswap, 0 waits however long it takes. A slow block or a
+# low fee rate can delay them by hours. Coins already in the coinswap are then
+# got back with `recover-from-incomplete-coinswap`
+#funding_confirmation_timeout_sec = 0
+
+# Maximum number of makers asked for their offers at the same time, the rest wait
+# for one of them to finish so tor isnt flooded when there are hundreds of makers
+#max_concurrent_offer_requests = 20
+
+# How much more than the coinswap amount, in parts per billion, the funding
+# transactions may send so they can spend whole coins without a change output.
+# Change links the coinswap back to the wallet, 0 always makes change
+#change_avoidance_tolerance_ppb = 5000000
+
+# How far, in parts per billion, the amount actually sent in a coinswap may be
+# randomly moved away from the requested amount, so a taker's coinswaps dont all
+# send the same round amount. 0 sends exactly the requested amount
+#amount_randomization_ppb = 10000000
+
+# Makers never used in a coinswap route, by their address e.g. "abcd...xyz.onion:6102"
+#exclude_makers = []
+
+# Dont use makers which were in the routes of this many of the last completed
+# coinswaps. A route never has two makers whose fidelity bonds were funded from
+# the same transactions either
+#avoid_recent_makers = 0
+
+# Locktimes of the coinswap contracts, in blocks. The contract paying back to the
+# taker has refund_locktime and each hop before it refund_locktime_step more, so
+# the taker's own coins are locked longest. Every maker needs min_contract_react_time
+# blocks between its incoming and outgoing contracts, so the step cant be less.
+# Longer locktimes are safer but lock the coins up for longer if a coinswap fails
+#refund_locktime = 48
+#refund_locktime_step = 48
+#min_contract_react_time = 48
+
+# Lowest fee rate in sat/vbyte accepted for the makers' funding transactions, a
+# coinswap is abandoned if a maker's funding pays less
+#min_funding_tx_fee_rate = 1
+
+[tor]
+# Refuse every clearnet connection: makers without an onion address are skipped,
+# payjoin endpoints are reached through tor, a maker must have an onion address and
+# watchtowers must be onion addresses or on this machine
+# Can also be set with --tor-only or the TELEPORT_TOR_ONLY environment variable
+#only = false
+
+# Tor control port, used by makers to create onion services
+#control_address = "127.0.0.1:9051"
+
+# Password of the control port, if unset the cookie file or no authentication is used
+#control_password = ""
+
+# Makers move to a fresh onion service this often, in hours, so that they cant be
+# tracked by their address for long. Their fidelity bond signs each new address so
+# takers can still tell it is the same maker. The previous address keeps working for
+# one more period for coinswaps in progress. 0 keeps the address from maker.public_addresses
+#onion_rotation_hours = 0
+
+# Makers listen on i2p when "i2p" is one of maker.listen_addresses, and takers reach
+# makers with .b32.i2p addresses through i2p
+[i2p]
+# SAM bridge of the i2p router, version 3.1 or newer must be enabled
+#sam_address = "127.0.0.1:7656"
+
+[watchtower]
+# Watchtowers which makers register their contracts with, host:port
+# Contracts sent to a watchtower which isnt on this machine are encrypted so it can
+# only read them once a contract transaction appears on the network. Onion
+# addresses are reached through tor
+# A coinswap goes ahead if at least one watchtower acknowledges its contracts, the
+# others are sent them again once they answer a ping
+#addresses = ["localhost:6103"]
+
+# A watchtower forgets the contracts of a coinswap which settled without them being
+# broadcast once the transactions spending its funding outputs are this many blocks deep
+#settled_contract_retention_blocks = 144
+
+# Alerts sent by makers and watchtowers when a contract transaction is broadcast, a
+# contract timelock is about to expire, a watchtower broadcasts a spend of a contract or a
+# maker's fidelity bond is about to unlock
+[alerts]
+# URL which is POSTed every alert as a JSON object
+#webhook_url = ""
+
+# Telegram bot which sends every alert to a chat
+#telegram_bot_token = ""
+#telegram_chat_id = ""
+
+# Email address to send alerts to, using the sendmail command of this machine
+#email_to = ""
+#sendmail_command = "sendmail"
+
+# Alert this many blocks before a watched contract's timelock expires
+#timelock_warning_blocks = 12
+
+# Alert this many days before a maker's fidelity bond registered with the watchtower unlocks,
+# there is another alert when it unlocks
+#fidelity_bond_reminder_days = 30
+
+# Shell commands run on coinswap events, each is given a JSON object describing the event
+# on stdin and the event name in the TELEPORT_EVENT environment variable
+[hooks]
+# Run when a coinswap starts, by the taker or by a maker once it has been funded
+#on_swap_start = ""
+#on_swap_success = ""
+#on_swap_failure = ""
+# Run by takers, makers and watchtowers when a contract transaction is broadcast
+#on_contract_broadcast = ""
+
+# The RUST_LOG environment variable, if set, replaces all the log levels below
+[logging]
+# Log level used unless a more specific one is set below,
+# one of "off", "error", "warn", "info", "debug" or "trace"
+#level = "info"
+
+# Log levels of the taker, maker, watchtower and offerbook code
+#taker = "info"
+#maker = "info"
+#watchtower = "info"
+#offerbook = "info"
+
+# Format of the logs printed to the console, "text" or "json"
+# Can also be set with the TELEPORT_LOG_FORMAT environment variable
+#console_format = "text"
+
+# Also write logs to this file, relative paths are inside the data directory
+#file = "teleport.log"
+
+# How often to start a new log file, "never", "hourly" or "daily"
+# The date and time is appended to the file name unless this is "never"
+#file_rotation = "daily"
+
+# Number of rotated log files to keep, if unset all are kept
+#max_log_files = 7
+"#;
+
+/// Environment variables which override settings in teleport.conf, and the setting they override
+pub const ENV_OVERRIDES: [(&str, &str); 9] = [
+    ("TELEPORT_NETWORK", "blockchain.network"),
+    ("TELEPORT_RPC_HOST", "blockchain.rpc_host"),
+    ("TELEPORT_RPC_PORT", "blockchain.rpc_port"),
+    ("TELEPORT_RPC_USER", "blockchain.rpc_user"),
+    ("TELEPORT_RPC_PASSWORD", "blockchain.rpc_password"),
+    ("TELEPORT_RPC_COOKIE_FILE", "blockchain.rpc_cookie_file"),
+    ("TELEPORT_RPC_WALLET_FILE", "blockchain.rpc_wallet_file"),
+    ("TELEPORT_LOG_FORMAT", "logging.console_format"),
+    ("TELEPORT_TOR_ONLY", "tor.only"),
+];
+
+/// Settings given as command line flags, these take precedence over both
+/// environment variables and teleport.conf
+#[derive(Clone, Debug, Default, StructOpt)]
+pub struct SettingsOverrides {
+    /// Bitcoin network of the node, overrides blockchain.network and selects which
+    /// [blockchain.<network>] section of teleport.conf is used
+    #[structopt(long)]
+    pub network: Option<String>,
+    /// Bitcoin node RPC host, overrides blockchain.rpc_host
+    #[structopt(long)]
+    pub rpc_host: Option<String>,
+    /// Bitcoin node RPC port, overrides blockchain.rpc_port
+    #[structopt(long)]
+    pub rpc_port: Option<u16>,
+    /// Bitcoin node RPC username, overrides blockchain.rpc_user
+    #[structopt(long)]
+    pub rpc_user: Option<String>,
+    /// Bitcoin node RPC password, overrides blockchain.rpc_password
+    #[structopt(long)]
+    pub rpc_password: Option<String>,
+    /// Bitcoin node RPC cookie file name or path, overrides blockchain.rpc_cookie_file
+    #[structopt(long)]
+    pub rpc_cookie_file: Option<String>,
+    /// Bitcoin node wallet name, overrides blockchain.rpc_wallet_file
+    #[structopt(long)]
+    pub rpc_wallet_file: Option<String>,
+    /// Refuse every clearnet connection, overrides tor.only
+    #[structopt(long)]
+    pub tor_only: bool,
+}
 
 /// Global settings
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Settings {
     pub blockchain: BlockchainSettings,
+    pub maker: MakerSettings,
+    pub taker: TakerSettings,
+    pub tor: TorSettings,
+    pub i2p: I2pSettings,
+    pub watchtower: WatchtowerSettings,
+    pub alerts: AlertSettings,
+    pub hooks: HookSettings,
+    pub logging: LoggingSettings,
     pub datadir: Option<PathBuf>,
+    /// See wallet_files.rs
+    pub default_wallet: Option<String>,
+    /// See recovery_destinations.rs
+    pub recovery_descriptor: Option<String>,
 }
 
 /// Settings relating to the bitcoin node
@@ -19,12 +532,323 @@ pub struct Settings {
 pub struct BlockchainSettings {
     pub network: String,
     pub rpc_host: String,
-    /// default ports: mainnet=8332, testnet=18332, regtest=18443, signet=38332
+    /// default ports: mainnet=8332, testnet=18332, testnet4=48332, regtest=18443, signet=38332
     pub rpc_port: u16,
     pub rpc_user: Option<String>,
     pub rpc_password: Option<String>,
     pub rpc_cookie_file: String,
+    pub rpc_cookie_command: Option<String>,
     pub rpc_wallet_file: String,
+    /// See rpc.rs
+    pub rpc_tls: bool,
+    pub rpc_tls_ca_file: Option<String>,
+    pub rpc_tls_client_cert_file: Option<String>,
+    pub rpc_tls_client_key_file: Option<String>,
+    pub rpc_socks_proxy: Option<String>,
+    pub rpc_retry_attempts: u32,
+    pub rpc_retry_initial_delay_ms: u64,
+    pub rpc_retry_max_delay_ms: u64,
+    /// See rpc.rs
+    pub rpc_timeout_secs: u64,
+}
+
+/// Fee and size policy of a maker, can be reloaded while the maker is running
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct MakerSettings {
+    pub absolute_fee_sat: u64,
+    /// In sat/vbyte, see maker_protocol::scaled_absolute_fee
+    pub dynamic_fee_reference_rate: Option<u64>,
+    pub amount_relative_fee_ppb: u64,
+    pub time_relative_fee_ppb: u64,
+    pub min_size: u64,
+    /// Offers are also limited by the wallet balance
+    pub max_size: Option<u64>,
+    pub max_concurrent_connections: usize,
+    /// Limits of taker misbehavior, see taker_blacklist.rs
+    pub max_aborted_connections: u32,
+    pub max_protocol_errors: u32,
+    pub max_offer_requests: u32,
+    pub misbehavior_window_secs: u64,
+    pub ban_duration_secs: u64,
+    /// In sat/vbyte, see maker_protocol::consolidate_small_coins
+    pub consolidation_max_fee_rate: Option<u64>,
+    pub consolidation_batch_size: usize,
+    /// In sat/vbyte, see contracts::verify_funding_tx_fee_rate
+    pub min_funding_tx_fee_rate: u64,
+    /// See maker_rebalance.rs
+    pub rebalance_interval_hours: u64,
+    pub rebalance_max_utxo_count: usize,
+    pub rebalance_max_size_skew: u64,
+    /// Each one is a ListenAddress, not reloaded
+    pub listen_addresses: Vec<String>,
+    /// Not reloaded
+    #[serde(default)]
+    pub public_addresses: Vec<String>,
+    /// Not reloaded, see maker_protocol::instance_listen_addresses
+    pub instances: u32,
+    /// See wallet_sync::derive_cold_timelock_pubkey
+    pub cold_timelock_xpub: Option<String>,
+    /// Sorted by amount, see contracts::calculate_required_confirms
+    #[serde(default)]
+    pub tiers: Vec<ConfirmsTier>,
+}
+
+impl MakerSettings {
+    fn check_values(&self) -> Vec<String> {
+        let mut problems = self
+            .listen_addresses
+            .iter()
+            .filter_map(|address| address.parse::<ListenAddress>().err())
+            .map(|e| format!("maker.listen_addresses: {}", e))
+            .collect::<Vec<String>>();
+        if self.dynamic_fee_reference_rate == Some(0) {
+            problems.push("maker.dynamic_fee_reference_rate: must be more than 0".to_string());
+        }
+        if self.consolidation_batch_size < 2 {
+            problems.push("maker.consolidation_batch_size: must be at least 2".to_string());
+        }
+        if self.min_funding_tx_fee_rate == 0 {
+            problems.push("maker.min_funding_tx_fee_rate: must be more than 0".to_string());
+        }
+        if self.rebalance_max_size_skew < 2 {
+            problems.push("maker.rebalance_max_size_skew: must be at least 2".to_string());
+        }
+        if self.instances == 0 {
+            problems.push("maker.instances: must be at least 1".to_string());
+        }
+        if let Some(xpub) = &self.cold_timelock_xpub {
+            if ExtendedPubKey::from_str(xpub).is_err() {
+                problems.push(format!("maker.cold_timelock_xpub: invalid xpub `{}`", xpub));
+            }
+        }
+        if self.tiers.windows(2).any(|w| w[0].amount >= w[1].amount) {
+            problems.push("maker.tiers: amounts must be in increasing order".to_string());
+        }
+        if self.tiers.iter().any(|tier| tier.required_confirms < 1) {
+            problems.push("maker.tiers: required_confirms must be at least 1".to_string());
+        }
+        problems
+    }
+}
+
+/// How a taker connects to makers and how long it waits on each step of a coinswap
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TakerSettings {
+    pub first_connect_attempts: u32,
+    pub first_connect_sleep_delay_sec: u64,
+    pub first_connect_attempt_timeout_sec: u64,
+    pub senders_contract_sigs_timeout_sec: u64,
+    pub contract_sigs_exchange_timeout_sec: u64,
+    /// 0 for no timeout
+    pub funding_confirmation_timeout_sec: u64,
+    pub private_key_handover_timeout_sec: u64,
+    pub max_concurrent_offer_requests: usize,
+    /// See funding_tx::find_changeless_inputs
+    pub change_avoidance_tolerance_ppb: u64,
+    /// See funding_tx::randomize_amount
+    pub amount_randomization_ppb: u64,
+    /// See route_filter::route_filters
+    #[serde(default)]
+    pub exclude_makers: Vec<String>,
+    pub avoid_recent_makers: usize,
+    /// See locktime_policy::LocktimePolicy
+    pub refund_locktime: u16,
+    pub refund_locktime_step: u16,
+    pub min_contract_react_time: u16,
+    /// In sat/vbyte
+    pub min_funding_tx_fee_rate: u64,
+}
+
+impl TakerSettings {
+    fn check_values(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (key, timeout_sec) in [
+            (
+                "first_connect_attempt_timeout_sec",
+                self.first_connect_attempt_timeout_sec,
+            ),
+            (
+                "senders_contract_sigs_timeout_sec",
+                self.senders_contract_sigs_timeout_sec,
+            ),
+            (
+                "contract_sigs_exchange_timeout_sec",
+                self.contract_sigs_exchange_timeout_sec,
+            ),
+            (
+                "private_key_handover_timeout_sec",
+                self.private_key_handover_timeout_sec,
+            ),
+        ] {
+            if timeout_sec == 0 {
+                problems.push(format!("taker.{}: must be more than 0", key));
+            }
+        }
+        if self.max_concurrent_offer_requests == 0 {
+            problems.push("taker.max_concurrent_offer_requests: must be more than 0".to_string());
+        }
+        if self.amount_randomization_ppb >= 1_000_000_000 {
+            problems
+                .push("taker.amount_randomization_ppb: must be less than 1000000000".to_string());
+        }
+        if self.refund_locktime == 0 {
+            problems.push("taker.refund_locktime: must be more than 0".to_string());
+        }
+        if self.min_funding_tx_fee_rate == 0 {
+            problems.push("taker.min_funding_tx_fee_rate: must be more than 0".to_string());
+        }
+        if self.refund_locktime_step < self.min_contract_react_time {
+            problems.push(
+                "taker.refund_locktime_step: must be at least min_contract_react_time".to_string(),
+            );
+        }
+        problems
+    }
+}
+
+/// Settings relating to tor
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TorSettings {
+    /// Only connect to onion services, see Settings::is_tor_only
+    pub only: bool,
+    pub control_address: String,
+    pub control_password: Option<String>,
+    /// 0 means never rotate
+    pub onion_rotation_hours: u64,
+}
+
+/// Settings relating to i2p, see i2p.rs
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct I2pSettings {
+    pub sam_address: String,
+}
+
+/// Settings of the watchtower and its clients
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WatchtowerSettings {
+    pub addresses: Vec<String>,
+    pub settled_contract_retention_blocks: u64,
+}
+
+/// Where alerts are sent, see alerts.rs
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AlertSettings {
+    pub webhook_url: Option<String>,
+    pub telegram_bot_token: Option<String>,
+    pub telegram_chat_id: Option<String>,
+    pub email_to: Option<String>,
+    pub sendmail_command: String,
+    pub timelock_warning_blocks: u32,
+    pub fidelity_bond_reminder_days: u32,
+}
+
+impl AlertSettings {
+    /// Return true if alerts are sent anywhere
+    pub fn is_configured(&self) -> bool {
+        self.webhook_url.is_some()
+            || (self.telegram_bot_token.is_some() && self.telegram_chat_id.is_some())
+            || self.email_to.is_some()
+    }
+}
+
+/// Commands run on coinswap events, see hooks.rs
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct HookSettings {
+    pub on_swap_start: Option<String>,
+    pub on_swap_success: Option<String>,
+    pub on_swap_failure: Option<String>,
+    pub on_contract_broadcast: Option<String>,
+}
+
+/// Settings relating to logging, levels are one of LOG_LEVELS
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LoggingSettings {
+    pub level: String,
+    pub taker: Option<String>,
+    pub maker: Option<String>,
+    pub watchtower: Option<String>,
+    pub offerbook: Option<String>,
+    /// "text" or "json"
+    pub console_format: String,
+    /// Relative paths are inside the datadir
+    pub file: Option<PathBuf>,
+    /// "never", "hourly" or "daily"
+    pub file_rotation: String,
+    pub max_log_files: Option<usize>,
+}
+
+impl LoggingSettings {
+    /// Return the tracing filter directives for the configured log levels
+    pub fn filter_directives(&self) -> String {
+        let mut directives = [
+            "teleport",
+            "main",
+            "wallet",
+            "offerbook",
+            "watchtower_client",
+        ]
+        .iter()
+        .map(|target| format!("{}={}", target, self.level))
+        .collect::<Vec<String>>();
+        let target_levels = [
+            (&self.taker, &["teleport::taker_protocol"][..]),
+            (&self.maker, &["teleport::maker_protocol"][..]),
+            (
+                &self.watchtower,
+                &[
+                    "teleport::watchtower_protocol",
+                    "teleport::watchtower_client",
+                    "watchtower_client",
+                ][..],
+            ),
+            (
+                &self.offerbook,
+                &["teleport::offerbook_sync", "offerbook"][..],
+            ),
+        ];
+        for (level, targets) in target_levels.iter() {
+            if let Some(level) = level {
+                directives.extend(targets.iter().map(|target| format!("{}={}", target, level)));
+            }
+        }
+        directives.join(",")
+    }
+
+    fn check_values(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let levels = [
+            ("level", Some(&self.level)),
+            ("taker", self.taker.as_ref()),
+            ("maker", self.maker.as_ref()),
+            ("watchtower", self.watchtower.as_ref()),
+            ("offerbook", self.offerbook.as_ref()),
+        ];
+        for (key, level) in levels.iter() {
+            if let Some(level) = level {
+                if !LOG_LEVELS.contains(&level.as_str()) {
+                    problems.push(format!(
+                        "invalid logging.{} `{}`, must be one of {}",
+                        key,
+                        level,
+                        LOG_LEVELS.join(", ")
+                    ));
+                }
+            }
+        }
+        if !["text", "json"].contains(&self.console_format.as_str()) {
+            problems.push(format!(
+                "invalid logging.console_format `{}`, must be text or json",
+                self.console_format
+            ));
+        }
+        if !["never", "hourly", "daily"].contains(&self.file_rotation.as_str()) {
+            problems.push(format!(
+                "invalid logging.file_rotation `{}`, must be one of never, hourly or daily",
+                self.file_rotation
+            ));
+        }
+        problems
+    }
 }
 
 impl BlockchainSettings {
@@ -36,19 +860,60 @@ impl BlockchainSettings {
         }
     }
 
-    /// Return the file path to the bitcoin RPC cookie file.
+    /// Return the file path to the bitcoin RPC cookie file, rpc_cookie_file is used as is
+    /// if it is an absolute path or starts with ~/, otherwise it is in the bitcoin data directory.
     /// Note that this file only exists if bitcoind is actively running
     pub fn rpc_cookie_path(&self) -> PathBuf {
-        bitcoin_data_dir(&self.network).join(&self.rpc_cookie_file)
+        match self.rpc_cookie_file.strip_prefix("~/") {
+            Some(path) => dirs::home_dir().unwrap().join(path),
+            None => bitcoin_data_dir(&self.network).join(&self.rpc_cookie_file),
+        }
+    }
+
+    /// Return true if the network is testnet4, which has no `bitcoin::Network` of its own
+    pub fn is_testnet4(&self) -> bool {
+        self.network == "testnet4"
     }
 
-    /// Return the RPC URL
+    /// Return the RPC URL, https if rpc_tls is set
     pub fn rpc_url(&self) -> String {
+        self.rpc_wallet_url(&self.rpc_wallet_file)
+    }
+
+    /// The url of another Core wallet of the node
+    pub fn rpc_wallet_url(&self, wallet_name: &str) -> String {
         format!(
-            "http://{}:{}/wallet/{}",
-            self.rpc_host, self.rpc_port, &self.rpc_wallet_file
+            "{}://{}:{}/wallet/{}",
+            if self.rpc_tls { "https" } else { "http" },
+            self.rpc_host,
+            self.rpc_port,
+            wallet_name
         )
     }
+
+    fn check_values(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.rpc_tls_client_cert_file.is_some() != self.rpc_tls_client_key_file.is_some() {
+            problems.push(
+                "blockchain.rpc_tls_client_cert_file: must be set together with \
+                rpc_tls_client_key_file"
+                    .to_string(),
+            );
+        }
+        if self.rpc_retry_initial_delay_ms == 0 {
+            problems.push("blockchain.rpc_retry_initial_delay_ms: must be more than 0".to_string());
+        }
+        if self.rpc_timeout_secs == 0 {
+            problems.push("blockchain.rpc_timeout_secs: must be more than 0".to_string());
+        }
+        if self.rpc_retry_max_delay_ms < self.rpc_retry_initial_delay_ms {
+            problems.push(
+                "blockchain.rpc_retry_max_delay_ms: must be at least rpc_retry_initial_delay_ms"
+                    .to_string(),
+            );
+        }
+        problems
+    }
 }
 
 impl Settings {
@@ -56,22 +921,295 @@ impl Settings {
         SETTINGS.get().as_ref().expect("Settings not initialized")
     }
 
-    pub fn init_settings(datadir: &Path) -> &'static Settings {
-        let config_location = datadir.join("teleport.conf");
+    pub fn is_initialized() -> bool {
+        SETTINGS.get().is_some()
+    }
+
+    /// Return true if clearnet connections must be refused, false if the settings
+    /// arent initialized
+    pub fn is_tor_only() -> bool {
+        SETTINGS.get().is_some_and(|settings| settings.tor.only)
+    }
+
+    /// A copy with the passwords replaced, for showing the settings
+    pub fn redacted(&self) -> Settings {
+        let mut settings = self.clone();
+        for secret in [
+            &mut settings.blockchain.rpc_password,
+            &mut settings.tor.control_password,
+        ] {
+            if secret.is_some() {
+                *secret = Some("<hidden>".to_string());
+            }
+        }
+        settings
+    }
+
+    /// Load the settings, in order of increasing precedence from: the defaults,
+    /// teleport.conf in the datadir, environment variables and command line flags
+    /// Returns Error::Config listing every problem found if the settings are invalid
+    pub fn init_settings(
+        datadir: &Path,
+        overrides: &SettingsOverrides,
+    ) -> Result<&'static Settings, Error> {
+        let settings = Settings::load(datadir, &env_var_overrides(), overrides)?;
+        SETTINGS.set(settings).unwrap();
+        SETTINGS_OVERRIDES.set(overrides.clone()).unwrap();
+        Ok(Settings::global())
+    }
+
+    /// Load the settings again, e.g. after teleport.conf was edited, using the same
+    /// datadir and command line flags as init_settings. The global settings are unchanged
+    pub fn reload() -> Result<Settings, Error> {
+        let datadir = Settings::global().datadir.clone().unwrap();
+        Settings::load(
+            &datadir,
+            &env_var_overrides(),
+            SETTINGS_OVERRIDES.get().unwrap(),
+        )
+    }
+
+    fn load(
+        datadir: &Path,
+        env_vars: &[(&str, String)],
+        overrides: &SettingsOverrides,
+    ) -> Result<Settings, Error> {
+        let config_location = datadir.join(CONFIG_FILE_NAME);
+        let mut problems = check_config_file(&config_location);
+
+        //first pass to find out which network is selected, so that the
+        // per-network defaults and section of teleport.conf can be applied
+        let first_pass = Settings::config_builder(
+            datadir,
+            &Settings::default(),
+            &Map::new(),
+            env_vars,
+            overrides,
+        )
+        .build()
+        .and_then(|c| {
+            c.get_string("blockchain.network")
+                .map(|network| (c, network))
+        });
+        let (first_pass, network) = match first_pass {
+            Ok(first_pass) => first_pass,
+            Err(e) => {
+                //the file couldnt be parsed, which check_config_file already reported
+                if problems.is_empty() {
+                    problems.push(e.to_string());
+                }
+                return Err(Error::Config(problems));
+            }
+        };
+
+        let mut defaults = Settings::default();
+        let mut network_section = Map::new();
+        match network_section_name(&network) {
+            Some(section_name) => {
+                defaults.blockchain.rpc_port = default_rpc_port(section_name);
+                if let Ok(section) = first_pass.get_table(&format!("blockchain.{}", section_name)) {
+                    network_section = section;
+                }
+            }
+            None => problems.push(format!(
+                "invalid network `{}`, must be one of main, testnet, testnet4, signet or regtest",
+                network
+            )),
+        }
+
+        let settings =
+            Settings::config_builder(datadir, &defaults, &network_section, env_vars, overrides)
+                .build()
+                .and_then(|s| s.try_deserialize::<Settings>());
+        match settings {
+            Ok(settings) => {
+                problems.extend(settings.blockchain.check_values());
+                problems.extend(settings.maker.check_values());
+                problems.extend(settings.taker.check_values());
+                problems.extend(settings.logging.check_values());
+                if let Some(descriptor) = &settings.recovery_descriptor {
+                    if let Err(e) = checked_descriptor(descriptor) {
+                        problems.push(format!("recovery_descriptor: {}", e));
+                    }
+                }
+                if problems.is_empty() {
+                    Ok(settings)
+                } else {
+                    Err(Error::Config(problems))
+                }
+            }
+            Err(e) => {
+                problems.push(e.to_string());
+                Err(Error::Config(problems))
+            }
+        }
+    }
+
+    //overrides are applied in order so later ones win
+    fn config_builder(
+        datadir: &Path,
+        defaults: &Settings,
+        network_section: &Map<String, Value>,
+        env_vars: &[(&str, String)],
+        overrides: &SettingsOverrides,
+    ) -> ConfigBuilder<DefaultState> {
+        let config_location = datadir.join(CONFIG_FILE_NAME);
 
-        let s = Config::builder()
-            .add_source(Config::try_from(&Settings::default()).unwrap())
+        let mut builder = Config::builder()
+            .add_source(Config::try_from(defaults).unwrap())
             .add_source(
                 File::new(config_location.to_str().unwrap(), FileFormat::Toml).required(false),
+            );
+        for (key, value) in network_section {
+            //the network is chosen before the section is read, so cant be changed by it
+            if key != "network" {
+                builder = builder
+                    .set_override(format!("blockchain.{}", key), value.clone())
+                    .unwrap();
+            }
+        }
+        for (key, value) in env_vars {
+            builder = builder.set_override(*key, value.as_str()).unwrap();
+        }
+        builder
+            .set_override_option("blockchain.network", overrides.network.clone())
+            .unwrap()
+            .set_override_option("blockchain.rpc_host", overrides.rpc_host.clone())
+            .unwrap()
+            .set_override_option("blockchain.rpc_port", overrides.rpc_port)
+            .unwrap()
+            .set_override_option("blockchain.rpc_user", overrides.rpc_user.clone())
+            .unwrap()
+            .set_override_option("blockchain.rpc_password", overrides.rpc_password.clone())
+            .unwrap()
+            .set_override_option(
+                "blockchain.rpc_cookie_file",
+                overrides.rpc_cookie_file.clone(),
             )
+            .unwrap()
+            .set_override_option(
+                "blockchain.rpc_wallet_file",
+                overrides.rpc_wallet_file.clone(),
+            )
+            .unwrap()
+            .set_override_option("tor.only", overrides.tor_only.then_some(true))
+            .unwrap()
             .set_override("datadir", datadir.to_str())
             .unwrap()
-            .build()
-            .unwrap();
+    }
+}
 
-        let settings = s.try_deserialize().unwrap();
-        SETTINGS.set(settings).unwrap();
-        Settings::global()
+fn env_var_overrides() -> Vec<(&'static str, String)> {
+    ENV_OVERRIDES
+        .iter()
+        .filter_map(|(var, key)| std::env::var(var).ok().map(|value| (*key, value)))
+        .collect()
+}
+
+/// Write DEFAULT_CONFIG_FILE to path, an existing file is only replaced if overwrite is set
+pub fn write_default_config_file(path: &Path, overwrite: bool) -> Result<(), Error> {
+    if path.exists() && !overwrite {
+        return Err(Error::Config(vec![format!(
+            "{} already exists, use --force to overwrite it",
+            path.display()
+        )]));
+    }
+    std::fs::write(path, DEFAULT_CONFIG_FILE)?;
+    Ok(())
+}
+
+/// Checks teleport.conf is valid toml and only contains known keys, so that typos are
+/// reported instead of silently falling back to defaults
+fn check_config_file(config_location: &Path) -> Vec<String> {
+    let contents = match std::fs::read_to_string(config_location) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            return vec![format!(
+                "unable to read {}: {}",
+                config_location.display(),
+                e
+            )]
+        }
+    };
+    let table = match contents.parse::<toml::Table>() {
+        Ok(table) => table,
+        Err(e) => {
+            return vec![format!(
+                "unable to parse {}: {}",
+                config_location.display(),
+                e
+            )]
+        }
+    };
+
+    let mut problems = Vec::new();
+    let mut unknown_key = |key: String| {
+        problems.push(format!(
+            "unknown key `{}` in {}",
+            key,
+            config_location.display()
+        ))
+    };
+    for (section, value) in &table {
+        let section_table = value.as_table().into_iter().flatten();
+        match section.as_str() {
+            "datadir" | "default_wallet" | "recovery_descriptor" => (),
+            "blockchain" => {
+                for (key, value) in section_table {
+                    if NETWORK_SECTIONS.contains(&key.as_str()) {
+                        for network_key in value.as_table().into_iter().flatten().map(|(k, _)| k) {
+                            if !BLOCKCHAIN_KEYS[1..].contains(&network_key.as_str()) {
+                                unknown_key(format!("blockchain.{}.{}", key, network_key));
+                            }
+                        }
+                    } else if !BLOCKCHAIN_KEYS.contains(&key.as_str()) {
+                        unknown_key(format!("blockchain.{}", key));
+                    }
+                }
+            }
+            "maker" | "taker" | "tor" | "i2p" | "watchtower" | "alerts" | "hooks" | "logging" => {
+                let known_keys = match section.as_str() {
+                    "maker" => &MAKER_KEYS[..],
+                    "taker" => &TAKER_KEYS[..],
+                    "tor" => &TOR_KEYS[..],
+                    "i2p" => &I2P_KEYS[..],
+                    "watchtower" => &WATCHTOWER_KEYS[..],
+                    "alerts" => &ALERTS_KEYS[..],
+                    "hooks" => &HOOKS_KEYS[..],
+                    _ => &LOGGING_KEYS[..],
+                };
+                for (key, _) in section_table {
+                    if !known_keys.contains(&key.as_str()) {
+                        unknown_key(format!("{}.{}", section, key));
+                    }
+                }
+            }
+            _ => unknown_key(section.clone()),
+        }
+    }
+    problems
+}
+
+/// Return the name of the `[blockchain.<name>]` section of teleport.conf for a network
+fn network_section_name(network: &str) -> Option<&'static str> {
+    match network {
+        "main" | "mainnet" => Some("mainnet"),
+        "test" | "testnet" => Some("testnet"),
+        "testnet4" => Some("testnet4"),
+        "signet" => Some("signet"),
+        "regtest" => Some("regtest"),
+        _ => None,
+    }
+}
+
+fn default_rpc_port(section_name: &str) -> u16 {
+    match section_name {
+        "mainnet" => 8332,
+        "testnet" => 18332,
+        "testnet4" => 48332,
+        "signet" => 38332,
+        _ => 18443,
     }
 }
 
@@ -85,9 +1223,462 @@ impl Default for Settings {
                 rpc_user: None,
                 rpc_password: None,
                 rpc_cookie_file: ".cookie".to_string(),
+                rpc_cookie_command: None,
                 rpc_wallet_file: "teleport".to_string(),
+                rpc_tls: false,
+                rpc_tls_ca_file: None,
+                rpc_tls_client_cert_file: None,
+                rpc_tls_client_key_file: None,
+                rpc_socks_proxy: None,
+                rpc_retry_attempts: 5,
+                rpc_retry_initial_delay_ms: 500,
+                rpc_retry_max_delay_ms: 30_000,
+                rpc_timeout_secs: 120,
+            },
+            maker: MakerSettings {
+                absolute_fee_sat: 1000,
+                dynamic_fee_reference_rate: None,
+                amount_relative_fee_ppb: 10_000_000,
+                time_relative_fee_ppb: 100_000,
+                min_size: 10_000,
+                max_size: None,
+                max_concurrent_connections: 100,
+                max_aborted_connections: 10,
+                max_protocol_errors: 3,
+                max_offer_requests: 60,
+                misbehavior_window_secs: 3600,
+                ban_duration_secs: 86400,
+                consolidation_max_fee_rate: None,
+                consolidation_batch_size: 20,
+                min_funding_tx_fee_rate: 1,
+                rebalance_interval_hours: 0,
+                rebalance_max_utxo_count: 50,
+                rebalance_max_size_skew: 10,
+                listen_addresses: vec!["127.0.0.1:6102".to_string()],
+                public_addresses: Vec::new(),
+                instances: 1,
+                cold_timelock_xpub: None,
+                tiers: vec![
+                    ConfirmsTier {
+                        amount: 1_000_000,
+                        required_confirms: 2,
+                        ..ConfirmsTier::default()
+                    },
+                    ConfirmsTier {
+                        amount: 100_000_000,
+                        required_confirms: 3,
+                        ..ConfirmsTier::default()
+                    },
+                ],
+            },
+            taker: TakerSettings {
+                first_connect_attempts: 5,
+                first_connect_sleep_delay_sec: 1,
+                first_connect_attempt_timeout_sec: 20,
+                senders_contract_sigs_timeout_sec: 20,
+                contract_sigs_exchange_timeout_sec: 300,
+                funding_confirmation_timeout_sec: 0,
+                private_key_handover_timeout_sec: 300,
+                max_concurrent_offer_requests: 20,
+                change_avoidance_tolerance_ppb: 5_000_000,
+                amount_randomization_ppb: 10_000_000,
+                exclude_makers: Vec::new(),
+                avoid_recent_makers: 0,
+                refund_locktime: 48,
+                refund_locktime_step: 48,
+                min_contract_react_time: 48,
+                min_funding_tx_fee_rate: 1,
+            },
+            tor: TorSettings {
+                only: false,
+                control_address: "127.0.0.1:9051".to_string(),
+                control_password: None,
+                onion_rotation_hours: 0,
+            },
+            i2p: I2pSettings {
+                sam_address: "127.0.0.1:7656".to_string(),
+            },
+            watchtower: WatchtowerSettings {
+                addresses: vec!["localhost:6103".to_string()],
+                settled_contract_retention_blocks: 144,
+            },
+            alerts: AlertSettings {
+                webhook_url: None,
+                telegram_bot_token: None,
+                telegram_chat_id: None,
+                email_to: None,
+                sendmail_command: "sendmail".to_string(),
+                timelock_warning_blocks: 12,
+                fidelity_bond_reminder_days: 30,
+            },
+            hooks: HookSettings::default(),
+            logging: LoggingSettings {
+                level: "info".to_string(),
+                taker: None,
+                maker: None,
+                watchtower: None,
+                offerbook: None,
+                console_format: "text".to_string(),
+                file: None,
+                file_rotation: "daily".to_string(),
+                max_log_files: None,
             },
             datadir: None,
+            default_wallet: None,
+            recovery_descriptor: None,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_settings_precedence() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("teleport.conf"),
+            "[blockchain]\nrpc_host = \"conf-host\"\nrpc_port = 1000\nrpc_user = \"conf-user\"\n",
+        )
+        .unwrap();
+        let env_vars = vec![
+            ("blockchain.rpc_port", "2000".to_string()),
+            ("blockchain.rpc_user", "env-user".to_string()),
+        ];
+        let overrides = SettingsOverrides {
+            rpc_user: Some("cli-user".to_string()),
+            ..SettingsOverrides::default()
+        };
+
+        let settings = Settings::load(dir.path(), &env_vars, &overrides).unwrap();
+        assert_eq!(settings.blockchain.network, "regtest");
+        assert_eq!(settings.blockchain.rpc_host, "conf-host");
+        assert_eq!(settings.blockchain.rpc_port, 2000);
+        assert_eq!(settings.blockchain.rpc_user, Some("cli-user".to_string()));
+        assert_eq!(settings.datadir, Some(dir.path().to_path_buf()));
+        assert!(!settings.tor.only);
+
+        let env_vars = vec![("tor.only", "true".to_string())];
+        let settings = Settings::load(dir.path(), &env_vars, &SettingsOverrides::default());
+        assert!(settings.unwrap().tor.only);
+        let overrides = SettingsOverrides {
+            tor_only: true,
+            ..SettingsOverrides::default()
+        };
+        assert!(
+            Settings::load(dir.path(), &[], &overrides)
+                .unwrap()
+                .tor
+                .only
+        );
+    }
+
+    #[test]
+    fn test_per_network_settings() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("teleport.conf"),
+            concat!(
+                "[blockchain]\nrpc_user = \"user\"\n",
+                "[blockchain.regtest]\nrpc_wallet_file = \"regtest-wallet\"\n",
+                "[blockchain.testnet]\nrpc_wallet_file = \"testnet-wallet\"\n",
+            ),
+        )
+        .unwrap();
+
+        let settings = Settings::load(dir.path(), &[], &SettingsOverrides::default()).unwrap();
+        assert_eq!(settings.blockchain.rpc_port, 18443);
+        assert_eq!(settings.blockchain.rpc_wallet_file, "regtest-wallet");
+
+        let overrides = SettingsOverrides {
+            network: Some("testnet".to_string()),
+            ..SettingsOverrides::default()
+        };
+        let settings = Settings::load(dir.path(), &[], &overrides).unwrap();
+        assert_eq!(settings.blockchain.network, "testnet");
+        assert_eq!(settings.blockchain.rpc_port, 18332);
+        assert_eq!(settings.blockchain.rpc_wallet_file, "testnet-wallet");
+        assert_eq!(settings.blockchain.rpc_user, Some("user".to_string()));
+
+        let overrides = SettingsOverrides {
+            network: Some("signet".to_string()),
+            ..SettingsOverrides::default()
+        };
+        let settings = Settings::load(dir.path(), &[], &overrides).unwrap();
+        assert_eq!(settings.blockchain.rpc_port, 38332);
+        assert_eq!(settings.blockchain.rpc_wallet_file, "teleport");
+
+        let overrides = SettingsOverrides {
+            network: Some("testnet4".to_string()),
+            ..SettingsOverrides::default()
+        };
+        let settings = Settings::load(dir.path(), &[], &overrides).unwrap();
+        assert_eq!(settings.blockchain.rpc_port, 48332);
+        assert_eq!(settings.blockchain.rpc_wallet_file, "teleport");
+        assert!(settings.blockchain.is_testnet4());
+        assert_eq!(
+            settings.blockchain.rpc_cookie_path(),
+            bitcoin_data_dir("testnet4").join(".cookie")
+        );
+    }
+
+    #[test]
+    fn test_rpc_cookie_path() {
+        let dir = tempdir().unwrap();
+        let cookie_file = dir.path().join("bitcoind").join(".cookie");
+        let env_vars = [(
+            "blockchain.rpc_cookie_file",
+            cookie_file.to_str().unwrap().to_string(),
+        )];
+        let settings =
+            Settings::load(dir.path(), &env_vars, &SettingsOverrides::default()).unwrap();
+        assert_eq!(settings.blockchain.rpc_cookie_path(), cookie_file);
+
+        let overrides = SettingsOverrides {
+            rpc_cookie_file: Some("~/bitcoind/.cookie".to_string()),
+            ..SettingsOverrides::default()
+        };
+        let settings = Settings::load(dir.path(), &env_vars, &overrides).unwrap();
+        assert_eq!(
+            settings.blockchain.rpc_cookie_path(),
+            dirs::home_dir().unwrap().join("bitcoind").join(".cookie")
+        );
+
+        let overrides = SettingsOverrides {
+            rpc_cookie_file: Some(".regtest-cookie".to_string()),
+            ..SettingsOverrides::default()
+        };
+        let settings = Settings::load(dir.path(), &[], &overrides).unwrap();
+        assert_eq!(
+            settings.blockchain.rpc_cookie_path(),
+            bitcoin_data_dir("regtest").join(".regtest-cookie")
+        );
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        let dir = tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("teleport.conf"),
+            concat!(
+                "[blockchain]\nrpc_hots = \"localhost\"\n",
+                "[blockchain.regtest]\nnetwork = \"main\"\n",
+                "[maker]\nfee = 1\n",
+            ),
+        )
+        .unwrap();
+        match Settings::load(dir.path(), &[], &SettingsOverrides::default()) {
+            Err(Error::Config(problems)) => {
+                assert_eq!(problems.len(), 3);
+                for key in [
+                    "`blockchain.rpc_hots`",
+                    "`blockchain.regtest.network`",
+                    "`maker.fee`",
+                ] {
+                    assert!(problems.iter().any(|p| p.contains(key)));
+                }
+            }
+            _ => panic!("expected config error"),
+        }
+
+        std::fs::write(dir.path().join("teleport.conf"), "[blockchain]\n").unwrap();
+        let overrides = SettingsOverrides {
+            network: Some("mainet".to_string()),
+            ..SettingsOverrides::default()
+        };
+        match Settings::load(dir.path(), &[], &overrides) {
+            Err(Error::Config(problems)) => {
+                assert_eq!(problems.len(), 1);
+                assert!(problems[0].contains("invalid network `mainet`"));
+            }
+            _ => panic!("expected config error"),
+        }
+
+        std::fs::write(
+            dir.path().join("teleport.conf"),
+            "[maker]\nlisten_addresses = [\"127.0.0.1:6102\", \"localhost:6102\"]\n",
+        )
+        .unwrap();
+        match Settings::load(dir.path(), &[], &SettingsOverrides::default()) {
+            Err(Error::Config(problems)) => {
+                assert_eq!(problems.len(), 1);
+                assert!(problems[0].contains("`localhost:6102`"));
+            }
+            _ => panic!("expected config error"),
+        }
+
+        std::fs::write(
+            dir.path().join("teleport.conf"),
+            concat!(
+                "[maker]\ntiers = [{ amount = 100000000, required_confirms = 3 }, ",
+                "{ amount = 1000000, required_confirms = 2, maximum_locktime = 240 }]\n",
+            ),
+        )
+        .unwrap();
+        match Settings::load(dir.path(), &[], &SettingsOverrides::default()) {
+            Err(Error::Config(problems)) => {
+                assert_eq!(
+                    problems,
+                    vec!["maker.tiers: amounts must be in increasing order"]
+                );
+            }
+            _ => panic!("expected config error"),
+        }
+        std::fs::write(
+            dir.path().join("teleport.conf"),
+            "[maker]\ntiers = [{ amount = 1000000, required_confirms = 0 }]\n",
+        )
+        .unwrap();
+        match Settings::load(dir.path(), &[], &SettingsOverrides::default()) {
+            Err(Error::Config(problems)) => {
+                assert_eq!(
+                    problems,
+                    vec!["maker.tiers: required_confirms must be at least 1"]
+                );
+            }
+            _ => panic!("expected config error"),
+        }
+        std::fs::write(dir.path().join("teleport.conf"), "[blockchain]\n").unwrap();
+
+        let env_vars = vec![("logging.console_format", "xml".to_string())];
+        match Settings::load(dir.path(), &env_vars, &SettingsOverrides::default()) {
+            Err(Error::Config(problems)) => {
+                assert_eq!(problems.len(), 1);
+                assert!(problems[0].contains("logging.console_format `xml`"));
+            }
+            _ => panic!("expected config error"),
+        }
+
+        let env_vars = vec![
+            ("maker.min_funding_tx_fee_rate", "0".to_string()),
+            ("taker.min_funding_tx_fee_rate", "0".to_string()),
+        ];
+        match Settings::load(dir.path(), &env_vars, &SettingsOverrides::default()) {
+            Err(Error::Config(problems)) => {
+                assert_eq!(
+                    problems,
+                    vec![
+                        "maker.min_funding_tx_fee_rate: must be more than 0",
+                        "taker.min_funding_tx_fee_rate: must be more than 0"
+                    ]
+                );
+            }
+            _ => panic!("expected config error"),
+        }
+
+        let env_vars = vec![("taker.max_concurrent_offer_requests", "0".to_string())];
+        match Settings::load(dir.path(), &env_vars, &SettingsOverrides::default()) {
+            Err(Error::Config(problems)) => {
+                assert_eq!(problems.len(), 1);
+                assert!(problems[0].contains("taker.max_concurrent_offer_requests"));
+            }
+            _ => panic!("expected config error"),
+        }
+
+        let env_vars = vec![
+            ("taker.contract_sigs_exchange_timeout_sec", "0".to_string()),
+            ("taker.funding_confirmation_timeout_sec", "0".to_string()),
+        ];
+        match Settings::load(dir.path(), &env_vars, &SettingsOverrides::default()) {
+            Err(Error::Config(problems)) => {
+                assert_eq!(
+                    problems,
+                    vec!["taker.contract_sigs_exchange_timeout_sec: must be more than 0"]
+                );
+            }
+            _ => panic!("expected config error"),
+        }
+
+        let env_vars = vec![("blockchain.rpc_port", "not-a-port".to_string())];
+        assert!(Settings::load(dir.path(), &env_vars, &SettingsOverrides::default()).is_err());
+    }
+
+    #[test]
+    fn test_default_config_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("teleport.conf");
+        write_default_config_file(&path, false).unwrap();
+        assert!(write_default_config_file(&path, false).is_err());
+        write_default_config_file(&path, true).unwrap();
+
+        //with the options uncommented the file must be valid and give the defaults
+        let uncommented = DEFAULT_CONFIG_FILE
+            .lines()
+            .filter(|line| {
+                !line.starts_with("#[blockchain.")
+                    && !line.starts_with("#rpc_wallet_file = \"teleport-")
+            })
+            .map(|line| match line.strip_prefix('#') {
+                Some(option) if option.contains(" = ") => option,
+                _ => line,
+            })
+            .collect::<Vec<&str>>()
+            .join("\n");
+        std::fs::write(&path, uncommented).unwrap();
+        let settings = Settings::load(dir.path(), &[], &SettingsOverrides::default()).unwrap();
+        let defaults = Settings::default();
+        assert_eq!(settings.blockchain.rpc_port, defaults.blockchain.rpc_port);
+        assert_eq!(
+            settings.blockchain.rpc_cookie_file,
+            defaults.blockchain.rpc_cookie_file
+        );
+        assert_eq!(
+            settings.blockchain.rpc_wallet_file,
+            defaults.blockchain.rpc_wallet_file
+        );
+        assert_eq!(settings.maker.max_size, Some(100_000_000));
+        assert_eq!(settings.maker.dynamic_fee_reference_rate, Some(10));
+        assert_eq!(settings.maker.consolidation_max_fee_rate, Some(2));
+        assert_eq!(settings.logging.file, Some(PathBuf::from("teleport.log")));
+        assert_eq!(settings.watchtower.addresses, defaults.watchtower.addresses);
+        assert_eq!(
+            settings.taker.max_concurrent_offer_requests,
+            defaults.taker.max_concurrent_offer_requests
+        );
+        assert_eq!(
+            MakerSettings {
+                max_size: None,
+                dynamic_fee_reference_rate: None,
+                consolidation_max_fee_rate: None,
+                cold_timelock_xpub: None,
+                ..settings.maker
+            },
+            defaults.maker
+        );
+    }
+
+    #[test]
+    fn test_redacted_settings() {
+        let mut settings = Settings::default();
+        settings.blockchain.rpc_password = Some("rpc-secret".to_string());
+        settings.tor.control_password = Some("tor-secret".to_string());
+        let shown = toml::to_string(&settings.redacted()).unwrap();
+        for secret in ["rpc-secret", "tor-secret"] {
+            assert!(!shown.contains(secret));
+        }
+        assert!(shown.contains("<hidden>"));
+        //unset passwords stay unset
+        assert_eq!(Settings::default().redacted().tor.control_password, None);
+    }
+
+    #[test]
+    fn test_logging_filter_directives() {
+        let mut logging = Settings::default().logging;
+        assert_eq!(
+            logging.filter_directives(),
+            "teleport=info,main=info,wallet=info,offerbook=info,watchtower_client=info"
+        );
+        logging.level = "warn".to_string();
+        logging.maker = Some("debug".to_string());
+        logging.offerbook = Some("error".to_string());
+        assert_eq!(
+            logging.filter_directives(),
+            concat!(
+                "teleport=warn,main=warn,wallet=warn,offerbook=warn,watchtower_client=warn,",
+                "teleport::maker_protocol=debug,teleport::offerbook_sync=error,offerbook=error"
+            )
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//while a coinswap runs the taker watches the contracts of every hop, its own as well as those
+// between two makers, which it only knows through WatchOnlySwapCoin
+//a contract tx appearing before the coinswap settled means someone gave up on it, so the
+// coinswap cant complete and the taker gets its coins back by its own contract txes
+//a funding output spent by anything but its contract tx takes both parties of the hop, so the
+// two makers of the hop are likely the same entity. that doesnt put the taker's coins at risk
+// and the coinswap carries on
+
+use std::collections::HashSet;
+
+use bitcoin::{BlockHash, OutPoint, Script, Transaction, Txid};
+use bitcoincore_rpc::RpcApi;
+
+use crate::error::Error;
+use crate::rpc::Client;
+use crate::watchtower_protocol::{
+    check_for_broadcasted_contract_txes, ContractTransaction, ContractsInfo,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SwapAlarm {
+    //contract txes of the hop were seen in the mempool or a block
+    ContractBroadcast { hop: usize, txids: Vec<Txid> },
+    //a funding output of the hop was spent by a tx other than its contract tx
+    FundingSpent { hop: usize, outpoint: OutPoint },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryDecision {
+    Continue,
+    //give up on the coinswap and broadcast the taker's own contract txes
+    BroadcastContracts,
+}
+
+/// What the taker does about the alarms raised while watching a coinswap
+pub fn recovery_decision(alarms: &[SwapAlarm]) -> RecoveryDecision {
+    if alarms
+        .iter()
+        .any(|a| matches!(a, SwapAlarm::ContractBroadcast { .. }))
+    {
+        RecoveryDecision::BroadcastContracts
+    } else {
+        RecoveryDecision::Continue
+    }
+}
+
+//whether the funding output was spent since it was last seen unspent
+fn funding_output_spent(
+    unspent_outpoints: &mut HashSet<OutPoint>,
+    outpoint: OutPoint,
+    unspent: bool,
+) -> bool {
+    if unspent {
+        unspent_outpoints.insert(outpoint);
+        false
+    } else {
+        unspent_outpoints.remove(&outpoint)
+    }
+}
+
+/// The contracts of each hop of a coinswap, hop 0 being the taker's outgoing contracts and
+/// hop n those between maker n-1 and maker n
+#[derive(Default)]
+pub struct SwapMonitor {
+    hops: Vec<Vec<Transaction>>,
+    //funding outputs seen unspent, a funding tx may not be broadcast yet when its hop is added
+    unspent_funding_outpoints: HashSet<OutPoint>,
+    last_checked_block_height: Option<u64>,
+    last_checked_block_hash: Option<BlockHash>,
+}
+
+impl SwapMonitor {
+    pub fn new() -> SwapMonitor {
+        SwapMonitor::default()
+    }
+
+    pub fn watch_hop(&mut self, contract_txes: Vec<Transaction>) {
+        self.hops.push(contract_txes);
+    }
+
+    /// Look for contract txes and funding output spends of every hop on the network since the
+    /// last poll
+    pub fn poll(&mut self, rpc: &Client) -> Result<Vec<SwapAlarm>, Error> {
+        if self.hops.is_empty() {
+            return Ok(Vec::new());
+        }
+        let broadcasted_contracts = check_for_broadcasted_contract_txes(
+            rpc,
+            &self
+                .hops
+                .iter()
+                .map(|txes| ContractsInfo {
+                    contract_txes: txes
+                        .iter()
+                        .map(|tx| ContractTransaction {
+                            tx: tx.clone(),
+                            redeemscript: Script::new(),
+                            hashlock_spend_without_preimage: None,
+                            timelock_spend: None,
+                            timelock_spend_broadcasted: false,
+                            hashlock_spend_broadcasted: false,
+                        })
+                        .collect::<Vec<ContractTransaction>>(),
+                    wallet_label: String::new(),
+                })
+                .collect::<Vec<ContractsInfo>>(),
+            &mut self.last_checked_block_height,
+            &mut self.last_checked_block_hash,
+        )?;
+        let broadcasted_txids = broadcasted_contracts
+            .iter()
+            .flat_map(|c| c.contract_txes.iter().map(|ctx| ctx.tx.txid()))
+            .collect::<HashSet<Txid>>();
+
+        let mut alarms = Vec::<SwapAlarm>::new();
+        for (hop, contract_txes) in self.hops.iter().enumerate() {
+            let txids = contract_txes
+                .iter()
+                .map(|tx| tx.txid())
+                .filter(|txid| broadcasted_txids.contains(txid))
+                .collect::<Vec<Txid>>();
+            if !txids.is_empty() {
+                alarms.push(SwapAlarm::ContractBroadcast { hop, txids });
+                continue;
+            }
+            for contract_tx in contract_txes {
+                let outpoint = contract_tx.input[0].previous_output;
+                let unspent = rpc
+                    .get_tx_out(&outpoint.txid, outpoint.vout, Some(true))?
+                    .is_some();
+                if funding_output_spent(&mut self.unspent_funding_outpoints, outpoint, unspent) {
+                    alarms.push(SwapAlarm::FundingSpent { hop, outpoint });
+                }
+            }
+        }
+        Ok(alarms)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_recovery_decision() {
+        let outpoint = OutPoint {
+            txid: Txid::from_slice(&[1; 32]).unwrap(),
+            vout: 0,
+        };
+        let funding_spent = SwapAlarm::FundingSpent { hop: 1, outpoint };
+        assert_eq!(recovery_decision(&[]), RecoveryDecision::Continue);
+        assert_eq!(
+            recovery_decision(&[funding_spent.clone()]),
+            RecoveryDecision::Continue
+        );
+        let contract_broadcast = SwapAlarm::ContractBroadcast {
+            hop: 2,
+            txids: vec![Txid::from_slice(&[2; 32]).unwrap()],
+        };
+        assert_eq!(
+            recovery_decision(&[funding_spent, contract_broadcast]),
+            RecoveryDecision::BroadcastContracts
+        );
+
+        //a funding output only counts as spent after it was seen unspent, and only once
+        let mut unspent_outpoints = HashSet::new();
+        assert!(!funding_output_spent(
+            &mut unspent_outpoints,
+            outpoint,
+            false
+        ));
+        assert!(!funding_output_spent(
+            &mut unspent_outpoints,
+            outpoint,
+            true
+        ));
+        assert!(funding_output_spent(
+            &mut unspent_outpoints,
+            outpoint,
+            false
+        ));
+        assert!(!funding_output_spent(
+            &mut unspent_outpoints,
+            outpoint,
+            false
+        ));
+    }
+}
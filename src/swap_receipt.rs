@@ -0,0 +1,192 @@
+//receipts of completed coinswaps, written by the taker so it can later show a third party
+// what happened in a coinswap e.g. in an audit or a dispute with a maker
+//a receipt is signed with the same wallet key as the audit log, and commits to the audit log
+// entries of the coinswap's messages through the hashes of the entries before and after it,
+// so together with audit.log it proves which messages were exchanged
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use bitcoin::hashes::hash160::Hash as Hash160;
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{Message, Secp256k1, SecretKey, Signature};
+use bitcoin::util::ecdsa::PublicKey;
+use bitcoin::Txid;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::utils::teleport_data_dir;
+
+pub const SWAP_RECEIPTS_DIR_NAME: &str = "receipts";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReceiptMaker {
+    pub address: String,
+    //the tweakable point the maker's offer was signed with
+    pub identity_pubkey: PublicKey,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SwapReceiptContents {
+    pub swap_id: Hash160,
+    pub completed_timestamp: i64,
+    pub sent_amount_sat: u64,
+    pub received_amount_sat: u64,
+    //paid to the makers and for their miner fees, sent minus received
+    pub coinswap_fee_sat: u64,
+    //of the taker's own funding transactions
+    pub miner_fee_sat: u64,
+    pub funding_txids: Vec<Txid>,
+    //funding transactions of the coins the taker received, which settled the coinswap
+    pub settlement_txids: Vec<Txid>,
+    //in the order the coins went through them
+    pub makers: Vec<ReceiptMaker>,
+    pub transcript_start_hash: Option<sha256::Hash>,
+    pub transcript_end_hash: Option<sha256::Hash>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SwapReceipt {
+    #[serde(flatten)]
+    pub contents: SwapReceiptContents,
+    pub signing_pubkey: PublicKey,
+    pub signature: Signature,
+}
+
+fn receipt_digest(contents: &SwapReceiptContents) -> Result<Message, Error> {
+    let json = serde_json::to_string(contents).map_err(io::Error::from)?;
+    Ok(Message::from_slice(&sha256::Hash::hash(json.as_bytes())[..]).unwrap())
+}
+
+impl SwapReceipt {
+    pub fn sign(contents: SwapReceiptContents, privkey: &SecretKey) -> Result<SwapReceipt, Error> {
+        let secp = Secp256k1::new();
+        let signature = secp.sign(&receipt_digest(&contents)?, privkey);
+        Ok(SwapReceipt {
+            contents,
+            signing_pubkey: PublicKey {
+                compressed: true,
+                key: bitcoin::secp256k1::PublicKey::from_secret_key(&secp, privkey),
+            },
+            signature,
+        })
+    }
+
+    pub fn verify(&self) -> Result<(), Error> {
+        Secp256k1::verification_only()
+            .verify(
+                &receipt_digest(&self.contents)?,
+                &self.signature,
+                &self.signing_pubkey.key,
+            )
+            .map_err(|_| Error::Protocol("invalid swap receipt signature"))
+    }
+}
+
+/// Path of the receipt of a coinswap in the data directory
+pub fn swap_receipt_path(swap_id: Hash160) -> PathBuf {
+    teleport_data_dir()
+        .join(SWAP_RECEIPTS_DIR_NAME)
+        .join(format!("{}.json", swap_id))
+}
+
+pub fn write_swap_receipt(receipt: &SwapReceipt, path: &Path) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = File::create(path)?;
+    serde_json::to_writer_pretty(&mut file, receipt).map_err(io::Error::from)?;
+    writeln!(file)?;
+    Ok(())
+}
+
+/// Read a receipt and check its signature
+pub fn verify_swap_receipt(path: &Path) -> Result<SwapReceipt, Error> {
+    let receipt = serde_json::from_reader::<_, SwapReceipt>(File::open(path)?)
+        .map_err(|_| Error::Protocol("unable to parse swap receipt"))?;
+    receipt.verify()?;
+    Ok(receipt)
+}
+
+/// Every receipt in dir which can be read, newest first
+pub fn load_swap_receipts(dir: &Path) -> Vec<SwapReceipt> {
+    let mut receipts = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| File::open(entry.path()).ok())
+            .filter_map(|file| serde_json::from_reader::<_, SwapReceipt>(file).ok())
+            .collect::<Vec<SwapReceipt>>(),
+        Err(_) => Vec::new(),
+    };
+    receipts.sort_by_key(|r| std::cmp::Reverse(r.contents.completed_timestamp));
+    receipts
+}
+
+/// Addresses of the makers in the last swap_count completed coinswaps
+pub fn recent_swap_makers(swap_count: usize) -> Vec<String> {
+    load_swap_receipts(&teleport_data_dir().join(SWAP_RECEIPTS_DIR_NAME))
+        .into_iter()
+        .take(swap_count)
+        .flat_map(|r| r.contents.makers.into_iter().map(|m| m.address))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_swap_receipt() {
+        let secp = Secp256k1::new();
+        let maker_key = SecretKey::from_slice(&[2; 32]).unwrap();
+        let contents = SwapReceiptContents {
+            swap_id: Hash160::hash(&[1; 32]),
+            completed_timestamp: 1000,
+            sent_amount_sat: 500_000,
+            received_amount_sat: 490_000,
+            coinswap_fee_sat: 10_000,
+            miner_fee_sat: 300,
+            funding_txids: vec![Txid::from_slice(&[3; 32]).unwrap()],
+            settlement_txids: vec![Txid::from_slice(&[4; 32]).unwrap()],
+            makers: vec![ReceiptMaker {
+                address: "a.onion:6102".to_string(),
+                identity_pubkey: PublicKey {
+                    compressed: true,
+                    key: bitcoin::secp256k1::PublicKey::from_secret_key(&secp, &maker_key),
+                },
+            }],
+            transcript_start_hash: Some(sha256::Hash::hash(b"start")),
+            transcript_end_hash: Some(sha256::Hash::hash(b"end")),
+        };
+        let receipt =
+            SwapReceipt::sign(contents, &SecretKey::from_slice(&[5; 32]).unwrap()).unwrap();
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("receipts").join("receipt.json");
+        write_swap_receipt(&receipt, &path).unwrap();
+        assert_eq!(verify_swap_receipt(&path).unwrap(), receipt);
+
+        let mut older = receipt.clone();
+        older.contents.completed_timestamp = 500;
+        write_swap_receipt(&older, &dir.path().join("receipts").join("older.json")).unwrap();
+        fs::write(
+            dir.path().join("receipts").join("other.txt"),
+            "not a receipt",
+        )
+        .unwrap();
+        assert_eq!(
+            load_swap_receipts(&dir.path().join("receipts")),
+            vec![receipt.clone(), older]
+        );
+
+        //changing anything breaks the signature
+        let edited = fs::read_to_string(&path)
+            .unwrap()
+            .replace("490000", "495000");
+        fs::write(&path, edited).unwrap();
+        assert!(verify_swap_receipt(&path).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//sessions tie together the messages of one coinswap between a taker and a maker, which can come
+// over several connections
+//the maker gives every connection a new random session id in its hello. the taker's hello
+// takes it up, starting a new session, or gives the session of an earlier connection of the
+// same coinswap to carry it on. every coinswap message after that carries the session id
+//a maker only accepts coinswap messages carrying the session of their connection, and refuses
+// to carry on sessions it never started or which already completed, so messages recorded from
+// one coinswap cant be replayed into another one running at the same time or into a new one
+//a session taken up in a hello is only kept in memory until the maker accepts the first
+// coinswap message of it, so peers which never go on to a coinswap cant fill the file, and is
+// forgotten if unused for about as long as the maker keeps an idle connection open
+//accepted sessions are kept in swap-sessions.json in the data directory so coinswaps can carry
+// on after the maker restarts
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use bitcoin::hashes::hex::ToHex;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::messages::SessionId;
+use crate::settings::Settings;
+use crate::utils::teleport_data_dir;
+
+//sessions unused for longer are forgotten, no step of a coinswap waits this long as it is
+// more than the longest contract locktime a maker accepts
+pub const SESSION_EXPIRY_SECS: i64 = 7 * 24 * 60 * 60;
+
+static SWAP_SESSIONS: OnceLock<Mutex<SwapSessions>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwapSession {
+    pub completed: bool,
+    pub last_used: i64,
+}
+
+//a session taken up in a hello whose coinswap hasnt started yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OfferedSession {
+    pub last_used: i64,
+    pub expiry_secs: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SwapSessions {
+    //by hex session id
+    pub sessions: HashMap<String, SwapSession>,
+    //by hex session id, never saved
+    #[serde(skip)]
+    pub offered: HashMap<String, OfferedSession>,
+}
+
+pub fn generate_session_id() -> SessionId {
+    rand::thread_rng().gen::<SessionId>()
+}
+
+impl SwapSessions {
+    /// Read the sessions from path, a missing file means no session was started yet
+    pub fn load(path: &Path) -> Result<SwapSessions, Error> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(file).map_err(io::Error::from)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(SwapSessions::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        serde_json::to_writer(File::create(path)?, self).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    /// Start a session which is forgotten if unused for expiry_secs, until accepted
+    pub fn start(&mut self, session_id: &SessionId, now: i64, expiry_secs: i64) {
+        self.offered
+            .retain(|_, session| session.last_used + session.expiry_secs > now);
+        self.offered.insert(
+            session_id.to_hex(),
+            OfferedSession {
+                last_used: now,
+                expiry_secs,
+            },
+        );
+    }
+
+    /// The session's coinswap has started, from now on it is kept for SESSION_EXPIRY_SECS
+    /// and saved. Returns false if the session was already accepted or isnt known
+    pub fn accept(&mut self, session_id: &SessionId, now: i64) -> bool {
+        if self.offered.remove(&session_id.to_hex()).is_none() {
+            return false;
+        }
+        self.sessions
+            .retain(|_, session| session.last_used + SESSION_EXPIRY_SECS > now);
+        self.sessions.insert(
+            session_id.to_hex(),
+            SwapSession {
+                completed: false,
+                last_used: now,
+            },
+        );
+        true
+    }
+
+    /// Fails if the session was never started, has expired or has completed
+    pub fn resume(&mut self, session_id: &SessionId, now: i64) -> Result<(), Error> {
+        if let Some(session) = self.offered.get_mut(&session_id.to_hex()) {
+            if session.last_used + session.expiry_secs <= now {
+                return Err(Error::Protocol("unknown session"));
+            }
+            session.last_used = now;
+            return Ok(());
+        }
+        match self.sessions.get_mut(&session_id.to_hex()) {
+            Some(session) if session.last_used + SESSION_EXPIRY_SECS <= now => {
+                Err(Error::Protocol("unknown session"))
+            }
+            Some(session) if session.completed => Err(Error::Protocol("session already complete")),
+            Some(session) => {
+                session.last_used = now;
+                Ok(())
+            }
+            None => Err(Error::Protocol("unknown session")),
+        }
+    }
+
+    pub fn complete(&mut self, session_id: &SessionId, now: i64) {
+        if let Some(session) = self.sessions.get_mut(&session_id.to_hex()) {
+            session.completed = true;
+            session.last_used = now;
+        }
+    }
+}
+
+pub fn swap_sessions_path() -> PathBuf {
+    teleport_data_dir().join("swap-sessions.json")
+}
+
+//shared by every maker instance of the process, sessions are only kept in memory if the
+// settings arent initialized
+fn swap_sessions() -> &'static Mutex<SwapSessions> {
+    SWAP_SESSIONS.get_or_init(|| {
+        if !Settings::is_initialized() {
+            return Mutex::new(SwapSessions::default());
+        }
+        Mutex::new(
+            SwapSessions::load(&swap_sessions_path()).unwrap_or_else(|e| {
+                tracing::warn!("unable to load swap sessions: {:?}", e);
+                SwapSessions::default()
+            }),
+        )
+    })
+}
+
+fn save_swap_sessions(sessions: &SwapSessions) {
+    if !Settings::is_initialized() {
+        return;
+    }
+    if let Err(e) = sessions.save(&swap_sessions_path()) {
+        tracing::warn!("unable to save swap sessions: {:?}", e);
+    }
+}
+
+/// Start the session given to a connection in the maker's hello, it is forgotten if unused for
+/// expiry_secs until accept_session()
+pub fn start_session(session_id: &SessionId, expiry_secs: i64) {
+    swap_sessions()
+        .lock()
+        .unwrap()
+        .start(session_id, chrono::Utc::now().timestamp(), expiry_secs);
+}
+
+/// The maker accepted the first coinswap message of the session, keep it for as long as a
+/// coinswap may take
+pub fn accept_session(session_id: &SessionId) {
+    let mut sessions = swap_sessions().lock().unwrap();
+    if sessions.accept(session_id, chrono::Utc::now().timestamp()) {
+        save_swap_sessions(&sessions);
+    }
+}
+
+/// Carry on a session on a new connection or with a new message, fails if the maker never
+/// started it or it already completed
+pub fn resume_session(session_id: &SessionId) -> Result<(), Error> {
+    swap_sessions()
+        .lock()
+        .unwrap()
+        .resume(session_id, chrono::Utc::now().timestamp())
+}
+
+/// The coinswap of the session is done, no more messages of it are accepted
+pub fn complete_session(session_id: &SessionId) {
+    let mut sessions = swap_sessions().lock().unwrap();
+    sessions.complete(session_id, chrono::Utc::now().timestamp());
+    save_swap_sessions(&sessions);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_swap_sessions() {
+        let now = 1_792_022_400;
+        let mut sessions = SwapSessions::default();
+        let session_id = generate_session_id();
+        let other_session_id = generate_session_id();
+        assert_ne!(session_id, other_session_id);
+
+        assert!(sessions.resume(&session_id, now).is_err());
+        sessions.start(&session_id, now, 300);
+        assert!(sessions.resume(&session_id, now + 60).is_ok());
+        //nothing is saved until the session is accepted
+        assert!(sessions.sessions.is_empty());
+        assert!(sessions.accept(&session_id, now + 90));
+        assert!(!sessions.accept(&session_id, now + 90));
+        assert_eq!(sessions.sessions.len(), 1);
+        assert!(sessions.resume(&other_session_id, now + 60).is_err());
+        sessions.complete(&session_id, now + 120);
+        match sessions.resume(&session_id, now + 180) {
+            Err(Error::Protocol(e)) => assert_eq!(e, "session already complete"),
+            _ => panic!("expected a protocol error"),
+        }
+
+        //sessions which never got to a coinswap are forgotten once unused for their expiry,
+        // and dropped when the next one starts
+        let unused_session_id = generate_session_id();
+        sessions.start(&unused_session_id, now, 300);
+        assert!(sessions.resume(&unused_session_id, now + 200).is_ok());
+        assert!(sessions.resume(&unused_session_id, now + 500).is_err());
+        assert!(!sessions.accept(&generate_session_id(), now + 500));
+        sessions.start(&generate_session_id(), now + 500, 300);
+        assert_eq!(sessions.offered.len(), 1);
+
+        //accepted sessions unused for too long expire, and are dropped when the next one is
+        // accepted
+        sessions.start(&other_session_id, now, SESSION_EXPIRY_SECS);
+        assert!(sessions.accept(&other_session_id, now));
+        let later = now + SESSION_EXPIRY_SECS;
+        assert!(sessions.resume(&other_session_id, later).is_err());
+        let new_session_id = generate_session_id();
+        sessions.start(&new_session_id, later + 120, 300);
+        assert!(sessions.accept(&new_session_id, later + 120));
+        assert_eq!(sessions.sessions.len(), 1);
+
+        //only accepted sessions are saved
+        sessions.start(&generate_session_id(), later + 120, 300);
+        let json = serde_json::to_string(&sessions).unwrap();
+        let sessions = serde_json::from_str::<SwapSessions>(&json).unwrap();
+        assert_eq!(sessions.sessions.len(), 1);
+        assert!(sessions.offered.is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+//block heights at which each live swapcoin's contract can be spent, shown by `swap-timeline`
+//a contract can be spent by the hashlock as soon as it is on the blockchain and by the timelock
+// once it has been confirmed for its locktime, so for a contract not broadcast yet the heights
+// are the earliest possible ones, counted from the funding tx confirmation
+//the taker or maker with the outgoing swapcoin gets its coins back with the timelock and the
+// other side claims them with the hashlock, the other way around for incoming swapcoins
+
+use bitcoin::hashes::hex::ToHex;
+use bitcoincore_rpc::json::ListUnspentResultEntry;
+use bitcoincore_rpc::RpcApi;
+use serde::Serialize;
+
+use crate::contracts::SwapCoin;
+use crate::error::Error;
+use crate::locktime_policy::AVERAGE_BLOCK_INTERVAL_SECS;
+use crate::rpc::Client;
+use crate::wallet_sync::Wallet;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SwapcoinTimeline {
+    //funding outpoint, or the contract outpoint once the contract is broadcast
+    pub outpoint: String,
+    pub hashvalue: String,
+    //"incoming" or "outgoing"
+    pub direction: &'static str,
+    pub contract_broadcast: bool,
+    pub locktime: u16,
+    pub amount_sat: u64,
+    //None while the funding or contract tx is unconfirmed
+    pub confirmed_height: Option<u64>,
+    pub hashlock_height: Option<u64>,
+    pub timelock_height: Option<u64>,
+    //estimated unix time of timelock_height, from the average block interval
+    pub timelock_timestamp: Option<i64>,
+}
+
+//confirmed_height is of the contract tx if it is broadcast, otherwise of the funding tx and
+// then the contract can be confirmed a block later at the earliest
+fn spend_heights(
+    confirmed_height: Option<u64>,
+    contract_broadcast: bool,
+    locktime: u16,
+) -> (Option<u64>, Option<u64>) {
+    let contract_height = confirmed_height.map(|h| if contract_broadcast { h } else { h + 1 });
+    (
+        contract_height,
+        contract_height.map(|h| h + locktime as u64),
+    )
+}
+
+/// Estimated unix time when the blockchain reaches height
+pub fn estimate_height_timestamp(height: u64, tip_height: u64, now: i64) -> i64 {
+    now + (height as i64 - tip_height as i64) * AVERAGE_BLOCK_INTERVAL_SECS as i64
+}
+
+fn swapcoin_timeline(
+    swapcoin: &dyn SwapCoin,
+    utxo: &ListUnspentResultEntry,
+    direction: &'static str,
+    contract_broadcast: bool,
+    tip_height: u64,
+    now: i64,
+) -> SwapcoinTimeline {
+    let confirmed_height = if utxo.confirmations > 0 {
+        Some(tip_height + 1 - utxo.confirmations as u64)
+    } else {
+        None
+    };
+    let locktime = swapcoin.get_timelock();
+    let (hashlock_height, timelock_height) =
+        spend_heights(confirmed_height, contract_broadcast, locktime);
+    SwapcoinTimeline {
+        outpoint: format!("{}:{}", utxo.txid, utxo.vout),
+        hashvalue: swapcoin.get_hashvalue().to_hex(),
+        direction,
+        contract_broadcast,
+        locktime,
+        amount_sat: utxo.amount.as_sat(),
+        confirmed_height,
+        hashlock_height,
+        timelock_height,
+        timelock_timestamp: timelock_height.map(|h| estimate_height_timestamp(h, tip_height, now)),
+    }
+}
+
+/// Timeline of every swapcoin of the wallet whose coinswap is not finished, from the unspent
+/// funding outputs of unfinished coinswaps and the unspent outputs of broadcast contracts
+pub fn swap_timeline(rpc: &Client, wallet: &mut Wallet) -> Result<Vec<SwapcoinTimeline>, Error> {
+    let tip_height = rpc.get_block_count()?;
+    let now = chrono::Utc::now().timestamp();
+    let mut timelines = Vec::new();
+    for (_hashvalue, (incoming, outgoing)) in wallet.find_incomplete_coinswaps(rpc)? {
+        for (utxo, swapcoin) in incoming {
+            timelines.push(swapcoin_timeline(
+                swapcoin, &utxo, "incoming", false, tip_height, now,
+            ));
+        }
+        for (utxo, swapcoin) in outgoing {
+            timelines.push(swapcoin_timeline(
+                swapcoin, &utxo, "outgoing", false, tip_height, now,
+            ));
+        }
+    }
+    let (incoming, outgoing) = wallet.find_live_contract_unspents(rpc)?;
+    for (swapcoin, utxo) in incoming {
+        timelines.push(swapcoin_timeline(
+            swapcoin, &utxo, "incoming", true, tip_height, now,
+        ));
+    }
+    for (swapcoin, utxo) in outgoing {
+        timelines.push(swapcoin_timeline(
+            swapcoin, &utxo, "outgoing", true, tip_height, now,
+        ));
+    }
+    timelines.sort_by_key(|t| t.timelock_height.unwrap_or(u64::MAX));
+    Ok(timelines)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_spend_heights() {
+        assert_eq!(spend_heights(None, false, 48), (None, None));
+        assert_eq!(spend_heights(Some(100), false, 48), (Some(101), Some(149)));
+        assert_eq!(spend_heights(Some(100), true, 48), (Some(100), Some(148)));
+        assert_eq!(estimate_height_timestamp(148, 100, 1000), 1000 + 48 * 600);
+        assert_eq!(estimate_height_timestamp(90, 100, 10_000), 4000);
+    }
+}
@@ -0,0 +1,178 @@
+//descriptors of the wallet's swapcoins with their private keys, so the coins in 2-of-2 multisigs
+// stay recoverable with Bitcoin Core alone if teleport itself cant run
+//  incoming swapcoins whose other key was handed over have both private keys, Core can spend
+//  them on its own
+//  other swapcoins have the wallet's private key and the other party's pubkey, Core can sign
+//  the wallet's half of a psbt spending them
+//the descriptors are in the form `importdescriptors` takes, and are imported into a Core
+// descriptor wallet of their own as teleport's Core wallet is watch-only
+//contracts arent standard descriptors, they are spent with `spend-contract`
+
+use bitcoin::secp256k1::SecretKey;
+use bitcoin::{Network, PrivateKey, PublicKey};
+use bitcoincore_rpc::RpcApi;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::rpc::Client;
+use crate::wallet_sync::Wallet;
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+//one request of `importdescriptors`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DescriptorImport {
+    pub desc: String,
+    //unix time Core rescans the blockchain from
+    pub timestamp: i64,
+    pub label: String,
+}
+
+fn polymod(c: u64, value: u64) -> u64 {
+    let c0 = c >> 35;
+    let mut c = ((c & 0x7ffffffff) << 5) ^ value;
+    for (bit, generator) in [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ]
+    .iter()
+    .enumerate()
+    {
+        if c0 & (1 << bit) != 0 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+/// The checksum Core appends to descriptors after a '#', None if the descriptor has a
+/// character descriptors cant have
+pub fn descriptor_checksum(descriptor: &str) -> Option<String> {
+    let mut c = 1;
+    let mut class = 0;
+    let mut class_count = 0;
+    for ch in descriptor.chars() {
+        let position = INPUT_CHARSET.find(ch)? as u64;
+        c = polymod(c, position & 31);
+        class = class * 3 + (position >> 5);
+        class_count += 1;
+        if class_count == 3 {
+            c = polymod(c, class);
+            class = 0;
+            class_count = 0;
+        }
+    }
+    if class_count > 0 {
+        c = polymod(c, class);
+    }
+    for _ in 0..8 {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+    Some(
+        (0..8)
+            .map(|i| CHECKSUM_CHARSET[((c >> (5 * (7 - i))) & 31) as usize] as char)
+            .collect(),
+    )
+}
+
+/// The descriptor of a swapcoin's 2-of-2 multisig with the wallet's private key, and the other
+/// party's private key if it was handed over, with its checksum
+pub fn swapcoin_descriptor(
+    my_privkey: SecretKey,
+    other_pubkey: &PublicKey,
+    other_privkey: Option<SecretKey>,
+    network: Network,
+) -> String {
+    let wif = |key: SecretKey| {
+        PrivateKey {
+            compressed: true,
+            network,
+            key,
+        }
+        .to_wif()
+    };
+    let descriptor = format!(
+        "wsh(sortedmulti(2,{},{}))",
+        wif(my_privkey),
+        other_privkey.map_or(other_pubkey.to_string(), wif)
+    );
+    let checksum = descriptor_checksum(&descriptor).unwrap();
+    format!("{}#{}", descriptor, checksum)
+}
+
+/// Requests importing all the wallet's swapcoins into a Core wallet, which rescans from
+/// timestamp for their coins
+pub fn swapcoin_descriptor_imports(wallet: &Wallet, timestamp: i64) -> Vec<DescriptorImport> {
+    let label = wallet.get_core_wallet_label();
+    wallet
+        .get_swapcoin_multisig_keys()
+        .into_iter()
+        .map(
+            |(my_privkey, other_pubkey, other_privkey)| DescriptorImport {
+                desc: swapcoin_descriptor(my_privkey, &other_pubkey, other_privkey, wallet.network),
+                timestamp,
+                label: label.clone(),
+            },
+        )
+        .collect()
+}
+
+/// Time of the block at the wallet's birthday height, Core rescans from it for the coins of
+/// imported swapcoins. 0 rescans the whole blockchain if the wallet has no birthday
+pub fn wallet_birthday_timestamp(rpc: &Client, wallet: &Wallet) -> Result<i64, Error> {
+    match wallet.get_birthday_height() {
+        Some(height) => {
+            let block_hash = rpc.get_block_hash(height)?;
+            Ok(rpc.get_block_header_info(&block_hash)?.time as i64)
+        }
+        None => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::secp256k1::{self, Secp256k1};
+
+    #[test]
+    fn test_swapcoin_descriptors() {
+        //from bip380
+        assert_eq!(
+            descriptor_checksum("raw(deadbeef)"),
+            Some("89f8spxm".to_string())
+        );
+        assert_eq!(descriptor_checksum("raw(\u{e9})"), None);
+
+        let secp = Secp256k1::new();
+        let my_privkey = SecretKey::from_slice(&[1; 32]).unwrap();
+        let other_privkey = SecretKey::from_slice(&[2; 32]).unwrap();
+        let other_pubkey = PublicKey {
+            compressed: true,
+            key: secp256k1::PublicKey::from_secret_key(&secp, &other_privkey),
+        };
+        let partial = swapcoin_descriptor(my_privkey, &other_pubkey, None, Network::Regtest);
+        let complete = swapcoin_descriptor(
+            my_privkey,
+            &other_pubkey,
+            Some(other_privkey),
+            Network::Regtest,
+        );
+        let my_wif = PrivateKey {
+            compressed: true,
+            network: Network::Regtest,
+            key: my_privkey,
+        }
+        .to_wif();
+        assert!(partial.starts_with(&format!("wsh(sortedmulti(2,{},{}))#", my_wif, other_pubkey)));
+        assert!(complete.starts_with(&format!("wsh(sortedmulti(2,{},c", my_wif)));
+        let (descriptor, checksum) = complete.split_once('#').unwrap();
+        assert_eq!(descriptor_checksum(descriptor).unwrap(), checksum);
+    }
+}
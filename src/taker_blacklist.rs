@@ -0,0 +1,138 @@
+//makers refuse takers which keep misbehaving for a while, so a taker cant tie up a maker's
+// connections and liquidity by griefing it
+//takers are told apart by their ip address or i2p destination. takers connecting through
+// tor all come from the local tor daemon and cant be told apart, so they are never refused
+//the limits are in the [maker] section of teleport.conf, the blacklist isnt saved so a
+// restart of the maker forgets it
+
+use std::collections::HashMap;
+
+use crate::settings::MakerSettings;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehavior {
+    //disconnected after the hello without asking for anything
+    AbortedConnection,
+    //sent an invalid message, e.g. one with invalid signatures
+    ProtocolError,
+    //asked for an offer, only counts against the taker until it completes a coinswap
+    OfferRequest,
+}
+
+#[derive(Default)]
+struct PeerRecord {
+    //timestamps of the misbehaviors still inside the window
+    misbehaviors: Vec<(Misbehavior, i64)>,
+    banned_until: Option<i64>,
+}
+
+#[derive(Default)]
+pub struct TakerBlacklist {
+    peers: HashMap<String, PeerRecord>,
+}
+
+fn misbehavior_limit(misbehavior: Misbehavior, settings: &MakerSettings) -> u32 {
+    match misbehavior {
+        Misbehavior::AbortedConnection => settings.max_aborted_connections,
+        Misbehavior::ProtocolError => settings.max_protocol_errors,
+        Misbehavior::OfferRequest => settings.max_offer_requests,
+    }
+}
+
+impl TakerBlacklist {
+    pub fn is_banned(&self, fingerprint: &str, now: i64) -> bool {
+        self.peers
+            .get(fingerprint)
+            .and_then(|record| record.banned_until)
+            .is_some_and(|banned_until| banned_until > now)
+    }
+
+    /// Count a misbehavior of the taker, banning it once it is over the limit within the
+    /// window. Returns true if the taker was banned
+    pub fn record(
+        &mut self,
+        fingerprint: &str,
+        misbehavior: Misbehavior,
+        settings: &MakerSettings,
+        now: i64,
+    ) -> bool {
+        self.peers.retain(|_, record| {
+            record.banned_until.is_some_and(|t| t > now)
+                || record
+                    .misbehaviors
+                    .iter()
+                    .any(|(_, t)| t + settings.misbehavior_window_secs as i64 > now)
+        });
+        if settings.ban_duration_secs == 0 {
+            return false;
+        }
+        let record = self.peers.entry(fingerprint.to_string()).or_default();
+        record
+            .misbehaviors
+            .retain(|(_, t)| t + settings.misbehavior_window_secs as i64 > now);
+        record.misbehaviors.push((misbehavior, now));
+        let count = record
+            .misbehaviors
+            .iter()
+            .filter(|(m, _)| *m == misbehavior)
+            .count();
+        if count as u32 > misbehavior_limit(misbehavior, settings) {
+            record.banned_until = Some(now + settings.ban_duration_secs as i64);
+            record.misbehaviors.clear();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The taker completed a coinswap, so the offers it asked for werent probing
+    pub fn record_completed_swap(&mut self, fingerprint: &str) {
+        if let Some(record) = self.peers.get_mut(fingerprint) {
+            record
+                .misbehaviors
+                .retain(|(m, _)| *m != Misbehavior::OfferRequest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::settings::Settings;
+
+    #[test]
+    fn test_taker_blacklist() {
+        let mut settings = Settings::default().maker;
+        settings.max_protocol_errors = 2;
+        settings.max_offer_requests = 3;
+        settings.misbehavior_window_secs = 100;
+        settings.ban_duration_secs = 1000;
+        let mut blacklist = TakerBlacklist::default();
+
+        //errors which fall out of the window dont count
+        assert!(!blacklist.record("1.2.3.4", Misbehavior::ProtocolError, &settings, 0));
+        assert!(!blacklist.record("1.2.3.4", Misbehavior::ProtocolError, &settings, 50));
+        assert!(!blacklist.record("1.2.3.4", Misbehavior::ProtocolError, &settings, 120));
+        assert!(!blacklist.is_banned("1.2.3.4", 120));
+        assert!(blacklist.record("1.2.3.4", Misbehavior::ProtocolError, &settings, 130));
+        assert!(blacklist.is_banned("1.2.3.4", 130));
+        assert!(!blacklist.is_banned("5.6.7.8", 130));
+        assert!(!blacklist.is_banned("1.2.3.4", 1130));
+
+        //completing a coinswap forgives the offers asked for
+        for t in 0..3 {
+            assert!(!blacklist.record("5.6.7.8", Misbehavior::OfferRequest, &settings, t));
+        }
+        blacklist.record_completed_swap("5.6.7.8");
+        for t in 3..6 {
+            assert!(!blacklist.record("5.6.7.8", Misbehavior::OfferRequest, &settings, t));
+        }
+        assert!(blacklist.record("5.6.7.8", Misbehavior::OfferRequest, &settings, 6));
+
+        settings.ban_duration_secs = 0;
+        for t in 0..10 {
+            assert!(!blacklist.record("9.9.9.9", Misbehavior::ProtocolError, &settings, t));
+        }
+    }
+}
@@ -1,66 +1,84 @@
 use std::collections::{HashMap, HashSet};
 use std::io::ErrorKind;
-use std::iter::once;
-use std::time::Duration;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
 
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::tcp::{ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
 use tokio::select;
+use tokio::sync::mpsc;
 use tokio::time::sleep;
 
 use tokio_socks::tcp::Socks5Stream;
 
+use tracing::Instrument;
+
 use bitcoin::consensus::encode::deserialize;
 use bitcoin::hashes::hash160::Hash as Hash160;
 use bitcoin::hashes::{hex::ToHex, Hash};
 use bitcoin::secp256k1::{SecretKey, Signature};
 use bitcoin::util::ecdsa::PublicKey;
 use bitcoin::{BlockHash, OutPoint, Script, Transaction, Txid};
-use bitcoincore_rpc::{Client, RpcApi};
+use bitcoincore_rpc::RpcApi;
+
+use serde::Serialize;
 
 use rand::rngs::OsRng;
-use rand::RngCore;
+use rand::{CryptoRng, RngCore};
 
 use itertools::izip;
 
+use crate::audit_log::{record_message, AuditLog, Direction};
 use crate::contracts;
 use crate::contracts::SwapCoin;
 use crate::contracts::{
-    calculate_coinswap_fee, create_contract_redeemscript, create_receivers_contract_tx,
-    find_funding_output, read_pubkeys_from_multisig_redeemscript, sign_contract_tx,
-    validate_contract_tx, WatchOnlySwapCoin, MAKER_FUNDING_TX_VBYTE_SIZE,
+    apply_fee_multiplier, calculate_coinswap_fee, calculate_maximum_locktime,
+    calculate_required_confirms, check_fee_rate, check_funding_tx_conflicts,
+    create_contract_redeemscript, create_receivers_contract_tx, find_funding_output,
+    read_pubkeys_from_multisig_redeemscript, sign_contract_tx, validate_contract_tx,
+    verify_all_parallel, WatchOnlySwapCoin, MAKER_FUNDING_TX_VBYTE_SIZE,
 };
 use crate::error::Error;
+use crate::fidelity_bonds::weighted_bond_order;
+use crate::funding_tx::randomize_amount;
+use crate::hooks::{run_hook, HookEvent};
+use crate::i2p;
+use crate::locktime_policy::{recovery_timeline, HopTimeline, LocktimePolicy};
+use crate::maker_banlist::ban_maker;
+use crate::maker_connections::MakerConnections;
+use crate::maker_identity::{
+    check_maker_identity, identity_challenge, verify_identity_proof, PinResult,
+};
+use crate::maker_uptime::{load_maker_uptime, record_maker_connection, MakerUptime};
 use crate::messages::{
-    ConfirmedCoinSwapTxInfo, HashPreimage, MakerToTakerMessage, NextCoinSwapTxInfo, Preimage,
-    PrivateKeyHandover, ProofOfFunding, ReceiversContractTxInfo, SenderContractTxNoncesInfo,
-    SendersAndReceiversContractSigs, SignReceiversContractTx, SignSendersAndReceiversContractTxes,
+    ConfirmedCoinSwapTxInfo, HashPreimage, IdentityProof, MakerHello, MakerToTakerMessage,
+    NextCoinSwapTxInfo, Offer, Preimage, PrivateKeyHandover, ProofOfFunding, ReceiversContractSig,
+    ReceiversContractTxInfo, SenderContractTxNoncesInfo, SendersAndReceiversContractSigs,
+    SendersContractSig, SessionId, SignReceiversContractTx, SignSendersAndReceiversContractTxes,
     SignSendersContractTx, SwapCoinPrivateKey, TakerHello, TakerToMakerMessage, PREIMAGE_LEN,
 };
 
-use crate::offerbook_sync::{sync_offerbook, MakerAddress, OfferAndAddress};
-use crate::wallet_sync::{
-    generate_keypair, import_watchonly_redeemscript, IncomingSwapCoin, OutgoingSwapCoin, Wallet,
+use crate::offerbook_sync::{
+    download_maker_offer_attempt_once, get_advertised_maker_addresses,
+    stream_offerbook_with_addresses, MakerAddress, OfferAndAddress,
 };
-
-use crate::watchtower_protocol::{
-    check_for_broadcasted_contract_txes, ContractTransaction, ContractsInfo,
+use crate::protocol_phase::expect_message;
+use crate::route_filter::{route_filters, RouteCandidates};
+use crate::rpc::Client;
+use crate::settings::{Settings, TakerSettings};
+use crate::swap_monitor::{recovery_decision, RecoveryDecision, SwapAlarm, SwapMonitor};
+use crate::swap_receipt::{
+    swap_receipt_path, write_swap_receipt, ReceiptMaker, SwapReceipt, SwapReceiptContents,
+};
+use crate::wallet_accounts::WalletAccount;
+use crate::wallet_sync::{
+    generate_keypair, import_watchonly_redeemscript, is_block_in_best_chain, IncomingSwapCoin,
+    OutgoingSwapCoin, Wallet, WalletSwapCoin,
 };
 
-//relatively low value for now so that its easier to test without having to wait too much
-//right now only the very brave will try coinswap out on mainnet with non-trivial amounts
-pub const REFUND_LOCKTIME: u16 = 48; //in blocks
-pub const REFUND_LOCKTIME_STEP: u16 = 48; //in blocks
-
-//first connect means the first time you're ever connecting, without having gotten any txes
-// confirmed yet, so the taker will not be very persistent since there should be plenty of other
-// makers out there
-//but also it should allow for flaky connections, otherwise you exclude raspberry pi nodes running
-// in people's closets, which are very important for decentralization
-pub const FIRST_CONNECT_ATTEMPTS: u32 = 5;
-pub const FIRST_CONNECT_SLEEP_DELAY_SEC: u64 = 1;
-pub const FIRST_CONNECT_ATTEMPT_TIMEOUT_SEC: u64 = 20;
+//sats the amount a maker sends on may differ from what its offer's fees give
+const FEE_MISMATCH_TOLERANCE_SAT: u64 = 10;
 
 //reconnect means when connecting to a maker again after having already gotten txes confirmed
 // as it would be a waste of miner fees to give up, the taker is coded to be very persistent
@@ -72,7 +90,11 @@ const RECONNECT_ATTEMPTS: u32 = 3200;
 const RECONNECT_SHORT_SLEEP_DELAY_SEC: u64 = 10;
 const RECONNECT_LONG_SLEEP_DELAY_SEC: u64 = 60;
 const SHORT_LONG_SLEEP_DELAY_TRANSITION: u32 = 60; //after this many attempts, switch to sleeping longer
-const RECONNECT_ATTEMPT_TIMEOUT_SEC: u64 = 60 * 5;
+
+//the route is chosen once this many usable offers per maker in the route have arrived, so
+// the taker doesnt wait for the slowest makers to time out but still has spares to try if
+// some makers in the route fail
+const USABLE_OFFERS_PER_ROUTE_MAKER: usize = 3;
 
 #[derive(Debug, Clone, Copy)]
 pub struct TakerConfig {
@@ -81,24 +103,200 @@ pub struct TakerConfig {
     pub tx_count: u32,
     pub required_confirms: i32,
     pub fee_rate: u64,
+    pub min_funding_tx_fee_rate: u64,
+    //the account the coinswap is funded from, None for the one with the biggest balance
+    pub account: Option<WalletAccount>,
+}
+
+//progress of a coinswap, sent out so that GUIs and scripts can display it
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TakerEvent {
+    OfferReceived {
+        maker_address: String,
+    },
+    OfferbookSynced {
+        offer_count: usize,
+    },
+    MakerChosen {
+        maker_address: String,
+    },
+    //sent before the taker's funding txes are broadcast
+    RecoveryTimeline {
+        hops: Vec<HopTimeline>,
+    },
+    FundingTxesBroadcast {
+        txids: Vec<Txid>,
+    },
+    FundingTxConfirmations {
+        txid: Txid,
+        confirmations: i32,
+        required_confirmations: i32,
+    },
+    HashPreimageRevealed,
+    PrivateKeysHandedOver {
+        maker_address: String,
+    },
+    CoinswapComplete,
+}
+
+//first connect means the first time you're ever connecting, without having gotten any txes
+// confirmed yet, so the taker will not be very persistent since there should be plenty of other
+// makers out there
+//but also it should allow for flaky connections, otherwise you exclude raspberry pi nodes running
+// in people's closets, which are very important for decentralization
+//the attempts and timeouts are in the [taker] section of teleport.conf
+pub(crate) fn taker_settings() -> TakerSettings {
+    if Settings::is_initialized() {
+        Settings::global().taker.clone()
+    } else {
+        Settings::default().taker
+    }
+}
+
+//taker.min_funding_tx_fee_rate in sats per 1000 vbytes, like the fee rates of TakerConfig
+pub(crate) fn min_funding_tx_fee_rate() -> u64 {
+    taker_settings().min_funding_tx_fee_rate * 1000
+}
+
+fn send_progress(progress_tx: &Option<Sender<TakerEvent>>, event: TakerEvent) {
+    tracing::debug!("progress event = {:?}", event);
+    if let Some(tx) = progress_tx {
+        //nobody listening anymore is no reason to stop the coinswap
+        let _ = tx.send(event);
+    }
 }
 
 #[tokio::main]
-pub async fn start_taker(rpc: &Client, wallet: &mut Wallet, config: TakerConfig) {
-    match run(rpc, wallet, config).await {
+pub async fn start_taker(
+    rpc: &Client,
+    wallet: &mut Wallet,
+    config: TakerConfig,
+    progress_tx: Option<Sender<TakerEvent>>,
+) {
+    match run(rpc, wallet, config, &[], &progress_tx).await {
         Ok(_o) => (),
-        Err(e) => log::error!("err {:?}", e),
+        Err(e) => tracing::error!("err {:?}", e),
     };
 }
 
-async fn run(rpc: &Client, wallet: &mut Wallet, config: TakerConfig) -> Result<(), Error> {
-    let offers_addresses = sync_offerbook(wallet.network)
+/// Like start_taker, but makers in avoided_makers are only used if there arent enough others,
+/// so that the coinswaps of a tumble go through different makers
+#[tokio::main]
+pub async fn start_taker_avoiding_makers(
+    rpc: &Client,
+    wallet: &mut Wallet,
+    config: TakerConfig,
+    avoided_makers: &[String],
+    progress_tx: Option<Sender<TakerEvent>>,
+) -> Result<(), Error> {
+    run(rpc, wallet, config, avoided_makers, &progress_tx).await
+}
+
+async fn run(
+    rpc: &Client,
+    wallet: &mut Wallet,
+    config: TakerConfig,
+    avoided_makers: &[String],
+    progress_tx: &Option<Sender<TakerEvent>>,
+) -> Result<(), Error> {
+    let config = TakerConfig {
+        send_amount: randomize_amount(
+            config.send_amount,
+            taker_settings().amount_randomization_ppb,
+            &mut OsRng,
+        ),
+        ..config
+    };
+    tracing::info!("Coinswap amount = {} sat", config.send_amount);
+    let maker_addresses = get_advertised_maker_addresses(wallet.network)
         .await
         .expect("unable to sync maker addresses from directory servers");
-    log::info!("<=== Got Offers ({} offers)", offers_addresses.len());
-    log::debug!("Offers : {:#?}", offers_addresses);
-    send_coinswap(rpc, wallet, config, &offers_addresses).await?;
-    Ok(())
+    let offers_addresses = receive_offers(
+        stream_offerbook_with_addresses(maker_addresses, &taker_settings()),
+        &config,
+        progress_tx,
+    )
+    .await;
+    tracing::info!("<=== Got Offers ({} offers)", offers_addresses.len());
+    tracing::debug!("Offers : {:#?}", offers_addresses);
+    send_progress(
+        progress_tx,
+        TakerEvent::OfferbookSynced {
+            offer_count: offers_addresses.len(),
+        },
+    );
+
+    let preimage = generate_preimage(&mut OsRng);
+    //the hashvalue is shared by every hop of the coinswap so makers and watchtowers log
+    // the same swap id
+    let swap_id = Hash160::hash(&preimage);
+    let swap_span = tracing::info_span!("swap", swap_id = %swap_id);
+    run_hook(
+        "taker",
+        HookEvent::SwapStart {
+            swap_id,
+            amount_sat: config.send_amount,
+        },
+    )
+    .await;
+    let result = send_coinswap(
+        rpc,
+        wallet,
+        config,
+        &offers_addresses,
+        avoided_makers,
+        preimage,
+        progress_tx,
+    )
+    .instrument(swap_span)
+    .await;
+    run_hook(
+        "taker",
+        match &result {
+            Ok(()) => HookEvent::SwapSuccess { swap_id },
+            Err(e) => HookEvent::SwapFailure {
+                swap_id,
+                error: format!("{:?}", e),
+            },
+        },
+    )
+    .await;
+    result
+}
+
+fn is_offer_usable(offer_address: &OfferAndAddress, amount: u64) -> bool {
+    amount >= offer_address.offer.min_size && amount <= offer_address.offer.max_size
+}
+
+//returns once every maker responded, or once there are enough usable offers to choose a route
+async fn receive_offers(
+    mut offers_reader: mpsc::Receiver<OfferAndAddress>,
+    config: &TakerConfig,
+    progress_tx: &Option<Sender<TakerEvent>>,
+) -> Vec<OfferAndAddress> {
+    let enough_offers = config.maker_count as usize * USABLE_OFFERS_PER_ROUTE_MAKER;
+    let mut offers_addresses = Vec::<OfferAndAddress>::new();
+    while let Some(offer_address) = offers_reader.recv().await {
+        tracing::debug!("offer received from {}", offer_address.address);
+        send_progress(
+            progress_tx,
+            TakerEvent::OfferReceived {
+                maker_address: offer_address.address.to_string(),
+            },
+        );
+        offers_addresses.push(offer_address);
+        if offers_addresses
+            .iter()
+            .filter(|o| is_offer_usable(o, config.send_amount))
+            .count()
+            >= enough_offers
+        {
+            tracing::debug!("enough offers to choose a route, not waiting for the rest");
+            break;
+        }
+    }
+    offers_addresses
 }
 
 async fn send_coinswap(
@@ -106,16 +304,36 @@ async fn send_coinswap(
     wallet: &mut Wallet,
     config: TakerConfig,
     all_maker_offers_addresses: &[OfferAndAddress],
+    avoided_makers: &[String],
+    preimage: Preimage,
+    progress_tx: &Option<Sender<TakerEvent>>,
 ) -> Result<(), Error> {
-    let mut preimage = [0u8; PREIMAGE_LEN];
-    OsRng.fill_bytes(&mut preimage);
     let hashvalue = Hash160::hash(&preimage);
+    let audit_log = wallet.audit_log();
+    let transcript_start_hash = audit_log.as_ref().map(|log| log.last_entry_hash());
 
-    let first_swap_locktime = REFUND_LOCKTIME + REFUND_LOCKTIME_STEP * config.maker_count;
+    let hop_locktimes =
+        LocktimePolicy::from_settings(&taker_settings()).hop_locktimes(config.maker_count)?;
+    let first_swap_locktime = hop_locktimes[0];
 
     let mut maker_offers_addresses = all_maker_offers_addresses
         .iter()
         .collect::<Vec<&OfferAndAddress>>();
+    order_by_fidelity_bond_value(rpc, &mut maker_offers_addresses);
+    deprioritize_makers(
+        &mut maker_offers_addresses,
+        &load_maker_uptime(),
+        avoided_makers,
+    );
+    let filters = route_filters(
+        rpc,
+        &maker_offers_addresses,
+        &taker_settings(),
+        &hop_locktimes,
+        config.send_amount,
+    );
+    let mut maker_offers_addresses = RouteCandidates::new(maker_offers_addresses, filters);
+    let mut connections = MakerConnections::new(audit_log.clone());
 
     let (
         first_maker,
@@ -123,10 +341,12 @@ async fn send_coinswap(
         mut this_maker_hashlock_privkeys,
         my_funding_txes,
         mut outgoing_swapcoins,
+        my_total_miner_fee,
         first_maker_senders_contract_sigs,
     ) = loop {
         //loop to help error handling, loop ends if we run out of makers to try
-        let first_maker = choose_next_maker(&mut maker_offers_addresses, config.send_amount)
+        let first_maker = choose_next_valid_maker(&mut maker_offers_addresses, config.send_amount)
+            .await
             .expect("not enough offers");
         let (
             first_maker_multisig_pubkeys,
@@ -136,8 +356,9 @@ async fn send_coinswap(
         ) = generate_maker_multisig_and_hashlock_keys(
             &first_maker.offer.tweakable_point,
             config.tx_count,
+            &mut OsRng,
         );
-        let (my_funding_txes, outgoing_swapcoins, _my_total_miner_fee) = wallet
+        let (my_funding_txes, outgoing_swapcoins, my_total_miner_fee) = wallet
             .initalize_coinswap(
                 rpc,
                 config.send_amount,
@@ -146,9 +367,13 @@ async fn send_coinswap(
                 hashvalue,
                 first_swap_locktime,
                 config.fee_rate,
+                taker_settings().change_avoidance_tolerance_ppb,
+                None,
+                config.account,
             )
             .unwrap();
         let first_maker_senders_contract_sigs = match request_senders_contract_tx_signatures(
+            &mut connections,
             &first_maker.address,
             &outgoing_swapcoins,
             &this_maker_multisig_privkeys,
@@ -159,11 +384,12 @@ async fn send_coinswap(
         {
             Ok(r) => r,
             Err(e) => {
-                log::debug!(
+                tracing::debug!(
                     "Failed to obtain senders contract tx signature from first_maker {}: {:?}",
                     first_maker.address,
                     e
                 );
+                record_maker_connection(&first_maker.address.to_string(), false);
                 continue; //go back to the start and try another maker
             }
         };
@@ -173,9 +399,16 @@ async fn send_coinswap(
             this_maker_hashlock_privkeys,
             my_funding_txes,
             outgoing_swapcoins,
+            my_total_miner_fee,
             first_maker_senders_contract_sigs,
         );
     };
+    send_progress(
+        progress_tx,
+        TakerEvent::MakerChosen {
+            maker_address: first_maker.address.to_string(),
+        },
+    );
     first_maker_senders_contract_sigs
         .iter()
         .zip(outgoing_swapcoins.iter_mut())
@@ -185,28 +418,58 @@ async fn send_coinswap(
     }
     wallet.update_swapcoins_list().unwrap();
 
-    log::debug!("My Funding Tx:  {:#?}", my_funding_txes);
-    log::debug!("Outgoing SwapCoins: {:#?}", outgoing_swapcoins);
+    let timeline = recovery_timeline(&hop_locktimes);
+    for hop in &timeline {
+        tracing::info!(
+            "hop {} locktime={} blocks, refundable about {} hours after its funding confirms",
+            hop.hop,
+            hop.locktime,
+            hop.estimated_secs / 3600
+        );
+    }
+    send_progress(progress_tx, TakerEvent::RecoveryTimeline { hops: timeline });
+
+    tracing::debug!("My Funding Tx:  {:#?}", my_funding_txes);
+    tracing::debug!("Outgoing SwapCoins: {:#?}", outgoing_swapcoins);
     for my_funding_tx in my_funding_txes.iter() {
         let txid = rpc.send_raw_transaction(my_funding_tx)?;
-        log::info!("Broadcasting My Funding Tx: {}", txid);
+        tracing::info!("Broadcasting My Funding Tx: {}", txid);
         assert_eq!(txid, my_funding_tx.txid());
     }
+    send_progress(
+        progress_tx,
+        TakerEvent::FundingTxesBroadcast {
+            txids: my_funding_txes.iter().map(|tx| tx.txid()).collect(),
+        },
+    );
+    let mut monitor = SwapMonitor::new();
     let (mut funding_txes, mut funding_tx_merkleproofs) = wait_for_funding_tx_confirmation(
         rpc,
         &my_funding_txes
             .iter()
             .map(|tx| tx.txid())
             .collect::<Vec<Txid>>(),
-        first_maker.offer.required_confirms,
-        &[],
-        &mut None,
+        calculate_required_confirms(
+            first_maker.offer.required_confirms,
+            &first_maker.offer.required_confirms_tiers,
+            config.send_amount,
+        ),
+        0, //our own funding txes, no need to check their fee rate
+        &mut monitor,
+        progress_tx,
     )
     .await?
     .unwrap();
-    //unwrap the option without checking for Option::None because we passed no contract txes
-    //to watch and therefore they cant be broadcast
+    //unwrap the option without checking for Option::None because the monitor watches no
+    //contract txes yet and therefore they cant be broadcast
+    monitor.watch_hop(
+        outgoing_swapcoins
+            .iter()
+            .map(|osc| osc.contract_tx.clone())
+            .collect::<Vec<Transaction>>(),
+    );
 
+    let mut active_makers = Vec::<&OfferAndAddress>::new();
     let mut active_maker_addresses = Vec::<&MakerAddress>::new();
     let mut next_maker = first_maker;
     let mut previous_maker: Option<&OfferAndAddress> = None;
@@ -214,14 +477,11 @@ async fn send_coinswap(
     let mut watchonly_swapcoins = Vec::<Vec<WatchOnlySwapCoin>>::new();
     let mut incoming_swapcoins = Vec::<IncomingSwapCoin>::new();
 
-    let mut last_checked_block_height: Option<u64> = None;
-
     for maker_index in 0..config.maker_count {
         let is_taker_next_peer = maker_index == config.maker_count - 1;
         let is_taker_previous_peer = maker_index == 0;
 
-        let maker_refund_locktime =
-            REFUND_LOCKTIME + REFUND_LOCKTIME_STEP * (config.maker_count - maker_index - 1);
+        let maker_refund_locktime = hop_locktimes[maker_index as usize + 1];
         let (
             this_maker_multisig_redeemscripts,
             this_maker_contract_redeemscripts,
@@ -242,6 +502,7 @@ async fn send_coinswap(
             found_next_maker,
         ) = exchange_signatures_and_find_next_maker(
             rpc,
+            &mut connections,
             &config,
             &mut maker_offers_addresses,
             this_maker,
@@ -262,7 +523,24 @@ async fn send_coinswap(
         )
         .await?;
         next_maker = found_next_maker;
+        active_makers.push(this_maker);
         active_maker_addresses.push(&this_maker.address);
+        if !is_taker_next_peer {
+            send_progress(
+                progress_tx,
+                TakerEvent::MakerChosen {
+                    maker_address: next_maker.address.to_string(),
+                },
+            );
+            monitor.watch_hop(
+                watchonly_swapcoins
+                    .last()
+                    .unwrap()
+                    .iter()
+                    .map(|watchonly_swapcoin| watchonly_swapcoin.contract_tx.clone())
+                    .collect::<Vec<Transaction>>(),
+            );
+        }
 
         let wait_for_confirm_result = wait_for_funding_tx_confirmation(
             rpc,
@@ -278,33 +556,31 @@ async fn send_coinswap(
             if is_taker_next_peer {
                 config.required_confirms
             } else {
-                next_maker.offer.required_confirms
-            },
-            &watchonly_swapcoins
-                .iter()
-                .map(|watchonly_swapcoin_list| {
-                    watchonly_swapcoin_list
+                calculate_required_confirms(
+                    next_maker.offer.required_confirms,
+                    &next_maker.offer.required_confirms_tiers,
+                    maker_sign_sender_and_receiver_contracts
+                        .senders_contract_txes_info
                         .iter()
-                        .map(|watchonly_swapcoin| watchonly_swapcoin.contract_tx.clone())
-                        .collect::<Vec<Transaction>>()
-                })
-                .chain(once(
-                    outgoing_swapcoins
-                        .iter()
-                        .map(|osc| osc.contract_tx.clone())
-                        .collect::<Vec<Transaction>>(),
-                ))
-                .collect::<Vec<Vec<Transaction>>>(),
-            &mut last_checked_block_height,
+                        .map(|senders_contract_tx_info| senders_contract_tx_info.funding_amount)
+                        .sum::<u64>(),
+                )
+            },
+            config.min_funding_tx_fee_rate,
+            &mut monitor,
+            progress_tx,
         )
         .await?;
         if wait_for_confirm_result.is_none() {
-            log::info!(concat!(
+            tracing::info!(concat!(
                 "Somebody deviated from the protocol by broadcasting one or more contract",
-                " transactions! Use main method `recover-from-incomplete-coinswap` to recover",
-                " coins"
+                " transactions! Broadcasting our own contract transactions, the coins come back",
+                " to the wallet after the timelock"
+            ));
+            broadcast_outgoing_contracts(rpc, wallet, &outgoing_swapcoins);
+            return Err(Error::Protocol(
+                "contract transactions broadcast, coinswap aborted",
             ));
-            panic!("ending");
         }
         let (next_funding_txes, next_funding_tx_merkleproofs) = wait_for_confirm_result.unwrap();
         funding_txes = next_funding_txes;
@@ -333,11 +609,12 @@ async fn send_coinswap(
     }
 
     let last_maker = previous_maker.unwrap();
-    log::info!(
+    tracing::info!(
         "===> Sending SignReceiversContractTx to {}",
         last_maker.address
     );
     let last_receiver_contract_sig = request_receivers_contract_tx_signatures(
+        &mut connections,
         &last_maker.address,
         &incoming_swapcoins,
         &incoming_swapcoins
@@ -358,17 +635,19 @@ async fn send_coinswap(
     wallet.update_swapcoins_list().unwrap();
 
     settle_all_coinswaps_send_hash_preimage_and_privkeys(
+        &mut connections,
         &config,
         preimage,
         &active_maker_addresses,
         &outgoing_swapcoins,
         &mut watchonly_swapcoins,
         &mut incoming_swapcoins,
+        progress_tx,
     )
     .await?;
 
     for (index, watchonly_swapcoin) in watchonly_swapcoins.iter().enumerate() {
-        log::debug!(
+        tracing::debug!(
             "maker[{}] funding txes = {:#?}",
             index,
             watchonly_swapcoin
@@ -377,7 +656,7 @@ async fn send_coinswap(
                 .collect::<Vec<_>>()
         );
     }
-    log::debug!(
+    tracing::debug!(
         "my incoming txes = {:#?}",
         incoming_swapcoins
             .iter()
@@ -394,38 +673,198 @@ async fn send_coinswap(
     }
     wallet.update_swapcoins_list().unwrap();
 
-    log::info!("Successfully Completed Coinswap");
+    tracing::info!("Successfully Completed Coinswap");
+    //the coinswap is done even if the receipt cant be written
+    let sent_amount = outgoing_swapcoins
+        .iter()
+        .map(|s| s.funding_amount)
+        .sum::<u64>();
+    let received_amount = incoming_swapcoins
+        .iter()
+        .map(|s| s.funding_amount)
+        .sum::<u64>();
+    let receipt_contents = SwapReceiptContents {
+        swap_id: hashvalue,
+        completed_timestamp: chrono::Utc::now().timestamp(),
+        sent_amount_sat: sent_amount,
+        received_amount_sat: received_amount,
+        coinswap_fee_sat: sent_amount.saturating_sub(received_amount),
+        miner_fee_sat: my_total_miner_fee,
+        funding_txids: my_funding_txes.iter().map(|tx| tx.txid()).collect(),
+        settlement_txids: incoming_swapcoins
+            .iter()
+            .map(|s| s.contract_tx.input[0].previous_output.txid)
+            .collect(),
+        makers: active_makers
+            .iter()
+            .map(|m| ReceiptMaker {
+                address: m.address.to_string(),
+                identity_pubkey: m.offer.tweakable_point,
+            })
+            .collect(),
+        transcript_start_hash,
+        transcript_end_hash: audit_log.as_ref().map(|log| log.last_entry_hash()),
+    };
+    let receipt_path = swap_receipt_path(hashvalue);
+    match SwapReceipt::sign(receipt_contents, &wallet.get_tweakable_keypair().0)
+        .and_then(|receipt| write_swap_receipt(&receipt, &receipt_path))
+    {
+        Ok(()) => tracing::info!("swap receipt written to {}", receipt_path.display()),
+        Err(e) => tracing::warn!("unable to write swap receipt: {:?}", e),
+    }
+    send_progress(progress_tx, TakerEvent::CoinswapComplete);
     Ok(())
 }
 
 fn choose_next_maker<'a>(
-    maker_offers_addresses: &mut Vec<&'a OfferAndAddress>,
+    maker_offers_addresses: &mut RouteCandidates<'a>,
     amount: u64,
 ) -> Option<&'a OfferAndAddress> {
     loop {
         let m = maker_offers_addresses.pop()?;
-        if amount < m.offer.min_size || amount > m.offer.max_size {
-            log::debug!("amount out of range for maker = {:?}", m);
+        if !is_offer_usable(m, amount) {
+            tracing::debug!("amount out of range for maker = {:?}", m);
             continue;
         }
-        log::debug!("next maker = {:?}", m);
+        tracing::debug!("next maker = {:?}", m);
         break Some(m);
     }
 }
 
-pub async fn send_message(
-    socket_writer: &mut WriteHalf<'_>,
-    message: TakerToMakerMessage,
+//makers are chosen at random with a probability proportional to their fidelity bond value, so
+// an attacker needs valuable bonds to be in many routes. deprioritize_makers sorts stably so
+// this order is kept among the makers it doesnt move
+fn order_by_fidelity_bond_value(rpc: &Client, maker_offers_addresses: &mut Vec<&OfferAndAddress>) {
+    let bond_values = maker_offers_addresses
+        .iter()
+        .map(|m| m.fidelity_bond_value(rpc).unwrap_or(0.0))
+        .collect::<Vec<f64>>();
+    *maker_offers_addresses = weighted_bond_order(&bond_values, &mut rand::thread_rng())
+        .into_iter()
+        .map(|i| maker_offers_addresses[i])
+        .collect();
+}
+
+//choose_next_maker takes makers from the back, so avoided makers and makers which often
+// failed to answer are moved to the front to be tried after all the others
+fn deprioritize_makers(
+    maker_offers_addresses: &mut [&OfferAndAddress],
+    uptime: &MakerUptime,
+    avoided_makers: &[String],
+) {
+    maker_offers_addresses.sort_by_key(|o| {
+        let address = o.address.to_string();
+        (
+            !avoided_makers.contains(&address),
+            !uptime.is_flaky(&address),
+        )
+    });
+}
+
+//like choose_next_maker, but the offer is fetched again first because it may have changed
+// since the offerbook sync, makers whose offer changed for the worse are skipped
+async fn choose_next_valid_maker<'a>(
+    maker_offers_addresses: &mut RouteCandidates<'a>,
+    amount: u64,
+) -> Option<&'a OfferAndAddress> {
+    loop {
+        let m = choose_next_maker(maker_offers_addresses, amount)?;
+        match revalidate_offer(m, amount).await {
+            Ok(()) => {
+                maker_offers_addresses.choose(m);
+                return Some(m);
+            }
+            Err(e) => tracing::warn!("not using maker {}, error={:?}", m.address, e),
+        }
+    }
+}
+
+async fn revalidate_offer(offer_address: &OfferAndAddress, amount: u64) -> Result<(), Error> {
+    let timeout_sec = taker_settings().first_connect_attempt_timeout_sec;
+    let current_offer = select! {
+        ret = download_maker_offer_attempt_once(&offer_address.address) => ret,
+        _ = sleep(Duration::from_secs(timeout_sec)) => {
+            Err(Error::Protocol("timed out fetching offer again"))
+        },
+    };
+    record_maker_connection(&offer_address.address.to_string(), current_offer.is_ok());
+    let current_offer = current_offer?;
+    check_offer_still_valid(&offer_address.offer, &current_offer, amount)
+}
+
+//max_size follows the maker's balance so it changes all the time, only whether the amount
+// is still accepted matters
+fn check_offer_still_valid(offer: &Offer, current_offer: &Offer, amount: u64) -> Result<(), Error> {
+    if current_offer.tweakable_point != offer.tweakable_point {
+        return Err(Error::Protocol("maker tweakable point changed"));
+    }
+    if current_offer.absolute_fee_sat > offer.absolute_fee_sat
+        || current_offer.amount_relative_fee_ppb > offer.amount_relative_fee_ppb
+        || current_offer.time_relative_fee_ppb > offer.time_relative_fee_ppb
+    {
+        return Err(Error::Protocol(
+            "maker fees went up since the offerbook sync",
+        ));
+    }
+    if amount < current_offer.min_size || amount > current_offer.max_size {
+        return Err(Error::Protocol(
+            "maker no longer accepts the coinswap amount",
+        ));
+    }
+    let required_confirms = |o: &Offer| {
+        calculate_required_confirms(o.required_confirms, &o.required_confirms_tiers, amount)
+    };
+    let maximum_locktime = |o: &Offer| {
+        calculate_maximum_locktime(o.maximum_locktime, &o.required_confirms_tiers, amount)
+            .unwrap_or(u16::MAX)
+    };
+    if apply_fee_multiplier(100, &current_offer.required_confirms_tiers, amount)
+        > apply_fee_multiplier(100, &offer.required_confirms_tiers, amount)
+    {
+        return Err(Error::Protocol(
+            "maker fees went up since the offerbook sync",
+        ));
+    }
+    if current_offer.minimum_locktime > offer.minimum_locktime
+        || maximum_locktime(current_offer) < maximum_locktime(offer)
+        || current_offer.minimum_contract_react_time.unwrap_or(0)
+            > offer.minimum_contract_react_time.unwrap_or(0)
+        || required_confirms(current_offer) > required_confirms(offer)
+    {
+        return Err(Error::Protocol(
+            "maker limits changed since the offerbook sync",
+        ));
+    }
+    Ok(())
+}
+
+//messages are recorded in audit_log with the maker's identity key, if its hello had one
+pub async fn send_message<W: AsyncWrite + Unpin>(
+    socket_writer: &mut W,
+    maker_address: &MakerAddress,
+    audit_log: Option<&AuditLog>,
+    maker_identity: Option<&PublicKey>,
+    message: &TakerToMakerMessage,
 ) -> Result<(), Error> {
-    log::debug!("==> {:#?}", message);
-    let mut result_bytes = serde_json::to_vec(&message).map_err(std::io::Error::from)?;
+    tracing::debug!("==> {:#?}", message);
+    let mut result_bytes = serde_json::to_vec(message).map_err(std::io::Error::from)?;
+    record_message(
+        audit_log,
+        &maker_address.to_string(),
+        maker_identity,
+        Direction::Sent,
+        &result_bytes,
+    );
     result_bytes.push(b'\n');
     socket_writer.write_all(&result_bytes).await?;
     Ok(())
 }
 
-pub async fn read_message(
-    reader: &mut BufReader<ReadHalf<'_>>,
+pub async fn read_message<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    maker_address: &MakerAddress,
+    audit_log: Option<&AuditLog>,
+    maker_identity: Option<&PublicKey>,
 ) -> Result<MakerToTakerMessage, Error> {
     let mut line = String::new();
     let n = reader.read_line(&mut line).await?;
@@ -435,47 +874,134 @@ pub async fn read_message(
             "EOF",
         ))));
     }
+    record_message(
+        audit_log,
+        &maker_address.to_string(),
+        maker_identity,
+        Direction::Received,
+        line.trim_end().as_bytes(),
+    );
     let message: MakerToTakerMessage = match serde_json::from_str(&line) {
         Ok(r) => r,
         Err(_e) => return Err(Error::Protocol("json parsing error")),
     };
-    log::debug!("<== {:#?}", message);
+    tracing::debug!("<== {:#?}", message);
     Ok(message)
 }
 
-pub async fn handshake_maker<'a>(
-    socket: &'a mut TcpStream,
+//goes through the proxy to the maker, if its address needs one
+pub(crate) async fn connect_to_maker(
+    socket: &mut TcpStream,
     maker_address: &MakerAddress,
-) -> Result<(BufReader<ReadHalf<'a>>, WriteHalf<'a>), Error> {
-    let socket = match maker_address {
-        MakerAddress::Clearnet { address: _ } => socket,
-        MakerAddress::Tor { address } => Socks5Stream::connect_with_socket(socket, address.clone())
-            .await?
-            .into_inner(),
-    };
-    let (reader, mut socket_writer) = socket.split();
-    let mut socket_reader = BufReader::new(reader);
+) -> Result<(), Error> {
+    match maker_address {
+        MakerAddress::Clearnet { address: _ } => {}
+        MakerAddress::Tor { address } => {
+            Socks5Stream::connect_with_socket(&mut *socket, address.clone()).await?;
+        }
+        MakerAddress::I2p { address } => i2p::connect(socket, address).await?,
+    }
+    Ok(())
+}
+
+//the maker says hello first, with the session it offers the connection
+pub(crate) async fn read_maker_hello<R: AsyncBufRead + Unpin>(
+    socket_reader: &mut R,
+    maker_address: &MakerAddress,
+    audit_log: Option<&AuditLog>,
+) -> Result<MakerHello, Error> {
+    let makerhello: MakerHello =
+        expect_message(read_message(socket_reader, maker_address, audit_log, None).await?)?;
+    tracing::debug!("{:#?}", makerhello);
+    Ok(makerhello)
+}
+
+//session_id is None if the connection isnt for a coinswap, see swap_sessions.rs
+//a maker whose hello has an identity key is challenged to prove it, older makers have none, see
+// maker_identity.rs. returns the proven identity key
+pub(crate) async fn send_taker_hello<R: AsyncBufRead + Unpin, W: AsyncWrite + Unpin>(
+    socket_reader: &mut R,
+    socket_writer: &mut W,
+    maker_address: &MakerAddress,
+    audit_log: Option<&AuditLog>,
+    makerhello: &MakerHello,
+    session_id: Option<SessionId>,
+) -> Result<Option<PublicKey>, Error> {
+    let address = maker_address.to_string();
+    let challenge = makerhello
+        .identity_pubkey
+        .map(|_| identity_challenge(&address));
     send_message(
-        &mut socket_writer,
-        TakerToMakerMessage::TakerHello(TakerHello {
+        socket_writer,
+        maker_address,
+        audit_log,
+        None,
+        &TakerToMakerMessage::TakerHello(TakerHello {
             protocol_version_min: 0,
             protocol_version_max: 0,
+            session_id,
+            identity_challenge: challenge.clone(),
         }),
     )
     .await?;
-    let makerhello =
-        if let MakerToTakerMessage::MakerHello(m) = read_message(&mut socket_reader).await? {
-            m
-        } else {
-            return Err(Error::Protocol("expected method makerhello"));
-        };
-    log::debug!("{:#?}", makerhello);
-    Ok((socket_reader, socket_writer))
+    if let (Some(identity_pubkey), Some(challenge)) = (&makerhello.identity_pubkey, &challenge) {
+        let proof: IdentityProof =
+            expect_message(read_message(socket_reader, maker_address, audit_log, None).await?)?;
+        let identity = proof.identity.ok_or(Error::Protocol(
+            "maker refused to prove its identity at this address",
+        ))?;
+        verify_identity_proof(&identity, identity_pubkey, challenge)?;
+    }
+    if let PinResult::Missing { .. } = check_maker_identity(&address, makerhello.identity_pubkey) {
+        return Err(Error::Protocol(
+            "maker presented no identity key although one is pinned",
+        ));
+    }
+    Ok(makerhello.identity_pubkey)
 }
 
-fn generate_maker_multisig_and_hashlock_keys(
+//returns the identity key the maker proved at this address, if its hello presented one
+pub(crate) async fn exchange_hellos<R: AsyncBufRead + Unpin, W: AsyncWrite + Unpin>(
+    socket_reader: &mut R,
+    socket_writer: &mut W,
+    maker_address: &MakerAddress,
+) -> Result<Option<PublicKey>, Error> {
+    let makerhello = read_maker_hello(socket_reader, maker_address, None).await?;
+    send_taker_hello(
+        socket_reader,
+        socket_writer,
+        maker_address,
+        None,
+        &makerhello,
+        None,
+    )
+    .await
+}
+
+/// Connect and say hello to the maker, also returning the identity key it proved if any
+pub async fn handshake_maker<'a>(
+    socket: &'a mut TcpStream,
+    maker_address: &MakerAddress,
+) -> Result<(BufReader<ReadHalf<'a>>, WriteHalf<'a>, Option<PublicKey>), Error> {
+    connect_to_maker(socket, maker_address).await?;
+    let (reader, mut socket_writer) = socket.split();
+    let mut socket_reader = BufReader::new(reader);
+    let identity_pubkey =
+        exchange_hellos(&mut socket_reader, &mut socket_writer, maker_address).await?;
+    Ok((socket_reader, socket_writer, identity_pubkey))
+}
+
+//tests and fuzzers can pass a seeded rng to get a reproducible coinswap
+pub(crate) fn generate_preimage<R: RngCore + CryptoRng>(rng: &mut R) -> Preimage {
+    let mut preimage = [0u8; PREIMAGE_LEN];
+    rng.fill_bytes(&mut preimage);
+    preimage
+}
+
+pub(crate) fn generate_maker_multisig_and_hashlock_keys<R: RngCore + CryptoRng>(
     tweakable_point: &PublicKey,
     count: u32,
+    rng: &mut R,
 ) -> (
     Vec<PublicKey>,
     Vec<SecretKey>,
@@ -483,10 +1009,10 @@ fn generate_maker_multisig_and_hashlock_keys(
     Vec<SecretKey>,
 ) {
     let (multisig_pubkeys, multisig_keys_or_nonces): (Vec<_>, Vec<_>) = (0..count)
-        .map(|_| contracts::derive_maker_pubkey_and_nonce(*tweakable_point).unwrap())
+        .map(|_| contracts::derive_maker_pubkey_and_nonce_with_rng(*tweakable_point, rng).unwrap())
         .unzip();
     let (hashlock_pubkeys, hashlock_keys_or_nonces): (Vec<_>, Vec<_>) = (0..count)
-        .map(|_| contracts::derive_maker_pubkey_and_nonce(*tweakable_point).unwrap())
+        .map(|_| contracts::derive_maker_pubkey_and_nonce_with_rng(*tweakable_point, rng).unwrap())
         .unzip();
     (
         multisig_pubkeys,
@@ -517,17 +1043,20 @@ fn generate_my_multisig_and_hashlock_keys(
 }
 
 async fn request_senders_contract_tx_signatures<S: SwapCoin>(
+    connections: &mut MakerConnections,
     maker_address: &MakerAddress,
     outgoing_swapcoins: &[S],
     maker_multisig_nonces: &[SecretKey],
     maker_hashlock_nonces: &[SecretKey],
     locktime: u16,
 ) -> Result<Vec<Signature>, Error> {
+    let taker_settings = taker_settings();
     let mut ii = 0;
     loop {
         ii += 1;
         select! {
             ret = request_senders_contract_tx_signatures_attempt_once(
+                connections,
                 maker_address,
                 outgoing_swapcoins,
                 maker_multisig_nonces,
@@ -537,14 +1066,15 @@ async fn request_senders_contract_tx_signatures<S: SwapCoin>(
                 match ret {
                     Ok(sigs) => return Ok(sigs),
                     Err(e) => {
-                        log::warn!(
+                        tracing::warn!(
                             "Failed to request senders contract tx sigs from maker {}, \
                             reattempting... error={:?}",
                             maker_address,
                             e
                         );
-                        if ii <= FIRST_CONNECT_ATTEMPTS {
-                            sleep(Duration::from_secs(FIRST_CONNECT_SLEEP_DELAY_SEC)).await;
+                        connections.close(maker_address);
+                        if ii <= taker_settings.first_connect_attempts {
+                            sleep(Duration::from_secs(taker_settings.first_connect_sleep_delay_sec)).await;
                             continue;
                         } else {
                             return Err(e);
@@ -552,12 +1082,12 @@ async fn request_senders_contract_tx_signatures<S: SwapCoin>(
                     }
                 }
             },
-            _ = sleep(Duration::from_secs(FIRST_CONNECT_ATTEMPT_TIMEOUT_SEC)) => {
-                log::warn!(
+            _ = sleep(Duration::from_secs(taker_settings.senders_contract_sigs_timeout_sec)) => {
+                tracing::warn!(
                     "Timeout for request senders contract tx sig from maker {}, reattempting...",
                     maker_address
                 );
-                if ii <= FIRST_CONNECT_ATTEMPTS {
+                if ii <= taker_settings.first_connect_attempts {
                     continue;
                 } else {
                     return Err(Error::Protocol(
@@ -569,66 +1099,63 @@ async fn request_senders_contract_tx_signatures<S: SwapCoin>(
 }
 
 async fn request_senders_contract_tx_signatures_attempt_once<S: SwapCoin>(
+    connections: &mut MakerConnections,
     maker_address: &MakerAddress,
     outgoing_swapcoins: &[S],
     maker_multisig_nonces: &[SecretKey],
     maker_hashlock_nonces: &[SecretKey],
     locktime: u16,
 ) -> Result<Vec<Signature>, Error> {
-    log::info!("Connecting to {}", maker_address);
-    let mut socket = TcpStream::connect(maker_address.get_tcpstream_address()).await?;
-    let (mut socket_reader, mut socket_writer) =
-        handshake_maker(&mut socket, maker_address).await?;
-    log::info!("===> Sending SignSendersContractTx to {}", maker_address);
-    send_message(
-        &mut socket_writer,
-        TakerToMakerMessage::SignSendersContractTx(SignSendersContractTx {
-            txes_info: izip!(
-                maker_multisig_nonces.iter(),
-                maker_hashlock_nonces.iter(),
-                outgoing_swapcoins.iter()
-            )
-            .map(
-                |(&multisig_key_nonce, &hashlock_key_nonce, outgoing_swapcoin)| {
-                    SenderContractTxNoncesInfo {
-                        multisig_key_nonce,
-                        hashlock_key_nonce,
-                        timelock_pubkey: outgoing_swapcoin.get_timelock_pubkey(),
-                        senders_contract_tx: outgoing_swapcoin.get_contract_tx(),
-                        multisig_redeemscript: outgoing_swapcoin.get_multisig_redeemscript(),
-                        funding_input_value: outgoing_swapcoin.get_funding_amount(),
-                    }
-                },
-            )
-            .collect::<Vec<SenderContractTxNoncesInfo>>(),
-            hashvalue: outgoing_swapcoins[0].get_hashvalue(),
-            locktime,
-        }),
-    )
-    .await?;
-    let maker_senders_contract_sig = if let MakerToTakerMessage::SendersContractSig(m) =
-        read_message(&mut socket_reader).await?
-    {
-        m
-    } else {
-        return Err(Error::Protocol("expected method senderscontractsig"));
-    };
+    tracing::info!("===> Sending SignSendersContractTx to {}", maker_address);
+    let session_id = connections.session_id(maker_address).await?;
+    let reply = connections
+        .request(
+            maker_address,
+            &TakerToMakerMessage::SignSendersContractTx(SignSendersContractTx {
+                session_id,
+                txes_info: izip!(
+                    maker_multisig_nonces.iter(),
+                    maker_hashlock_nonces.iter(),
+                    outgoing_swapcoins.iter()
+                )
+                .map(
+                    |(&multisig_key_nonce, &hashlock_key_nonce, outgoing_swapcoin)| {
+                        SenderContractTxNoncesInfo {
+                            multisig_key_nonce,
+                            hashlock_key_nonce,
+                            timelock_pubkey: outgoing_swapcoin.get_timelock_pubkey(),
+                            senders_contract_tx: outgoing_swapcoin.get_contract_tx(),
+                            multisig_redeemscript: outgoing_swapcoin.get_multisig_redeemscript(),
+                            funding_input_value: outgoing_swapcoin.get_funding_amount(),
+                        }
+                    },
+                )
+                .collect::<Vec<SenderContractTxNoncesInfo>>(),
+                hashvalue: outgoing_swapcoins[0].get_hashvalue(),
+                locktime,
+            }),
+        )
+        .await?;
+    let maker_senders_contract_sig: SendersContractSig = expect_message(reply)?;
     if maker_senders_contract_sig.sigs.len() != outgoing_swapcoins.len() {
         return Err(Error::Protocol("wrong number of signatures from maker"));
     }
-    if maker_senders_contract_sig
-        .sigs
-        .iter()
-        .zip(outgoing_swapcoins.iter())
-        .any(|(sig, outgoing_swapcoin)| !outgoing_swapcoin.verify_contract_tx_sender_sig(sig))
-    {
+    if !verify_all_parallel(
+        &maker_senders_contract_sig
+            .sigs
+            .iter()
+            .zip(outgoing_swapcoins.iter())
+            .collect::<Vec<_>>(),
+        |(sig, outgoing_swapcoin)| outgoing_swapcoin.verify_contract_tx_sender_sig(sig),
+    ) {
         return Err(Error::Protocol("invalid signature from maker"));
     }
-    log::info!("<=== Received SendersContractSig from {}", maker_address);
+    tracing::info!("<=== Received SendersContractSig from {}", maker_address);
     Ok(maker_senders_contract_sig.sigs)
 }
 
 async fn request_receivers_contract_tx_signatures<S: SwapCoin>(
+    connections: &mut MakerConnections,
     maker_address: &MakerAddress,
     incoming_swapcoins: &[S],
     receivers_contract_txes: &[Transaction],
@@ -638,6 +1165,7 @@ async fn request_receivers_contract_tx_signatures<S: SwapCoin>(
         ii += 1;
         select! {
             ret = request_receivers_contract_tx_signatures_attempt_once(
+                connections,
                 maker_address,
                 incoming_swapcoins,
                 receivers_contract_txes,
@@ -645,12 +1173,13 @@ async fn request_receivers_contract_tx_signatures<S: SwapCoin>(
                 match ret {
                     Ok(sigs) => return Ok(sigs),
                     Err(e) => {
-                        log::warn!(
+                        tracing::warn!(
                             "Failed to request receivers contract tx sigs from maker {}, \
                             reattempting... error={:?}",
                             maker_address,
                             e
                         );
+                        connections.close(maker_address);
                         if ii <= RECONNECT_ATTEMPTS {
                             sleep(Duration::from_secs(
                                 if ii <= SHORT_LONG_SLEEP_DELAY_TRANSITION {
@@ -667,8 +1196,8 @@ async fn request_receivers_contract_tx_signatures<S: SwapCoin>(
                     }
                 }
             },
-            _ = sleep(Duration::from_secs(RECONNECT_ATTEMPT_TIMEOUT_SEC)) => {
-                log::warn!(
+            _ = sleep(Duration::from_secs(taker_settings().contract_sigs_exchange_timeout_sec)) => {
+                tracing::warn!(
                     "Timeout for request receivers contract tx sig from maker {}, reattempting...",
                     maker_address
                 );
@@ -684,70 +1213,96 @@ async fn request_receivers_contract_tx_signatures<S: SwapCoin>(
 }
 
 async fn request_receivers_contract_tx_signatures_attempt_once<S: SwapCoin>(
+    connections: &mut MakerConnections,
     maker_address: &MakerAddress,
     incoming_swapcoins: &[S],
     receivers_contract_txes: &[Transaction],
 ) -> Result<Vec<Signature>, Error> {
-    log::info!("Connecting to {}", maker_address);
-    let mut socket = TcpStream::connect(maker_address.get_tcpstream_address()).await?;
-    let (mut socket_reader, mut socket_writer) =
-        handshake_maker(&mut socket, maker_address).await?;
-    send_message(
-        &mut socket_writer,
-        TakerToMakerMessage::SignReceiversContractTx(SignReceiversContractTx {
-            txes: incoming_swapcoins
-                .iter()
-                .zip(receivers_contract_txes.iter())
-                .map(
-                    |(swapcoin, receivers_contract_tx)| ReceiversContractTxInfo {
-                        multisig_redeemscript: swapcoin.get_multisig_redeemscript(),
-                        contract_tx: receivers_contract_tx.clone(),
-                    },
-                )
-                .collect::<Vec<ReceiversContractTxInfo>>(),
-        }),
-    )
-    .await?;
-    let maker_receiver_contract_sig = if let MakerToTakerMessage::ReceiversContractSig(m) =
-        read_message(&mut socket_reader).await?
-    {
-        m
-    } else {
-        return Err(Error::Protocol("expected method receiverscontractsig"));
-    };
+    let session_id = connections.session_id(maker_address).await?;
+    let reply = connections
+        .request(
+            maker_address,
+            &TakerToMakerMessage::SignReceiversContractTx(SignReceiversContractTx {
+                session_id,
+                txes: incoming_swapcoins
+                    .iter()
+                    .zip(receivers_contract_txes.iter())
+                    .map(
+                        |(swapcoin, receivers_contract_tx)| ReceiversContractTxInfo {
+                            multisig_redeemscript: swapcoin.get_multisig_redeemscript(),
+                            contract_tx: receivers_contract_tx.clone(),
+                        },
+                    )
+                    .collect::<Vec<ReceiversContractTxInfo>>(),
+            }),
+        )
+        .await?;
+    let maker_receiver_contract_sig: ReceiversContractSig = expect_message(reply)?;
     if maker_receiver_contract_sig.sigs.len() != incoming_swapcoins.len() {
         return Err(Error::Protocol("wrong number of signatures from maker"));
     }
-    if maker_receiver_contract_sig
-        .sigs
-        .iter()
-        .zip(incoming_swapcoins.iter())
-        .any(|(sig, swapcoin)| !swapcoin.verify_contract_tx_receiver_sig(sig))
-    {
+    if !verify_all_parallel(
+        &maker_receiver_contract_sig
+            .sigs
+            .iter()
+            .zip(incoming_swapcoins.iter())
+            .collect::<Vec<_>>(),
+        |(sig, swapcoin)| swapcoin.verify_contract_tx_receiver_sig(sig),
+    ) {
         return Err(Error::Protocol("invalid signature from maker"));
     }
 
-    log::info!("<=== Received ReceiversContractSig from {}", maker_address);
+    tracing::info!("<=== Received ReceiversContractSig from {}", maker_address);
     Ok(maker_receiver_contract_sig.sigs)
 }
 
+//a contract tx of the coinswap was broadcast so it cant complete, the same as
+// recover-from-incomplete-coinswap does
+fn broadcast_outgoing_contracts(
+    rpc: &Client,
+    wallet: &Wallet,
+    outgoing_swapcoins: &[OutgoingSwapCoin],
+) {
+    for outgoing_swapcoin in outgoing_swapcoins {
+        if let Err(e) = wallet.import_wallet_contract_redeemscript(
+            rpc,
+            &outgoing_swapcoin.get_contract_redeemscript(),
+        ) {
+            tracing::warn!("unable to import contract redeemscript, error={:?}", e);
+        }
+        let contract_tx = outgoing_swapcoin.get_fully_signed_contract_tx();
+        //a maker may have broadcast it already
+        match rpc.send_raw_transaction(&contract_tx) {
+            Ok(txid) => tracing::info!("Broadcasted contract tx {}", txid),
+            Err(e) => tracing::warn!(
+                "contract tx {} not broadcast, error={:?}",
+                contract_tx.txid(),
+                e
+            ),
+        }
+    }
+}
+
 //return a list of the transactions and merkleproofs if the funding txes confirmed
-//return None if any of the contract transactions were seen on the network
-// if it turns out i want to return data in the contract tx broadcast case, then maybe use an enum
+//return None if the swap monitor found the coinswap cant complete, see swap_monitor.rs
 async fn wait_for_funding_tx_confirmation(
     rpc: &Client,
     funding_txids: &[Txid],
     required_confirmations: i32,
-    contract_to_watch: &[Vec<Transaction>],
-    last_checked_block_height: &mut Option<u64>,
+    min_fee_rate: u64,
+    monitor: &mut SwapMonitor,
+    progress_tx: &Option<Sender<TakerEvent>>,
 ) -> Result<Option<(Vec<Transaction>, Vec<String>)>, Error> {
     let mut txid_tx_map = HashMap::<Txid, Transaction>::new();
     let mut txid_blockhash_map = HashMap::<Txid, BlockHash>::new();
-    log::info!(
+    tracing::info!(
         "Waiting for funding transaction confirmations ({} conf required)",
         required_confirmations
     );
     let mut txids_seen_once = HashSet::<Txid>::new();
+    let mut txid_confirmations_map = HashMap::<Txid, i32>::new();
+    let timeout_sec = taker_settings().funding_confirmation_timeout_sec;
+    let started = Instant::now();
     loop {
         for txid in funding_txids {
             if txid_tx_map.contains_key(txid) {
@@ -758,6 +1313,13 @@ async fn wait_for_funding_tx_confirmation(
                 //if we lose connection to the node, just try again, no point returning an error
                 Err(_e) => continue,
             };
+            //until it confirms the tx can still be replaced or double spent, and one with
+            // negative confirmations already was
+            check_funding_tx_conflicts(
+                rpc,
+                &deserialize::<Transaction>(&gettx.hex).unwrap(),
+                gettx.info.confirmations,
+            )?;
             if !txids_seen_once.contains(txid) {
                 txids_seen_once.insert(*txid);
                 if gettx.info.confirmations == 0 {
@@ -765,26 +1327,53 @@ async fn wait_for_funding_tx_confirmation(
                         Ok(m) => m,
                         Err(_e) => continue,
                     };
-                    log::info!(
+                    tracing::info!(
                         "Seen in mempool: {} [{:.1} sat/vbyte]",
                         txid,
                         mempool_tx.fees.base.as_sat() as f32 / mempool_tx.vsize as f32
                     );
+                    check_fee_rate(
+                        mempool_tx.fees.base.as_sat(),
+                        mempool_tx.vsize,
+                        min_fee_rate,
+                    )?;
                 }
             }
-            //TODO handle confirm<0
+            if txid_confirmations_map.get(txid) != Some(&gettx.info.confirmations) {
+                txid_confirmations_map.insert(*txid, gettx.info.confirmations);
+                send_progress(
+                    progress_tx,
+                    TakerEvent::FundingTxConfirmations {
+                        txid: *txid,
+                        confirmations: gettx.info.confirmations,
+                        required_confirmations,
+                    },
+                );
+            }
             if gettx.info.confirmations >= required_confirmations {
                 txid_tx_map.insert(*txid, deserialize::<Transaction>(&gettx.hex).unwrap());
                 txid_blockhash_map.insert(*txid, gettx.info.blockhash.unwrap());
-                log::debug!(
+                tracing::debug!(
                     "funding tx {} reached {} confirmation(s)",
                     txid,
                     required_confirmations
                 );
             }
         }
+        //a confirmation only counts while its block is in the best chain, otherwise the
+        // proof of funding would be made from a block which was reorged out
+        let reorged_txids = txid_blockhash_map
+            .iter()
+            .filter(|(_, blockhash)| !is_block_in_best_chain(rpc, blockhash).unwrap_or(true))
+            .map(|(txid, _)| *txid)
+            .collect::<Vec<Txid>>();
+        for txid in reorged_txids {
+            tracing::warn!("funding tx {} was reorged out, waiting for it again", txid);
+            txid_tx_map.remove(&txid);
+            txid_blockhash_map.remove(&txid);
+        }
         if txid_tx_map.len() == funding_txids.len() {
-            log::info!("Funding Transactions confirmed");
+            tracing::info!("Funding Transactions confirmed");
             let txes = funding_txids
                 .iter()
                 .map(|txid| txid_tx_map.get(txid).unwrap().clone())
@@ -798,31 +1387,31 @@ async fn wait_for_funding_tx_confirmation(
                 .collect::<Result<Vec<String>, bitcoincore_rpc::Error>>()?;
             return Ok(Some((txes, merkleproofs)));
         }
-        if !contract_to_watch.is_empty() {
-            let contracts_broadcasted = check_for_broadcasted_contract_txes(
-                rpc,
-                &contract_to_watch
-                    .iter()
-                    .map(|txes| ContractsInfo {
-                        contract_txes: txes
-                            .iter()
-                            .map(|tx| ContractTransaction {
-                                tx: tx.clone(),
-                                redeemscript: Script::new(),
-                                hashlock_spend_without_preimage: None,
-                                timelock_spend: None,
-                                timelock_spend_broadcasted: false,
-                            })
-                            .collect::<Vec<ContractTransaction>>(),
-                        wallet_label: String::new(),
-                    })
-                    .collect::<Vec<ContractsInfo>>(),
-                last_checked_block_height,
-            )?;
-            if !contracts_broadcasted.is_empty() {
-                log::info!("Contract transactions were broadcasted! Aborting");
-                return Ok(None);
-            }
+        let alarms = monitor.poll(rpc)?;
+        for alarm in &alarms {
+            tracing::warn!("swap monitor alarm = {:?}", alarm);
+        }
+        if recovery_decision(&alarms) == RecoveryDecision::BroadcastContracts {
+            tracing::info!("Contract transactions were broadcasted! Aborting");
+            run_hook(
+                "taker",
+                HookEvent::ContractBroadcast {
+                    txids: alarms
+                        .iter()
+                        .flat_map(|alarm| match alarm {
+                            SwapAlarm::ContractBroadcast { txids, .. } => txids.clone(),
+                            SwapAlarm::FundingSpent { .. } => Vec::new(),
+                        })
+                        .collect(),
+                },
+            )
+            .await;
+            return Ok(None);
+        }
+        if timeout_sec > 0 && started.elapsed() > Duration::from_secs(timeout_sec) {
+            return Err(Error::Protocol(
+                "timed out waiting for funding transactions to confirm",
+            ));
         }
         sleep(Duration::from_millis(1000)).await;
     }
@@ -850,8 +1439,9 @@ fn get_swapcoin_multisig_contract_redeemscripts_txes<S: SwapCoin>(
 
 async fn exchange_signatures_and_find_next_maker<'a>(
     rpc: &Client,
+    connections: &mut MakerConnections,
     config: &TakerConfig,
-    maker_offers_addresses: &mut Vec<&'a OfferAndAddress>,
+    maker_offers_addresses: &mut RouteCandidates<'a>,
     this_maker: &'a OfferAndAddress,
     previous_maker: Option<&'a OfferAndAddress>,
     is_taker_previous_peer: bool,
@@ -884,6 +1474,7 @@ async fn exchange_signatures_and_find_next_maker<'a>(
         select! {
             ret = exchange_signatures_and_find_next_maker_attempt_once(
                 rpc,
+                connections,
                 config,
                 maker_offers_addresses,
                 this_maker,
@@ -905,12 +1496,13 @@ async fn exchange_signatures_and_find_next_maker<'a>(
                 match ret {
                     Ok(return_value) => return Ok(return_value),
                     Err(e) => {
-                        log::warn!(
+                        tracing::warn!(
                             "Failed to exchange signatures with maker {}, \
                             reattempting... error={:?}",
                             this_maker.address,
                             e
                         );
+                        connections.close(&this_maker.address);
                         if ii <= RECONNECT_ATTEMPTS {
                             sleep(Duration::from_secs(
                                 if ii <= SHORT_LONG_SLEEP_DELAY_TRANSITION {
@@ -927,8 +1519,8 @@ async fn exchange_signatures_and_find_next_maker<'a>(
                     }
                 }
             },
-            _ = sleep(Duration::from_secs(RECONNECT_ATTEMPT_TIMEOUT_SEC)) => {
-                log::warn!(
+            _ = sleep(Duration::from_secs(taker_settings().contract_sigs_exchange_timeout_sec)) => {
+                tracing::warn!(
                     "Timeout for exchange signatures with maker {}, reattempting...",
                     this_maker.address
                 );
@@ -945,8 +1537,9 @@ async fn exchange_signatures_and_find_next_maker<'a>(
 
 async fn exchange_signatures_and_find_next_maker_attempt_once<'a>(
     rpc: &Client,
+    connections: &mut MakerConnections,
     config: &TakerConfig,
-    maker_offers_addresses: &mut Vec<&'a OfferAndAddress>,
+    maker_offers_addresses: &mut RouteCandidates<'a>,
     this_maker: &'a OfferAndAddress,
     previous_maker: Option<&'a OfferAndAddress>,
     is_taker_previous_peer: bool,
@@ -976,10 +1569,6 @@ async fn exchange_signatures_and_find_next_maker_attempt_once<'a>(
     //return next_peer_multisig_pubkeys, next_peer_multisig_keys_or_nonces,
     //    next_peer_hashlock_keys_or_nonces, (), next_swap_contract_redeemscripts, found_next_maker
 
-    log::info!("Connecting to {}", this_maker.address);
-    let mut socket = TcpStream::connect(this_maker.address.get_tcpstream_address()).await?;
-    let (mut socket_reader, mut socket_writer) =
-        handshake_maker(&mut socket, &this_maker.address).await?;
     let mut next_maker = this_maker;
     let (
         next_peer_multisig_pubkeys,
@@ -999,20 +1588,21 @@ async fn exchange_signatures_and_find_next_maker_attempt_once<'a>(
         ) = if is_taker_next_peer {
             generate_my_multisig_and_hashlock_keys(config.tx_count)
         } else {
-            next_maker = choose_next_maker(maker_offers_addresses, config.send_amount)
+            next_maker = choose_next_valid_maker(maker_offers_addresses, config.send_amount)
+                .await
                 .expect("not enough offers");
             //next_maker is only ever accessed when the next peer is a maker, not a taker
             //i.e. if its ever used when is_taker_next_peer == true, then thats a bug
             generate_maker_multisig_and_hashlock_keys(
                 &next_maker.offer.tweakable_point,
                 config.tx_count,
+                &mut OsRng,
             )
         };
-        log::info!("===> Sending ProofOfFunding to {}", this_maker.address);
+        tracing::info!("===> Sending ProofOfFunding to {}", this_maker.address);
         let (maker_sign_sender_and_receiver_contracts, next_swap_contract_redeemscripts) =
             send_proof_of_funding_and_check_reply(
-                &mut socket_reader,
-                &mut socket_writer,
+                connections,
                 this_maker,
                 funding_txes,
                 funding_tx_merkleproofs,
@@ -1028,13 +1618,13 @@ async fn exchange_signatures_and_find_next_maker_attempt_once<'a>(
                 hashvalue,
             )
             .await?;
-        log::info!(
+        tracing::info!(
             "<=== Recieved SignSendersAndReceiversContractTxes from {}",
             this_maker.address
         );
 
         let senders_sigs = if is_taker_next_peer {
-            log::info!("Taker is next peer. Signing Sender's Contract Txs",);
+            tracing::info!("Taker is next peer. Signing Sender's Contract Txs",);
             sign_senders_contract_txes(
                 &next_peer_multisig_keys_or_nonces,
                 &maker_sign_sender_and_receiver_contracts,
@@ -1047,6 +1637,7 @@ async fn exchange_signatures_and_find_next_maker_attempt_once<'a>(
                 &next_swap_contract_redeemscripts,
             )?;
             let sigs = match request_senders_contract_tx_signatures(
+                connections,
                 &next_maker.address,
                 &next_swapcoins,
                 &next_peer_multisig_keys_or_nonces,
@@ -1057,11 +1648,12 @@ async fn exchange_signatures_and_find_next_maker_attempt_once<'a>(
             {
                 Ok(r) => r,
                 Err(e) => {
-                    log::debug!(
+                    tracing::debug!(
                         "Fail to obtain senders contract tx signature from next_maker {}: {:?}",
                         next_maker.address,
                         e
                     );
+                    record_maker_connection(&next_maker.address.to_string(), false);
                     continue; //go back to the start of the loop and try another maker
                 }
             };
@@ -1079,7 +1671,7 @@ async fn exchange_signatures_and_find_next_maker_attempt_once<'a>(
     };
 
     let receivers_sigs = if is_taker_previous_peer {
-        log::info!("Taker is previous peer. Signing Receivers Contract Txs",);
+        tracing::info!("Taker is previous peer. Signing Receivers Contract Txs",);
         sign_receivers_contract_txes(
             &maker_sign_sender_and_receiver_contracts.receivers_contract_txes,
             outgoing_swapcoins,
@@ -1087,7 +1679,7 @@ async fn exchange_signatures_and_find_next_maker_attempt_once<'a>(
     } else {
         assert!(previous_maker.is_some());
         let previous_maker_addr = &previous_maker.unwrap().address;
-        log::info!(
+        tracing::info!(
             "===> Sending SignReceiversContractTx, previous maker is {}",
             previous_maker_addr,
         );
@@ -1098,24 +1690,30 @@ async fn exchange_signatures_and_find_next_maker_attempt_once<'a>(
             &watchonly_swapcoins[watchonly_swapcoins.len() - 2]
         };
         request_receivers_contract_tx_signatures(
+            connections,
             previous_maker_addr,
             previous_maker_watchonly_swapcoins,
             &maker_sign_sender_and_receiver_contracts.receivers_contract_txes,
         )
         .await?
     };
-    log::info!(
+    tracing::info!(
         "===> Sending SendersAndReceiversContractSigs to {}",
         this_maker.address
     );
-    send_message(
-        &mut socket_writer,
-        TakerToMakerMessage::SendersAndReceiversContractSigs(SendersAndReceiversContractSigs {
-            receivers_sigs,
-            senders_sigs,
-        }),
-    )
-    .await?;
+    let session_id = connections.session_id(&this_maker.address).await?;
+    connections
+        .send(
+            &this_maker.address,
+            &TakerToMakerMessage::SendersAndReceiversContractSigs(
+                SendersAndReceiversContractSigs {
+                    session_id,
+                    receivers_sigs,
+                    senders_sigs,
+                },
+            ),
+        )
+        .await?;
     Ok((
         next_peer_multisig_pubkeys,
         next_peer_multisig_keys_or_nonces,
@@ -1127,8 +1725,7 @@ async fn exchange_signatures_and_find_next_maker_attempt_once<'a>(
 }
 
 async fn send_proof_of_funding_and_check_reply(
-    socket_reader: &mut BufReader<ReadHalf<'_>>,
-    socket_writer: &mut WriteHalf<'_>,
+    connections: &mut MakerConnections,
     this_maker: &OfferAndAddress,
     funding_txes: &[Transaction],
     funding_tx_merkleproofs: &[String],
@@ -1143,60 +1740,55 @@ async fn send_proof_of_funding_and_check_reply(
     this_maker_contract_txes: &[Transaction],
     hashvalue: Hash160,
 ) -> Result<(SignSendersAndReceiversContractTxes, Vec<Script>), Error> {
-    send_message(
-        socket_writer,
-        TakerToMakerMessage::ProofOfFunding(ProofOfFunding {
-            confirmed_funding_txes: izip!(
-                funding_txes.iter(),
-                funding_tx_merkleproofs.iter(),
-                this_maker_multisig_redeemscripts.iter(),
-                this_maker_multisig_nonces,
-                this_maker_contract_redeemscripts.iter(),
-                this_maker_hashlock_nonces
-            )
-            .map(
-                |(
-                    funding_tx,
-                    funding_tx_merkleproof,
-                    multisig_redeemscript,
-                    &multisig_key_nonce,
-                    contract_redeemscript,
-                    &hashlock_key_nonce,
-                )| ConfirmedCoinSwapTxInfo {
-                    funding_tx: funding_tx.clone(),
-                    funding_tx_merkleproof: funding_tx_merkleproof.clone(),
-                    multisig_redeemscript: multisig_redeemscript.clone(),
-                    multisig_key_nonce,
-                    contract_redeemscript: contract_redeemscript.clone(),
-                    hashlock_key_nonce,
-                },
-            )
-            .collect::<Vec<ConfirmedCoinSwapTxInfo>>(),
-            next_coinswap_info: next_peer_multisig_pubkeys
-                .iter()
-                .zip(next_peer_hashlock_pubkeys.iter())
+    let session_id = connections.session_id(&this_maker.address).await?;
+    let reply = connections
+        .request(
+            &this_maker.address,
+            &TakerToMakerMessage::ProofOfFunding(ProofOfFunding {
+                session_id,
+                confirmed_funding_txes: izip!(
+                    funding_txes.iter(),
+                    funding_tx_merkleproofs.iter(),
+                    this_maker_multisig_redeemscripts.iter(),
+                    this_maker_multisig_nonces,
+                    this_maker_contract_redeemscripts.iter(),
+                    this_maker_hashlock_nonces
+                )
                 .map(
-                    |(&next_coinswap_multisig_pubkey, &next_hashlock_pubkey)| NextCoinSwapTxInfo {
-                        next_coinswap_multisig_pubkey,
-                        next_hashlock_pubkey,
+                    |(
+                        funding_tx,
+                        funding_tx_merkleproof,
+                        multisig_redeemscript,
+                        &multisig_key_nonce,
+                        contract_redeemscript,
+                        &hashlock_key_nonce,
+                    )| ConfirmedCoinSwapTxInfo {
+                        funding_tx: funding_tx.clone(),
+                        funding_tx_merkleproof: funding_tx_merkleproof.clone(),
+                        multisig_redeemscript: multisig_redeemscript.clone(),
+                        multisig_key_nonce,
+                        contract_redeemscript: contract_redeemscript.clone(),
+                        hashlock_key_nonce,
                     },
                 )
-                .collect::<Vec<NextCoinSwapTxInfo>>(),
-            next_locktime: next_maker_refund_locktime,
-            next_fee_rate: next_maker_fee_rate,
-        }),
-    )
-    .await?;
-    let maker_sign_sender_and_receiver_contracts =
-        if let MakerToTakerMessage::SignSendersAndReceiversContractTxes(m) =
-            read_message(socket_reader).await?
-        {
-            m
-        } else {
-            return Err(Error::Protocol(
-                "expected method signsendersandreceiverscontracttxes",
-            ));
-        };
+                .collect::<Vec<ConfirmedCoinSwapTxInfo>>(),
+                next_coinswap_info: next_peer_multisig_pubkeys
+                    .iter()
+                    .zip(next_peer_hashlock_pubkeys.iter())
+                    .map(|(&next_coinswap_multisig_pubkey, &next_hashlock_pubkey)| {
+                        NextCoinSwapTxInfo {
+                            next_coinswap_multisig_pubkey,
+                            next_hashlock_pubkey,
+                        }
+                    })
+                    .collect::<Vec<NextCoinSwapTxInfo>>(),
+                next_locktime: next_maker_refund_locktime,
+                next_fee_rate: next_maker_fee_rate,
+            }),
+        )
+        .await?;
+    let maker_sign_sender_and_receiver_contracts: SignSendersAndReceiversContractTxes =
+        expect_message(reply)?;
     if maker_sign_sender_and_receiver_contracts
         .receivers_contract_txes
         .len()
@@ -1234,22 +1826,30 @@ async fn send_proof_of_funding_and_check_reply(
         .iter()
         .map(|i| i.funding_amount)
         .sum::<u64>();
-    let coinswap_fees = calculate_coinswap_fee(
-        this_maker.offer.absolute_fee_sat,
-        this_maker.offer.amount_relative_fee_ppb,
-        this_maker.offer.time_relative_fee_ppb,
+    let coinswap_fees = apply_fee_multiplier(
+        calculate_coinswap_fee(
+            this_maker.offer.absolute_fee_sat,
+            this_maker.offer.amount_relative_fee_ppb,
+            this_maker.offer.time_relative_fee_ppb,
+            this_amount,
+            1, //time_in_blocks just 1 for now
+        ),
+        &this_maker.offer.required_confirms_tiers,
         this_amount,
-        1, //time_in_blocks just 1 for now
     );
     let miner_fees_paid_by_taker = MAKER_FUNDING_TX_VBYTE_SIZE
         * next_maker_fee_rate
         * (next_peer_multisig_pubkeys.len() as u64)
         / 1000;
     let calculated_next_amount = this_amount - coinswap_fees - miner_fees_paid_by_taker;
-    if calculated_next_amount != next_amount {
-        return Err(Error::Protocol("next_amount incorrect"));
+    if let Err(e) = check_next_amount(calculated_next_amount, next_amount) {
+        //sending on less than the offer's fees give is the maker skimming off the coinswap
+        if next_amount < calculated_next_amount {
+            ban_maker(&this_maker.address.to_string(), "fee mismatch");
+        }
+        return Err(e);
     }
-    log::info!(
+    tracing::info!(
         "this_amount={} coinswap_fees={} miner_fees_paid_by_taker={} next_amount={}",
         this_amount,
         coinswap_fees,
@@ -1292,6 +1892,18 @@ async fn send_proof_of_funding_and_check_reply(
     ))
 }
 
+fn check_next_amount(calculated_next_amount: u64, next_amount: u64) -> Result<(), Error> {
+    if calculated_next_amount.abs_diff(next_amount) > FEE_MISMATCH_TOLERANCE_SAT {
+        tracing::warn!(
+            "fee mismatch, next_amount={} but the offer gives {}",
+            next_amount,
+            calculated_next_amount
+        );
+        return Err(Error::Protocol("fee mismatch"));
+    }
+    Ok(())
+}
+
 fn sign_receivers_contract_txes(
     receivers_contract_txes: &[Transaction],
     outgoing_swapcoins: &[OutgoingSwapCoin],
@@ -1487,14 +2099,19 @@ fn check_and_apply_maker_private_keys<S: SwapCoin>(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn settle_all_coinswaps_send_hash_preimage_and_privkeys(
+    connections: &mut MakerConnections,
     config: &TakerConfig,
     preimage: Preimage,
     active_maker_addresses: &[&MakerAddress],
     outgoing_swapcoins: &[OutgoingSwapCoin],
     watchonly_swapcoins: &mut [Vec<WatchOnlySwapCoin>],
     incoming_swapcoins: &mut [IncomingSwapCoin],
+    progress_tx: &Option<Sender<TakerEvent>>,
 ) -> Result<(), Error> {
+    //from here on the preimage is given out, there's no going back
+    send_progress(progress_tx, TakerEvent::HashPreimageRevealed);
     let mut outgoing_privkeys: Option<Vec<SwapCoinPrivateKey>> = None;
     for (index, maker_address) in active_maker_addresses.iter().enumerate() {
         let is_taker_previous_peer = index == 0;
@@ -1516,6 +2133,7 @@ async fn settle_all_coinswaps_send_hash_preimage_and_privkeys(
             ii += 1;
             select! {
                 ret = settle_one_coinswap(
+                    connections,
                     maker_address,
                     index,
                     is_taker_previous_peer,
@@ -1529,12 +2147,13 @@ async fn settle_all_coinswaps_send_hash_preimage_and_privkeys(
                     preimage,
                 ) => {
                     if let Err(e) = ret {
-                        log::warn!(
+                        tracing::warn!(
                             "Failed to connect to maker {} to settle coinswap, \
                             reattempting... error={:?}",
                             maker_address,
                             e
                         );
+                        connections.close(maker_address);
                         if ii <= RECONNECT_ATTEMPTS {
                             sleep(Duration::from_secs(
                                 if ii <= SHORT_LONG_SLEEP_DELAY_TRANSITION {
@@ -1549,10 +2168,16 @@ async fn settle_all_coinswaps_send_hash_preimage_and_privkeys(
                             return Err(e);
                         }
                     }
+                    send_progress(
+                        progress_tx,
+                        TakerEvent::PrivateKeysHandedOver {
+                            maker_address: maker_address.to_string(),
+                        },
+                    );
                     break;
                 },
-                _ = sleep(Duration::from_secs(RECONNECT_ATTEMPT_TIMEOUT_SEC)) => {
-                    log::warn!(
+                _ = sleep(Duration::from_secs(taker_settings().private_key_handover_timeout_sec)) => {
+                    tracing::warn!(
                         "Timeout for settling coinswap with maker {}, reattempting...",
                         maker_address
                     );
@@ -1569,7 +2194,9 @@ async fn settle_all_coinswaps_send_hash_preimage_and_privkeys(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn settle_one_coinswap(
+    connections: &mut MakerConnections,
     maker_address: &MakerAddress,
     index: usize,
     is_taker_previous_peer: bool,
@@ -1582,21 +2209,16 @@ async fn settle_one_coinswap(
     receivers_multisig_redeemscripts: &[Script],
     preimage: Preimage,
 ) -> Result<(), Error> {
-    log::info!("Connecting to {}", maker_address);
-    let mut socket = TcpStream::connect(maker_address.get_tcpstream_address()).await?;
-    let (mut socket_reader, mut socket_writer) =
-        handshake_maker(&mut socket, maker_address).await?;
-
-    log::info!("===> Sending HashPreimage to {}", maker_address);
+    tracing::info!("===> Sending HashPreimage to {}", maker_address);
     let maker_private_key_handover = send_hash_preimage_and_get_private_keys(
-        &mut socket_reader,
-        &mut socket_writer,
+        connections,
+        maker_address,
         senders_multisig_redeemscripts,
         receivers_multisig_redeemscripts,
         preimage,
     )
     .await?;
-    log::info!("<=== Received PrivateKeyHandover from {}", maker_address);
+    tracing::info!("<=== Received PrivateKeyHandover from {}", maker_address);
 
     let privkeys_reply = if is_taker_previous_peer {
         outgoing_swapcoins
@@ -1625,40 +2247,42 @@ async fn settle_one_coinswap(
         *outgoing_privkeys = Some(maker_private_key_handover.swapcoin_private_keys);
         ret
     }?;
-    log::info!("===> Sending PrivateKeyHandover to {}", maker_address);
-    send_message(
-        &mut socket_writer,
-        TakerToMakerMessage::PrivateKeyHandover(PrivateKeyHandover {
-            swapcoin_private_keys: privkeys_reply,
-        }),
-    )
-    .await?;
+    tracing::info!("===> Sending PrivateKeyHandover to {}", maker_address);
+    let session_id = connections.session_id(maker_address).await?;
+    connections
+        .send(
+            maker_address,
+            &TakerToMakerMessage::PrivateKeyHandover(PrivateKeyHandover {
+                session_id,
+                swapcoin_private_keys: privkeys_reply,
+            }),
+        )
+        .await?;
+    connections.close(maker_address);
     Ok(())
 }
 
 async fn send_hash_preimage_and_get_private_keys(
-    socket_reader: &mut BufReader<ReadHalf<'_>>,
-    socket_writer: &mut WriteHalf<'_>,
+    connections: &mut MakerConnections,
+    maker_address: &MakerAddress,
     senders_multisig_redeemscripts: &[Script],
     receivers_multisig_redeemscripts: &[Script],
     preimage: Preimage,
 ) -> Result<PrivateKeyHandover, Error> {
     let receivers_multisig_redeemscripts_len = receivers_multisig_redeemscripts.len();
-    send_message(
-        socket_writer,
-        TakerToMakerMessage::HashPreimage(HashPreimage {
-            senders_multisig_redeemscripts: senders_multisig_redeemscripts.to_vec(),
-            receivers_multisig_redeemscripts: receivers_multisig_redeemscripts.to_vec(),
-            preimage,
-        }),
-    )
-    .await?;
-    let maker_private_key_handover =
-        if let MakerToTakerMessage::PrivateKeyHandover(m) = read_message(socket_reader).await? {
-            m
-        } else {
-            return Err(Error::Protocol("expected method privatekeyhandover"));
-        };
+    let session_id = connections.session_id(maker_address).await?;
+    let reply = connections
+        .request(
+            maker_address,
+            &TakerToMakerMessage::HashPreimage(HashPreimage {
+                session_id,
+                senders_multisig_redeemscripts: senders_multisig_redeemscripts.to_vec(),
+                receivers_multisig_redeemscripts: receivers_multisig_redeemscripts.to_vec(),
+                preimage,
+            }),
+        )
+        .await?;
+    let maker_private_key_handover: PrivateKeyHandover = expect_message(reply)?;
     if maker_private_key_handover.swapcoin_private_keys.len()
         != receivers_multisig_redeemscripts_len
     {
@@ -1666,3 +2290,157 @@ async fn send_hash_preimage_and_get_private_keys(
     }
     Ok(maker_private_key_handover)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::str::FromStr;
+    use std::sync::mpsc::channel;
+
+    use crate::offerbook_sync::test_offer_and_address;
+
+    #[test]
+    fn test_check_offer_still_valid() {
+        let offer = test_offer_and_address("localhost:6102", 10_000, 1_000_000).offer;
+        assert!(check_offer_still_valid(&offer, &offer, 500_000).is_ok());
+
+        let cheaper_offer = Offer {
+            absolute_fee_sat: 500,
+            max_size: 600_000,
+            ..offer.clone()
+        };
+        assert!(check_offer_still_valid(&offer, &cheaper_offer, 500_000).is_ok());
+        let dearer_offer = Offer {
+            amount_relative_fee_ppb: offer.amount_relative_fee_ppb + 1,
+            ..offer.clone()
+        };
+        assert!(check_offer_still_valid(&offer, &dearer_offer, 500_000).is_err());
+        let smaller_offer = Offer {
+            max_size: 400_000,
+            ..offer.clone()
+        };
+        assert!(check_offer_still_valid(&offer, &smaller_offer, 500_000).is_err());
+        let stricter_offer = Offer {
+            required_confirms: 2,
+            ..offer.clone()
+        };
+        assert!(check_offer_still_valid(&offer, &stricter_offer, 500_000).is_err());
+        let other_maker_offer = Offer {
+            tweakable_point: PublicKey::from_str(
+                "039b6347398505f5ec93826dc61c19f47c66c0283ee9be980e29ce325a0f4679ef",
+            )
+            .unwrap(),
+            ..offer.clone()
+        };
+        assert!(check_offer_still_valid(&offer, &other_maker_offer, 500_000).is_err());
+
+        let expiring_offer = Offer {
+            expires_at: Some(1000),
+            identity: None,
+            ..offer.clone()
+        };
+        assert!(expiring_offer.is_expired(1001));
+        assert!(!expiring_offer.is_expired(999));
+        assert!(!offer.is_expired(1001));
+    }
+
+    #[test]
+    fn test_check_next_amount() {
+        assert!(check_next_amount(100_000, 100_000).is_ok());
+        assert!(check_next_amount(100_000, 99_990).is_ok());
+        assert!(check_next_amount(100_000, 100_010).is_ok());
+        assert!(check_next_amount(100_000, 99_989).is_err());
+        assert!(check_next_amount(100_000, 100_011).is_err());
+    }
+
+    #[test]
+    fn test_deprioritize_flaky_makers() {
+        let offers = [
+            test_offer_and_address("maker-a", 10_000, 1_000_000),
+            test_offer_and_address("maker-b", 10_000, 1_000_000),
+            test_offer_and_address("maker-c", 10_000, 1_000_000),
+        ];
+        let mut uptime = MakerUptime::default();
+        uptime.record("maker-a", false, 0);
+        uptime.record("maker-c", true, 0);
+        let mut maker_offers_addresses = offers.iter().collect::<Vec<&OfferAndAddress>>();
+        deprioritize_makers(&mut maker_offers_addresses, &uptime, &[]);
+        let order = maker_offers_addresses
+            .iter()
+            .map(|o| o.address.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(order, vec!["maker-a", "maker-b", "maker-c"]);
+        let mut maker_offers_addresses = RouteCandidates::new(maker_offers_addresses, Vec::new());
+        assert_eq!(
+            choose_next_maker(&mut maker_offers_addresses, 500_000)
+                .unwrap()
+                .address
+                .to_string(),
+            "maker-c"
+        );
+
+        //a maker used earlier in a tumble goes even before a flaky one
+        let mut maker_offers_addresses = offers.iter().collect::<Vec<&OfferAndAddress>>();
+        deprioritize_makers(
+            &mut maker_offers_addresses,
+            &uptime,
+            &["maker-c".to_string()],
+        );
+        let order = maker_offers_addresses
+            .iter()
+            .map(|o| o.address.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(order, vec!["maker-c", "maker-a", "maker-b"]);
+    }
+
+    #[tokio::test]
+    async fn test_receive_offers() {
+        let config = TakerConfig {
+            send_amount: 500_000,
+            maker_count: 1,
+            tx_count: 3,
+            required_confirms: 1,
+            fee_rate: 1000,
+            min_funding_tx_fee_rate: 1000,
+            account: None,
+        };
+        let (offers_writer, offers_reader) = mpsc::channel(10);
+        for ii in 0..5 {
+            //the second offer is too small for the coinswap amount
+            let max_size = if ii == 1 { 100_000 } else { 1_000_000 };
+            offers_writer
+                .send(test_offer_and_address(
+                    &format!("localhost:{}", ii),
+                    10_000,
+                    max_size,
+                ))
+                .await
+                .unwrap();
+        }
+        let (progress_tx, progress_rx) = channel();
+        let offers = receive_offers(offers_reader, &config, &Some(progress_tx)).await;
+        assert_eq!(offers.len(), USABLE_OFFERS_PER_ROUTE_MAKER + 1);
+        assert_eq!(progress_rx.try_iter().count(), offers.len());
+
+        //every offer is waited for if there arent enough
+        let config = TakerConfig {
+            maker_count: 2,
+            ..config
+        };
+        let (offers_writer, offers_reader) = mpsc::channel(10);
+        for ii in 0..2 {
+            offers_writer
+                .send(test_offer_and_address(
+                    &format!("localhost:{}", ii),
+                    10_000,
+                    1_000_000,
+                ))
+                .await
+                .unwrap();
+        }
+        drop(offers_writer);
+        let offers = receive_offers(offers_reader, &config, &None).await;
+        assert_eq!(offers.len(), 2);
+    }
+}
@@ -0,0 +1,323 @@
+//helpers for integration tests on regtest, built with the test-framework feature
+//
+//by default a regtest bitcoind must already be running with a wallet called "teleport"
+// loaded, as set up by tests/init.sh. it is reached with the usual settings, so a node with
+// rpc credentials or a cookie file outside the default location can be used by setting
+// TELEPORT_RPC_USER and TELEPORT_RPC_PASSWORD or TELEPORT_RPC_COOKIE_FILE, or by passing
+// SettingsOverrides to TestFramework::with_overrides
+//if TELEPORT_TEST_BITCOIND is the path of a bitcoind binary then a fresh node is started in
+// the test's temporary directory instead and stopped when the TestFramework is dropped
+//
+//    let mut framework = TestFramework::new();
+//    let mut maker_wallet = framework.create_wallet("maker-wallet");
+//    framework.fund_wallet(&mut maker_wallet, 3, Amount::from_btc(0.05).unwrap());
+//    framework.generate_blocks(1);
+//    framework.start_watchtower();
+//    framework.start_maker("maker-wallet", "127.0.0.1:6102", MakerBehavior::Normal);
+//    framework.start_block_generation(Duration::from_secs(5));
+//    ... run a taker ...
+//    framework.stop();
+//
+//the settings are global, so there can only be one TestFramework in each test binary
+
+use std::env;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use bitcoin::{Address, Amount, Network};
+use bitcoin_wallet::mnemonic;
+use bitcoincore_rpc::RpcApi;
+use serde_json::{json, Value};
+use tempfile::{tempdir, TempDir};
+
+use crate::fidelity_bonds::YearAndMonth;
+use crate::maker_protocol::MakerBehavior;
+use crate::rpc::Client;
+use crate::settings::{Settings, SettingsOverrides};
+use crate::wallet_sync::{Wallet, WalletSyncAddressAmount};
+use crate::{get_bitcoin_rpc, run_maker, run_watchtower, setup_teleport};
+
+pub const BITCOIND_ENV_VAR: &str = "TELEPORT_TEST_BITCOIND";
+const BITCOIND_STARTUP_TIMEOUT_SECS: u64 = 30;
+const WATCHTOWER_DATA_FILE_NAME: &str = "watchtower.dat";
+
+pub struct TestFramework {
+    pub rpc: Arc<Client>,
+    data_dir: TempDir,
+    kill_flag: Arc<RwLock<bool>>,
+    threads: Vec<JoinHandle<()>>,
+    bitcoind: Option<Child>,
+}
+
+impl TestFramework {
+    /// Initialize the settings with a temporary data directory and connect to the
+    /// regtest node, starting one first if TELEPORT_TEST_BITCOIND is set
+    pub fn new() -> TestFramework {
+        TestFramework::with_overrides(SettingsOverrides::default())
+    }
+
+    /// Like new() but with settings overridden as if given on the command line, the rpc
+    /// settings are replaced by those of the started node if TELEPORT_TEST_BITCOIND is set
+    pub fn with_overrides(overrides: SettingsOverrides) -> TestFramework {
+        assert!(
+            !Settings::is_initialized(),
+            "only one TestFramework per test binary"
+        );
+        let data_dir = tempdir().expect("error making temporary directory");
+        let (bitcoind, overrides) = match env::var_os(BITCOIND_ENV_VAR) {
+            Some(bitcoind_path) => {
+                let (bitcoind, node_overrides) =
+                    start_bitcoind(&bitcoind_path.into(), data_dir.path());
+                let overrides = SettingsOverrides {
+                    rpc_wallet_file: overrides.rpc_wallet_file,
+                    tor_only: overrides.tor_only,
+                    ..node_overrides
+                };
+                (Some(bitcoind), overrides)
+            }
+            None => (None, overrides),
+        };
+        Settings::init_settings(data_dir.path(), &overrides).unwrap();
+        setup_teleport();
+
+        let rpc = if bitcoind.is_some() {
+            let rpc = wait_for_bitcoind();
+            create_core_wallet(&rpc);
+            let (rpc, _network) = get_bitcoin_rpc().unwrap();
+            //coinbase outputs can be spent after 100 blocks
+            let address = rpc.get_new_address(None, None).unwrap();
+            rpc.generate_to_address(101, &address).unwrap();
+            rpc
+        } else {
+            let (rpc, network) = get_bitcoin_rpc().unwrap();
+            assert_eq!(network, Network::Regtest);
+            rpc
+        };
+        //unlock all utxos to avoid "insufficient fund" errors from earlier runs
+        rpc.call::<Value>("lockunspent", &[Value::Bool(true)])
+            .unwrap();
+
+        TestFramework {
+            rpc: Arc::new(rpc),
+            data_dir,
+            kill_flag: Arc::new(RwLock::new(false)),
+            threads: Vec::new(),
+            bitcoind,
+        }
+    }
+
+    /// Teleport's data directory, the wallets are in its "wallets" subdirectory
+    pub fn data_dir(&self) -> &Path {
+        self.data_dir.path()
+    }
+
+    /// Set to true by stop(), to end the makers and watchtower
+    pub fn kill_flag(&self) -> Arc<RwLock<bool>> {
+        Arc::clone(&self.kill_flag)
+    }
+
+    pub fn generate_blocks(&self, count: u64) {
+        let address = self.rpc.get_new_address(None, None).unwrap();
+        self.rpc.generate_to_address(count, &address).unwrap();
+    }
+
+    /// Create a wallet file with a random seed phrase and import its addresses into the node
+    pub fn create_wallet(&self, wallet_file_name: &str) -> Wallet {
+        let mnemonic =
+            mnemonic::Mnemonic::new_random(bitcoin_wallet::account::MasterKeyEntropy::Sufficient)
+                .unwrap();
+        Wallet::save_new_wallet_file(
+            wallet_file_name,
+            mnemonic.to_string(),
+            "".to_string(),
+            self.rpc.get_block_count().ok(),
+        )
+        .unwrap();
+        let wallet = Wallet::load_wallet_from_file(
+            wallet_file_name,
+            Network::Regtest,
+            WalletSyncAddressAmount::Testing,
+        )
+        .unwrap();
+        wallet
+            .import_initial_addresses(
+                &self.rpc,
+                &wallet
+                    .get_hd_wallet_descriptors(&self.rpc)
+                    .unwrap()
+                    .iter()
+                    .collect::<Vec<&String>>(),
+                &Vec::<_>::new(),
+                &Vec::<_>::new(),
+            )
+            .unwrap();
+        wallet
+    }
+
+    /// Send amount to each of the wallet's next utxo_count external addresses, the
+    /// coins are unconfirmed until a block is generated
+    pub fn fund_wallet(&self, wallet: &mut Wallet, utxo_count: u32, amount: Amount) {
+        for _ in 0..utxo_count {
+            let address = wallet.get_next_external_address(&self.rpc).unwrap();
+            self.send_to_address(&address, amount);
+        }
+    }
+
+    pub fn fund_fidelity_bond(&self, wallet: &Wallet, locktime: &YearAndMonth, amount: Amount) {
+        let address = wallet.get_timelocked_address(locktime).0;
+        self.send_to_address(&address, amount);
+    }
+
+    fn send_to_address(&self, address: &Address, amount: Amount) {
+        self.rpc
+            .send_to_address(address, amount, None, None, None, None, None, None)
+            .unwrap();
+    }
+
+    pub fn start_watchtower(&mut self) {
+        let data_file_path = self.data_dir().join(WATCHTOWER_DATA_FILE_NAME);
+        let kill_flag = self.kill_flag();
+        self.threads.push(thread::spawn(move || {
+            run_watchtower(&data_file_path, Some(kill_flag));
+        }));
+    }
+
+    /// Run a maker with the wallet file in a thread, listen_address is ip:port or unix:<path>
+    pub fn start_maker(
+        &mut self,
+        wallet_file_name: &str,
+        listen_address: &str,
+        maker_behavior: MakerBehavior,
+    ) {
+        let wallet_file_name = PathBuf::from(wallet_file_name);
+        let listen_address = listen_address.parse().unwrap();
+        let kill_flag = self.kill_flag();
+        self.threads.push(thread::spawn(move || {
+            run_maker(
+                &wallet_file_name,
+                WalletSyncAddressAmount::Testing,
+                vec![listen_address],
+                maker_behavior,
+                Some(kill_flag),
+            );
+        }));
+    }
+
+    /// Generate a block every interval until stop() is called
+    pub fn start_block_generation(&mut self, interval: Duration) {
+        let rpc = Arc::clone(&self.rpc);
+        let kill_flag = self.kill_flag();
+        self.threads.push(thread::spawn(move || {
+            while !*kill_flag.read().unwrap() {
+                thread::sleep(interval);
+                let address = rpc.get_new_address(None, None).unwrap();
+                rpc.generate_to_address(1, &address).unwrap();
+                println!("created block");
+            }
+            println!("ending block creation thread");
+        }));
+    }
+
+    /// Set the kill flag and wait for every thread started by the framework to end
+    pub fn stop(&mut self) {
+        *self.kill_flag.write().unwrap() = true;
+        for thread in self.threads.drain(..) {
+            thread.join().unwrap();
+        }
+    }
+}
+
+impl Default for TestFramework {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TestFramework {
+    fn drop(&mut self) {
+        *self.kill_flag.write().unwrap() = true;
+        if let Some(bitcoind) = self.bitcoind.as_mut() {
+            let _ = bitcoind.kill();
+            let _ = bitcoind.wait();
+        }
+    }
+}
+
+fn unused_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+//the node gets its own directory, ports and rpc credentials so it doesnt touch any other node
+fn start_bitcoind(bitcoind_path: &PathBuf, data_dir: &Path) -> (Child, SettingsOverrides) {
+    let bitcoind_data_dir = data_dir.join("bitcoind");
+    std::fs::create_dir(&bitcoind_data_dir).unwrap();
+    let rpc_port = unused_port();
+    let rpc_password = format!("{:x}", rand::random::<u64>());
+    let bitcoind = Command::new(bitcoind_path)
+        .arg("-regtest")
+        .arg(format!("-datadir={}", bitcoind_data_dir.display()))
+        .arg(format!("-port={}", unused_port()))
+        .arg(format!("-rpcport={}", rpc_port))
+        .arg("-rpcuser=teleport")
+        .arg(format!("-rpcpassword={}", rpc_password))
+        .arg("-listen=0")
+        .arg("-fallbackfee=0.0001")
+        //newer versions only make the legacy wallets which teleport uses with this
+        .arg("-deprecatedrpc=create_bdb")
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("unable to start bitcoind");
+    let overrides = SettingsOverrides {
+        network: Some("regtest".to_string()),
+        rpc_host: Some("127.0.0.1".to_string()),
+        rpc_port: Some(rpc_port),
+        rpc_user: Some("teleport".to_string()),
+        rpc_password: Some(rpc_password),
+        ..SettingsOverrides::default()
+    };
+    (bitcoind, overrides)
+}
+
+//returns a client for the node itself, the wallet doesnt exist yet
+fn wait_for_bitcoind() -> Client {
+    let blockchain_settings = &Settings::global().blockchain;
+    let (user, pass) = blockchain_settings.rpc_userpass().unwrap();
+    let url = format!(
+        "http://{}:{}",
+        blockchain_settings.rpc_host, blockchain_settings.rpc_port
+    );
+    let rpc = Client::new(url, bitcoincore_rpc::Auth::UserPass(user, pass)).unwrap();
+    let start = Instant::now();
+    while rpc.get_blockchain_info().is_err() {
+        if start.elapsed() > Duration::from_secs(BITCOIND_STARTUP_TIMEOUT_SECS) {
+            panic!("bitcoind didnt start");
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+    rpc
+}
+
+fn create_core_wallet(rpc: &Client) {
+    let wallet_name = &Settings::global().blockchain.rpc_wallet_file;
+    //wallet_name, disable_private_keys, blank, passphrase, avoid_reuse, descriptors
+    rpc.call::<Value>(
+        "createwallet",
+        &[
+            json!(wallet_name),
+            json!(false),
+            json!(false),
+            json!(""),
+            json!(false),
+            json!(false),
+        ],
+    )
+    .unwrap();
+}
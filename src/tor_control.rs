@@ -0,0 +1,160 @@
+//minimal client for tor's control port, used by makers to create onion services
+//https://spec.torproject.org/control-spec/
+
+use std::path::PathBuf;
+
+use bitcoin::hashes::hex::ToHex;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+use crate::error::Error;
+
+pub struct TorControl {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl TorControl {
+    //authenticates with the password if given, otherwise with the cookie file or no auth
+    pub async fn connect(control_address: &str, password: Option<&str>) -> Result<Self, Error> {
+        let (reader, writer) = TcpStream::connect(control_address).await?.into_split();
+        let mut control = TorControl {
+            reader: BufReader::new(reader),
+            writer,
+        };
+        let auth = match password {
+            Some(password) => format!("AUTHENTICATE \"{}\"", escape(password)),
+            None => {
+                let protocol_info = control.command("PROTOCOLINFO 1").await?;
+                let auth_line = protocol_info
+                    .iter()
+                    .find(|line| line.starts_with("AUTH "))
+                    .ok_or(Error::Protocol("tor control gave no auth methods"))?;
+                match find_value(auth_line, "COOKIEFILE") {
+                    Some(cookie_file) if !auth_line.contains("NULL") => {
+                        let cookie = tokio::fs::read(PathBuf::from(cookie_file)).await?;
+                        format!("AUTHENTICATE {}", cookie.to_hex())
+                    }
+                    _ => "AUTHENTICATE".to_string(),
+                }
+            }
+        };
+        control.command(&auth).await?;
+        Ok(control)
+    }
+
+    //returns the reply lines without their status codes
+    async fn command(&mut self, command: &str) -> Result<Vec<String>, Error> {
+        self.writer
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await?;
+        let mut reply = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).await? == 0 {
+                return Err(Error::Protocol("tor control connection closed"));
+            }
+            let line = line.trim_end();
+            if line.len() < 4 {
+                return Err(Error::Protocol("tor control reply too short"));
+            }
+            let (status, separator, text) = (&line[..3], &line[3..4], &line[4..]);
+            if status != "250" {
+                tracing::warn!("tor control error reply: {}", line);
+                return Err(Error::Protocol("tor control command failed"));
+            }
+            reply.push(text.to_string());
+            //a space after the status code marks the last line of the reply
+            if separator == " " {
+                return Ok(reply);
+            }
+        }
+    }
+
+    //creates an onion service forwarding virtual_port to target, e.g. 127.0.0.1:6102 or
+    // unix:/path, and returns its hostname. it is removed when this connection closes
+    pub async fn add_onion(&mut self, virtual_port: u16, target: &str) -> Result<String, Error> {
+        let reply = self
+            .command(&format!(
+                "ADD_ONION NEW:ED25519-V3 Flags=DiscardPK Port={},{}",
+                virtual_port, target
+            ))
+            .await?;
+        reply
+            .iter()
+            .find_map(|line| line.strip_prefix("ServiceID="))
+            .map(|service_id| format!("{}.onion", service_id))
+            .ok_or(Error::Protocol("tor control gave no onion service id"))
+    }
+
+    pub async fn del_onion(&mut self, hostname: &str) -> Result<(), Error> {
+        let service_id = hostname.trim_end_matches(".onion");
+        self.command(&format!("DEL_ONION {}", service_id)).await?;
+        Ok(())
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+//finds KEY="value" in a reply line
+fn find_value<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let start = line.find(&format!("{}=\"", key))? + key.len() + 2;
+    let len = line[start..].find('"')?;
+    Some(&line[start..start + len])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_find_value() {
+        let line = r#"AUTH METHODS=COOKIE,SAFECOOKIE COOKIEFILE="/run/tor/control.authcookie""#;
+        assert_eq!(
+            find_value(line, "COOKIEFILE"),
+            Some("/run/tor/control.authcookie")
+        );
+        assert_eq!(find_value(line, "METHODS"), None);
+    }
+
+    #[tokio::test]
+    async fn test_tor_control() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let control_address = listener.local_addr().unwrap().to_string();
+        //pretends to be tor, replying to the commands the client is expected to send
+        let fake_tor = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = socket.into_split();
+            let mut reader = BufReader::new(reader);
+            let exchanges = [
+                ("AUTHENTICATE \"pass\\\"word\"", "250 OK\r\n"),
+                (
+                    "ADD_ONION NEW:ED25519-V3 Flags=DiscardPK Port=6102,127.0.0.1:16102",
+                    "250-ServiceID=abcdef\r\n250 OK\r\n",
+                ),
+                ("DEL_ONION abcdef", "250 OK\r\n"),
+                ("DEL_ONION abcdef", "552 Unknown Onion Service id\r\n"),
+            ];
+            for (expected_command, reply) in exchanges.iter() {
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                assert_eq!(line.trim_end(), *expected_command);
+                writer.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+
+        let mut control = TorControl::connect(&control_address, Some("pass\"word"))
+            .await
+            .unwrap();
+        let hostname = control.add_onion(6102, "127.0.0.1:16102").await.unwrap();
+        assert_eq!(hostname, "abcdef.onion");
+        control.del_onion(&hostname).await.unwrap();
+        assert!(control.del_onion(&hostname).await.is_err());
+        fake_tor.await.unwrap();
+    }
+}
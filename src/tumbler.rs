@@ -0,0 +1,369 @@
+//runs a series of coinswaps spread out over time, like joinmarket's tumbler, so the coins
+// cant be followed by amount or by when they moved
+//each coinswap has a random amount, starts a random delay after the previous one finished
+// and prefers makers none of the earlier coinswaps went through. the last coinswaps send the
+// coins they made on to the destination addresses after another delay, the others keep them
+// in the wallet
+//the schedule is saved after every step so a tumble which was stopped carries on where it was
+// when started again
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use bitcoin::{Address, OutPoint};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::fan_out::{send_coins_to_destination, swapcoin_values};
+use crate::rpc::Client;
+use crate::taker_protocol::{start_taker_avoiding_makers, TakerConfig, TakerEvent};
+use crate::utils::teleport_data_dir;
+use crate::wallet_sync::Wallet;
+
+//a failed coinswap is tried again after a new delay, the tumble stops after this many tries
+const MAX_COINSWAP_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone)]
+pub struct TumbleParams {
+    pub amount: u64,
+    pub coinswap_count: usize,
+    pub maker_count: u16,
+    pub tx_count: u32,
+    pub min_delay_secs: u64,
+    pub max_delay_secs: u64,
+    pub destinations: Vec<Address>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum CoinswapState {
+    Pending,
+    //the coins it made still have to be sent to the destination
+    Swapped { coins: Vec<OutPoint> },
+    Done { completed_timestamp: i64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledCoinswap {
+    pub amount: u64,
+    pub maker_count: u16,
+    pub tx_count: u32,
+    //waited after the previous coinswap finished
+    pub delay_secs: u64,
+    //None keeps the coins in the wallet
+    pub destination: Option<String>,
+    //makers it went through, avoided by the coinswaps after it
+    pub makers: Vec<String>,
+    pub attempts: u32,
+    #[serde(flatten)]
+    pub state: CoinswapState,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TumbleSchedule {
+    pub coinswaps: Vec<ScheduledCoinswap>,
+    //the next step doesnt start before this unix time
+    pub next_step_timestamp: i64,
+}
+
+impl TumbleSchedule {
+    /// Split params.amount randomly between params.coinswap_count coinswaps, one destination
+    /// each for the last coinswaps
+    pub fn generate<R: Rng>(params: &TumbleParams, now: i64, rng: &mut R) -> TumbleSchedule {
+        let weights = (0..params.coinswap_count)
+            .map(|_| rng.gen_range(1.0, 2.0))
+            .collect::<Vec<f64>>();
+        let total_weight = weights.iter().sum::<f64>();
+        let mut amounts = weights
+            .iter()
+            .map(|w| (params.amount as f64 * w / total_weight) as u64)
+            .collect::<Vec<u64>>();
+        //rounding leaves a few sats over
+        let left_over = params.amount - amounts.iter().sum::<u64>();
+        if let Some(last) = amounts.last_mut() {
+            *last += left_over;
+        }
+        let first_destination = params
+            .coinswap_count
+            .saturating_sub(params.destinations.len());
+        let coinswaps = amounts
+            .into_iter()
+            .enumerate()
+            .map(|(i, amount)| ScheduledCoinswap {
+                amount,
+                maker_count: params.maker_count,
+                tx_count: params.tx_count,
+                delay_secs: rng.gen_range(params.min_delay_secs, params.max_delay_secs + 1),
+                destination: i
+                    .checked_sub(first_destination)
+                    .and_then(|d| params.destinations.get(d))
+                    .map(|a| a.to_string()),
+                makers: Vec::new(),
+                attempts: 0,
+                state: CoinswapState::Pending,
+            })
+            .collect();
+        //the first coinswap starts right away
+        TumbleSchedule {
+            coinswaps,
+            next_step_timestamp: now,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<TumbleSchedule, Error> {
+        Ok(serde_json::from_reader(File::open(path)?).map_err(io::Error::from)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        serde_json::to_writer_pretty(File::create(path)?, self).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    /// Index of the first coinswap which isnt done, None once the tumble finished
+    pub fn current(&self) -> Option<usize> {
+        self.coinswaps
+            .iter()
+            .position(|c| !matches!(c.state, CoinswapState::Done { .. }))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.current().is_none()
+    }
+
+    /// Makers of the coinswaps before index
+    pub fn used_makers(&self, index: usize) -> Vec<String> {
+        self.coinswaps[..index]
+            .iter()
+            .flat_map(|c| c.makers.iter().cloned())
+            .collect()
+    }
+
+    fn complete_step(&mut self, index: usize, state: CoinswapState, now: i64) {
+        self.coinswaps[index].state = state;
+        if let Some(next) = self.current() {
+            self.next_step_timestamp = now + self.coinswaps[next].delay_secs as i64;
+        }
+    }
+}
+
+/// Path of the tumble schedule of a wallet in the data directory
+pub fn tumble_schedule_path(wallet_file_name: &Path) -> PathBuf {
+    let mut file_name = wallet_file_name
+        .file_name()
+        .unwrap_or(wallet_file_name.as_os_str())
+        .to_os_string();
+    file_name.push(".tumble.json");
+    teleport_data_dir().join(file_name)
+}
+
+fn swapcoin_outpoints(rpc: &Client, wallet: &Wallet) -> Result<Vec<OutPoint>, Error> {
+    Ok(swapcoin_values(rpc, wallet)?
+        .into_iter()
+        .map(|(coin, _)| coin)
+        .collect())
+}
+
+//sleeps until the schedule's next step is due
+fn wait_for_next_step(schedule: &TumbleSchedule) {
+    let wait_secs = schedule.next_step_timestamp - chrono::Utc::now().timestamp();
+    if wait_secs > 0 {
+        tracing::info!("waiting {} seconds before the next tumble step", wait_secs);
+        std::thread::sleep(Duration::from_secs(wait_secs as u64));
+    }
+}
+
+/// Run the tumble saved at schedule_path until every coinswap is done, saving its progress
+/// after each step
+pub fn run_tumble(
+    rpc: &Client,
+    wallet: &mut Wallet,
+    schedule_path: &Path,
+    fee_rate: u64,
+    min_funding_tx_fee_rate: u64,
+) -> Result<(), Error> {
+    let mut schedule = TumbleSchedule::load(schedule_path)?;
+    while let Some(index) = schedule.current() {
+        wait_for_next_step(&schedule);
+        let coinswap = schedule.coinswaps[index].clone();
+        tracing::info!(
+            "tumble coinswap {} of {}, amount = {} sat",
+            index + 1,
+            schedule.coinswaps.len(),
+            coinswap.amount
+        );
+        let now = chrono::Utc::now().timestamp();
+        match coinswap.state {
+            CoinswapState::Pending => {
+                let swapcoins_before = swapcoin_outpoints(rpc, wallet)?;
+                let (progress_tx, progress_rx) = mpsc::channel();
+                let result = start_taker_avoiding_makers(
+                    rpc,
+                    wallet,
+                    TakerConfig {
+                        send_amount: coinswap.amount,
+                        maker_count: coinswap.maker_count,
+                        tx_count: coinswap.tx_count,
+                        required_confirms: 1,
+                        fee_rate,
+                        min_funding_tx_fee_rate,
+                        account: None,
+                    },
+                    &schedule.used_makers(index),
+                    Some(progress_tx),
+                );
+                let mut completed = false;
+                for event in progress_rx.try_iter() {
+                    match event {
+                        TakerEvent::MakerChosen { maker_address } => {
+                            schedule.coinswaps[index].makers.push(maker_address)
+                        }
+                        TakerEvent::CoinswapComplete => completed = true,
+                        _ => (),
+                    }
+                }
+                schedule.coinswaps[index].attempts += 1;
+                if let (Ok(()), true) = (&result, completed) {
+                    let state = match coinswap.destination {
+                        Some(_) => CoinswapState::Swapped {
+                            coins: swapcoin_outpoints(rpc, wallet)?
+                                .into_iter()
+                                .filter(|c| !swapcoins_before.contains(c))
+                                .collect(),
+                        },
+                        None => CoinswapState::Done {
+                            completed_timestamp: now,
+                        },
+                    };
+                    schedule.complete_step(index, state, now);
+                } else {
+                    tracing::warn!("tumble coinswap failed, error = {:?}", result.err());
+                    //the makers it went through may have been the problem
+                    schedule.coinswaps[index].makers.clear();
+                    if schedule.coinswaps[index].attempts >= MAX_COINSWAP_ATTEMPTS {
+                        schedule.save(schedule_path)?;
+                        return Err(Error::Protocol("tumble coinswap failed too many times"));
+                    }
+                    schedule.next_step_timestamp = now + coinswap.delay_secs as i64;
+                }
+            }
+            CoinswapState::Swapped { coins } => {
+                //unwrap ok because only coinswaps with a destination are swapped
+                let destination = coinswap.destination.as_ref().unwrap();
+                if coins.is_empty() {
+                    tracing::warn!("no coins from the coinswap left to send to {}", destination);
+                } else {
+                    let txid =
+                        send_coins_to_destination(rpc, wallet, fee_rate, &coins, destination)?;
+                    tracing::info!("sent coinswapped coins to destination in {}", txid);
+                }
+                schedule.complete_step(
+                    index,
+                    CoinswapState::Done {
+                        completed_timestamp: now,
+                    },
+                    now,
+                );
+            }
+            CoinswapState::Done { .. } => unreachable!(),
+        }
+        schedule.save(schedule_path)?;
+    }
+    tracing::info!("tumble finished");
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn params(destinations: Vec<Address>) -> TumbleParams {
+        TumbleParams {
+            amount: 1_000_000,
+            coinswap_count: 4,
+            maker_count: 2,
+            tx_count: 3,
+            min_delay_secs: 60,
+            max_delay_secs: 600,
+            destinations,
+        }
+    }
+
+    #[test]
+    fn test_generate_schedule() {
+        let destination = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        let schedule = TumbleSchedule::generate(&params(vec![destination.clone()]), 1000, &mut rng);
+        assert_eq!(schedule.coinswaps.len(), 4);
+        assert_eq!(
+            schedule.coinswaps.iter().map(|c| c.amount).sum::<u64>(),
+            1_000_000
+        );
+        for coinswap in &schedule.coinswaps {
+            //every weight is between 1 and 2 so no coinswap is more than twice another
+            assert!(coinswap.amount > 1_000_000 / 8);
+            assert!(coinswap.delay_secs >= 60 && coinswap.delay_secs <= 600);
+            assert_eq!(coinswap.state, CoinswapState::Pending);
+        }
+        let destinations = schedule
+            .coinswaps
+            .iter()
+            .map(|c| c.destination.clone())
+            .collect::<Vec<Option<String>>>();
+        assert_eq!(
+            destinations,
+            vec![None, None, None, Some(destination.to_string())]
+        );
+        assert_eq!(schedule.next_step_timestamp, 1000);
+    }
+
+    #[test]
+    fn test_schedule_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("taker.teleport.tumble.json");
+        let mut schedule =
+            TumbleSchedule::generate(&params(Vec::new()), 0, &mut rand::thread_rng());
+        assert_eq!(schedule.current(), Some(0));
+
+        schedule.coinswaps[0].makers = vec!["maker-a".to_string(), "maker-b".to_string()];
+        schedule.complete_step(
+            0,
+            CoinswapState::Done {
+                completed_timestamp: 100,
+            },
+            100,
+        );
+        assert_eq!(schedule.current(), Some(1));
+        assert_eq!(
+            schedule.next_step_timestamp,
+            100 + schedule.coinswaps[1].delay_secs as i64
+        );
+        assert_eq!(schedule.used_makers(1), vec!["maker-a", "maker-b"]);
+        schedule.complete_step(1, CoinswapState::Swapped { coins: Vec::new() }, 200);
+        assert_eq!(schedule.current(), Some(1));
+
+        //resuming after a restart carries on from the same step
+        schedule.save(&path).unwrap();
+        let loaded = TumbleSchedule::load(&path).unwrap();
+        assert_eq!(loaded, schedule);
+
+        for i in 1..4 {
+            schedule.complete_step(
+                i,
+                CoinswapState::Done {
+                    completed_timestamp: 300,
+                },
+                300,
+            );
+        }
+        assert!(schedule.is_finished());
+    }
+}
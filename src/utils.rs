@@ -37,8 +37,9 @@ pub fn teleport_data_dir() -> PathBuf {
 pub fn bitcoin_data_dir(network: &str) -> PathBuf {
     let bitcoin_dir = default_data_dir("bitcoin");
     let network_subdir = match network {
-        "main" => "",
-        "testnet" => "testnet3",
+        "main" | "mainnet" => "",
+        "test" | "testnet" => "testnet3",
+        "testnet4" => "testnet4",
         _ => network,
     };
     bitcoin_dir.join(network_subdir)
@@ -52,11 +53,13 @@ mod test {
     fn test_bitcoin_data_dir() {
         let main_dir = bitcoin_data_dir("main");
         let testnet_dir = bitcoin_data_dir("testnet");
+        let testnet4_dir = bitcoin_data_dir("testnet4");
         let signet_dir = bitcoin_data_dir("signet");
         let regtest_dir = bitcoin_data_dir("regtest");
 
         assert_eq!(main_dir, default_data_dir("bitcoin"));
         assert_eq!(testnet_dir, default_data_dir("bitcoin").join("testnet3"));
+        assert_eq!(testnet4_dir, default_data_dir("bitcoin").join("testnet4"));
         assert_eq!(signet_dir, default_data_dir("bitcoin").join("signet"));
         assert_eq!(regtest_dir, default_data_dir("bitcoin").join("regtest"));
     }
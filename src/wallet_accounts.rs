@@ -0,0 +1,192 @@
+//the wallet's coins are kept in separate accounts, like joinmarket's mixdepths, so that coins
+// of different origins are never spent together and linked on the blockchain
+//  receive  coins paid to the wallet's receive addresses
+//  swap     coins the wallet got from coinswaps, and the contracts of unfinished ones
+//  change   change outputs of the wallet's own transactions
+//a coinswap is funded from one account, given with `do-coinswap --account` or otherwise the
+// one with the biggest balance, and its change goes to the change account
+//a direct send spends the coins of one account too, the ones given or otherwise the biggest coins
+// of the account with the biggest balance which has enough for it
+//fidelity bonds arent in any account
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::Error;
+use crate::wallet_sync::UTXOSpendInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum WalletAccount {
+    Receive,
+    Swap,
+    Change,
+}
+
+pub const WALLET_ACCOUNTS: [WalletAccount; 3] = [
+    WalletAccount::Receive,
+    WalletAccount::Swap,
+    WalletAccount::Change,
+];
+
+impl FromStr for WalletAccount {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "receive" => Ok(WalletAccount::Receive),
+            "swap" => Ok(WalletAccount::Swap),
+            "change" => Ok(WalletAccount::Change),
+            _ => Err("account must be receive, swap or change"),
+        }
+    }
+}
+
+impl fmt::Display for WalletAccount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                WalletAccount::Receive => "receive",
+                WalletAccount::Swap => "swap",
+                WalletAccount::Change => "change",
+            }
+        )
+    }
+}
+
+/// The account of a coin of the wallet, seed coins are in the receive or change account by
+/// the branch of their hd path "m/<branch>/<index>"
+pub fn utxo_account(spend_info: &UTXOSpendInfo) -> Option<WalletAccount> {
+    match spend_info {
+        UTXOSpendInfo::SeedCoin { path, .. } => match path.split('/').nth(1) {
+            Some("0") => Some(WalletAccount::Receive),
+            Some("1") => Some(WalletAccount::Change),
+            _ => None,
+        },
+        UTXOSpendInfo::SwapCoin { .. }
+        | UTXOSpendInfo::TimelockContract { .. }
+        | UTXOSpendInfo::HashlockContract { .. } => Some(WalletAccount::Swap),
+        UTXOSpendInfo::FidelityBondCoin { .. } => None,
+    }
+}
+
+/// Total value of the coins in each account, coins are given as (spend info, value)
+pub fn account_balances<'a, I: Iterator<Item = (&'a UTXOSpendInfo, u64)>>(
+    coins: I,
+) -> Vec<(WalletAccount, u64)> {
+    let mut balances = WALLET_ACCOUNTS
+        .iter()
+        .map(|&account| (account, 0))
+        .collect::<Vec<(WalletAccount, u64)>>();
+    for (spend_info, value) in coins {
+        if let Some(account) = utxo_account(spend_info) {
+            balances.iter_mut().find(|(a, _)| *a == account).unwrap().1 += value;
+        }
+    }
+    balances
+}
+
+/// The coins of each account which has any, coins are given as (spend info, coin). Coins in no
+/// account, fidelity bonds, are left out
+pub fn group_by_account<'a, T, I: Iterator<Item = (&'a UTXOSpendInfo, T)>>(
+    coins: I,
+) -> Vec<(WalletAccount, Vec<T>)> {
+    let mut groups = Vec::<(WalletAccount, Vec<T>)>::new();
+    for (spend_info, coin) in coins {
+        let account = match utxo_account(spend_info) {
+            Some(account) => account,
+            None => continue,
+        };
+        match groups.iter_mut().find(|(a, _)| *a == account) {
+            Some((_, group)) => group.push(coin),
+            None => groups.push((account, vec![coin])),
+        }
+    }
+    groups.sort_by_key(|(account, _)| *account);
+    groups
+}
+
+/// The account a coinswap or direct send of amount is funded from when none is given, the one
+/// with the biggest balance. Fails if no account has enough on its own, as coins of different
+/// accounts are never spent together
+pub fn choose_funding_account(
+    balances: &[(WalletAccount, u64)],
+    amount: u64,
+) -> Result<WalletAccount, Error> {
+    balances
+        .iter()
+        .filter(|(_, balance)| *balance >= amount)
+        .max_by_key(|(_, balance)| *balance)
+        .map(|(account, _)| *account)
+        .ok_or(Error::Protocol(
+            "no account has enough coins for the amount on its own",
+        ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::Script;
+
+    #[test]
+    fn test_wallet_accounts() {
+        let seed_coin = |path: &str| UTXOSpendInfo::SeedCoin {
+            path: path.to_string(),
+            input_value: 0,
+        };
+        let coins = [
+            (seed_coin("m/0/4"), 50_000),
+            (seed_coin("m/0/7"), 20_000),
+            (seed_coin("m/1/2"), 10_000),
+            (
+                UTXOSpendInfo::SwapCoin {
+                    multisig_redeemscript: Script::new(),
+                },
+                60_000,
+            ),
+            (
+                UTXOSpendInfo::FidelityBondCoin {
+                    index: 0,
+                    input_value: 0,
+                },
+                1_000_000,
+            ),
+        ];
+        assert_eq!(utxo_account(&coins[0].0), Some(WalletAccount::Receive));
+        assert_eq!(utxo_account(&coins[2].0), Some(WalletAccount::Change));
+        assert_eq!(utxo_account(&coins[4].0), None);
+
+        let balances = account_balances(coins.iter().map(|(s, v)| (s, *v)));
+        assert_eq!(
+            balances,
+            vec![
+                (WalletAccount::Receive, 70_000),
+                (WalletAccount::Swap, 60_000),
+                (WalletAccount::Change, 10_000),
+            ]
+        );
+        assert_eq!(
+            choose_funding_account(&balances, 30_000).unwrap(),
+            WalletAccount::Receive
+        );
+        //there is enough in the wallet but not in any one account
+        assert!(choose_funding_account(&balances, 100_000).is_err());
+
+        let groups = group_by_account(coins.iter().map(|(s, v)| (s, *v)));
+        assert_eq!(
+            groups,
+            vec![
+                (WalletAccount::Receive, vec![50_000, 20_000]),
+                (WalletAccount::Swap, vec![60_000]),
+                (WalletAccount::Change, vec![10_000]),
+            ]
+        );
+
+        for account in WALLET_ACCOUNTS {
+            assert_eq!(account.to_string().parse::<WalletAccount>(), Ok(account));
+        }
+        assert!("mixdepth".parse::<WalletAccount>().is_err());
+    }
+}
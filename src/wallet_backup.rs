@@ -0,0 +1,160 @@
+//encrypted snapshots of a wallet file, made by `teleport wallet backup` and restored by
+// `teleport wallet restore`
+//the seed phrase alone only recovers the coins of the HD chain, a backup also has the swapcoins
+// of unfinished coinswaps with their keys and contracts, the contract cache and the send and
+// swap history
+//the key comes from a passphrase with PBKDF2-HMAC-SHA256 and the wallet file is encrypted with
+// ChaCha20-Poly1305, the same cipher as the contracts given to watchtowers
+
+use bitcoin::hashes::hex::{FromHex, ToHex};
+use openssl::hash::MessageDigest;
+use openssl::pkcs5::pbkdf2_hmac;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+pub const WALLET_BACKUP_VERSION: u32 = 1;
+pub const DEFAULT_PBKDF2_ITERATIONS: u32 = 200_000;
+//a backup file asking for fewer iterations is weak or broken, for more would hang the restore
+const MIN_PBKDF2_ITERATIONS: u32 = 10_000;
+const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletBackup {
+    pub version: u32,
+    pub created_timestamp: i64,
+    pub pbkdf2_iterations: u32,
+    //hex
+    pub salt: String,
+    //hex of the nonce, the encrypted wallet file and the tag
+    pub encrypted_wallet_file: String,
+}
+
+fn backup_key(passphrase: &str, salt: &[u8], iterations: u32) -> [u8; KEY_LEN] {
+    let mut key = [0u8; KEY_LEN];
+    pbkdf2_hmac(
+        passphrase.as_bytes(),
+        salt,
+        iterations as usize,
+        MessageDigest::sha256(),
+        &mut key,
+    )
+    .unwrap();
+    key
+}
+
+/// Encrypt the contents of a wallet file with a key derived from passphrase
+pub fn encrypt_wallet_file(
+    wallet_file: &[u8],
+    passphrase: &str,
+    pbkdf2_iterations: u32,
+    created_timestamp: i64,
+) -> WalletBackup {
+    let mut rng = rand::thread_rng();
+    let salt = rng.gen::<[u8; SALT_LEN]>();
+    let nonce = rng.gen::<[u8; NONCE_LEN]>();
+    let mut tag = [0u8; TAG_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::chacha20_poly1305(),
+        &backup_key(passphrase, &salt, pbkdf2_iterations),
+        Some(&nonce),
+        &[],
+        wallet_file,
+        &mut tag,
+    )
+    .unwrap();
+    WalletBackup {
+        version: WALLET_BACKUP_VERSION,
+        created_timestamp,
+        pbkdf2_iterations,
+        salt: salt.to_hex(),
+        encrypted_wallet_file: [&nonce[..], &ciphertext, &tag].concat().to_hex(),
+    }
+}
+
+/// Contents of the wallet file in backup, fails if the passphrase is wrong or the backup has
+/// been changed
+pub fn decrypt_wallet_file(backup: &WalletBackup, passphrase: &str) -> Result<Vec<u8>, Error> {
+    if backup.version != WALLET_BACKUP_VERSION {
+        return Err(Error::Protocol("unsupported wallet backup version"));
+    }
+    if !(MIN_PBKDF2_ITERATIONS..=MAX_PBKDF2_ITERATIONS).contains(&backup.pbkdf2_iterations) {
+        return Err(Error::Protocol("invalid backup pbkdf2 iterations"));
+    }
+    let salt =
+        Vec::<u8>::from_hex(&backup.salt).map_err(|_| Error::Protocol("invalid backup salt"))?;
+    let blob = Vec::<u8>::from_hex(&backup.encrypted_wallet_file)
+        .map_err(|_| Error::Protocol("invalid backup encrypted wallet file"))?;
+    if blob.len() < NONCE_LEN + TAG_LEN {
+        return Err(Error::Protocol("backup encrypted wallet file too short"));
+    }
+    let (nonce, rest) = blob.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+    decrypt_aead(
+        Cipher::chacha20_poly1305(),
+        &backup_key(passphrase, &salt, backup.pbkdf2_iterations),
+        Some(nonce),
+        &[],
+        ciphertext,
+        tag,
+    )
+    .map_err(|_| Error::Protocol("wrong passphrase or corrupted backup"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wallet_backup() {
+        let wallet_file = br#"{"version":1,"seedphrase":"abandon about"}"#;
+        let backup = encrypt_wallet_file(
+            wallet_file,
+            "passphrase",
+            MIN_PBKDF2_ITERATIONS,
+            1_792_022_400,
+        );
+        assert_eq!(backup.salt.len(), SALT_LEN * 2);
+        assert!(!backup.encrypted_wallet_file.contains(&wallet_file.to_hex()));
+        assert_eq!(
+            decrypt_wallet_file(&backup, "passphrase").unwrap(),
+            wallet_file.to_vec()
+        );
+        assert!(decrypt_wallet_file(&backup, "wrong").is_err());
+
+        let json = serde_json::to_string(&backup).unwrap();
+        let backup = serde_json::from_str::<WalletBackup>(&json).unwrap();
+        assert_eq!(
+            decrypt_wallet_file(&backup, "passphrase").unwrap(),
+            wallet_file.to_vec()
+        );
+
+        let mut blob = Vec::<u8>::from_hex(&backup.encrypted_wallet_file).unwrap();
+        blob[NONCE_LEN] ^= 1;
+        let tampered = WalletBackup {
+            encrypted_wallet_file: blob.to_hex(),
+            ..backup.clone()
+        };
+        assert!(decrypt_wallet_file(&tampered, "passphrase").is_err());
+        let newer = WalletBackup {
+            version: WALLET_BACKUP_VERSION + 1,
+            ..backup.clone()
+        };
+        assert!(decrypt_wallet_file(&newer, "passphrase").is_err());
+        //rejected before deriving the key, 0 would panic and u32::MAX take days
+        for pbkdf2_iterations in [0, MIN_PBKDF2_ITERATIONS - 1, u32::MAX] {
+            let bad_iterations = WalletBackup {
+                pbkdf2_iterations,
+                ..backup.clone()
+            };
+            assert!(decrypt_wallet_file(&bad_iterations, "passphrase").is_err());
+        }
+    }
+}
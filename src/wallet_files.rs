@@ -0,0 +1,116 @@
+//the wallet files in the wallets directory of the datadir, managed with `teleport wallet`
+//a command uses the wallet given by --wallet or --wallet-file-name, otherwise the one set with
+// `teleport wallet default` which is default_wallet in teleport.conf, otherwise wallet.teleport
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const WALLETS_DIR_NAME: &str = "wallets";
+pub const WALLET_FILE_EXTENSION: &str = "teleport";
+pub const FALLBACK_WALLET_FILE_NAME: &str = "wallet.teleport";
+
+/// File name of the wallet called name, the extension is added if name doesnt have it
+pub fn wallet_file_name(name: &str) -> PathBuf {
+    let path = PathBuf::from(name);
+    if path.extension().is_some_and(|e| e == WALLET_FILE_EXTENSION) {
+        path
+    } else {
+        PathBuf::from(format!("{}.{}", name, WALLET_FILE_EXTENSION))
+    }
+}
+
+/// Name of the wallet file a command uses, from in order of precedence the --wallet-file-name
+/// and --wallet flags and the default_wallet setting
+pub fn resolve_wallet_file_name(
+    wallet_file_name_flag: Option<PathBuf>,
+    wallet_flag: Option<&str>,
+    default_wallet: Option<&str>,
+) -> PathBuf {
+    wallet_file_name_flag
+        .or_else(|| wallet_flag.or(default_wallet).map(wallet_file_name))
+        .unwrap_or_else(|| PathBuf::from(FALLBACK_WALLET_FILE_NAME))
+}
+
+/// Names of the wallets in dir without their extension, sorted
+pub fn list_wallet_names(dir: &Path) -> io::Result<Vec<String>> {
+    let mut names = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file() && path.extension().is_some_and(|e| e == WALLET_FILE_EXTENSION)
+        })
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().to_string()))
+        .collect::<Vec<String>>();
+    names.sort();
+    Ok(names)
+}
+
+/// Contents of teleport.conf with default_wallet set to name, the other lines are kept as
+/// they are. It is a top level key so it goes before the first section
+pub fn set_default_wallet_in_config(contents: &str, name: &str) -> String {
+    let setting = format!("default_wallet = {}", toml::Value::String(name.to_string()));
+    let mut lines = contents.lines().map(String::from).collect::<Vec<String>>();
+    let first_section = lines
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .unwrap_or(lines.len());
+    match lines[..first_section]
+        .iter()
+        .position(|line| line.trim_start().starts_with("default_wallet"))
+    {
+        Some(i) => lines[i] = setting,
+        None if first_section < lines.len() => {
+            lines.insert(first_section, String::new());
+            lines.insert(first_section, setting);
+        }
+        None => lines.push(setting),
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wallet_files() {
+        assert_eq!(wallet_file_name("maker1"), PathBuf::from("maker1.teleport"));
+        assert_eq!(
+            wallet_file_name("maker1.teleport"),
+            PathBuf::from("maker1.teleport")
+        );
+        assert_eq!(
+            resolve_wallet_file_name(None, None, None),
+            PathBuf::from("wallet.teleport")
+        );
+        assert_eq!(
+            resolve_wallet_file_name(None, Some("taker"), Some("maker")),
+            PathBuf::from("taker.teleport")
+        );
+        assert_eq!(
+            resolve_wallet_file_name(Some(PathBuf::from("old.teleport")), None, Some("maker")),
+            PathBuf::from("old.teleport")
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("b.teleport"), "").unwrap();
+        fs::write(dir.path().join("a.teleport"), "").unwrap();
+        fs::write(dir.path().join("notes.txt"), "").unwrap();
+        assert_eq!(list_wallet_names(dir.path()).unwrap(), vec!["a", "b"]);
+
+        let contents = "# teleport.conf\n\n[blockchain]\nnetwork = \"regtest\"\n";
+        let contents = set_default_wallet_in_config(contents, "maker");
+        assert_eq!(
+            contents,
+            "# teleport.conf\n\ndefault_wallet = \"maker\"\n\n[blockchain]\nnetwork = \"regtest\"\n"
+        );
+        let contents = set_default_wallet_in_config(&contents, "taker");
+        assert!(contents.contains("default_wallet = \"taker\"\n"));
+        assert!(!contents.contains("maker"));
+        assert_eq!(
+            set_default_wallet_in_config("", "taker"),
+            "default_wallet = \"taker\"\n"
+        );
+    }
+}
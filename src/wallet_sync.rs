@@ -34,14 +34,14 @@ use bitcoin::{
         ecdsa::PublicKey,
         psbt::serialize::Serialize,
     },
-    Address, Amount, Network, OutPoint, SigHashType, Transaction, TxIn, TxOut, Txid,
+    Address, Amount, BlockHash, Network, OutPoint, SigHashType, Transaction, TxIn, TxOut, Txid,
 };
 
 use bitcoincore_rpc::json::{
     ImportMultiOptions, ImportMultiRequest, ImportMultiRequestScriptPubkey, ImportMultiRescanSince,
     ListUnspentResultEntry,
 };
-use bitcoincore_rpc::{Client, RpcApi};
+use bitcoincore_rpc::RpcApi;
 
 use serde_json::json;
 use serde_json::Value;
@@ -51,12 +51,18 @@ use rand::RngCore;
 
 use chrono::NaiveDateTime;
 
+use crate::audit_log::{AuditLog, AUDIT_LOG_FILE_NAME};
+use crate::coin_viability;
 use crate::contracts;
 use crate::contracts::SwapCoin;
 use crate::error::Error;
 use crate::fidelity_bonds;
+use crate::funding_tx::reserved_utxos;
 use crate::messages::Preimage;
+use crate::rpc::Client;
 use crate::utils::teleport_data_dir;
+use crate::wallet_accounts::{utxo_account, WalletAccount};
+use crate::wallet_files::WALLETS_DIR_NAME;
 
 //these subroutines are coded so that as much as possible they keep all their
 //data in the bitcoin core wallet
@@ -65,6 +71,11 @@ use crate::utils::teleport_data_dir;
 const DERIVATION_PATH: &str = "m/84'/1'/0'";
 const WALLET_FILE_VERSION: u32 = 0;
 
+//a cached contract of a prevout is kept for as long as the prevout is unspent, as a contract
+// spending it could still be broadcast. an entry of a spent prevout, or one never funded, is
+// dropped once it is this many blocks old, deep enough that the spend wont be reorged out
+const PREVOUT_CACHE_EXPIRY_BLOCKS: u64 = 1008;
+
 //TODO the wallet file format is probably best handled with sqlite
 
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -76,6 +87,62 @@ struct WalletFileData {
     incoming_swapcoins: Vec<IncomingSwapCoin>,
     outgoing_swapcoins: Vec<OutgoingSwapCoin>,
     prevout_to_contract_map: HashMap<OutPoint, Script>,
+    //block height when each prevout_to_contract_map entry was first seen by a compaction
+    #[serde(default)]
+    prevout_cache_heights: HashMap<OutPoint, u64>,
+    #[serde(default)]
+    send_history: Vec<SendHistoryEntry>,
+    #[serde(default)]
+    swap_history: Vec<SwapHistoryEntry>,
+    //block height when the wallet was created, None for recovered and older wallets
+    #[serde(default)]
+    birthday_height: Option<u64>,
+    //next indexes of the change chain and recovery_descriptor handed out as destinations
+    // of recovery spends, see recovery_destinations.rs
+    #[serde(default)]
+    recovery_internal_index: u32,
+    #[serde(default)]
+    recovery_descriptor_index: u32,
+}
+
+//a payment made with direct-send, the label comes from the bitcoin: uri if paid to one
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct SendHistoryEntry {
+    pub txid: Txid,
+    pub address: String,
+    pub amount_sat: u64,
+    pub label: Option<String>,
+    pub timestamp: i64,
+    //not recorded by older versions
+    #[serde(default)]
+    pub miner_fee_sat: Option<u64>,
+}
+
+//a coinswap done by the maker, added when the taker proves the funding and completed
+// when the taker hands over the incoming coins' private keys
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SwapHistoryEntry {
+    pub hashvalue: Hash160,
+    pub incoming_amount_sat: u64,
+    pub outgoing_amount_sat: u64,
+    //coinswap fee calculated from the offer, the rest of incoming - outgoing pays the
+    // miner fees of our funding txes
+    pub coinswap_fee_sat: u64,
+    //actual miner fees of our funding txes
+    pub miner_fee_sat: u64,
+    pub started_timestamp: i64,
+    pub completed_timestamp: Option<i64>,
+}
+
+impl SwapHistoryEntry {
+    pub fn fee_income_sat(&self) -> u64 {
+        self.incoming_amount_sat
+            .saturating_sub(self.outgoing_amount_sat)
+    }
+
+    pub fn earned_sat(&self) -> i64 {
+        self.fee_income_sat() as i64 - self.miner_fee_sat as i64
+    }
 }
 
 pub struct Wallet {
@@ -88,6 +155,9 @@ pub struct Wallet {
     outgoing_swapcoins: HashMap<Script, OutgoingSwapCoin>,
     offer_maxsize_cache: u64,
     timelocked_script_index_map: HashMap<Script, u32>,
+    birthday_height: Option<u64>,
+    //records the messages of the wallet's coinswaps, see open_audit_log
+    audit_log: Option<Arc<AuditLog>>,
 }
 
 pub enum WalletSyncAddressAmount {
@@ -97,6 +167,11 @@ pub enum WalletSyncAddressAmount {
 
 const WATCH_ONLY_SWAPCOIN_LABEL: &str = "watchonly_swapcoin_label";
 
+//a locked coin of the wallet still worth spending at this fee rate wasnt locked by teleport,
+// which only locks coins too small to spend
+const CORE_WALLET_CONFLICT_FEE_RATE: u64 = 1_000_000;
+const CORE_WALLET_CONFLICT_INPUT_VBYTES: u64 = 100;
+
 #[derive(PartialEq, Debug)]
 pub enum DisplayAddressType {
     All,
@@ -156,6 +231,24 @@ pub enum UTXOSpendInfo {
     },
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AddressReuseKind {
+    //an address given out for receiving was paid more than once
+    External,
+    //a change output went to a script which had already been paid, linking the spend to it
+    Change,
+}
+
+//one of the wallet's addresses paid in more than one transaction, anyone watching the
+// blockchain can tell those coins belong to the same wallet
+#[derive(Debug, Clone)]
+pub struct AddressReuse {
+    pub address: Address,
+    pub kind: AddressReuseKind,
+    pub index: u32,
+    pub txids: Vec<Txid>,
+}
+
 //swapcoins are UTXOs + metadata which are not from the deterministic wallet
 //they are made in the process of a coinswap
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -179,7 +272,12 @@ pub struct OutgoingSwapCoin {
     pub other_pubkey: PublicKey,
     pub contract_tx: Transaction,
     pub contract_redeemscript: Script,
-    pub timelock_privkey: SecretKey,
+    //None when the timelock key is a child of the maker's cold xpub, then only the holder of
+    // the cold key can get the coins back after the timelock
+    pub timelock_privkey: Option<SecretKey>,
+    //the child of the cold xpub which is the timelock key
+    #[serde(default)]
+    pub cold_timelock_key_index: Option<u32>,
     pub funding_amount: u64,
     pub others_contract_sig: Option<Signature>,
     pub hash_preimage: Option<Preimage>,
@@ -356,7 +454,31 @@ impl OutgoingSwapCoin {
             other_pubkey,
             contract_tx,
             contract_redeemscript,
-            timelock_privkey,
+            timelock_privkey: Some(timelock_privkey),
+            cold_timelock_key_index: None,
+            funding_amount,
+            others_contract_sig: None,
+            hash_preimage: None,
+        }
+    }
+
+    /// An outgoing swapcoin whose timelock key is child cold_timelock_key_index of the
+    /// maker's cold xpub, the wallet cant spend its contract by the timelock branch
+    pub fn new_with_cold_timelock_key(
+        my_privkey: SecretKey,
+        other_pubkey: PublicKey,
+        contract_tx: Transaction,
+        contract_redeemscript: Script,
+        cold_timelock_key_index: u32,
+        funding_amount: u64,
+    ) -> Self {
+        Self {
+            my_privkey,
+            other_pubkey,
+            contract_tx,
+            contract_redeemscript,
+            timelock_privkey: None,
+            cold_timelock_key_index: Some(cold_timelock_key_index),
             funding_amount,
             others_contract_sig: None,
             hash_preimage: None,
@@ -370,54 +492,127 @@ impl OutgoingSwapCoin {
         input: &mut TxIn,
         input_value: u64,
     ) {
-        let secp = Secp256k1::new();
-        let sighash = secp256k1::Message::from_slice(
-            &SigHashCache::new(tx).signature_hash(
-                index,
-                &self.contract_redeemscript,
-                input_value,
-                SigHashType::All,
-            )[..],
-        )
-        .unwrap();
+        sign_timelocked_contract_input(
+            &self.contract_redeemscript,
+            self.timelock_privkey
+                .as_ref()
+                .expect("bug: spending contract with cold timelock key"),
+            index,
+            tx,
+            input,
+            input_value,
+        );
+    }
 
-        let sig_timelock = secp.sign(&sighash, &self.timelock_privkey);
-        input.witness.push(sig_timelock.serialize_der().to_vec());
-        input.witness[0].push(SigHashType::All as u8);
-        input.witness.push(Vec::new());
-        input.witness.push(self.contract_redeemscript.to_bytes());
+    /// The contract spent by the timelock branch, None if the timelock key is the cold key's
+    pub fn create_timelock_spend(&self, destination_address: &Address) -> Option<Transaction> {
+        self.timelock_privkey.as_ref().map(|timelock_privkey| {
+            create_timelock_spend_with_key(
+                &self.contract_tx,
+                &self.contract_redeemscript,
+                timelock_privkey,
+                destination_address,
+            )
+        })
     }
+}
 
-    pub fn create_timelock_spend(&self, destination_address: &Address) -> Transaction {
-        let miner_fee = 128; //128 vbytes x 1 sat/vb, size calculated using testmempoolaccept
-        let mut tx = Transaction {
-            input: vec![TxIn {
-                previous_output: OutPoint {
-                    txid: self.contract_tx.txid(),
-                    vout: 0, //contract_tx is one-input-one-output
-                },
-                sequence: self.get_timelock() as u32,
-                witness: Vec::new(),
-                script_sig: Script::new(),
-            }],
-            output: vec![TxOut {
-                script_pubkey: destination_address.script_pubkey(),
-                value: self.contract_tx.output[0].value - miner_fee,
-            }],
-            lock_time: 0,
-            version: 2,
-        };
-        let index = 0;
-        self.sign_timelocked_transaction_input(
+fn sign_timelocked_contract_input(
+    contract_redeemscript: &Script,
+    timelock_privkey: &SecretKey,
+    index: usize,
+    tx: &Transaction,
+    input: &mut TxIn,
+    input_value: u64,
+) {
+    let secp = Secp256k1::new();
+    let sighash = secp256k1::Message::from_slice(
+        &SigHashCache::new(tx).signature_hash(
             index,
-            &tx.clone(),
-            &mut tx.input[0],
-            self.contract_tx.output[0].value,
-        );
-        tx
+            contract_redeemscript,
+            input_value,
+            SigHashType::All,
+        )[..],
+    )
+    .unwrap();
+
+    let sig_timelock = secp.sign(&sighash, timelock_privkey);
+    input.witness.push(sig_timelock.serialize_der().to_vec());
+    input.witness[0].push(SigHashType::All as u8);
+    input.witness.push(Vec::new());
+    input.witness.push(contract_redeemscript.to_bytes());
+}
+
+/// Spend the output of contract_tx by the timelock branch of contract_redeemscript, signed
+/// with timelock_privkey. Valid once the contract tx has the contract's locktime in
+/// confirmations
+//teleport labels its addresses with the master key fingerprint of the wallet file, and
+// several teleport wallets can share one Core wallet
+fn core_wallet_label_problem(label: &str) -> Option<String> {
+    if label.is_empty() {
+        Some(
+            "addresses from the Core wallet's keypool were given out, another program is \
+            getting addresses from it"
+                .to_string(),
+        )
+    } else if label == WATCH_ONLY_SWAPCOIN_LABEL
+        || (label.len() == 8 && label.chars().all(|c| c.is_ascii_hexdigit()))
+    {
+        None
+    } else {
+        Some(format!(
+            "addresses labelled \"{}\" were imported into the Core wallet by another program",
+            label
+        ))
     }
 }
 
+pub fn create_timelock_spend_with_key(
+    contract_tx: &Transaction,
+    contract_redeemscript: &Script,
+    timelock_privkey: &SecretKey,
+    destination_address: &Address,
+) -> Transaction {
+    let miner_fee = 128; //128 vbytes x 1 sat/vb, size calculated using testmempoolaccept
+    let mut tx = Transaction {
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: contract_tx.txid(),
+                vout: 0, //contract_tx is one-input-one-output
+            },
+            sequence: contracts::read_locktime_from_contract(contract_redeemscript)
+                .expect("unable to read locktime from contract") as u32,
+            witness: Vec::new(),
+            script_sig: Script::new(),
+        }],
+        output: vec![TxOut {
+            script_pubkey: destination_address.script_pubkey(),
+            value: contract_tx.output[0].value - miner_fee,
+        }],
+        lock_time: 0,
+        version: 2,
+    };
+    let index = 0;
+    sign_timelocked_contract_input(
+        contract_redeemscript,
+        timelock_privkey,
+        index,
+        &tx.clone(),
+        &mut tx.input[0],
+        contract_tx.output[0].value,
+    );
+    tx
+}
+
+/// The timelock key which is child index of the maker's cold xpub
+pub fn derive_cold_timelock_pubkey(cold_xpub: &ExtendedPubKey, index: u32) -> PublicKey {
+    let secp = Secp256k1::new();
+    cold_xpub
+        .ckd_pub(&secp, ChildNumber::from_normal_idx(index).unwrap())
+        .unwrap()
+        .public_key
+}
+
 pub trait WalletSwapCoin: SwapCoin {
     fn get_my_pubkey(&self) -> PublicKey;
     fn get_other_pubkey(&self) -> &PublicKey;
@@ -620,7 +815,9 @@ impl Wallet {
     }
 
     fn get_wallet_path<P: AsRef<Path>>(wallet_file_name: P) -> PathBuf {
-        teleport_data_dir().join("wallets").join(wallet_file_name)
+        teleport_data_dir()
+            .join(WALLETS_DIR_NAME)
+            .join(wallet_file_name)
     }
 
     fn wallet_path(&self) -> PathBuf {
@@ -631,6 +828,7 @@ impl Wallet {
         wallet_file_name: P,
         seedphrase: String,
         extension: String,
+        birthday_height: Option<u64>,
     ) -> Result<(), Error> {
         let wallet_file_data = WalletFileData {
             version: WALLET_FILE_VERSION,
@@ -640,6 +838,12 @@ impl Wallet {
             incoming_swapcoins: Vec::new(),
             outgoing_swapcoins: Vec::new(),
             prevout_to_contract_map: HashMap::<OutPoint, Script>::new(),
+            prevout_cache_heights: HashMap::<OutPoint, u64>::new(),
+            send_history: Vec::new(),
+            swap_history: Vec::new(),
+            birthday_height,
+            recovery_internal_index: 0,
+            recovery_descriptor_index: 0,
         };
         let wallet_file = OpenOptions::new()
             .write(true)
@@ -657,6 +861,28 @@ impl Wallet {
         Ok(serde_json::from_str::<WalletFileData>(&wallet_file_str).map_err(io::Error::from)?)
     }
 
+    /// Contents of the wallet file, checked to be a valid wallet file
+    pub fn read_wallet_file<P: AsRef<Path>>(wallet_file_name: P) -> Result<Vec<u8>, Error> {
+        let wallet_file_data = Wallet::load_wallet_file_data(wallet_file_name)?;
+        Ok(serde_json::to_vec(&wallet_file_data).map_err(io::Error::from)?)
+    }
+
+    /// Write a new wallet file with contents from read_wallet_file, an existing wallet file is
+    /// never overwritten
+    pub fn restore_wallet_file<P: AsRef<Path>>(
+        wallet_file_name: P,
+        contents: &[u8],
+    ) -> Result<(), Error> {
+        let wallet_file_data =
+            serde_json::from_slice::<WalletFileData>(contents).map_err(io::Error::from)?;
+        let wallet_file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(Wallet::get_wallet_path(wallet_file_name))?;
+        serde_json::to_writer(wallet_file, &wallet_file_data).map_err(io::Error::from)?;
+        Ok(())
+    }
+
     pub fn load_wallet_from_file<P: AsRef<Path>>(
         wallet_file_name: P,
         network: Network,
@@ -668,20 +894,13 @@ impl Wallet {
             .to_string_lossy()
             .to_string();
         let wallet_file_data = Wallet::load_wallet_file_data(&wallet_file_name)?;
-        let mnemonic_ret = mnemonic::Mnemonic::from_str(&wallet_file_data.seedphrase);
-        if mnemonic_ret.is_err() {
-            return Err(Error::Disk(io::Error::new(
-                io::ErrorKind::Other,
-                "invalid seed phrase",
-            )));
-        }
-
-        let seed = mnemonic_ret
-            .unwrap()
-            .to_seed(Some(&wallet_file_data.extension));
-        let xprv = ExtendedPrivKey::new_master(network, &seed.0).unwrap();
+        let xprv = master_key_from_seed_phrase(
+            &wallet_file_data.seedphrase,
+            &wallet_file_data.extension,
+            network,
+        )?;
 
-        log::debug!(target: "wallet",
+        tracing::debug!(target: "wallet",
             "loaded wallet file, external_index={} incoming_swapcoins={} outgoing_swapcoins={}",
             wallet_file_data.external_index,
             wallet_file_data.incoming_swapcoins.len(), wallet_file_data.outgoing_swapcoins.len());
@@ -707,10 +926,26 @@ impl Wallet {
                 .collect::<HashMap<Script, OutgoingSwapCoin>>(),
             offer_maxsize_cache: 0,
             timelocked_script_index_map: fidelity_bonds::generate_all_timelocked_addresses(&xprv),
+            birthday_height: wallet_file_data.birthday_height,
+            audit_log: None,
         };
         Ok(wallet)
     }
 
+    /// Load the wallet file again, as another Wallet on the same file may have changed it
+    pub fn reload(&self) -> Result<Wallet, Error> {
+        let wallet = Wallet::load_wallet_from_file(
+            &self.wallet_file_name,
+            self.network,
+            WalletSyncAddressAmount::Normal,
+        )?;
+        Ok(Wallet {
+            initial_address_import_count: self.initial_address_import_count,
+            audit_log: self.audit_log.clone(),
+            ..wallet
+        })
+    }
+
     pub fn delete_wallet_file(&self) -> Result<(), Error> {
         Ok(fs::remove_file(self.wallet_path())?)
     }
@@ -728,6 +963,51 @@ impl Wallet {
         self.external_index
     }
 
+    pub fn add_send_history_entry(&self, entry: SendHistoryEntry) -> Result<(), Error> {
+        let mut wallet_file_data = Wallet::load_wallet_file_data(&self.wallet_file_name)?;
+        wallet_file_data.send_history.push(entry);
+        let wallet_file = File::create(self.wallet_path())?;
+        serde_json::to_writer(wallet_file, &wallet_file_data).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    pub fn get_send_history(&self) -> Result<Vec<SendHistoryEntry>, Error> {
+        Ok(Wallet::load_wallet_file_data(&self.wallet_file_name)?.send_history)
+    }
+
+    //replaces an entry of the same coinswap, in case the taker sent the proof of funding again
+    pub fn add_swap_history_entry(&self, entry: SwapHistoryEntry) -> Result<(), Error> {
+        let mut wallet_file_data = Wallet::load_wallet_file_data(&self.wallet_file_name)?;
+        wallet_file_data
+            .swap_history
+            .retain(|e| e.hashvalue != entry.hashvalue);
+        wallet_file_data.swap_history.push(entry);
+        let wallet_file = File::create(self.wallet_path())?;
+        serde_json::to_writer(wallet_file, &wallet_file_data).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    pub fn complete_swap_history_entry(
+        &self,
+        hashvalue: Hash160,
+        timestamp: i64,
+    ) -> Result<(), Error> {
+        let mut wallet_file_data = Wallet::load_wallet_file_data(&self.wallet_file_name)?;
+        let entry = wallet_file_data
+            .swap_history
+            .iter_mut()
+            .find(|e| e.hashvalue == hashvalue)
+            .ok_or(Error::Protocol("coinswap not found in swap history"))?;
+        entry.completed_timestamp = Some(timestamp);
+        let wallet_file = File::create(self.wallet_path())?;
+        serde_json::to_writer(wallet_file, &wallet_file_data).map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    pub fn get_swap_history(&self) -> Result<Vec<SwapHistoryEntry>, Error> {
+        Ok(Wallet::load_wallet_file_data(&self.wallet_file_name)?.swap_history)
+    }
+
     pub fn update_swapcoins_list(&self) -> Result<(), Error> {
         let mut wallet_file_data = Wallet::load_wallet_file_data(&self.wallet_file_name)?;
         wallet_file_data.incoming_swapcoins = self
@@ -759,6 +1039,26 @@ impl Wallet {
         self.outgoing_swapcoins.get(multisig_redeemscript)
     }
 
+    /// The wallet's key of a branch of the contract, the hashlock key of an incoming
+    /// swapcoin's contract or the timelock key of an outgoing one
+    pub fn find_contract_branch_privkey(
+        &self,
+        contract_redeemscript: &Script,
+        hashlock: bool,
+    ) -> Option<SecretKey> {
+        if hashlock {
+            self.incoming_swapcoins
+                .values()
+                .find(|swapcoin| swapcoin.contract_redeemscript == *contract_redeemscript)
+                .map(|swapcoin| swapcoin.hashlock_privkey)
+        } else {
+            self.outgoing_swapcoins
+                .values()
+                .find(|swapcoin| swapcoin.contract_redeemscript == *contract_redeemscript)
+                .and_then(|swapcoin| swapcoin.timelock_privkey)
+        }
+    }
+
     pub fn find_incoming_swapcoin_mut(
         &mut self,
         multisig_redeemscript: &Script,
@@ -776,6 +1076,20 @@ impl Wallet {
             .insert(coin.get_multisig_redeemscript(), coin);
     }
 
+    /// The keys of every swapcoin's 2-of-2 multisig, the wallet's private key, the other
+    /// party's pubkey and the other party's private key if it was handed over
+    pub fn get_swapcoin_multisig_keys(&self) -> Vec<(SecretKey, PublicKey, Option<SecretKey>)> {
+        self.incoming_swapcoins
+            .values()
+            .map(|sc| (sc.my_privkey, sc.other_pubkey, sc.other_privkey))
+            .chain(
+                self.outgoing_swapcoins
+                    .values()
+                    .map(|sc| (sc.my_privkey, sc.other_pubkey, None)),
+            )
+            .collect()
+    }
+
     pub fn get_swapcoins_count(&self) -> usize {
         self.incoming_swapcoins.len() + self.outgoing_swapcoins.len()
     }
@@ -826,6 +1140,25 @@ impl Wallet {
         Ok(())
     }
 
+    /// Drop the expired entries of the prevout to contract cache, returns how many
+    pub fn compact_prevout_cache(&self, rpc: &Client) -> Result<usize, Error> {
+        let mut wallet_file_data = Wallet::load_wallet_file_data(&self.wallet_file_name[..])?;
+        if wallet_file_data.prevout_to_contract_map.is_empty() {
+            return Ok(0);
+        }
+        let tip_height = rpc.get_block_count()?;
+        let removed_count = compact_prevout_cache(&mut wallet_file_data, tip_height, |prevout| {
+            Ok(rpc
+                .get_tx_out(&prevout.txid, prevout.vout, Some(true))?
+                .is_some())
+        })?;
+        tracing::debug!(target: "wallet", "removed {} expired prevout cache entries, {} left",
+            removed_count, wallet_file_data.prevout_to_contract_map.len());
+        let wallet_file = File::create(self.wallet_path())?;
+        serde_json::to_writer(wallet_file, &wallet_file_data).map_err(io::Error::from)?;
+        Ok(removed_count)
+    }
+
     //pub fn get_recovery_phrase_from_file()
 
     fn is_xpub_descriptor_imported(&self, rpc: &Client, descriptor: &str) -> Result<bool, Error> {
@@ -887,7 +1220,7 @@ impl Wallet {
         swapcoin_descriptors_to_import: &[String],
         contract_scriptpubkeys_to_import: &[Script],
     ) -> Result<(), Error> {
-        log::debug!(target: "wallet",
+        tracing::debug!(target: "wallet",
             "import_initial_addresses with initial_address_import_count = {}",
             self.initial_address_import_count);
         let address_label = self.get_core_wallet_label();
@@ -953,7 +1286,69 @@ impl Wallet {
         Ok(())
     }
 
+    pub fn get_birthday_height(&self) -> Option<u64> {
+        self.birthday_height
+    }
+
+    /// Import every script of the wallet into Core again, including those of swapcoins and
+    /// their contracts, and rescan the blockchain from start_height or else the wallet's
+    /// birthday. Returns the heights Core rescanned between
+    pub fn rescan(
+        &self,
+        rpc: &Client,
+        start_height: Option<u64>,
+    ) -> Result<(usize, Option<usize>), Error> {
+        let start_height = start_height
+            .or(self.birthday_height)
+            .ok_or(Error::Protocol(
+                "wallet has no birthday height, give the height to rescan from",
+            ))?;
+        let hd_descriptors = self.get_hd_wallet_descriptors(rpc)?;
+        let swapcoin_descriptors = self
+            .incoming_swapcoins
+            .values()
+            .map(|sc| (sc.get_other_pubkey(), sc.get_my_pubkey()))
+            .chain(
+                self.outgoing_swapcoins
+                    .values()
+                    .map(|sc| (sc.get_other_pubkey(), sc.get_my_pubkey())),
+            )
+            .map(|(other_pubkey, my_pubkey)| {
+                rpc.get_descriptor_info(&format!(
+                    "wsh(sortedmulti(2,{},{}))",
+                    other_pubkey, my_pubkey
+                ))
+                .map(|info| info.descriptor)
+            })
+            .collect::<Result<Vec<String>, _>>()?;
+        let contract_scriptpubkeys = self
+            .incoming_swapcoins
+            .values()
+            .map(|sc| &sc.contract_redeemscript)
+            .chain(
+                self.outgoing_swapcoins
+                    .values()
+                    .map(|sc| &sc.contract_redeemscript),
+            )
+            .map(contracts::redeemscript_to_scriptpubkey)
+            .collect::<Vec<Script>>();
+        tracing::info!(target: "wallet",
+            "importing {} swapcoin and {} contract scripts, rescanning from height {}",
+            swapcoin_descriptors.len(), contract_scriptpubkeys.len(), start_height);
+        self.import_initial_addresses(
+            rpc,
+            &hd_descriptors.iter().collect::<Vec<&String>>(),
+            &swapcoin_descriptors,
+            &contract_scriptpubkeys,
+        )?;
+        Ok(rpc.rescan_blockchain(Some(start_height as usize), None)?)
+    }
+
     pub fn startup_sync(&mut self, rpc: &Client) -> Result<(), Error> {
+        for problem in self.find_core_wallet_conflicts(rpc)? {
+            tracing::warn!(target: "wallet", "core wallet conflict: {}", problem);
+        }
+        self.compact_prevout_cache(rpc)?;
         //TODO many of these unwraps to be replaced with proper error handling
         let hd_descriptors = self.get_hd_wallet_descriptors(rpc)?;
         let hd_descriptors_to_import = hd_descriptors
@@ -1033,7 +1428,7 @@ impl Wallet {
             ),
             self.network,
         );
-        log::debug!(target: "wallet", "first_timelocked_addr={} last_timelocked_addr={}",
+        tracing::debug!(target: "wallet", "first_timelocked_addr={} last_timelocked_addr={}",
             first_timelocked_addr, last_timelocked_addr);
         let is_timelock_branch_imported = rpc
             .get_address_info(&first_timelocked_addr)?
@@ -1044,7 +1439,7 @@ impl Wallet {
                 .is_watchonly
                 .unwrap_or(false);
 
-        log::debug!(target: "wallet",
+        tracing::debug!(target: "wallet",
             concat!("hd_descriptors_to_import.len = {} swapcoin_descriptors_to_import.len = {}",
                 " contract_scriptpubkeys_to_import = {} is_timelock_branch_imported = {}"),
             hd_descriptors_to_import.len(), swapcoin_descriptors_to_import.len(),
@@ -1055,10 +1450,11 @@ impl Wallet {
             && contract_scriptpubkeys_to_import.is_empty()
             && is_timelock_branch_imported
         {
+            self.warn_about_address_reuse(rpc);
             return Ok(());
         }
 
-        log::info!(target: "wallet", "New wallet detected, synchronizing balance...");
+        tracing::info!(target: "wallet", "New wallet detected, synchronizing balance...");
         self.import_initial_addresses(
             rpc,
             &hd_descriptors_to_import,
@@ -1092,11 +1488,11 @@ impl Wallet {
         if !scantxoutset_result["success"].as_bool().unwrap() {
             return Err(Error::Rpc(bitcoincore_rpc::Error::UnexpectedStructure));
         }
-        log::info!(target: "wallet", "TxOut set scan complete, found {} btc",
+        tracing::info!(target: "wallet", "TxOut set scan complete, found {} btc",
             Amount::from_sat(convert_json_rpc_bitcoin_to_satoshis(&scantxoutset_result["total_amount"])),
         );
         let unspent_list = scantxoutset_result["unspents"].as_array().unwrap();
-        log::debug!(target: "wallet", "scantxoutset found_coins={} txouts={} height={} bestblock={}",
+        tracing::debug!(target: "wallet", "scantxoutset found_coins={} txouts={} height={} bestblock={}",
             unspent_list.len(),
             scantxoutset_result["txouts"].as_u64().unwrap(),
             scantxoutset_result["height"].as_u64().unwrap(),
@@ -1107,7 +1503,7 @@ impl Wallet {
             let txid = Txid::from_hex(unspent["txid"].as_str().unwrap()).unwrap();
             let rawtx = rpc.get_raw_transaction_hex(&txid, Some(&blockhash));
             if let Ok(rawtx_hex) = rawtx {
-                log::debug!(target: "wallet", "found coin {}:{} {} height={} {}",
+                tracing::debug!(target: "wallet", "found coin {}:{} {} height={} {}",
                     txid,
                     unspent["vout"].as_u64().unwrap(),
                     Amount::from_sat(convert_json_rpc_bitcoin_to_satoshis(&unspent["amount"])),
@@ -1115,21 +1511,70 @@ impl Wallet {
                     unspent["desc"].as_str().unwrap(),
                 );
                 let merkleproof = rpc.get_tx_out_proof(&[txid], Some(&blockhash))?.to_hex();
-                rpc.call(
+                rpc.call::<()>(
                     "importprunedfunds",
                     &[Value::String(rawtx_hex), Value::String(merkleproof)],
                 )?;
             } else {
-                log::error!(target: "wallet", "block pruned, TODO add UTXO to wallet file");
+                tracing::error!(target: "wallet", "block pruned, TODO add UTXO to wallet file");
                 panic!("teleport doesnt work with pruning yet, try rescanning");
             }
         }
 
         let max_external_index = self.find_hd_next_index(rpc, 0)?;
         self.update_external_index(max_external_index)?;
+        self.warn_about_address_reuse(rpc);
         Ok(())
     }
 
+    fn warn_about_address_reuse(&self, rpc: &Client) {
+        match self.find_address_reuse(rpc) {
+            Ok(address_reuse) => {
+                for reuse in address_reuse {
+                    tracing::warn!(target: "wallet", "{}", reuse_warning(&reuse));
+                }
+            }
+            Err(e) => tracing::debug!(target: "wallet", "unable to check address reuse: {:?}", e),
+        }
+    }
+
+    /// Find the wallet's seed addresses paid in more than one transaction, from its unspent
+    /// coins and the payments the node has a record of
+    pub fn find_address_reuse(&self, rpc: &Client) -> Result<Vec<AddressReuse>, Error> {
+        let last_index = (self.initial_address_import_count - 1) as u32;
+        let mut hd_addresses = HashMap::<Script, (Address, AddressReuseKind, u32)>::new();
+        for (descriptor, kind) in self
+            .get_hd_wallet_descriptors(rpc)?
+            .iter()
+            .zip([AddressReuseKind::External, AddressReuseKind::Change])
+        {
+            for (index, address) in rpc
+                .derive_addresses(descriptor, Some([0, last_index]))?
+                .into_iter()
+                .enumerate()
+            {
+                hd_addresses.insert(address.script_pubkey(), (address, kind, index as u32));
+            }
+        }
+        //listunspent also has change outputs, which listreceivedbyaddress leaves out, but
+        // only listreceivedbyaddress remembers payments which were spent
+        let payments = rpc
+            .list_unspent(Some(0), Some(9999999), None, None, None)?
+            .into_iter()
+            .map(|u| (u.script_pub_key, u.txid))
+            .chain(
+                rpc.list_received_by_address(None, Some(0), Some(false), Some(true))?
+                    .into_iter()
+                    .flat_map(|r| {
+                        let script_pubkey = r.address.script_pubkey();
+                        r.txids
+                            .into_iter()
+                            .map(move |txid| (script_pubkey.clone(), txid))
+                    }),
+            );
+        Ok(group_address_reuse(&hd_addresses, payments))
+    }
+
     fn create_contract_scriptpubkey_outgoing_swapcoin_hashmap(
         &self,
     ) -> HashMap<Script, &OutgoingSwapCoin> {
@@ -1182,7 +1627,8 @@ impl Wallet {
             if let Some(outgoing_swapcoins) = option_contract_scriptpubkeys_outgoing_swapcoins {
                 if let Some(swapcoin) = outgoing_swapcoins.get(&u.script_pub_key) {
                     let timelock = swapcoin.get_timelock();
-                    if u.confirmations >= timelock.into() {
+                    //only the holder of the cold key can spend a cold keyed contract
+                    if u.confirmations >= timelock.into() && swapcoin.timelock_privkey.is_some() {
                         return Some(UTXOSpendInfo::TimelockContract {
                             swapcoin_multisig_redeemscript: swapcoin.get_multisig_redeemscript(),
                             input_value: u.amount.as_sat(),
@@ -1246,22 +1692,118 @@ impl Wallet {
         }
     }
 
+    /// Signs that other software uses the same Core wallet as teleport, which breaks its
+    /// assumption that every address and coin lock in there is teleport's. Must be called
+    /// before the coin locks are redone, as listing the wallet's coins unlocks all of them
+    pub fn find_core_wallet_conflicts(&self, rpc: &Client) -> Result<Vec<String>, Error> {
+        let mut problems = Vec::new();
+
+        //teleport labels its addresses with the master key fingerprint of the wallet file,
+        // several teleport wallets can share one Core wallet
+        let labels = rpc.call::<Vec<String>>("listlabels", &[])?;
+        problems.extend(labels.iter().filter_map(|l| core_wallet_label_problem(l)));
+
+        let locked = rpc.call::<Vec<Value>>("listlockunspent", &[])?;
+        let locked = locked
+            .iter()
+            .filter_map(|l| {
+                Some(OutPoint {
+                    txid: Txid::from_str(l["txid"].as_str()?).ok()?,
+                    vout: l["vout"].as_u64()? as u32,
+                })
+            })
+            .collect::<HashSet<OutPoint>>();
+        //listunspent leaves out locked coins, so look them up one by one
+        let reserved = reserved_utxos(chrono::Utc::now().timestamp());
+        let own_label = self.get_core_wallet_label();
+        let min_value = coin_viability::spend_cost_sat(
+            CORE_WALLET_CONFLICT_INPUT_VBYTES,
+            CORE_WALLET_CONFLICT_FEE_RATE,
+        );
+        let mut foreign_locks = 0;
+        for outpoint in locked.iter().filter(|o| !reserved.contains(o)) {
+            let txout = match rpc.get_tx_out(&outpoint.txid, outpoint.vout, Some(true))? {
+                Some(txout) => txout,
+                None => continue,
+            };
+            if txout.value.as_sat() < min_value {
+                continue;
+            }
+            let script = Script::from(txout.script_pub_key.hex);
+            let address = match Address::from_script(&script, self.network) {
+                Some(address) => address,
+                None => continue,
+            };
+            let info = rpc.call::<Value>("getaddressinfo", &[json!(address.to_string())])?;
+            let is_own = info["labels"]
+                .as_array()
+                .is_some_and(|labels| labels.iter().any(|l| l.as_str() == Some(&own_label)));
+            if is_own {
+                foreign_locks += 1;
+            }
+        }
+        if foreign_locks > 0 {
+            problems.push(format!(
+                "{} of the wallet's coins were locked in the Core wallet by another program",
+                foreign_locks
+            ));
+        }
+        Ok(problems)
+    }
+
     pub fn lock_all_nonwallet_unspents(&self, rpc: &Client) -> Result<(), Error> {
+        self.lock_unspents_not_to_spend(rpc, None, None)
+    }
+
+    /// Lock the coins which arent the wallet's, the wallet's coins which cost more to spend at
+    /// fee_rate than they're worth and those of other accounts, so the node's coin selection
+    /// only uses coins of account
+    pub fn lock_unspents_not_to_fund_from(
+        &self,
+        rpc: &Client,
+        fee_rate: u64,
+        account: WalletAccount,
+    ) -> Result<(), Error> {
+        self.lock_unspents_not_to_spend(rpc, Some(fee_rate), Some(account))
+    }
+
+    fn lock_unspents_not_to_spend(
+        &self,
+        rpc: &Client,
+        fee_rate: Option<u64>,
+        account: Option<WalletAccount>,
+    ) -> Result<(), Error> {
         //rpc.unlock_unspent(&[])?;
         //https://github.com/rust-bitcoin/rust-bitcoincore-rpc/issues/148
         rpc.call::<Value>("lockunspent", &[Value::Bool(true)])?;
 
         let all_unspents = rpc.list_unspent(Some(0), Some(9999999), None, None, None)?;
+        let reserved = reserved_utxos(chrono::Utc::now().timestamp());
         let utxos_to_lock = &all_unspents
             .into_iter()
-            .filter(|u| {
-                self.is_utxo_ours_and_spendable_get_pointer(u, None, None, false)
-                    .is_none()
+            .map(|u| {
+                let outpoint = OutPoint {
+                    txid: u.txid,
+                    vout: u.vout,
+                };
+                (u, outpoint)
             })
-            .map(|u| OutPoint {
-                txid: u.txid,
-                vout: u.vout,
+            .filter(|(u, outpoint)| {
+                reserved.contains(outpoint)
+                    || match self.is_utxo_ours_and_spendable_get_pointer(u, None, None, false) {
+                        None => true,
+                        Some(spend_info) => {
+                            fee_rate.is_some_and(|fee_rate| {
+                                !coin_viability::is_economic(
+                                    u.amount.as_sat(),
+                                    &spend_info,
+                                    fee_rate,
+                                )
+                            }) || account.is_some_and(|a| utxo_account(&spend_info) != Some(a))
+                        }
+                    }
             })
+            .map(|(_, outpoint)| outpoint)
             .collect::<Vec<OutPoint>>();
         rpc.lock_unspent(utxos_to_lock)?;
         Ok(())
@@ -1481,6 +2023,43 @@ impl Wallet {
         )?)
     }
 
+    /// Fresh change addresses for recovery spends, each is handed out only once even before
+    /// coins arrive on it, unlike get_next_internal_addresses
+    pub fn get_fresh_internal_addresses(
+        &self,
+        rpc: &Client,
+        count: u32,
+    ) -> Result<Vec<Address>, Error> {
+        let first_unused_index = self.find_hd_next_index(rpc, 1)?;
+        let first_index = self.reserve_recovery_indexes(false, first_unused_index, count)?;
+        let change_branch_descriptor = &self.get_hd_wallet_descriptors(rpc)?[1];
+        Ok(rpc.derive_addresses(
+            change_branch_descriptor,
+            Some([first_index, first_index + count - 1]),
+        )?)
+    }
+
+    /// Reserve count indexes of the change chain, or of recovery_descriptor if descriptor is
+    /// set, starting at no less than first_unused_index. Returns the first one
+    pub fn reserve_recovery_indexes(
+        &self,
+        descriptor: bool,
+        first_unused_index: u32,
+        count: u32,
+    ) -> Result<u32, Error> {
+        let mut wallet_file_data = Wallet::load_wallet_file_data(&self.wallet_file_name)?;
+        let next_index = if descriptor {
+            &mut wallet_file_data.recovery_descriptor_index
+        } else {
+            &mut wallet_file_data.recovery_internal_index
+        };
+        let first_index = (*next_index).max(first_unused_index);
+        *next_index = first_index + count;
+        let wallet_file = File::create(self.wallet_path())?;
+        serde_json::to_writer(wallet_file, &wallet_file_data).map_err(io::Error::from)?;
+        Ok(first_index)
+    }
+
     pub fn refresh_offer_maxsize_cache(&mut self, rpc: Arc<Client>) -> Result<(), Error> {
         let utxos = self.list_unspent_from_wallet(&rpc, false, false)?;
         let balance: Amount = utxos.iter().fold(Amount::ZERO, |acc, u| acc + u.0.amount);
@@ -1488,6 +2067,16 @@ impl Wallet {
         Ok(())
     }
 
+    /// Seed phrase and extension from the wallet file
+    pub fn get_seed_phrase(&self) -> Result<(String, String), Error> {
+        let wallet_file_data = Wallet::load_wallet_file_data(&self.wallet_file_name)?;
+        Ok((wallet_file_data.seedphrase, wallet_file_data.extension))
+    }
+
+    pub fn get_first_receive_addresses(&self, count: u32) -> Vec<Address> {
+        derive_receive_addresses(&self.master_key, self.network, count)
+    }
+
     pub fn get_offer_maxsize_cache(&self) -> u64 {
         self.offer_maxsize_cache
     }
@@ -1502,10 +2091,68 @@ impl Wallet {
         (privkey.key, privkey.public_key(&secp))
     }
 
+    /// Record the messages of the wallet's coinswaps in audit.log in the data directory,
+    /// signed with the tweakable key, see audit_log.rs
+    pub fn open_audit_log(&mut self) -> Result<(), Error> {
+        self.audit_log = Some(AuditLog::open(
+            teleport_data_dir().join(AUDIT_LOG_FILE_NAME),
+            self.get_tweakable_keypair().0,
+        )?);
+        Ok(())
+    }
+
+    /// None if open_audit_log wasnt called
+    pub fn audit_log(&self) -> Option<Arc<AuditLog>> {
+        self.audit_log.clone()
+    }
+
+    /// The tweakable key maker instance number instance advertises in its offers, each instance
+    /// has its own so that their offers cant be linked. The first instance's is the wallet's
+    /// tweakable key, which the swapcoins of makers from before instances were added use
+    pub fn get_instance_tweakable_keypair(&self, instance: u32) -> (SecretKey, PublicKey) {
+        if instance == 0 {
+            return self.get_tweakable_keypair();
+        }
+        let secp = Secp256k1::new();
+        let privkey = self
+            .master_key
+            .ckd_priv(&secp, ChildNumber::from_hardened_idx(2).unwrap())
+            .unwrap()
+            .ckd_priv(&secp, ChildNumber::from_hardened_idx(instance).unwrap())
+            .unwrap()
+            .private_key;
+        (privkey.key, privkey.public_key(&secp))
+    }
+
+    /// The long-term identity key maker instance number instance signs its hellos and offers
+    /// with, see maker_identity.rs
+    pub fn get_identity_keypair(&self, instance: u32) -> (SecretKey, PublicKey) {
+        let secp = Secp256k1::new();
+        let privkey = self
+            .master_key
+            .ckd_priv(&secp, ChildNumber::from_hardened_idx(1).unwrap())
+            .unwrap()
+            .ckd_priv(&secp, ChildNumber::from_hardened_idx(instance).unwrap())
+            .unwrap()
+            .private_key;
+        (privkey.key, privkey.public_key(&secp))
+    }
+
     pub fn sign_transaction(
         &self,
         tx: &mut Transaction,
         inputs_info: &mut dyn Iterator<Item = UTXOSpendInfo>,
+    ) {
+        let inputs_info = inputs_info.enumerate().collect::<Vec<_>>();
+        self.sign_transaction_inputs(tx, &inputs_info);
+    }
+
+    //signs only the inputs at the given indexes, for transactions where the other
+    // inputs belong to someone else like payjoins
+    pub fn sign_transaction_inputs(
+        &self,
+        tx: &mut Transaction,
+        inputs_info: &[(usize, UTXOSpendInfo)],
     ) {
         let secp = Secp256k1::new();
         let master_private_key = self
@@ -1514,8 +2161,9 @@ impl Wallet {
             .unwrap();
         let tx_clone = tx.clone();
 
-        for (ix, (input, input_info)) in tx.input.iter_mut().zip(inputs_info).enumerate() {
-            log::debug!(target: "wallet", "signing with input_info = {:?}", input_info);
+        for (ix, input_info) in inputs_info.iter().cloned() {
+            let input = &mut tx.input[ix];
+            tracing::debug!(target: "wallet", "signing with input_info = {:?}", input_info);
             match input_info {
                 UTXOSpendInfo::SwapCoin {
                     multisig_redeemscript,
@@ -1590,7 +2238,7 @@ impl Wallet {
     ) -> Result<Transaction, Error> {
         //TODO rust-bitcoin handles psbt, use those functions instead
         let decoded_psbt = rpc.call::<Value>("decodepsbt", &[Value::String(psbt.to_string())])?;
-        log::debug!(target: "wallet", "decoded_psbt = {:?}", decoded_psbt);
+        tracing::debug!(target: "wallet", "decoded_psbt = {:?}", decoded_psbt);
 
         //TODO proper error handling, theres many unwrap()s here
         //make this function return Result<>
@@ -1627,7 +2275,7 @@ impl Wallet {
             lock_time: 0,
             version: 2,
         };
-        log::debug!(target: "wallet", "tx = {:?}", tx);
+        tracing::debug!(target: "wallet", "tx = {:?}", tx);
 
         let mut inputs_info = decoded_psbt["inputs"]
             .as_array()
@@ -1652,10 +2300,10 @@ impl Wallet {
                     }
                 }
             });
-        log::debug!(target: "wallet", "inputs_info = {:?}", inputs_info);
+        tracing::debug!(target: "wallet", "inputs_info = {:?}", inputs_info);
         self.sign_transaction(&mut tx, &mut inputs_info);
 
-        log::debug!(target: "wallet",
+        tracing::debug!(target: "wallet",
             "txhex = {}",
             bitcoin::consensus::encode::serialize_hex(&tx)
         );
@@ -1725,14 +2373,15 @@ impl Wallet {
     ) -> Result<(), Error> {
         let rawtx_hex = bitcoin::consensus::encode::serialize(tx).to_hex();
 
-        rpc.call(
+        rpc.call::<()>(
             "importprunedfunds",
             &[Value::String(rawtx_hex), Value::String(merkleproof)],
         )?;
-        log::debug!(target: "wallet", "import_tx_with_merkleproof txid={}", tx.txid());
+        tracing::debug!(target: "wallet", "import_tx_with_merkleproof txid={}", tx.txid());
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn initalize_coinswap(
         &mut self,
         rpc: &Client,
@@ -1742,15 +2391,24 @@ impl Wallet {
         hashvalue: Hash160,
         locktime: u16, //returns: funding_txes, swapcoins, total_miner_fee
         fee_rate: u64,
+        change_avoidance_tolerance_ppb: u64,
+        cold_timelock_xpub: Option<&ExtendedPubKey>,
+        account: Option<WalletAccount>,
     ) -> Result<(Vec<Transaction>, Vec<OutgoingSwapCoin>, u64), Error> {
         let (coinswap_addresses, my_multisig_privkeys): (Vec<_>, Vec<_>) = other_multisig_pubkeys
             .iter()
             .map(|other_key| self.create_and_import_coinswap_address(rpc, other_key))
             .unzip();
-        log::debug!(target: "wallet", "coinswap_addresses = {:?}", coinswap_addresses);
+        tracing::debug!(target: "wallet", "coinswap_addresses = {:?}", coinswap_addresses);
 
-        let create_funding_txes_result =
-            self.create_funding_txes(rpc, total_coinswap_amount, &coinswap_addresses, fee_rate)?;
+        let create_funding_txes_result = self.create_funding_txes(
+            rpc,
+            total_coinswap_amount,
+            &coinswap_addresses,
+            fee_rate,
+            change_avoidance_tolerance_ppb,
+            account,
+        )?;
         //for sweeping there would be another function, probably
         //probably have an enum called something like SendAmount which can be
         // an integer but also can be Sweep
@@ -1778,7 +2436,18 @@ impl Wallet {
             other_multisig_pubkeys.iter(),
             hashlock_pubkeys.iter(),
         ) {
-            let (timelock_pubkey, timelock_privkey) = generate_keypair();
+            //a random child so contracts dont show they share a cold key
+            let cold_timelock_key_index = cold_timelock_xpub.map(|_| OsRng.next_u32() & !(1 << 31));
+            let (timelock_pubkey, timelock_privkey) = match cold_timelock_xpub {
+                Some(xpub) => (
+                    derive_cold_timelock_pubkey(xpub, cold_timelock_key_index.unwrap()),
+                    None,
+                ),
+                None => {
+                    let (pubkey, privkey) = generate_keypair();
+                    (pubkey, Some(privkey))
+                }
+            };
             let contract_redeemscript = contracts::create_contract_redeemscript(
                 hashlock_pubkey,
                 &timelock_pubkey,
@@ -1796,14 +2465,24 @@ impl Wallet {
             );
 
             self.import_wallet_contract_redeemscript(rpc, &contract_redeemscript)?;
-            outgoing_swapcoins.push(OutgoingSwapCoin::new(
-                my_multisig_privkey,
-                other_multisig_pubkey,
-                my_senders_contract_tx,
-                contract_redeemscript,
-                timelock_privkey,
-                funding_amount,
-            ));
+            outgoing_swapcoins.push(match timelock_privkey {
+                Some(timelock_privkey) => OutgoingSwapCoin::new(
+                    my_multisig_privkey,
+                    other_multisig_pubkey,
+                    my_senders_contract_tx,
+                    contract_redeemscript,
+                    timelock_privkey,
+                    funding_amount,
+                ),
+                None => OutgoingSwapCoin::new_with_cold_timelock_key(
+                    my_multisig_privkey,
+                    other_multisig_pubkey,
+                    my_senders_contract_tx,
+                    contract_redeemscript,
+                    cold_timelock_key_index.unwrap(),
+                    funding_amount,
+                ),
+            });
         }
 
         Ok((
@@ -1838,6 +2517,37 @@ pub fn create_multisig_redeemscript(key1: &PublicKey, key2: &PublicKey) -> Scrip
     .into_script()
 }
 
+/// Whether the block is still in the best chain, a tx confirmed in a block which was reorged
+/// out is unconfirmed again. core gives blocks outside the best chain -1 confirmations
+pub fn is_block_in_best_chain(rpc: &Client, blockhash: &BlockHash) -> Result<bool, Error> {
+    Ok(rpc.get_block_header_info(blockhash)?.confirmations >= 0)
+}
+
+//entries are timed from the first compaction which sees them, so entries added while the
+// wallet runs, and those of wallet files from before entries were timed, expire later rather
+// than earlier
+fn compact_prevout_cache<F: Fn(&OutPoint) -> Result<bool, Error>>(
+    wallet_file_data: &mut WalletFileData,
+    tip_height: u64,
+    is_unspent: F,
+) -> Result<usize, Error> {
+    let prevout_to_contract_map = &mut wallet_file_data.prevout_to_contract_map;
+    let cache_heights = &mut wallet_file_data.prevout_cache_heights;
+    cache_heights.retain(|prevout, _| prevout_to_contract_map.contains_key(prevout));
+    let mut expired_prevouts = Vec::<OutPoint>::new();
+    for prevout in prevout_to_contract_map.keys() {
+        let height = *cache_heights.entry(*prevout).or_insert(tip_height);
+        if height + PREVOUT_CACHE_EXPIRY_BLOCKS <= tip_height && !is_unspent(prevout)? {
+            expired_prevouts.push(*prevout);
+        }
+    }
+    for prevout in &expired_prevouts {
+        prevout_to_contract_map.remove(prevout);
+        cache_heights.remove(prevout);
+    }
+    Ok(expired_prevouts.len())
+}
+
 pub fn import_watchonly_redeemscript(
     rpc: &Client,
     redeemscript: &Script,
@@ -1938,8 +2648,94 @@ pub fn convert_json_rpc_bitcoin_to_satoshis(amount: &Value) -> u64 {
         .unwrap()
 }
 
+pub fn reuse_warning(reuse: &AddressReuse) -> String {
+    match reuse.kind {
+        AddressReuseKind::External => format!(
+            "address {} (m/0/{}) was reused, it was paid in {} transactions",
+            reuse.address,
+            reuse.index,
+            reuse.txids.len()
+        ),
+        AddressReuseKind::Change => format!(
+            "change address {} (m/1/{}) was paid in {} transactions, linking them together",
+            reuse.address,
+            reuse.index,
+            reuse.txids.len()
+        ),
+    }
+}
+
+fn group_address_reuse<I: Iterator<Item = (Script, Txid)>>(
+    hd_addresses: &HashMap<Script, (Address, AddressReuseKind, u32)>,
+    payments: I,
+) -> Vec<AddressReuse> {
+    let mut script_txids = HashMap::<Script, HashSet<Txid>>::new();
+    for (script_pubkey, txid) in payments {
+        if hd_addresses.contains_key(&script_pubkey) {
+            script_txids.entry(script_pubkey).or_default().insert(txid);
+        }
+    }
+    let mut address_reuse = script_txids
+        .into_iter()
+        .filter(|(_, txids)| txids.len() > 1)
+        .map(|(script_pubkey, txids)| {
+            let (address, kind, index) = hd_addresses[&script_pubkey].clone();
+            let mut txids = txids.into_iter().collect::<Vec<Txid>>();
+            txids.sort();
+            AddressReuse {
+                address,
+                kind,
+                index,
+                txids,
+            }
+        })
+        .collect::<Vec<AddressReuse>>();
+    address_reuse.sort_by_key(|r| (r.kind, r.index));
+    address_reuse
+}
+
+pub fn master_key_from_seed_phrase(
+    seedphrase: &str,
+    extension: &str,
+    network: Network,
+) -> Result<ExtendedPrivKey, Error> {
+    let mnemonic = mnemonic::Mnemonic::from_str(seedphrase)
+        .map_err(|_| Error::Disk(io::Error::new(io::ErrorKind::Other, "invalid seed phrase")))?;
+    let seed = mnemonic.to_seed(Some(extension));
+    Ok(ExtendedPrivKey::new_master(network, &seed.0).unwrap())
+}
+
+/// The first count addresses of the receive branch of master_key
+pub fn derive_receive_addresses(
+    master_key: &ExtendedPrivKey,
+    network: Network,
+    count: u32,
+) -> Vec<Address> {
+    let secp = Secp256k1::new();
+    let receive_branch = ExtendedPubKey::from_private(
+        &secp,
+        &master_key
+            .derive_priv(&secp, &DerivationPath::from_str(DERIVATION_PATH).unwrap())
+            .unwrap(),
+    )
+    .ckd_pub(&secp, ChildNumber::Normal { index: 0 })
+    .unwrap();
+    (0..count)
+        .map(|i| {
+            Address::p2wpkh(
+                &receive_branch
+                    .ckd_pub(&secp, ChildNumber::Normal { index: i })
+                    .unwrap()
+                    .public_key,
+                network,
+            )
+            .unwrap()
+        })
+        .collect()
+}
+
 // returns None if not a hd descriptor (but possibly a swapcoin (multisig) descriptor instead)
-fn get_hd_path_from_descriptor(descriptor: &str) -> Option<(&str, u32, i32)> {
+pub(crate) fn get_hd_path_from_descriptor(descriptor: &str) -> Option<(&str, u32, i32)> {
     //e.g
     //"desc": "wpkh([a945b5ca/1/1]029b77637989868dcd502dbc07d6304dc2150301693ae84a60b379c3b696b289ad)#aq759em9",
     let open = descriptor.find('[');
@@ -1957,7 +2753,7 @@ fn get_hd_path_from_descriptor(descriptor: &str) -> Option<(&str, u32, i32)> {
     }
     let addr_type = path_chunks[1].parse::<u32>();
     if addr_type.is_err() {
-        log::debug!(target: "wallet", "unexpected address_type = {}", path);
+        tracing::debug!(target: "wallet", "unexpected address_type = {}", path);
         return None;
     }
     let index = path_chunks[2].parse::<i32>();
@@ -1966,3 +2762,190 @@ fn get_hd_path_from_descriptor(descriptor: &str) -> Option<(&str, u32, i32)> {
     }
     Some((path_chunks[0], addr_type.unwrap(), index.unwrap()))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_seed_phrase_addresses() {
+        let seedphrase = "abandon abandon abandon abandon abandon abandon abandon abandon \
+            abandon abandon abandon about";
+        //the test vector of bip84, m/84'/0'/0'/0/0
+        let secp = Secp256k1::new();
+        let mainnet_key = master_key_from_seed_phrase(seedphrase, "", Network::Bitcoin).unwrap();
+        let first_key = mainnet_key
+            .derive_priv(&secp, &DerivationPath::from_str("m/84'/0'/0'/0/0").unwrap())
+            .unwrap();
+        assert_eq!(
+            Address::p2wpkh(
+                &ExtendedPubKey::from_private(&secp, &first_key).public_key,
+                Network::Bitcoin
+            )
+            .unwrap()
+            .to_string(),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+
+        //the wallet derives from coin type 1 on every network, m/84'/1'/0'/0/0
+        let master_key = master_key_from_seed_phrase(seedphrase, "", Network::Testnet).unwrap();
+        let addresses = derive_receive_addresses(&master_key, Network::Testnet, 3);
+        assert_eq!(addresses.len(), 3);
+        assert_eq!(
+            addresses[0].to_string(),
+            "tb1q6rz28mcfaxtmd6v789l9rrlrusdprr9pqcpvkl"
+        );
+        assert_ne!(addresses[0], addresses[1]);
+
+        let with_extension =
+            master_key_from_seed_phrase(seedphrase, "extension", Network::Testnet).unwrap();
+        assert_ne!(
+            derive_receive_addresses(&with_extension, Network::Testnet, 3),
+            addresses
+        );
+        assert!(master_key_from_seed_phrase("abandon about", "", Network::Regtest).is_err());
+    }
+
+    #[test]
+    fn test_group_address_reuse() {
+        let addresses = [
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+            "bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c",
+        ]
+        .iter()
+        .map(|a| Address::from_str(a).unwrap())
+        .collect::<Vec<Address>>();
+        let hd_addresses = addresses
+            .iter()
+            .zip([
+                AddressReuseKind::External,
+                AddressReuseKind::External,
+                AddressReuseKind::Change,
+            ])
+            .enumerate()
+            .map(|(i, (a, kind))| (a.script_pubkey(), (a.clone(), kind, i as u32)))
+            .collect::<HashMap<Script, (Address, AddressReuseKind, u32)>>();
+        let txid = |b: u8| Txid::from_slice(&[b; 32]).unwrap();
+        let payments = vec![
+            //the same payment seen by both listunspent and listreceivedbyaddress
+            (addresses[0].script_pubkey(), txid(1)),
+            (addresses[0].script_pubkey(), txid(1)),
+            (addresses[1].script_pubkey(), txid(2)),
+            (addresses[1].script_pubkey(), txid(3)),
+            (addresses[2].script_pubkey(), txid(4)),
+            (addresses[2].script_pubkey(), txid(5)),
+            //not one of ours
+            (Script::new(), txid(6)),
+            (Script::new(), txid(7)),
+        ];
+        let address_reuse = group_address_reuse(&hd_addresses, payments.into_iter());
+        assert_eq!(address_reuse.len(), 2);
+        assert_eq!(address_reuse[0].address, addresses[1]);
+        assert_eq!(address_reuse[0].kind, AddressReuseKind::External);
+        assert_eq!(address_reuse[0].txids, vec![txid(2), txid(3)]);
+        assert_eq!(address_reuse[1].kind, AddressReuseKind::Change);
+        assert!(reuse_warning(&address_reuse[1]).contains("m/1/2"));
+    }
+
+    #[test]
+    fn test_compact_prevout_cache() {
+        let prevout = |b: u8| OutPoint {
+            txid: Txid::from_slice(&[b; 32]).unwrap(),
+            vout: 0,
+        };
+        let mut wallet_file_data = WalletFileData {
+            version: WALLET_FILE_VERSION,
+            seedphrase: String::new(),
+            extension: String::new(),
+            external_index: 0,
+            incoming_swapcoins: Vec::new(),
+            outgoing_swapcoins: Vec::new(),
+            prevout_to_contract_map: (1..=3).map(|b| (prevout(b), Script::new())).collect(),
+            prevout_cache_heights: HashMap::new(),
+            send_history: Vec::new(),
+            swap_history: Vec::new(),
+            birthday_height: None,
+            recovery_internal_index: 0,
+            recovery_descriptor_index: 0,
+        };
+        //prevout 2 is still unspent
+        let is_unspent = |p: &OutPoint| Ok(*p == prevout(2));
+
+        //entries are timed at the first compaction and none have expired yet
+        assert_eq!(
+            compact_prevout_cache(&mut wallet_file_data, 1000, is_unspent).unwrap(),
+            0
+        );
+        assert_eq!(wallet_file_data.prevout_cache_heights[&prevout(1)], 1000);
+        wallet_file_data
+            .prevout_to_contract_map
+            .insert(prevout(4), Script::new());
+        let expiry_height = 1000 + PREVOUT_CACHE_EXPIRY_BLOCKS;
+        assert_eq!(
+            compact_prevout_cache(&mut wallet_file_data, expiry_height - 1, is_unspent).unwrap(),
+            0
+        );
+
+        //spent prevouts expire, the unspent one and the newer entry are kept
+        assert_eq!(
+            compact_prevout_cache(&mut wallet_file_data, expiry_height, is_unspent).unwrap(),
+            2
+        );
+        let mut remaining = wallet_file_data
+            .prevout_to_contract_map
+            .keys()
+            .cloned()
+            .collect::<Vec<OutPoint>>();
+        remaining.sort();
+        assert_eq!(remaining, vec![prevout(2), prevout(4)]);
+        assert_eq!(wallet_file_data.prevout_cache_heights.len(), 2);
+    }
+
+    #[test]
+    fn test_instance_tweakable_keypair() {
+        let seedphrase = "abandon abandon abandon abandon abandon abandon abandon abandon \
+            abandon abandon abandon about";
+        let wallet = Wallet {
+            network: Network::Regtest,
+            master_key: master_key_from_seed_phrase(seedphrase, "", Network::Regtest).unwrap(),
+            wallet_file_name: String::new(),
+            external_index: 0,
+            initial_address_import_count: 0,
+            incoming_swapcoins: HashMap::new(),
+            outgoing_swapcoins: HashMap::new(),
+            offer_maxsize_cache: 0,
+            timelocked_script_index_map: HashMap::new(),
+            birthday_height: None,
+            audit_log: None,
+        };
+        //the first instance keeps the key its existing swapcoins were made with
+        assert_eq!(
+            wallet.get_instance_tweakable_keypair(0),
+            wallet.get_tweakable_keypair()
+        );
+        let points = (0..3)
+            .map(|instance| wallet.get_instance_tweakable_keypair(instance).1)
+            .collect::<Vec<PublicKey>>();
+        assert_ne!(points[0], points[1]);
+        assert_ne!(points[1], points[2]);
+        assert_ne!(points[0], points[2]);
+        //and none of them is an identity key
+        assert_ne!(points[1], wallet.get_identity_keypair(1).1);
+        assert_eq!(
+            wallet.get_instance_tweakable_keypair(1),
+            wallet.get_instance_tweakable_keypair(1)
+        );
+    }
+
+    #[test]
+    fn test_core_wallet_label_problem() {
+        assert!(core_wallet_label_problem("9a1b2c3d").is_none());
+        assert!(core_wallet_label_problem(WATCH_ONLY_SWAPCOIN_LABEL).is_none());
+        assert!(core_wallet_label_problem("").is_some());
+        assert!(core_wallet_label_problem("savings").is_some());
+        assert!(core_wallet_label_problem("9a1b2c3dff").is_some());
+    }
+}
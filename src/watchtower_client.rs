@@ -1,21 +1,44 @@
-const WATCHTOWER_HOSTPORT: &str = "localhost:6103";
-
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::time::Duration;
 
+use bitcoin::OutPoint;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
 use tokio::select;
 use tokio::time::sleep;
+use tokio_socks::tcp::Socks5Stream;
 
+use crate::directory_servers::TOR_ADDR;
 use crate::error::Error;
+use crate::messages::Preimage;
+use crate::settings::{Settings, WatchtowerSettings};
+use crate::utils::teleport_data_dir;
 use crate::watchtower_protocol::{
-    ContractsInfo, MakerToWatchtowerMessage, Ping, WatchContractTxes, WatchtowerToMakerMessage,
+    encrypt_contracts_info, ContractsInfo, GetStatus, HashPreimage, MakerToWatchtowerMessage, Ping,
+    WatchContractTxes, WatchEncryptedContractTxes, WatchFidelityBond, WatchtowerStatus,
+    WatchtowerToMakerMessage,
 };
 
 pub const CONNECT_ATTEMPTS: u32 = 10;
 pub const CONNECT_SLEEP_DELAY_SEC: u64 = 5;
 pub const CONNECT_ATTEMPT_TIMEOUT_SEC: u64 = 10;
 
+const PENDING_MESSAGES_FILE_NAME: &str = "watchtower-pending.json";
+//held while the pending messages file is read and written
+static PENDING_MESSAGES_LOCK: Mutex<()> = Mutex::new(());
+
+//messages which a watchtower didnt acknowledge, with its address, they are sent again
+// when it next answers a ping
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PendingMessages {
+    messages: Vec<(String, MakerToWatchtowerMessage)>,
+}
+
 #[tokio::main]
 pub async fn test_watchtower_client(contracts_to_watch: ContractsInfo) {
     ping_watchtowers().await.unwrap();
@@ -24,6 +47,27 @@ pub async fn test_watchtower_client(contracts_to_watch: ContractsInfo) {
         .unwrap();
 }
 
+fn watchtower_settings() -> WatchtowerSettings {
+    if Settings::is_initialized() {
+        Settings::global().watchtower.clone()
+    } else {
+        Settings::default().watchtower
+    }
+}
+
+fn pending_messages_path() -> PathBuf {
+    teleport_data_dir().join(PENDING_MESSAGES_FILE_NAME)
+}
+
+//contracts and preimages are only sent in plaintext to a watchtower on this machine
+fn is_local_watchtower(address: &str) -> bool {
+    let host = match address.rsplit_once(':') {
+        Some((host, _port)) => host,
+        None => address,
+    };
+    ["localhost", "127.0.0.1", "[::1]"].contains(&host)
+}
+
 fn parse_message(line: &str) -> Result<WatchtowerToMakerMessage, Error> {
     serde_json::from_str::<WatchtowerToMakerMessage>(line)
         .map_err(|_| Error::Protocol("watchtower sent invalid message"))
@@ -32,31 +76,266 @@ fn parse_message(line: &str) -> Result<WatchtowerToMakerMessage, Error> {
 pub async fn register_coinswap_with_watchtowers(
     contracts_to_watch: ContractsInfo,
 ) -> Result<(), Error> {
-    send_message_to_watchtowers(&MakerToWatchtowerMessage::WatchContractTxes(
-        WatchContractTxes {
+    let messages = watchtower_settings()
+        .addresses
+        .into_iter()
+        .map(|address| {
+            let message = if is_local_watchtower(&address) {
+                MakerToWatchtowerMessage::WatchContractTxes(WatchContractTxes {
+                    protocol_version_min: 0,
+                    protocol_version_max: 0,
+                    contracts_to_watch: contracts_to_watch.clone(),
+                })
+            } else {
+                //any one of the contract txes appearing on the network lets the watchtower
+                // read them
+                MakerToWatchtowerMessage::WatchEncryptedContractTxes(WatchEncryptedContractTxes {
+                    protocol_version_min: 0,
+                    protocol_version_max: 0,
+                    encrypted_contracts: contracts_to_watch
+                        .contract_txes
+                        .iter()
+                        .map(|ctx| encrypt_contracts_info(&contracts_to_watch, &ctx.tx.txid()))
+                        .collect(),
+                })
+            };
+            (address, message)
+        })
+        .collect();
+    send_messages_to_watchtowers(messages).await?;
+    tracing::info!("Successfully registered contract txes with watchtower");
+    Ok(())
+}
+
+//the preimage is only sent to watchtowers on this machine, a remote one would learn that a
+// coinswap happened and its hashvalue before any contract appears on the network. remote ones
+// still find preimages in the hashlock spends they see
+pub async fn register_hash_preimage_with_watchtowers(preimage: Preimage) -> Result<(), Error> {
+    let messages = watchtower_settings()
+        .addresses
+        .into_iter()
+        .filter(|address| is_local_watchtower(address))
+        .map(|address| {
+            let message = MakerToWatchtowerMessage::HashPreimage(HashPreimage {
+                protocol_version_min: 0,
+                protocol_version_max: 0,
+                preimage,
+            });
+            (address, message)
+        })
+        .collect();
+    send_messages_to_watchtowers(messages).await?;
+    tracing::info!("Successfully sent hash preimage to watchtower");
+    Ok(())
+}
+
+//the watchtowers alert before the bond unlocks and when it does, locktime is unix time
+pub async fn register_fidelity_bond_with_watchtowers(
+    outpoint: OutPoint,
+    locktime: i64,
+) -> Result<(), Error> {
+    let messages = watchtower_settings()
+        .addresses
+        .into_iter()
+        .map(|address| {
+            let message = MakerToWatchtowerMessage::WatchFidelityBond(WatchFidelityBond {
+                protocol_version_min: 0,
+                protocol_version_max: 0,
+                outpoint,
+                locktime,
+            });
+            (address, message)
+        })
+        .collect();
+    send_messages_to_watchtowers(messages).await?;
+    tracing::info!("Successfully sent fidelity bond to watchtower");
+    Ok(())
+}
+
+//succeeds if any watchtower answers, the ones which do are sent the messages they missed
+pub async fn ping_watchtowers() -> Result<(), Error> {
+    tracing::debug!("pinging watchtowers");
+    let addresses = watchtower_settings().addresses;
+    let ping_results = join_all(addresses.iter().map(|address| async move {
+        let ping = MakerToWatchtowerMessage::Ping(Ping {
             protocol_version_min: 0,
             protocol_version_max: 0,
-            contracts_to_watch,
-        },
-    ))
-    .await?;
-    log::info!("Successfully registered contract txes with watchtower");
+        });
+        send_message_to_watchtower(address, &ping).await?;
+        resend_pending_messages(&pending_messages_path(), address).await
+    }))
+    .await;
+    let mut last_error = Error::Protocol("no watchtowers configured");
+    let mut any_answered = false;
+    for (address, ping_result) in addresses.iter().zip(ping_results) {
+        match ping_result {
+            Ok(()) => any_answered = true,
+            Err(e) => {
+                tracing::warn!("watchtower {} didnt answer ping, error={:?}", address, e);
+                last_error = e;
+            }
+        }
+    }
+    if any_answered {
+        Ok(())
+    } else {
+        Err(last_error)
+    }
+}
+
+//sends every message to its watchtower concurrently, succeeds if any watchtower acknowledged
+// its message. the unacknowledged ones are saved to be sent again by ping_watchtowers
+async fn send_messages_to_watchtowers(
+    messages: Vec<(String, MakerToWatchtowerMessage)>,
+) -> Result<(), Error> {
+    let send_results = join_all(
+        messages
+            .iter()
+            .map(|(address, message)| send_message_to_watchtower(address, message)),
+    )
+    .await;
+    let mut last_error = Error::Protocol("no watchtowers configured");
+    let mut unacknowledged = Vec::<(String, MakerToWatchtowerMessage)>::new();
+    let message_count = messages.len();
+    for ((address, message), send_result) in messages.into_iter().zip(send_results) {
+        match send_result {
+            Ok(()) => tracing::debug!("watchtower {} acknowledged message", address),
+            Err(e) => {
+                tracing::warn!(
+                    "watchtower {} didnt acknowledge message, will resend it later, error={:?}",
+                    address,
+                    e
+                );
+                unacknowledged.push((address, message));
+                last_error = e;
+            }
+        }
+    }
+    if unacknowledged.is_empty() {
+        return Ok(());
+    }
+    let all_unacknowledged = unacknowledged.len() == message_count;
+    add_pending_messages(&pending_messages_path(), unacknowledged)?;
+    if all_unacknowledged {
+        Err(last_error)
+    } else {
+        Ok(())
+    }
+}
+
+fn read_pending_messages(path: &Path) -> Result<PendingMessages, Error> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes).map_err(io::Error::from)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(PendingMessages::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_pending_messages(path: &Path, pending_messages: &PendingMessages) -> Result<(), Error> {
+    fs::write(path, serde_json::to_vec(pending_messages).unwrap())?;
     Ok(())
 }
 
-pub async fn ping_watchtowers() -> Result<(), Error> {
-    log::debug!("pinging watchtowers");
-    send_message_to_watchtowers(&MakerToWatchtowerMessage::Ping(Ping {
+fn add_pending_messages(
+    path: &Path,
+    messages: Vec<(String, MakerToWatchtowerMessage)>,
+) -> Result<(), Error> {
+    let _lock = PENDING_MESSAGES_LOCK.lock().unwrap();
+    let mut pending_messages = read_pending_messages(path)?;
+    pending_messages.messages.extend(messages);
+    write_pending_messages(path, &pending_messages)
+}
+
+//the messages are taken out of the file while being sent so others can be added meanwhile,
+// the ones still not acknowledged are put back
+async fn resend_pending_messages(path: &Path, address: &str) -> Result<(), Error> {
+    let messages = {
+        let _lock = PENDING_MESSAGES_LOCK.lock().unwrap();
+        let mut pending_messages = read_pending_messages(path)?;
+        let (messages, others) = pending_messages
+            .messages
+            .into_iter()
+            .partition::<Vec<_>, _>(|(a, _)| a == address);
+        if messages.is_empty() {
+            return Ok(());
+        }
+        pending_messages.messages = others;
+        write_pending_messages(path, &pending_messages)?;
+        messages
+    };
+    tracing::info!(
+        "Resending {} messages to watchtower {}",
+        messages.len(),
+        address
+    );
+    let mut unacknowledged = Vec::<(String, MakerToWatchtowerMessage)>::new();
+    let mut result = Ok(());
+    for (address, message) in messages {
+        if result.is_ok() {
+            result = send_message_to_watchtower_once(&address, &message).await;
+            if result.is_ok() {
+                continue;
+            }
+        }
+        unacknowledged.push((address, message));
+    }
+    if !unacknowledged.is_empty() {
+        add_pending_messages(path, unacknowledged)?;
+    }
+    result
+}
+
+pub async fn get_watchtower_status(address: &str) -> Result<WatchtowerStatus, Error> {
+    let message = MakerToWatchtowerMessage::GetStatus(GetStatus {
         protocol_version_min: 0,
         protocol_version_max: 0,
-    }))
-    .await
+    });
+    let request = request_watchtower_once(address, &message);
+    let reply = select! {
+        ret = request => ret?,
+        _ = sleep(Duration::from_secs(CONNECT_ATTEMPT_TIMEOUT_SEC)) => {
+            return Err(Error::Protocol("timed out waiting for watchtower status"));
+        },
+    };
+    match reply {
+        WatchtowerToMakerMessage::Status(status) => Ok(status),
+        _ => Err(Error::Protocol("wrong protocol message2 from watchtower")),
+    }
 }
 
-async fn send_message_to_watchtowers_once(message: &MakerToWatchtowerMessage) -> Result<(), Error> {
-    //TODO add support for registering with multiple watchtowers concurrently
+async fn send_message_to_watchtower_once(
+    address: &str,
+    message: &MakerToWatchtowerMessage,
+) -> Result<(), Error> {
+    match request_watchtower_once(address, message).await? {
+        WatchtowerToMakerMessage::Success(_success) => Ok(()),
+        _ => {
+            tracing::trace!(target: "watchtower_client", "wrong protocol message2");
+            Err(Error::Protocol("wrong protocol message2 from watchtower"))
+        }
+    }
+}
 
-    let mut socket = TcpStream::connect(WATCHTOWER_HOSTPORT).await?;
+//sends one message and returns the watchtower's reply to it
+async fn request_watchtower_once(
+    address: &str,
+    message: &MakerToWatchtowerMessage,
+) -> Result<WatchtowerToMakerMessage, Error> {
+    let is_onion = address.contains(".onion:");
+    //a watchtower on this machine isnt a clearnet connection
+    if Settings::is_tor_only() && !is_onion && !is_local_watchtower(address) {
+        return Err(Error::Protocol(
+            "tor only, refusing to contact clearnet watchtower",
+        ));
+    }
+    let mut socket = if is_onion {
+        let socket = TcpStream::connect(TOR_ADDR).await?;
+        Socks5Stream::connect_with_socket(socket, address)
+            .await?
+            .into_inner()
+    } else {
+        TcpStream::connect(address).await?
+    };
 
     let (socket_reader, mut socket_writer) = socket.split();
     let mut socket_reader = BufReader::new(socket_reader);
@@ -73,35 +352,31 @@ async fn send_message_to_watchtowers_once(message: &MakerToWatchtowerMessage) ->
         if let WatchtowerToMakerMessage::WatchtowerHello(h) = parse_message(&line1)? {
             h
         } else {
-            log::trace!(target: "watchtower_client", "wrong protocol message");
+            tracing::trace!(target: "watchtower_client", "wrong protocol message");
             return Err(Error::Protocol("wrong protocol message from watchtower"));
         };
-    log::trace!(target: "watchtower_client", "watchtower hello = {:?}", _watchtower_hello);
+    tracing::trace!(target: "watchtower_client", "watchtower hello = {:?}", _watchtower_hello);
 
     let mut line2 = String::new();
     if socket_reader.read_line(&mut line2).await? == 0 {
         return Err(Error::Protocol("watchtower eof"));
     }
-    let _success = if let WatchtowerToMakerMessage::Success(s) = parse_message(&line2)? {
-        s
-    } else {
-        log::trace!(target: "watchtower_client", "wrong protocol message2");
-        return Err(Error::Protocol("wrong protocol message2 from watchtower"));
-    };
-
-    Ok(())
+    parse_message(&line2)
 }
 
-async fn send_message_to_watchtowers(message: &MakerToWatchtowerMessage) -> Result<(), Error> {
+async fn send_message_to_watchtower(
+    address: &str,
+    message: &MakerToWatchtowerMessage,
+) -> Result<(), Error> {
     let mut ii = 0;
     loop {
         ii += 1;
         select! {
-            ret = send_message_to_watchtowers_once(message) => {
+            ret = send_message_to_watchtower_once(address, message) => {
                 match ret {
                     Ok(_) => return Ok(()),
                     Err(e) => {
-                        log::warn!(
+                        tracing::warn!(
                             "Failed to send message to watchtower, reattempting... error={:?}",
                             e
                         );
@@ -115,7 +390,7 @@ async fn send_message_to_watchtowers(message: &MakerToWatchtowerMessage) -> Resu
                 }
             },
             _ = sleep(Duration::from_secs(CONNECT_ATTEMPT_TIMEOUT_SEC)) => {
-                log::warn!(
+                tracing::warn!(
                     "Timeout for sending message to watchtower, reattempting...",
                 );
                 if ii <= CONNECT_ATTEMPTS {
@@ -128,3 +403,98 @@ async fn send_message_to_watchtowers(message: &MakerToWatchtowerMessage) -> Resu
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tempfile::tempdir;
+    use tokio::net::TcpListener;
+
+    use crate::watchtower_protocol::{Success, WatchtowerHello};
+
+    #[test]
+    fn test_is_local_watchtower() {
+        for (address, is_local) in [
+            ("localhost:6103", true),
+            ("127.0.0.1:6103", true),
+            ("[::1]:6103", true),
+            ("192.168.1.2:6103", false),
+            ("abcdef.onion:6103", false),
+            ("localhost.example.com:6103", false),
+        ] {
+            assert_eq!(is_local_watchtower(address), is_local, "{}", address);
+        }
+    }
+
+    fn preimage_message(preimage: Preimage) -> MakerToWatchtowerMessage {
+        MakerToWatchtowerMessage::HashPreimage(HashPreimage {
+            protocol_version_min: 0,
+            protocol_version_max: 0,
+            preimage,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_resend_pending_messages() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(PENDING_MESSAGES_FILE_NAME);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        //bound then dropped so nothing listens there
+        let offline_address = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+
+        add_pending_messages(
+            &path,
+            vec![
+                (address.clone(), preimage_message([1u8; 32])),
+                (offline_address.clone(), preimage_message([2u8; 32])),
+                (address.clone(), preimage_message([3u8; 32])),
+            ],
+        )
+        .unwrap();
+
+        //acknowledges two messages like a watchtower would
+        let watchtower = tokio::spawn(async move {
+            let mut preimages = Vec::new();
+            for _ in 0..2 {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let (reader, mut writer) = socket.split();
+                let mut line = String::new();
+                BufReader::new(reader).read_line(&mut line).await.unwrap();
+                match serde_json::from_str(&line).unwrap() {
+                    MakerToWatchtowerMessage::HashPreimage(hp) => preimages.push(hp.preimage),
+                    _ => panic!("wrong message"),
+                }
+                for reply in [
+                    WatchtowerToMakerMessage::WatchtowerHello(WatchtowerHello {
+                        protocol_version_min: 0,
+                        protocol_version_max: 0,
+                    }),
+                    WatchtowerToMakerMessage::Success(Success),
+                ] {
+                    let mut reply = serde_json::to_vec(&reply).unwrap();
+                    reply.push(b'\n');
+                    writer.write_all(&reply).await.unwrap();
+                }
+            }
+            preimages
+        });
+
+        resend_pending_messages(&path, &address).await.unwrap();
+        assert_eq!(watchtower.await.unwrap(), vec![[1u8; 32], [3u8; 32]]);
+        let pending_messages = read_pending_messages(&path).unwrap().messages;
+        assert_eq!(pending_messages.len(), 1);
+        assert_eq!(pending_messages[0].0, offline_address);
+
+        assert!(resend_pending_messages(&path, &offline_address)
+            .await
+            .is_err());
+        assert_eq!(read_pending_messages(&path).unwrap().messages.len(), 1);
+    }
+}
@@ -13,26 +13,36 @@ use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::tcp::WriteHalf;
 use tokio::net::TcpListener;
 use tokio::select;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use tokio::time::sleep;
 
 use serde::{Deserialize, Serialize};
 
-use bitcoin::hashes::{hash160::Hash as Hash160, Hash};
-use bitcoin::{Address, Network, Script, Transaction, Txid};
+use bitcoin::hashes::{hash160::Hash as Hash160, sha256, Hash};
+use bitcoin::{Address, BlockHash, Network, OutPoint, Script, Transaction, TxIn, Txid};
 use bitcoincore_rpc::{
     json::{GetBlockResult, ListTransactionResult},
-    Client, RpcApi,
+    RpcApi,
 };
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use rand::Rng;
 
+use crate::alerts::{send_alert, Alert};
 use crate::contracts::{
     create_contract_redeemscript, read_hashlock_pubkey_from_contract, read_hashvalue_from_contract,
     read_locktime_from_contract, read_timelock_pubkey_from_contract,
 };
 use crate::error::Error;
+use crate::hooks::{spawn_hook, HookEvent};
+use crate::messages::Preimage;
+use crate::rpc::Client;
+use crate::settings::Settings;
 use crate::utils::teleport_data_dir;
 use crate::wallet_sync::import_redeemscript;
 
+//TODO port number in config file
+pub const WATCHTOWER_PORT: u16 = 6103;
+
 //TODO these two structs below are used for two different purposes
 //one purpose is as a message format for messages sent down the wire
 //the other is as internal data stores for this watchtower application
@@ -46,6 +56,8 @@ pub struct ContractTransaction {
     pub hashlock_spend_without_preimage: Option<Transaction>,
     pub timelock_spend: Option<Transaction>,
     pub timelock_spend_broadcasted: bool,
+    #[serde(default)]
+    pub hashlock_spend_broadcasted: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
@@ -54,12 +66,58 @@ pub struct ContractsInfo {
     pub wallet_label: String,
 }
 
+//contracts for a watchtower which isnt trusted with them until they're broadcast
+//one of these is made for each contract tx of a coinswap, the ContractsInfo is encrypted
+// with a key derived from that txid, and the first half of the txid is the hint which
+// tells the watchtower which blob to try decrypting when it sees a tx on the network
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct EncryptedContracts {
+    pub hint: [u8; 16],
+    //nonce, then ciphertext, then tag
+    pub blob: Vec<u8>,
+}
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
 #[derive(Debug, Serialize, Deserialize)]
 struct WatchtowerDataFile {
     coinswap_in_progress_contracts: Vec<ContractsInfo>,
     last_checked_block_height: Option<u64>,
+    //hash of the block at last_checked_block_height, to find out when it was reorged out
+    #[serde(default)]
+    last_checked_block_hash: Option<BlockHash>,
     live_contracts: Vec<ContractsInfo>,
     last_checked_txid: Option<Txid>,
+    //preimages the makers told us, so their incoming contracts can be claimed straight away
+    #[serde(default)]
+    hash_preimages: Vec<Preimage>,
+    #[serde(default)]
+    encrypted_contracts: Vec<EncryptedContracts>,
+    #[serde(default)]
+    funding_outputs: Vec<FundingOutput>,
+    #[serde(default)]
+    fidelity_bonds: Vec<WatchedFidelityBond>,
+}
+
+//a funding output spent by the contract txes of an in progress coinswap, added once it
+// is seen unspent in the utxo set
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+struct FundingOutput {
+    outpoint: OutPoint,
+    //block height when it was first seen spent in a block, the spend is at least this deep
+    spent_height: Option<u64>,
+}
+
+//a maker's fidelity bond, the maker is alerted fidelity_bond_reminder_days before it unlocks so
+// it can plan moving the coins into a new bond, and again when it unlocks
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct WatchedFidelityBond {
+    pub outpoint: OutPoint,
+    //unix time
+    pub locktime: i64,
+    #[serde(default)]
+    pub reminder_sent: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -69,17 +127,49 @@ pub struct WatchContractTxes {
     pub contracts_to_watch: ContractsInfo,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WatchEncryptedContractTxes {
+    pub protocol_version_min: u32,
+    pub protocol_version_max: u32,
+    pub encrypted_contracts: Vec<EncryptedContracts>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GetStatus {
+    pub protocol_version_min: u32,
+    pub protocol_version_max: u32,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Ping {
     pub protocol_version_min: u32,
     pub protocol_version_max: u32,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HashPreimage {
+    pub protocol_version_min: u32,
+    pub protocol_version_max: u32,
+    pub preimage: Preimage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WatchFidelityBond {
+    pub protocol_version_min: u32,
+    pub protocol_version_max: u32,
+    pub outpoint: OutPoint,
+    pub locktime: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "method", rename_all = "lowercase")]
 pub enum MakerToWatchtowerMessage {
     Ping(Ping),
     WatchContractTxes(WatchContractTxes),
+    WatchEncryptedContractTxes(WatchEncryptedContractTxes),
+    HashPreimage(HashPreimage),
+    GetStatus(GetStatus),
+    WatchFidelityBond(WatchFidelityBond),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -91,11 +181,44 @@ pub struct WatchtowerHello {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Success;
 
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct WatchedContracts {
+    //"in_progress" until one of the contract txes is seen on the network, then "live"
+    pub state: String,
+    pub txids: Vec<Txid>,
+    //None if the contracts were registered without their redeemscripts
+    pub locktime: Option<u16>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WatchtowerStatus {
+    pub bitcoin_node_connected: bool,
+    pub block_height: Option<u64>,
+    pub last_checked_block_height: Option<u64>,
+    pub accepting_clients: bool,
+    pub contracts: Vec<WatchedContracts>,
+    //these cant be read until a contract tx is seen
+    pub encrypted_contracts: usize,
+    #[serde(default)]
+    pub fidelity_bonds: Vec<WatchedFidelityBond>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "method", rename_all = "lowercase")]
 pub enum WatchtowerToMakerMessage {
     WatchtowerHello(WatchtowerHello),
     Success(Success),
+    Status(WatchtowerStatus),
+}
+
+//passes the requests of client connections to the main loop
+#[derive(Clone)]
+struct WatchtowerComms {
+    watched_txes: mpsc::Sender<ContractsInfo>,
+    encrypted_txes: mpsc::Sender<Vec<EncryptedContracts>>,
+    preimages: mpsc::Sender<Preimage>,
+    fidelity_bonds: mpsc::Sender<WatchedFidelityBond>,
+    status_requests: mpsc::Sender<oneshot::Sender<WatchtowerStatus>>,
 }
 
 //the point of these Display structs is so that ContractsInfo can be printed
@@ -135,6 +258,19 @@ impl ContractsInfoDisplay {
     }
 }
 
+//contracts of one coinswap all share a hashvalue, which is also the swap id the taker and
+// makers use in their logs
+fn swap_span(contracts_info: &ContractsInfo) -> tracing::Span {
+    match contracts_info
+        .contract_txes
+        .first()
+        .map(|ctx| read_hashvalue_from_contract(&ctx.redeemscript))
+    {
+        Some(Ok(hashvalue)) => tracing::info_span!("swap", swap_id = %hashvalue),
+        _ => tracing::info_span!("swap"),
+    }
+}
+
 #[tokio::main]
 pub async fn start_watchtower(
     rpc: &Client,
@@ -143,8 +279,8 @@ pub async fn start_watchtower(
     kill_flag: Arc<RwLock<bool>>,
 ) {
     match run(rpc, data_file_path, network, kill_flag).await {
-        Ok(_o) => log::info!("watchtower ended without error"),
-        Err(e) => log::info!("watchtower ended with err {:?}", e),
+        Ok(_o) => tracing::info!("watchtower ended without error"),
+        Err(e) => tracing::info!("watchtower ended with err {:?}", e),
     };
 }
 
@@ -154,15 +290,14 @@ async fn run(
     network: Network,
     kill_flag: Arc<RwLock<bool>>,
 ) -> Result<(), Error> {
-    //TODO port number in config file
-    let port = 6103;
+    let port = WATCHTOWER_PORT;
     let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
-    log::info!("Starting teleport watchtower. Listening On Port {}", port);
+    tracing::info!("Starting teleport watchtower. Listening On Port {}", port);
 
     let data_file = read_from_data_file(data_file_path);
     if data_file.is_ok() {
         let data_file = data_file.unwrap();
-        log::info!(
+        tracing::info!(
             "Loaded data file. Coinswap contracts in progress = {}, live_contracts = {}",
             data_file.coinswap_in_progress_contracts.len(),
             data_file.live_contracts.len()
@@ -173,16 +308,40 @@ async fn run(
             WatchtowerDataFile {
                 coinswap_in_progress_contracts: Vec::<ContractsInfo>::new(),
                 last_checked_block_height: None,
+                last_checked_block_hash: None,
                 live_contracts: Vec::<ContractsInfo>::new(),
                 last_checked_txid: None,
+                hash_preimages: Vec::<Preimage>::new(),
+                encrypted_contracts: Vec::<EncryptedContracts>::new(),
+                funding_outputs: Vec::<FundingOutput>::new(),
+                fidelity_bonds: Vec::<WatchedFidelityBond>::new(),
             },
         )?;
     }
 
     let (watched_txes_comms_tx, mut watched_txes_comms_rx) = mpsc::channel::<ContractsInfo>(100);
+    let (preimage_comms_tx, mut preimage_comms_rx) = mpsc::channel::<Preimage>(100);
+    let (fidelity_bond_comms_tx, mut fidelity_bond_comms_rx) =
+        mpsc::channel::<WatchedFidelityBond>(100);
+    let (encrypted_txes_comms_tx, mut encrypted_txes_comms_rx) =
+        mpsc::channel::<Vec<EncryptedContracts>>(100);
+    let (status_comms_tx, mut status_comms_rx) =
+        mpsc::channel::<oneshot::Sender<WatchtowerStatus>>(100);
+    let comms = WatchtowerComms {
+        watched_txes: watched_txes_comms_tx,
+        encrypted_txes: encrypted_txes_comms_tx,
+        preimages: preimage_comms_tx,
+        fidelity_bonds: fidelity_bond_comms_tx,
+        status_requests: status_comms_tx,
+    };
 
     let (server_loop_err_comms_tx, mut server_loop_err_comms_rx) = mpsc::channel::<Error>(100);
     let mut accepting_clients = true;
+    //mempool txes already checked for hashlock spends, not saved because after a restart
+    // checking the mempool again is cheap
+    let mut checked_mempool_txids = HashSet::<Txid>::new();
+    //contract txes whose timelock expiry was already alerted
+    let mut timelock_alerted_txids = HashSet::<Txid>::new();
 
     loop {
         let (mut socket, addr) = select! {
@@ -193,20 +352,21 @@ async fn run(
                     Error::Rpc(e) => {
                         let rpc_connection_success = rpc.get_best_block_hash().is_ok();
                         if !rpc_connection_success {
-                            log::warn!("lost connection with bitcoin node, temporarily shutting \
+                            tracing::warn!("lost connection with bitcoin node, temporarily shutting \
                                       down server until connection reestablished, error={:?}", e);
                             accepting_clients = false;
                         }
                         continue;
                     },
-                    _ => log::error!("ending watchtower"),
+                    _ => tracing::error!("ending watchtower"),
                 }
                 break Err(client_err.unwrap());
             },
             new_watched_txes = watched_txes_comms_rx.recv() => {
                 //unwrap the option here because we'll never close the mscp so it will always work
                 let new_watched_contracts = new_watched_txes.as_ref().unwrap();
-                log::info!("New watched contracts = {:?}",
+                let _span = swap_span(new_watched_contracts).entered();
+                tracing::info!("New watched contracts = {:?}",
                     new_watched_contracts.contract_txes
                         .iter()
                         .map(|ctx| ctx.tx.txid())
@@ -219,6 +379,63 @@ async fn run(
 
                 continue;
             },
+            new_encrypted_txes = encrypted_txes_comms_rx.recv() => {
+                //unwrap the option here because we'll never close the mscp so it will always work
+                let new_encrypted_contracts = new_encrypted_txes.unwrap();
+                tracing::info!("New encrypted contracts, count = {}", new_encrypted_contracts.len());
+
+                let mut data_file = read_from_data_file(data_file_path)?;
+                data_file.encrypted_contracts.extend(new_encrypted_contracts);
+                write_to_data_file(data_file_path, data_file)?;
+
+                continue;
+            },
+            status_request = status_comms_rx.recv() => {
+                //unwrap the option here because we'll never close the mscp so it will always work
+                let status_reply_tx = status_request.unwrap();
+                let data_file = read_from_data_file(data_file_path)?;
+                let block_height = rpc.get_block_count().ok();
+                let status = WatchtowerStatus {
+                    bitcoin_node_connected: block_height.is_some(),
+                    block_height,
+                    last_checked_block_height: data_file.last_checked_block_height,
+                    accepting_clients,
+                    contracts: watched_contracts(&data_file),
+                    encrypted_contracts: data_file.encrypted_contracts.len(),
+                    fidelity_bonds: data_file.fidelity_bonds.clone(),
+                };
+                //the connection might have closed meanwhile
+                let _ = status_reply_tx.send(status);
+                continue;
+            },
+            new_preimage = preimage_comms_rx.recv() => {
+                //unwrap the option here because we'll never close the mscp so it will always work
+                let preimage = new_preimage.unwrap();
+                tracing::info!("New hash preimage for hashvalue={}", Hash160::hash(&preimage));
+
+                let mut data_file = read_from_data_file(data_file_path)?;
+                if !data_file.hash_preimages.contains(&preimage) {
+                    data_file.hash_preimages.push(preimage);
+                }
+                write_to_data_file(data_file_path, data_file)?;
+
+                continue;
+            },
+            new_fidelity_bond = fidelity_bond_comms_rx.recv() => {
+                //unwrap the option here because we'll never close the mscp so it will always work
+                let fidelity_bond = new_fidelity_bond.unwrap();
+                tracing::info!("New fidelity bond {} unlocking at {}", fidelity_bond.outpoint,
+                    fidelity_bond.locktime);
+
+                //makers register their bond every time they start
+                let mut data_file = read_from_data_file(data_file_path)?;
+                if !data_file.fidelity_bonds.iter().any(|b| b.outpoint == fidelity_bond.outpoint) {
+                    data_file.fidelity_bonds.push(fidelity_bond);
+                }
+                write_to_data_file(data_file_path, data_file)?;
+
+                continue;
+            },
             //TODO make a const for this magic number of how often to poll, see similar
             // comment in maker_protocol.rs
             _ = sleep(Duration::from_secs(10)) => {
@@ -227,19 +444,18 @@ async fn run(
                 let contract_check_result = run_contract_checks(
                     rpc,
                     network,
-                    &mut data_file.coinswap_in_progress_contracts,
-                    &mut data_file.last_checked_block_height,
-                    &mut data_file.live_contracts,
-                    &mut data_file.last_checked_txid
+                    &mut data_file,
+                    &mut checked_mempool_txids,
+                    &mut timelock_alerted_txids,
                 );
                 accepting_clients = contract_check_result.is_ok();
                 if !accepting_clients {
-                    log::warn!("not accepting clients, error={:?}", contract_check_result);
+                    tracing::warn!("not accepting clients, error={:?}", contract_check_result);
                 }
 
                 write_to_data_file(data_file_path, data_file)?;
 
-                log::debug!("Heartbeat, accepting clients on port {}", port);
+                tracing::debug!("Heartbeat, accepting clients on port {}", port);
                 if *kill_flag.read().unwrap() {
                     break Err(Error::Protocol("kill flag is true"));
                 }
@@ -247,14 +463,15 @@ async fn run(
             },
         };
 
+        //connections are still accepted so the status can be asked for, other requests are
+        // rejected in handle_message
         if !accepting_clients {
-            log::warn!("Rejecting Connection From {:?}", addr);
-            continue;
+            tracing::warn!("Only answering status requests from {:?}", addr);
         }
 
-        log::info!("<=== [{}] | Accepted Connection From", addr.port());
+        tracing::info!("<=== [{}] | Accepted Connection From", addr.port());
         let server_loop_err_comms_tx = server_loop_err_comms_tx.clone();
-        let watched_txes_comms_tx = watched_txes_comms_tx.clone();
+        let comms = comms.clone();
 
         tokio::spawn(async move {
             let (socket_reader, mut socket_writer) = socket.split();
@@ -269,7 +486,7 @@ async fn run(
             )
             .await
             {
-                log::error!("io error sending first message: {:?}", e);
+                tracing::error!("io error sending first message: {:?}", e);
                 return;
             }
 
@@ -277,31 +494,32 @@ async fn run(
                 let mut line = String::new();
                 match reader.read_line(&mut line).await {
                     Ok(n) if n == 0 => {
-                        log::info!("Connection closed by peer");
+                        tracing::info!("Connection closed by peer");
                         break;
                     }
                     Ok(_n) => (),
                     Err(e) => {
-                        log::error!("error reading from socket: {:?}", e);
+                        tracing::error!("error reading from socket: {:?}", e);
                         break;
                     }
                 };
 
                 line = line.trim_end().to_string();
-                let message_result = handle_message(line, &watched_txes_comms_tx).await;
+                let message_result = handle_message(line, &comms, accepting_clients).await;
                 match message_result {
-                    Ok(()) => {
-                        let success_message = WatchtowerToMakerMessage::Success(Success);
-                        if let Err(e) = send_message(&mut socket_writer, &success_message).await {
-                            log::error!("closing due to io error sending message: {:?}", e);
+                    Ok(reply) => {
+                        if let Err(e) = send_message(&mut socket_writer, &reply).await {
+                            tracing::error!("closing due to io error sending message: {:?}", e);
                             break;
                         }
                     }
                     Err(err) => {
-                        log::error!("error handling request: {:?}", err);
+                        tracing::error!("error handling request: {:?}", err);
                         match err {
                             Error::Network(_e) => (),
                             Error::Protocol(_e) => (),
+                            Error::FundingTxFeeRate { .. } => (),
+                            Error::Config(_e) => (),
                             Error::Disk(e) => {
                                 server_loop_err_comms_tx.send(Error::Disk(e)).await.unwrap()
                             }
@@ -333,26 +551,87 @@ async fn send_message(
 
 async fn handle_message(
     line: String,
-    watched_txes_comms_tx: &mpsc::Sender<ContractsInfo>,
-) -> Result<(), Error> {
+    comms: &WatchtowerComms,
+    accepting_clients: bool,
+) -> Result<WatchtowerToMakerMessage, Error> {
     let request: MakerToWatchtowerMessage = match serde_json::from_str(&line) {
         Ok(r) => r,
         Err(_e) => return Err(Error::Protocol("message parsing error")),
     };
-    log::debug!("request = {:?}", request);
+    tracing::debug!("request = {:?}", request);
     match request {
+        MakerToWatchtowerMessage::GetStatus(_get_status) => {
+            let (status_reply_tx, status_reply_rx) = oneshot::channel();
+            comms.status_requests.send(status_reply_tx).await.unwrap();
+            let status = status_reply_rx
+                .await
+                .map_err(|_| Error::Protocol("watchtower stopped"))?;
+            return Ok(WatchtowerToMakerMessage::Status(status));
+        }
+        _ if !accepting_clients => {
+            return Err(Error::Protocol("watchtower not accepting clients"));
+        }
         MakerToWatchtowerMessage::Ping(_ping) => {}
         MakerToWatchtowerMessage::WatchContractTxes(watch_contract_txes_message) => {
             //TODO check that all the hashvalues are the same
-            watched_txes_comms_tx
+            comms
+                .watched_txes
                 .send(watch_contract_txes_message.contracts_to_watch)
                 .await
                 .unwrap();
             //TODO can someone crash the watchtower by maxing out this list?
             //only the maker knows this watchtower's address though, a maker wont crash their own
         }
+        MakerToWatchtowerMessage::WatchEncryptedContractTxes(watch_encrypted_message) => {
+            comms
+                .encrypted_txes
+                .send(watch_encrypted_message.encrypted_contracts)
+                .await
+                .unwrap();
+        }
+        MakerToWatchtowerMessage::HashPreimage(hash_preimage_message) => {
+            comms
+                .preimages
+                .send(hash_preimage_message.preimage)
+                .await
+                .unwrap();
+        }
+        MakerToWatchtowerMessage::WatchFidelityBond(watch_fidelity_bond) => {
+            comms
+                .fidelity_bonds
+                .send(WatchedFidelityBond {
+                    outpoint: watch_fidelity_bond.outpoint,
+                    locktime: watch_fidelity_bond.locktime,
+                    reminder_sent: false,
+                })
+                .await
+                .unwrap();
+        }
     }
-    Ok(())
+    Ok(WatchtowerToMakerMessage::Success(Success))
+}
+
+fn watched_contracts(data_file: &WatchtowerDataFile) -> Vec<WatchedContracts> {
+    let in_progress = data_file
+        .coinswap_in_progress_contracts
+        .iter()
+        .map(|ci| ("in_progress", ci));
+    let live = data_file.live_contracts.iter().map(|ci| ("live", ci));
+    in_progress
+        .chain(live)
+        .map(|(state, contracts_info)| WatchedContracts {
+            state: state.to_string(),
+            txids: contracts_info
+                .contract_txes
+                .iter()
+                .map(|ctx| ctx.tx.txid())
+                .collect(),
+            locktime: contracts_info
+                .contract_txes
+                .first()
+                .and_then(|ctx| read_locktime_from_contract(&ctx.redeemscript)),
+        })
+        .collect()
 }
 
 fn read_from_data_file<P: AsRef<Path>>(data_file_path: P) -> Result<WatchtowerDataFile, Error> {
@@ -375,19 +654,29 @@ fn write_to_data_file<P: AsRef<Path>>(
 fn run_contract_checks(
     rpc: &Client,
     network: Network,
-    coinswap_in_progress_contracts: &mut Vec<ContractsInfo>,
-    last_checked_block_height: &mut Option<u64>,
-    live_contracts: &mut Vec<ContractsInfo>,
-    last_checked_txid: &mut Option<Txid>,
+    data_file: &mut WatchtowerDataFile,
+    checked_mempool_txids: &mut HashSet<Txid>,
+    timelock_alerted_txids: &mut HashSet<Txid>,
 ) -> Result<(), bitcoincore_rpc::Error> {
-    log::debug!(
+    let WatchtowerDataFile {
+        coinswap_in_progress_contracts,
+        last_checked_block_height,
+        last_checked_block_hash,
+        live_contracts,
+        last_checked_txid,
+        hash_preimages,
+        encrypted_contracts,
+        funding_outputs,
+        fidelity_bonds,
+    } = data_file;
+    tracing::debug!(
         "coinswap_in_progress_contracts = {:?}",
         coinswap_in_progress_contracts
             .iter()
             .map(ContractsInfoDisplay::from)
             .collect::<Vec<ContractsInfoDisplay>>()
     );
-    log::debug!(
+    tracing::debug!(
         "live_contracts = {:?}",
         live_contracts
             .iter()
@@ -395,13 +684,30 @@ fn run_contract_checks(
             .collect::<Vec<ContractsInfoDisplay>>()
     );
 
-    let broadcasted_contracts = check_for_broadcasted_contract_txes(
-        rpc,
-        coinswap_in_progress_contracts,
-        last_checked_block_height,
-    )?;
+    let network_txs = get_network_txids(rpc, last_checked_block_height, last_checked_block_hash)?;
+    if !encrypted_contracts.is_empty() {
+        //decrypted contracts are in progress, so the check below finds them broadcasted
+        let decrypted_contracts = decrypt_broadcasted_contracts(&network_txs, encrypted_contracts);
+        coinswap_in_progress_contracts.extend(decrypted_contracts);
+    }
+    let broadcasted_contracts =
+        find_broadcasted_contract_txes(rpc, coinswap_in_progress_contracts, &network_txs);
     if !broadcasted_contracts.is_empty() {
         import_broadcasted_contract_redeemscripts(rpc, network, &broadcasted_contracts)?;
+        for contracts_info in &broadcasted_contracts {
+            let txids = contracts_info
+                .contract_txes
+                .iter()
+                .map(|ctx| ctx.tx.txid())
+                .collect::<Vec<Txid>>();
+            send_alert(
+                "watchtower",
+                Alert::ContractBroadcast {
+                    txids: txids.clone(),
+                },
+            );
+            spawn_hook("watchtower", HookEvent::ContractBroadcast { txids });
+        }
         //remove broadcasted_contracts from the vec coinswap_in_progress_contracts
         coinswap_in_progress_contracts.retain(|cipc| {
             !broadcasted_contracts
@@ -409,20 +715,157 @@ fn run_contract_checks(
         });
         live_contracts.extend(broadcasted_contracts);
     }
+    if let Some(block_height) = *last_checked_block_height {
+        prune_settled_contracts(
+            rpc,
+            block_height,
+            coinswap_in_progress_contracts,
+            funding_outputs,
+        )?;
+    }
 
+    if !live_contracts.is_empty() {
+        //redeemscripts are imported without a rescan so the wallet wont see hashlock spends
+        // which were already in mempool, also this finds preimages before they're mined
+        let mempool_closed_contracts =
+            check_mempool_for_hashlock_spends(rpc, live_contracts, checked_mempool_txids)?;
+        live_contracts.retain(|lc| !mempool_closed_contracts.contains(lc));
+    }
     if !live_contracts.is_empty() {
         let mut closed_contracts =
             check_for_hashlock_spends(rpc, live_contracts, last_checked_txid)?;
         let closed_contracts2 = check_for_timelock_maturity(rpc, live_contracts)?;
         closed_contracts.extend(closed_contracts2);
+        let closed_contracts3 =
+            check_for_known_preimage_spends(rpc, live_contracts, hash_preimages)?;
+        closed_contracts.extend(closed_contracts3);
+        check_for_timelocks_near_expiry(rpc, live_contracts, timelock_alerted_txids)?;
         if !closed_contracts.is_empty() {
             //remove closed_contracts from the vec coinswap_in_progress_contracts
             live_contracts.retain(|cipc| !closed_contracts.iter().any(|c| c == cipc));
         }
     }
+    //forget preimages once all the contracts they unlock are closed
+    hash_preimages.retain(|preimage| {
+        let hashvalue = Hash160::hash(preimage);
+        coinswap_in_progress_contracts
+            .iter()
+            .chain(live_contracts.iter())
+            .any(|ci| contracts_hashvalue(ci) == Some(hashvalue))
+    });
+    if !fidelity_bonds.is_empty() {
+        check_for_fidelity_bond_expiry(rpc, fidelity_bonds)?;
+    }
+    Ok(())
+}
+
+fn contract_funding_outpoints(
+    contracts_info: &ContractsInfo,
+) -> impl Iterator<Item = OutPoint> + '_ {
+    contracts_info
+        .contract_txes
+        .iter()
+        .flat_map(|ctx| ctx.tx.input.iter().map(|input| input.previous_output))
+}
+
+//a coinswap settled cooperatively when its funding outputs were spent by txes other than
+// its contract txes, which would have been found broadcasted and made live already
+//such coinswaps are forgotten once all those spends are settled_contract_retention_blocks
+// deep, they cant be reorged into a state where the contract txes are valid again
+fn prune_settled_contracts(
+    rpc: &Client,
+    block_height: u64,
+    coinswap_in_progress_contracts: &mut Vec<ContractsInfo>,
+    funding_outputs: &mut Vec<FundingOutput>,
+) -> Result<(), bitcoincore_rpc::Error> {
+    let retention_blocks = if Settings::is_initialized() {
+        Settings::global()
+            .watchtower
+            .settled_contract_retention_blocks
+    } else {
+        Settings::default()
+            .watchtower
+            .settled_contract_retention_blocks
+    };
+    for contracts_info in coinswap_in_progress_contracts.iter() {
+        for outpoint in contract_funding_outpoints(contracts_info) {
+            let unspent = rpc
+                .get_tx_out(&outpoint.txid, outpoint.vout, Some(false))?
+                .is_some();
+            update_funding_output(funding_outputs, outpoint, unspent, block_height);
+        }
+    }
+    coinswap_in_progress_contracts.retain(|contracts_info| {
+        let settled = is_settled(
+            contracts_info,
+            funding_outputs,
+            block_height,
+            retention_blocks,
+        );
+        if settled {
+            let _span = swap_span(contracts_info).entered();
+            tracing::info!("coinswap settled, no longer watching its contracts");
+        }
+        !settled
+    });
+    //also forgets the funding outputs of contracts which went live
+    funding_outputs.retain(|fo| {
+        coinswap_in_progress_contracts
+            .iter()
+            .any(|ci| contract_funding_outpoints(ci).any(|outpoint| outpoint == fo.outpoint))
+    });
     Ok(())
 }
 
+fn update_funding_output(
+    funding_outputs: &mut Vec<FundingOutput>,
+    outpoint: OutPoint,
+    unspent: bool,
+    block_height: u64,
+) {
+    match funding_outputs
+        .iter_mut()
+        .find(|fo| fo.outpoint == outpoint)
+    {
+        Some(funding_output) => {
+            if unspent {
+                //the spend was reorged out
+                funding_output.spent_height = None;
+            } else if funding_output.spent_height.is_none() {
+                funding_output.spent_height = Some(block_height);
+            }
+        }
+        //an output never seen unspent might just not be confirmed yet
+        None if unspent => funding_outputs.push(FundingOutput {
+            outpoint,
+            spent_height: None,
+        }),
+        None => {}
+    }
+}
+
+fn is_settled(
+    contracts_info: &ContractsInfo,
+    funding_outputs: &[FundingOutput],
+    block_height: u64,
+    retention_blocks: u64,
+) -> bool {
+    let mut outpoints = contract_funding_outpoints(contracts_info).peekable();
+    outpoints.peek().is_some()
+        && outpoints.all(|outpoint| {
+            funding_outputs
+                .iter()
+                .find(|fo| fo.outpoint == outpoint)
+                .and_then(|fo| fo.spent_height)
+                .map(|spent_height| block_height >= spent_height + retention_blocks)
+                .unwrap_or(false)
+        })
+}
+
+fn contracts_hashvalue(contracts_info: &ContractsInfo) -> Option<Hash160> {
+    read_hashvalue_from_contract(&contracts_info.contract_txes.first()?.redeemscript).ok()
+}
+
 //the point of this enum is to store whether the transaction is from mempool or from a block
 //the watchtower needs to know that because obtaining the full transaction differs depending
 // if a tx is already in a block you use `getrawtransaction <txid> <blockhash>`
@@ -436,43 +879,109 @@ pub fn check_for_broadcasted_contract_txes(
     rpc: &Client,
     coinswap_in_progress_contracts: &[ContractsInfo],
     last_checked_block_height: &mut Option<u64>,
+    last_checked_block_hash: &mut Option<BlockHash>,
 ) -> Result<Vec<ContractsInfo>, bitcoincore_rpc::Error> {
+    let network_txs = get_network_txids(rpc, last_checked_block_height, last_checked_block_hash)?;
+    Ok(find_broadcasted_contract_txes(
+        rpc,
+        coinswap_in_progress_contracts,
+        &network_txs,
+    ))
+}
+
+//height of the last block of the best chain which the checked block builds on, the checked
+// block's own height unless it was reorged out
+//block_header returns whether a block is in the best chain, its height and its previous block
+fn find_fork_height<E, F>(checked_block_hash: BlockHash, block_header: F) -> Result<u64, E>
+where
+    F: Fn(&BlockHash) -> Result<(bool, u64, Option<BlockHash>), E>,
+{
+    let mut blockhash = checked_block_hash;
+    loop {
+        let (in_best_chain, height, previous_block_hash) = block_header(&blockhash)?;
+        match previous_block_hash {
+            Some(previous) if !in_best_chain => blockhash = previous,
+            _ => return Ok(height),
+        }
+    }
+}
+
+//txids in the mempool and in the blocks since last_checked_block_height, which is updated
+//blocks replaced by a reorg since the last check are checked again from the fork point
+fn get_network_txids(
+    rpc: &Client,
+    last_checked_block_height: &mut Option<u64>,
+    last_checked_block_hash: &mut Option<BlockHash>,
+) -> Result<Vec<TxidListType>, bitcoincore_rpc::Error> {
     let mut network_txs = Vec::<TxidListType>::new();
 
     let mempool_txids = rpc.get_raw_mempool()?;
     network_txs.push(TxidListType::FromMempool(mempool_txids));
 
     if last_checked_block_height.is_none() {
-        log::debug!("initial setting of last_checked_block_height");
+        tracing::debug!("initial setting of last_checked_block_height");
         *last_checked_block_height = Some(rpc.get_block_count()?);
     }
+    if let (Some(checked_height), Some(checked_hash)) =
+        (*last_checked_block_height, *last_checked_block_hash)
+    {
+        //core gives blocks outside the best chain -1 confirmations
+        let fork_height = find_fork_height(
+            checked_hash,
+            |blockhash| -> Result<_, bitcoincore_rpc::Error> {
+                let header = rpc.get_block_header_info(blockhash)?;
+                Ok((
+                    header.confirmations >= 0,
+                    header.height as u64,
+                    header.previous_block_hash,
+                ))
+            },
+        )?;
+        if fork_height < checked_height {
+            tracing::warn!(
+                "reorg of {} blocks, checking blocks again from height {}",
+                checked_height - fork_height,
+                fork_height + 1
+            );
+            *last_checked_block_height = Some(fork_height);
+        }
+    }
     let blockchain_tip_height = rpc.get_block_count()?;
     //note the plus one here
     for height in (last_checked_block_height.unwrap() + 1)..(blockchain_tip_height + 1) {
         let block_info = rpc.get_block_info(&rpc.get_block_hash(height)?)?;
-        log::debug!("height = {}, txes.len = {}", height, block_info.tx.len());
+        tracing::debug!("height = {}, txes.len = {}", height, block_info.tx.len());
         network_txs.push(TxidListType::FromBlock(block_info));
     }
     *last_checked_block_height = Some(blockchain_tip_height);
+    *last_checked_block_hash = Some(rpc.get_block_hash(blockchain_tip_height)?);
+    Ok(network_txs)
+}
 
+fn find_broadcasted_contract_txes(
+    rpc: &Client,
+    coinswap_in_progress_contracts: &[ContractsInfo],
+    network_txs: &[TxidListType],
+) -> Vec<ContractsInfo> {
     let mut broadcasted_contracts = Vec::<ContractsInfo>::new();
     for txid_list_type in network_txs {
         let txid_list = match txid_list_type {
             TxidListType::FromMempool(txids) => {
-                log::debug!("mempool_txids.len = {}", txids.len());
+                tracing::debug!("mempool_txids.len = {}", txids.len());
                 txids
             }
             TxidListType::FromBlock(block_info) => {
-                log::debug!(
+                tracing::debug!(
                     "height = {}, block_txes.len = {}",
                     block_info.height,
                     block_info.tx.len()
                 );
-                block_info.tx
+                &block_info.tx
             }
         };
-        let network_txids = txid_list.into_iter().collect::<HashSet<Txid>>();
+        let network_txids = txid_list.iter().cloned().collect::<HashSet<Txid>>();
         for coinswap_in_progress_contract in coinswap_in_progress_contracts {
+            let _span = swap_span(coinswap_in_progress_contract).entered();
             let contracts_txids = HashSet::from_iter(
                 coinswap_in_progress_contract
                     .contract_txes
@@ -482,7 +991,7 @@ pub fn check_for_broadcasted_contract_txes(
             let contract_txids_on_network = network_txids
                 .intersection(&contracts_txids)
                 .collect::<Vec<&Txid>>();
-            log::debug!(
+            tracing::debug!(
                 "contract_txids_on_network = {:?}",
                 contract_txids_on_network
             );
@@ -506,7 +1015,7 @@ pub fn check_for_broadcasted_contract_txes(
                 })
                 .map(|ctx| &ctx.tx)
                 .collect::<Vec<&Transaction>>();
-            log::info!(
+            tracing::info!(
                 "broadcasting contract txes = {:?}",
                 unbroadcasted_contract_txes
                     .iter()
@@ -514,15 +1023,102 @@ pub fn check_for_broadcasted_contract_txes(
                     .collect::<Vec<Txid>>()
             );
             for tx in unbroadcasted_contract_txes {
-                log::debug!("broadcasting txid = {:?}", tx.txid());
+                tracing::debug!("broadcasting txid = {:?}", tx.txid());
                 let ret_txid = rpc.send_raw_transaction(tx);
                 if ret_txid.is_err() {
-                    log::debug!("broadcast failed = {:?}", ret_txid);
+                    tracing::debug!("broadcast failed = {:?}", ret_txid);
                 }
             }
         }
     }
-    Ok(broadcasted_contracts)
+    broadcasted_contracts
+}
+
+fn contracts_key(txid: &Txid) -> sha256::Hash {
+    sha256::Hash::hash(&txid[..])
+}
+
+pub fn encrypt_contracts_info(contracts_info: &ContractsInfo, txid: &Txid) -> EncryptedContracts {
+    let plaintext = serde_json::to_vec(contracts_info).unwrap();
+    let nonce = rand::thread_rng().gen::<[u8; NONCE_LEN]>();
+    let mut tag = [0u8; TAG_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::chacha20_poly1305(),
+        &contracts_key(txid)[..],
+        Some(&nonce),
+        &[],
+        &plaintext,
+        &mut tag,
+    )
+    .unwrap();
+    let mut hint = [0u8; 16];
+    hint.copy_from_slice(&txid[..16]);
+    EncryptedContracts {
+        hint,
+        blob: [&nonce[..], &ciphertext, &tag].concat(),
+    }
+}
+
+//returns None if the blob wasnt encrypted for this txid
+pub fn decrypt_contracts_info(
+    encrypted_contracts: &EncryptedContracts,
+    txid: &Txid,
+) -> Option<ContractsInfo> {
+    let blob = &encrypted_contracts.blob;
+    if blob.len() < NONCE_LEN + TAG_LEN {
+        return None;
+    }
+    let (nonce, rest) = blob.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+    let plaintext = decrypt_aead(
+        Cipher::chacha20_poly1305(),
+        &contracts_key(txid)[..],
+        Some(nonce),
+        &[],
+        ciphertext,
+        tag,
+    )
+    .ok()?;
+    serde_json::from_slice::<ContractsInfo>(&plaintext).ok()
+}
+
+//decrypts the contracts whose hint matches a tx on the network, and removes every blob of
+// those coinswaps from encrypted_contracts
+fn decrypt_broadcasted_contracts(
+    network_txs: &[TxidListType],
+    encrypted_contracts: &mut Vec<EncryptedContracts>,
+) -> Vec<ContractsInfo> {
+    let mut decrypted_contracts = Vec::<ContractsInfo>::new();
+    for txid in network_txs
+        .iter()
+        .flat_map(|txid_list_type| match txid_list_type {
+            TxidListType::FromMempool(txids) => txids.iter(),
+            TxidListType::FromBlock(block_info) => block_info.tx.iter(),
+        })
+    {
+        let contracts_info = match encrypted_contracts
+            .iter()
+            .filter(|ec| ec.hint[..] == txid[..16])
+            .find_map(|ec| decrypt_contracts_info(ec, txid))
+        {
+            Some(ci) => ci,
+            None => continue,
+        };
+        let _span = swap_span(&contracts_info).entered();
+        tracing::info!("Decrypted contracts after seeing txid={}", txid);
+        let contract_txids = contracts_info
+            .contract_txes
+            .iter()
+            .map(|ctx| ctx.tx.txid())
+            .collect::<Vec<Txid>>();
+        encrypted_contracts.retain(|ec| {
+            !contract_txids
+                .iter()
+                .any(|contract_txid| ec.hint[..] == contract_txid[..16])
+        });
+        decrypted_contracts.push(contracts_info);
+    }
+    decrypted_contracts
 }
 
 fn import_broadcasted_contract_redeemscripts(
@@ -530,7 +1126,7 @@ fn import_broadcasted_contract_redeemscripts(
     network: Network,
     broadcasted_contracts: &[ContractsInfo],
 ) -> Result<(), bitcoincore_rpc::Error> {
-    log::debug!(
+    tracing::debug!(
         "broadcasted transactions, now importing their redeemscripts = {:?}",
         broadcasted_contracts
             .iter()
@@ -545,7 +1141,7 @@ fn import_broadcasted_contract_redeemscripts(
     for contracts_info in broadcasted_contracts {
         for contract_tx in &contracts_info.contract_txes {
             if contract_tx.redeemscript.is_empty() {
-                log::debug!(
+                tracing::debug!(
                     "not importing redeemscript associated with txid={}",
                     contract_tx.tx.txid()
                 );
@@ -568,7 +1164,7 @@ fn check_for_hashlock_spends(
                 .info
                 .txid,
         );
-        log::debug!(
+        tracing::debug!(
             "initial setting of last_checked_txid = {:?}",
             last_checked_txid.unwrap()
         );
@@ -597,7 +1193,7 @@ fn check_for_hashlock_spends(
             break;
         }
     }
-    log::debug!(
+    tracing::debug!(
         "wallet_transactions = {:?}",
         wallet_transactions
             .iter()
@@ -606,9 +1202,9 @@ fn check_for_hashlock_spends(
     );
     if !wallet_transactions.is_empty() {
         *last_checked_txid = Some(wallet_transactions[0].info.txid);
-        log::debug!("updating last_checked_txid to: {:?}", last_checked_txid);
+        tracing::debug!("updating last_checked_txid to: {:?}", last_checked_txid);
     } else {
-        log::debug!(
+        tracing::debug!(
             "last_checked_txid remaining unchanged: {:?}",
             last_checked_txid
         );
@@ -624,115 +1220,320 @@ fn check_for_hashlock_spends(
             .transaction()
             .unwrap();
         for input in tx.input {
-            //TODO most of this below for checking whether a tx spends using a preimage we're
-            //interested in should be in its own function so its easier to test
-            if input.witness.len() < 3 {
-                log::debug!(
-                    "txid={} not hashlock spend, witness not enough elements",
-                    wallet_tx.info.txid
-                );
-                continue;
-            }
-            let contract_redeemscript = Script::from(input.witness[2].clone());
-            let pub_hashlock =
-                if let Ok(phl) = read_hashlock_pubkey_from_contract(&contract_redeemscript) {
-                    phl
-                } else {
-                    log::debug!(
-                        "txid={} not hashlock spend, unable to obtain pub_hashlock",
-                        wallet_tx.info.txid
-                    );
+            let (hashvalue, preimage) = match read_hashlock_spend_preimage(&input) {
+                Ok(hp) => hp,
+                Err(e) => {
+                    tracing::debug!("txid={} not hashlock spend, {}", wallet_tx.info.txid, e);
                     continue;
-                };
-            let pub_timelock =
-                if let Ok(ptl) = read_timelock_pubkey_from_contract(&contract_redeemscript) {
-                    ptl
-                } else {
-                    log::debug!(
-                        "txid={} not hashlock spend, unable to obtain pub_hashlock",
-                        wallet_tx.info.txid
-                    );
-                    continue;
-                };
-            let locktime = if let Some(lt) = read_locktime_from_contract(&contract_redeemscript) {
-                lt
-            } else {
-                log::debug!(
-                    "txid={} not hashlock spend, unable to obtain locktime",
-                    wallet_tx.info.txid
-                );
-                continue;
+                }
             };
-            let hashvalue = if let Ok(hv) = read_hashvalue_from_contract(&contract_redeemscript) {
-                hv
-            } else {
-                log::debug!(
-                    "txid={} not hashlock spend, unable to obtain hashvalue",
-                    wallet_tx.info.txid
-                );
+            closed_contracts.extend(broadcast_hashlock_spends(
+                rpc,
+                live_contracts,
+                &wallet_tx.info.txid,
+                hashvalue,
+                &preimage,
+            )?);
+        }
+        already_checked_txids.insert(wallet_tx.info.txid);
+    }
+    Ok(closed_contracts)
+}
+
+fn check_mempool_for_hashlock_spends(
+    rpc: &Client,
+    live_contracts: &[ContractsInfo],
+    checked_mempool_txids: &mut HashSet<Txid>,
+) -> Result<Vec<ContractsInfo>, bitcoincore_rpc::Error> {
+    let mempool_txids = rpc.get_raw_mempool()?;
+    //forget txes which left the mempool, so the set doesnt keep growing
+    let mempool_txid_set = mempool_txids.iter().collect::<HashSet<&Txid>>();
+    checked_mempool_txids.retain(|txid| mempool_txid_set.contains(txid));
+
+    let mut closed_contracts = Vec::<ContractsInfo>::new();
+    for txid in mempool_txids {
+        if !checked_mempool_txids.insert(txid) {
+            continue;
+        }
+        let tx = match rpc.get_raw_transaction(&txid, None) {
+            Ok(tx) => tx,
+            Err(e) => {
+                //the tx might have been mined or replaced since getrawmempool
+                tracing::debug!("unable to get mempool tx txid={}: {:?}", txid, e);
                 continue;
+            }
+        };
+        for input in tx.input {
+            let (hashvalue, preimage) = match read_hashlock_spend_preimage(&input) {
+                Ok(hp) => hp,
+                Err(_e) => continue,
             };
-            if create_contract_redeemscript(&pub_hashlock, &pub_timelock, hashvalue, locktime)
-                != contract_redeemscript
+            //another mempool tx could have revealed the same preimage already
+            let open_contracts = live_contracts
+                .iter()
+                .filter(|lc| !closed_contracts.contains(lc))
+                .cloned()
+                .collect::<Vec<ContractsInfo>>();
+            closed_contracts.extend(broadcast_hashlock_spends(
+                rpc,
+                &open_contracts,
+                &txid,
+                hashvalue,
+                &preimage,
+            )?);
+        }
+    }
+    Ok(closed_contracts)
+}
+
+//returns the hashvalue and preimage if the input is a hashlock spend of a contract
+fn read_hashlock_spend_preimage(input: &TxIn) -> Result<(Hash160, Vec<u8>), &'static str> {
+    if input.witness.len() < 3 {
+        return Err("witness not enough elements");
+    }
+    let contract_redeemscript = Script::from(input.witness[2].clone());
+    let pub_hashlock = read_hashlock_pubkey_from_contract(&contract_redeemscript)
+        .map_err(|_| "unable to obtain pub_hashlock")?;
+    let pub_timelock = read_timelock_pubkey_from_contract(&contract_redeemscript)
+        .map_err(|_| "unable to obtain pub_timelock")?;
+    let locktime =
+        read_locktime_from_contract(&contract_redeemscript).ok_or("unable to obtain locktime")?;
+    let hashvalue = read_hashvalue_from_contract(&contract_redeemscript)
+        .map_err(|_| "unable to obtain hashvalue")?;
+    if create_contract_redeemscript(&pub_hashlock, &pub_timelock, hashvalue, locktime)
+        != contract_redeemscript
+    {
+        return Err("tx not in contract_redeemscript form");
+    }
+    let preimage = &input.witness[1];
+    if Hash160::hash(preimage) != hashvalue {
+        return Err("preimage does not match");
+    }
+    Ok((hashvalue, preimage.clone()))
+}
+
+//adds the found preimage to the hashlock spends of every live contract with this hashvalue
+// and broadcasts them, returns those contracts which are now closed
+fn broadcast_hashlock_spends(
+    rpc: &Client,
+    live_contracts: &[ContractsInfo],
+    spending_txid: &Txid,
+    hashvalue: Hash160,
+    preimage: &[u8],
+) -> Result<Vec<ContractsInfo>, bitcoincore_rpc::Error> {
+    let mut closed_contracts = Vec::<ContractsInfo>::new();
+    for live_contract in live_contracts {
+        let contract_hashvalue =
+            read_hashvalue_from_contract(&live_contract.contract_txes[0].redeemscript).unwrap();
+        if contract_hashvalue != hashvalue {
+            tracing::debug!(
+                "txid={} not hashlock spend, hashvalue doesnt match contract being monitored",
+                spending_txid
+            );
+            continue;
+        }
+        let _span = swap_span(live_contract).entered();
+
+        closed_contracts.push(live_contract.clone());
+        tracing::info!(
+            "Found hashlock spend (txid={}) for one of our contracts, hashvalue={}",
+            spending_txid,
+            hashvalue
+        );
+        for contract_transaction in &live_contract.contract_txes {
+            if contract_transaction
+                .hashlock_spend_without_preimage
+                .is_none()
             {
-                log::debug!(
-                    "txid={} not hashlock spend, tx not in contract_redeemscript form",
-                    wallet_tx.info.txid
-                );
                 continue;
             }
-            let preimage = &input.witness[1];
-            if Hash160::hash(preimage) != hashvalue {
-                log::debug!(
-                    "txid={} not hashlock spend, preimage does not match",
-                    wallet_tx.info.txid
+            let mut spend_tx = contract_transaction
+                .hashlock_spend_without_preimage
+                .as_ref()
+                .unwrap()
+                .clone();
+            //assumes the spend tx is one-input-one-output
+            spend_tx.input[0].witness[1] = preimage.to_vec();
+            tracing::info!("Broadcasting hashlock spend tx: {}", spend_tx.txid());
+            let txid = rpc.send_raw_transaction(&spend_tx)?;
+            assert_eq!(txid, spend_tx.txid());
+            send_alert(
+                "watchtower",
+                Alert::ResponseBroadcast {
+                    txid,
+                    spend_type: "hashlock",
+                },
+            );
+        }
+    }
+    Ok(closed_contracts)
+}
+
+//the maker already knows the preimage of its incoming contracts, so if they get broadcast
+// the hashlock spends can be broadcast without waiting for the preimage to appear on chain
+fn check_for_known_preimage_spends(
+    rpc: &Client,
+    live_contracts: &mut [ContractsInfo],
+    hash_preimages: &[Preimage],
+) -> Result<Vec<ContractsInfo>, bitcoincore_rpc::Error> {
+    let mut closed_contracts = Vec::<ContractsInfo>::new();
+    for live_contract in live_contracts {
+        let preimage = match contracts_hashvalue(live_contract)
+            .and_then(|hv| hash_preimages.iter().find(|p| Hash160::hash(*p) == hv))
+        {
+            Some(p) => p,
+            None => continue,
+        };
+        let _span = swap_span(live_contract).entered();
+        for contract_transaction in &mut live_contract.contract_txes {
+            if contract_transaction.hashlock_spend_broadcasted {
+                continue;
+            }
+            let hashlock_spend = match &contract_transaction.hashlock_spend_without_preimage {
+                Some(hs) => hs,
+                None => continue,
+            };
+            //the hashlock branch is locked with 1 OP_CSV, so wait for the contract to confirm
+            let contract_txout = rpc.get_tx_out(
+                &contract_transaction.tx.txid(),
+                hashlock_spend.input[0].previous_output.vout,
+                Some(false),
+            )?;
+            if contract_txout.is_none() {
+                tracing::debug!(
+                    "contract txout (txid={}) not confirmed yet",
+                    contract_transaction.tx.txid()
                 );
                 continue;
             }
+            let mut spend_tx = hashlock_spend.clone();
+            //assumes the spend tx is one-input-one-output
+            spend_tx.input[0].witness[1] = preimage.to_vec();
+            tracing::info!("Broadcasting hashlock spend tx: {}", spend_tx.txid());
+            let txid = rpc.send_raw_transaction(&spend_tx)?;
+            contract_transaction.hashlock_spend_broadcasted = true;
+            send_alert(
+                "watchtower",
+                Alert::ResponseBroadcast {
+                    txid,
+                    spend_type: "hashlock",
+                },
+            );
+        }
 
-            //check if any of the live contracts we're monitoring have this hashvalue
-            for live_contract in live_contracts {
-                let contract_hashvalue =
-                    read_hashvalue_from_contract(&live_contract.contract_txes[0].redeemscript)
-                        .unwrap();
-                if contract_hashvalue != hashvalue {
-                    log::debug!(
-                        "txid={} not hashlock spend, hashvalue doesnt match contract being monitored",
-                        wallet_tx.info.txid
-                    );
-                    continue;
-                }
-                //time to add the found preimage and broadcast the spend txes
+        let hashlock_spends = live_contract
+            .contract_txes
+            .iter()
+            .filter(|ct| ct.hashlock_spend_without_preimage.is_some());
+        if hashlock_spends.clone().count() > 0
+            && hashlock_spends
+                .clone()
+                .all(|ct| ct.hashlock_spend_broadcasted)
+        {
+            tracing::debug!("live contract has all hashlock spends broadcast");
+            closed_contracts.push(live_contract.clone());
+        }
+    }
+    Ok(closed_contracts)
+}
 
-                closed_contracts.push(live_contract.clone());
-                log::info!(
-                    "Found hashlock spend (txid={}) for one of our contracts, hashvalue={}",
-                    wallet_tx.info.txid,
-                    hashvalue
+//alerts when the other side of an incoming contract can soon take the coins back with
+// the timelock, so the operator can check it gets claimed with the hashlock first
+fn check_for_timelocks_near_expiry(
+    rpc: &Client,
+    live_contracts: &[ContractsInfo],
+    timelock_alerted_txids: &mut HashSet<Txid>,
+) -> Result<(), bitcoincore_rpc::Error> {
+    let timelock_warning_blocks = if Settings::is_initialized() {
+        Settings::global().alerts.timelock_warning_blocks
+    } else {
+        Settings::default().alerts.timelock_warning_blocks
+    };
+    for live_contract in live_contracts {
+        let _span = swap_span(live_contract).entered();
+        for contract_transaction in &live_contract.contract_txes {
+            let txid = contract_transaction.tx.txid();
+            let hashlock_spend = match &contract_transaction.hashlock_spend_without_preimage {
+                Some(hs) if !timelock_alerted_txids.contains(&txid) => hs,
+                _ => continue,
+            };
+            let locktime = match read_locktime_from_contract(&contract_transaction.redeemscript) {
+                Some(lt) => lt as u32,
+                None => continue,
+            };
+            let confirmations = match rpc.get_tx_out(
+                &txid,
+                hashlock_spend.input[0].previous_output.vout,
+                Some(false),
+            )? {
+                Some(txout) => txout.confirmations,
+                None => continue,
+            };
+            let blocks_remaining = locktime.saturating_sub(confirmations);
+            if blocks_remaining <= timelock_warning_blocks {
+                timelock_alerted_txids.insert(txid);
+                send_alert(
+                    "watchtower",
+                    Alert::TimelockNearExpiry {
+                        txid,
+                        blocks_remaining,
+                    },
                 );
-                for contract_transaction in &live_contract.contract_txes {
-                    if contract_transaction
-                        .hashlock_spend_without_preimage
-                        .is_none()
-                    {
-                        continue;
-                    }
-                    let mut spend_tx = contract_transaction
-                        .hashlock_spend_without_preimage
-                        .as_ref()
-                        .unwrap()
-                        .clone();
-                    //assumes the spend tx is one-input-one-output
-                    spend_tx.input[0].witness[1] = preimage.clone();
-                    log::info!("Broadcasting hashlock spend tx: {}", spend_tx.txid());
-                    let txid = rpc.send_raw_transaction(&spend_tx)?;
-                    assert_eq!(txid, spend_tx.txid());
-                }
             }
         }
-        already_checked_txids.insert(wallet_tx.info.txid);
     }
-    Ok(closed_contracts)
+    Ok(())
+}
+
+//the alert about a fidelity bond at time now, a bond can be spent once the median time past
+// reaches its locktime like OP_CHECKLOCKTIMEVERIFY
+fn fidelity_bond_alert(
+    fidelity_bond: &mut WatchedFidelityBond,
+    now: i64,
+    reminder_days: u32,
+) -> Option<Alert> {
+    const DAY: i64 = 24 * 60 * 60;
+    let secs_remaining = fidelity_bond.locktime - now;
+    if secs_remaining <= 0 {
+        Some(Alert::FidelityBondUnlocked {
+            outpoint: fidelity_bond.outpoint,
+        })
+    } else if !fidelity_bond.reminder_sent && secs_remaining <= reminder_days as i64 * DAY {
+        fidelity_bond.reminder_sent = true;
+        Some(Alert::FidelityBondExpiring {
+            outpoint: fidelity_bond.outpoint,
+            days_remaining: ((secs_remaining + DAY - 1) / DAY) as u32,
+        })
+    } else {
+        None
+    }
+}
+
+//bonds are forgotten once they unlock
+fn check_for_fidelity_bond_expiry(
+    rpc: &Client,
+    fidelity_bonds: &mut Vec<WatchedFidelityBond>,
+) -> Result<(), bitcoincore_rpc::Error> {
+    let reminder_days = if Settings::is_initialized() {
+        Settings::global().alerts.fidelity_bond_reminder_days
+    } else {
+        Settings::default().alerts.fidelity_bond_reminder_days
+    };
+    let now = rpc.get_blockchain_info()?.median_time as i64;
+    fidelity_bonds.retain_mut(|fidelity_bond| {
+        match fidelity_bond_alert(fidelity_bond, now, reminder_days) {
+            Some(alert @ Alert::FidelityBondUnlocked { .. }) => {
+                send_alert("watchtower", alert);
+                false
+            }
+            Some(alert) => {
+                send_alert("watchtower", alert);
+                true
+            }
+            None => true,
+        }
+    });
+    Ok(())
 }
 
 fn check_for_timelock_maturity(
@@ -741,6 +1542,7 @@ fn check_for_timelock_maturity(
 ) -> Result<Vec<ContractsInfo>, bitcoincore_rpc::Error> {
     let mut closed_contracts = Vec::<ContractsInfo>::new();
     for live_contract in live_contracts {
+        let _span = swap_span(live_contract).entered();
         for contract_transaction in &mut live_contract.contract_txes {
             if contract_transaction.timelock_spend.is_none() {
                 continue;
@@ -748,7 +1550,7 @@ fn check_for_timelock_maturity(
             let timelock_spend = contract_transaction.timelock_spend.as_ref().unwrap();
             let gettx = rpc.get_transaction(&contract_transaction.tx.txid(), Some(true))?;
             if gettx.info.confirmations < (timelock_spend.input[0].sequence as i32) {
-                log::debug!(
+                tracing::debug!(
                     "timelock txout (txid={}) maturing in {} blocks",
                     contract_transaction.tx.txid(),
                     ((timelock_spend.input[0].sequence as i32) - gettx.info.confirmations)
@@ -756,14 +1558,21 @@ fn check_for_timelock_maturity(
                 continue;
             }
             if contract_transaction.timelock_spend_broadcasted {
-                log::debug!(
+                tracing::debug!(
                     "not broadcasting timelock spend ({}) again, already broadcasted",
                     timelock_spend.txid()
                 );
             } else {
-                log::info!("Broadcasting timelock spend tx: {}", timelock_spend.txid());
-                let _txid = rpc.send_raw_transaction(timelock_spend)?;
+                tracing::info!("Broadcasting timelock spend tx: {}", timelock_spend.txid());
+                let txid = rpc.send_raw_transaction(timelock_spend)?;
                 contract_transaction.timelock_spend_broadcasted = true;
+                send_alert(
+                    "watchtower",
+                    Alert::ResponseBroadcast {
+                        txid,
+                        spend_type: "timelock",
+                    },
+                );
             }
         }
 
@@ -777,13 +1586,13 @@ fn check_for_timelock_maturity(
             .iter()
             .filter(|ct| ct.timelock_spend.is_some())
             .count();
-        log::debug!(
+        tracing::debug!(
             "timelock_spends_broadcasted = {}, total_timelock_spends = {}",
             timelock_spends_broadcasted,
             total_timelock_spends
         );
         if timelock_spends_broadcasted == total_timelock_spends {
-            log::debug!(
+            tracing::debug!(
                 "live contract {:?} has all timelock spends broadcast",
                 live_contract
                     .contract_txes
@@ -796,3 +1605,342 @@ fn check_for_timelock_maturity(
     }
     Ok(closed_contracts)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    use bitcoin::util::ecdsa::PublicKey;
+    use bitcoin::OutPoint;
+
+    #[test]
+    fn test_read_hashlock_spend_preimage() {
+        let preimage = vec![7u8; 32];
+        let hashvalue = Hash160::hash(&preimage);
+        let pub_hashlock = PublicKey::from_str(
+            "032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af",
+        )
+        .unwrap();
+        let pub_timelock = PublicKey::from_str(
+            "039b6347398505f5ec93826dc61c19f47c66c0283ee9be980e29ce325a0f4679ef",
+        )
+        .unwrap();
+        let redeemscript =
+            create_contract_redeemscript(&pub_hashlock, &pub_timelock, hashvalue, 20);
+        let spend_input = |witness: Vec<Vec<u8>>| TxIn {
+            previous_output: OutPoint::default(),
+            script_sig: Script::new(),
+            sequence: 1,
+            witness,
+        };
+
+        let sig = vec![1u8; 72];
+        let hashlock_spend =
+            spend_input(vec![sig.clone(), preimage.clone(), redeemscript.to_bytes()]);
+        assert_eq!(
+            read_hashlock_spend_preimage(&hashlock_spend),
+            Ok((hashvalue, preimage))
+        );
+
+        //the timelock branch is spent with an empty preimage
+        let timelock_spend = spend_input(vec![sig.clone(), Vec::new(), redeemscript.to_bytes()]);
+        assert!(read_hashlock_spend_preimage(&timelock_spend).is_err());
+
+        let p2wpkh_spend = spend_input(vec![sig, pub_hashlock.to_bytes()]);
+        assert!(read_hashlock_spend_preimage(&p2wpkh_spend).is_err());
+    }
+
+    #[test]
+    fn test_encrypted_contracts() {
+        let contract_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: Vec::new(),
+            output: Vec::new(),
+        };
+        let contracts_info = ContractsInfo {
+            contract_txes: vec![ContractTransaction {
+                tx: contract_tx.clone(),
+                redeemscript: Script::new(),
+                hashlock_spend_without_preimage: None,
+                timelock_spend: None,
+                timelock_spend_broadcasted: false,
+                hashlock_spend_broadcasted: false,
+            }],
+            wallet_label: "label".to_string(),
+        };
+        let txid = contract_tx.txid();
+        let encrypted_contracts = encrypt_contracts_info(&contracts_info, &txid);
+        assert_eq!(encrypted_contracts.hint[..], txid[..16]);
+        assert_eq!(
+            decrypt_contracts_info(&encrypted_contracts, &txid),
+            Some(contracts_info.clone())
+        );
+        let other_txid = Txid::from_slice(&[1u8; 32]).unwrap();
+        assert_eq!(
+            decrypt_contracts_info(&encrypted_contracts, &other_txid),
+            None
+        );
+
+        //a blob of another coinswap whose hint collides with txid, it cant be decrypted
+        let mut colliding = encrypt_contracts_info(&contracts_info, &other_txid);
+        colliding.hint.copy_from_slice(&txid[..16]);
+        let mut stored = vec![colliding, encrypted_contracts];
+        let unrelated_txs = [TxidListType::FromMempool(vec![other_txid])];
+        assert!(decrypt_broadcasted_contracts(&unrelated_txs, &mut stored).is_empty());
+        assert_eq!(stored.len(), 2);
+
+        let network_txs = [TxidListType::FromMempool(vec![other_txid, txid])];
+        assert_eq!(
+            decrypt_broadcasted_contracts(&network_txs, &mut stored),
+            vec![contracts_info]
+        );
+        assert!(stored.is_empty());
+    }
+
+    #[test]
+    fn test_find_fork_height() {
+        let blockhash = |b: u8| BlockHash::from_slice(&[b; 32]).unwrap();
+        //blocks 1 to 3 are in the best chain, blocks 12 and 13 were reorged out and built
+        // on block 1
+        let chain = vec![
+            (blockhash(1), (true, 1, Some(blockhash(0)))),
+            (blockhash(2), (true, 2, Some(blockhash(1)))),
+            (blockhash(3), (true, 3, Some(blockhash(2)))),
+            (blockhash(12), (false, 2, Some(blockhash(1)))),
+            (blockhash(13), (false, 3, Some(blockhash(12)))),
+        ]
+        .into_iter()
+        .collect::<HashMap<BlockHash, (bool, u64, Option<BlockHash>)>>();
+        let block_header = |h: &BlockHash| chain.get(h).cloned().ok_or(());
+        assert_eq!(find_fork_height(blockhash(3), block_header), Ok(3));
+        assert_eq!(find_fork_height(blockhash(2), block_header), Ok(2));
+        assert_eq!(find_fork_height(blockhash(13), block_header), Ok(1));
+        assert_eq!(find_fork_height(blockhash(12), block_header), Ok(1));
+    }
+
+    #[test]
+    fn test_settled_contracts() {
+        let funding_outpoint = |vout: u32| OutPoint {
+            txid: Txid::from_slice(&[2u8; 32]).unwrap(),
+            vout,
+        };
+        let contract_tx = |vout: u32| ContractTransaction {
+            tx: Transaction {
+                version: 2,
+                lock_time: 0,
+                input: vec![TxIn {
+                    previous_output: funding_outpoint(vout),
+                    script_sig: Script::new(),
+                    sequence: 0,
+                    witness: Vec::new(),
+                }],
+                output: Vec::new(),
+            },
+            redeemscript: Script::new(),
+            hashlock_spend_without_preimage: None,
+            timelock_spend: None,
+            timelock_spend_broadcasted: false,
+            hashlock_spend_broadcasted: false,
+        };
+        let contracts_info = ContractsInfo {
+            contract_txes: vec![contract_tx(0), contract_tx(1)],
+            wallet_label: String::new(),
+        };
+        let mut funding_outputs = Vec::<FundingOutput>::new();
+
+        //not seen unspent yet, so not counted as spent either
+        update_funding_output(&mut funding_outputs, funding_outpoint(0), false, 100);
+        assert!(funding_outputs.is_empty());
+
+        update_funding_output(&mut funding_outputs, funding_outpoint(0), true, 100);
+        update_funding_output(&mut funding_outputs, funding_outpoint(1), true, 100);
+        update_funding_output(&mut funding_outputs, funding_outpoint(0), false, 101);
+        assert!(!is_settled(&contracts_info, &funding_outputs, 200, 10));
+
+        update_funding_output(&mut funding_outputs, funding_outpoint(1), false, 105);
+        //seeing it spent again doesnt move the height
+        update_funding_output(&mut funding_outputs, funding_outpoint(1), false, 106);
+        assert!(!is_settled(&contracts_info, &funding_outputs, 114, 10));
+        assert!(is_settled(&contracts_info, &funding_outputs, 115, 10));
+
+        //reorged out
+        update_funding_output(&mut funding_outputs, funding_outpoint(1), true, 115);
+        assert!(!is_settled(&contracts_info, &funding_outputs, 200, 10));
+
+        let no_contract_txes = ContractsInfo {
+            contract_txes: Vec::new(),
+            wallet_label: String::new(),
+        };
+        assert!(!is_settled(&no_contract_txes, &funding_outputs, 200, 10));
+    }
+
+    #[tokio::test]
+    async fn test_watchtower_status() {
+        let redeemscript = create_contract_redeemscript(
+            &PublicKey::from_str(
+                "032e58afe51f9ed8ad3cc7897f634d881fdbe49a81564629ded8156bebd2ffd1af",
+            )
+            .unwrap(),
+            &PublicKey::from_str(
+                "039b6347398505f5ec93826dc61c19f47c66c0283ee9be980e29ce325a0f4679ef",
+            )
+            .unwrap(),
+            Hash160::hash(&[7u8; 32]),
+            20,
+        );
+        let contracts_info = |lock_time: u32, redeemscript: Script| ContractsInfo {
+            contract_txes: vec![ContractTransaction {
+                tx: Transaction {
+                    version: 2,
+                    lock_time,
+                    input: Vec::new(),
+                    output: Vec::new(),
+                },
+                redeemscript,
+                hashlock_spend_without_preimage: None,
+                timelock_spend: None,
+                timelock_spend_broadcasted: false,
+                hashlock_spend_broadcasted: false,
+            }],
+            wallet_label: String::new(),
+        };
+        let in_progress = contracts_info(0, redeemscript);
+        let live = contracts_info(1, Script::new());
+        let data_file = WatchtowerDataFile {
+            coinswap_in_progress_contracts: vec![in_progress.clone()],
+            last_checked_block_height: Some(100),
+            last_checked_block_hash: None,
+            live_contracts: vec![live.clone()],
+            last_checked_txid: None,
+            hash_preimages: Vec::new(),
+            encrypted_contracts: Vec::new(),
+            funding_outputs: Vec::new(),
+            fidelity_bonds: Vec::new(),
+        };
+        let contracts = watched_contracts(&data_file);
+        assert_eq!(
+            contracts,
+            vec![
+                WatchedContracts {
+                    state: "in_progress".to_string(),
+                    txids: vec![in_progress.contract_txes[0].tx.txid()],
+                    locktime: Some(20),
+                },
+                WatchedContracts {
+                    state: "live".to_string(),
+                    txids: vec![live.contract_txes[0].tx.txid()],
+                    locktime: None,
+                },
+            ]
+        );
+
+        //answers the status requests like the main loop does
+        let (status_requests, mut status_requests_rx) =
+            mpsc::channel::<oneshot::Sender<WatchtowerStatus>>(1);
+        tokio::spawn(async move {
+            while let Some(status_reply_tx) = status_requests_rx.recv().await {
+                let _ = status_reply_tx.send(WatchtowerStatus {
+                    bitcoin_node_connected: false,
+                    block_height: None,
+                    last_checked_block_height: data_file.last_checked_block_height,
+                    accepting_clients: false,
+                    contracts: watched_contracts(&data_file),
+                    encrypted_contracts: 0,
+                    fidelity_bonds: Vec::new(),
+                });
+            }
+        });
+        let comms = WatchtowerComms {
+            watched_txes: mpsc::channel(1).0,
+            encrypted_txes: mpsc::channel(1).0,
+            preimages: mpsc::channel(1).0,
+            fidelity_bonds: mpsc::channel(1).0,
+            status_requests,
+        };
+        let get_status = serde_json::to_string(&MakerToWatchtowerMessage::GetStatus(GetStatus {
+            protocol_version_min: 0,
+            protocol_version_max: 0,
+        }))
+        .unwrap();
+        //the status is still given when the watchtower isnt accepting other requests
+        match handle_message(get_status, &comms, false).await.unwrap() {
+            WatchtowerToMakerMessage::Status(status) => {
+                assert!(!status.bitcoin_node_connected);
+                assert_eq!(status.last_checked_block_height, Some(100));
+                assert_eq!(status.contracts, contracts);
+            }
+            _ => panic!("wrong message"),
+        }
+        let ping = serde_json::to_string(&MakerToWatchtowerMessage::Ping(Ping {
+            protocol_version_min: 0,
+            protocol_version_max: 0,
+        }))
+        .unwrap();
+        assert!(handle_message(ping.clone(), &comms, false).await.is_err());
+        assert!(matches!(
+            handle_message(ping, &comms, true).await,
+            Ok(WatchtowerToMakerMessage::Success(_))
+        ));
+    }
+
+    #[test]
+    fn test_hash_preimage_message() {
+        let message = MakerToWatchtowerMessage::HashPreimage(HashPreimage {
+            protocol_version_min: 0,
+            protocol_version_max: 0,
+            preimage: [3u8; 32],
+        });
+        let message_json = serde_json::to_string(&message).unwrap();
+        assert!(message_json.starts_with(r#"{"method":"hashpreimage","#));
+        match serde_json::from_str::<MakerToWatchtowerMessage>(&message_json).unwrap() {
+            MakerToWatchtowerMessage::HashPreimage(hp) => assert_eq!(hp.preimage, [3u8; 32]),
+            _ => panic!("wrong message"),
+        }
+
+        //data files written before preimages were sent to the watchtower
+        let data_file = serde_json::from_str::<WatchtowerDataFile>(
+            r#"{"coinswap_in_progress_contracts":[],"last_checked_block_height":null,
+                "live_contracts":[],"last_checked_txid":null}"#,
+        )
+        .unwrap();
+        assert!(data_file.hash_preimages.is_empty());
+        assert!(data_file.fidelity_bonds.is_empty());
+    }
+
+    #[test]
+    fn test_fidelity_bond_alert() {
+        const DAY: i64 = 24 * 60 * 60;
+        let message = MakerToWatchtowerMessage::WatchFidelityBond(WatchFidelityBond {
+            protocol_version_min: 0,
+            protocol_version_max: 0,
+            outpoint: OutPoint::default(),
+            locktime: 1_822_348_800,
+        });
+        let message_json = serde_json::to_string(&message).unwrap();
+        assert!(message_json.starts_with(r#"{"method":"watchfidelitybond","#));
+
+        let mut fidelity_bond = WatchedFidelityBond {
+            outpoint: OutPoint::default(),
+            locktime: 1_822_348_800,
+            reminder_sent: false,
+        };
+        assert!(fidelity_bond_alert(&mut fidelity_bond, 1_822_348_800 - 31 * DAY, 30).is_none());
+        assert!(matches!(
+            fidelity_bond_alert(&mut fidelity_bond, 1_822_348_800 - 29 * DAY - 1, 30),
+            Some(Alert::FidelityBondExpiring {
+                days_remaining: 30,
+                ..
+            })
+        ));
+        //the reminder is sent once
+        assert!(fidelity_bond_alert(&mut fidelity_bond, 1_822_348_800 - DAY, 30).is_none());
+        assert!(matches!(
+            fidelity_bond_alert(&mut fidelity_bond, 1_822_348_800, 30),
+            Some(Alert::FidelityBondUnlocked { .. })
+        ));
+    }
+}
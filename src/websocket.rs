@@ -0,0 +1,169 @@
+//websocket endpoint of teleportd, which pushes daemon events to GUIs so they dont need
+// to poll the JSON-RPC interface
+//connect to ws://127.0.0.1:<port>/?cookie=<contents of the daemon cookie file>
+//every event is a JSON object in a text message, with a "type" field of balance_changed,
+// offerbook_updated, coinswap, coinswap_finished, contract_broadcast or incoming_transaction
+//messages sent by the client are ignored
+
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+
+use futures::{SinkExt, StreamExt};
+use serde_json::json;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::select;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::tungstenite::handshake::server::{
+    Callback, ErrorResponse, Request, Response,
+};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::daemon::{is_correct_cookie, DaemonState};
+
+pub(crate) async fn serve(state: Arc<DaemonState>, port: u16) {
+    let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await {
+        Ok(l) => l,
+        Err(e) => {
+            tracing::error!("cant listen for websocket clients: {:?}", e);
+            return;
+        }
+    };
+    tracing::info!("Pushing Events To Websocket Clients On Port {}", port);
+    accept_connections(state, listener).await;
+}
+
+async fn accept_connections(state: Arc<DaemonState>, listener: TcpListener) {
+    loop {
+        let (socket, addr) = match listener.accept().await {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::debug!("error accepting websocket client: {:?}", e);
+                continue;
+            }
+        };
+        tracing::debug!("[{}] ===> Accepted Websocket Connection", addr.port());
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(state, socket).await {
+                tracing::debug!("websocket error: {:?}", e);
+            }
+            tracing::debug!("[{}] Websocket connection closed", addr.port());
+        });
+    }
+}
+
+fn cookie_from_query(query: Option<&str>) -> Option<&str> {
+    query?
+        .split('&')
+        .find_map(|param| param.strip_prefix("cookie="))
+}
+
+//rejects the handshake unless the url has the right cookie
+struct CheckCookie<'a> {
+    cookie: &'a str,
+}
+
+impl Callback for CheckCookie<'_> {
+    fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+        if cookie_from_query(request.uri().query())
+            .is_some_and(|cookie| is_correct_cookie(cookie, self.cookie))
+        {
+            Ok(response)
+        } else {
+            let mut error_response = ErrorResponse::new(Some("incorrect cookie".to_string()));
+            *error_response.status_mut() = StatusCode::UNAUTHORIZED;
+            Err(error_response)
+        }
+    }
+}
+
+async fn handle_connection(
+    state: Arc<DaemonState>,
+    socket: TcpStream,
+) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    let check_cookie = CheckCookie {
+        cookie: &state.cookie,
+    };
+    let mut websocket = tokio_tungstenite::accept_hdr_async(socket, check_cookie).await?;
+    let mut events_rx = state.events.subscribe();
+
+    loop {
+        select! {
+            event = events_rx.recv() => {
+                let event_json = match event {
+                    Ok(event) => json!(event),
+                    //the client should fetch everything again over JSON-RPC
+                    Err(RecvError::Lagged(count)) => json!({
+                        "type": "events_missed",
+                        "count": count,
+                    }),
+                    Err(RecvError::Closed) => break,
+                };
+                websocket.send(Message::Text(event_json.to_string())).await?;
+            },
+            message = websocket.next() => match message {
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_message)) => (),
+                Some(Err(e)) => return Err(e),
+            },
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::path::Path;
+
+    use bitcoin::Network;
+    use serde_json::Value;
+
+    use crate::daemon::{CoinswapState, DaemonEvent};
+
+    #[test]
+    fn test_cookie_from_query() {
+        assert_eq!(cookie_from_query(None), None);
+        assert_eq!(cookie_from_query(Some("a=b")), None);
+        assert_eq!(cookie_from_query(Some("cookie=abcd")), Some("abcd"));
+        assert_eq!(cookie_from_query(Some("a=b&cookie=abcd")), Some("abcd"));
+    }
+
+    #[tokio::test]
+    async fn test_websocket_events() {
+        let state = Arc::new(DaemonState::new(
+            Path::new("wallet.teleport"),
+            Network::Regtest,
+            "secret".to_string(),
+        ));
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(accept_connections(Arc::clone(&state), listener));
+
+        let url = format!("ws://127.0.0.1:{}/?cookie=wrong", port);
+        assert!(tokio_tungstenite::connect_async(url).await.is_err());
+
+        let url = format!("ws://127.0.0.1:{}/?cookie=secret", port);
+        let (mut websocket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        //the server subscribes after the handshake, wait for that
+        while state.events.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+        state
+            .events
+            .send(DaemonEvent::CoinswapFinished {
+                swap_id: 0,
+                state: CoinswapState::Complete,
+            })
+            .unwrap();
+
+        let message = websocket.next().await.unwrap().unwrap();
+        let event: Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        assert_eq!(
+            event,
+            json!({"type": "coinswap_finished", "swap_id": 0, "state": "complete"})
+        );
+    }
+}